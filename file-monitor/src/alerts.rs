@@ -0,0 +1,71 @@
+use crate::error::{invalid_config, Result};
+use crate::rates::{EventRates, MAX_RATE_WINDOW};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Fires when more than `threshold` events of kind `event` (as named by
+/// [`crate::FileEvent::kind`]) are recorded within `window_secs`, e.g. a burst of deletions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateAlertRule {
+    pub name: String,
+    pub event: String,
+    pub threshold: usize,
+    pub window_secs: u64,
+}
+
+impl RateAlertRule {
+    pub fn new(name: &str, event: &str, threshold: usize, window: Duration) -> Result<Self> {
+        let rule = Self {
+            name: name.to_string(),
+            event: event.to_ascii_lowercase(),
+            threshold,
+            window_secs: window.as_secs(),
+        };
+        rule.validate()?;
+        Ok(rule)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.window_secs == 0 || self.window_secs > MAX_RATE_WINDOW.as_secs() {
+            return Err(invalid_config!(
+                "Rate alert {} needs a window between 1 and {} seconds",
+                self.name,
+                MAX_RATE_WINDOW.as_secs()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+}
+
+/// A rule and whether it is currently over its threshold. A rule fires once when it
+/// crosses the threshold and again only after the rate has dropped back to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateAlertState {
+    pub rule: RateAlertRule,
+    pub firing: bool,
+}
+
+impl RateAlertState {
+    pub fn new(rule: RateAlertRule) -> Self {
+        Self {
+            rule,
+            firing: false,
+        }
+    }
+
+    /// Updates the state from the current rates after an event of `kind` was recorded.
+    /// Returns the event count when the rule starts firing.
+    pub(crate) fn check(&mut self, kind: &str, rates: &EventRates) -> Option<usize> {
+        if !self.rule.event.eq_ignore_ascii_case(kind) {
+            return None;
+        }
+        let count = rates.rate(kind, self.rule.window()).count;
+        let was_firing = std::mem::replace(&mut self.firing, count > self.rule.threshold);
+        (self.firing && !was_firing).then_some(count)
+    }
+}
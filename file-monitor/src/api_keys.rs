@@ -0,0 +1,174 @@
+use crate::config_guard::to_hex;
+use crate::error::{monitor_error, MonitorError, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+const KEY_PREFIX: &str = "fm_";
+
+/// What an API key may do on the control server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// `GET` routes only: status, stats, tasks, history and the event stream.
+    Read,
+    /// Every route, including pausing, changing paths and maintenance windows.
+    Control,
+}
+
+impl ApiScope {
+    pub fn allows(self, required: ApiScope) -> bool {
+        self == ApiScope::Control || required == ApiScope::Read
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = MonitorError;
+
+    fn from_str(scope: &str) -> Result<Self> {
+        match scope.to_ascii_lowercase().as_str() {
+            "read" => Ok(ApiScope::Read),
+            "control" => Ok(ApiScope::Control),
+            _ => Err(monitor_error!(
+                "Unknown API scope {}, expected read or control",
+                scope
+            )),
+        }
+    }
+}
+
+/// A named API key. Only the SHA-256 of the secret is kept, so the key file does not
+/// grant access by itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub scope: ApiScope,
+    pub key_hash: String,
+    pub created: DateTime<Local>,
+    /// Keys registered at startup (e.g. from an environment variable) are not saved.
+    #[serde(skip)]
+    pub ephemeral: bool,
+}
+
+/// API keys accepted by the control server, persisted as JSON when backed by a file.
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    path: Option<PathBuf>,
+    keys: Mutex<Vec<ApiKey>>,
+}
+
+impl ApiKeyStore {
+    /// A store that is not persisted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads keys from `path`, which need not exist yet; changes are saved back to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let keys = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| monitor_error!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Creates a key named `name` and returns its secret, which is not stored and cannot
+    /// be shown again.
+    pub fn add(&self, name: &str, scope: ApiScope) -> Result<String> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret)
+            .map_err(|e| monitor_error!("Failed to generate API key: {}", e))?;
+        let secret = format!("{}{}", KEY_PREFIX, to_hex(&secret));
+        self.insert(name, &secret, scope, false)?;
+        Ok(secret)
+    }
+
+    /// Accepts an existing secret, e.g. the legacy control token, without saving it.
+    /// Surrounding whitespace, such as a trailing newline, is not part of the secret.
+    pub fn insert_ephemeral(&self, name: &str, secret: &str, scope: ApiScope) -> Result<()> {
+        if secret.trim().is_empty() {
+            return Err(monitor_error!("API key {} must not be empty", name));
+        }
+        self.insert(name, secret, scope, true)
+    }
+
+    /// Revokes the key named `name`; returns whether it existed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut keys = self.keys.lock().unwrap();
+        let before = keys.len();
+        keys.retain(|key| key.name != name);
+        let removed = keys.len() != before;
+        if removed {
+            self.save(&keys)?;
+        }
+        Ok(removed)
+    }
+
+    pub fn list(&self) -> Vec<ApiKey> {
+        self.keys.lock().unwrap().clone()
+    }
+
+    /// The key whose secret is `secret`, if any.
+    pub fn authenticate(&self, secret: &str) -> Option<ApiKey> {
+        let given = secret_hash(secret);
+        self.keys
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|key| constant_time_eq(given.as_bytes(), key.key_hash.as_bytes()))
+            .cloned()
+    }
+
+    fn insert(&self, name: &str, secret: &str, scope: ApiScope, ephemeral: bool) -> Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.iter().any(|key| key.name == name) {
+            return Err(MonitorError::AlreadyExists {
+                kind: "API key",
+                name: name.to_string(),
+            });
+        }
+        keys.push(ApiKey {
+            name: name.to_string(),
+            scope,
+            key_hash: secret_hash(secret),
+            created: Local::now(),
+            ephemeral,
+        });
+        if ephemeral {
+            return Ok(());
+        }
+        if let Err(e) = self.save(&keys) {
+            keys.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn save(&self, keys: &[ApiKey]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let persisted: Vec<_> = keys.iter().filter(|key| !key.ephemeral).collect();
+        std::fs::write(path, serde_json::to_vec_pretty(&persisted)?)?;
+        Ok(())
+    }
+}
+
+/// Hash under which a secret is stored and looked up, ignoring surrounding whitespace
+/// the same way on both sides.
+fn secret_hash(secret: &str) -> String {
+    to_hex(&Sha256::digest(secret.trim().as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
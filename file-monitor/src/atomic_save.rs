@@ -0,0 +1,152 @@
+use crate::timing::ReceivedEvent;
+use chrono::{DateTime, Local};
+use notify::event::{ModifyKind, RenameMode};
+use notify::EventKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_ATOMIC_SAVE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Whether `path` looks like a temporary file that editors and tools write before
+/// renaming it over the real file: `*.tmp`, `*.temp`, `*.part`, `.goutputstream-*`
+/// (GTK), `*___jb_tmp___` (JetBrains), `.tmp*` (tempfile) and `sedXXXXXX` (`sed -i`).
+pub fn is_temp_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let lower = name.to_ascii_lowercase();
+    [".tmp", ".temp", ".part", "___jb_tmp___"]
+        .iter()
+        .any(|suffix| lower.ends_with(suffix))
+        || lower.starts_with(".goutputstream-")
+        || lower.starts_with(".tmp")
+        || (name.len() == 9
+            && name.starts_with("sed")
+            && name[3..].chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// What the coalescer lets through to the event pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Coalesced {
+    Event(ReceivedEvent),
+    /// `temp` was renamed over `target`: one logical save of `target`.
+    Replaced {
+        temp: PathBuf,
+        target: PathBuf,
+        /// When the rename completing the save was received.
+        received: DateTime<Local>,
+    },
+}
+
+/// Collapses atomic saves (write a temp file, rename it over the target) into a single
+/// [`Coalesced::Replaced`]. Events on temp files are held back for `window`; if the file
+/// is renamed over another one in that time they are dropped, otherwise released as
+/// they were.
+#[derive(Debug)]
+pub(crate) struct AtomicSaveCoalescer {
+    window: Duration,
+    held: Vec<(Instant, ReceivedEvent)>,
+    /// Rename tracker and source of a temp file moved away, waiting for its destination.
+    renaming: Option<(Option<usize>, PathBuf)>,
+    /// Tracker of a rename already collapsed, whose trailing `Both` event is dropped.
+    collapsed: Option<usize>,
+}
+
+impl AtomicSaveCoalescer {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            held: Vec::new(),
+            renaming: None,
+            collapsed: None,
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub(crate) fn push(&mut self, event: ReceivedEvent, now: Instant) -> Vec<Coalesced> {
+        let mut out = self.expire(now);
+        match &event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                if self.collapsed.is_some() && self.collapsed == event.tracker() {
+                    self.collapsed = None;
+                } else if is_temp_file(&event.paths[0]) {
+                    out.push(self.collapse(
+                        event.paths[0].clone(),
+                        event.paths[1].clone(),
+                        event.received,
+                    ));
+                } else {
+                    out.push(Coalesced::Event(event));
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From))
+                if event.paths.first().is_some_and(|path| is_temp_file(path)) =>
+            {
+                self.renaming = Some((event.tracker(), event.paths[0].clone()));
+                self.held.push((now, event));
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) if event.paths.len() == 1 => {
+                match self.renaming.take() {
+                    Some((tracker, temp)) if tracker == event.tracker() => {
+                        self.collapsed = tracker;
+                        out.push(self.collapse(temp, event.paths[0].clone(), event.received));
+                    }
+                    _ => out.push(Coalesced::Event(event)),
+                }
+            }
+            _ if event.paths.first().is_some_and(|path| is_temp_file(path)) => {
+                self.held.push((now, event));
+            }
+            _ => out.push(Coalesced::Event(event)),
+        }
+        out
+    }
+
+    /// Releases events held for longer than the window.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<Coalesced> {
+        let expired = self
+            .held
+            .iter()
+            .take_while(|(held, _)| now.duration_since(*held) >= self.window)
+            .count();
+        if expired > 0
+            && self.held[..expired]
+                .iter()
+                .any(|(_, event)| self.is_renaming(event))
+        {
+            self.renaming = None;
+        }
+        self.held
+            .drain(..expired)
+            .map(|(_, event)| Coalesced::Event(event))
+            .collect()
+    }
+
+    /// Releases everything held, e.g. when the monitor stops.
+    pub(crate) fn drain(&mut self) -> Vec<Coalesced> {
+        self.renaming = None;
+        self.held
+            .drain(..)
+            .map(|(_, event)| Coalesced::Event(event))
+            .collect()
+    }
+
+    fn is_renaming(&self, event: &ReceivedEvent) -> bool {
+        self.renaming
+            .as_ref()
+            .is_some_and(|(_, temp)| event.paths.first() == Some(temp))
+    }
+
+    fn collapse(&mut self, temp: PathBuf, target: PathBuf, received: DateTime<Local>) -> Coalesced {
+        self.held
+            .retain(|(_, event)| event.paths.first() != Some(&temp));
+        Coalesced::Replaced {
+            temp,
+            target,
+            received,
+        }
+    }
+}
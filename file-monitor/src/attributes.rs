@@ -0,0 +1,137 @@
+use crate::FileEvent;
+use chrono::{DateTime, Local};
+use notify::event::MetadataKind;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Files whose attributes are kept by the initial scan of the watches; past this, files
+/// are only learned from their events.
+const MAX_REMEMBERED: usize = 100_000;
+
+/// A file's metadata as of an event, kept with the record so it can be analyzed after
+/// the file is gone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    pub modified: Option<DateTime<Local>>,
+    /// Permission bits (`st_mode`) on Unix; elsewhere only `readonly` is meaningful.
+    pub mode: u32,
+    pub readonly: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+impl FileMetadata {
+    /// Metadata of `path` itself, not following symlinks. `None` if it does not exist.
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::symlink_metadata(path).ok()?;
+        let readonly = metadata.permissions().readonly();
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.mode(), Some(metadata.uid()), Some(metadata.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (if readonly { 0o444 } else { 0o666 }, None, None);
+        Some(Self {
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Local>::from),
+            mode,
+            readonly,
+            uid,
+            gid,
+        })
+    }
+}
+
+/// How a modification changed a file's length, for telling log growth apart from
+/// rotation or rewrites in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeChange {
+    /// The file grew by this many bytes.
+    Appended(u64),
+    /// The file got shorter, e.g. `truncate` or `> file`.
+    Truncated,
+}
+
+impl SizeChange {
+    /// The change from `previous` to `current` bytes; `None` if the length is the same.
+    pub fn between(previous: u64, current: u64) -> Option<Self> {
+        match current.cmp(&previous) {
+            std::cmp::Ordering::Greater => Some(SizeChange::Appended(current - previous)),
+            std::cmp::Ordering::Less => Some(SizeChange::Truncated),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+/// Tells apart what a metadata event changed. inotify reports chmod, chown and `touch`
+/// alike as `IN_ATTRIB`, so the metadata of known files is kept and compared.
+#[derive(Debug, Default)]
+pub(crate) struct AttributeTracker {
+    known: HashMap<PathBuf, FileMetadata>,
+}
+
+impl AttributeTracker {
+    /// The event for a metadata change of `path`. `None` when the backend did not say what
+    /// changed and `path` was not known before, or is gone.
+    pub(crate) fn classify(&mut self, path: &Path, kind: MetadataKind) -> Option<FileEvent> {
+        let current = FileMetadata::read(path);
+        let previous = match &current {
+            Some(current) => self.known.insert(path.to_path_buf(), current.clone()),
+            None => self.known.remove(path),
+        };
+        match kind {
+            MetadataKind::Permissions => return Some(FileEvent::PermissionsChanged),
+            MetadataKind::Ownership => return Some(FileEvent::OwnershipChanged),
+            MetadataKind::AccessTime => return Some(FileEvent::TimestampChanged),
+            // Polling reports every write this way, as it only sees the new mtime.
+            MetadataKind::WriteTime => return Some(FileEvent::Modified),
+            _ => {}
+        }
+        let (previous, current) = (previous?, current?);
+        if previous.mode != current.mode {
+            Some(FileEvent::PermissionsChanged)
+        } else if (previous.uid, previous.gid) != (current.uid, current.gid) {
+            Some(FileEvent::OwnershipChanged)
+        } else {
+            Some(FileEvent::TimestampChanged)
+        }
+    }
+
+    /// The metadata last seen for `path`, e.g. for a file that was just deleted.
+    pub(crate) fn last_known(&self, path: &Path) -> Option<FileMetadata> {
+        self.known.get(path).cloned()
+    }
+
+    /// Keeps the metadata the initial scan read for `path`.
+    pub(crate) fn insert(&mut self, path: &Path, metadata: FileMetadata) {
+        if self.known.len() < MAX_REMEMBERED {
+            self.known.insert(path.to_path_buf(), metadata);
+        }
+    }
+
+    /// Brings the metadata kept for `path` up to date after `event`.
+    pub(crate) fn update(&mut self, path: &Path, event: &FileEvent) {
+        match event {
+            FileEvent::Created | FileEvent::Modified | FileEvent::Replaced(_) => {
+                if let Some(metadata) = FileMetadata::read(path) {
+                    self.known.insert(path.to_path_buf(), metadata);
+                }
+            }
+            FileEvent::Renamed { from, to } => {
+                if let Some(metadata) = self.known.remove(from) {
+                    self.known.insert(to.clone(), metadata);
+                }
+            }
+            FileEvent::Deleted | FileEvent::Trashed(_) => {
+                self.known.remove(path);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::error::{MonitorError, Result};
+use log::debug;
+use notify::{Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Which notify watcher observes a watch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WatchBackend {
+    /// The platform's change notifications (inotify, FSEvents, ReadDirectoryChangesW).
+    #[default]
+    Native,
+    /// Rescans the watch every `interval`, for filesystems that do not deliver change
+    /// notifications, such as NFS, SMB and many FUSE mounts. With `compare_contents`,
+    /// files are hashed on each scan so changes that keep size and mtime are seen too.
+    Poll {
+        interval: Duration,
+        compare_contents: bool,
+    },
+    /// fanotify on Linux: the same changes as the native backend, with the PID and
+    /// executable of the process that made them. Needs `CAP_SYS_ADMIN`.
+    Fanotify,
+}
+
+impl WatchBackend {
+    pub fn poll(interval: Duration) -> Self {
+        WatchBackend::Poll {
+            interval,
+            compare_contents: false,
+        }
+    }
+
+    fn notify_config(&self) -> notify::Config {
+        match *self {
+            WatchBackend::Native | WatchBackend::Fanotify => notify::Config::default(),
+            WatchBackend::Poll {
+                interval,
+                compare_contents,
+            } => notify::Config::default()
+                .with_poll_interval(interval)
+                .with_compare_contents(compare_contents),
+        }
+    }
+}
+
+impl fmt::Display for WatchBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchBackend::Native => write!(f, "native"),
+            WatchBackend::Fanotify => write!(f, "fanotify, with process attribution"),
+            WatchBackend::Poll {
+                interval,
+                compare_contents,
+            } => {
+                write!(f, "poll every {:?}", interval)?;
+                if *compare_contents {
+                    write!(f, ", comparing contents")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What is watched for a watched path that is a symlink. Symlinks inside a watched
+/// directory are entries like any other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// The file or directory the link points to, resolved when the watch is set up.
+    /// Changes to it are recorded under the link's path; retargeting or removing the
+    /// link itself goes unnoticed.
+    #[default]
+    Follow,
+    /// The link itself, through its parent directory: it being created, replaced or
+    /// removed, but not changes to what it points to.
+    Link,
+    /// Both the target and the link.
+    Both,
+}
+
+impl SymlinkPolicy {
+    /// Paths the watchers observe for a watch of `path` with `mode`.
+    fn targets(self, path: &Path, mode: RecursiveMode) -> Vec<(PathBuf, RecursiveMode)> {
+        let watched = (path.to_path_buf(), mode);
+        let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        else {
+            return vec![watched];
+        };
+        let parent = (parent.to_path_buf(), RecursiveMode::NonRecursive);
+        match self {
+            SymlinkPolicy::Follow => vec![watched],
+            SymlinkPolicy::Link => vec![parent],
+            SymlinkPolicy::Both => vec![watched, parent],
+        }
+    }
+}
+
+impl fmt::Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymlinkPolicy::Follow => write!(f, "follow"),
+            SymlinkPolicy::Link => write!(f, "link"),
+            SymlinkPolicy::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Whether `path` itself is a symlink.
+pub(crate) fn is_symlink(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+pub(crate) type EventHandler = Arc<dyn Fn(notify::Result<Event>) + Send + Sync>;
+
+/// A running watcher of one [`WatchBackend`], shared by the watches using that backend.
+pub(crate) trait BackendWatcher: Send {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()>;
+
+    fn unwatch(&mut self, path: &Path) -> Result<()>;
+}
+
+impl BackendWatcher for RecommendedWatcher {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+        Watcher::watch(self, path, mode).map_err(|source| watch_failed(path, source))
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        Ok(Watcher::unwatch(self, path)?)
+    }
+}
+
+impl BackendWatcher for PollWatcher {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+        Watcher::watch(self, path, mode).map_err(|source| watch_failed(path, source))?;
+        debug!("Polling {}", path.display());
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        Ok(Watcher::unwatch(self, path)?)
+    }
+}
+
+fn watch_failed(path: &Path, source: notify::Error) -> MonitorError {
+    MonitorError::WatchFailed {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+impl WatchBackend {
+    /// Starts a watcher of this backend that passes its events to `handler`.
+    fn start(&self, handler: EventHandler) -> Result<Box<dyn BackendWatcher>> {
+        let config = self.notify_config();
+        match self {
+            WatchBackend::Native => Ok(Box::new(RecommendedWatcher::new(
+                move |res| handler(res),
+                config,
+            )?)),
+            WatchBackend::Poll { .. } => {
+                Ok(Box::new(PollWatcher::new(move |res| handler(res), config)?))
+            }
+            #[cfg(target_os = "linux")]
+            WatchBackend::Fanotify => Ok(Box::new(crate::fanotify::FanotifyWatcher::new(handler)?)),
+            #[cfg(not(target_os = "linux"))]
+            WatchBackend::Fanotify => Err(monitor_error!("fanotify is only available on Linux")),
+        }
+    }
+}
+
+/// The watchers of a monitor, one per backend in use, each started with the first watch
+/// using it and stopped with the last.
+pub(crate) struct Watchers {
+    handler: EventHandler,
+    backends: HashMap<PathBuf, WatchBackend>,
+    default_backend: WatchBackend,
+    running: HashMap<WatchBackend, Box<dyn BackendWatcher>>,
+    watched: HashMap<PathBuf, WatchBackend>,
+    symlinks: SymlinkPolicy,
+    /// What each watch observes: its path, unless it is a symlink, see [`SymlinkPolicy`].
+    targets: HashMap<PathBuf, Vec<(PathBuf, RecursiveMode)>>,
+    /// The watches that were symlinks when watched, shared with the monitor.
+    links: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl Watchers {
+    /// `backends` are by watch path; other paths use `default_backend`. `links` is
+    /// cleared and then kept up to date with the watches that are symlinks.
+    pub(crate) fn new<F>(
+        backends: HashMap<PathBuf, WatchBackend>,
+        default_backend: WatchBackend,
+        symlinks: SymlinkPolicy,
+        links: Arc<Mutex<Vec<PathBuf>>>,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(notify::Result<Event>) + Send + Sync + 'static,
+    {
+        links.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Self {
+            handler: Arc::new(handler),
+            backends,
+            default_backend,
+            running: HashMap::new(),
+            watched: HashMap::new(),
+            symlinks,
+            targets: HashMap::new(),
+            links,
+        }
+    }
+
+    pub(crate) fn backend(&self, path: &Path) -> WatchBackend {
+        self.backends
+            .get(path)
+            .copied()
+            .unwrap_or(self.default_backend)
+    }
+
+    pub(crate) fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+        let backend = self.backend(path);
+        let watcher = match self.running.entry(backend) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(backend.start(Arc::clone(&self.handler))?),
+        };
+        let is_link = is_symlink(path);
+        let targets = if is_link {
+            self.symlinks.targets(path, mode)
+        } else {
+            vec![(path.to_path_buf(), mode)]
+        };
+        // A directory watched for a link may already be observed for another watch,
+        // recursively or the same way.
+        for (target, target_mode) in &targets {
+            let observed = self
+                .targets
+                .iter()
+                .filter(|(watch, _)| watch.as_path() != path)
+                .flat_map(|(_, targets)| targets)
+                .any(|(other, other_mode)| {
+                    other == target
+                        && (other_mode == target_mode || *other_mode == RecursiveMode::Recursive)
+                });
+            if !observed {
+                watcher.watch(target, *target_mode)?;
+            }
+        }
+        self.watched.insert(path.to_path_buf(), backend);
+        self.targets.insert(path.to_path_buf(), targets);
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        links.retain(|link| link != path);
+        if is_link {
+            links.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let Some(backend) = self.watched.remove(path) else {
+            return Err(MonitorError::NotWatching(path.to_path_buf()));
+        };
+        self.links
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|link| link != path);
+        let targets = self.targets.remove(path).unwrap_or_default();
+        if let Some(watcher) = self.running.get_mut(&backend) {
+            for (target, _) in targets {
+                let observed = self
+                    .targets
+                    .values()
+                    .flatten()
+                    .any(|(other, _)| *other == target);
+                if !observed {
+                    watcher.unwatch(&target)?;
+                }
+            }
+        }
+        // Dropping a watcher stops its threads, e.g. the scans of a poll watcher.
+        if !self.watched.values().any(|watched| *watched == backend) {
+            self.running.remove(&backend);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::error::{monitor_error, MonitorError, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+pub const DEFAULT_BACKUP_VERSIONS: usize = 10;
+pub const DEFAULT_BACKUP_MAX_FILE_BYTES: u64 = 100 * 1024 * 1024;
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3f";
+
+/// Where versions of changed files are kept and how many.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupPolicy {
+    pub dir: PathBuf,
+    /// Versions kept per file; older ones are deleted.
+    pub max_versions: usize,
+    /// Larger files are not backed up.
+    pub max_file_bytes: u64,
+}
+
+impl BackupPolicy {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_versions: DEFAULT_BACKUP_VERSIONS,
+            max_file_bytes: DEFAULT_BACKUP_MAX_FILE_BYTES,
+        }
+    }
+}
+
+/// One backed-up copy of a file. `id` is its path relative to the backup directory,
+/// as accepted by [`BackupStore::restore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackupVersion {
+    pub id: String,
+    pub path: PathBuf,
+    pub time: DateTime<Local>,
+    pub size: u64,
+}
+
+/// Versioned copies of files, stored as `<dir>/<absolute path>.<timestamp>` so every
+/// version can be traced back to the file it belongs to.
+#[derive(Debug)]
+pub struct BackupStore {
+    policy: BackupPolicy,
+    /// Serializes changes, so concurrent backups of a file do not prune the same version.
+    lock: Mutex<()>,
+}
+
+impl BackupStore {
+    pub fn new(policy: BackupPolicy) -> Self {
+        Self {
+            policy,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn policy(&self) -> &BackupPolicy {
+        &self.policy
+    }
+
+    /// Whether `path` is inside the backup directory, whose own changes are not events.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.policy.dir)
+    }
+
+    /// Copies `path` as a new version, unless it is not a regular file, is empty (as it
+    /// briefly is while being rewritten), is over the size limit or matches the latest
+    /// version. Prunes versions beyond the retention limit. Blocks while copying.
+    pub fn backup(&self, path: &Path) -> Result<Option<BackupVersion>> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        if !metadata.is_file() || metadata.len() == 0 || metadata.len() > self.policy.max_file_bytes
+        {
+            return Ok(None);
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut versions = self.versions(path)?;
+        if let Some(latest) = versions.last() {
+            if latest.size == metadata.len()
+                && std::fs::read(self.policy.dir.join(&latest.id))? == std::fs::read(path)?
+            {
+                return Ok(None);
+            }
+        }
+        let time = Local::now();
+        let id = format!(
+            "{}.{}",
+            encode_path(path)?.display(),
+            time.format(TIMESTAMP_FORMAT)
+        );
+        let target = self.policy.dir.join(&id);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Copied under a name `versions` ignores and renamed once the old versions are
+        // pruned, so a listing never shows more than `max_versions`.
+        let partial = PathBuf::from(format!("{}.partial", target.display()));
+        std::fs::copy(path, &partial)?;
+        let excess = (versions.len() + 1).saturating_sub(self.policy.max_versions.max(1));
+        for old in versions.drain(..excess.min(versions.len())) {
+            std::fs::remove_file(self.policy.dir.join(&old.id))?;
+        }
+        std::fs::rename(&partial, &target)?;
+        Ok(Some(BackupVersion {
+            id,
+            path: path.to_path_buf(),
+            time,
+            size: metadata.len(),
+        }))
+    }
+
+    /// Versions of `path`, oldest first.
+    pub fn versions(&self, path: &Path) -> Result<Vec<BackupVersion>> {
+        let encoded = self.policy.dir.join(encode_path(path)?);
+        let (Some(parent), Some(name)) = (
+            encoded.parent(),
+            encoded.file_name().and_then(|name| name.to_str()),
+        ) else {
+            return Ok(Vec::new());
+        };
+        let entries = match std::fs::read_dir(parent) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let prefix = format!("{}.", name);
+        let mut versions = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let Some(time) = file_name.strip_prefix(&prefix).and_then(parse_timestamp) else {
+                continue;
+            };
+            let id = entry
+                .path()
+                .strip_prefix(&self.policy.dir)
+                .map_err(|e| monitor_error!("{}", e))?
+                .to_string_lossy()
+                .to_string();
+            versions.push(BackupVersion {
+                id,
+                path: path.to_path_buf(),
+                time,
+                size: entry.metadata()?.len(),
+            });
+        }
+        versions.sort_by_key(|version| version.time);
+        Ok(versions)
+    }
+
+    /// Copies version `id` back over the file it was taken from, recreating it if it was
+    /// deleted. Returns the restored path.
+    pub fn restore(&self, id: &str) -> Result<PathBuf> {
+        let relative = Path::new(id);
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(monitor_error!("Invalid backup version: {}", id));
+        }
+        let (encoded, _) = id
+            .rsplit_once('.')
+            .filter(|(_, timestamp)| parse_timestamp(timestamp).is_some())
+            .ok_or_else(|| monitor_error!("Invalid backup version: {}", id))?;
+        let source = self.policy.dir.join(relative);
+        if !source.is_file() {
+            return Err(MonitorError::NotFound {
+                kind: "backup version",
+                name: id.to_string(),
+            });
+        }
+        let target = decode_path(Path::new(encoded));
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&source, &target)?;
+        Ok(target)
+    }
+}
+
+/// The absolute path as a relative one, with a Windows drive prefix as its first part.
+fn encode_path(path: &Path) -> Result<PathBuf> {
+    let mut encoded = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                encoded.push(prefix.as_os_str().to_string_lossy().replace(':', ""))
+            }
+            Component::RootDir => {}
+            Component::Normal(part) => encoded.push(part),
+            _ => {
+                return Err(monitor_error!(
+                    "Cannot back up relative path {}",
+                    path.display()
+                ))
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+fn decode_path(encoded: &Path) -> PathBuf {
+    if cfg!(windows) {
+        let mut components = encoded.components();
+        let drive = components
+            .next()
+            .map(|drive| drive.as_os_str().to_string_lossy().to_string())
+            .unwrap_or_default();
+        PathBuf::from(format!("{}:\\", drive)).join(components.as_path())
+    } else {
+        Path::new("/").join(encoded)
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Local>> {
+    let naive = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
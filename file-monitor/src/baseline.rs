@@ -0,0 +1,141 @@
+use crate::error::{monitor_error, Result};
+use crate::filter::PathFilter;
+use crate::hashing::{ContentHash, ContentHasher, HashPolicy};
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A watched path covered by a baseline, scanned to `max_depth` levels below it (all of
+/// them when `None`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineRoot {
+    pub path: PathBuf,
+    pub max_depth: Option<usize>,
+    pub policy: HashPolicy,
+}
+
+/// How a file differs from the baseline.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Drift {
+    Added,
+    Removed,
+    /// Which of `size`, `modified` and `content` changed.
+    Changed(Vec<String>),
+}
+
+/// Size, modification time and hash of every watched file at one point in time, to
+/// check later for drift like an integrity checker (AIDE, Tripwire) does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub created: DateTime<Local>,
+    pub roots: Vec<BaselineRoot>,
+    pub files: BTreeMap<PathBuf, ContentHash>,
+}
+
+impl Baseline {
+    /// Records every regular file below `roots` that `filter` allows. Symlinks are not
+    /// followed. Blocks while reading the files.
+    pub fn create(roots: Vec<BaselineRoot>, filter: &PathFilter) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for root in &roots {
+            let mut hasher = ContentHasher::new(root.policy);
+            scan(&root.path, root.max_depth, filter, &mut hasher, &mut files)?;
+        }
+        Ok(Self {
+            created: Local::now(),
+            roots,
+            files,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        serde_json::from_str(&content)
+            .map_err(|e| monitor_error!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Scans the baseline's roots again and lists the files that drifted.
+    pub fn verify(&self, filter: &PathFilter) -> Result<Vec<(PathBuf, Drift)>> {
+        let current = Self::create(self.roots.clone(), filter)?;
+        Ok(self.diff(&current))
+    }
+
+    pub fn diff(&self, current: &Baseline) -> Vec<(PathBuf, Drift)> {
+        let mut drift = Vec::new();
+        for (path, recorded) in &self.files {
+            match current.files.get(path) {
+                None => drift.push((path.clone(), Drift::Removed)),
+                Some(now) => {
+                    let mut changed = Vec::new();
+                    if now.size != recorded.size {
+                        changed.push("size".to_string());
+                    }
+                    if now.modified != recorded.modified {
+                        changed.push("modified".to_string());
+                    }
+                    if now.sha256 != recorded.sha256 {
+                        changed.push("content".to_string());
+                    }
+                    if !changed.is_empty() {
+                        drift.push((path.clone(), Drift::Changed(changed)));
+                    }
+                }
+            }
+        }
+        for path in current.files.keys() {
+            if !self.files.contains_key(path) {
+                drift.push((path.clone(), Drift::Added));
+            }
+        }
+        drift
+    }
+}
+
+fn scan(
+    path: &Path,
+    depth_left: Option<usize>,
+    filter: &PathFilter,
+    hasher: &mut ContentHasher,
+    files: &mut BTreeMap<PathBuf, ContentHash>,
+) -> Result<()> {
+    let file_type = std::fs::symlink_metadata(path)?.file_type();
+    if file_type.is_file() {
+        if filter.allows(path) {
+            match hasher.hash(path) {
+                Ok(hash) => {
+                    files.insert(path.to_path_buf(), hash);
+                }
+                Err(e) => warn!("Failed to hash {} for the baseline: {}", path.display(), e),
+            }
+            hasher.forget(path);
+        }
+        return Ok(());
+    }
+    if !file_type.is_dir() || depth_left == Some(0) {
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if let Err(e) = scan(
+            &entry,
+            depth_left.map(|depth| depth - 1),
+            filter,
+            hasher,
+            files,
+        ) {
+            warn!("Failed to scan {} for the baseline: {}", entry.display(), e);
+        }
+    }
+    Ok(())
+}
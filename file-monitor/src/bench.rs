@@ -0,0 +1,251 @@
+use crate::builder::DEFAULT_CHANNEL_CAPACITY;
+use crate::error::{monitor_error, Result};
+use crate::shutdown::ShutdownToken;
+use crate::FileMonitor;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the load generator catches up with its target rate.
+const WRITE_TICK: Duration = Duration::from_millis(10);
+/// The run ends once no event arrived for this long after the last write.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+const MAX_SETTLE_TIME: Duration = Duration::from_secs(10);
+
+/// Synthetic load for [`run`]: `events_per_sec` appends spread over `files` files for
+/// `duration`, against a monitor with the given queue and debounce settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub files: usize,
+    pub events_per_sec: u64,
+    pub duration: Duration,
+    pub channel_capacity: usize,
+    pub debounce: Duration,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            files: 100,
+            events_per_sec: 1000,
+            duration: Duration::from_secs(5),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            debounce: Duration::ZERO,
+        }
+    }
+}
+
+/// End-to-end results of a benchmark run. Latency is measured from a write until the
+/// monitor recorded an event for the file; each event is attributed to the oldest write
+/// on its file not accounted for yet, so with coalesced writes it is an upper bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub config: BenchConfig,
+    pub generated: usize,
+    pub recorded: usize,
+    pub elapsed: Duration,
+    /// Sorted latencies of the recorded events.
+    pub latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    /// Writes that produced no event: coalesced by the kernel, debounced, or dropped.
+    pub fn unrecorded(&self) -> usize {
+        self.generated.saturating_sub(self.recorded)
+    }
+
+    pub fn throughput(&self) -> f64 {
+        self.recorded as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency below which `percent` of the recorded events were.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        let index = ((last as f64) * percent / 100.0).round() as usize;
+        self.latencies.get(index.min(last)).copied()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        let _ = writeln!(
+            summary,
+            "Benchmark: {} files, {} events/s for {:?}, channel capacity {}, debounce {:?}",
+            self.config.files,
+            self.config.events_per_sec,
+            self.config.duration,
+            self.config.channel_capacity,
+            self.config.debounce
+        );
+        let _ = writeln!(
+            summary,
+            "  generated {} writes, recorded {} events in {:?} ({:.0} events/s)",
+            self.generated,
+            self.recorded,
+            self.elapsed,
+            self.throughput()
+        );
+        let _ = writeln!(
+            summary,
+            "  unrecorded (coalesced, debounced or dropped): {} ({:.1}%)",
+            self.unrecorded(),
+            self.unrecorded() as f64 * 100.0 / self.generated.max(1) as f64
+        );
+        match (
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.latencies.last(),
+        ) {
+            (Some(p50), Some(p90), Some(p99), Some(max)) => {
+                let _ = writeln!(
+                    summary,
+                    "  latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                    p50, p90, p99, max
+                );
+            }
+            _ => summary.push_str("  latency: no events recorded\n"),
+        }
+        summary
+    }
+}
+
+/// Removes the benchmark directory however the run ends.
+struct ScratchDir(PathBuf);
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs a monitor on a fresh temp directory, generates the configured load and measures
+/// how much of it the pipeline recorded and how fast.
+pub async fn run(config: BenchConfig) -> Result<BenchReport> {
+    if config.files == 0 || config.events_per_sec == 0 {
+        return Err(monitor_error!(
+            "The benchmark needs at least one file and one event/s"
+        ));
+    }
+    let mut suffix = [0u8; 8];
+    getrandom::getrandom(&mut suffix).map_err(|e| monitor_error!("{}", e))?;
+    let dir = ScratchDir(std::env::temp_dir().join(format!(
+        "file-monitor-bench-{}",
+        crate::config_guard::to_hex(&suffix)
+    )));
+    std::fs::create_dir(&dir.0)?;
+    // Events carry the resolved path, e.g. /private/var rather than /var on macOS.
+    let root = std::fs::canonicalize(&dir.0)?;
+    let files: Vec<PathBuf> = (0..config.files)
+        .map(|i| root.join(format!("file-{}.log", i)))
+        .collect();
+    for file in &files {
+        std::fs::write(file, b"")?;
+    }
+
+    let shutdown = ShutdownToken::new();
+    let monitor = Arc::new(
+        FileMonitor::builder(&root)
+            .channel_capacity(config.channel_capacity)
+            .debounce(config.debounce)
+            .history_size(1)
+            .shutdown_token(shutdown.child())
+            .build(),
+    );
+    let recorded: Arc<Mutex<Vec<(PathBuf, Instant)>>> = Arc::default();
+    let hook_recorded = Arc::clone(&recorded);
+    monitor
+        .on_event(move |record| {
+            hook_recorded
+                .lock()
+                .unwrap()
+                .push((record.path.clone(), Instant::now()))
+        })
+        .await;
+    let task_monitor = Arc::clone(&monitor);
+    let task = tokio::spawn(async move { task_monitor.monitor().await });
+    monitor.wait_until_watching().await;
+
+    let writes = generate_load(&files, config).await?;
+    let last_write = Instant::now();
+    let mut seen = 0;
+    let mut last_change = Instant::now();
+    while last_write.elapsed() < MAX_SETTLE_TIME {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let count = recorded.lock().unwrap().len();
+        if count != seen {
+            seen = count;
+            last_change = Instant::now();
+        } else if last_change.elapsed() >= SETTLE_TIME {
+            break;
+        }
+    }
+    shutdown.cancel();
+    task.await??;
+
+    let recorded = std::mem::take(&mut *recorded.lock().unwrap());
+    Ok(report(config, writes, recorded))
+}
+
+/// Appends a line to the files round-robin at the configured rate. Returns when each
+/// write happened, per file.
+async fn generate_load(
+    files: &[PathBuf],
+    config: BenchConfig,
+) -> Result<HashMap<PathBuf, VecDeque<Instant>>> {
+    let mut handles = files
+        .iter()
+        .map(|path| std::fs::OpenOptions::new().append(true).open(path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let mut writes: HashMap<PathBuf, VecDeque<Instant>> = HashMap::new();
+    let started = Instant::now();
+    let mut written: u64 = 0;
+    let mut tick = tokio::time::interval(WRITE_TICK);
+    while started.elapsed() < config.duration {
+        tick.tick().await;
+        let target = (started.elapsed().as_secs_f64() * config.events_per_sec as f64) as u64;
+        while written < target {
+            let index = written as usize % files.len();
+            writeln!(handles[index], "event {}", written)?;
+            writes
+                .entry(files[index].clone())
+                .or_default()
+                .push_back(Instant::now());
+            written += 1;
+        }
+    }
+    Ok(writes)
+}
+
+fn report(
+    config: BenchConfig,
+    mut writes: HashMap<PathBuf, VecDeque<Instant>>,
+    recorded: Vec<(PathBuf, Instant)>,
+) -> BenchReport {
+    let generated = writes.values().map(VecDeque::len).sum();
+    let first_write = writes
+        .values()
+        .filter_map(|times| times.front())
+        .min()
+        .copied();
+    let elapsed = match (first_write, recorded.last()) {
+        (Some(first), Some((_, last))) => last.saturating_duration_since(first),
+        _ => Duration::ZERO,
+    };
+    let mut latencies = Vec::new();
+    for (path, at) in &recorded {
+        if let Some(written) = writes.get_mut(path).and_then(VecDeque::pop_front) {
+            latencies.push(at.saturating_duration_since(written));
+        }
+    }
+    latencies.sort();
+    BenchReport {
+        config,
+        generated,
+        recorded: recorded.len(),
+        elapsed: elapsed.max(Duration::from_millis(1)),
+        latencies,
+    }
+}
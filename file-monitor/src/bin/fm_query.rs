@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use file_monitor_core::query::{self, GroupBy, HistoryQuery};
+use std::path::PathBuf;
+
+/// Offline analysis of history files saved by the file monitor.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Filters {
+    /// Only events of this kind (opened, modified, deleted, renamed, created, closed,
+    /// permissions_changed, ownership_changed, timestamp_changed)
+    #[arg(long)]
+    event: Option<String>,
+
+    /// Only events on paths below this prefix
+    #[arg(long)]
+    path_prefix: Option<PathBuf>,
+
+    /// Only events at or after this time (RFC 3339)
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Only events before this time (RFC 3339)
+    #[arg(long)]
+    until: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the matching events
+    Filter {
+        file: PathBuf,
+        #[command(flatten)]
+        filters: Filters,
+        /// Print events as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Count matching events per group
+    Stats {
+        file: PathBuf,
+        #[command(flatten)]
+        filters: Filters,
+        #[arg(long, value_enum, default_value_t = Group::Event)]
+        by: Group,
+    },
+    /// Show the N largest groups of matching events
+    Top {
+        file: PathBuf,
+        #[command(flatten)]
+        filters: Filters,
+        #[arg(long, value_enum, default_value_t = Group::Path)]
+        by: Group,
+        #[arg(short, long, default_value_t = 10)]
+        n: usize,
+    },
+    /// Compare per-group counts of two history files
+    Diff {
+        left: PathBuf,
+        right: PathBuf,
+        #[command(flatten)]
+        filters: Filters,
+        #[arg(long, value_enum, default_value_t = Group::Path)]
+        by: Group,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Group {
+    Event,
+    Path,
+    Watch,
+    Hour,
+}
+
+impl From<Group> for GroupBy {
+    fn from(group: Group) -> Self {
+        match group {
+            Group::Event => GroupBy::Event,
+            Group::Path => GroupBy::Path,
+            Group::Watch => GroupBy::Watch,
+            Group::Hour => GroupBy::Hour,
+        }
+    }
+}
+
+impl Filters {
+    fn to_query(&self) -> Result<HistoryQuery> {
+        Ok(HistoryQuery {
+            event: self.event.clone(),
+            path_prefix: self.path_prefix.clone(),
+            since: self.since.as_deref().map(parse_time).transpose()?,
+            until: self.until.as_deref().map(parse_time).transpose()?,
+        })
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Local))
+        .map_err(|e| anyhow!("Invalid time {}: {}", value, e))
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Filter {
+            file,
+            filters,
+            json,
+        } => {
+            let history = query::read_history_file(file)?;
+            for record in filters.to_query()?.apply(&history) {
+                if json {
+                    println!("{}", serde_json::to_string(record)?);
+                } else {
+                    println!(
+                        "{} - {:?} - {}",
+                        record.time,
+                        record.event,
+                        record.path.display()
+                    );
+                }
+            }
+        }
+        Command::Stats { file, filters, by } => {
+            let history = query::read_history_file(file)?;
+            let matching = filters.to_query()?.apply(&history);
+            for (key, count) in query::aggregate(matching, by.into()) {
+                println!("{:>8}  {}", count, key);
+            }
+        }
+        Command::Top {
+            file,
+            filters,
+            by,
+            n,
+        } => {
+            let history = query::read_history_file(file)?;
+            let matching = filters.to_query()?.apply(&history);
+            for (key, count) in query::aggregate(matching, by.into()).into_iter().take(n) {
+                println!("{:>8}  {}", count, key);
+            }
+        }
+        Command::Diff {
+            left,
+            right,
+            filters,
+            by,
+        } => {
+            let history_query = filters.to_query()?;
+            let left: Vec<_> = history_query
+                .apply(&query::read_history_file(left)?)
+                .into_iter()
+                .cloned()
+                .collect();
+            let right: Vec<_> = history_query
+                .apply(&query::read_history_file(right)?)
+                .into_iter()
+                .cloned()
+                .collect();
+            for entry in query::diff(&left, &right, by.into()) {
+                let delta = entry.right as i64 - entry.left as i64;
+                println!(
+                    "{:>8} {:>8} {:>+8}  {}",
+                    entry.left, entry.right, delta, entry.key
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,691 @@
+use crate::alerts::{RateAlertRule, RateAlertState};
+use crate::atomic_save::{AtomicSaveCoalescer, DEFAULT_ATOMIC_SAVE_WINDOW};
+use crate::backend::{SymlinkPolicy, WatchBackend};
+use crate::backup::{BackupPolicy, BackupStore};
+use crate::config::MonitorConfig;
+use crate::config_guard::ConfigGuard;
+use crate::container::ContainerResolver;
+use crate::coverage::CoverageTracker;
+use crate::diff::{TextSnapshots, DEFAULT_DIFF_MAX_BYTES};
+use crate::enrich::Enricher;
+use crate::error::{invalid_config, Result};
+use crate::escalation::{AlertEscalator, EscalationPolicy};
+use crate::filter::PathFilter;
+use crate::hashing::{ContentHasher, HashPolicy};
+use crate::rules::EventRule;
+use crate::shell_hook::{
+    ShellHook, ShellHooks, DEFAULT_SHELL_HOOK_CONCURRENCY, DEFAULT_SHELL_HOOK_TIMEOUT,
+};
+use crate::shutdown::ShutdownToken;
+use crate::spill::{MemoryLimits, SpillQueue};
+use crate::throttle::{BreakerConfig, RateLimit};
+use crate::trash::Trash;
+use crate::watchset::Watchset;
+use crate::webhook::{RetryPolicy, DEFAULT_WEBHOOK_BREAKER, DEFAULT_WEBHOOK_RATE_LIMIT};
+use crate::{absolute_path, FileMonitor, WatchMode};
+use log::error;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 100;
+pub const DEFAULT_PRIORITY_CHANNEL_CAPACITY: usize = 100;
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 256;
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// Configures a [`FileMonitor`] before it starts watching.
+pub struct FileMonitorBuilder {
+    initial_path: PathBuf,
+    channel_capacity: usize,
+    drop_when_full: bool,
+    /// Unset means [`DEFAULT_PRIORITY_CHANNEL_CAPACITY`], or the memory limit's queue.
+    priority_channel_capacity: Option<usize>,
+    priority_paths: Vec<PathBuf>,
+    rules: Vec<Box<dyn EventRule>>,
+    enrichers: Vec<Enricher>,
+    watch_mode: WatchMode,
+    git_integration: bool,
+    container_awareness: bool,
+    full_enrichment: bool,
+    subscriber_capacity: usize,
+    coverage_file: Option<PathBuf>,
+    heartbeat_interval: Duration,
+    policy_file: Option<PathBuf>,
+    config_manifest: Option<(PathBuf, Vec<u8>)>,
+    config_file: Option<(PathBuf, MonitorConfig)>,
+    shutdown: ShutdownToken,
+    webhook_retry: RetryPolicy,
+    webhook_limits: (RateLimit, BreakerConfig),
+    watches: Vec<PathBuf>,
+    watchsets: Vec<Watchset>,
+    path_substitutions: HashMap<PathBuf, PathBuf>,
+    filters: PathFilter,
+    history_size: usize,
+    history_max_age: Option<Duration>,
+    rate_alerts: Vec<RateAlertRule>,
+    debounce: Duration,
+    atomic_save_window: Duration,
+    content_hashing: Vec<(PathBuf, HashPolicy)>,
+    backends: Vec<(PathBuf, WatchBackend)>,
+    default_backend: WatchBackend,
+    symlink_policy: SymlinkPolicy,
+    follow_names: bool,
+    allow_missing: bool,
+    canonicalize_paths: bool,
+    trash_detection: bool,
+    trashes: Vec<Trash>,
+    isolate_callback: bool,
+    tailed: Vec<PathBuf>,
+    diff_max_bytes: u64,
+    backups: Option<BackupPolicy>,
+    shell_hooks: Vec<ShellHook>,
+    shell_hook_limits: (usize, Duration),
+    escalation: Option<EscalationPolicy>,
+    memory_limits: Option<MemoryLimits>,
+}
+
+impl FileMonitorBuilder {
+    pub fn new<P: AsRef<Path>>(initial_path: P) -> Self {
+        Self {
+            initial_path: initial_path.as_ref().to_path_buf(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            drop_when_full: false,
+            priority_channel_capacity: None,
+            priority_paths: Vec::new(),
+            rules: Vec::new(),
+            enrichers: Vec::new(),
+            watch_mode: WatchMode::default(),
+            git_integration: false,
+            container_awareness: false,
+            full_enrichment: false,
+            subscriber_capacity: DEFAULT_SUBSCRIBER_CAPACITY,
+            coverage_file: None,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            policy_file: None,
+            config_manifest: None,
+            config_file: None,
+            shutdown: ShutdownToken::new(),
+            webhook_retry: RetryPolicy::default(),
+            webhook_limits: (DEFAULT_WEBHOOK_RATE_LIMIT, DEFAULT_WEBHOOK_BREAKER),
+            watches: Vec::new(),
+            watchsets: Vec::new(),
+            path_substitutions: HashMap::new(),
+            filters: PathFilter::default(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            history_max_age: None,
+            rate_alerts: Vec::new(),
+            debounce: Duration::ZERO,
+            atomic_save_window: DEFAULT_ATOMIC_SAVE_WINDOW,
+            content_hashing: Vec::new(),
+            backends: Vec::new(),
+            default_backend: WatchBackend::default(),
+            symlink_policy: SymlinkPolicy::default(),
+            follow_names: false,
+            allow_missing: false,
+            canonicalize_paths: false,
+            trash_detection: true,
+            trashes: Vec::new(),
+            isolate_callback: false,
+            tailed: Vec::new(),
+            diff_max_bytes: 0,
+            backups: None,
+            shell_hooks: Vec::new(),
+            shell_hook_limits: (DEFAULT_SHELL_HOOK_CONCURRENCY, DEFAULT_SHELL_HOOK_TIMEOUT),
+            escalation: None,
+            memory_limits: None,
+        }
+    }
+
+    /// Watches an additional path alongside the primary one.
+    pub fn watch<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.watches.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Watches the paths of `watchset` and manages them as one unit.
+    pub fn watchset(mut self, watchset: Watchset) -> Self {
+        self.watchsets.push(watchset);
+        self
+    }
+
+    pub fn path_substitution<P: AsRef<Path>>(
+        mut self,
+        original_path: P,
+        substitute_path: P,
+    ) -> Self {
+        self.path_substitutions.insert(
+            original_path.as_ref().to_path_buf(),
+            substitute_path.as_ref().to_path_buf(),
+        );
+        self
+    }
+
+    /// Filters applied from the start, replacing any set before.
+    pub fn filters(mut self, filters: PathFilter) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Number of events kept in the in-memory history.
+    pub fn history_size(mut self, size: usize) -> Self {
+        self.history_size = size.max(1);
+        self
+    }
+
+    /// Drops events older than `max_age` from the in-memory history, in addition to the
+    /// [`FileMonitorBuilder::history_size`] cap. By default events are kept regardless of
+    /// age.
+    pub fn history_max_age(mut self, max_age: Duration) -> Self {
+        self.history_max_age = Some(max_age);
+        self
+    }
+
+    /// Raises an alert whenever events exceed the rule's rate; see [`RateAlertRule`].
+    pub fn rate_alert(mut self, rule: RateAlertRule) -> Self {
+        self.rate_alerts.push(rule);
+        self
+    }
+
+    /// How long events on temp files are held back to recognize atomic saves (a temp file
+    /// written and renamed over the target), which are recorded as a single
+    /// [`crate::FileEvent::Replaced`] on the target. Zero disables this and records every
+    /// event as it arrives. Defaults to [`DEFAULT_ATOMIC_SAVE_WINDOW`].
+    pub fn atomic_save_window(mut self, window: Duration) -> Self {
+        self.atomic_save_window = window;
+        self
+    }
+
+    /// Records a fingerprint of each file under `watch` (the primary path or an added
+    /// watch) after it is created or modified, with a strategy chosen by file size; see
+    /// [`HashPolicy`].
+    pub fn content_hashing<P: AsRef<Path>>(mut self, watch: P, policy: HashPolicy) -> Self {
+        self.content_hashing
+            .push((watch.as_ref().to_path_buf(), policy));
+        self
+    }
+
+    /// Observes `watch` (the primary path or an added watch) with `backend` instead of
+    /// the platform's change notifications, e.g. polling for a network filesystem.
+    pub fn backend<P: AsRef<Path>>(mut self, watch: P, backend: WatchBackend) -> Self {
+        self.backends.push((watch.as_ref().to_path_buf(), backend));
+        self
+    }
+
+    /// Backend of the watches without one of their own; native by default.
+    pub fn default_backend(mut self, backend: WatchBackend) -> Self {
+        self.default_backend = backend;
+        self
+    }
+
+    /// What is watched for watched paths that are symlinks: the target, as by default,
+    /// the link itself, or both. Records of events reached through a followed link name
+    /// it in [`crate::FileEventRecord::via_symlink`].
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Re-establishes watches whose path was deleted or renamed away once the path is
+    /// recreated, like `tail -F`, so log rotation and atomic saves do not end them.
+    pub fn follow_names(mut self, enabled: bool) -> Self {
+        self.follow_names = enabled;
+        self
+    }
+
+    /// Accepts a watched path that does not exist yet, from the start or from
+    /// [`crate::FileMonitor::update_path`], instead of failing with
+    /// [`crate::MonitorError::PathNotFound`]: its parent directory is watched until the
+    /// path is created, then the path itself, and the creation is recorded as
+    /// [`crate::FileEvent::Created`]. The parent has to exist.
+    pub fn allow_missing(mut self, enabled: bool) -> Self {
+        self.allow_missing = enabled;
+        self
+    }
+
+    /// Resolves symlinks in paths passed to [`crate::FileMonitor::update_path`], so the
+    /// target is watched and recorded under its real location. `.` and `..` are always
+    /// resolved.
+    pub fn canonicalize_paths(mut self, enabled: bool) -> Self {
+        self.canonicalize_paths = enabled;
+        self
+    }
+
+    /// Records files moved to the platform's trash as [`crate::FileEvent::Trashed`]
+    /// rather than as deleted. Enabled by default.
+    pub fn trash_detection(mut self, enabled: bool) -> Self {
+        self.trash_detection = enabled;
+        self
+    }
+
+    /// Checks `trash` for trashed files too, e.g. the trash of a network share.
+    pub fn trash(mut self, trash: Trash) -> Self {
+        self.trashes.push(trash);
+        self
+    }
+
+    /// Attaches the lines appended to `path`, or to files below it, to their
+    /// modifications, like `tail -f`.
+    pub fn tail<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.tailed.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs the watcher callback on a dedicated thread whose panics are caught and logged,
+    /// after which the watcher is rebuilt. Otherwise a panic in the callback ends event
+    /// delivery while the monitor keeps running. The thread queues as many events as the
+    /// event queue; beyond that they are dropped and counted like a full event queue.
+    pub fn isolate_callback(mut self, enabled: bool) -> Self {
+        self.isolate_callback = enabled;
+        self
+    }
+
+    /// Keeps the content of watched text files up to `max_bytes` and attaches a unified
+    /// diff to their modifications, see [`crate::FileMonitor::get_history_detail`]. Zero,
+    /// the default, disables diffs.
+    pub fn text_diffs(mut self, max_bytes: u64) -> Self {
+        self.diff_max_bytes = max_bytes;
+        self
+    }
+
+    /// Copies each watched file to a new version in the policy's directory when it is
+    /// created or modified, keeping the newest `max_versions` per file, so it can be rolled
+    /// back with [`crate::FileMonitor::restore_backup`]. Disabled by default.
+    pub fn backups(mut self, policy: BackupPolicy) -> Self {
+        self.backups = Some(policy);
+        self
+    }
+
+    /// Drops repeats of the same event on the same path that arrive within `window` of
+    /// the last recorded one. Zero, the default, records every event.
+    pub fn debounce(mut self, window: Duration) -> Self {
+        self.debounce = window;
+        self
+    }
+
+    /// Caps what the event queue, the debounce cache and the diff snapshots keep in
+    /// memory. Events beyond the queue go to a temporary file instead of being dropped
+    /// or holding up the backend, and are handled in order as the queue drains; see
+    /// [`crate::FileMonitor::get_spilled_events`]. Overrides the regular lane capacity
+    /// and sets the priority lane to the same; an explicit priority lane capacity that
+    /// differs is rejected by [`Self::try_build`].
+    pub fn memory_limits(mut self, limits: MemoryLimits) -> Self {
+        self.memory_limits = Some(limits);
+        self
+    }
+
+    /// Capacity of the lane carrying events for regular paths.
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity.max(1);
+        self
+    }
+
+    /// When the regular lane is full, drop new events instead of holding up the backend
+    /// until there is room. Either way, a backend held up for too long loses events in
+    /// the kernel; dropping them here keeps count, and records how many were lost as an
+    /// [`crate::FileEvent::EventsDropped`] event. With priority paths, regular events
+    /// are always dropped rather than waited for.
+    pub fn drop_when_full(mut self, enabled: bool) -> Self {
+        self.drop_when_full = enabled;
+        self
+    }
+
+    /// Capacity of the dedicated lane carrying events for high-priority paths.
+    pub fn priority_channel_capacity(mut self, capacity: usize) -> Self {
+        self.priority_channel_capacity = Some(capacity.max(1));
+        self
+    }
+
+    /// Marks a path (or everything below it) as high priority. Its events are routed through
+    /// a separate lane that is always drained first and never dropped.
+    pub fn priority_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.priority_paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// How many events each subscriber can fall behind before it starts losing the oldest.
+    pub fn subscriber_capacity(mut self, capacity: usize) -> Self {
+        self.subscriber_capacity = capacity.max(1);
+        self
+    }
+
+    /// Persists coverage accounting (pauses, watcher errors, process downtime derived from
+    /// heartbeats) to this file so it survives restarts.
+    pub fn coverage_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.coverage_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Loads filters from this JSON policy file and hot-reloads them when it changes. Invalid
+    /// changes are rejected with a critical alert.
+    pub fn policy_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.policy_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only accepts policy contents whose hash is listed in this manifest, signed with `key`.
+    pub fn config_manifest<P: AsRef<Path>>(mut self, path: P, key: impl Into<Vec<u8>>) -> Self {
+        self.config_manifest = Some((path.as_ref().to_path_buf(), key.into()));
+        self
+    }
+
+    /// Watches the config file `config` was loaded from and applies changes to paths,
+    /// watches, substitutions and filters live, see [`FileMonitor::reload_config`].
+    pub fn config_file<P: AsRef<Path>>(mut self, path: P, config: MonitorConfig) -> Self {
+        self.config_file = Some((path.as_ref().to_path_buf(), config));
+        self
+    }
+
+    /// Makes [`FileMonitor::monitor`] return once `token` is cancelled, after handling the
+    /// events already queued.
+    pub fn shutdown_token(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// How failed webhook deliveries are retried.
+    pub fn webhook_retry(mut self, retry: RetryPolicy) -> Self {
+        self.webhook_retry = RetryPolicy {
+            max_attempts: retry.max_attempts.max(1),
+            ..retry
+        };
+        self
+    }
+
+    /// Runs `hook` in the background whenever an event of its kind is recorded.
+    pub fn shell_hook(mut self, hook: ShellHook) -> Self {
+        self.shell_hooks.push(hook);
+        self
+    }
+
+    /// How many shell hooks run at once, and how long each may take before it is killed.
+    pub fn shell_hook_limits(mut self, max_concurrent: usize, timeout: Duration) -> Self {
+        self.shell_hook_limits = (max_concurrent, timeout);
+        self
+    }
+
+    /// Rate limit and circuit breaker of each webhook added to the monitor.
+    pub fn webhook_limits(mut self, rate_limit: RateLimit, breaker: BreakerConfig) -> Self {
+        self.webhook_limits = (rate_limit, breaker);
+        self
+    }
+
+    /// How often the monitor writes a heartbeat while running.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Whether to watch only the path itself or the whole tree below it.
+    pub fn watch_mode(mut self, watch_mode: WatchMode) -> Self {
+        self.watch_mode = watch_mode;
+        self
+    }
+
+    /// Enriches events with the state of the git repository they happen in. Runs the `git`
+    /// CLI for every event, so leave it off for busy trees.
+    pub fn git_integration(mut self, enabled: bool) -> Self {
+        self.git_integration = enabled;
+        self
+    }
+
+    /// Tags events on container filesystems (overlayfs layers, bind mount sources) with the
+    /// owning container's ID and name.
+    pub fn container_awareness(mut self, enabled: bool) -> Self {
+        self.container_awareness = enabled;
+        self
+    }
+
+    /// Turns on every enrichment: git and container context, text diffs of at least
+    /// [`DEFAULT_DIFF_MAX_BYTES`], and content hashes of every watch without a
+    /// [`HashPolicy`] of its own. Costs a `git` run and a hash per event, so it suits short
+    /// high-fidelity monitoring such as an investigation.
+    pub fn full_enrichment(mut self) -> Self {
+        self.full_enrichment = true;
+        self
+    }
+
+    /// Adds a rule that classifies events before they are recorded.
+    pub fn rule<R: EventRule + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Escalates critical alerts, and those of the policy's rules, to its steps for as
+    /// long as nobody acknowledges them, see [`crate::FileMonitor::acknowledge_alert`].
+    pub fn escalation(mut self, policy: EscalationPolicy) -> Self {
+        self.escalation = Some(policy);
+        self
+    }
+
+    /// Adds an enricher whose fields are attached to events before rules see them.
+    pub fn enricher(mut self, enricher: Enricher) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Builds the monitor, panicking if the configuration is invalid; see
+    /// [`Self::try_build`].
+    pub fn build(self) -> FileMonitor {
+        match self.try_build() {
+            Ok(monitor) => monitor,
+            Err(e) => panic!("Invalid monitor configuration: {}", e),
+        }
+    }
+
+    /// Builds the monitor. Fails if the spill file, the alert store or the backup
+    /// directory cannot be set up, if a rate alert is invalid or given twice, or if the
+    /// priority lane capacity contradicts the memory limits.
+    pub fn try_build(mut self) -> Result<FileMonitor> {
+        if self.full_enrichment {
+            self.git_integration = true;
+            self.container_awareness = true;
+            self.diff_max_bytes = self.diff_max_bytes.max(DEFAULT_DIFF_MAX_BYTES);
+            // Policies set explicitly come last and win.
+            let mut content_hashing: Vec<_> = std::iter::once(&self.initial_path)
+                .chain(&self.watches)
+                .chain(self.watchsets.iter().flat_map(|watchset| &watchset.paths))
+                .map(|watch| (watch.clone(), HashPolicy::default()))
+                .collect();
+            content_hashing.append(&mut self.content_hashing);
+            self.content_hashing = content_hashing;
+        }
+        let primary = absolute_path(&self.initial_path).unwrap_or(self.initial_path.clone());
+        let mut monitor = FileMonitor::with_path(self.initial_path);
+        let mut priority_channel_capacity = self
+            .priority_channel_capacity
+            .unwrap_or(DEFAULT_PRIORITY_CHANNEL_CAPACITY);
+        if let Some(limits) = &self.memory_limits {
+            let queued = limits.queued_events.max(1);
+            if let Some(capacity) = self.priority_channel_capacity.filter(|&c| c != queued) {
+                return Err(invalid_config!(
+                    "Priority lane capacity {} conflicts with the memory limit of {} queued \
+                     events",
+                    capacity,
+                    queued
+                ));
+            }
+            self.channel_capacity = queued;
+            priority_channel_capacity = queued;
+            monitor.dedup_capacity = limits.dedup_entries.max(1);
+            let spill =
+                SpillQueue::create(&limits.spill_dir, limits.spill_max_bytes).map_err(|e| {
+                    invalid_config!(
+                        "Failed to create spill file in {}: {}",
+                        limits.spill_dir.display(),
+                        e
+                    )
+                })?;
+            monitor.spill = Some(Arc::new(std::sync::Mutex::new(spill)));
+        }
+        monitor.channel_capacity = self.channel_capacity;
+        monitor.drop_when_full = self.drop_when_full;
+        monitor.priority_channel_capacity = priority_channel_capacity;
+        monitor.priority_paths = self.priority_paths;
+        monitor.rules = self.rules;
+        monitor.enrichers = self.enrichers;
+        if let Some(policy) = self.escalation {
+            let store = policy.store.clone();
+            let escalator = AlertEscalator::open(policy).map_err(|e| {
+                invalid_config!("Failed to open alert store {}: {}", store.display(), e)
+            })?;
+            monitor.escalator = Some(Arc::new(escalator));
+        }
+        monitor.watch_mode = self.watch_mode;
+        monitor.git_integration = self.git_integration;
+        monitor.event_tx = tokio::sync::broadcast::channel(self.subscriber_capacity).0;
+        monitor.heartbeat_interval = self.heartbeat_interval;
+        monitor.webhook_retry = self.webhook_retry;
+        monitor.webhook_limits = self.webhook_limits;
+        monitor.history_size = self.history_size;
+        monitor.history_max_age = self.history_max_age;
+        let mut rate_alerts: Vec<RateAlertState> = Vec::new();
+        for rule in self.rate_alerts {
+            rule.validate()?;
+            if rate_alerts.iter().any(|state| state.rule.name == rule.name) {
+                return Err(invalid_config!("Duplicate rate alert {}", rule.name));
+            }
+            rate_alerts.push(RateAlertState::new(rule));
+        }
+        monitor.rate_alerts = Arc::new(Mutex::new(rate_alerts));
+        monitor.debounce = self.debounce;
+        for (watch, policy) in self.content_hashing {
+            match absolute_path(&watch) {
+                Ok(watch) => {
+                    monitor.content_hashers.insert(
+                        watch,
+                        Arc::new(std::sync::Mutex::new(ContentHasher::new(policy))),
+                    );
+                }
+                Err(e) => error!("Failed to resolve hashed watch {}: {}", watch.display(), e),
+            }
+        }
+        monitor.default_backend = self.default_backend;
+        monitor.symlink_policy = self.symlink_policy;
+        monitor.follow_names = Arc::new(Mutex::new(self.follow_names));
+        monitor.allow_missing = self.allow_missing;
+        monitor.canonicalize_paths = self.canonicalize_paths;
+        monitor.trash_detection = self.trash_detection;
+        monitor.trashes = self.trashes;
+        monitor.isolate_callback = self.isolate_callback;
+        for (watch, backend) in self.backends {
+            match absolute_path(&watch) {
+                Ok(watch) => {
+                    monitor.watch_backends.insert(watch, backend);
+                }
+                Err(e) => error!("Failed to resolve watch {}: {}", watch.display(), e),
+            }
+        }
+        if !self.shell_hooks.is_empty() {
+            let (max_concurrent, timeout) = self.shell_hook_limits;
+            monitor.shell_hooks = Some(Arc::new(ShellHooks::new(
+                self.shell_hooks,
+                max_concurrent,
+                timeout,
+            )));
+        }
+        if let Some(mut policy) = self.backups {
+            policy.dir = absolute_path(&policy.dir).map_err(|e| {
+                invalid_config!(
+                    "Failed to resolve backup directory {}: {}",
+                    policy.dir.display(),
+                    e
+                )
+            })?;
+            monitor.backups = Some(Arc::new(BackupStore::new(policy)));
+        }
+        monitor.text_snapshots = (self.diff_max_bytes > 0).then(|| {
+            let snapshots = TextSnapshots::new(self.diff_max_bytes);
+            Arc::new(std::sync::Mutex::new(match &self.memory_limits {
+                Some(limits) => snapshots.with_total_limit(limits.snapshot_bytes),
+                None => snapshots,
+            }))
+        });
+        monitor.atomic_saves = (!self.atomic_save_window.is_zero()).then(|| {
+            Arc::new(Mutex::new(AtomicSaveCoalescer::new(
+                self.atomic_save_window,
+            )))
+        });
+        monitor.filters = Arc::new(Mutex::new(self.filters));
+        monitor.path_substitutions = Arc::new(Mutex::new(self.path_substitutions));
+        let mut watches: Vec<PathBuf> = Vec::new();
+        for watch in self.watches {
+            match absolute_path(&watch) {
+                Ok(watch) if watch != primary && !watches.contains(&watch) => watches.push(watch),
+                Ok(_) => {}
+                Err(e) => error!("Failed to resolve watch {}: {}", watch.display(), e),
+            }
+        }
+        let mut watchsets: BTreeMap<String, Watchset> = BTreeMap::new();
+        for mut watchset in self.watchsets {
+            if watchsets.contains_key(&watchset.name) {
+                error!("Duplicate watchset {}", watchset.name);
+                continue;
+            }
+            let mut paths: Vec<PathBuf> = Vec::new();
+            for path in &watchset.paths {
+                match absolute_path(path) {
+                    Ok(path) if watchsets.values().any(|other| other.contains(&path)) => {
+                        error!(
+                            "{} is already in another watchset, not adding it to {}",
+                            path.display(),
+                            watchset.name
+                        );
+                    }
+                    Ok(path) if !paths.contains(&path) => {
+                        if path != primary && !watches.contains(&path) {
+                            watches.push(path.clone());
+                        }
+                        paths.push(path);
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to resolve watch {}: {}", path.display(), e),
+                }
+            }
+            watchset.paths = paths;
+            watchsets.insert(watchset.name.clone(), watchset);
+        }
+        monitor.extra_watches = Arc::new(Mutex::new(watches));
+        let mut tailed: Vec<PathBuf> = Vec::new();
+        for path in self.tailed {
+            match absolute_path(&path) {
+                Ok(path) if !tailed.contains(&path) => tailed.push(path),
+                Ok(_) => {}
+                Err(e) => error!("Failed to resolve tailed path {}: {}", path.display(), e),
+            }
+        }
+        monitor.tailed = Arc::new(Mutex::new(tailed));
+        monitor.watchsets = Arc::new(Mutex::new(watchsets));
+        let coverage = match &self.coverage_file {
+            Some(path) => {
+                CoverageTracker::load(path, self.heartbeat_interval).unwrap_or_else(|e| {
+                    error!("Failed to load coverage from {}: {}", path.display(), e);
+                    CoverageTracker::new(self.heartbeat_interval)
+                })
+            }
+            None => CoverageTracker::new(self.heartbeat_interval),
+        };
+        monitor.coverage = Arc::new(Mutex::new(coverage));
+        if let Some(policy_file) = self.policy_file {
+            // The manifest lists canonical paths, and notify reports them that way too.
+            let policy_file = std::fs::canonicalize(&policy_file).unwrap_or(policy_file);
+            let mut guard = ConfigGuard::new(policy_file);
+            if let Some((manifest, key)) = self.config_manifest {
+                let manifest = std::fs::canonicalize(&manifest).unwrap_or(manifest);
+                guard = guard.with_manifest(manifest, key);
+            }
+            monitor.config_guard = Some(Arc::new(Mutex::new(guard)));
+        }
+        if let Some((path, config)) = self.config_file {
+            let path = std::fs::canonicalize(&path).unwrap_or(path);
+            monitor.config_file = Some((path, Arc::new(Mutex::new(config))));
+        }
+        monitor.shutdown = self.shutdown;
+        if self.container_awareness {
+            monitor.container_resolver = Some(Arc::new(Mutex::new(ContainerResolver::new())));
+        }
+        Ok(monitor)
+    }
+}
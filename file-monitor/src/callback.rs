@@ -0,0 +1,75 @@
+use log::{debug, error};
+use notify::Event;
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+
+/// Runs the watcher callback on a thread of its own instead of the backends' threads. A
+/// panic in the callback is caught and logged with the event it was handling, and
+/// `on_panic` is called so the monitor can rebuild its watcher; the thread carries on
+/// with the next event. Without this, a panic would end the backend's event thread and
+/// events would silently stop arriving.
+///
+/// At most `capacity` events wait for the thread; further ones are dropped and reported
+/// to `on_full`, so a stuck callback cannot grow the queue without bound.
+pub(crate) struct CallbackThread {
+    events: mpsc::SyncSender<notify::Result<Event>>,
+    on_full: Box<dyn Fn() + Send + Sync>,
+}
+
+impl CallbackThread {
+    pub(crate) fn spawn<F, P, D>(
+        capacity: usize,
+        callback: F,
+        on_panic: P,
+        on_full: D,
+    ) -> std::io::Result<Self>
+    where
+        F: Fn(notify::Result<Event>) + Send + 'static,
+        P: Fn() + Send + 'static,
+        D: Fn() + Send + Sync + 'static,
+    {
+        let (events, received) = mpsc::sync_channel::<notify::Result<Event>>(capacity);
+        thread::Builder::new()
+            .name("watcher-callback".to_string())
+            .spawn(move || {
+                // Ends once every sender, i.e. the watcher, is dropped.
+                for event in received {
+                    let context = match &event {
+                        Ok(event) => format!("{:?} on {:?}", event.kind, event.paths),
+                        Err(e) => format!("watch error {}", e),
+                    };
+                    if let Err(panic) = catch_unwind(AssertUnwindSafe(|| callback(event))) {
+                        error!(
+                            "Watcher callback panicked handling {}: {}",
+                            context,
+                            panic_message(&*panic)
+                        );
+                        on_panic();
+                    }
+                }
+            })?;
+        Ok(Self {
+            events,
+            on_full: Box::new(on_full),
+        })
+    }
+
+    /// Hands an event from a backend to the callback thread, or drops it if the thread is
+    /// too far behind.
+    pub(crate) fn send(&self, event: notify::Result<Event>) {
+        if let Err(TrySendError::Full(event)) = self.events.try_send(event) {
+            debug!("Callback thread queue full, dropping {:?}", event);
+            (self.on_full)();
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
@@ -0,0 +1,577 @@
+use crate::alerts::RateAlertRule;
+use crate::backend::{SymlinkPolicy, WatchBackend, DEFAULT_POLL_INTERVAL};
+use crate::backup::BackupPolicy;
+use crate::builder::FileMonitorBuilder;
+use crate::config_guard::{Policy, PolicyFilter};
+use crate::enrich::Enricher;
+use crate::error::{invalid_config, Result};
+use crate::escalation::{EscalationPolicy, EscalationStep};
+use crate::filter::FilterKind;
+use crate::hashing::HashPolicy;
+use crate::plugins::{self, PluginLimits};
+use crate::shell_hook::{ShellHook, DEFAULT_SHELL_HOOK_CONCURRENCY, DEFAULT_SHELL_HOOK_TIMEOUT};
+use crate::spill::MemoryLimits;
+use crate::watchset::Watchset;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Startup settings read from a TOML file, e.g.
+///
+/// ```toml
+/// path = "/srv/app"
+/// watches = ["/etc/nginx"]
+/// history_size = 1000
+/// history_max_age_hours = 168
+/// debounce_ms = 200
+/// atomic_save_window_ms = 1000
+/// diff_max_kb = 256
+/// log_level = "warn"
+///
+/// [[substitutions]]
+/// original = "/srv/app/current"
+/// substitute = "/srv/app/releases/42"
+///
+/// [[filters]]
+/// kind = "exclude"
+/// pattern = "**/*.swp"
+///
+/// [backups]
+/// dir = "/var/lib/file-monitor/backups"
+/// max_versions = 10
+/// max_file_mb = 100
+///
+/// [shell_hooks]
+/// on_modified = "rsync {path} backup:/srv/"
+/// max_concurrent = 4
+/// timeout_secs = 60
+///
+/// [[content_hashing]]
+/// watch = "/srv/app"
+/// full_max_mb = 64
+/// sampled_max_mb = 4096
+/// incremental = true
+///
+/// [[backends]]
+/// watch = "/mnt/share"
+/// kind = "poll"
+/// poll_interval_ms = 5000
+/// compare_contents = false
+///
+/// [[watchsets]]
+/// name = "prod-configs"
+/// paths = ["/etc/nginx", "/etc/ssl"]
+/// exclude = ["*.bak"]
+/// content_hashing = true
+///
+/// [[rate_alerts]]
+/// name = "mass-delete"
+/// event = "deleted"
+/// threshold = 50
+/// window_secs = 60
+///
+/// [[enrichers]]
+/// name = "cmdb"
+/// command = "/usr/local/bin/cmdb-lookup --json"
+/// timeout_ms = 500
+///
+/// [escalation]
+/// store = "/var/lib/file-monitor/alerts.json"
+/// rules = ["mass-delete"]
+///
+/// [[escalation.steps]]
+/// after_mins = 15
+/// command = "mail -s 'Unacknowledged alert {id}: {reason}' oncall@example.com"
+///
+/// [[escalation.steps]]
+/// after_mins = 60
+/// webhook = "http://pager.internal/alerts"
+///
+/// [plugins]
+/// dir = "/etc/file-monitor/plugins"
+/// fuel = 10000000
+/// memory_kb = 16384
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorConfig {
+    /// Primary path to monitor; `--path` takes precedence.
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub watches: Vec<PathBuf>,
+    #[serde(default)]
+    pub substitutions: Vec<Substitution>,
+    #[serde(default)]
+    pub filters: Vec<PolicyFilter>,
+    #[serde(default)]
+    pub content_hashing: Vec<ContentHashing>,
+    #[serde(default)]
+    pub rate_alerts: Vec<RateAlertRule>,
+    /// Paths (or trees) whose events take the high-priority lane.
+    #[serde(default)]
+    pub priority_paths: Vec<PathBuf>,
+    pub priority_channel_capacity: Option<usize>,
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+    /// `follow`, `link` or `both`, for watched paths that are symlinks.
+    pub symlinks: Option<SymlinkPolicy>,
+    #[serde(default)]
+    pub watchsets: Vec<WatchsetConfig>,
+    pub backups: Option<Backups>,
+    pub shell_hooks: Option<ShellHooksConfig>,
+    #[serde(default)]
+    pub enrichers: Vec<EnricherConfig>,
+    pub escalation: Option<EscalationConfig>,
+    pub plugins: Option<PluginsConfig>,
+    pub memory_limits: Option<MemoryLimitsConfig>,
+    pub history_size: Option<usize>,
+    /// Events older than this are dropped from the history.
+    pub history_max_age_hours: Option<u64>,
+    pub debounce_ms: Option<u64>,
+    /// How long temp-file events are held to collapse atomic saves; 0 disables it.
+    pub atomic_save_window_ms: Option<u64>,
+    /// Text files up to this many KiB get diffs of their modifications; 0 disables them.
+    pub diff_max_kb: Option<u64>,
+    /// `env_logger` filter, e.g. `info` or `file_monitor_core=debug`.
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Substitution {
+    pub original: PathBuf,
+    pub substitute: PathBuf,
+}
+
+/// Content hashing of one watch; unset limits keep the [`HashPolicy`] defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContentHashing {
+    pub watch: PathBuf,
+    /// Files up to this many MiB are hashed in full.
+    pub full_max_mb: Option<u64>,
+    /// Files up to this many MiB are sampled; larger ones get size and mtime only.
+    pub sampled_max_mb: Option<u64>,
+    pub incremental: Option<bool>,
+}
+
+impl ContentHashing {
+    pub fn policy(&self) -> HashPolicy {
+        let defaults = HashPolicy::default();
+        HashPolicy {
+            full_max_bytes: self
+                .full_max_mb
+                .map_or(defaults.full_max_bytes, |mb| mb * 1024 * 1024),
+            sampled_max_bytes: self
+                .sampled_max_mb
+                .map_or(defaults.sampled_max_bytes, |mb| mb * 1024 * 1024),
+            incremental: self.incremental.unwrap_or(defaults.incremental),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Native,
+    Poll,
+    Fanotify,
+}
+
+/// Watcher backend of one watch, see [`WatchBackend`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backend {
+    pub watch: PathBuf,
+    #[serde(default)]
+    pub kind: BackendKind,
+    pub poll_interval_ms: Option<u64>,
+    pub compare_contents: Option<bool>,
+}
+
+impl Backend {
+    pub fn backend(&self) -> Result<WatchBackend> {
+        match self.kind {
+            BackendKind::Native | BackendKind::Fanotify
+                if self.poll_interval_ms.is_some() || self.compare_contents.is_some() =>
+            {
+                Err(invalid_config!(
+                    "Backend of {}: poll_interval_ms and compare_contents need kind = \"poll\"",
+                    self.watch.display()
+                ))
+            }
+            BackendKind::Native => Ok(WatchBackend::Native),
+            BackendKind::Fanotify => Ok(WatchBackend::Fanotify),
+            BackendKind::Poll if self.poll_interval_ms == Some(0) => Err(invalid_config!(
+                "Backend of {}: poll_interval_ms must be positive",
+                self.watch.display()
+            )),
+            BackendKind::Poll => Ok(WatchBackend::Poll {
+                interval: self
+                    .poll_interval_ms
+                    .map_or(DEFAULT_POLL_INTERVAL, Duration::from_millis),
+                compare_contents: self.compare_contents.unwrap_or(false),
+            }),
+        }
+    }
+}
+
+/// A [`Watchset`] and the options shared by all of its paths.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchsetConfig {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Hashes the content of all paths with the default [`HashPolicy`].
+    #[serde(default)]
+    pub content_hashing: bool,
+    pub backend: Option<BackendKind>,
+    pub poll_interval_ms: Option<u64>,
+}
+
+impl WatchsetConfig {
+    pub fn watchset(&self) -> Result<Watchset> {
+        let mut watchset = Watchset::new(&self.name)?;
+        for path in &self.paths {
+            watchset = watchset.path(path);
+        }
+        for pattern in &self.include {
+            watchset = watchset.filter(FilterKind::Include, pattern)?;
+        }
+        for pattern in &self.exclude {
+            watchset = watchset.filter(FilterKind::Exclude, pattern)?;
+        }
+        Ok(watchset)
+    }
+
+    /// Backend of every path, if one is set.
+    pub fn backend(&self) -> Result<Option<WatchBackend>> {
+        if self.backend.is_none() && self.poll_interval_ms.is_none() {
+            return Ok(None);
+        }
+        let backend = Backend {
+            watch: PathBuf::from(&self.name),
+            kind: self.backend.unwrap_or_default(),
+            poll_interval_ms: self.poll_interval_ms,
+            compare_contents: None,
+        };
+        Ok(Some(backend.backend()?))
+    }
+}
+
+/// Commands per event kind as `on_<kind> = "command"`, see [`ShellHook`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ShellHooksConfig {
+    pub max_concurrent: Option<usize>,
+    pub timeout_secs: Option<u64>,
+    #[serde(flatten)]
+    pub commands: BTreeMap<String, String>,
+}
+
+impl ShellHooksConfig {
+    pub fn hooks(&self) -> Result<Vec<ShellHook>> {
+        self.commands
+            .iter()
+            .map(|(key, command)| {
+                let event = key
+                    .strip_prefix("on_")
+                    .ok_or_else(|| invalid_config!("Unknown shell_hooks setting {}", key))?;
+                ShellHook::new(event, command)
+            })
+            .collect()
+    }
+
+    pub fn limits(&self) -> (usize, Duration) {
+        (
+            self.max_concurrent
+                .unwrap_or(DEFAULT_SHELL_HOOK_CONCURRENCY),
+            self.timeout_secs
+                .map_or(DEFAULT_SHELL_HOOK_TIMEOUT, Duration::from_secs),
+        )
+    }
+}
+
+/// An external command enriching every event, see [`Enricher::command`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnricherConfig {
+    pub name: String,
+    pub command: String,
+    pub timeout_ms: Option<u64>,
+}
+
+impl EnricherConfig {
+    pub fn enricher(&self) -> Result<Enricher> {
+        let enricher = Enricher::command(&self.name, &self.command)?;
+        Ok(match self.timeout_ms {
+            Some(timeout_ms) => enricher.timeout(Duration::from_millis(timeout_ms)),
+            None => enricher,
+        })
+    }
+}
+
+/// Escalation of unacknowledged alerts, see [`EscalationPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationConfig {
+    pub store: PathBuf,
+    #[serde(default)]
+    pub rules: Vec<String>,
+    pub steps: Vec<EscalationStepConfig>,
+}
+
+/// One step, sent either to `command` or to `webhook`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationStepConfig {
+    pub after_mins: u64,
+    pub command: Option<String>,
+    pub webhook: Option<String>,
+}
+
+impl EscalationConfig {
+    pub fn policy(&self) -> Result<EscalationPolicy> {
+        let mut policy = EscalationPolicy::new(&self.store);
+        for rule in &self.rules {
+            policy = policy.rule(rule);
+        }
+        for step in &self.steps {
+            let after = Duration::from_secs(step.after_mins * 60);
+            policy = policy.step(match (&step.command, &step.webhook) {
+                (Some(command), None) => EscalationStep::command(after, command)?,
+                (None, Some(url)) => EscalationStep::webhook(after, url)?,
+                _ => {
+                    return Err(invalid_config!(
+                        "Escalation step after {} minutes needs either command or webhook",
+                        step.after_mins
+                    ))
+                }
+            });
+        }
+        Ok(policy)
+    }
+}
+
+/// WebAssembly rule plugins, see [`WasmRule`]; unset limits keep the [`PluginLimits`]
+/// defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginsConfig {
+    pub dir: PathBuf,
+    pub fuel: Option<u64>,
+    pub memory_kb: Option<usize>,
+    pub max_record_kb: Option<usize>,
+}
+
+impl PluginsConfig {
+    pub fn limits(&self) -> PluginLimits {
+        let defaults = PluginLimits::default();
+        PluginLimits {
+            fuel: self.fuel.unwrap_or(defaults.fuel),
+            memory_bytes: self.memory_kb.map_or(defaults.memory_bytes, |kb| kb * 1024),
+            max_record_bytes: self
+                .max_record_kb
+                .map_or(defaults.max_record_bytes, |kb| kb * 1024),
+        }
+    }
+}
+
+/// Versioned backups of changed files; unset limits keep the [`BackupPolicy`] defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backups {
+    pub dir: PathBuf,
+    pub max_versions: Option<usize>,
+    /// Larger files are not backed up.
+    pub max_file_mb: Option<u64>,
+}
+
+impl Backups {
+    pub fn policy(&self) -> BackupPolicy {
+        let defaults = BackupPolicy::new(&self.dir);
+        BackupPolicy {
+            max_versions: self.max_versions.unwrap_or(defaults.max_versions),
+            max_file_bytes: self
+                .max_file_mb
+                .map_or(defaults.max_file_bytes, |mb| mb * 1024 * 1024),
+            ..defaults
+        }
+    }
+}
+
+/// Bounded memory mode; unset limits keep the [`MemoryLimits`] defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryLimitsConfig {
+    pub spill_dir: PathBuf,
+    pub queued_events: Option<usize>,
+    pub dedup_entries: Option<usize>,
+    pub snapshot_kb: Option<u64>,
+    pub spill_max_mb: Option<u64>,
+}
+
+impl MemoryLimitsConfig {
+    pub fn limits(&self) -> MemoryLimits {
+        let defaults = MemoryLimits::new(&self.spill_dir);
+        MemoryLimits {
+            queued_events: self.queued_events.unwrap_or(defaults.queued_events),
+            dedup_entries: self.dedup_entries.unwrap_or(defaults.dedup_entries),
+            snapshot_bytes: self
+                .snapshot_kb
+                .map_or(defaults.snapshot_bytes, |kb| kb * 1024),
+            spill_max_bytes: self
+                .spill_max_mb
+                .map_or(defaults.spill_max_bytes, |mb| mb * 1024 * 1024),
+            ..defaults
+        }
+    }
+}
+
+impl MonitorConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        Self::parse(&content).map_err(|e| invalid_config!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).map_err(|e| invalid_config!("{}", e.to_string().trim_end()))
+    }
+
+    /// Settings that differ from `applied` but only take effect after a restart, as
+    /// [`crate::FileMonitor::reload_config`] applies just the path, watches,
+    /// substitutions, filters and rate alerts.
+    pub fn restart_required(&self, applied: &MonitorConfig) -> Vec<&'static str> {
+        [
+            (
+                "content_hashing",
+                self.content_hashing != applied.content_hashing,
+            ),
+            (
+                "priority_paths",
+                self.priority_paths != applied.priority_paths,
+            ),
+            (
+                "priority_channel_capacity",
+                self.priority_channel_capacity != applied.priority_channel_capacity,
+            ),
+            ("backends", self.backends != applied.backends),
+            ("symlinks", self.symlinks != applied.symlinks),
+            ("watchsets", self.watchsets != applied.watchsets),
+            ("backups", self.backups != applied.backups),
+            ("shell_hooks", self.shell_hooks != applied.shell_hooks),
+            ("enrichers", self.enrichers != applied.enrichers),
+            ("escalation", self.escalation != applied.escalation),
+            ("plugins", self.plugins != applied.plugins),
+            ("memory_limits", self.memory_limits != applied.memory_limits),
+            ("history_size", self.history_size != applied.history_size),
+            (
+                "history_max_age_hours",
+                self.history_max_age_hours != applied.history_max_age_hours,
+            ),
+            ("debounce_ms", self.debounce_ms != applied.debounce_ms),
+            (
+                "atomic_save_window_ms",
+                self.atomic_save_window_ms != applied.atomic_save_window_ms,
+            ),
+            ("diff_max_kb", self.diff_max_kb != applied.diff_max_kb),
+            ("log_level", self.log_level != applied.log_level),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+    }
+
+    /// Applies everything except the path and log level, which the caller needs before
+    /// the builder exists.
+    pub fn apply(&self, mut builder: FileMonitorBuilder) -> Result<FileMonitorBuilder> {
+        for watch in &self.watches {
+            builder = builder.watch(watch);
+        }
+        for substitution in &self.substitutions {
+            builder = builder.path_substitution(&substitution.original, &substitution.substitute);
+        }
+        if !self.filters.is_empty() {
+            let policy = Policy {
+                filters: self.filters.clone(),
+            };
+            builder = builder.filters(policy.to_filter()?);
+        }
+        for hashing in &self.content_hashing {
+            builder = builder.content_hashing(&hashing.watch, hashing.policy());
+        }
+        for priority_path in &self.priority_paths {
+            builder = builder.priority_path(priority_path);
+        }
+        if let Some(capacity) = self.priority_channel_capacity {
+            builder = builder.priority_channel_capacity(capacity);
+        }
+        for backend in &self.backends {
+            builder = builder.backend(&backend.watch, backend.backend()?);
+        }
+        if let Some(symlinks) = self.symlinks {
+            builder = builder.symlink_policy(symlinks);
+        }
+        for watchset in &self.watchsets {
+            let backend = watchset.backend()?;
+            for path in &watchset.paths {
+                if watchset.content_hashing {
+                    builder = builder.content_hashing(path, HashPolicy::default());
+                }
+                if let Some(backend) = backend {
+                    builder = builder.backend(path, backend);
+                }
+            }
+            builder = builder.watchset(watchset.watchset()?);
+        }
+        if let Some(shell_hooks) = &self.shell_hooks {
+            for hook in shell_hooks.hooks()? {
+                builder = builder.shell_hook(hook);
+            }
+            let (max_concurrent, timeout) = shell_hooks.limits();
+            builder = builder.shell_hook_limits(max_concurrent, timeout);
+        }
+        for enricher in &self.enrichers {
+            builder = builder.enricher(enricher.enricher()?);
+        }
+        if let Some(backups) = &self.backups {
+            builder = builder.backups(backups.policy());
+        }
+        if let Some(escalation) = &self.escalation {
+            builder = builder.escalation(escalation.policy()?);
+        }
+        if let Some(plugins) = &self.plugins {
+            for plugin in plugins::load_plugins(&plugins.dir, plugins.limits())? {
+                builder = builder.rule(plugin);
+            }
+        }
+        if let Some(memory_limits) = &self.memory_limits {
+            builder = builder.memory_limits(memory_limits.limits());
+        }
+        for rule in &self.rate_alerts {
+            rule.validate()?;
+            builder = builder.rate_alert(rule.clone());
+        }
+        if let Some(history_size) = self.history_size {
+            builder = builder.history_size(history_size);
+        }
+        if let Some(hours) = self.history_max_age_hours {
+            builder = builder.history_max_age(Duration::from_secs(hours * 3600));
+        }
+        if let Some(debounce_ms) = self.debounce_ms {
+            builder = builder.debounce(Duration::from_millis(debounce_ms));
+        }
+        if let Some(window_ms) = self.atomic_save_window_ms {
+            builder = builder.atomic_save_window(Duration::from_millis(window_ms));
+        }
+        if let Some(kb) = self.diff_max_kb {
+            builder = builder.text_diffs(kb * 1024);
+        }
+        Ok(builder)
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::{invalid_config, Result};
+use crate::filter::{FilterKind, PathFilter};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// Filters loaded from a policy file, e.g.
+/// `{"filters": [{"kind": "exclude", "pattern": "**/target/**"}]}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    #[serde(default)]
+    pub filters: Vec<PolicyFilter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyFilter {
+    pub kind: FilterKind,
+    pub pattern: String,
+}
+
+impl Policy {
+    pub fn to_filter(&self) -> Result<PathFilter> {
+        let mut filter = PathFilter::default();
+        for entry in &self.filters {
+            filter.add(entry.kind, &entry.pattern)?;
+        }
+        Ok(filter)
+    }
+}
+
+/// Expected SHA-256 hashes of the monitor's config files, authenticated with HMAC-SHA256
+/// under a key only the operators have.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigManifest {
+    pub files: BTreeMap<PathBuf, String>,
+    pub signature: String,
+}
+
+impl ConfigManifest {
+    /// Hashes the current contents of `files` and signs the result.
+    pub fn create<P: AsRef<Path>>(files: &[P], key: &[u8]) -> Result<Self> {
+        let files = files
+            .iter()
+            .map(|path| Ok((path.as_ref().to_path_buf(), file_sha256(path)?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let signature = to_hex(&hmac_sha256(key, &serde_json::to_vec(&files)?));
+        Ok(Self { files, signature })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let Ok(payload) = serde_json::to_vec(&self.files) else {
+            return false;
+        };
+        let expected = to_hex(&hmac_sha256(key, &payload));
+        let given = self.signature.to_ascii_lowercase();
+        given.len() == expected.len()
+            && given
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Watches over the monitor's own policy file: only changes that parse and, when a
+/// manifest is configured, match its signed hash are applied.
+pub(crate) struct ConfigGuard {
+    policy_path: PathBuf,
+    manifest: Option<(PathBuf, Vec<u8>)>,
+    applied_hash: Option<String>,
+}
+
+impl ConfigGuard {
+    pub(crate) fn new(policy_path: PathBuf) -> Self {
+        Self {
+            policy_path,
+            manifest: None,
+            applied_hash: None,
+        }
+    }
+
+    pub(crate) fn with_manifest(mut self, path: PathBuf, key: Vec<u8>) -> Self {
+        self.manifest = Some((path, key));
+        self
+    }
+
+    pub(crate) fn watched_files(&self) -> Vec<PathBuf> {
+        std::iter::once(self.policy_path.clone())
+            .chain(self.manifest.iter().map(|(path, _)| path.clone()))
+            .collect()
+    }
+
+    /// Returns the filter to apply if the policy changed and is valid, `None` if it is
+    /// unchanged, and an error describing the problem if it is invalid or unauthorized.
+    pub(crate) fn check(&mut self) -> Result<Option<PathFilter>> {
+        let content = std::fs::read(&self.policy_path).map_err(|e| {
+            invalid_config!(
+                "Policy file {} is unreadable: {}",
+                self.policy_path.display(),
+                e
+            )
+        })?;
+        let hash = to_hex(&Sha256::digest(&content));
+
+        if let Some((manifest_path, key)) = &self.manifest {
+            let manifest = ConfigManifest::load(manifest_path).map_err(|e| {
+                invalid_config!("Manifest {} is unreadable: {}", manifest_path.display(), e)
+            })?;
+            if !manifest.verify(key) {
+                return Err(invalid_config!(
+                    "Manifest {} has an invalid signature",
+                    manifest_path.display()
+                ));
+            }
+            match manifest.files.get(&self.policy_path) {
+                Some(expected) if expected.eq_ignore_ascii_case(&hash) => {}
+                Some(_) => {
+                    return Err(invalid_config!(
+                        "Unauthorized modification of {}: hash does not match the signed manifest",
+                        self.policy_path.display()
+                    ))
+                }
+                None => {
+                    return Err(invalid_config!(
+                        "Policy file {} is not listed in the signed manifest",
+                        self.policy_path.display()
+                    ))
+                }
+            }
+        }
+
+        if self.applied_hash.as_deref() == Some(hash.as_str()) {
+            return Ok(None);
+        }
+        let policy: Policy = serde_json::from_slice(&content).map_err(|e| {
+            invalid_config!(
+                "Policy file {} is invalid: {}",
+                self.policy_path.display(),
+                e
+            )
+        })?;
+        let filter = policy.to_filter()?;
+        self.applied_hash = Some(hash);
+        Ok(Some(filter))
+    }
+}
+
+pub fn file_sha256<P: AsRef<Path>>(path: P) -> Result<String> {
+    Ok(to_hex(&Sha256::digest(std::fs::read(path)?)))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
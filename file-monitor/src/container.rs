@@ -0,0 +1,283 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PROC_ROOT: &str = "/proc";
+pub const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The container a host path belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerInfo {
+    pub id: String,
+    /// Name reported by the container runtime, when its socket is reachable.
+    pub name: Option<String>,
+    pub runtime: String,
+}
+
+/// Maps host paths (overlayfs upper/merged dirs, bind mount sources, `/proc/<pid>/root`)
+/// to the containers using them, based on the cgroups and mount tables in `/proc`.
+pub struct ContainerResolver {
+    proc_root: PathBuf,
+    docker_socket: PathBuf,
+    roots: Vec<(PathBuf, ContainerInfo)>,
+    names: HashMap<String, Option<String>>,
+    refreshed_at: Option<Instant>,
+}
+
+impl Default for ContainerResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContainerResolver {
+    pub fn new() -> Self {
+        Self {
+            proc_root: PathBuf::from(DEFAULT_PROC_ROOT),
+            docker_socket: PathBuf::from(DEFAULT_DOCKER_SOCKET),
+            roots: Vec::new(),
+            names: HashMap::new(),
+            refreshed_at: None,
+        }
+    }
+
+    pub fn with_proc_root<P: AsRef<Path>>(mut self, proc_root: P) -> Self {
+        self.proc_root = proc_root.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_docker_socket<P: AsRef<Path>>(mut self, docker_socket: P) -> Self {
+        self.docker_socket = docker_socket.as_ref().to_path_buf();
+        self
+    }
+
+    /// Returns the container owning `path`, rescanning `/proc` if the last scan is stale.
+    pub async fn resolve(&mut self, path: &Path) -> Option<ContainerInfo> {
+        if self
+            .refreshed_at
+            .is_none_or(|refreshed_at| refreshed_at.elapsed() > REFRESH_INTERVAL)
+        {
+            self.refresh().await;
+        }
+        self.lookup(path)
+    }
+
+    /// The container owning `path` according to the last scan.
+    pub fn lookup(&self, path: &Path) -> Option<ContainerInfo> {
+        self.roots
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, info)| info.clone())
+    }
+
+    /// Rescans every process for container cgroups and records the host paths backing
+    /// each container's filesystem.
+    pub async fn refresh(&mut self) {
+        self.refreshed_at = Some(Instant::now());
+        let host_mounts = std::fs::read_to_string(self.proc_root.join("self/mountinfo"))
+            .map(|content| parse_mountinfo(&content))
+            .unwrap_or_default();
+
+        let Ok(entries) = std::fs::read_dir(&self.proc_root) else {
+            return;
+        };
+        let mut roots = Vec::new();
+        let mut seen = HashSet::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(pid) = file_name
+                .to_str()
+                .filter(|name| name.parse::<u32>().is_ok())
+            else {
+                continue;
+            };
+            let Ok(cgroup) = std::fs::read_to_string(entry.path().join("cgroup")) else {
+                continue;
+            };
+            let Some((runtime, id)) = parse_container_id(&cgroup) else {
+                continue;
+            };
+
+            let name = match self.names.get(&id) {
+                Some(name) => name.clone(),
+                None => {
+                    let name = self.query_name(&runtime, &id).await;
+                    self.names.insert(id.clone(), name.clone());
+                    name
+                }
+            };
+            let info = ContainerInfo { id, name, runtime };
+            roots.push((self.proc_root.join(pid).join("root"), info.clone()));
+
+            // Processes of the same container share mounts; scan them once.
+            if !seen.insert(info.id.clone()) {
+                continue;
+            }
+            let Ok(mountinfo) = std::fs::read_to_string(entry.path().join("mountinfo")) else {
+                continue;
+            };
+            for host_path in container_host_paths(&parse_mountinfo(&mountinfo), &host_mounts) {
+                roots.push((host_path, info.clone()));
+            }
+        }
+        debug!("Container scan found {} path roots", roots.len());
+        self.roots = roots;
+    }
+
+    async fn query_name(&self, runtime: &str, id: &str) -> Option<String> {
+        if runtime != "docker" {
+            return None;
+        }
+        docker_container_name(&self.docker_socket, id).await
+    }
+}
+
+/// One line of `/proc/<pid>/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub device: String,
+    /// Path within the mounted filesystem that forms the root of the mount.
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub super_options: String,
+}
+
+pub fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" - ")?;
+            let left: Vec<&str> = left.split_whitespace().collect();
+            let right: Vec<&str> = right.split_whitespace().collect();
+            Some(MountEntry {
+                device: left.get(2)?.to_string(),
+                root: PathBuf::from(unescape_octal(left.get(3)?)),
+                mount_point: PathBuf::from(unescape_octal(left.get(4)?)),
+                fs_type: right.first()?.to_string(),
+                super_options: right.get(2).unwrap_or(&"").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Extracts the runtime and container ID from `/proc/<pid>/cgroup`, recognising Docker,
+/// containerd (including Kubernetes) and Podman layouts.
+pub fn parse_container_id(cgroup: &str) -> Option<(String, String)> {
+    for line in cgroup.lines() {
+        let Some(path) = line.splitn(3, ':').nth(2) else {
+            continue;
+        };
+        for segment in path.split('/').rev() {
+            let segment = segment.trim_end_matches(".scope");
+            let (runtime, id) = if let Some(id) = segment.strip_prefix("docker-") {
+                ("docker", id)
+            } else if let Some(id) = segment.strip_prefix("cri-containerd-") {
+                ("containerd", id)
+            } else if let Some(id) = segment.strip_prefix("libpod-") {
+                ("podman", id)
+            } else if path.contains("/docker/") {
+                ("docker", segment)
+            } else {
+                continue;
+            };
+            if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Some((runtime.to_string(), id.to_string()));
+            }
+        }
+    }
+    None
+}
+
+/// Host paths backing a container's mounts: the overlayfs upper (and merged) dirs of its
+/// root filesystem, and the host sources of its bind mounts.
+fn container_host_paths(
+    container_mounts: &[MountEntry],
+    host_mounts: &[MountEntry],
+) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for mount in container_mounts {
+        if mount.fs_type == "overlay" {
+            if let Some(upper) = mount
+                .super_options
+                .split(',')
+                .find_map(|option| option.strip_prefix("upperdir="))
+            {
+                let upper = PathBuf::from(upper);
+                if upper.ends_with("diff") {
+                    if let Some(layer) = upper.parent() {
+                        paths.push(layer.join("merged"));
+                    }
+                }
+                paths.push(upper);
+            }
+            continue;
+        }
+
+        if mount.root == Path::new("/") || is_virtual_fs(&mount.fs_type) {
+            continue;
+        }
+        // A bind mount: find where the same filesystem is mounted on the host.
+        for host in host_mounts
+            .iter()
+            .filter(|host| host.device == mount.device)
+        {
+            if let Ok(relative) = mount.root.strip_prefix(&host.root) {
+                paths.push(host.mount_point.join(relative));
+                break;
+            }
+        }
+    }
+    paths
+}
+
+fn is_virtual_fs(fs_type: &str) -> bool {
+    matches!(
+        fs_type,
+        "proc" | "sysfs" | "tmpfs" | "devpts" | "mqueue" | "cgroup" | "cgroup2" | "shm"
+    )
+}
+
+fn unescape_octal(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+#[cfg(unix)]
+async fn docker_container_name(socket: &Path, id: &str) -> Option<String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(socket).await.ok()?;
+    let request = format!(
+        "GET /containers/{}/json HTTP/1.0\r\nHost: docker\r\n\r\n",
+        id
+    );
+    stream.write_all(request.as_bytes()).await.ok()?;
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(2), stream.read_to_string(&mut response))
+        .await
+        .ok()?
+        .ok()?;
+
+    let (_, body) = response.split_once("\r\n\r\n")?;
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    Some(
+        value
+            .get("Name")?
+            .as_str()?
+            .trim_start_matches('/')
+            .to_string(),
+    )
+}
+
+#[cfg(not(unix))]
+async fn docker_container_name(_socket: &Path, _id: &str) -> Option<String> {
+    None
+}
@@ -0,0 +1,764 @@
+use crate::api_keys::{ApiKey, ApiKeyStore, ApiScope};
+use crate::error::{monitor_error, MonitorError, Result};
+use crate::maintenance::DEFAULT_MAINTENANCE_LABEL;
+use crate::subscription::{EventSubscription, MonitorEvent};
+use crate::supervisor::Supervisor;
+use crate::throttle::{RateLimit, RateLimiter};
+use crate::tls::ReloadableTls;
+use crate::FileMonitor;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Query, Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use chrono::{DateTime, Local};
+use futures_util::{SinkExt, StreamExt};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+use tower::ServiceExt;
+
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Limits on the request line and headers, enforced before the key is checked.
+const MAX_HEADERS: usize = 32;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Clients only send control frames on the event stream, so their messages stay small.
+const MAX_CLIENT_MESSAGE: usize = 64 * 1024;
+
+/// Inbound HTTP endpoint that lets CI/CD pipelines and scripts steer a headless monitor.
+///
+/// Every request must carry `Authorization: Bearer <key>` with a key from the server's
+/// [`ApiKeyStore`]. `GET` routes need the `read` scope, all others the `control` scope.
+/// Routes:
+///
+/// - `GET /status` - paused flag and watched paths
+/// - `GET /stats` - event counts by kind and coverage
+/// - `GET /tasks` - health of supervised tasks, see [`ControlServer::with_supervisor`]
+/// - `GET /history?limit=N&kind=K` - recorded events, oldest first; only events of kind
+///   `K` (e.g. `deleted`) with `kind`, and the last `N` of those with `limit`
+/// - `POST /pause`, `POST /resume`, of one watched path with a `{"path": "..."}` body;
+///   `POST /pause` with a `{"for_secs": N}` body resumes on its own after `N` seconds
+/// - `POST /path` with a `{"path": "..."}` body, like the `update` command
+/// - `POST /watches`, `DELETE /watches` with a `{"path": "..."}` body
+/// - `GET /alerts` - unacknowledged escalated alerts; `POST /alerts/ack` with an
+///   `{"id": "...", "by": "..."}` body stops escalating one
+/// - `POST /maintenance` with an optional `{"label": "...", "downgrade_alerts": true}` body,
+///   `DELETE /maintenance`
+/// - `GET /events` - WebSocket that pushes every recorded event as a JSON text message.
+///   Browsers cannot set headers on WebSocket connections, so this route also accepts the
+///   token as `?token=...`.
+#[derive(Clone)]
+pub struct ControlServer {
+    monitor: Arc<FileMonitor>,
+    keys: Arc<ApiKeyStore>,
+    audit_log: Option<PathBuf>,
+    supervisor: Option<Supervisor>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    tls: Option<Arc<ReloadableTls>>,
+}
+
+type ServerState = State<Arc<ControlServer>>;
+
+/// A handler's answer: a JSON body, or `{"error": "..."}` with an error status.
+struct Response {
+    status: StatusCode,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self {
+            status: StatusCode::OK,
+            body,
+        }
+    }
+
+    fn error(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+/// A failed command is answered with `422 Unprocessable Entity`.
+impl From<Result<Value>> for Response {
+    fn from(result: Result<Value>) -> Self {
+        match result {
+            Ok(body) => Self::ok(body),
+            Err(e) => Self::error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> axum::response::Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+type Reply = std::result::Result<Response, Response>;
+
+#[derive(Deserialize)]
+struct PathRequest {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct AckRequest {
+    id: String,
+    by: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PauseRequest {
+    path: Option<PathBuf>,
+    for_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct MaintenanceRequest {
+    label: Option<String>,
+    #[serde(default)]
+    downgrade_alerts: bool,
+}
+
+/// Numbers are parsed by the handlers, so that a bad one gets a JSON error.
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<String>,
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RatesQuery {
+    window: Option<String>,
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// One request to the control server, as written to the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiCall {
+    pub time: DateTime<Local>,
+    /// Name of the API key used; `None` when the request was not authenticated.
+    pub key: Option<String>,
+    pub peer: String,
+    pub method: String,
+    pub route: String,
+    pub status: u16,
+}
+
+impl ControlServer {
+    /// A server accepting the single `token` with the `control` scope.
+    pub fn new(monitor: Arc<FileMonitor>, token: impl Into<String>) -> Result<Self> {
+        let token = token.into();
+        if token.is_empty() {
+            return Err(monitor_error!("Control server token must not be empty"));
+        }
+        let keys = ApiKeyStore::new();
+        keys.insert_ephemeral("default", &token, ApiScope::Control)?;
+        Ok(Self::with_api_keys(monitor, Arc::new(keys)))
+    }
+
+    /// A server accepting the keys in `keys`, including ones added while it runs.
+    pub fn with_api_keys(monitor: Arc<FileMonitor>, keys: Arc<ApiKeyStore>) -> Self {
+        Self {
+            monitor,
+            keys,
+            audit_log: None,
+            supervisor: None,
+            rate_limiter: None,
+            tls: None,
+        }
+    }
+
+    /// Serves HTTPS and `wss://` instead of plain HTTP. The certificate, key and client CA
+    /// files are reloaded when they change, so renewed certificates need no restart.
+    pub fn with_tls(mut self, tls: Arc<ReloadableTls>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Appends every request, with the key that made it and the response status, to `path`
+    /// as a JSON line.
+    pub fn with_audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    /// Serves the health of the supervisor's tasks on `GET /tasks`.
+    pub fn with_supervisor(mut self, supervisor: Supervisor) -> Self {
+        self.supervisor = Some(supervisor);
+        self
+    }
+
+    /// Answers requests beyond `limit` with `429 Too Many Requests`, before checking the
+    /// token, so the endpoint also resists token guessing.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(limit)));
+        self
+    }
+
+    /// Binds `addr` and serves requests until the monitor shuts down.
+    pub async fn run(self, addr: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Control server listening on {}", listener.local_addr()?);
+        self.serve(listener).await
+    }
+
+    /// Serves requests until the monitor shuts down.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let shutdown = self.monitor.shutdown_token();
+        let (reload_tx, mut reload_rx) = mpsc::channel(16);
+        let _tls_watcher = match &self.tls {
+            Some(tls) => Some(tls.watch(reload_tx)?),
+            None => None,
+        };
+        let server = Arc::new(self);
+        let router = server.router();
+        loop {
+            let (stream, peer) = select! {
+                accepted = listener.accept() => accepted?,
+                Some(()) = reload_rx.recv() => {
+                    if let Some(tls) = &server.tls {
+                        match tls.reload() {
+                            Ok(()) => info!("Reloaded control server TLS certificates"),
+                            Err(e) => warn!(
+                                "Failed to reload TLS certificates, keeping current ones: {}",
+                                e
+                            ),
+                        }
+                    }
+                    continue;
+                }
+                _ = shutdown.cancelled() => return Ok(()),
+            };
+            let server = Arc::clone(&server);
+            let router = router.clone();
+            tokio::spawn(async move {
+                let result = match &server.tls {
+                    Some(tls) => {
+                        match tokio::time::timeout(REQUEST_TIMEOUT, tls.acceptor().accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => serve_connection(router, stream, peer).await,
+                            Ok(Err(e)) => Err(monitor_error!("TLS handshake failed: {}", e)),
+                            Err(_) => Err(monitor_error!("TLS handshake timed out")),
+                        }
+                    }
+                    None => serve_connection(router, stream, peer).await,
+                };
+                if let Err(e) = result {
+                    debug!("Control connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    fn router(self: &Arc<Self>) -> Router {
+        Router::new()
+            .route("/status", get(status))
+            .route("/stats", get(stats))
+            .route("/rates", get(rates))
+            .route("/tasks", get(tasks))
+            .route("/history", get(history))
+            .route("/events", get(events))
+            .route("/pause", post(pause))
+            .route("/resume", post(resume))
+            .route("/path", post(update_path))
+            .route("/watches", post(add_watch).delete(remove_watch))
+            .route("/alerts", get(alerts))
+            .route("/alerts/ack", post(acknowledge_alert))
+            .route(
+                "/maintenance",
+                post(start_maintenance).delete(stop_maintenance),
+            )
+            .method_not_allowed_fallback(|| async {
+                Response::error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            })
+            .fallback(|| async { Response::error(StatusCode::NOT_FOUND, "not found") })
+            .layer(middleware::from_fn_with_state(Arc::clone(self), guard))
+            .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+            .with_state(Arc::clone(self))
+    }
+
+    async fn audit(
+        &self,
+        method: &Method,
+        route: &str,
+        peer: SocketAddr,
+        key: Option<&ApiKey>,
+        status: StatusCode,
+    ) {
+        let Some(path) = &self.audit_log else {
+            return;
+        };
+        let call = ApiCall {
+            time: Local::now(),
+            key: key.map(|key| key.name.clone()),
+            peer: peer.to_string(),
+            method: method.to_string(),
+            route: route.to_string(),
+            status: status.as_u16(),
+        };
+        let result = async {
+            let mut line = serde_json::to_vec(&call)?;
+            line.push(b'\n');
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(&line).await?;
+            // tokio completes writes in the background; flush so the record is on disk
+            // before the response is sent.
+            file.flush().await?;
+            Ok::<_, MonitorError>(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to write API audit log {}: {}", path.display(), e);
+        }
+    }
+
+    fn authenticate(&self, request: &Request) -> Option<ApiKey> {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let token = match bearer {
+            Some(token) => token.to_string(),
+            None if request.uri().path() == "/events" => {
+                Query::<TokenQuery>::try_from_uri(request.uri())
+                    .ok()?
+                    .0
+                    .token?
+            }
+            None => return None,
+        };
+        self.keys.authenticate(&token)
+    }
+
+    /// Pushes events to an upgraded connection until the client closes it. Pings are
+    /// answered by tungstenite while the socket is polled.
+    async fn stream_events<S>(&self, stream: S, mut events: EventSubscription) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let config = WebSocketConfig {
+            max_frame_size: Some(MAX_CLIENT_MESSAGE),
+            max_message_size: Some(MAX_CLIENT_MESSAGE),
+            ..WebSocketConfig::default()
+        };
+        let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, Some(config)).await;
+        let shutdown = self.monitor.shutdown_token();
+        loop {
+            select! {
+                event = events.recv() => match event {
+                    Some(MonitorEvent::File(record)) => {
+                        let message = serde_json::to_string(&record)?;
+                        socket.send(Message::text(message)).await.map_err(websocket_error)?;
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+                message = socket.next() => match message {
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(websocket_error(e)),
+                },
+                _ = shutdown.cancelled() => break,
+            }
+        }
+        // Starts the closing handshake, or sends the reply to the client's close frame.
+        SinkExt::close(&mut socket).await.map_err(websocket_error)
+    }
+}
+
+/// Serves the one request on `stream`. hyper bounds the request line and headers before
+/// the router, and so the key check, sees them.
+async fn serve_connection<S>(router: Router, stream: S, peer: SocketAddr) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        router.clone().oneshot(request)
+    });
+    let connection = http1::Builder::new()
+        .keep_alive(false)
+        .max_headers(MAX_HEADERS)
+        .max_header_size(MAX_HEADER_BYTES)
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades();
+    // An upgraded connection is handed over once the handshake is answered, so the
+    // timeout does not cut event streams short.
+    match tokio::time::timeout(REQUEST_TIMEOUT, connection).await {
+        Ok(result) => result.map_err(|e| monitor_error!("{}", e)),
+        Err(_) => Err(monitor_error!("request timed out")),
+    }
+}
+
+/// Applies the rate limit and checks the key and its scope before any route runs, then
+/// audits the outcome.
+async fn guard(
+    State(server): ServerState,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> axum::response::Response {
+    let method = request.method().clone();
+    // The query may carry the key, so only the route is logged.
+    let route = request.uri().path().to_string();
+    let throttled = server
+        .rate_limiter
+        .as_ref()
+        .is_some_and(|limiter| !limiter.try_acquire());
+    let key = if throttled {
+        None
+    } else {
+        server.authenticate(&request)
+    };
+    let required = if matches!(method, Method::GET | Method::HEAD) {
+        ApiScope::Read
+    } else {
+        ApiScope::Control
+    };
+    let response = match &key {
+        _ if throttled => {
+            warn!(
+                "Rate limited control request {} {} from {}",
+                method, route, peer
+            );
+            Response::error(StatusCode::TOO_MANY_REQUESTS, "too many requests").into_response()
+        }
+        None => {
+            warn!(
+                "Rejected unauthenticated control request {} {} from {}",
+                method, route, peer
+            );
+            Response::error(StatusCode::UNAUTHORIZED, "unauthorized").into_response()
+        }
+        Some(key) if !key.scope.allows(required) => {
+            warn!(
+                "Rejected control request {} {} from {}: key {} lacks the {:?} scope",
+                method, route, peer, key.name, required
+            );
+            Response::error(StatusCode::FORBIDDEN, "forbidden").into_response()
+        }
+        Some(key) => {
+            info!(
+                "Control request {} {} from {} with key {}",
+                method, route, peer, key.name
+            );
+            request.extensions_mut().insert(key.clone());
+            next.run(request).await
+        }
+    };
+    server
+        .audit(&method, &route, peer, key.as_ref(), response.status())
+        .await;
+    response
+}
+
+async fn status(State(server): ServerState) -> Response {
+    let monitor = &server.monitor;
+    Response::ok(json!({
+        "paused": monitor.is_paused().await,
+        "paused_paths": monitor.get_paused_paths().await,
+        "resumes_at": monitor.get_resume_time(),
+        "maintenance": monitor.active_maintenance().await.map(|window| window.label),
+        "watches": monitor.get_watches().await,
+        "scans": monitor.get_scans(),
+        "rate_limit": server.rate_limiter.as_ref().map(|limiter| limiter.metrics()),
+    }))
+}
+
+async fn stats(State(server): ServerState) -> Response {
+    let monitor = &server.monitor;
+    let mut events = BTreeMap::new();
+    for (event, count) in monitor.get_stats().await {
+        *events.entry(event.kind()).or_insert(0) += count;
+    }
+    let latency = monitor.get_latency().await;
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    Response::ok(json!({
+        "events": events,
+        "dropped_events": monitor.get_dropped_events(),
+        "spilled_events": monitor.get_spilled_events(),
+        "coverage_percent": monitor.get_coverage().await.coverage_percent,
+        "latency": {
+            "count": latency.count,
+            "mean_ms": latency.mean().map(millis),
+            "max_ms": millis(latency.max),
+            "last_ms": millis(latency.last),
+        },
+    }))
+}
+
+async fn rates(State(server): ServerState, Query(query): Query<RatesQuery>) -> Reply {
+    let window = match query.window.as_deref().map(str::parse::<u64>) {
+        Some(Ok(window)) => window,
+        Some(Err(_)) => {
+            return Err(Response::error(
+                StatusCode::BAD_REQUEST,
+                "window must be a number",
+            ))
+        }
+        None => 60,
+    };
+    let window = Duration::from_secs(window);
+    Ok(match query.kind {
+        Some(kind) => {
+            let rate = server.monitor.get_rate(&kind, window).await;
+            Response::ok(json!({ kind: rate }))
+        }
+        None => serde_json::to_value(server.monitor.get_rates(window).await)
+            .map_err(Into::into)
+            .into(),
+    })
+}
+
+async fn tasks(State(server): ServerState) -> Response {
+    match &server.supervisor {
+        Some(supervisor) => serde_json::to_value(supervisor.status().await)
+            .map_err(Into::into)
+            .into(),
+        None => Response::ok(json!([])),
+    }
+}
+
+async fn history(State(server): ServerState, Query(query): Query<HistoryQuery>) -> Reply {
+    let limit = match query.limit.as_deref().map(str::parse::<usize>) {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(_)) => {
+            return Err(Response::error(
+                StatusCode::BAD_REQUEST,
+                "limit must be a number",
+            ))
+        }
+        None => None,
+    };
+    let history = match &query.kind {
+        Some(kind) => server.monitor.get_history_filtered(kind).await,
+        None => server.monitor.get_history().await,
+    };
+    let start = limit.map_or(0, |limit| history.len().saturating_sub(limit));
+    Ok(serde_json::to_value(&history[start..])
+        .map_err(Into::into)
+        .into())
+}
+
+async fn pause(State(server): ServerState, body: Bytes) -> Reply {
+    if body.is_empty() {
+        return Ok(server
+            .monitor
+            .pause()
+            .await
+            .map(|_| json!({ "paused": true }))
+            .into());
+    }
+    Ok(match parse_body::<PauseRequest>(&body)? {
+        PauseRequest {
+            path: Some(path),
+            for_secs: None,
+        } => server
+            .monitor
+            .pause_path(&path)
+            .await
+            .map(|_| json!({ "paused": path }))
+            .into(),
+        PauseRequest {
+            path: None,
+            for_secs: Some(secs),
+        } => server
+            .monitor
+            .pause_for(Duration::from_secs(secs))
+            .await
+            .map(|_| json!({ "paused": true, "for_secs": secs }))
+            .into(),
+        _ => {
+            return Err(Response::error(
+                StatusCode::BAD_REQUEST,
+                "expected either path or for_secs",
+            ))
+        }
+    })
+}
+
+async fn resume(State(server): ServerState, body: Bytes) -> Reply {
+    if body.is_empty() {
+        return Ok(server
+            .monitor
+            .resume()
+            .await
+            .map(|_| json!({ "paused": false }))
+            .into());
+    }
+    let path = parse_body::<PathRequest>(&body)?.path;
+    Ok(server
+        .monitor
+        .resume_path(&path)
+        .await
+        .map(|_| json!({ "resumed": path }))
+        .into())
+}
+
+async fn update_path(State(server): ServerState, body: Bytes) -> Reply {
+    let path = parse_body::<PathRequest>(&body)?.path;
+    Ok(server
+        .monitor
+        .update_path(&path)
+        .await
+        .map(|_| json!({ "path": path }))
+        .into())
+}
+
+async fn add_watch(State(server): ServerState, body: Bytes) -> Reply {
+    let path = parse_body::<PathRequest>(&body)?.path;
+    Ok(server
+        .monitor
+        .add_watch(&path)
+        .await
+        .map(|_| json!({ "watching": path }))
+        .into())
+}
+
+async fn remove_watch(State(server): ServerState, body: Bytes) -> Reply {
+    let path = parse_body::<PathRequest>(&body)?.path;
+    Ok(server
+        .monitor
+        .remove_watch(&path)
+        .await
+        .map(|_| json!({ "unwatched": path }))
+        .into())
+}
+
+async fn alerts(State(server): ServerState) -> Response {
+    serde_json::to_value(server.monitor.get_open_alerts().await)
+        .map_err(Into::into)
+        .into()
+}
+
+async fn acknowledge_alert(State(server): ServerState, body: Bytes) -> Reply {
+    let ack = parse_body::<AckRequest>(&body)?;
+    let by = ack.by.unwrap_or_else(|| "control-api".to_string());
+    Ok(server
+        .monitor
+        .acknowledge_alert(&ack.id, &by)
+        .await
+        .map(|_| json!({ "acknowledged": ack.id, "by": by }))
+        .into())
+}
+
+async fn start_maintenance(State(server): ServerState, body: Bytes) -> Reply {
+    let maintenance = if body.is_empty() {
+        MaintenanceRequest::default()
+    } else {
+        parse_body::<MaintenanceRequest>(&body)?
+    };
+    let label = maintenance
+        .label
+        .unwrap_or_else(|| DEFAULT_MAINTENANCE_LABEL.to_string());
+    Ok(server
+        .monitor
+        .start_maintenance(&label, maintenance.downgrade_alerts)
+        .await
+        .map(|_| json!({ "maintenance": label }))
+        .into())
+}
+
+async fn stop_maintenance(State(server): ServerState) -> Response {
+    server
+        .monitor
+        .stop_maintenance()
+        .await
+        .and_then(|window| Ok(serde_json::to_value(window)?))
+        .into()
+}
+
+/// Answers the WebSocket handshake and streams events on the upgraded connection.
+async fn events(
+    State(server): ServerState,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(key): Extension<ApiKey>,
+    request: Request,
+) -> axum::response::Response {
+    let headers = request.headers();
+    let upgrade = headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let Some(accept) = headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .filter(|_| upgrade)
+        .map(|key| derive_accept_key(key.as_bytes()))
+    else {
+        return Response::error(StatusCode::UPGRADE_REQUIRED, "websocket upgrade required")
+            .into_response();
+    };
+    info!("Event stream opened by {} with key {}", peer, key.name);
+    // Subscribe before answering, so no event recorded after the handshake is missed.
+    let subscription = server.monitor.subscribe();
+    let upgraded = hyper::upgrade::on(request);
+    tokio::spawn(async move {
+        let result = match upgraded.await {
+            Ok(upgraded) => {
+                server
+                    .stream_events(TokioIo::new(upgraded), subscription)
+                    .await
+            }
+            Err(e) => Err(monitor_error!("WebSocket upgrade failed: {}", e)),
+        };
+        if let Err(e) = result {
+            debug!("Event stream to {} failed: {}", peer, e);
+        }
+    });
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, "websocket".to_string()),
+            (header::CONNECTION, "Upgrade".to_string()),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response()
+}
+
+fn websocket_error(e: WsError) -> MonitorError {
+    monitor_error!("WebSocket error: {}", e)
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(body: &[u8]) -> std::result::Result<T, Response> {
+    serde_json::from_slice(body).map_err(|e| {
+        Response::error(
+            StatusCode::BAD_REQUEST,
+            format!("invalid request body: {}", e),
+        )
+    })
+}
@@ -0,0 +1,182 @@
+use crate::error::Result;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Why events may be missing from the record for a period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GapKind {
+    Paused,
+    WatcherError,
+    /// No heartbeat was written, i.e. the monitor process was not running.
+    ProcessDown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageGap {
+    pub kind: GapKind,
+    pub start: DateTime<Local>,
+    /// `None` while the gap is still open.
+    pub end: Option<DateTime<Local>>,
+}
+
+impl CoverageGap {
+    fn duration_until(&self, now: DateTime<Local>) -> Duration {
+        self.end.unwrap_or(now) - self.start
+    }
+}
+
+/// Coverage of the event record over the tracked lifetime of the monitor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub since: DateTime<Local>,
+    pub until: DateTime<Local>,
+    pub gaps: Vec<CoverageGap>,
+    pub coverage_percent: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CoverageState {
+    first_start: DateTime<Local>,
+    last_heartbeat: DateTime<Local>,
+    gaps: Vec<CoverageGap>,
+}
+
+/// Records when monitoring was not effective, optionally persisted across restarts so
+/// process downtime (a stale heartbeat) shows up as a gap too.
+pub struct CoverageTracker {
+    path: Option<PathBuf>,
+    heartbeat_interval: Duration,
+    state: CoverageState,
+}
+
+impl CoverageTracker {
+    pub fn new(heartbeat_interval: std::time::Duration) -> Self {
+        let now = Local::now();
+        Self {
+            path: None,
+            heartbeat_interval: Duration::from_std(heartbeat_interval)
+                .unwrap_or_else(|_| Duration::seconds(30)),
+            state: CoverageState {
+                first_start: now,
+                last_heartbeat: now,
+                gaps: Vec::new(),
+            },
+        }
+    }
+
+    /// Loads earlier coverage from `path`. If the last heartbeat there is older than two
+    /// heartbeat intervals, the time since then is recorded as process downtime.
+    pub fn load<P: AsRef<Path>>(path: P, heartbeat_interval: std::time::Duration) -> Result<Self> {
+        let mut tracker = Self::new(heartbeat_interval);
+        tracker.path = Some(path.as_ref().to_path_buf());
+
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(content) => {
+                let mut state: CoverageState = serde_json::from_str(&content)?;
+                let now = Local::now();
+                // Anything left open belonged to the previous run and ended with it.
+                for gap in state.gaps.iter_mut().filter(|gap| gap.end.is_none()) {
+                    gap.end = Some(state.last_heartbeat.max(gap.start));
+                }
+                if now - state.last_heartbeat > tracker.heartbeat_interval * 2 {
+                    state.gaps.push(CoverageGap {
+                        kind: GapKind::ProcessDown,
+                        start: state.last_heartbeat,
+                        end: Some(now),
+                    });
+                }
+                state.last_heartbeat = now;
+                tracker.state = state;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        tracker.save()?;
+        Ok(tracker)
+    }
+
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.state.last_heartbeat = Local::now();
+        self.save()
+    }
+
+    /// Opens a gap of this kind unless one is already open.
+    pub fn open_gap(&mut self, kind: GapKind) -> Result<()> {
+        if self.open(kind).is_some() {
+            return Ok(());
+        }
+        self.state.gaps.push(CoverageGap {
+            kind,
+            start: Local::now(),
+            end: None,
+        });
+        self.save()
+    }
+
+    pub fn close_gap(&mut self, kind: GapKind) -> Result<()> {
+        match self.open(kind) {
+            Some(gap) => {
+                gap.end = Some(Local::now());
+                self.save()
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_open(&self, kind: GapKind) -> bool {
+        self.state
+            .gaps
+            .iter()
+            .any(|gap| gap.kind == kind && gap.end.is_none())
+    }
+
+    pub fn report(&self) -> CoverageReport {
+        let now = Local::now();
+        let total = now - self.state.first_start;
+        let mut uncovered: Vec<(DateTime<Local>, DateTime<Local>)> = self
+            .state
+            .gaps
+            .iter()
+            .map(|gap| (gap.start, gap.start + gap.duration_until(now)))
+            .collect();
+        // Gaps of different kinds can overlap (e.g. paused while the watcher was broken).
+        uncovered.sort();
+        let mut missing = Duration::zero();
+        let mut cursor = self.state.first_start;
+        for (start, end) in uncovered {
+            let start = start.max(cursor);
+            if end > start {
+                missing += end - start;
+                cursor = end;
+            }
+        }
+
+        let coverage_percent = if total <= Duration::zero() {
+            100.0
+        } else {
+            let total_ms = total.num_milliseconds() as f64;
+            (100.0 * (total_ms - missing.num_milliseconds() as f64) / total_ms).clamp(0.0, 100.0)
+        };
+        CoverageReport {
+            since: self.state.first_start,
+            until: now,
+            gaps: self.state.gaps.clone(),
+            coverage_percent,
+        }
+    }
+
+    fn open(&mut self, kind: GapKind) -> Option<&mut CoverageGap> {
+        self.state
+            .gaps
+            .iter_mut()
+            .find(|gap| gap.kind == kind && gap.end.is_none())
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_vec_pretty(&self.state)?)?;
+        }
+        Ok(())
+    }
+}
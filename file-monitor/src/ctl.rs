@@ -0,0 +1,281 @@
+use crate::error::{monitor_error, Result};
+use crate::shutdown::ShutdownToken;
+use log::{debug, info};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+
+const MAX_COMMAND_LENGTH: u64 = 4096;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A command line received on the control socket; its output is sent back through `reply`.
+pub struct CtlRequest {
+    pub command: String,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Local control channel of a running monitor, for daemons without an interactive stdin:
+/// a Unix domain socket, or a named pipe on Windows. A client sends one command line and
+/// reads its output until the connection closes. Only the user running the monitor can
+/// connect, so unlike [`crate::ControlServer`] there is no token: the Unix socket is
+/// created with mode 0600 in a directory other users cannot write to, and the pipe gets a
+/// security descriptor that grants access to its owner alone.
+#[derive(Clone)]
+pub struct ControlSocket {
+    path: PathBuf,
+    shutdown: ShutdownToken,
+}
+
+/// `$XDG_RUNTIME_DIR/file-monitor.sock` on Unix, `\\.\pipe\file-monitor` on Windows.
+/// Without `XDG_RUNTIME_DIR` there is no default, since the shared temp directory would let
+/// other users take the path first.
+pub fn default_socket_path() -> Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        Ok(PathBuf::from(r"\\.\pipe\file-monitor"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("file-monitor.sock"))
+            .ok_or_else(|| {
+                monitor_error!("XDG_RUNTIME_DIR is not set; give the control socket path")
+            })
+    }
+}
+
+impl ControlSocket {
+    /// Listens on `path` until `shutdown` is cancelled.
+    pub fn new(path: impl Into<PathBuf>, shutdown: ShutdownToken) -> Self {
+        Self {
+            path: path.into(),
+            shutdown,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts connections and forwards their commands to `requests` until the shutdown
+    /// token is cancelled. The socket file is removed again on return.
+    #[cfg(unix)]
+    pub async fn run(self, requests: mpsc::Sender<CtlRequest>) -> Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        use tokio::net::{UnixListener, UnixStream};
+
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        check_socket_dir(dir)?;
+        if UnixStream::connect(&self.path).await.is_ok() {
+            return Err(monitor_error!(
+                "Another monitor is listening on {}",
+                self.path.display()
+            ));
+        }
+        // Left behind by an instance that did not shut down cleanly.
+        let _ = std::fs::remove_file(&self.path);
+
+        // Bound in a directory only this user can enter and moved into place once it is
+        // 0600, so no other user can connect in between.
+        let staging = dir.join(format!(".file-monitor-ctl.{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join("socket");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, &self.path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_dir_all(&staging);
+        let listener = bound?;
+        info!("Control socket listening on {}", self.path.display());
+
+        let result = loop {
+            select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => spawn_connection(stream, requests.clone()),
+                    Err(e) => break Err(e.into()),
+                },
+                _ = self.shutdown.cancelled() => break Ok(()),
+            }
+        };
+        let _ = std::fs::remove_file(&self.path);
+        result
+    }
+
+    /// Accepts connections and forwards their commands to `requests` until the shutdown
+    /// token is cancelled.
+    #[cfg(windows)]
+    pub async fn run(self, requests: mpsc::Sender<CtlRequest>) -> Result<()> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let security = OwnerOnly::new()?;
+        let mut server =
+            security.create(ServerOptions::new().first_pipe_instance(true), &self.path)?;
+        info!("Control pipe listening on {}", self.path.display());
+        loop {
+            select! {
+                connected = server.connect() => {
+                    connected?;
+                    // The next instance must exist before this one is handed off, or
+                    // clients connecting in between would find no pipe.
+                    let next = security.create(&ServerOptions::new(), &self.path)?;
+                    let client = std::mem::replace(&mut server, next);
+                    spawn_connection(client, requests.clone());
+                }
+                _ = self.shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Refuses a socket directory other users can write to, where they could remove or
+/// replace the socket.
+#[cfg(unix)]
+fn check_socket_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(dir)
+        .map_err(|e| monitor_error!("Control socket directory {}: {}", dir.display(), e))?;
+    // SAFETY: geteuid cannot fail and has no side effects.
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid && metadata.uid() != 0 {
+        return Err(monitor_error!(
+            "Control socket directory {} belongs to another user",
+            dir.display()
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(monitor_error!(
+            "Control socket directory {} is writable by other users",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// A security descriptor whose DACL grants access to the owner alone. Without it, a pipe
+/// gets the default DACL, which lets other local users connect.
+#[cfg(windows)]
+struct OwnerOnly(windows_sys::Win32::Security::PSECURITY_DESCRIPTOR);
+
+// SAFETY: the descriptor is never modified after creation, only read by CreateNamedPipe.
+#[cfg(windows)]
+unsafe impl Send for OwnerOnly {}
+
+#[cfg(windows)]
+impl OwnerOnly {
+    fn new() -> Result<Self> {
+        use windows_sys::Win32::Security::Authorization::{
+            ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+        };
+
+        // Protected DACL with a single ACE: full access for the owner.
+        let sddl: Vec<u16> = "D:P(A;;GA;;;OW)".encode_utf16().chain([0]).collect();
+        let mut descriptor = std::ptr::null_mut();
+        // SAFETY: `sddl` is NUL-terminated and `descriptor` receives a LocalAlloc'd
+        // descriptor, freed on drop.
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            )
+        };
+        if converted == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self(descriptor))
+    }
+
+    fn create(
+        &self,
+        options: &tokio::net::windows::named_pipe::ServerOptions,
+        path: &Path,
+    ) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        use windows_sys::Win32::Security::SECURITY_ATTRIBUTES;
+
+        let mut attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: self.0,
+            bInheritHandle: 0,
+        };
+        // SAFETY: `attributes` is a valid SECURITY_ATTRIBUTES that outlives the call.
+        unsafe {
+            options.create_with_security_attributes_raw(
+                path,
+                &mut attributes as *mut SECURITY_ATTRIBUTES as *mut std::ffi::c_void,
+            )
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for OwnerOnly {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor was allocated by
+        // ConvertStringSecurityDescriptorToSecurityDescriptorW and is freed once.
+        unsafe {
+            windows_sys::Win32::Foundation::LocalFree(self.0);
+        }
+    }
+}
+
+/// Sends `command` to the monitor listening on `path` and returns its output.
+pub async fn send(path: &Path, command: &str) -> Result<String> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(path).await;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(path);
+    let mut stream =
+        stream.map_err(|e| monitor_error!("No monitor listening on {}: {}", path.display(), e))?;
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let mut output = String::new();
+    stream.read_to_string(&mut output).await?;
+    Ok(output)
+}
+
+fn spawn_connection<S>(stream: S, requests: mpsc::Sender<CtlRequest>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(e) = handle_connection(stream, &requests).await {
+            debug!("Control socket connection failed: {}", e);
+        }
+    });
+}
+
+async fn handle_connection<S>(stream: S, requests: &mpsc::Sender<CtlRequest>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut command = String::new();
+    BufReader::new(reader.take(MAX_COMMAND_LENGTH))
+        .read_line(&mut command)
+        .await?;
+    let (reply, output) = oneshot::channel();
+    requests
+        .send(CtlRequest {
+            command: command.trim().to_string(),
+            reply,
+        })
+        .await
+        .map_err(|_| monitor_error!("Monitor is shutting down"))?;
+    let output = tokio::time::timeout(REPLY_TIMEOUT, output)
+        .await
+        .map_err(|_| monitor_error!("No reply within {:?}", REPLY_TIMEOUT))?
+        .map_err(|_| monitor_error!("Monitor is shutting down"))?;
+    writer.write_all(output.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_DIFF_MAX_BYTES: u64 = 256 * 1024;
+const CONTEXT_LINES: usize = 3;
+
+/// Last known content of watched text files, to diff against when they change. Only
+/// UTF-8 files without NUL bytes and up to `max_bytes` are kept, and no more than
+/// `total_max_bytes` of them altogether.
+#[derive(Debug)]
+pub struct TextSnapshots {
+    max_bytes: u64,
+    total_max_bytes: u64,
+    total_bytes: u64,
+    files: HashMap<PathBuf, String>,
+}
+
+impl TextSnapshots {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            total_max_bytes: u64::MAX,
+            total_bytes: 0,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Caps the content kept across all files; files that do not fit are not kept.
+    pub fn with_total_limit(mut self, total_max_bytes: u64) -> Self {
+        self.total_max_bytes = total_max_bytes;
+        self
+    }
+
+    /// Bytes of content kept across all files.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Keeps the current content of `path`, if it is a text file within the limit.
+    pub fn remember(&mut self, path: &Path) {
+        match read_text(path, self.max_bytes) {
+            Some(content) => {
+                self.keep(path, content);
+            }
+            None => self.forget(path),
+        }
+    }
+
+    /// Reads `path` again and returns a unified diff from the content kept for it. `None`
+    /// when the file was not known before, is no longer text within the limit, or did not
+    /// change. Blocks while reading.
+    pub fn update(&mut self, path: &Path) -> Option<String> {
+        let Some(content) = read_text(path, self.max_bytes) else {
+            self.forget(path);
+            return None;
+        };
+        let diff = self
+            .files
+            .get(path)
+            .filter(|previous| **previous != content)
+            .map(|previous| unified_diff(path, previous, &content));
+        self.keep(path, content);
+        diff
+    }
+
+    /// Replaces the content kept for `path`, unless it does not fit in the total limit.
+    fn keep(&mut self, path: &Path, content: String) {
+        self.forget(path);
+        let size = content.len() as u64;
+        if self.total_bytes + size <= self.total_max_bytes {
+            self.total_bytes += size;
+            self.files.insert(path.to_path_buf(), content);
+        }
+    }
+
+    /// Moves the content kept for `from` to `to`, after a rename.
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if let Some(content) = self.files.remove(from) {
+            self.files.insert(to.to_path_buf(), content);
+        }
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        if let Some(content) = self.files.remove(path) {
+            self.total_bytes -= content.len() as u64;
+        }
+    }
+}
+
+/// Unified diff of `old` and `new` with three lines of context, headed by `path`.
+pub fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    let path = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&path, &path)
+        .to_string()
+}
+
+fn read_text(path: &Path, max_bytes: u64) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > max_bytes {
+        return None;
+    }
+    let content = std::fs::read(path).ok()?;
+    if content.contains(&0) {
+        return None;
+    }
+    String::from_utf8(content).ok()
+}
@@ -0,0 +1,176 @@
+use crate::error::{monitor_error, Result};
+use crate::FileEventRecord;
+use log::warn;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+pub const DEFAULT_ENRICHMENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Fields an enricher adds to an event.
+pub type Enrichment = Map<String, Value>;
+
+type EnrichFn = Arc<
+    dyn Fn(FileEventRecord) -> Pin<Box<dyn Future<Output = Result<Enrichment>> + Send>>
+        + Send
+        + Sync,
+>;
+
+#[derive(Clone)]
+enum Source {
+    Function(EnrichFn),
+    Command(Vec<String>),
+}
+
+/// Adds fields to every event before it is recorded, e.g. the owner of a file's asset
+/// looked up in a CMDB. The fields are kept under the enricher's name in
+/// [`FileEventRecord::enrichment`], so rules, history and sinks all see them.
+///
+/// Enrichers run concurrently, each on a task of its own. One that fails, panics or
+/// takes longer than its timeout only loses its own fields, with a warning; the event
+/// is recorded regardless.
+#[derive(Clone)]
+pub struct Enricher {
+    name: String,
+    source: Source,
+    timeout: Duration,
+}
+
+impl Enricher {
+    /// An async function returning the fields to add.
+    pub fn function<F, Fut>(name: &str, enrich: F) -> Self
+    where
+        F: Fn(FileEventRecord) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Enrichment>> + Send + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            source: Source::Function(Arc::new(move |record| Box::pin(enrich(record)))),
+            timeout: DEFAULT_ENRICHMENT_TIMEOUT,
+        }
+    }
+
+    /// An external command, split into arguments like a [`crate::ShellHook`] but run
+    /// once per event with the event as JSON on standard input. It must print a JSON
+    /// object of the fields to add.
+    pub fn command(name: &str, command: &str) -> Result<Self> {
+        let argv = shlex::split(command)
+            .filter(|argv| !argv.is_empty())
+            .ok_or_else(|| monitor_error!("Enricher {} command is empty or badly quoted", name))?;
+        Ok(Self {
+            name: name.to_string(),
+            source: Source::Command(argv),
+            timeout: DEFAULT_ENRICHMENT_TIMEOUT,
+        })
+    }
+
+    /// How long the enricher may take per event; a command still running is killed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn enrich(&self, record: FileEventRecord) -> Result<Enrichment> {
+        let enriched = match &self.source {
+            Source::Function(enrich) => tokio::time::timeout(self.timeout, enrich(record)).await,
+            Source::Command(argv) => {
+                tokio::time::timeout(self.timeout, execute(argv, &record)).await
+            }
+        };
+        enriched.map_err(|_| monitor_error!("timed out after {:?}", self.timeout))?
+    }
+}
+
+impl fmt::Debug for Enricher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match &self.source {
+            Source::Function(_) => "function".to_string(),
+            Source::Command(argv) => format!("{:?}", argv),
+        };
+        f.debug_struct("Enricher")
+            .field("name", &self.name)
+            .field("source", &source)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Runs every enricher on `record`, returning the fields of those that succeeded by name.
+pub(crate) async fn enrich(
+    enrichers: &[Enricher],
+    record: &FileEventRecord,
+) -> BTreeMap<String, Value> {
+    let tasks: Vec<_> = enrichers
+        .iter()
+        .map(|enricher| {
+            let enricher = enricher.clone();
+            let record = record.clone();
+            tokio::spawn(async move { enricher.enrich(record).await })
+        })
+        .collect();
+    let mut enrichment = BTreeMap::new();
+    for (enricher, task) in enrichers.iter().zip(tasks) {
+        match task.await {
+            Ok(Ok(fields)) => {
+                enrichment.insert(enricher.name.clone(), Value::Object(fields));
+            }
+            Ok(Err(e)) => warn!(
+                "Enricher {} failed for {}: {}",
+                enricher.name,
+                record.path.display(),
+                e
+            ),
+            Err(e) => warn!(
+                "Enricher {} panicked for {}: {}",
+                enricher.name,
+                record.path.display(),
+                e
+            ),
+        }
+    }
+    enrichment
+}
+
+async fn execute(argv: &[String], record: &FileEventRecord) -> Result<Enrichment> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| monitor_error!("empty command"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that does not need the event may exit without reading it.
+        if let Err(e) = stdin.write_all(&serde_json::to_vec(record)?).await {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+        }
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(monitor_error!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    match serde_json::from_slice(&output.stdout)? {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(monitor_error!("output is not a JSON object")),
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+/// Errors returned by the library.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum MonitorError {
+    /// The watcher could not start observing `path`.
+    #[error("Failed to watch {}: {source}", path.display())]
+    WatchFailed {
+        path: PathBuf,
+        #[source]
+        source: notify::Error,
+    },
+    #[error("{} does not exist", .0.display())]
+    PathNotFound(PathBuf),
+    /// The path exists but cannot be watched, e.g. a socket or device.
+    #[error("{} is not a {expected}", path.display())]
+    WrongPathType {
+        path: PathBuf,
+        expected: &'static str,
+    },
+    #[error("Already watching {}", .0.display())]
+    AlreadyWatching(PathBuf),
+    #[error("Not watching {}", .0.display())]
+    NotWatching(PathBuf),
+    /// A display substitution was requested for a path other than the watched one.
+    #[error(
+        "Cannot substitute {}: the watched path is {}",
+        expected.display(),
+        current.display()
+    )]
+    SubstitutionMismatch { expected: PathBuf, current: PathBuf },
+    /// An optional feature, e.g. backups, is used without being enabled.
+    #[error("{0} is not enabled")]
+    NotEnabled(&'static str),
+    /// No `kind` (watchset, webhook, ...) is named `name`.
+    #[error("No {kind} {name}")]
+    NotFound { kind: &'static str, name: String },
+    #[error("{kind} {name} already exists")]
+    AlreadyExists { kind: &'static str, name: String },
+    /// A configuration file, rule or setting is invalid.
+    #[error("{0}")]
+    InvalidConfig(String),
+    /// A supervised task failed and its restart policy gave up.
+    #[error("task {name} failed: {error}")]
+    TaskFailed { name: String, error: String },
+    /// A circuit breaker refused the call.
+    #[error("Circuit {0} is open after repeated failures")]
+    CircuitOpen(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Notify(#[from] notify::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = MonitorError> = std::result::Result<T, E>;
+
+/// A [`MonitorError::Other`] with a formatted message.
+macro_rules! monitor_error {
+    ($($arg:tt)*) => {
+        $crate::error::MonitorError::Other(format!($($arg)*))
+    };
+}
+
+/// A [`MonitorError::InvalidConfig`] with a formatted message.
+macro_rules! invalid_config {
+    ($($arg:tt)*) => {
+        $crate::error::MonitorError::InvalidConfig(format!($($arg)*))
+    };
+}
+
+pub(crate) use {invalid_config, monitor_error};
@@ -0,0 +1,330 @@
+use crate::error::{monitor_error, MonitorError, Result};
+use crate::webhook::{post_json, HttpUrl};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// How often due escalations are looked for, at most.
+pub const ESCALATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+/// Acknowledged and fully escalated alerts are dropped from the store after this long.
+const RETENTION_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    /// Arguments of a command run with the alert as JSON on standard input.
+    Command(Vec<String>),
+    Webhook(String),
+}
+
+/// Where an unacknowledged alert is sent once it is `after` old, e.g. mail after 15
+/// minutes and the pager after an hour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscalationStep {
+    pub after: Duration,
+    target: Target,
+}
+
+impl EscalationStep {
+    /// A command split into arguments like a [`crate::ShellHook`], with `{id}`, `{rule}`,
+    /// `{reason}` and `{path}` substituted, e.g. `mail -s "Alert {id}: {reason}" oncall`.
+    /// It gets the alert as JSON on standard input.
+    pub fn command(after: Duration, command: &str) -> Result<Self> {
+        let argv = shlex::split(command)
+            .filter(|argv| !argv.is_empty())
+            .ok_or_else(|| monitor_error!("Escalation command is empty or badly quoted"))?;
+        Ok(Self {
+            after,
+            target: Target::Command(argv),
+        })
+    }
+
+    /// Posts the alert as JSON to a plain `http://` URL.
+    pub fn webhook(after: Duration, url: &str) -> Result<Self> {
+        HttpUrl::parse(url)?;
+        Ok(Self {
+            after,
+            target: Target::Webhook(url.to_string()),
+        })
+    }
+
+    async fn fire(&self, alert: &EscalatedAlert, step: usize) -> Result<()> {
+        let payload = serde_json::to_vec(&json!({
+            "alert": alert,
+            "escalation": step + 1,
+            "age_secs": (Local::now() - alert.raised_at).num_seconds(),
+        }))?;
+        match &self.target {
+            Target::Webhook(url) => post_json(url, &payload).await.map(|_| ()),
+            Target::Command(argv) => {
+                tokio::time::timeout(COMMAND_TIMEOUT, execute(argv, alert, &payload))
+                    .await
+                    .map_err(|_| monitor_error!("timed out after {:?}", COMMAND_TIMEOUT))?
+            }
+        }
+    }
+}
+
+/// Which alerts are escalated and how. Critical alerts, about the monitor's own
+/// configuration, always are; rule and rate alerts only if their rule is listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscalationPolicy {
+    /// File keeping the alerts and their escalations across restarts.
+    pub store: PathBuf,
+    pub rules: Vec<String>,
+    pub steps: Vec<EscalationStep>,
+}
+
+impl EscalationPolicy {
+    pub fn new<P: AsRef<Path>>(store: P) -> Self {
+        Self {
+            store: store.as_ref().to_path_buf(),
+            rules: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Also escalates the alerts of rule or rate alert `name`.
+    pub fn rule(mut self, name: &str) -> Self {
+        self.rules.push(name.to_string());
+        self
+    }
+
+    pub fn step(mut self, step: EscalationStep) -> Self {
+        self.steps.push(step);
+        self.steps.sort_by_key(|step| step.after);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acknowledgement {
+    pub by: String,
+    pub at: DateTime<Local>,
+}
+
+/// An alert tracked for escalation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscalatedAlert {
+    pub id: String,
+    /// Rule that raised it; `None` for a critical alert.
+    pub rule: Option<String>,
+    pub reason: String,
+    pub path: Option<PathBuf>,
+    pub raised_at: DateTime<Local>,
+    /// Steps of the policy that were fired, in order.
+    pub escalations: usize,
+    pub acknowledged: Option<Acknowledgement>,
+}
+
+/// Re-sends alerts nobody acknowledged to the policy's steps as they age, so an alert
+/// seen only on an unwatched dashboard still reaches someone.
+pub struct AlertEscalator {
+    policy: EscalationPolicy,
+    alerts: Mutex<Vec<EscalatedAlert>>,
+}
+
+impl AlertEscalator {
+    /// Loads the alerts tracked before from the policy's store.
+    pub fn open(policy: EscalationPolicy) -> Result<Self> {
+        let alerts = match std::fs::read(&policy.store) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            policy,
+            alerts: Mutex::new(alerts),
+        })
+    }
+
+    pub fn policy(&self) -> &EscalationPolicy {
+        &self.policy
+    }
+
+    /// Whether alerts of `rule` are escalated; `None` is a critical alert.
+    pub fn covers(&self, rule: Option<&str>) -> bool {
+        rule.is_none_or(|rule| self.policy.rules.iter().any(|r| r == rule))
+    }
+
+    /// How often to look for due escalations.
+    pub fn check_interval(&self) -> Duration {
+        self.policy
+            .steps
+            .first()
+            .map_or(ESCALATION_CHECK_INTERVAL, |step| {
+                step.after.min(ESCALATION_CHECK_INTERVAL)
+            })
+            .max(Duration::from_millis(100))
+    }
+
+    /// Starts tracking an alert, returning its id.
+    pub async fn raise(&self, rule: Option<&str>, reason: &str, path: Option<&Path>) -> String {
+        let raised_at = Local::now();
+        let mut alerts = self.alerts.lock().await;
+        let base = format!("A{}", raised_at.format("%Y%m%d%H%M%S%3f"));
+        let mut id = base.clone();
+        let mut suffix = 1;
+        while alerts.iter().any(|alert| alert.id == id) {
+            suffix += 1;
+            id = format!("{}-{}", base, suffix);
+        }
+        alerts.push(EscalatedAlert {
+            id: id.clone(),
+            rule: rule.map(str::to_string),
+            reason: reason.to_string(),
+            path: path.map(Path::to_path_buf),
+            raised_at,
+            escalations: 0,
+            acknowledged: None,
+        });
+        self.save(&mut alerts).await;
+        id
+    }
+
+    /// Stops escalating alert `id`.
+    pub async fn acknowledge(&self, id: &str, by: &str) -> Result<()> {
+        let mut alerts = self.alerts.lock().await;
+        let alert = alerts
+            .iter_mut()
+            .find(|alert| alert.id == id)
+            .ok_or_else(|| MonitorError::NotFound {
+                kind: "alert",
+                name: id.to_string(),
+            })?;
+        if let Some(acknowledged) = &alert.acknowledged {
+            return Err(monitor_error!(
+                "Alert {} was acknowledged by {}",
+                id,
+                acknowledged.by
+            ));
+        }
+        alert.acknowledged = Some(Acknowledgement {
+            by: by.to_string(),
+            at: Local::now(),
+        });
+        info!("Alert {} acknowledged by {}", id, by);
+        self.save(&mut alerts).await;
+        Ok(())
+    }
+
+    /// Alerts not acknowledged yet, oldest first.
+    pub async fn open_alerts(&self) -> Vec<EscalatedAlert> {
+        self.alerts
+            .lock()
+            .await
+            .iter()
+            .filter(|alert| alert.acknowledged.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Fires the next step of every unacknowledged alert old enough for it, returning
+    /// how many were fired. A step that fails is tried again on the next call.
+    pub async fn escalate_due(&self) -> usize {
+        let now = Local::now();
+        let due: Vec<_> = self
+            .open_alerts()
+            .await
+            .into_iter()
+            .filter_map(|alert| {
+                let step = self.policy.steps.get(alert.escalations)?;
+                let age = (now - alert.raised_at).to_std().unwrap_or_default();
+                (age >= step.after).then_some((alert, step))
+            })
+            .collect();
+        if due.is_empty() {
+            return 0;
+        }
+
+        let mut fired = Vec::new();
+        for (alert, step) in due {
+            match step.fire(&alert, alert.escalations).await {
+                Ok(()) => {
+                    warn!(
+                        "Alert {} unacknowledged for {}s, escalated ({}): {}",
+                        alert.id,
+                        (now - alert.raised_at).num_seconds(),
+                        alert.escalations + 1,
+                        alert.reason
+                    );
+                    fired.push(alert.id);
+                }
+                Err(e) => warn!("Failed to escalate alert {}: {}", alert.id, e),
+            }
+        }
+        let mut alerts = self.alerts.lock().await;
+        for alert in alerts.iter_mut().filter(|alert| fired.contains(&alert.id)) {
+            alert.escalations += 1;
+        }
+        self.save(&mut alerts).await;
+        fired.len()
+    }
+
+    async fn save(&self, alerts: &mut Vec<EscalatedAlert>) {
+        let cutoff = Local::now() - chrono::Duration::days(RETENTION_DAYS);
+        let steps = self.policy.steps.len();
+        alerts.retain(|alert| {
+            alert.raised_at > cutoff || (alert.acknowledged.is_none() && alert.escalations < steps)
+        });
+        let data = serde_json::to_vec(&*alerts).unwrap_or_default();
+        if let Err(e) = tokio::fs::write(&self.policy.store, data).await {
+            warn!(
+                "Failed to save alerts to {}: {}",
+                self.policy.store.display(),
+                e
+            );
+        }
+    }
+}
+
+async fn execute(argv: &[String], alert: &EscalatedAlert, payload: &[u8]) -> Result<()> {
+    let path = alert
+        .path
+        .as_ref()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let argv: Vec<String> = argv
+        .iter()
+        .map(|arg| {
+            arg.replace("{id}", &alert.id)
+                .replace("{rule}", alert.rule.as_deref().unwrap_or("critical"))
+                .replace("{reason}", &alert.reason)
+                .replace("{path}", &path)
+        })
+        .collect();
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| monitor_error!("empty command"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that does not need the alert may exit without reading it.
+        if let Err(e) = stdin.write_all(payload).await {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+        }
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(monitor_error!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
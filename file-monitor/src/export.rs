@@ -0,0 +1,242 @@
+use crate::error::{monitor_error, MonitorError, Result};
+use crate::{FileEvent, FileEventRecord, Watchset};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = MonitorError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(monitor_error!(
+                "Unknown export format {} (expected json or csv)",
+                s
+            )),
+        }
+    }
+}
+
+/// One event as a single-line JSON object, for piping live output into `jq` or log shippers.
+pub fn event_line(record: &FileEventRecord, substituted_path: &Path) -> String {
+    let mut line = json!({
+        "timestamp": record.time.to_rfc3339(),
+        "event": record.event.kind(),
+        "path": record.path,
+        "substituted_path": substituted_path,
+        "watch": record.watch,
+    });
+    if let Some(watchset) = &record.watchset {
+        line["watchset"] = json!(watchset);
+    }
+    if let FileEvent::Renamed { from, to } = &record.event {
+        line["renamed_from"] = json!(from);
+        line["renamed_to"] = json!(to);
+    }
+    if let FileEvent::Replaced(temp) = &record.event {
+        line["replaced_via"] = json!(temp);
+    }
+    if let FileEvent::Trashed(location) = &record.event {
+        line["trashed_to"] = json!(location);
+    }
+    if let Some(metadata) = &record.metadata {
+        line["metadata"] = json!(metadata);
+    }
+    if let Some(size_change) = &record.size_change {
+        line["size_change"] = json!(size_change);
+    }
+    if let Some(lines) = &record.appended_lines {
+        line["appended_lines"] = json!(lines);
+    }
+    if !record.enrichment.is_empty() {
+        line["enrichment"] = json!(record.enrichment);
+    }
+    if let Some(process) = &record.process {
+        line["process"] = json!(process);
+    }
+    if let Some(occurred_at) = &record.occurred_at {
+        line["occurred_at"] = json!(occurred_at.to_rfc3339());
+    }
+    if let Some(label) = &record.maintenance {
+        line["maintenance"] = json!(label);
+    }
+    if let Some(content) = &record.content {
+        line["content"] = json!(content);
+    }
+    line.to_string()
+}
+
+/// Event count of one kind under one watched path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatsRow {
+    pub watch: PathBuf,
+    pub event: &'static str,
+    pub count: usize,
+}
+
+/// Flattens per-watch stats into rows sorted by watch and event kind. Renames to different
+/// targets are counted together.
+pub fn stats_rows(watch_stats: &HashMap<PathBuf, HashMap<FileEvent, usize>>) -> Vec<StatsRow> {
+    let mut counts: BTreeMap<(PathBuf, &'static str), usize> = BTreeMap::new();
+    for (watch, stats) in watch_stats {
+        for (event, count) in stats {
+            *counts.entry((watch.clone(), event.kind())).or_insert(0) += count;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|((watch, event), count)| StatsRow {
+            watch,
+            event,
+            count,
+        })
+        .collect()
+}
+
+/// Event count of one kind under all paths of one watchset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WatchsetStatsRow {
+    pub watchset: String,
+    pub event: &'static str,
+    pub count: usize,
+}
+
+/// Per-watchset totals, sorted by watchset and event kind.
+pub fn watchset_stats_rows(
+    watchsets: &[Watchset],
+    watch_stats: &HashMap<PathBuf, HashMap<FileEvent, usize>>,
+) -> Vec<WatchsetStatsRow> {
+    let mut counts: BTreeMap<(String, &'static str), usize> = BTreeMap::new();
+    for watchset in watchsets {
+        for (event, count) in watchset.stats(watch_stats) {
+            *counts
+                .entry((watchset.name.clone(), event.kind()))
+                .or_insert(0) += count;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|((watchset, event), count)| WatchsetStatsRow {
+            watchset,
+            event,
+            count,
+        })
+        .collect()
+}
+
+/// Writes history as a JSON array, or as CSV with one row per event.
+pub fn write_history<W: Write>(
+    history: &[FileEventRecord],
+    format: ExportFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(&mut out, history)?,
+        ExportFormat::Csv => {
+            writeln!(
+                out,
+                "time,watch,path,event,renamed_to,maintenance,container,git_branch"
+            )?;
+            for record in history {
+                let renamed_to = match &record.event {
+                    FileEvent::Renamed { to, .. } => to.display().to_string(),
+                    _ => String::new(),
+                };
+                let container = record.container.as_ref().map(|container| {
+                    container
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| container.id.clone())
+                });
+                let branch = record.git.as_ref().and_then(|git| git.branch.clone());
+                write_csv_row(
+                    &mut out,
+                    &[
+                        record.time.to_rfc3339(),
+                        record.watch.display().to_string(),
+                        record.path.display().to_string(),
+                        record.event.kind().to_string(),
+                        renamed_to,
+                        record.maintenance.clone().unwrap_or_default(),
+                        container.unwrap_or_default(),
+                        branch.unwrap_or_default(),
+                    ],
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_stats<W: Write>(rows: &[StatsRow], format: ExportFormat, mut out: W) -> Result<()> {
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(&mut out, rows)?,
+        ExportFormat::Csv => {
+            writeln!(out, "watch,event,count")?;
+            for row in rows {
+                write_csv_row(
+                    &mut out,
+                    &[
+                        row.watch.display().to_string(),
+                        row.event.to_string(),
+                        row.count.to_string(),
+                    ],
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_watchset_stats<W: Write>(
+    rows: &[WatchsetStatsRow],
+    format: ExportFormat,
+    mut out: W,
+) -> Result<()> {
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(&mut out, rows)?,
+        ExportFormat::Csv => {
+            writeln!(out, "watchset,event,count")?;
+            for row in rows {
+                write_csv_row(
+                    &mut out,
+                    &[
+                        row.watchset.clone(),
+                        row.event.to_string(),
+                        row.count.to_string(),
+                    ],
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv_row<W: Write>(out: &mut W, fields: &[String]) -> Result<()> {
+    let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+    writeln!(out, "{}", fields.join(","))?;
+    Ok(())
+}
+
+/// Quotes a field per RFC 4180 when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
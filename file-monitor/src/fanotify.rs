@@ -0,0 +1,404 @@
+use notify::Event;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The process that caused an event, as reported by the fanotify backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Resolved when the event is read; `None` if the process had already exited.
+    pub executable: Option<PathBuf>,
+}
+
+impl ProcessInfo {
+    /// The process attached to `event` by the fanotify backend, which passes the
+    /// executable as the event's info.
+    pub(crate) fn from_event(event: &Event) -> Option<Self> {
+        Some(Self {
+            pid: event.attrs.process_id()?,
+            executable: event.info().map(PathBuf::from),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::FanotifyWatcher;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::backend::{BackendWatcher, EventHandler};
+    use crate::error::{monitor_error, MonitorError, Result};
+    use log::{debug, warn};
+    use notify::event::{
+        AccessKind, AccessMode, CreateKind, DataChange, MetadataKind, ModifyKind, RemoveKind,
+        RenameMode,
+    };
+    use notify::{Event, EventKind, RecursiveMode};
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+
+    /// The changes notify's inotify backend subscribes to, so both report the same events.
+    const MASK: u64 = libc::FAN_ATTRIB
+        | libc::FAN_CREATE
+        | libc::FAN_DELETE
+        | libc::FAN_CLOSE_WRITE
+        | libc::FAN_MODIFY
+        | libc::FAN_MOVED_FROM
+        | libc::FAN_MOVED_TO
+        | libc::FAN_EVENT_ON_CHILD
+        | libc::FAN_ONDIR;
+    const MAX_HANDLE_SZ: usize = 128;
+    const POLL_TIMEOUT_MS: libc::c_int = 250;
+
+    #[repr(C)]
+    struct FileHandle {
+        handle_bytes: u32,
+        handle_type: i32,
+        f_handle: [u8; MAX_HANDLE_SZ],
+    }
+
+    #[derive(Default)]
+    struct Marks {
+        /// Marked directories by file handle, to resolve the handles events carry.
+        dirs: HashMap<Vec<u8>, PathBuf>,
+        /// Marked inodes by watch, to remove them again.
+        by_watch: HashMap<PathBuf, Vec<PathBuf>>,
+        recursive: Vec<PathBuf>,
+    }
+
+    /// Watches through fanotify, which unlike inotify reports the process behind each
+    /// change. Uses directory-name reporting (Linux 5.9+) and needs `CAP_SYS_ADMIN`; without
+    /// it, the kernel hides the PID of other processes. Events on a watched directory
+    /// itself being deleted or moved are not reported.
+    pub(crate) struct FanotifyWatcher {
+        fd: Arc<OwnedFd>,
+        marks: Arc<Mutex<Marks>>,
+        stop: Arc<AtomicBool>,
+        reader: Option<JoinHandle<()>>,
+    }
+
+    impl FanotifyWatcher {
+        pub(crate) fn new(handler: EventHandler) -> Result<Self> {
+            let fd = unsafe {
+                libc::fanotify_init(
+                    libc::FAN_CLASS_NOTIF
+                        | libc::FAN_CLOEXEC
+                        | libc::FAN_NONBLOCK
+                        | libc::FAN_REPORT_DFID_NAME,
+                    (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint,
+                )
+            };
+            if fd < 0 {
+                return Err(monitor_error!(
+                    "fanotify unavailable: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+            let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+            let marks = Arc::new(Mutex::new(Marks::default()));
+            let stop = Arc::new(AtomicBool::new(false));
+            let reader = {
+                let (fd, marks, stop) = (Arc::clone(&fd), Arc::clone(&marks), Arc::clone(&stop));
+                std::thread::Builder::new()
+                    .name("fanotify".to_string())
+                    .spawn(move || read_events(&fd, &marks, &stop, &handler))?
+            };
+            Ok(Self {
+                fd,
+                marks,
+                stop,
+                reader: Some(reader),
+            })
+        }
+    }
+
+    impl BackendWatcher for FanotifyWatcher {
+        fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+            let mut marks = self.marks.lock().unwrap();
+            let fd = self.fd.as_raw_fd();
+            let mut marked = Vec::new();
+            if path.is_dir() {
+                mark_tree(fd, &mut marks, path, mode, &mut marked)?;
+                if mode == RecursiveMode::Recursive {
+                    marks.recursive.push(path.to_path_buf());
+                }
+            } else {
+                // Events on a file are reported with its directory's handle and its name.
+                mark(fd, libc::FAN_MARK_ADD, path)?;
+                if let Some(parent) = path.parent() {
+                    let key = handle_key(parent)?;
+                    marks.dirs.insert(key, parent.to_path_buf());
+                }
+                marked.push(path.to_path_buf());
+            }
+            debug!("fanotify marks on {}: {}", path.display(), marked.len());
+            marks.by_watch.insert(path.to_path_buf(), marked);
+            Ok(())
+        }
+
+        fn unwatch(&mut self, path: &Path) -> Result<()> {
+            let mut marks = self.marks.lock().unwrap();
+            let Some(marked) = marks.by_watch.remove(path) else {
+                return Err(MonitorError::NotWatching(path.to_path_buf()));
+            };
+            for inode in &marked {
+                // Fails for inodes already gone, whose marks went with them.
+                let _ = mark(self.fd.as_raw_fd(), libc::FAN_MARK_REMOVE, inode);
+            }
+            marks.dirs.retain(|_, dir| !marked.contains(dir));
+            marks.recursive.retain(|watch| watch != path);
+            Ok(())
+        }
+    }
+
+    impl Drop for FanotifyWatcher {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(reader) = self.reader.take() {
+                let _ = reader.join();
+            }
+        }
+    }
+
+    fn mark(fd: RawFd, action: libc::c_uint, path: &Path) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let result =
+            unsafe { libc::fanotify_mark(fd, action, MASK, libc::AT_FDCWD, path.as_ptr()) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn mark_tree(
+        fd: RawFd,
+        marks: &mut Marks,
+        dir: &Path,
+        mode: RecursiveMode,
+        marked: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        mark(fd, libc::FAN_MARK_ADD, dir)?;
+        marks.dirs.insert(handle_key(dir)?, dir.to_path_buf());
+        marked.push(dir.to_path_buf());
+        if mode == RecursiveMode::NonRecursive {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if let Err(e) = mark_tree(fd, marks, &entry.path(), mode, marked) {
+                    debug!("Not marking {}: {}", entry.path().display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The handle type and bytes of `path`, as they appear in events.
+    fn handle_key(path: &Path) -> io::Result<Vec<u8>> {
+        let name = CString::new(path.as_os_str().as_bytes())?;
+        let mut handle = FileHandle {
+            handle_bytes: MAX_HANDLE_SZ as u32,
+            handle_type: 0,
+            f_handle: [0; MAX_HANDLE_SZ],
+        };
+        let mut mount_id: libc::c_int = 0;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_name_to_handle_at,
+                libc::AT_FDCWD,
+                name.as_ptr(),
+                &mut handle as *mut FileHandle,
+                &mut mount_id as *mut libc::c_int,
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(key(
+            handle.handle_type,
+            &handle.f_handle[..handle.handle_bytes as usize],
+        ))
+    }
+
+    fn key(handle_type: i32, handle: &[u8]) -> Vec<u8> {
+        let mut key = handle_type.to_ne_bytes().to_vec();
+        key.extend_from_slice(handle);
+        key
+    }
+
+    fn read_events(fd: &OwnedFd, marks: &Mutex<Marks>, stop: &AtomicBool, handler: &EventHandler) {
+        let mut buffer = vec![0u8; 64 * 1024];
+        while !stop.load(Ordering::Relaxed) {
+            let mut poll = libc::pollfd {
+                fd: fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT_MS) } <= 0 {
+                continue;
+            }
+            let read = unsafe {
+                libc::read(
+                    fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read <= 0 {
+                continue;
+            }
+            let mut offset = 0;
+            while offset + std::mem::size_of::<libc::fanotify_event_metadata>() <= read as usize {
+                let metadata: libc::fanotify_event_metadata =
+                    unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const _) };
+                let end = offset + metadata.event_len as usize;
+                if metadata.event_len == 0 || end > read as usize {
+                    break;
+                }
+                if metadata.mask & libc::FAN_Q_OVERFLOW != 0 {
+                    handler(Err(notify::Error::generic("fanotify queue overflow")));
+                } else {
+                    // Bound first, so the lock is released before `dispatch` marks new
+                    // directories.
+                    let path = event_path(
+                        &buffer[offset + metadata.metadata_len as usize..end],
+                        &marks.lock().unwrap(),
+                    );
+                    if let Some(path) = path {
+                        dispatch(fd, marks, metadata.mask, metadata.pid, path, handler);
+                    }
+                }
+                offset = end;
+            }
+        }
+    }
+
+    /// The path named by the directory handle and name in an event's info records.
+    fn event_path(mut info: &[u8], marks: &Marks) -> Option<PathBuf> {
+        // Header (type, padding, length), fsid, then a file handle followed by the name.
+        const HEADER: usize = 4;
+        const FSID: usize = 8;
+        while info.len() >= HEADER {
+            let (info_type, len) = (info[0], u16::from_ne_bytes([info[2], info[3]]) as usize);
+            if len < HEADER || len > info.len() {
+                return None;
+            }
+            let record = &info[..len];
+            info = &info[len..];
+            if info_type != libc::FAN_EVENT_INFO_TYPE_DFID_NAME {
+                continue;
+            }
+            let handle = record.get(HEADER + FSID..)?;
+            let handle_bytes = u32::from_ne_bytes(handle.get(..4)?.try_into().ok()?) as usize;
+            let handle_type = i32::from_ne_bytes(handle.get(4..8)?.try_into().ok()?);
+            let name = handle.get(8 + handle_bytes..)?;
+            let dir = marks
+                .dirs
+                .get(&key(handle_type, handle.get(8..8 + handle_bytes)?))?;
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            return Some(match name {
+                b"." => dir.clone(),
+                name => dir.join(OsStr::from_bytes(name)),
+            });
+        }
+        None
+    }
+
+    fn dispatch(
+        fd: &OwnedFd,
+        marks: &Mutex<Marks>,
+        mask: u64,
+        pid: i32,
+        path: PathBuf,
+        handler: &EventHandler,
+    ) {
+        let is_dir = mask & libc::FAN_ONDIR != 0;
+        if is_dir && mask & (libc::FAN_CREATE | libc::FAN_MOVED_TO) != 0 {
+            mark_new_dir(fd, marks, &path);
+        }
+        let executable = std::fs::read_link(format!("/proc/{}/exe", pid)).ok();
+        let kinds = [
+            (
+                libc::FAN_CREATE,
+                EventKind::Create(if is_dir {
+                    CreateKind::Folder
+                } else {
+                    CreateKind::File
+                }),
+            ),
+            (
+                libc::FAN_MOVED_FROM,
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            ),
+            (
+                libc::FAN_MOVED_TO,
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            ),
+            (
+                libc::FAN_MODIFY,
+                EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            ),
+            (
+                libc::FAN_ATTRIB,
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+            ),
+            (
+                libc::FAN_CLOSE_WRITE,
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+            ),
+            (
+                libc::FAN_DELETE,
+                EventKind::Remove(if is_dir {
+                    RemoveKind::Folder
+                } else {
+                    RemoveKind::File
+                }),
+            ),
+        ];
+        for (bit, kind) in kinds {
+            if mask & bit == 0 {
+                continue;
+            }
+            let mut event = Event::new(kind).add_path(path.clone());
+            if pid > 0 {
+                event = event.set_process_id(pid as u32);
+                if let Some(executable) = &executable {
+                    event = event.set_info(&executable.to_string_lossy());
+                }
+            }
+            handler(Ok(event));
+        }
+    }
+
+    /// Marks a directory created or moved into a recursive watch.
+    fn mark_new_dir(fd: &OwnedFd, marks: &Mutex<Marks>, dir: &Path) {
+        let mut marks = marks.lock().unwrap();
+        let Some(watch) = marks
+            .recursive
+            .iter()
+            .find(|watch| dir.starts_with(watch))
+            .cloned()
+        else {
+            return;
+        };
+        let mut marked = Vec::new();
+        if let Err(e) = mark_tree(
+            fd.as_raw_fd(),
+            &mut marks,
+            dir,
+            RecursiveMode::Recursive,
+            &mut marked,
+        ) {
+            warn!("Failed to watch new directory {}: {}", dir.display(), e);
+        }
+        marks.by_watch.entry(watch).or_default().extend(marked);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    /// Only paths matching at least one include filter are recorded.
+    Include,
+    /// Paths matching an exclude filter are dropped, even if included.
+    Exclude,
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterKind::Include => write!(f, "include"),
+            FilterKind::Exclude => write!(f, "exclude"),
+        }
+    }
+}
+
+/// A glob such as `*.tmp` or `**/target/**`.
+///
+/// `*` and `?` never cross a `/`, `**` matches any number of directories. Patterns without
+/// a `/` are matched against the file name only, like in `.gitignore`.
+#[derive(Debug, Clone)]
+pub struct GlobPattern {
+    pattern: String,
+    regex: Regex,
+    name_only: bool,
+}
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let name_only = !pattern.contains('/');
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex: Regex::new(&glob_to_regex(pattern))?,
+            name_only,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.name_only {
+            return path
+                .file_name()
+                .is_some_and(|name| self.regex.is_match(&name.to_string_lossy()));
+        }
+        self.regex
+            .is_match(&path.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    // Relative patterns with a `/` may match at any depth.
+    if !pattern.starts_with('/') && !pattern.starts_with("**") && pattern.contains('/') {
+        regex.push_str("(?:.*/)?");
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Include/exclude globs deciding which paths reach history and stats.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    filters: Vec<(FilterKind, GlobPattern)>,
+}
+
+impl PathFilter {
+    pub fn add(&mut self, kind: FilterKind, pattern: &str) -> Result<()> {
+        let glob = GlobPattern::new(pattern)?;
+        self.filters
+            .retain(|(_, existing)| existing.as_str() != pattern);
+        self.filters.push((kind, glob));
+        Ok(())
+    }
+
+    /// Removes the filter with this pattern. Returns whether one existed.
+    pub fn remove(&mut self, pattern: &str) -> bool {
+        let before = self.filters.len();
+        self.filters
+            .retain(|(_, existing)| existing.as_str() != pattern);
+        self.filters.len() != before
+    }
+
+    pub fn list(&self) -> Vec<(FilterKind, String)> {
+        self.filters
+            .iter()
+            .map(|(kind, glob)| (*kind, glob.as_str().to_string()))
+            .collect()
+    }
+
+    pub fn allows(&self, path: &Path) -> bool {
+        let mut has_includes = false;
+        let mut included = false;
+        for (kind, glob) in &self.filters {
+            match kind {
+                FilterKind::Exclude if glob.matches(path) => return false,
+                FilterKind::Exclude => {}
+                FilterKind::Include => {
+                    has_includes = true;
+                    included |= glob.matches(path);
+                }
+            }
+        }
+        !has_includes || included
+    }
+}
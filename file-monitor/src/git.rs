@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Whether git knows about a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GitFileStatus {
+    Tracked,
+    Untracked,
+    Ignored,
+}
+
+/// State of the git repository containing an event's path at the time of the event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitContext {
+    pub repo_root: PathBuf,
+    /// Current branch, or `None` on a detached HEAD or an unborn branch.
+    pub branch: Option<String>,
+    /// Whether the working tree has uncommitted changes.
+    pub dirty: bool,
+    pub file_status: GitFileStatus,
+}
+
+impl GitContext {
+    /// Inspects the repository containing `path` with the `git` CLI. Returns `None` when the
+    /// path is not inside a repository or git is unavailable.
+    pub async fn for_path(path: &Path) -> Option<Self> {
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().filter(|parent| parent.is_dir())?
+        };
+
+        let repo_root = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"]).await?);
+        let branch = git(dir, &["symbolic-ref", "--quiet", "--short", "HEAD"]).await;
+        let dirty = !git(dir, &["status", "--porcelain"]).await?.is_empty();
+
+        let path_arg = path.to_string_lossy();
+        let file_status = if git_succeeds(dir, &["ls-files", "--error-unmatch", &path_arg]).await {
+            GitFileStatus::Tracked
+        } else if git_succeeds(dir, &["check-ignore", "--quiet", &path_arg]).await {
+            GitFileStatus::Ignored
+        } else {
+            GitFileStatus::Untracked
+        };
+
+        Some(Self {
+            repo_root,
+            branch,
+            dirty,
+            file_status,
+        })
+    }
+}
+
+/// Runs git in `dir` and returns its trimmed stdout if it exited successfully.
+async fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+async fn git_succeeds(dir: &Path, args: &[&str]) -> bool {
+    git(dir, args).await.is_some()
+}
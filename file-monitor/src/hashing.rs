@@ -0,0 +1,174 @@
+use crate::config_guard::to_hex;
+use crate::error::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FULL_HASH_MAX_BYTES: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_SAMPLED_HASH_MAX_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+const SAMPLE_BLOCKS: u64 = 16;
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// How a file's content was fingerprinted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashStrategy {
+    /// SHA-256 of the whole file.
+    Full,
+    /// SHA-256 of the previous digest and the bytes appended since, for files that only
+    /// grew. Rewrites of the already hashed part fall back to another strategy.
+    Incremental,
+    /// SHA-256 of the size and evenly spaced blocks, including the first and last one.
+    Sampled,
+    /// Size and modification time only.
+    Metadata,
+}
+
+/// Which strategy is used for a file, chosen by its size: files up to `full_max_bytes`
+/// are hashed in full, files up to `sampled_max_bytes` are sampled (or, with
+/// `incremental`, hashed by their appended regions while they only grow, as logs do), and
+/// larger files only get their size and modification time recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct HashPolicy {
+    pub full_max_bytes: u64,
+    pub sampled_max_bytes: u64,
+    pub incremental: bool,
+}
+
+impl Default for HashPolicy {
+    fn default() -> Self {
+        Self {
+            full_max_bytes: DEFAULT_FULL_HASH_MAX_BYTES,
+            sampled_max_bytes: DEFAULT_SAMPLED_HASH_MAX_BYTES,
+            incremental: true,
+        }
+    }
+}
+
+/// Fingerprint of a file's content after an event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentHash {
+    pub strategy: HashStrategy,
+    pub size: u64,
+    pub modified: Option<DateTime<Local>>,
+    /// Hex SHA-256; absent for [`HashStrategy::Metadata`].
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct HashState {
+    size: u64,
+    sha256: String,
+    /// SHA-256 of the last block before `size`, to tell appends from rewrites.
+    tail: [u8; 32],
+}
+
+/// Fingerprints files under one watch according to its [`HashPolicy`], remembering the
+/// last fingerprint of each file for incremental hashing.
+#[derive(Debug)]
+pub struct ContentHasher {
+    policy: HashPolicy,
+    states: HashMap<PathBuf, HashState>,
+}
+
+impl ContentHasher {
+    pub fn new(policy: HashPolicy) -> Self {
+        Self {
+            policy,
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> HashPolicy {
+        self.policy
+    }
+
+    /// Fingerprints `path` with the strategy its size calls for. Blocks while reading, so
+    /// async callers should run it on a blocking thread.
+    pub fn hash(&mut self, path: &Path) -> Result<ContentHash> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let modified = metadata.modified().ok().map(DateTime::<Local>::from);
+
+        if size > self.policy.sampled_max_bytes {
+            self.states.remove(path);
+            return Ok(ContentHash {
+                strategy: HashStrategy::Metadata,
+                size,
+                modified,
+                sha256: None,
+            });
+        }
+        let previous = self.states.get(path).cloned();
+        let (strategy, sha256) = match previous {
+            Some(previous)
+                if self.policy.incremental
+                    && size > self.policy.full_max_bytes
+                    && size > previous.size
+                    && tail_hash(&mut file, previous.size)? == previous.tail =>
+            {
+                let mut hasher = Sha256::new();
+                hasher.update(previous.sha256.as_bytes());
+                file.seek(SeekFrom::Start(previous.size))?;
+                std::io::copy(&mut (&mut file).take(size - previous.size), &mut hasher)?;
+                (HashStrategy::Incremental, to_hex(&hasher.finalize()))
+            }
+            _ if size <= self.policy.full_max_bytes => {
+                let mut hasher = Sha256::new();
+                file.seek(SeekFrom::Start(0))?;
+                std::io::copy(&mut (&mut file).take(size), &mut hasher)?;
+                (HashStrategy::Full, to_hex(&hasher.finalize()))
+            }
+            _ => (HashStrategy::Sampled, sampled_hash(&mut file, size)?),
+        };
+        let tail = tail_hash(&mut file, size)?;
+        self.states.insert(
+            path.to_path_buf(),
+            HashState {
+                size,
+                sha256: sha256.clone(),
+                tail,
+            },
+        );
+        Ok(ContentHash {
+            strategy,
+            size,
+            modified,
+            sha256: Some(sha256),
+        })
+    }
+
+    /// Drops what is remembered about `path`, e.g. after it was deleted.
+    pub fn forget(&mut self, path: &Path) {
+        self.states.remove(path);
+    }
+}
+
+fn read_block(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut block = Vec::with_capacity(len as usize);
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(len).read_to_end(&mut block)?;
+    Ok(block)
+}
+
+fn tail_hash(file: &mut File, end: u64) -> Result<[u8; 32]> {
+    let start = end.saturating_sub(BLOCK_SIZE);
+    Ok(Sha256::digest(read_block(file, start, end - start)?).into())
+}
+
+fn sampled_hash(file: &mut File, size: u64) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+    let last = size.saturating_sub(BLOCK_SIZE);
+    for i in 0..SAMPLE_BLOCKS {
+        let offset = last * i / (SAMPLE_BLOCKS - 1);
+        hasher.update(read_block(file, offset, BLOCK_SIZE.min(size))?);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
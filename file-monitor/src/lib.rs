@@ -1,387 +1,7550 @@
-use anyhow::Result;
+pub mod alerts;
+pub mod api_keys;
+pub mod atomic_save;
+pub mod attributes;
+pub mod backend;
+pub mod backup;
+pub mod baseline;
+pub mod bench;
+pub mod builder;
+mod callback;
+pub mod config;
+pub mod config_guard;
+pub mod container;
+pub mod control;
+pub mod coverage;
+pub mod ctl;
+pub mod diff;
+pub mod enrich;
+pub mod error;
+pub mod escalation;
+pub mod export;
+pub mod fanotify;
+pub mod filter;
+pub mod git;
+pub mod hashing;
+pub mod maintenance;
+pub mod manager;
+pub mod plugins;
+pub mod profiling;
+pub mod query;
+pub mod rates;
+mod rename;
+pub mod rules;
+pub mod scan;
+pub mod shell_hook;
+pub mod shutdown;
+pub mod spill;
+pub mod subscription;
+pub mod supervisor;
+pub mod tail;
+pub mod throttle;
+pub mod timing;
+pub mod tls;
+pub mod trash;
+pub mod watchset;
+pub mod webhook;
+
+pub use alerts::{RateAlertRule, RateAlertState};
+pub use api_keys::{ApiKey, ApiKeyStore, ApiScope};
+pub use attributes::{FileMetadata, SizeChange};
+pub use backend::{SymlinkPolicy, WatchBackend};
+pub use backup::{BackupPolicy, BackupStore, BackupVersion};
+pub use baseline::{Baseline, BaselineRoot, Drift};
+pub use bench::{BenchConfig, BenchReport};
+pub use builder::FileMonitorBuilder;
+pub use config::MonitorConfig;
+pub use config_guard::{ConfigManifest, Policy};
+pub use container::{ContainerInfo, ContainerResolver};
+pub use control::{ApiCall, ControlServer};
+pub use coverage::{CoverageGap, CoverageReport, CoverageTracker, GapKind};
+pub use ctl::{ControlSocket, CtlRequest};
+pub use diff::TextSnapshots;
+pub use enrich::Enricher;
+pub use error::MonitorError;
+pub use escalation::{AlertEscalator, EscalatedAlert, EscalationPolicy, EscalationStep};
+pub use export::ExportFormat;
+pub use fanotify::ProcessInfo;
+pub use filter::{FilterKind, PathFilter};
+pub use git::{GitContext, GitFileStatus};
+pub use hashing::{ContentHash, ContentHasher, HashPolicy, HashStrategy};
+pub use maintenance::MaintenanceWindow;
+pub use manager::{ManagedStatus, MonitorManager};
+pub use plugins::{PluginLimits, WasmRule};
+pub use profiling::{MemoryFootprint, StartupProfile};
+pub use rates::{EventRate, EventRates};
+pub use rules::{EventRule, GitStatusRule, Verdict};
+pub use scan::ScanProgress;
+pub use shell_hook::{ShellHook, ShellHooks};
+pub use shutdown::ShutdownToken;
+pub use spill::MemoryLimits;
+pub use subscription::{EventSubscription, MonitorEvent};
+pub use supervisor::{RestartPolicy, Supervisor, TaskState, TaskStatus};
+pub use throttle::{BreakerConfig, BreakerState, CircuitBreaker, RateLimit, RateLimiter};
+pub use timing::LatencyMetrics;
+pub use tls::{ReloadableTls, TlsClient, TlsClientSettings, TlsSettings};
+pub use trash::{Trash, TrashEntry};
+pub use watchset::Watchset;
+pub use webhook::{RetryPolicy, Webhook};
+
+use crate::error::{monitor_error, Result};
+use backend::{is_symlink, Watchers};
 use chrono::{DateTime, Local};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use notify::event::{ModifyKind, RenameMode};
 use notify::{Event, EventKind, RecursiveMode, Watcher};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use spill::SpillQueue;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
 use std::io::Result as IoResult;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use timing::ReceivedEvent;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::Mutex;
 
+use atomic_save::{AtomicSaveCoalescer, Coalesced};
+use attributes::AttributeTracker;
+use callback::CallbackThread;
+use config_guard::ConfigGuard;
+use rename::RenamePairer;
+use scan::{Scan, ScanTargets};
+
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::OpenOptionsExt as WindowsOpenOptionsExt;
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::os::unix::fs::OpenOptionsExt as UnixOpenOptionsExt;
 
+pub type EventHistory = Vec<FileEventRecord>;
+
+/// How often lost watches are checked for their path being recreated.
+const LOST_WATCH_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A custom handler run for every recorded event, see [`FileMonitor::on_event`].
+pub type EventHook = Box<dyn FnMut(&FileEventRecord) + Send>;
+
+/// A recorded event together with the file that triggered it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEventRecord {
+    /// Sequence number within one monitor run, for [`FileMonitor::get_history_detail`].
+    #[serde(default)]
+    pub id: u64,
+    /// When the monitor recorded the event.
+    pub time: DateTime<Local>,
+    /// When the change happened, for events from the watcher: the file's fresh mtime for
+    /// writes, otherwise when the backend delivered the event. `time` minus this is the
+    /// processing latency.
+    #[serde(default)]
+    pub occurred_at: Option<DateTime<Local>>,
+    /// The watched path the event was reported under.
+    pub watch: PathBuf,
+    /// Name of the watchset `watch` belongs to.
+    #[serde(default)]
+    pub watchset: Option<String>,
+    pub path: PathBuf,
+    pub event: FileEvent,
+    /// Repository state, when git integration is enabled and the path is inside a repo.
+    pub git: Option<GitContext>,
+    /// Container whose filesystem the path belongs to, when container awareness is enabled.
+    pub container: Option<ContainerInfo>,
+    /// Label of the maintenance window the event happened in.
+    pub maintenance: Option<String>,
+    /// Fingerprint of the file after the event, when content hashing is enabled for its
+    /// watch.
+    pub content: Option<ContentHash>,
+    /// Size, mtime, permissions and ownership of the file when the event was recorded;
+    /// for a deleted file, as last seen.
+    #[serde(default)]
+    pub metadata: Option<FileMetadata>,
+    /// For modifications, how the file's length changed since it was last seen, as of
+    /// when the event was processed.
+    #[serde(default)]
+    pub size_change: Option<SizeChange>,
+    /// Lines appended by a modification, for files being tailed.
+    #[serde(default)]
+    pub appended_lines: Option<Vec<String>>,
+    /// Fields added by the monitor's [`Enricher`]s, by enricher name.
+    #[serde(default)]
+    pub enrichment: BTreeMap<String, serde_json::Value>,
+    /// Process that made the change, when its watch uses the fanotify backend.
+    #[serde(default)]
+    pub process: Option<ProcessInfo>,
+    /// The watched symlink the path was reached through, when its target is watched.
+    #[serde(default)]
+    pub via_symlink: Option<PathBuf>,
+}
+
+/// A history record with details kept apart from it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryDetail {
+    pub record: FileEventRecord,
+    /// Unified diff of the change, for modified text files when diffs are enabled.
+    pub diff: Option<String>,
+}
+
+/// How the watched path is monitored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatchMode {
+    /// Only the watched path itself.
+    #[default]
+    NonRecursive,
+    /// Everything below the watched directory, optionally limited to `max_depth` levels
+    /// (1 means direct children only).
+    Recursive { max_depth: Option<usize> },
+}
+
+impl WatchMode {
+    fn recursive_mode(&self) -> RecursiveMode {
+        match self {
+            WatchMode::NonRecursive => RecursiveMode::NonRecursive,
+            WatchMode::Recursive { .. } => RecursiveMode::Recursive,
+        }
+    }
+
+    /// Whether `path` is within the configured depth below `root`.
+    fn within_depth(&self, root: &Path, path: &Path) -> bool {
+        match self {
+            WatchMode::Recursive {
+                max_depth: Some(max_depth),
+            } => path
+                .strip_prefix(root)
+                .map(|relative| relative.components().count() <= *max_depth)
+                .unwrap_or(true),
+            _ => true,
+        }
+    }
+}
+
+/// A single hop of a watched file that was chased to a new location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMove {
+    pub time: DateTime<Local>,
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
 pub struct FileMonitor {
     current_path: Arc<Mutex<PathBuf>>,
     substitute_path: Arc<Mutex<Option<PathBuf>>>,
-    watcher: Arc<Mutex<Option<notify::RecommendedWatcher>>>,
-    event_history: Arc<Mutex<Vec<(DateTime<Local>, FileEvent)>>>,
+    watcher: Arc<Mutex<Option<Watchers>>>,
+    /// Watcher backends of the watches that do not use the native one.
+    watch_backends: HashMap<PathBuf, WatchBackend>,
+    default_backend: WatchBackend,
+    /// What is watched for watched paths that are symlinks.
+    symlink_policy: SymlinkPolicy,
+    /// The watches that are symlinks, kept up to date by the watcher.
+    watched_links: Arc<std::sync::Mutex<Vec<PathBuf>>>,
+    /// Whether the watcher callback runs on a dedicated thread that survives its panics.
+    isolate_callback: bool,
+    event_history: Arc<Mutex<EventHistory>>,
+    next_event_id: AtomicU64,
+    /// Diffs of the records in the history, by record id.
+    diffs: Arc<Mutex<BTreeMap<u64, String>>>,
     stats: Arc<Mutex<HashMap<FileEvent, usize>>>,
+    latency: Arc<Mutex<LatencyMetrics>>,
+    watch_stats: Arc<Mutex<HashMap<PathBuf, HashMap<FileEvent, usize>>>>,
+    rates: Arc<Mutex<EventRates>>,
+    rate_alerts: Arc<Mutex<Vec<RateAlertState>>>,
+    /// Never locked while `current_path` is held; copy the primary path out first.
+    extra_watches: Arc<Mutex<Vec<PathBuf>>>,
+    /// Paths whose appended lines are attached to their modifications.
+    tailed: Arc<Mutex<Vec<PathBuf>>>,
+    watchsets: Arc<Mutex<BTreeMap<String, Watchset>>>,
+    filters: Arc<Mutex<PathFilter>>,
     is_paused: Arc<Mutex<bool>>,
+    /// When the current pause started, to record its window once it ends.
+    paused_since: Arc<Mutex<Option<DateTime<Local>>>>,
+    /// When a timed pause ends, see [`FileMonitor::pause_for`].
+    resume_at: tokio::sync::watch::Sender<Option<Instant>>,
+    /// Paths whose events are dropped while the rest are monitored, see
+    /// [`FileMonitor::pause_path`].
+    paused_paths: Arc<Mutex<Vec<PathBuf>>>,
     path_substitutions: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    follow_moves: Arc<Mutex<bool>>,
+    /// Whether watches whose path was deleted or renamed away are re-established once
+    /// the path exists again, like `tail -F`.
+    follow_names: Arc<Mutex<bool>>,
+    /// Whether a missing watched path is waited for rather than an error.
+    allow_missing: bool,
+    /// Whether [`FileMonitor::update_path`] resolves symlinks.
+    canonicalize_paths: bool,
+    /// Watched paths that do not exist yet; their parent directory is watched until
+    /// they are created.
+    pending_creations: Arc<Mutex<Vec<PathBuf>>>,
+    /// Watches waiting for their path to be recreated.
+    lost_watches: Arc<Mutex<Vec<PathBuf>>>,
+    /// Whether files moved to a trash are told apart from deleted ones.
+    trash_detection: bool,
+    /// Trashes checked besides the platform's, see [`Trash::defaults_for`].
+    trashes: Vec<Trash>,
+    move_anchor: Arc<Mutex<Option<File>>>,
+    path_lineage: Arc<Mutex<Vec<PathMove>>>,
+    channel_capacity: usize,
+    /// Whether backends drop events when the regular lane is full instead of waiting.
+    drop_when_full: bool,
+    /// Events dropped because their lane was full, in total and not yet recorded as
+    /// [`FileEvent::EventsDropped`].
+    dropped_events: Arc<AtomicU64>,
+    unreported_drops: Arc<AtomicU64>,
+    /// Where events beyond the regular lane go in bounded memory mode; `None` otherwise.
+    spill: Option<Arc<std::sync::Mutex<SpillQueue>>>,
+    /// Events spilled to disk so far.
+    spilled_events: Arc<AtomicU64>,
+    priority_channel_capacity: usize,
+    priority_paths: Vec<PathBuf>,
+    rules: Vec<Box<dyn EventRule>>,
+    enrichers: Vec<Enricher>,
+    /// Re-sends unacknowledged alerts as they age; `None` when not configured.
+    escalator: Option<Arc<AlertEscalator>>,
+    watch_mode: WatchMode,
+    git_integration: bool,
+    container_resolver: Option<Arc<Mutex<ContainerResolver>>>,
+    event_tx: tokio::sync::broadcast::Sender<MonitorEvent>,
+    coverage: Arc<Mutex<CoverageTracker>>,
+    heartbeat_interval: std::time::Duration,
+    event_hooks: Arc<Mutex<Vec<EventHook>>>,
+    maintenance_windows: Arc<Mutex<Vec<MaintenanceWindow>>>,
+    config_guard: Option<Arc<Mutex<ConfigGuard>>>,
+    /// Config file to hot-reload and the settings last applied from it.
+    config_file: Option<(PathBuf, Arc<Mutex<MonitorConfig>>)>,
+    shutdown: ShutdownToken,
+    /// Whether [`FileMonitor::monitor`] has its watches in place.
+    watching: tokio::sync::watch::Sender<bool>,
+    /// Whether [`FileMonitor::monitor`] is running, until it has returned.
+    running: tokio::sync::watch::Sender<bool>,
+    webhooks: Arc<Mutex<Vec<Webhook>>>,
+    webhook_retry: RetryPolicy,
+    webhook_limits: (RateLimit, BreakerConfig),
+    history_size: usize,
+    /// Records older than this are dropped from the history.
+    history_max_age: Option<Duration>,
+    debounce: Duration,
+    /// Content hashers of the watches that have content hashing enabled.
+    content_hashers: HashMap<PathBuf, Arc<std::sync::Mutex<ContentHasher>>>,
+    /// Content of watched text files to diff changes against; `None` when disabled.
+    text_snapshots: Option<Arc<std::sync::Mutex<TextSnapshots>>>,
+    /// Versioned copies of changed files; `None` when disabled.
+    backups: Option<Arc<BackupStore>>,
+    /// Commands run per event kind; `None` when none are configured.
+    shell_hooks: Option<Arc<ShellHooks>>,
+    /// Holds back events on temp files to collapse atomic saves; `None` when disabled.
+    atomic_saves: Option<Arc<Mutex<AtomicSaveCoalescer>>>,
+    /// Holds the first half of a rename until the second one arrives.
+    renames: Arc<Mutex<RenamePairer>>,
+    /// Attributes of watched files, to tell apart what a metadata event changed.
+    attributes: Arc<std::sync::Mutex<AttributeTracker>>,
+    /// Initial scans of the watches, running or finished.
+    scans: Arc<std::sync::Mutex<Vec<Arc<Scan>>>>,
+    scans_running: tokio::sync::watch::Sender<usize>,
+    /// When each path last recorded each kind of event, while debouncing.
+    last_recorded: Arc<Mutex<HashMap<(PathBuf, FileEvent), Instant>>>,
+    /// Entries `last_recorded` may hold; the oldest is evicted beyond it.
+    dedup_capacity: usize,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FileEvent {
     Opened,
     Modified,
     Deleted,
-    Renamed(PathBuf),
+    /// The file at `from` now is at `to`; recorded for `from`.
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
     Created,
     Closed,
+    /// The file's mode changed (chmod).
+    PermissionsChanged,
+    /// The file's owner or group changed (chown).
+    OwnershipChanged,
+    /// Only the file's timestamps changed, e.g. by `touch`.
+    TimestampChanged,
+    /// The monitor's config file changed and was applied.
+    ConfigReloaded,
+    /// The watched path was recreated after a delete or rename, e.g. by log rotation, and
+    /// is watched again.
+    Recreated,
+    /// The file was replaced in one atomic save: the temporary file at this path was
+    /// written and renamed over it.
+    Replaced(PathBuf),
+    /// The file differs from a [`Baseline`] it was verified against.
+    BaselineDrift(Drift),
+    /// More events of one kind than a [`RateAlertRule`] allows were recorded within its
+    /// window.
+    RateAlert {
+        rule: String,
+        count: usize,
+        window_secs: u64,
+    },
+    /// This many events were dropped because the event queue was full; recorded for the
+    /// primary watch.
+    EventsDropped(u64),
+    /// Monitoring was paused between these times; recorded for the primary watch once
+    /// it resumes.
+    Paused {
+        since: DateTime<Local>,
+        until: DateTime<Local>,
+    },
+    /// The file was moved to a trash rather than deleted, and is at this path in it;
+    /// see [`FileMonitor::restore_from_trash`].
+    Trashed(PathBuf),
+}
+
+impl FileEvent {
+    /// Every value of [`FileEvent::kind`].
+    pub const KINDS: [&'static str; 17] = [
+        "opened",
+        "modified",
+        "deleted",
+        "renamed",
+        "created",
+        "closed",
+        "permissions_changed",
+        "ownership_changed",
+        "timestamp_changed",
+        "config_reloaded",
+        "recreated",
+        "replaced",
+        "baseline_drift",
+        "rate_alert",
+        "events_dropped",
+        "trashed",
+        "paused",
+    ];
+
+    /// Lower-case name of the event kind, without any payload.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileEvent::Opened => "opened",
+            FileEvent::Modified => "modified",
+            FileEvent::Deleted => "deleted",
+            FileEvent::Renamed { .. } => "renamed",
+            FileEvent::Created => "created",
+            FileEvent::Closed => "closed",
+            FileEvent::PermissionsChanged => "permissions_changed",
+            FileEvent::OwnershipChanged => "ownership_changed",
+            FileEvent::TimestampChanged => "timestamp_changed",
+            FileEvent::ConfigReloaded => "config_reloaded",
+            FileEvent::Recreated => "recreated",
+            FileEvent::Replaced(_) => "replaced",
+            FileEvent::BaselineDrift(_) => "baseline_drift",
+            FileEvent::RateAlert { .. } => "rate_alert",
+            FileEvent::EventsDropped(_) => "events_dropped",
+            FileEvent::Trashed(_) => "trashed",
+            FileEvent::Paused { .. } => "paused",
+        }
+    }
 }
 
 impl FileMonitor {
     pub fn new<P: AsRef<Path>>(initial_path: P) -> Self {
+        FileMonitorBuilder::new(initial_path).build()
+    }
+
+    pub fn builder<P: AsRef<Path>>(initial_path: P) -> FileMonitorBuilder {
+        FileMonitorBuilder::new(initial_path)
+    }
+
+    fn with_path(initial_path: PathBuf) -> Self {
         FileMonitor {
-            current_path: Arc::new(Mutex::new(initial_path.as_ref().to_path_buf())),
+            current_path: Arc::new(Mutex::new(initial_path)),
             substitute_path: Arc::new(Mutex::new(None)),
             watcher: Arc::new(Mutex::new(None)),
+            watch_backends: HashMap::new(),
+            default_backend: WatchBackend::default(),
+            symlink_policy: SymlinkPolicy::default(),
+            watched_links: Arc::new(std::sync::Mutex::new(Vec::new())),
+            isolate_callback: false,
             event_history: Arc::new(Mutex::new(Vec::new())),
+            next_event_id: AtomicU64::new(1),
+            diffs: Arc::new(Mutex::new(BTreeMap::new())),
             stats: Arc::new(Mutex::new(HashMap::new())),
+            latency: Arc::new(Mutex::new(LatencyMetrics::default())),
+            watch_stats: Arc::new(Mutex::new(HashMap::new())),
+            rates: Arc::new(Mutex::new(EventRates::new())),
+            rate_alerts: Arc::new(Mutex::new(Vec::new())),
+            extra_watches: Arc::new(Mutex::new(Vec::new())),
+            tailed: Arc::new(Mutex::new(Vec::new())),
+            watchsets: Arc::new(Mutex::new(BTreeMap::new())),
+            filters: Arc::new(Mutex::new(PathFilter::default())),
             is_paused: Arc::new(Mutex::new(false)),
+            paused_since: Arc::new(Mutex::new(None)),
+            resume_at: tokio::sync::watch::Sender::new(None),
+            paused_paths: Arc::new(Mutex::new(Vec::new())),
             path_substitutions: Arc::new(Mutex::new(HashMap::new())),
+            follow_moves: Arc::new(Mutex::new(false)),
+            follow_names: Arc::new(Mutex::new(false)),
+            allow_missing: false,
+            canonicalize_paths: false,
+            pending_creations: Arc::new(Mutex::new(Vec::new())),
+            lost_watches: Arc::new(Mutex::new(Vec::new())),
+            trash_detection: true,
+            trashes: Vec::new(),
+            move_anchor: Arc::new(Mutex::new(None)),
+            path_lineage: Arc::new(Mutex::new(Vec::new())),
+            channel_capacity: builder::DEFAULT_CHANNEL_CAPACITY,
+            drop_when_full: false,
+            dropped_events: Arc::new(AtomicU64::new(0)),
+            unreported_drops: Arc::new(AtomicU64::new(0)),
+            spill: None,
+            spilled_events: Arc::new(AtomicU64::new(0)),
+            priority_channel_capacity: builder::DEFAULT_PRIORITY_CHANNEL_CAPACITY,
+            priority_paths: Vec::new(),
+            rules: Vec::new(),
+            enrichers: Vec::new(),
+            escalator: None,
+            watch_mode: WatchMode::default(),
+            git_integration: false,
+            container_resolver: None,
+            event_tx: tokio::sync::broadcast::channel(builder::DEFAULT_SUBSCRIBER_CAPACITY).0,
+            coverage: Arc::new(Mutex::new(CoverageTracker::new(
+                builder::DEFAULT_HEARTBEAT_INTERVAL,
+            ))),
+            heartbeat_interval: builder::DEFAULT_HEARTBEAT_INTERVAL,
+            event_hooks: Arc::new(Mutex::new(Vec::new())),
+            maintenance_windows: Arc::new(Mutex::new(Vec::new())),
+            config_guard: None,
+            config_file: None,
+            shutdown: ShutdownToken::new(),
+            watching: tokio::sync::watch::Sender::new(false),
+            running: tokio::sync::watch::Sender::new(false),
+            webhooks: Arc::new(Mutex::new(Vec::new())),
+            webhook_retry: RetryPolicy::default(),
+            webhook_limits: (
+                webhook::DEFAULT_WEBHOOK_RATE_LIMIT,
+                webhook::DEFAULT_WEBHOOK_BREAKER,
+            ),
+            history_size: builder::DEFAULT_HISTORY_SIZE,
+            history_max_age: None,
+            debounce: Duration::ZERO,
+            atomic_saves: None,
+            renames: Arc::new(Mutex::new(RenamePairer::default())),
+            attributes: Arc::new(std::sync::Mutex::new(AttributeTracker::default())),
+            scans: Arc::new(std::sync::Mutex::new(Vec::new())),
+            scans_running: tokio::sync::watch::Sender::new(0),
+            content_hashers: HashMap::new(),
+            text_snapshots: None,
+            backups: None,
+            shell_hooks: None,
+            last_recorded: Arc::new(Mutex::new(HashMap::new())),
+            dedup_capacity: usize::MAX,
         }
     }
 
+    /// Watches the paths and records their events until the monitor is shut down, see
+    /// [`FileMonitor::shutdown`].
     pub async fn monitor(&self) -> Result<()> {
-        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        self.running.send_replace(true);
+        let _running = RunningGuard(&self.running);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(self.channel_capacity);
+        let (priority_tx, mut priority_rx) =
+            tokio::sync::mpsc::channel(self.priority_channel_capacity);
+
+        let (error_tx, mut error_rx) = tokio::sync::mpsc::channel(1);
+        let mut heartbeat = tokio::time::interval(self.heartbeat_interval);
+        let mut lost_watch_tick = tokio::time::interval(LOST_WATCH_CHECK_INTERVAL);
+        let mut escalation_tick = tokio::time::interval(match &self.escalator {
+            Some(escalator) => escalator.check_interval(),
+            None => self.heartbeat_interval,
+        });
+        let mut atomic_save_tick = tokio::time::interval(match &self.atomic_saves {
+            Some(coalescer) => coalescer.lock().await.window(),
+            None => self.heartbeat_interval,
+        });
 
         let path = self.current_path.lock().await.clone();
-        let watcher = self.create_watcher(tx.clone())?;
+        let (rebuild_tx, mut rebuild_rx) = tokio::sync::mpsc::channel(1);
+        let (overflow_tx, mut overflow_rx) = tokio::sync::mpsc::channel(1);
+        let (spilled_tx, mut spilled_rx) = tokio::sync::mpsc::channel(1);
+        let senders = WatcherSenders {
+            events: tx,
+            priority_events: priority_tx,
+            errors: error_tx,
+            rebuild: rebuild_tx,
+            overflow: overflow_tx,
+            spilled: spilled_tx,
+        };
+        let watcher = self.create_watcher(senders.clone())?;
 
         {
             let mut watcher_lock = self.watcher.lock().await;
             *watcher_lock = Some(watcher);
         }
 
-        self.watch_path(&path).await?;
-
-        while let Some(event) = rx.recv().await {
-            if !*self.is_paused.lock().await {
-                if let Some(file_event) = self.map_event(event) {
-                    self.handle_event(file_event).await?;
-                }
+        match self.validate_watch_path(&path) {
+            Err(MonitorError::PathNotFound(_)) if self.allow_missing => {
+                self.await_creation(&path).await?;
             }
+            result => {
+                result?;
+                self.watch_path(&path).await?;
+            }
+        }
+        for extra in self.extra_watches.lock().await.clone() {
+            self.watch_path(&extra).await?;
         }
+        self.refresh_move_anchor().await;
 
-        Ok(())
-    }
+        let (config_tx, mut config_rx) = tokio::sync::mpsc::channel(16);
+        let _config_watcher = self.watch_config(config_tx).await?;
+        let (reload_tx, mut reload_rx) = tokio::sync::mpsc::channel(16);
+        let _config_file_watcher = match &self.config_file {
+            Some((path, _)) => Some(watch_files(vec![path.clone()], reload_tx)?),
+            None => None,
+        };
+        self.watching.send_replace(true);
+        let mut resume_changes = self.resume_at.subscribe();
 
-    fn create_watcher(
-        &self,
-        tx: tokio::sync::mpsc::Sender<Event>,
-    ) -> Result<notify::RecommendedWatcher> {
-        let watcher =
-            notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
-                Ok(event) => {
-                    let _ = tx.blocking_send(event);
+        loop {
+            let rename_deadline = self.renames.lock().await.deadline();
+            let resume_at = *resume_changes.borrow_and_update();
+            let has_lost_watches = !self.lost_watches.lock().await.is_empty();
+            let event = tokio::select! {
+                biased;
+                Some(event) = priority_rx.recv() => event,
+                Some(()) = overflow_rx.recv() => {
+                    self.report_dropped_events().await?;
+                    continue;
                 }
-                Err(e) => error!("Watch error: {:?}", e),
-            })?;
-        Ok(watcher)
-    }
-
-    async fn watch_path(&self, path: &Path) -> Result<()> {
-        let mut watcher_lock = self.watcher.lock().await;
-        if let Some(watcher) = watcher_lock.as_mut() {
-            watcher.watch(path, RecursiveMode::NonRecursive)?;
-            info!("Now watching path: {}", path.display());
+                Some(event) = rx.recv() => event,
+                // Spilled events are newer than the queued ones, so they wait for the
+                // queue to run dry.
+                Some(()) = spilled_rx.recv() => {
+                    for event in self.take_spilled().await {
+                        self.process_event(event).await?;
+                    }
+                    self.report_dropped_events().await?;
+                    if self.spill.as_ref().is_some_and(|spill| !spill.lock().unwrap().is_empty()) {
+                        let _ = senders.spilled.try_send(());
+                    }
+                    continue;
+                }
+                Some(()) = config_rx.recv() => {
+                    self.check_config().await;
+                    continue;
+                }
+                Some(()) = reload_rx.recv() => {
+                    if let Err(e) = self.reload_config().await {
+                        error!("Failed to reload config, keeping current settings: {}", e);
+                    }
+                    continue;
+                }
+                Some(()) = rebuild_rx.recv() => {
+                    self.rebuild_watcher(senders.clone()).await?;
+                    continue;
+                }
+                Some(()) = error_rx.recv() => {
+                    self.update_coverage(|coverage| coverage.open_gap(GapKind::WatcherError))
+                        .await;
+                    continue;
+                }
+                _ = tokio::time::sleep_until(rename_deadline.unwrap_or_else(Instant::now).into()),
+                    if rename_deadline.is_some() => {
+                    self.release_renames(false).await?;
+                    continue;
+                }
+                Ok(()) = resume_changes.changed() => continue,
+                _ = tokio::time::sleep_until(resume_at.unwrap_or_else(Instant::now).into()),
+                    if resume_at.is_some() => {
+                    self.resume().await?;
+                    continue;
+                }
+                _ = lost_watch_tick.tick(), if has_lost_watches => {
+                    self.rewatch_recreated().await?;
+                    continue;
+                }
+                _ = atomic_save_tick.tick(), if self.atomic_saves.is_some() => {
+                    self.release_held_events(false).await?;
+                    continue;
+                }
+                _ = escalation_tick.tick(), if self.escalator.is_some() => {
+                    self.escalate_alerts().await;
+                    continue;
+                }
+                _ = heartbeat.tick() => {
+                    self.update_coverage(|coverage| coverage.heartbeat()).await;
+                    self.expire_history(&mut *self.event_history.lock().await);
+                    continue;
+                }
+                _ = self.shutdown.cancelled() => break,
+                else => break,
+            };
+            self.process_event(event).await?;
         }
-        Ok(())
-    }
 
-    fn map_event(&self, event: Event) -> Option<FileEvent> {
-        match event.kind {
-            EventKind::Access(notify::event::AccessKind::Close(_)) => Some(FileEvent::Closed),
-            EventKind::Access(_) => Some(FileEvent::Opened),
-            EventKind::Modify(_) => Some(FileEvent::Modified),
-            EventKind::Remove(_) => Some(FileEvent::Deleted),
-            EventKind::Create(_) => Some(FileEvent::Created),
-            EventKind::Any => {
-                if event.paths.len() == 2 {
-                    Some(FileEvent::Renamed(event.paths[1].clone()))
-                } else {
-                    None
+        // Stop new events, then handle the ones already queued.
+        if let Some(mut watcher) = self.watcher.lock().await.take() {
+            for path in self.get_watches().await {
+                if let Err(e) = watcher.unwatch(&path) {
+                    debug!("Failed to unwatch {}: {}", path.display(), e);
                 }
             }
-            _ => None,
         }
-    }
-
-    async fn handle_event(&self, event: FileEvent) -> Result<()> {
-        let path = self.current_path.lock().await;
-        let substitute = self.substitute_path.lock().await;
-        let now = Local::now();
+        for scan in self.scans.lock().unwrap().iter() {
+            scan.cancel();
+        }
+        self.watching.send_replace(false);
+        let mut drained = 0;
+        while let Ok(event) = priority_rx.try_recv().or_else(|_| rx.try_recv()) {
+            self.process_event(event).await?;
+            drained += 1;
+        }
+        loop {
+            let spilled = self.take_spilled().await;
+            if spilled.is_empty() {
+                break;
+            }
+            for event in spilled {
+                self.process_event(event).await?;
+                drained += 1;
+            }
+        }
+        self.release_renames(true).await?;
+        self.release_held_events(true).await?;
+        self.report_dropped_events().await?;
+        self.update_coverage(|coverage| coverage.heartbeat()).await;
+        info!("Monitor stopped, {} queued events handled", drained);
 
-        let display_path = substitute.as_ref().unwrap_or(&path);
-        let substituted_path = self.get_substituted_path(display_path).await;
+        Ok(())
+    }
 
-        let event_message = match &event {
-            FileEvent::Opened => format!(
-                "File opened: {} (actual: {})",
-                display_path.display(),
-                substituted_path.display()
-            ),
-            FileEvent::Modified => format!(
-                "File modified: {} (actual: {})",
-                display_path.display(),
-                substituted_path.display()
-            ),
-            FileEvent::Deleted => format!(
-                "File deleted: {} (actual: {})",
-                display_path.display(),
-                substituted_path.display()
-            ),
-            FileEvent::Created => format!(
-                "File created: {} (actual: {})",
-                display_path.display(),
-                substituted_path.display()
-            ),
-            FileEvent::Renamed(new_path) => {
-                let substituted_new_path = self.get_substituted_path(new_path).await;
-                format!(
-                    "File renamed from {} (actual: {}) to {} (actual: {})",
-                    display_path.display(),
-                    substituted_path.display(),
-                    new_path.display(),
-                    substituted_new_path.display()
-                )
-            }
-            FileEvent::Closed => format!(
-                "File closed: {} (actual: {})",
-                display_path.display(),
-                substituted_path.display()
-            ),
-        };
+    /// Progress of the initial scans of the watches, including finished ones.
+    pub fn get_scans(&self) -> Vec<ScanProgress> {
+        self.scans
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|scan| scan.progress())
+            .collect()
+    }
 
-        info!("{} at {}", event_message, now);
+    /// Resolves once no initial scan is running, e.g. before relying on what the scans
+    /// learned about existing files.
+    pub async fn wait_until_scanned(&self) {
+        let mut running = self.scans_running.subscribe();
+        let _ = running.wait_for(|running| *running == 0).await;
+    }
 
-        self.update_history(now, event.clone()).await;
-        self.update_stats(event).await;
+    /// Resolves once [`FileMonitor::monitor`] has set up its watches and is handling events.
+    pub async fn wait_until_watching(&self) {
+        let mut watching = self.watching.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = watching.wait_for(|watching| *watching).await;
+    }
 
+    async fn process_event(&self, event: ReceivedEvent) -> Result<()> {
+        if !self.pending_creations.lock().await.is_empty() && self.check_creation(&event).await? {
+            return Ok(());
+        }
+        if self.beside_watched_link(&event).await {
+            return Ok(());
+        }
+        for scan in self.scans.lock().unwrap().iter() {
+            for path in &event.paths {
+                scan.touch(path);
+            }
+        }
+        self.update_coverage(|coverage| coverage.close_gap(GapKind::WatcherError))
+            .await;
+        if *self.is_paused.lock().await {
+            return Ok(());
+        }
+        let paired = self.renames.lock().await.push(event, Instant::now());
+        for event in paired {
+            self.coalesce_event(event).await?;
+        }
         Ok(())
     }
 
-    async fn update_history(&self, time: DateTime<Local>, event: FileEvent) {
-        let mut history = self.event_history.lock().await;
-        history.push((time, event));
-        if history.len() > 100 {
-            history.remove(0);
+    /// Releases rename halves whose partner did not arrive in time, or all of them with
+    /// `all`.
+    async fn release_renames(&self, all: bool) -> Result<()> {
+        let released = if all {
+            self.renames.lock().await.drain()
+        } else {
+            self.renames.lock().await.expire(Instant::now())
+        };
+        for event in released {
+            self.coalesce_event(event).await?;
         }
+        Ok(())
     }
 
-    async fn update_stats(&self, event: FileEvent) {
-        let mut stats = self.stats.lock().await;
-        *stats.entry(event).or_insert(0) += 1;
+    async fn coalesce_event(&self, event: ReceivedEvent) -> Result<()> {
+        let Some(coalescer) = &self.atomic_saves else {
+            return self.record_event(event).await;
+        };
+        // A watched path itself is never treated as a temp file.
+        let watch_root = {
+            let current_path = self.current_path.lock().await.clone();
+            let extra_watches = self.extra_watches.lock().await;
+            event
+                .paths
+                .iter()
+                .any(|path| *path == current_path || extra_watches.contains(path))
+        };
+        if watch_root {
+            return self.record_event(event).await;
+        }
+        let coalesced = coalescer.lock().await.push(event, Instant::now());
+        self.record_coalesced(coalesced).await
     }
 
-    pub async fn update_path<P: AsRef<Path>>(&self, new_path: P) -> Result<()> {
-        let new_path = new_path.as_ref();
-        let absolute_path = if new_path.is_relative() {
-            std::env::current_dir()?.join(new_path)
+    /// Records events held back on temp files once their window has passed, or all of
+    /// them with `all`.
+    async fn release_held_events(&self, all: bool) -> Result<()> {
+        let Some(coalescer) = &self.atomic_saves else {
+            return Ok(());
+        };
+        let released = if all {
+            coalescer.lock().await.drain()
         } else {
-            new_path.to_path_buf()
+            coalescer.lock().await.expire(Instant::now())
         };
+        self.record_coalesced(released).await
+    }
 
-        let mut current_path = self.current_path.lock().await;
-        debug!(
-            "Updating path from {} to {}",
-            current_path.display(),
-            absolute_path.display()
-        );
-
-        if let Some(watcher) = self.watcher.lock().await.as_mut() {
-            watcher.unwatch(&*current_path)?;
-            watcher.watch(&absolute_path, RecursiveMode::NonRecursive)?;
+    async fn record_coalesced(&self, coalesced: Vec<Coalesced>) -> Result<()> {
+        for item in coalesced {
+            match item {
+                Coalesced::Event(event) => self.record_event(event).await?,
+                Coalesced::Replaced {
+                    temp,
+                    target,
+                    received,
+                } => {
+                    let current_path = self.current_path.lock().await.clone();
+                    let root = watch_root(&current_path, &self.extra_watches.lock().await, &target);
+                    if self.watch_mode.within_depth(&root, &target) {
+                        debug!("Atomic save of {} via {}", target.display(), temp.display());
+                        self.handle_event_at(
+                            target,
+                            FileEvent::Replaced(temp),
+                            Some(received),
+                            None,
+                        )
+                        .await?;
+                    }
+                }
+            }
         }
-
-        *current_path = absolute_path.clone();
-        info!("Path updated to: {}", absolute_path.display());
         Ok(())
     }
 
-    pub async fn substitute_path<P: AsRef<Path>>(&self, old_path: P, new_path: P) -> Result<()> {
-        let current_path = self.current_path.lock().await;
-        let mut substitute = self.substitute_path.lock().await;
-
-        if current_path.as_path() == old_path.as_ref() {
-            *substitute = Some(new_path.as_ref().to_path_buf());
-            info!(
-                "Path substituted: {} -> {}",
-                old_path.as_ref().display(),
-                new_path.as_ref().display()
-            );
-        } else {
-            error!("Cannot substitute path: current path does not match the old path");
+    async fn record_event(&self, event: ReceivedEvent) -> Result<()> {
+        let current_path = self.current_path.lock().await.clone();
+        let event_path = event
+            .paths
+            .first()
+            .cloned()
+            .unwrap_or_else(|| current_path.clone());
+        let root = watch_root(&current_path, &self.extra_watches.lock().await, &event_path);
+        if !self.watch_mode.within_depth(&root, &event_path) {
+            return Ok(());
         }
 
+        let moved_out = Self::is_move_out(&event) && event_path == current_path;
+        let process = ProcessInfo::from_event(&event);
+        if let Some(mut file_event) = self.map_event(event.event) {
+            if moved_out {
+                if let Some(destination) = self.chase_move().await? {
+                    file_event = FileEvent::Renamed {
+                        from: event_path.clone(),
+                        to: destination,
+                    };
+                }
+            }
+            if self.trash_detection {
+                if let Some(location) = self.trashed_to(&event_path, &file_event).await {
+                    file_event = FileEvent::Trashed(location);
+                }
+            }
+            self.note_lost_watch(&event_path, &file_event).await;
+            self.handle_event_at(event_path, file_event, Some(event.received), process)
+                .await?;
+        }
         Ok(())
     }
 
-    pub async fn pause(&self) -> Result<()> {
-        let mut is_paused = self.is_paused.lock().await;
-        *is_paused = true;
-        info!("Monitoring paused");
-        Ok(())
+    /// Where a file that vanished from `path` went, if it was moved to a trash: renamed
+    /// into one that is watched, or found in one with a matching record.
+    async fn trashed_to(&self, path: &Path, event: &FileEvent) -> Option<PathBuf> {
+        let trashes = self.trashes_for(path);
+        match event {
+            FileEvent::Renamed { to, .. } => trashes
+                .iter()
+                .any(|trash| trash.holds(to))
+                .then(|| to.clone()),
+            FileEvent::Deleted => {
+                let last_known = self.attributes.lock().unwrap().last_known(path);
+                let path = path.to_path_buf();
+                tokio::task::spawn_blocking(move || {
+                    trash::locate(&trashes, &path, last_known.as_ref())
+                })
+                .await
+                .ok()?
+                .map(|entry| entry.location)
+            }
+            _ => None,
+        }
     }
 
-    pub async fn resume(&self) -> Result<()> {
-        let mut is_paused = self.is_paused.lock().await;
-        *is_paused = false;
-        info!("Monitoring resumed");
-        Ok(())
+    fn trashes_for(&self, path: &Path) -> Vec<Trash> {
+        let mut trashes = self.trashes.clone();
+        trashes.extend(Trash::defaults_for(path));
+        trashes
     }
 
-    pub async fn get_stats(&self) -> HashMap<FileEvent, usize> {
-        self.stats.lock().await.clone()
+    /// Moves the file last trashed from `path` back, if it is still in a trash and
+    /// nothing was created at `path` since. The move is recorded as a creation.
+    pub async fn restore_from_trash<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = absolute_path(path.as_ref())?;
+        let trashes = self.trashes_for(&path);
+        let restored = tokio::task::spawn_blocking(move || {
+            trashes
+                .iter()
+                .flat_map(|trash| trash.entries_for(&path))
+                .max_by_key(|entry| entry.trashed_at)
+                .ok_or_else(|| monitor_error!("{} is not in a trash", path.display()))?
+                .restore()
+        })
+        .await??;
+        info!("Restored {} from trash", restored.display());
+        Ok(restored)
     }
 
-    pub async fn get_history(&self) -> Vec<(DateTime<Local>, FileEvent)> {
-        self.event_history.lock().await.clone()
+    /// Takes the oldest spilled events, as many as fit in the regular lane. If the spill
+    /// file cannot be read, the events in it are counted as dropped and the monitor
+    /// carries on.
+    async fn take_spilled(&self) -> Vec<ReceivedEvent> {
+        let Some(spill) = &self.spill else {
+            return Vec::new();
+        };
+        let max = self.channel_capacity;
+        let popped = {
+            let spill = Arc::clone(spill);
+            tokio::task::spawn_blocking(move || spill.lock().unwrap().pop(max)).await
+        };
+        let error = match popped {
+            Ok(Ok(events)) => return events,
+            Ok(Err(e)) => e,
+            Err(e) => e.into(),
+        };
+        let mut spill = spill.lock().unwrap();
+        let lost = spill.clear() as u64;
+        error!(
+            "Failed to read spilled events from {}, {} dropped: {}",
+            spill.path().display(),
+            lost,
+            error
+        );
+        self.dropped_events.fetch_add(lost, Ordering::Relaxed);
+        self.unreported_drops.fetch_add(lost, Ordering::Relaxed);
+        Vec::new()
     }
 
-    pub async fn add_path_substitution<P: AsRef<Path>>(
-        &self,
-        original_path: P,
-        substitute_path: P,
-    ) -> Result<()> {
-        let mut substitutions = self.path_substitutions.lock().await;
-        substitutions.insert(
-            original_path.as_ref().to_path_buf(),
-            substitute_path.as_ref().to_path_buf(),
-        );
-        info!(
-            "Path substitution added: {} -> {}",
-            original_path.as_ref().display(),
-            substitute_path.as_ref().display()
+    /// Records the events dropped since the last report as one
+    /// [`FileEvent::EventsDropped`], so the history shows where it has a gap.
+    async fn report_dropped_events(&self) -> Result<()> {
+        let dropped = self.unreported_drops.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return Ok(());
+        }
+        warn!(
+            "Event queue full (capacity {}), {} events dropped",
+            self.channel_capacity, dropped
         );
-        Ok(())
+        let path = self.current_path.lock().await.clone();
+        self.handle_event(path, FileEvent::EventsDropped(dropped))
+            .await
     }
 
-    pub async fn remove_path_substitution<P: AsRef<Path>>(&self, original_path: P) -> Result<()> {
-        let mut substitutions = self.path_substitutions.lock().await;
-        if substitutions.remove(original_path.as_ref()).is_some() {
-            info!(
-                "Path substitution removed for: {}",
-                original_path.as_ref().display()
-            );
-        } else {
-            info!(
-                "No path substitution found for: {}",
-                original_path.as_ref().display()
-            );
+    /// Watches the parent directory of `path`, which does not exist yet, until `path` is
+    /// created, see [`FileMonitorBuilder::allow_missing`]. Fails with
+    /// [`MonitorError::PathNotFound`] if the parent does not exist either.
+    async fn await_creation(&self, path: &Path) -> Result<()> {
+        let Some(parent) = path.parent().filter(|parent| parent.is_dir()) else {
+            let parent = path.parent().unwrap_or(path);
+            return Err(MonitorError::PathNotFound(parent.to_path_buf()));
+        };
+        let parent_watched = self.parent_watched(parent).await;
+        {
+            let mut pending = self.pending_creations.lock().await;
+            if !pending.iter().any(|pending| pending == path) {
+                pending.push(path.to_path_buf());
+            }
+        }
+        if !parent_watched {
+            if let Some(watcher) = self.watcher.lock().await.as_mut() {
+                watcher.watch(parent, RecursiveMode::NonRecursive)?;
+            }
         }
+        info!("Waiting for {} to be created", path.display());
+        // It may have been created before its parent was watched.
+        self.complete_creation(path).await?;
         Ok(())
     }
 
-    pub async fn get_substituted_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
-        let substitutions = self.path_substitutions.lock().await;
-        substitutions
-            .get(path.as_ref())
-            .cloned()
-            .unwrap_or_else(|| path.as_ref().to_path_buf())
+    /// Whether `parent` is already watched, by a watch or for another path waiting to be
+    /// created.
+    async fn parent_watched(&self, parent: &Path) -> bool {
+        self.pending_creations
+            .lock()
+            .await
+            .iter()
+            .any(|pending| pending.parent() == Some(parent))
+            || self.get_watches().await.iter().any(|watch| watch == parent)
     }
 
-    pub async fn open_file<P: AsRef<Path>>(&self, path: P) -> IoResult<File> {
-        let substituted_path = self.get_substituted_path(path).await;
-
-        #[cfg(target_os = "windows")]
+    /// Switches from watching the parent of a path waiting to be created to watching the
+    /// path itself, and records it as [`FileEvent::Created`]. Returns `false` if it does
+    /// not exist yet, or is not waited for.
+    async fn complete_creation(&self, path: &Path) -> Result<bool> {
         {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .attributes(WindowsOpenOptionsExt::FILE_ATTRIBUTE_HIDDEN)
-                .open(&substituted_path)
+            let mut pending = self.pending_creations.lock().await;
+            match pending.iter().position(|pending| pending == path) {
+                Some(index) if path.exists() => pending.remove(index),
+                _ => return Ok(false),
+            };
         }
-
-        #[cfg(any(target_os = "linux", target_os = "macos"))]
-        {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .mode(0o600)
-                .open(&substituted_path)
+        if let Some(parent) = path.parent() {
+            if !self.parent_watched(parent).await {
+                if let Some(watcher) = self.watcher.lock().await.as_mut() {
+                    if let Err(e) = watcher.unwatch(parent) {
+                        debug!("Failed to unwatch {}: {}", parent.display(), e);
+                    }
+                }
+            }
         }
-
-        #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
-        {
-            File::open(&substituted_path)
+        self.watch_path(path).await?;
+        if *self.current_path.lock().await == path {
+            self.refresh_move_anchor().await;
         }
+        info!("{} was created", path.display());
+        if !*self.is_paused.lock().await {
+            self.handle_event(path.to_path_buf(), FileEvent::Created)
+                .await?;
+        }
+        Ok(true)
     }
-}
+
+    /// Stops waiting for `path` to be created, unwatching its parent unless still needed.
+    async fn cancel_creation(&self, path: &Path) {
+        {
+            let mut pending = self.pending_creations.lock().await;
+            let before = pending.len();
+            pending.retain(|pending| pending != path);
+            if pending.len() == before {
+                return;
+            }
+        }
+        let Some(parent) = path.parent() else {
+            return;
+        };
+        if !self.parent_watched(parent).await {
+            if let Some(watcher) = self.watcher.lock().await.as_mut() {
+                if let Err(e) = watcher.unwatch(parent) {
+                    debug!("Failed to unwatch {}: {}", parent.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Handles an event while paths wait to be created: completes the wait for those
+    /// that now exist, and drops events on the other entries of their parent directories
+    /// that no watch covers. Returns whether the event was consumed.
+    async fn check_creation(&self, event: &ReceivedEvent) -> Result<bool> {
+        let pending = self.pending_creations.lock().await.clone();
+        let mut created = false;
+        for path in event.paths.iter().filter(|path| pending.contains(path)) {
+            created |= self.complete_creation(path).await?;
+        }
+        if created {
+            return Ok(true);
+        }
+        let mut watches = self.get_watches().await;
+        watches.retain(|watch| !pending.contains(watch));
+        Ok(!event.paths.is_empty()
+            && event.paths.iter().all(|path| {
+                pending.iter().any(|pending| {
+                    pending.parent() == path.parent() || pending.parent() == Some(path.as_path())
+                }) && !watches.iter().any(|watch| path.starts_with(watch))
+            }))
+    }
+
+    /// Whether all paths of `event` are other entries of the directory of a symlink
+    /// watched as a link, or that directory itself, and no watch covers them.
+    async fn beside_watched_link(&self, event: &ReceivedEvent) -> bool {
+        if self.symlink_policy == SymlinkPolicy::Follow {
+            return false;
+        }
+        let links = self.watched_links.lock().unwrap().clone();
+        if links.is_empty() || event.paths.is_empty() {
+            return false;
+        }
+        let watches = self.get_watches().await;
+        event.paths.iter().all(|path| {
+            links
+                .iter()
+                .any(|link| link.parent() == path.parent() || link.parent() == Some(path.as_path()))
+                && !watches.iter().any(|watch| path.starts_with(watch))
+        })
+    }
+
+    /// The watched symlink `path` was reached through, unless links are watched as
+    /// links only.
+    fn via_symlink(&self, path: &Path) -> Option<PathBuf> {
+        if self.symlink_policy == SymlinkPolicy::Link {
+            return None;
+        }
+        self.watched_links
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|link| path.starts_with(link))
+            .cloned()
+    }
+
+    /// Remembers a watch whose path was deleted or renamed away, when following names,
+    /// so it is re-established once the path is recreated.
+    async fn note_lost_watch(&self, event_path: &Path, event: &FileEvent) {
+        let lost = match event {
+            FileEvent::Deleted | FileEvent::Trashed(_) => true,
+            FileEvent::Renamed { from, .. } => from == event_path,
+            _ => false,
+        };
+        if !lost || !*self.follow_names.lock().await {
+            return;
+        }
+        let current_path = self.current_path.lock().await.clone();
+        let is_watch = current_path == event_path
+            || self
+                .extra_watches
+                .lock()
+                .await
+                .iter()
+                .any(|watch| watch == event_path);
+        let mut lost_watches = self.lost_watches.lock().await;
+        if is_watch && !lost_watches.iter().any(|lost| lost == event_path) {
+            debug!("Waiting for {} to be recreated", event_path.display());
+            lost_watches.push(event_path.to_path_buf());
+        }
+    }
+
+    /// Watches the lost paths that exist again, the new file rather than the one the old
+    /// watch was left on.
+    async fn rewatch_recreated(&self) -> Result<()> {
+        let lost_watches = self.lost_watches.lock().await.clone();
+        for path in lost_watches.into_iter().filter(|path| path.exists()) {
+            if let Some(watcher) = self.watcher.lock().await.as_mut() {
+                if let Err(e) = watcher.unwatch(&path) {
+                    debug!("Failed to unwatch {}: {}", path.display(), e);
+                }
+            }
+            if let Err(e) = self.watch_path(&path).await {
+                // Gone again before it could be watched; keep waiting.
+                debug!("Failed to watch recreated {}: {}", path.display(), e);
+                continue;
+            }
+            self.lost_watches.lock().await.retain(|lost| *lost != path);
+            if *self.current_path.lock().await == path {
+                self.refresh_move_anchor().await;
+            }
+            info!("Watching recreated {}", path.display());
+            self.handle_event(path, FileEvent::Recreated).await?;
+        }
+        Ok(())
+    }
+
+    /// Stops the monitor: [`FileMonitor::monitor`] unwatches every path, handles the
+    /// events already queued, persists coverage and returns. Resolves once it has
+    /// returned, right away if it is not running. Sinks and servers built on the
+    /// monitor's [`FileMonitor::shutdown_token`] are stopped too.
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        let mut running = self.running.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = running.wait_for(|running| !*running).await;
+    }
+
+    /// Cancelled when the monitor is shut down; sinks and servers built on the monitor
+    /// stop with it.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.child()
+    }
+
+    fn create_watcher(&self, senders: WatcherSenders) -> Result<Watchers> {
+        let WatcherSenders {
+            events: tx,
+            priority_events: priority_tx,
+            errors: error_tx,
+            rebuild: rebuild_tx,
+            overflow: overflow_tx,
+            spilled: spilled_tx,
+        } = senders;
+        let priority_paths = self.priority_paths.clone();
+        let drop_when_full = self.drop_when_full;
+        let dropped_events = Arc::clone(&self.dropped_events);
+        let unreported_drops = Arc::clone(&self.unreported_drops);
+        let count_drop = move || {
+            dropped_events.fetch_add(1, Ordering::Relaxed);
+            unreported_drops.fetch_add(1, Ordering::Relaxed);
+            let _ = overflow_tx.try_send(());
+        };
+        let drop_event = {
+            let count_drop = count_drop.clone();
+            move |event: &ReceivedEvent| {
+                debug!("Event queue full, dropping event for {:?}", event.paths);
+                count_drop();
+            }
+        };
+        let spill = self.spill.clone();
+        let spilled_events = Arc::clone(&self.spilled_events);
+        let callback = move |res: Result<Event, notify::Error>| match res {
+            Ok(event) => {
+                let event = ReceivedEvent::now(event);
+                if is_priority_event(&priority_paths, &event) {
+                    let _ = priority_tx.blocking_send(event);
+                } else if let Some(spill) = &spill {
+                    let mut spill = spill.lock().unwrap();
+                    // Once events are spilled, later ones queue up behind them to keep
+                    // the order.
+                    let event = match spill.is_empty() {
+                        true => match tx.try_send(event) {
+                            Err(TrySendError::Full(event)) => event,
+                            _ => return,
+                        },
+                        false => event,
+                    };
+                    match spill.push(&event) {
+                        Ok(true) => {
+                            spilled_events.fetch_add(1, Ordering::Relaxed);
+                            let _ = spilled_tx.try_send(());
+                        }
+                        Ok(false) => drop_event(&event),
+                        Err(e) => {
+                            error!("Failed to spill event to {}: {}", spill.path().display(), e);
+                            drop_event(&event);
+                        }
+                    }
+                } else if priority_paths.is_empty() && !drop_when_full {
+                    let _ = tx.blocking_send(event);
+                } else if let Err(TrySendError::Full(event)) = tx.try_send(event) {
+                    // Blocking here would hold up the priority lane behind bulk events.
+                    drop_event(&event);
+                }
+            }
+            Err(e) => {
+                error!("Watch error: {:?}", e);
+                let _ = error_tx.try_send(());
+            }
+        };
+        let watcher = if self.isolate_callback {
+            let thread = CallbackThread::spawn(
+                self.channel_capacity,
+                callback,
+                move || {
+                    let _ = rebuild_tx.try_send(());
+                },
+                count_drop,
+            )?;
+            Watchers::new(
+                self.watch_backends.clone(),
+                self.default_backend,
+                self.symlink_policy,
+                Arc::clone(&self.watched_links),
+                move |res| thread.send(res),
+            )
+        } else {
+            Watchers::new(
+                self.watch_backends.clone(),
+                self.default_backend,
+                self.symlink_policy,
+                Arc::clone(&self.watched_links),
+                callback,
+            )
+        };
+        Ok(watcher)
+    }
+
+    /// Replaces the watcher after its callback panicked, as the panic may have left its
+    /// state inconsistent, and watches the same paths again. Events in between are lost,
+    /// which the coverage report shows as a watcher error gap.
+    async fn rebuild_watcher(&self, senders: WatcherSenders) -> Result<()> {
+        warn!("Rebuilding the watcher after its callback panicked");
+        self.update_coverage(|coverage| coverage.open_gap(GapKind::WatcherError))
+            .await;
+        let mut watcher_lock = self.watcher.lock().await;
+        // The old watcher's threads stop before the new one starts.
+        *watcher_lock = None;
+        let mut watcher = self.create_watcher(senders)?;
+        let current_path = self.current_path.lock().await.clone();
+        let extra_watches = self.extra_watches.lock().await.clone();
+        let pending_creations = self.pending_creations.lock().await.clone();
+        for path in std::iter::once(current_path).chain(extra_watches) {
+            if pending_creations.contains(&path) {
+                continue;
+            }
+            if let Err(e) = watcher.watch(&path, self.watch_mode.recursive_mode()) {
+                error!("Failed to watch {} again: {}", path.display(), e);
+            }
+        }
+        for parent in pending_creations.iter().filter_map(|path| path.parent()) {
+            if let Err(e) = watcher.watch(parent, RecursiveMode::NonRecursive) {
+                error!("Failed to watch {} again: {}", parent.display(), e);
+            }
+        }
+        *watcher_lock = Some(watcher);
+        Ok(())
+    }
+
+    async fn watch_path(&self, path: &Path) -> Result<()> {
+        let mut watcher_lock = self.watcher.lock().await;
+        if let Some(watcher) = watcher_lock.as_mut() {
+            watcher.watch(path, self.watch_mode.recursive_mode())?;
+            info!("Now watching path: {}", path.display());
+        }
+        drop(watcher_lock);
+        if let Some(snapshots) = &self.text_snapshots {
+            let snapshots = Arc::clone(snapshots);
+            let path = path.to_path_buf();
+            let _ = tokio::task::spawn_blocking(move || snapshots.lock().unwrap().remember(&path))
+                .await;
+        }
+        self.start_scan(path);
+        // A watched file gets a first version, so it can be restored even if it is deleted
+        // before it is ever modified.
+        if path.is_file() {
+            self.back_up(path, &FileEvent::Created).await;
+        }
+        Ok(())
+    }
+
+    /// Scans what is already under `path` in the background, so that watching starts
+    /// right away even for large trees. See [`FileMonitor::get_scans`].
+    fn start_scan(&self, path: &Path) {
+        let scan = Arc::new(Scan::new(path));
+        {
+            let mut scans = self.scans.lock().unwrap();
+            for previous in scans.iter().filter(|previous| previous.watch() == path) {
+                previous.cancel();
+            }
+            scans.retain(|previous| previous.watch() != path);
+            scans.push(Arc::clone(&scan));
+        }
+        let targets = ScanTargets {
+            attributes: Arc::clone(&self.attributes),
+            hasher: absolute_path(path)
+                .ok()
+                .and_then(|watch| self.content_hashers.get(&watch))
+                .cloned(),
+        };
+        let mode = self.watch_mode.recursive_mode();
+        let running = self.scans_running.clone();
+        running.send_modify(|running| *running += 1);
+        tokio::task::spawn_blocking(move || {
+            scan.run(&targets, mode);
+            debug!("Initial scan of {} done", scan.watch().display());
+            running.send_modify(|running| *running -= 1);
+        });
+    }
+
+    fn map_event(&self, event: Event) -> Option<FileEvent> {
+        match event.kind {
+            EventKind::Access(notify::event::AccessKind::Close(_)) => Some(FileEvent::Closed),
+            EventKind::Access(_) => Some(FileEvent::Opened),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                Some(FileEvent::Renamed {
+                    from: event.paths[0].clone(),
+                    to: event.paths[1].clone(),
+                })
+            }
+            // Halves left unpaired: the file moved out of or into the watched scope.
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Some(FileEvent::Deleted),
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Some(FileEvent::Created),
+            EventKind::Modify(ModifyKind::Metadata(kind)) => Some(
+                event
+                    .paths
+                    .first()
+                    .and_then(|path| self.attributes.lock().unwrap().classify(path, kind))
+                    .unwrap_or(FileEvent::Modified),
+            ),
+            EventKind::Modify(_) => Some(FileEvent::Modified),
+            EventKind::Remove(_) => Some(FileEvent::Deleted),
+            EventKind::Create(_) => Some(FileEvent::Created),
+            EventKind::Any => {
+                if event.paths.len() == 2 {
+                    Some(FileEvent::Renamed {
+                        from: event.paths[0].clone(),
+                        to: event.paths[1].clone(),
+                    })
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// A move whose destination the backend could not see, i.e. the file left the watched scope.
+    fn is_move_out(event: &Event) -> bool {
+        matches!(
+            event.kind,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From))
+        )
+    }
+
+    /// Tries to locate the watched file after it was moved out of the watched scope and
+    /// re-targets the watch at the new location. Returns the destination if the chase succeeded.
+    async fn chase_move(&self) -> Result<Option<PathBuf>> {
+        if !*self.follow_moves.lock().await {
+            return Ok(None);
+        }
+
+        let destination = match self.move_anchor.lock().await.as_ref() {
+            Some(anchor) => resolve_anchor_path(anchor),
+            None => None,
+        };
+
+        let mut current_path = self.current_path.lock().await;
+        let destination = match destination {
+            Some(destination) if destination != *current_path => destination,
+            _ => {
+                debug!(
+                    "Could not resolve new location of {}",
+                    current_path.display()
+                );
+                return Ok(None);
+            }
+        };
+
+        if let Some(watcher) = self.watcher.lock().await.as_mut() {
+            if let Err(e) = watcher.unwatch(&current_path) {
+                debug!("Failed to unwatch {}: {}", current_path.display(), e);
+            }
+            watcher.watch(&destination, self.watch_mode.recursive_mode())?;
+        }
+
+        self.path_lineage.lock().await.push(PathMove {
+            time: Local::now(),
+            from: current_path.clone(),
+            to: destination.clone(),
+        });
+        info!(
+            "Followed {} to {}",
+            current_path.display(),
+            destination.display()
+        );
+        *current_path = destination.clone();
+
+        Ok(Some(destination))
+    }
+
+    /// Keeps a handle on the watched file so its new location can be resolved after a move.
+    async fn refresh_move_anchor(&self) {
+        let mut anchor = self.move_anchor.lock().await;
+        *anchor = if *self.follow_moves.lock().await {
+            File::open(&*self.current_path.lock().await).ok()
+        } else {
+            None
+        };
+    }
+
+    async fn handle_event(&self, event_path: PathBuf, event: FileEvent) -> Result<()> {
+        self.handle_event_at(event_path, event, None, None).await
+    }
+
+    /// Records an event received from the watcher at `received`, or generated by the
+    /// monitor itself with `None`. `process` is who caused it, if the backend knows.
+    async fn handle_event_at(
+        &self,
+        event_path: PathBuf,
+        event: FileEvent,
+        received: Option<DateTime<Local>>,
+        process: Option<ProcessInfo>,
+    ) -> Result<()> {
+        if let Some(backups) = &self.backups {
+            if backups.contains(&event_path) {
+                return Ok(());
+            }
+        }
+        if !self.filters.lock().await.allows(&event_path) {
+            debug!("Event {:?} on {} filtered out", event, event_path.display());
+            return Ok(());
+        }
+        if self
+            .paused_paths
+            .lock()
+            .await
+            .iter()
+            .any(|paused| event_path.starts_with(paused))
+        {
+            debug!(
+                "Event {:?} on {} dropped, path is paused",
+                event,
+                event_path.display()
+            );
+            return Ok(());
+        }
+        let watch = {
+            let current_path = self.current_path.lock().await.clone();
+            watch_root(&current_path, &self.extra_watches.lock().await, &event_path)
+        };
+        let watchset = match self
+            .watchsets
+            .lock()
+            .await
+            .values()
+            .find(|watchset| watchset.contains(&watch))
+        {
+            Some(watchset) if watchset.paused => {
+                debug!(
+                    "Event {:?} on {} dropped, watchset {} is paused",
+                    event,
+                    event_path.display(),
+                    watchset.name
+                );
+                return Ok(());
+            }
+            Some(watchset) if !watchset.filters.allows(&event_path) => {
+                debug!(
+                    "Event {:?} on {} filtered out by watchset {}",
+                    event,
+                    event_path.display(),
+                    watchset.name
+                );
+                return Ok(());
+            }
+            watchset => watchset.map(|watchset| watchset.name.clone()),
+        };
+        if !self.debounce.is_zero() {
+            let now = Instant::now();
+            let mut last_recorded = self.last_recorded.lock().await;
+            last_recorded.retain(|_, recorded| now.duration_since(*recorded) < self.debounce);
+            let key = (event_path.clone(), event.clone());
+            if last_recorded.contains_key(&key) {
+                debug!("Event {:?} on {} debounced", event, event_path.display());
+                return Ok(());
+            }
+            if last_recorded.len() >= self.dedup_capacity {
+                let oldest = last_recorded
+                    .iter()
+                    .min_by_key(|(_, recorded)| **recorded)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    last_recorded.remove(&oldest);
+                }
+            }
+            last_recorded.insert(key, now);
+        }
+
+        let path = self.current_path.lock().await;
+        let substitute = self.substitute_path.lock().await;
+        let now = Local::now();
+
+        // The display substitution only applies to the watched path itself, not to files
+        // below it in recursive mode.
+        let display_path = if event_path == *path {
+            substitute.as_ref().unwrap_or(&path)
+        } else {
+            &event_path
+        };
+        let substituted_path = self.get_substituted_path(display_path).await;
+
+        let git = if self.git_integration {
+            GitContext::for_path(&event_path).await
+        } else {
+            None
+        };
+        let container = match &self.container_resolver {
+            Some(resolver) => resolver.lock().await.resolve(&event_path).await,
+            None => None,
+        };
+        let maintenance = self.active_maintenance().await;
+        let content = self.hash_content(&watch, &event_path, &event).await;
+        let diff = self.text_diff(&event_path, &event).await;
+        let (metadata, sizes) = {
+            let mut attributes = self.attributes.lock().unwrap();
+            let metadata = match &event {
+                FileEvent::Deleted | FileEvent::Trashed(_) => attributes.last_known(&event_path),
+                FileEvent::Renamed { to, .. } => FileMetadata::read(to),
+                _ => FileMetadata::read(&event_path),
+            };
+            let sizes = match (&event, &metadata) {
+                (FileEvent::Modified, Some(current)) => attributes
+                    .last_known(&event_path)
+                    .map(|previous| (previous.size, current.size)),
+                _ => None,
+            };
+            attributes.update(&event_path, &event);
+            (metadata, sizes)
+        };
+        let size_change = sizes.and_then(|(previous, current)| {
+            SizeChange::between(previous, current).map(|change| (change, previous, current))
+        });
+        let appended_lines = match size_change {
+            Some((SizeChange::Appended(_), from, to)) => {
+                self.read_tailed(&event_path, from, to).await
+            }
+            _ => None,
+        };
+        let size_change = size_change.map(|(change, _, _)| change);
+        let occurred_at =
+            received.map(|received| timing::occurred_at(&event_path, &event, received));
+        let mut record = FileEventRecord {
+            id: 0,
+            time: now,
+            occurred_at,
+            watch: watch.clone(),
+            watchset,
+            path: event_path.clone(),
+            event: event.clone(),
+            git,
+            container,
+            maintenance: maintenance.as_ref().map(|window| window.label.clone()),
+            content,
+            metadata,
+            size_change,
+            appended_lines,
+            enrichment: BTreeMap::new(),
+            process,
+            via_symlink: self.via_symlink(&event_path),
+        };
+        if !self.enrichers.is_empty() {
+            record.enrichment = enrich::enrich(&self.enrichers, &record).await;
+        }
+
+        let (verdict, rule) = rules::evaluate_rules(&self.rules, &record);
+        if verdict == Verdict::Suppress {
+            debug!(
+                "Event {:?} on {} suppressed by rule {}",
+                event,
+                display_path.display(),
+                rule.unwrap_or_default()
+            );
+            return Ok(());
+        }
+        record.id = self.next_event_id();
+        if let Some(occurred_at) = record.occurred_at {
+            let latency = (record.time - occurred_at).to_std().unwrap_or_default();
+            self.latency.lock().await.record(latency);
+        }
+        let backup = self.back_up(&event_path, &event).await;
+
+        let event_message = match &event {
+            FileEvent::Opened => format!(
+                "File opened: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::Modified => format!(
+                "File modified: {} (actual: {}){}",
+                display_path.display(),
+                substituted_path.display(),
+                match record.size_change {
+                    Some(SizeChange::Appended(bytes)) => format!(", appended {} bytes", bytes),
+                    Some(SizeChange::Truncated) => ", truncated".to_string(),
+                    None => String::new(),
+                }
+            ),
+            FileEvent::Deleted => format!(
+                "File deleted: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::Trashed(location) => format!(
+                "File moved to trash: {} (actual: {}) at {}",
+                display_path.display(),
+                substituted_path.display(),
+                location.display()
+            ),
+            FileEvent::Created => format!(
+                "File created: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::Renamed { to: new_path, .. } => {
+                let substituted_new_path = self.get_substituted_path(new_path).await;
+                format!(
+                    "File renamed from {} (actual: {}) to {} (actual: {})",
+                    display_path.display(),
+                    substituted_path.display(),
+                    new_path.display(),
+                    substituted_new_path.display()
+                )
+            }
+            FileEvent::Closed => format!(
+                "File closed: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::PermissionsChanged => format!(
+                "File permissions changed: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::OwnershipChanged => format!(
+                "File ownership changed: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::TimestampChanged => format!(
+                "File timestamps changed: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::Replaced(temp) => format!(
+                "File replaced: {} (actual: {}) via {}",
+                display_path.display(),
+                substituted_path.display(),
+                temp.display()
+            ),
+            FileEvent::ConfigReloaded => {
+                format!("Configuration reloaded from {}", display_path.display())
+            }
+            FileEvent::Recreated => format!(
+                "File recreated: {} (actual: {})",
+                display_path.display(),
+                substituted_path.display()
+            ),
+            FileEvent::BaselineDrift(drift) => format!(
+                "Baseline drift: {} (actual: {}) {:?}",
+                display_path.display(),
+                substituted_path.display(),
+                drift
+            ),
+            FileEvent::RateAlert {
+                rule,
+                count,
+                window_secs,
+            } => format!(
+                "Rate alert {}: {} events in {}s at {}",
+                rule,
+                count,
+                window_secs,
+                display_path.display()
+            ),
+            FileEvent::Paused { since, until } => format!(
+                "Monitoring paused from {} to {} ({}s)",
+                since.format("%Y-%m-%d %H:%M:%S"),
+                until.format("%Y-%m-%d %H:%M:%S"),
+                (*until - *since).num_seconds()
+            ),
+            FileEvent::EventsDropped(dropped) => format!(
+                "Events dropped: {} events for {} lost, event queue full",
+                dropped,
+                display_path.display()
+            ),
+        };
+
+        let mut tags = Vec::new();
+        if let Some(git) = &record.git {
+            tags.push(format!(
+                "git: {} {}, {:?}",
+                git.branch.as_deref().unwrap_or("(detached)"),
+                if git.dirty { "dirty" } else { "clean" },
+                git.file_status
+            ));
+        }
+        if let Some(label) = &record.maintenance {
+            tags.push(format!("maintenance: {}", label));
+        }
+        if let Some(content) = &record.content {
+            match &content.sha256 {
+                Some(sha256) => tags.push(format!(
+                    "sha256 {:?}: {}, {} bytes",
+                    content.strategy, sha256, content.size
+                )),
+                None => tags.push(format!("size: {} bytes", content.size)),
+            }
+        }
+        if let Some(backup) = &backup {
+            tags.push(format!("backup: {}", backup.id));
+        }
+        if let Some(container) = &record.container {
+            tags.push(format!(
+                "container: {} ({})",
+                container.name.as_deref().unwrap_or(&container.id[..12]),
+                container.runtime
+            ));
+        }
+        if tags.is_empty() {
+            info!("{} at {}", event_message, now);
+        } else {
+            info!("{} at {} [{}]", event_message, now, tags.join("; "));
+        }
+        let alert = match verdict {
+            Verdict::Alert(reason) => {
+                let rule = rule.unwrap_or_default();
+                match maintenance.filter(|window| window.downgrade_alerts) {
+                    Some(window) => {
+                        info!(
+                            "Alert from rule {} downgraded during maintenance {}: {} ({})",
+                            rule, window.label, reason, event_message
+                        );
+                        None
+                    }
+                    None => {
+                        warn!("Alert from rule {}: {} ({})", rule, reason, event_message);
+                        Some((rule, reason))
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        for hook in self.event_hooks.lock().await.iter_mut() {
+            hook(&record);
+        }
+        self.notify_webhooks(&record).await;
+        if let Some(shell_hooks) = &self.shell_hooks {
+            shell_hooks.run(&record);
+        }
+
+        // Sending only fails when nobody is subscribed.
+        let _ = self.event_tx.send(MonitorEvent::File(record.clone()));
+        if let Some((rule, reason)) = alert {
+            self.track_alert(Some(&rule), &reason, Some(&record.path))
+                .await;
+            let _ = self.event_tx.send(MonitorEvent::Alert {
+                rule,
+                reason,
+                record: record.clone(),
+            });
+        }
+
+        if let Some(diff) = diff {
+            self.diffs.lock().await.insert(record.id, diff);
+        }
+        self.update_history(record).await;
+        let kind = event.kind();
+        self.update_stats(watch.clone(), event).await;
+        self.check_rate_alerts(kind, watch, event_path).await;
+
+        Ok(())
+    }
+
+    /// Raises an alert for every rate alert rule that the event just recorded pushed over
+    /// its threshold. The alert is delivered like an event, to hooks, webhooks and
+    /// subscribers, and recorded in history as a [`FileEvent::RateAlert`].
+    async fn check_rate_alerts(&self, kind: &str, watch: PathBuf, path: PathBuf) {
+        let fired: Vec<_> = {
+            let rates = self.rates.lock().await;
+            self.rate_alerts
+                .lock()
+                .await
+                .iter_mut()
+                .filter_map(|state| {
+                    let count = state.check(kind, &rates)?;
+                    Some((state.rule.clone(), count))
+                })
+                .collect()
+        };
+        if fired.is_empty() {
+            return;
+        }
+        let maintenance = self.active_maintenance().await;
+        for (rule, count) in fired {
+            let reason = format!(
+                "{} {} events in {}s, threshold {}",
+                count, rule.event, rule.window_secs, rule.threshold
+            );
+            let record = FileEventRecord {
+                id: self.next_event_id(),
+                time: Local::now(),
+                occurred_at: None,
+                metadata: None,
+                size_change: None,
+                appended_lines: None,
+                enrichment: BTreeMap::new(),
+                process: None,
+                via_symlink: None,
+                watchset: None,
+                watch: watch.clone(),
+                path: path.clone(),
+                event: FileEvent::RateAlert {
+                    rule: rule.name.clone(),
+                    count,
+                    window_secs: rule.window_secs,
+                },
+                git: None,
+                container: None,
+                maintenance: maintenance.as_ref().map(|window| window.label.clone()),
+                content: None,
+            };
+            let downgraded = maintenance
+                .as_ref()
+                .filter(|window| window.downgrade_alerts);
+            match downgraded {
+                Some(window) => info!(
+                    "Rate alert {} downgraded during maintenance {}: {}",
+                    rule.name, window.label, reason
+                ),
+                None => warn!("Rate alert {}: {}", rule.name, reason),
+            }
+
+            for hook in self.event_hooks.lock().await.iter_mut() {
+                hook(&record);
+            }
+            self.notify_webhooks(&record).await;
+            if let Some(shell_hooks) = &self.shell_hooks {
+                shell_hooks.run(&record);
+            }
+            let _ = self.event_tx.send(MonitorEvent::File(record.clone()));
+            if downgraded.is_none() {
+                self.track_alert(Some(&rule.name), &reason, Some(&record.path))
+                    .await;
+                let _ = self.event_tx.send(MonitorEvent::Alert {
+                    rule: rule.name,
+                    reason,
+                    record: record.clone(),
+                });
+            }
+            self.update_history(record).await;
+        }
+    }
+
+    /// Records size, modification time and hash of every file under the watched paths,
+    /// within the watch depth and filters. Watches with content hashing use its
+    /// [`HashPolicy`], others the default one.
+    pub async fn create_baseline(&self) -> Result<Baseline> {
+        let max_depth = match self.watch_mode {
+            WatchMode::NonRecursive => Some(1),
+            WatchMode::Recursive { max_depth } => max_depth,
+        };
+        let mut roots = Vec::new();
+        let primary = self.current_path.lock().await.clone();
+        for path in std::iter::once(primary).chain(self.extra_watches.lock().await.clone()) {
+            let path = absolute_path(&path)?;
+            let policy = self
+                .content_hashers
+                .get(&path)
+                .map_or_else(HashPolicy::default, |hasher| {
+                    hasher.lock().unwrap().policy()
+                });
+            roots.push(BaselineRoot {
+                path,
+                max_depth,
+                policy,
+            });
+        }
+        let filter = self.filters.lock().await.clone();
+        tokio::task::spawn_blocking(move || Baseline::create(roots, &filter)).await?
+    }
+
+    /// Compares the files under the baseline's paths with it and records each difference
+    /// as a [`FileEvent::BaselineDrift`] event, which goes to history, hooks, webhooks and
+    /// subscribers like any other. Returns the differences.
+    pub async fn verify_baseline(&self, baseline: &Baseline) -> Result<Vec<(PathBuf, Drift)>> {
+        let filter = self.filters.lock().await.clone();
+        let roots = baseline.clone();
+        let drift = tokio::task::spawn_blocking(move || roots.verify(&filter)).await??;
+        for (path, change) in &drift {
+            self.handle_event(path.clone(), FileEvent::BaselineDrift(change.clone()))
+                .await?;
+        }
+        info!(
+            "Baseline from {} verified: {} files drifted",
+            baseline.created,
+            drift.len()
+        );
+        Ok(drift)
+    }
+
+    /// Adds a rate alert rule; its name must be unique.
+    pub async fn add_rate_alert(&self, rule: RateAlertRule) -> Result<()> {
+        rule.validate()?;
+        let mut rate_alerts = self.rate_alerts.lock().await;
+        if rate_alerts.iter().any(|state| state.rule.name == rule.name) {
+            return Err(MonitorError::AlreadyExists {
+                kind: "Rate alert",
+                name: rule.name,
+            });
+        }
+        info!(
+            "Rate alert {} added: more than {} {} events in {}s",
+            rule.name, rule.threshold, rule.event, rule.window_secs
+        );
+        rate_alerts.push(RateAlertState::new(rule));
+        Ok(())
+    }
+
+    /// Removes the rate alert rule named `name`; returns whether it existed.
+    pub async fn remove_rate_alert(&self, name: &str) -> bool {
+        let mut rate_alerts = self.rate_alerts.lock().await;
+        let before = rate_alerts.len();
+        rate_alerts.retain(|state| state.rule.name != name);
+        rate_alerts.len() != before
+    }
+
+    pub async fn get_rate_alerts(&self) -> Vec<RateAlertState> {
+        self.rate_alerts.lock().await.clone()
+    }
+
+    /// Copies a created or modified file to the backup directory, returning the new
+    /// version. Deleted files keep the versions taken before.
+    async fn back_up(&self, path: &Path, event: &FileEvent) -> Option<BackupVersion> {
+        let backups = Arc::clone(self.backups.as_ref()?);
+        if !matches!(
+            event,
+            FileEvent::Created | FileEvent::Modified | FileEvent::Replaced(_)
+        ) {
+            return None;
+        }
+        let path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || backups.backup(&path)).await {
+            Ok(Ok(version)) => version,
+            Ok(Err(e)) => {
+                warn!("Failed to back up file: {}", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Backed-up versions of `path`, oldest first.
+    pub async fn get_backups<P: AsRef<Path>>(&self, path: P) -> Result<Vec<BackupVersion>> {
+        let backups = Arc::clone(
+            self.backups
+                .as_ref()
+                .ok_or(MonitorError::NotEnabled("Backups"))?,
+        );
+        let path = absolute_path(path.as_ref())?;
+        tokio::task::spawn_blocking(move || backups.versions(&path)).await?
+    }
+
+    /// Rolls a file back to a backed-up version, by the id listed in
+    /// [`FileMonitor::get_backups`]. The restore is itself recorded as a modification.
+    pub async fn restore_backup(&self, version: &str) -> Result<PathBuf> {
+        let backups = Arc::clone(
+            self.backups
+                .as_ref()
+                .ok_or(MonitorError::NotEnabled("Backups"))?,
+        );
+        let version = version.to_string();
+        let restored = tokio::task::spawn_blocking(move || backups.restore(&version)).await??;
+        info!("Restored {}", restored.display());
+        Ok(restored)
+    }
+
+    /// Diffs a modified text file against its last known content. Created files and
+    /// watched paths are only remembered, so files first seen through a modification get
+    /// a diff from their next change on.
+    async fn text_diff(&self, path: &Path, event: &FileEvent) -> Option<String> {
+        let snapshots = Arc::clone(self.text_snapshots.as_ref()?);
+        let path = path.to_path_buf();
+        let event = event.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut snapshots = snapshots.lock().unwrap();
+            match event {
+                FileEvent::Modified | FileEvent::Replaced(_) => snapshots.update(&path),
+                FileEvent::Created => {
+                    snapshots.remember(&path);
+                    None
+                }
+                FileEvent::Renamed { from, to } => {
+                    snapshots.rename(&from, &to);
+                    None
+                }
+                FileEvent::Deleted | FileEvent::Trashed(_) => {
+                    snapshots.forget(&path);
+                    None
+                }
+                _ => None,
+            }
+        })
+        .await
+        .ok()?
+    }
+
+    /// Lines written to `path` between lengths `from` and `to`, if it is being tailed.
+    async fn read_tailed(&self, path: &Path, from: u64, to: u64) -> Option<Vec<String>> {
+        if !self
+            .tailed
+            .lock()
+            .await
+            .iter()
+            .any(|tailed| path.starts_with(tailed))
+        {
+            return None;
+        }
+        let path = path.to_path_buf();
+        let read = tokio::task::spawn_blocking(move || tail::read_appended(&path, from, to))
+            .await
+            .ok()?;
+        match read {
+            Ok(lines) => Some(lines),
+            Err(e) => {
+                debug!("Failed to read appended lines: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Fingerprints the file after an event that may have changed its content, if content
+    /// hashing is enabled for `watch`. Files that are gone or unreadable get none.
+    async fn hash_content(
+        &self,
+        watch: &Path,
+        path: &Path,
+        event: &FileEvent,
+    ) -> Option<ContentHash> {
+        let watch = absolute_path(watch).ok()?;
+        let hasher = Arc::clone(self.content_hashers.get(&watch)?);
+        let path = path.to_path_buf();
+        match event {
+            FileEvent::Created | FileEvent::Modified | FileEvent::Replaced(_) => {}
+            FileEvent::Deleted | FileEvent::Trashed(_) | FileEvent::Renamed { .. } => {
+                hasher.lock().unwrap().forget(&path);
+                return None;
+            }
+            _ => return None,
+        }
+        if !path.is_file() {
+            return None;
+        }
+        let hashed = tokio::task::spawn_blocking(move || hasher.lock().unwrap().hash(&path))
+            .await
+            .ok()?;
+        match hashed {
+            Ok(content) => Some(content),
+            Err(e) => {
+                debug!("Failed to hash content: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Posts the event to every matching webhook in the background, so slow or failing
+    /// endpoints never hold up event handling.
+    async fn notify_webhooks(&self, record: &FileEventRecord) {
+        let webhooks: Vec<_> = self
+            .webhooks
+            .lock()
+            .await
+            .iter()
+            .filter(|webhook| webhook.matches(record))
+            .cloned()
+            .collect();
+        if webhooks.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_vec(&webhook::payload(record)) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to encode webhook payload: {}", e);
+                return;
+            }
+        };
+        for webhook in webhooks {
+            if !webhook.rate_limiter().try_acquire() {
+                debug!("Webhook {} is rate limited, dropping event", webhook.url);
+                continue;
+            }
+            let body = body.clone();
+            let retry = self.webhook_retry;
+            tokio::spawn(async move {
+                let delivery = webhook::deliver(&webhook.url, &body, retry);
+                if let Err(e) = webhook.circuit_breaker().call(delivery).await {
+                    debug!("Webhook {} not delivered: {}", webhook.url, e);
+                }
+            });
+        }
+    }
+
+    async fn update_history(&self, record: FileEventRecord) {
+        let mut history = self.event_history.lock().await;
+        history.push(record);
+        if history.len() > self.history_size {
+            let excess = history.len() - self.history_size;
+            history.drain(..excess);
+        }
+        self.expire_history(&mut history);
+        // Ids only grow, so diffs older than the oldest record left belong to dropped ones.
+        let oldest = history.first().map_or(u64::MAX, |record| record.id);
+        self.diffs.lock().await.retain(|id, _| *id >= oldest);
+    }
+
+    fn next_event_id(&self) -> u64 {
+        self.next_event_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Drops records older than the configured maximum age. Records are appended in time
+    /// order, so the expired ones are at the front.
+    fn expire_history(&self, history: &mut EventHistory) {
+        let Some(cutoff) = self
+            .history_max_age
+            .and_then(|max_age| chrono::Duration::from_std(max_age).ok())
+            .map(|max_age| Local::now() - max_age)
+        else {
+            return;
+        };
+        let expired = history
+            .iter()
+            .take_while(|record| record.time < cutoff)
+            .count();
+        history.drain(..expired);
+    }
+
+    async fn update_stats(&self, watch: PathBuf, event: FileEvent) {
+        let mut watch_stats = self.watch_stats.lock().await;
+        *watch_stats
+            .entry(watch)
+            .or_default()
+            .entry(event.clone())
+            .or_insert(0) += 1;
+
+        self.rates.lock().await.record(event.kind());
+        let mut stats = self.stats.lock().await;
+        *stats.entry(event).or_insert(0) += 1;
+    }
+
+    /// Moves the primary watch to `new_path`, with `.` and `..` resolved, and symlinks
+    /// too with [`FileMonitorBuilder::canonicalize_paths`]. Fails with
+    /// [`MonitorError::PathNotFound`] if it does not exist, unless missing paths are
+    /// allowed, see [`FileMonitorBuilder::allow_missing`]; then its parent directory is
+    /// watched until it is created.
+    pub async fn update_path<P: AsRef<Path>>(&self, new_path: P) -> Result<()> {
+        let new_path = self.resolve_watch_path(new_path.as_ref())?;
+        let missing = match self.validate_watch_path(&new_path) {
+            Ok(()) => false,
+            Err(MonitorError::PathNotFound(_)) if self.allow_missing => true,
+            Err(e) => return Err(e),
+        };
+
+        if missing && !new_path.parent().is_some_and(Path::is_dir) {
+            let parent = new_path.parent().unwrap_or(&new_path);
+            return Err(MonitorError::PathNotFound(parent.to_path_buf()));
+        }
+
+        let old_path = self.current_path.lock().await.clone();
+        debug!(
+            "Updating path from {} to {}",
+            old_path.display(),
+            new_path.display()
+        );
+        self.cancel_creation(&old_path).await;
+        self.lost_watches
+            .lock()
+            .await
+            .retain(|lost| *lost != old_path);
+        if let Some(watcher) = self.watcher.lock().await.as_mut() {
+            // The old path may be gone already.
+            if let Err(e) = watcher.unwatch(&old_path) {
+                debug!("Failed to unwatch {}: {}", old_path.display(), e);
+            }
+            if !missing {
+                watcher.watch(&new_path, self.watch_mode.recursive_mode())?;
+            }
+        }
+        *self.current_path.lock().await = new_path.clone();
+        if missing && self.watcher.lock().await.is_some() {
+            self.await_creation(&new_path).await?;
+        }
+        self.refresh_move_anchor().await;
+        info!("Path updated to: {}", new_path.display());
+        Ok(())
+    }
+
+    /// `path` made absolute with `.` and `..` resolved, and symlinks too when
+    /// canonicalizing paths.
+    fn resolve_watch_path(&self, path: &Path) -> Result<PathBuf> {
+        let path = normalize_path(&absolute_path(path)?);
+        if self.canonicalize_paths && path.exists() {
+            return Ok(std::fs::canonicalize(&path)?);
+        }
+        Ok(path)
+    }
+
+    /// Checks that `path` exists and is a file or directory, which is what backends
+    /// can watch. A symlink watched as a link only may dangle.
+    fn validate_watch_path(&self, path: &Path) -> Result<()> {
+        if self.symlink_policy == SymlinkPolicy::Link && is_symlink(path) {
+            return Ok(());
+        }
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(MonitorError::PathNotFound(path.to_path_buf()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !metadata.is_file() && !metadata.is_dir() {
+            return Err(MonitorError::WrongPathType {
+                path: path.to_path_buf(),
+                expected: "file or directory",
+            });
+        }
+        Ok(())
+    }
+
+    /// Enables or disables chasing the watched file when it is moved out of the watched scope.
+    pub async fn set_follow_moves(&self, enabled: bool) -> Result<()> {
+        *self.follow_moves.lock().await = enabled;
+        self.refresh_move_anchor().await;
+        info!(
+            "Following moves {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Enables or disables re-establishing watches whose path was deleted or renamed
+    /// away once it is recreated, see [`FileEvent::Recreated`].
+    pub async fn set_follow_names(&self, enabled: bool) {
+        *self.follow_names.lock().await = enabled;
+        if !enabled {
+            self.lost_watches.lock().await.clear();
+        }
+        info!(
+            "Following names {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+
+    pub async fn get_path_lineage(&self) -> Vec<PathMove> {
+        self.path_lineage.lock().await.clone()
+    }
+
+    pub async fn substitute_path<P: AsRef<Path>>(&self, old_path: P, new_path: P) -> Result<()> {
+        let current_path = self.current_path.lock().await;
+        let mut substitute = self.substitute_path.lock().await;
+
+        if current_path.as_path() != old_path.as_ref() {
+            return Err(MonitorError::SubstitutionMismatch {
+                expected: old_path.as_ref().to_path_buf(),
+                current: current_path.clone(),
+            });
+        }
+        *substitute = Some(new_path.as_ref().to_path_buf());
+        info!(
+            "Path substituted: {} -> {}",
+            old_path.as_ref().display(),
+            new_path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    /// Pauses monitoring until [`FileMonitor::resume`], ending a timed pause early.
+    pub async fn pause(&self) -> Result<()> {
+        let mut is_paused = self.is_paused.lock().await;
+        *is_paused = true;
+        self.paused_since
+            .lock()
+            .await
+            .get_or_insert_with(Local::now);
+        self.resume_at.send_replace(None);
+        self.update_coverage(|coverage| coverage.open_gap(GapKind::Paused))
+            .await;
+        info!("Monitoring paused");
+        Ok(())
+    }
+
+    /// Pauses monitoring and resumes it after `duration`, e.g. for a maintenance window,
+    /// unless resumed before. The monitor has to be running to resume on its own.
+    pub async fn pause_for(&self, duration: Duration) -> Result<()> {
+        self.pause().await?;
+        self.resume_at.send_replace(Some(Instant::now() + duration));
+        info!("Monitoring resumes in {:?}", duration);
+        Ok(())
+    }
+
+    /// When a timed pause ends.
+    pub fn get_resume_time(&self) -> Option<DateTime<Local>> {
+        let remaining = (*self.resume_at.borrow())?.saturating_duration_since(Instant::now());
+        Some(Local::now() + chrono::Duration::from_std(remaining).ok()?)
+    }
+
+    /// Resumes monitoring and records the pause as [`FileEvent::Paused`].
+    pub async fn resume(&self) -> Result<()> {
+        {
+            let mut is_paused = self.is_paused.lock().await;
+            *is_paused = false;
+        }
+        self.resume_at.send_replace(None);
+        self.update_coverage(|coverage| coverage.close_gap(GapKind::Paused))
+            .await;
+        info!("Monitoring resumed");
+        let since = self.paused_since.lock().await.take();
+        if let Some(since) = since {
+            let path = self.current_path.lock().await.clone();
+            let until = Local::now();
+            self.handle_event(path, FileEvent::Paused { since, until })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Drops events for `path`, or for anything below it if it is a directory, until
+    /// [`FileMonitor::resume_path`], while the other watched paths are still monitored.
+    /// `path` must be watched. Unlike [`FileMonitor::pause`], this leaves no coverage gap.
+    pub async fn pause_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let watched = self
+            .get_watches()
+            .await
+            .iter()
+            .filter_map(|watch| absolute_path(watch).ok())
+            .any(|watch| path.starts_with(watch));
+        if !watched {
+            return Err(MonitorError::NotWatching(path));
+        }
+        let mut paused_paths = self.paused_paths.lock().await;
+        if !paused_paths.contains(&path) {
+            paused_paths.push(path.clone());
+        }
+        info!("Monitoring of {} paused", path.display());
+        Ok(())
+    }
+
+    pub async fn resume_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let mut paused_paths = self.paused_paths.lock().await;
+        let Some(index) = paused_paths.iter().position(|paused| *paused == path) else {
+            return Err(monitor_error!("{} is not paused", path.display()));
+        };
+        paused_paths.remove(index);
+        info!("Monitoring of {} resumed", path.display());
+        Ok(())
+    }
+
+    pub async fn get_paused_paths(&self) -> Vec<PathBuf> {
+        self.paused_paths.lock().await.clone()
+    }
+
+    /// Re-checks the policy file. A valid, authorized change replaces the active filters;
+    /// an invalid or unauthorized one keeps them and raises a critical alert.
+    pub async fn check_config(&self) {
+        let Some(guard) = &self.config_guard else {
+            return;
+        };
+        let result = guard.lock().await.check();
+        match result {
+            Ok(Some(filter)) => {
+                *self.filters.lock().await = filter;
+                info!("Policy reloaded");
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("CRITICAL: {}", e);
+                self.track_alert(None, &e.to_string(), None).await;
+                let _ = self.event_tx.send(MonitorEvent::Critical {
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Starts escalating an alert if the escalation policy covers it; `rule` is `None`
+    /// for a critical alert.
+    async fn track_alert(&self, rule: Option<&str>, reason: &str, path: Option<&Path>) {
+        if let Some(escalator) = self.escalator.as_ref().filter(|e| e.covers(rule)) {
+            escalator.raise(rule, reason, path).await;
+        }
+    }
+
+    /// Sends the alerts nobody acknowledged to their next escalation step, if due.
+    /// Runs periodically while monitoring; returns how many were escalated.
+    pub async fn escalate_alerts(&self) -> usize {
+        match &self.escalator {
+            Some(escalator) => escalator.escalate_due().await,
+            None => 0,
+        }
+    }
+
+    /// Stops escalating alert `id`, see [`FileMonitor::get_open_alerts`].
+    pub async fn acknowledge_alert(&self, id: &str, by: &str) -> Result<()> {
+        self.escalator
+            .as_ref()
+            .ok_or(MonitorError::NotEnabled("Alert escalation"))?
+            .acknowledge(id, by)
+            .await
+    }
+
+    /// Escalated alerts not acknowledged yet, oldest first.
+    pub async fn get_open_alerts(&self) -> Vec<EscalatedAlert> {
+        match &self.escalator {
+            Some(escalator) => escalator.open_alerts().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Applies the policy file and watches it (and the manifest) for changes.
+    async fn watch_config(
+        &self,
+        config_tx: tokio::sync::mpsc::Sender<()>,
+    ) -> Result<Option<notify::RecommendedWatcher>> {
+        let Some(guard) = &self.config_guard else {
+            return Ok(None);
+        };
+        self.check_config().await;
+
+        let files = guard.lock().await.watched_files();
+        Ok(Some(watch_files(files, config_tx)?))
+    }
+
+    /// Re-reads the config file set with [`FileMonitorBuilder::config_file`] and applies
+    /// changed paths, watches, substitutions, filters and rate alerts without a restart,
+    /// recording a [`FileEvent::ConfigReloaded`] event. Returns whether anything changed.
+    /// Other changed settings are logged by name, see
+    /// [`MonitorConfig::restart_required`]. A file that does not parse or has invalid
+    /// filters leaves the current settings untouched.
+    pub async fn reload_config(&self) -> Result<bool> {
+        let Some((config_path, applied)) = &self.config_file else {
+            return Ok(false);
+        };
+        let config = MonitorConfig::load(config_path)?;
+        let mut applied = applied.lock().await;
+        if config == *applied {
+            return Ok(false);
+        }
+        let filters = Policy {
+            filters: config.filters.clone(),
+        }
+        .to_filter()?;
+        for rule in &config.rate_alerts {
+            rule.validate()?;
+        }
+
+        if let Some(path) = config
+            .path
+            .as_ref()
+            .filter(|path| applied.path.as_ref() != Some(*path))
+        {
+            self.update_path(path).await?;
+        }
+        for watch in applied
+            .watches
+            .iter()
+            .filter(|watch| !config.watches.contains(watch))
+        {
+            if let Err(e) = self.remove_watch(watch).await {
+                warn!("Failed to remove watch {}: {}", watch.display(), e);
+            }
+        }
+        for watch in config
+            .watches
+            .iter()
+            .filter(|watch| !applied.watches.contains(watch))
+        {
+            if let Err(e) = self.add_watch(watch).await {
+                warn!("Failed to add watch {}: {}", watch.display(), e);
+            }
+        }
+        for removed in applied.substitutions.iter().filter(|substitution| {
+            !config
+                .substitutions
+                .iter()
+                .any(|new| new.original == substitution.original)
+        }) {
+            self.remove_path_substitution(&removed.original).await?;
+        }
+        for added in config
+            .substitutions
+            .iter()
+            .filter(|substitution| !applied.substitutions.contains(substitution))
+        {
+            self.add_path_substitution(&added.original, &added.substitute)
+                .await?;
+        }
+        if config.filters != applied.filters {
+            *self.filters.lock().await = filters;
+        }
+        if config.rate_alerts != applied.rate_alerts {
+            // Rules that did not change keep whether they are firing.
+            let mut rate_alerts = self.rate_alerts.lock().await;
+            let updated = config
+                .rate_alerts
+                .iter()
+                .map(|rule| {
+                    rate_alerts
+                        .iter()
+                        .find(|state| state.rule == *rule)
+                        .cloned()
+                        .unwrap_or_else(|| RateAlertState::new(rule.clone()))
+                })
+                .collect();
+            *rate_alerts = updated;
+        }
+        let restart_required = config.restart_required(&applied);
+        if !restart_required.is_empty() {
+            warn!(
+                "Changes to {} in {} take effect after a restart",
+                restart_required.join(", "),
+                config_path.display()
+            );
+        }
+        *applied = config;
+        drop(applied);
+
+        info!("Configuration reloaded from {}", config_path.display());
+        let record = FileEventRecord {
+            id: self.next_event_id(),
+            time: Local::now(),
+            occurred_at: None,
+            metadata: None,
+            size_change: None,
+            appended_lines: None,
+            enrichment: BTreeMap::new(),
+            process: None,
+            via_symlink: None,
+            watchset: None,
+            watch: config_path.clone(),
+            path: config_path.clone(),
+            event: FileEvent::ConfigReloaded,
+            git: None,
+            container: None,
+            maintenance: self.active_maintenance().await.map(|window| window.label),
+            content: None,
+        };
+        let _ = self.event_tx.send(MonitorEvent::File(record.clone()));
+        self.update_history(record).await;
+        Ok(true)
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        *self.is_paused.lock().await
+    }
+
+    /// Starts a maintenance window: events are tagged with `label` until it is stopped.
+    /// With `downgrade_alerts`, rule alerts are logged at info level instead of raised.
+    pub async fn start_maintenance(&self, label: &str, downgrade_alerts: bool) -> Result<()> {
+        let mut windows = self.maintenance_windows.lock().await;
+        if let Some(active) = windows.last().filter(|window| window.is_active()) {
+            return Err(monitor_error!(
+                "Maintenance window {} is already active",
+                active.label
+            ));
+        }
+        windows.push(MaintenanceWindow::start(label, downgrade_alerts));
+        info!("Maintenance window {} started", label);
+        Ok(())
+    }
+
+    /// Ends the active maintenance window and returns it.
+    pub async fn stop_maintenance(&self) -> Result<MaintenanceWindow> {
+        let mut windows = self.maintenance_windows.lock().await;
+        let window = windows
+            .last_mut()
+            .filter(|window| window.is_active())
+            .ok_or_else(|| monitor_error!("No maintenance window is active"))?;
+        window.end = Some(Local::now());
+        info!("Maintenance window {} stopped", window.label);
+        Ok(window.clone())
+    }
+
+    pub async fn get_maintenance_windows(&self) -> Vec<MaintenanceWindow> {
+        self.maintenance_windows.lock().await.clone()
+    }
+
+    pub async fn active_maintenance(&self) -> Option<MaintenanceWindow> {
+        self.maintenance_windows
+            .lock()
+            .await
+            .last()
+            .filter(|window| window.is_active())
+            .cloned()
+    }
+
+    /// How much of the monitor's lifetime the event record actually covers.
+    pub async fn get_coverage(&self) -> CoverageReport {
+        self.coverage.lock().await.report()
+    }
+
+    async fn update_coverage<F>(&self, update: F)
+    where
+        F: FnOnce(&mut CoverageTracker) -> Result<()>,
+    {
+        if let Err(e) = update(&mut *self.coverage.lock().await) {
+            warn!("Failed to persist coverage: {}", e);
+        }
+    }
+
+    pub async fn get_stats(&self) -> HashMap<FileEvent, usize> {
+        self.stats.lock().await.clone()
+    }
+
+    /// Events dropped so far because the event queue was full.
+    pub fn get_dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Events spilled to disk so far in bounded memory mode, see
+    /// [`FileMonitorBuilder::memory_limits`].
+    pub fn get_spilled_events(&self) -> u64 {
+        self.spilled_events.load(Ordering::Relaxed)
+    }
+
+    /// Processing latency of the events recorded from the watcher so far.
+    pub async fn get_latency(&self) -> LatencyMetrics {
+        *self.latency.lock().await
+    }
+
+    /// How many events of one kind (e.g. `modified`) were recorded within the last
+    /// `window`, up to [`rates::MAX_RATE_WINDOW`].
+    pub async fn get_rate(&self, event_kind: &str, window: Duration) -> EventRate {
+        self.rates.lock().await.rate(event_kind, window)
+    }
+
+    /// Rates of every event kind recorded within the last `window`.
+    pub async fn get_rates(&self, window: Duration) -> BTreeMap<&'static str, EventRate> {
+        self.rates.lock().await.rates(window)
+    }
+
+    pub async fn get_history(&self) -> EventHistory {
+        self.event_history.lock().await.clone()
+    }
+
+    /// The recorded event with `id`, with its diff, while it is still in the history.
+    pub async fn get_history_detail(&self, id: u64) -> Option<HistoryDetail> {
+        let record = self
+            .event_history
+            .lock()
+            .await
+            .iter()
+            .find(|record| record.id == id)
+            .cloned()?;
+        let diff = self.diffs.lock().await.get(&id).cloned();
+        Some(HistoryDetail { record, diff })
+    }
+
+    /// Recorded events with `from <= time < to`, oldest first.
+    pub async fn get_history_range(
+        &self,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> EventHistory {
+        self.event_history
+            .lock()
+            .await
+            .iter()
+            .filter(|record| record.time >= from && record.time < to)
+            .cloned()
+            .collect()
+    }
+
+    /// Recorded events of one kind, named as by [`FileEvent::kind`] (e.g. `deleted`).
+    pub async fn get_history_filtered(&self, event_kind: &str) -> EventHistory {
+        self.event_history
+            .lock()
+            .await
+            .iter()
+            .filter(|record| record.event.kind().eq_ignore_ascii_case(event_kind))
+            .cloned()
+            .collect()
+    }
+
+    /// Registers a handler that runs for every recorded event, right after it is logged.
+    /// Events dropped by filters or suppressed by rules never reach it. Hooks run inline
+    /// in event handling, so they should be quick.
+    pub async fn on_event<F>(&self, hook: F)
+    where
+        F: FnMut(&FileEventRecord) + Send + 'static,
+    {
+        self.event_hooks.lock().await.push(Box::new(hook));
+    }
+
+    /// Subscribes to events as they are recorded.
+    pub fn subscribe(&self) -> EventSubscription {
+        EventSubscription::new(self.event_tx.subscribe())
+    }
+
+    /// Saves the current history as JSON lines for offline analysis with `fm-query`.
+    pub async fn save_history<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let history = self.get_history().await;
+        query::write_history_file(&history, path.as_ref())?;
+        info!(
+            "Saved {} history records to {}",
+            history.len(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    /// Writes the current history to `path` as a JSON array or as CSV.
+    pub async fn export_history<P: AsRef<Path>>(
+        &self,
+        format: ExportFormat,
+        path: P,
+    ) -> Result<()> {
+        let history = self.get_history().await;
+        let file = std::io::BufWriter::new(File::create(path.as_ref())?);
+        export::write_history(&history, format, file)?;
+        info!(
+            "Exported {} history records to {}",
+            history.len(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    /// Writes per-watch event counts to `path` as JSON or CSV.
+    pub async fn export_stats<P: AsRef<Path>>(&self, format: ExportFormat, path: P) -> Result<()> {
+        let rows = export::stats_rows(&*self.watch_stats.lock().await);
+        let file = std::io::BufWriter::new(File::create(path.as_ref())?);
+        export::write_stats(&rows, format, file)?;
+        info!("Exported stats to {}", path.as_ref().display());
+        Ok(())
+    }
+
+    /// Writes event counts per watchset to `path` as JSON or CSV.
+    pub async fn export_watchset_stats<P: AsRef<Path>>(
+        &self,
+        format: ExportFormat,
+        path: P,
+    ) -> Result<()> {
+        let rows = export::watchset_stats_rows(
+            &self.get_watchsets().await,
+            &*self.watch_stats.lock().await,
+        );
+        let file = std::io::BufWriter::new(File::create(path.as_ref())?);
+        export::write_watchset_stats(&rows, format, file)?;
+        info!("Exported watchset stats to {}", path.as_ref().display());
+        Ok(())
+    }
+
+    /// Adds a glob filter; a filter with the same pattern is replaced.
+    pub async fn add_filter(&self, kind: FilterKind, pattern: &str) -> Result<()> {
+        self.filters.lock().await.add(kind, pattern)?;
+        info!("Filter added: {} {}", kind, pattern);
+        Ok(())
+    }
+
+    pub async fn remove_filter(&self, pattern: &str) -> Result<()> {
+        if self.filters.lock().await.remove(pattern) {
+            info!("Filter removed: {}", pattern);
+            Ok(())
+        } else {
+            Err(MonitorError::NotFound {
+                kind: "filter with pattern",
+                name: pattern.to_string(),
+            })
+        }
+    }
+
+    /// Registers a webhook for the given event kinds (all kinds when empty). A webhook with
+    /// the same URL is replaced.
+    pub async fn add_webhook(&self, url: &str, events: Vec<String>) -> Result<()> {
+        let (rate_limit, breaker) = self.webhook_limits;
+        let webhook = Webhook::new(url, events)?.with_limits(rate_limit, breaker);
+        let mut webhooks = self.webhooks.lock().await;
+        webhooks.retain(|existing| existing.url != url);
+        webhooks.push(webhook);
+        info!("Webhook added: {}", url);
+        Ok(())
+    }
+
+    pub async fn remove_webhook(&self, url: &str) -> Result<()> {
+        let mut webhooks = self.webhooks.lock().await;
+        let before = webhooks.len();
+        webhooks.retain(|existing| existing.url != url);
+        if webhooks.len() == before {
+            return Err(MonitorError::NotFound {
+                kind: "webhook with URL",
+                name: url.to_string(),
+            });
+        }
+        info!("Webhook removed: {}", url);
+        Ok(())
+    }
+
+    pub async fn get_webhooks(&self) -> Vec<Webhook> {
+        self.webhooks.lock().await.clone()
+    }
+
+    pub async fn get_filters(&self) -> Vec<(FilterKind, String)> {
+        self.filters.lock().await.list()
+    }
+
+    /// Watches an additional path alongside the primary one.
+    pub async fn add_watch<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let current_path = self.current_path.lock().await.clone();
+        let mut extra_watches = self.extra_watches.lock().await;
+        if current_path == path || extra_watches.contains(&path) {
+            return Err(MonitorError::AlreadyWatching(path));
+        }
+
+        self.watch_path(&path).await?;
+        extra_watches.push(path.clone());
+        info!("Watch added: {}", path.display());
+        Ok(())
+    }
+
+    /// Stops watching a path previously added with [`FileMonitor::add_watch`].
+    pub async fn remove_watch<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let mut extra_watches = self.extra_watches.lock().await;
+        let Some(index) = extra_watches.iter().position(|watch| *watch == path) else {
+            return Err(MonitorError::NotWatching(path));
+        };
+
+        let mut lost_watches = self.lost_watches.lock().await;
+        let was_lost = lost_watches.contains(&path);
+        if let Some(watcher) = self.watcher.lock().await.as_mut() {
+            // The backend already dropped the watch of a deleted path.
+            if let Err(e) = watcher.unwatch(&path) {
+                if !was_lost {
+                    return Err(e);
+                }
+            }
+        }
+        lost_watches.retain(|lost| *lost != path);
+        drop(lost_watches);
+        self.scans.lock().unwrap().retain(|scan| {
+            if scan.watch() == path {
+                scan.cancel();
+            }
+            scan.watch() != path
+        });
+        extra_watches.remove(index);
+        self.watch_stats.lock().await.remove(&path);
+        for watchset in self.watchsets.lock().await.values_mut() {
+            watchset.paths.retain(|watch| *watch != path);
+        }
+        info!("Watch removed: {}", path.display());
+        Ok(())
+    }
+
+    /// Attaches the lines appended to `path`, or to files below it, to their
+    /// modifications, see [`FileEventRecord::appended_lines`].
+    pub async fn tail<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let mut tailed = self.tailed.lock().await;
+        if tailed.contains(&path) {
+            return Err(monitor_error!("Already tailing {}", path.display()));
+        }
+        tailed.push(path.clone());
+        info!("Tailing {}", path.display());
+        Ok(())
+    }
+
+    /// Stops attaching appended lines for `path`, which must have been passed to
+    /// [`FileMonitor::tail`].
+    pub async fn untail<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = absolute_path(path.as_ref())?;
+        let mut tailed = self.tailed.lock().await;
+        let Some(index) = tailed.iter().position(|tailed| *tailed == path) else {
+            return Err(monitor_error!("Not tailing {}", path.display()));
+        };
+        tailed.remove(index);
+        info!("Stopped tailing {}", path.display());
+        Ok(())
+    }
+
+    pub async fn get_tailed(&self) -> Vec<PathBuf> {
+        self.tailed.lock().await.clone()
+    }
+
+    /// All watched paths, primary first.
+    pub async fn get_watches(&self) -> Vec<PathBuf> {
+        let mut watches = vec![self.current_path.lock().await.clone()];
+        watches.extend(self.extra_watches.lock().await.iter().cloned());
+        watches
+    }
+
+    /// What is watched for watched paths that are symlinks.
+    pub fn get_symlink_policy(&self) -> SymlinkPolicy {
+        self.symlink_policy
+    }
+
+    /// Watcher backend observing `watch`.
+    pub fn get_watch_backend<P: AsRef<Path>>(&self, watch: P) -> WatchBackend {
+        self.watch_backends
+            .get(watch.as_ref())
+            .copied()
+            .unwrap_or(self.default_backend)
+    }
+
+    pub async fn get_watch_stats<P: AsRef<Path>>(&self, watch: P) -> HashMap<FileEvent, usize> {
+        self.watch_stats
+            .lock()
+            .await
+            .get(watch.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub async fn get_watch_history<P: AsRef<Path>>(&self, watch: P) -> EventHistory {
+        self.event_history
+            .lock()
+            .await
+            .iter()
+            .filter(|record| record.watch == watch.as_ref())
+            .cloned()
+            .collect()
+    }
+
+    /// Adds `paths` to the watchset `name`, creating it if needed. Paths not watched yet
+    /// are watched; a path can only be in one watchset.
+    pub async fn add_to_watchset<P: AsRef<Path>>(&self, name: &str, paths: &[P]) -> Result<()> {
+        let mut watchset = match self.watchsets.lock().await.get(name) {
+            Some(watchset) => watchset.clone(),
+            None => Watchset::new(name)?,
+        };
+        for path in paths {
+            let path = absolute_path(path.as_ref())?;
+            if let Some(other) = self
+                .watchsets
+                .lock()
+                .await
+                .values()
+                .find(|other| other.contains(&path))
+            {
+                return Err(monitor_error!(
+                    "{} is already in watchset {}",
+                    path.display(),
+                    other.name
+                ));
+            }
+            if !self.get_watches().await.contains(&path) {
+                self.add_watch(&path).await?;
+            }
+            watchset.paths.push(path);
+            // Saved after every path, so the ones already watched stay in the set when a
+            // later one fails.
+            self.watchsets
+                .lock()
+                .await
+                .insert(name.to_string(), watchset.clone());
+        }
+        info!("Watchset {}: {} paths", name, watchset.paths.len());
+        Ok(())
+    }
+
+    /// Removes the watchset `name` and stops watching its paths, except the primary one.
+    pub async fn remove_watchset(&self, name: &str) -> Result<()> {
+        let watchset = self
+            .watchsets
+            .lock()
+            .await
+            .remove(name)
+            .ok_or_else(|| no_watchset(name))?;
+        let primary = self.current_path.lock().await.clone();
+        for path in watchset.paths.iter().filter(|path| **path != primary) {
+            self.remove_watch(path).await?;
+        }
+        info!("Watchset removed: {}", name);
+        Ok(())
+    }
+
+    /// Drops events under the paths of watchset `name` until it is resumed. Unlike
+    /// [`FileMonitor::pause`], the rest of the monitor keeps recording.
+    pub async fn pause_watchset(&self, name: &str) -> Result<()> {
+        self.set_watchset_paused(name, true).await?;
+        info!("Watchset paused: {}", name);
+        Ok(())
+    }
+
+    pub async fn resume_watchset(&self, name: &str) -> Result<()> {
+        self.set_watchset_paused(name, false).await?;
+        info!("Watchset resumed: {}", name);
+        Ok(())
+    }
+
+    async fn set_watchset_paused(&self, name: &str, paused: bool) -> Result<()> {
+        let mut watchsets = self.watchsets.lock().await;
+        let watchset = watchsets.get_mut(name).ok_or_else(|| no_watchset(name))?;
+        watchset.paused = paused;
+        Ok(())
+    }
+
+    /// Adds a glob filter applying only to the paths of watchset `name`; a filter with the
+    /// same pattern is replaced.
+    pub async fn add_watchset_filter(
+        &self,
+        name: &str,
+        kind: FilterKind,
+        pattern: &str,
+    ) -> Result<()> {
+        let mut watchsets = self.watchsets.lock().await;
+        let watchset = watchsets.get_mut(name).ok_or_else(|| no_watchset(name))?;
+        watchset.filters.add(kind, pattern)?;
+        info!("Watchset {} filter added: {} {}", name, kind, pattern);
+        Ok(())
+    }
+
+    /// All watchsets, by name.
+    pub async fn get_watchsets(&self) -> Vec<Watchset> {
+        self.watchsets.lock().await.values().cloned().collect()
+    }
+
+    /// Event counts of all paths of watchset `name` together.
+    pub async fn get_watchset_stats(&self, name: &str) -> Result<HashMap<FileEvent, usize>> {
+        let watchsets = self.watchsets.lock().await;
+        let watchset = watchsets.get(name).ok_or_else(|| no_watchset(name))?;
+        Ok(watchset.stats(&*self.watch_stats.lock().await))
+    }
+
+    pub async fn add_path_substitution<P: AsRef<Path>>(
+        &self,
+        original_path: P,
+        substitute_path: P,
+    ) -> Result<()> {
+        let mut substitutions = self.path_substitutions.lock().await;
+        substitutions.insert(
+            original_path.as_ref().to_path_buf(),
+            substitute_path.as_ref().to_path_buf(),
+        );
+        info!(
+            "Path substitution added: {} -> {}",
+            original_path.as_ref().display(),
+            substitute_path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    pub async fn remove_path_substitution<P: AsRef<Path>>(&self, original_path: P) -> Result<()> {
+        let mut substitutions = self.path_substitutions.lock().await;
+        if substitutions.remove(original_path.as_ref()).is_some() {
+            info!(
+                "Path substitution removed for: {}",
+                original_path.as_ref().display()
+            );
+        } else {
+            info!(
+                "No path substitution found for: {}",
+                original_path.as_ref().display()
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn get_substituted_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let substitutions = self.path_substitutions.lock().await;
+        substitutions
+            .get(path.as_ref())
+            .cloned()
+            .unwrap_or_else(|| path.as_ref().to_path_buf())
+    }
+
+    pub async fn open_file<P: AsRef<Path>>(&self, path: P) -> IoResult<File> {
+        let substituted_path = self.get_substituted_path(path).await;
+
+        #[cfg(target_os = "windows")]
+        {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .attributes(WindowsOpenOptionsExt::FILE_ATTRIBUTE_HIDDEN)
+                .open(&substituted_path)
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .mode(0o600)
+                .open(&substituted_path)
+        }
+
+        #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+        {
+            File::open(&substituted_path)
+        }
+    }
+}
+
+fn no_watchset(name: &str) -> MonitorError {
+    MonitorError::NotFound {
+        kind: "watchset named",
+        name: name.to_string(),
+    }
+}
+
+pub(crate) fn absolute_path(path: &Path) -> IoResult<PathBuf> {
+    if path.is_relative() {
+        Ok(std::env::current_dir()?.join(path))
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// `path` with `.` components dropped and `..` applied to the component before it,
+/// without touching the file system, so symlinks are kept.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() && !normalized.has_root() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Signals `tx` whenever one of `files` changes.
+pub(crate) fn watch_files(
+    files: Vec<PathBuf>,
+    tx: tokio::sync::mpsc::Sender<()>,
+) -> Result<notify::RecommendedWatcher> {
+    let watched_files = files.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if event.paths.iter().any(|path| watched_files.contains(path)) => {
+            let _ = tx.try_send(());
+        }
+        Ok(_) => {}
+        Err(e) => error!("Config watch error: {:?}", e),
+    })?;
+    for file in &files {
+        // Watching the directory also catches editors that replace the file on save.
+        let dir = file.parent().unwrap_or(file);
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
+
+/// Clears [`FileMonitor::running`] however `monitor` returns.
+struct RunningGuard<'a>(&'a tokio::sync::watch::Sender<bool>);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(false);
+    }
+}
+
+/// Where the watcher callback delivers events and problems.
+#[derive(Clone)]
+struct WatcherSenders {
+    events: tokio::sync::mpsc::Sender<ReceivedEvent>,
+    priority_events: tokio::sync::mpsc::Sender<ReceivedEvent>,
+    errors: tokio::sync::mpsc::Sender<()>,
+    /// Asks the monitor to rebuild its watcher, after the callback panicked.
+    rebuild: tokio::sync::mpsc::Sender<()>,
+    /// Tells the monitor that events were dropped because their lane was full.
+    overflow: tokio::sync::mpsc::Sender<()>,
+    /// Tells the monitor that events were spilled to disk.
+    spilled: tokio::sync::mpsc::Sender<()>,
+}
+
+/// The most specific watched path containing `event_path`, falling back to the primary one.
+fn watch_root(primary: &Path, extra_watches: &[PathBuf], event_path: &Path) -> PathBuf {
+    std::iter::once(primary)
+        .chain(extra_watches.iter().map(PathBuf::as_path))
+        .filter(|watch| event_path.starts_with(watch))
+        .max_by_key(|watch| watch.components().count())
+        .unwrap_or(primary)
+        .to_path_buf()
+}
+
+fn is_priority_event(priority_paths: &[PathBuf], event: &Event) -> bool {
+    event.paths.iter().any(|path| {
+        priority_paths
+            .iter()
+            .any(|priority_path| path.starts_with(priority_path))
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn resolve_anchor_path(anchor: &File) -> Option<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+
+    let resolved = std::fs::read_link(format!("/proc/self/fd/{}", anchor.as_raw_fd())).ok()?;
+    // The kernel marks unlinked files instead of failing the readlink.
+    if resolved.to_string_lossy().ends_with(" (deleted)") {
+        None
+    } else {
+        Some(resolved)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resolve_anchor_path(_anchor: &File) -> Option<PathBuf> {
+    None
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs::File;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tempfile::tempdir;
     use tokio::runtime::Runtime;
 
     #[test]
-    fn test_new_file_monitor() {
+    fn test_new_file_monitor() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            assert!(monitor.current_path.lock().await.exists());
+        });
+    }
+
+    #[test]
+    fn test_add_path_substitution() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor
+                .add_path_substitution("/original/path", "/substituted/path")
+                .await
+                .unwrap();
+            let substitutions = monitor.path_substitutions.lock().await;
+            assert_eq!(
+                substitutions.get(Path::new("/original/path")),
+                Some(&PathBuf::from("/substituted/path"))
+            );
+        });
+    }
+
+    #[test]
+    fn test_get_substituted_path() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor
+                .add_path_substitution("/original/path", "/substituted/path")
+                .await
+                .unwrap();
+            let substituted = monitor.get_substituted_path("/original/path").await;
+            assert_eq!(substituted, PathBuf::from("/substituted/path"));
+        });
+    }
+
+    #[test]
+    fn test_open_file() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let file_path = temp_dir.path().join("test_file.txt");
+            File::create(&file_path).unwrap();
+
+            let opened_file = monitor.open_file(&file_path).await;
+            assert!(opened_file.is_ok());
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_chase_move_out_of_scope() {
+        let watched_dir = tempdir().unwrap();
+        let other_dir = tempdir().unwrap();
+        let file_path = watched_dir.path().join("watched.txt");
+        File::create(&file_path).unwrap();
+        let monitor = FileMonitor::new(&file_path);
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor.set_follow_moves(true).await.unwrap();
+            let moved_path = other_dir.path().join("moved.txt");
+            std::fs::rename(&file_path, &moved_path).unwrap();
+
+            let destination = monitor.chase_move().await.unwrap();
+            assert_eq!(destination, Some(moved_path.clone()));
+            assert_eq!(*monitor.current_path.lock().await, moved_path);
+
+            let lineage = monitor.get_path_lineage().await;
+            assert_eq!(lineage.len(), 1);
+            assert_eq!(lineage[0].from, file_path);
+            assert_eq!(lineage[0].to, moved_path);
+        });
+    }
+
+    #[test]
+    fn test_builder_priority_paths() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .channel_capacity(10)
+            .priority_channel_capacity(5)
+            .priority_path(temp_dir.path().join("shadow"))
+            .build();
+
+        assert_eq!(monitor.channel_capacity, 10);
+        assert_eq!(monitor.priority_channel_capacity, 5);
+
+        let priority_event = Event::new(EventKind::Any).add_path(temp_dir.path().join("shadow"));
+        let bulk_event = Event::new(EventKind::Any).add_path(temp_dir.path().join("bulk.log"));
+        assert!(is_priority_event(&monitor.priority_paths, &priority_event));
+        assert!(!is_priority_event(&monitor.priority_paths, &bulk_event));
+    }
+
+    #[test]
+    fn test_try_build_rejects_invalid_configuration() {
+        let temp_dir = tempdir().unwrap();
+        let limits = MemoryLimits {
+            queued_events: 8,
+            ..MemoryLimits::new(temp_dir.path().join("spill"))
+        };
+        let builder = || FileMonitor::builder(temp_dir.path());
+        let invalid =
+            |result: Result<FileMonitor>| matches!(result, Err(MonitorError::InvalidConfig(_)));
+
+        // An explicit priority lane must agree with the memory limits.
+        assert!(invalid(
+            builder()
+                .priority_channel_capacity(5)
+                .memory_limits(limits.clone())
+                .try_build()
+        ));
+        let monitor = builder()
+            .priority_channel_capacity(8)
+            .memory_limits(limits.clone())
+            .try_build()
+            .unwrap();
+        assert_eq!(monitor.priority_channel_capacity, 8);
+
+        let not_a_dir = temp_dir.path().join("file");
+        std::fs::write(&not_a_dir, "").unwrap();
+        assert!(invalid(
+            builder()
+                .memory_limits(MemoryLimits::new(not_a_dir.join("spill")))
+                .try_build()
+        ));
+
+        let store = temp_dir.path().join("alerts.json");
+        std::fs::write(&store, "not json").unwrap();
+        assert!(invalid(
+            builder()
+                .escalation(EscalationPolicy::new(&store))
+                .try_build()
+        ));
+
+        let rule =
+            RateAlertRule::new("mass-delete", "deleted", 10, Duration::from_secs(5)).unwrap();
+        assert!(invalid(
+            builder()
+                .rate_alert(rule.clone())
+                .rate_alert(rule.clone())
+                .try_build()
+        ));
+        assert!(invalid(
+            builder()
+                .rate_alert(RateAlertRule {
+                    window_secs: 0,
+                    ..rule
+                })
+                .try_build()
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_recursive_watch_respects_max_depth() {
+        let temp_dir = tempdir().unwrap();
+        let nested = temp_dir.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .watch_mode(WatchMode::Recursive { max_depth: Some(2) })
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let monitor_clone = Arc::clone(&monitor);
+            let handle = tokio::spawn(async move { monitor_clone.monitor().await });
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+            let shallow = temp_dir.path().join("a").join("shallow.txt");
+            let deep = nested.join("deep.txt");
+            File::create(&shallow).unwrap();
+            File::create(&deep).unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            handle.abort();
+
+            let history = monitor.get_history().await;
+            assert!(history
+                .iter()
+                .any(|record| record.path == shallow && record.event == FileEvent::Created));
+            assert!(history.iter().all(|record| record.path != deep));
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_git_context_and_status_rule() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let repo = tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(repo.path())
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(repo.path().join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(repo.path().join("tracked.txt"), "hello").unwrap();
+        git(&["add", "."]);
+        git(&[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "init",
+        ]);
+
+        let script = repo.path().join("dropper.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(repo.path().join("build.log"), "").unwrap();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let tracked = GitContext::for_path(&repo.path().join("tracked.txt"))
+                .await
+                .unwrap();
+            assert_eq!(tracked.file_status, GitFileStatus::Tracked);
+            assert_eq!(tracked.branch.as_deref(), Some("main"));
+            assert!(tracked.dirty);
+
+            let ignored = GitContext::for_path(&repo.path().join("build.log"))
+                .await
+                .unwrap();
+            assert_eq!(ignored.file_status, GitFileStatus::Ignored);
+
+            let untracked = GitContext::for_path(&script).await.unwrap();
+            assert_eq!(untracked.file_status, GitFileStatus::Untracked);
+
+            let rule = GitStatusRule::new(
+                "untracked-executable",
+                GitFileStatus::Untracked,
+                Verdict::Alert("untracked executable".to_string()),
+            )
+            .on_event(FileEvent::Created)
+            .executable_only();
+            let mut record = FileEventRecord {
+                id: 0,
+                time: Local::now(),
+                occurred_at: None,
+                metadata: None,
+                size_change: None,
+                appended_lines: None,
+                enrichment: BTreeMap::new(),
+                process: None,
+                via_symlink: None,
+                watchset: None,
+                watch: repo.path().to_path_buf(),
+                path: script.clone(),
+                event: FileEvent::Created,
+                git: Some(untracked),
+                container: None,
+                maintenance: None,
+                content: None,
+            };
+            assert_eq!(
+                rule.evaluate(&record),
+                Verdict::Alert("untracked executable".to_string())
+            );
+            record.event = FileEvent::Modified;
+            assert_eq!(rule.evaluate(&record), Verdict::Pass);
+            record.git = None;
+            record.event = FileEvent::Created;
+            assert_eq!(rule.evaluate(&record), Verdict::Pass);
+
+            let outside = tempdir().unwrap();
+            assert!(GitContext::for_path(outside.path()).await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_multiple_watches_track_stats_per_path() {
+        let primary = tempdir().unwrap();
+        let extra = tempdir().unwrap();
+        let monitor = FileMonitor::new(primary.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor.add_watch(extra.path()).await.unwrap();
+            assert!(monitor.add_watch(extra.path()).await.is_err());
+            assert_eq!(
+                monitor.get_watches().await,
+                vec![primary.path().to_path_buf(), extra.path().to_path_buf()]
+            );
+
+            monitor
+                .handle_event(primary.path().join("a.txt"), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(extra.path().join("b.txt"), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(extra.path().join("b.txt"), FileEvent::Modified)
+                .await
+                .unwrap();
+
+            let extra_stats = monitor.get_watch_stats(extra.path()).await;
+            assert_eq!(extra_stats.get(&FileEvent::Modified), Some(&2));
+            assert_eq!(extra_stats.get(&FileEvent::Created), None);
+            assert_eq!(
+                monitor
+                    .get_watch_stats(primary.path())
+                    .await
+                    .get(&FileEvent::Created),
+                Some(&1)
+            );
+            assert_eq!(monitor.get_watch_history(extra.path()).await.len(), 2);
+            assert_eq!(monitor.get_history().await.len(), 3);
+
+            monitor.remove_watch(extra.path()).await.unwrap();
+            assert!(monitor.remove_watch(extra.path()).await.is_err());
+            assert_eq!(monitor.get_watches().await.len(), 1);
+            assert!(monitor.get_watch_stats(extra.path()).await.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_container_resolver_maps_overlay_and_bind_paths() {
+        let proc_root = tempdir().unwrap();
+        let id = "3f4e1c2a".repeat(8);
+        let pid_dir = proc_root.path().join("4242");
+        std::fs::create_dir_all(&pid_dir).unwrap();
+        std::fs::create_dir_all(proc_root.path().join("self")).unwrap();
+        std::fs::write(
+            pid_dir.join("cgroup"),
+            format!("0::/system.slice/docker-{}.scope\n", id),
+        )
+        .unwrap();
+        std::fs::write(
+            pid_dir.join("mountinfo"),
+            "900 800 0:52 / / rw,relatime - overlay overlay rw,lowerdir=/var/lib/docker/overlay2/l/A,upperdir=/var/lib/docker/overlay2/abc/diff,workdir=/var/lib/docker/overlay2/abc/work\n\
+             901 900 8:1 /srv/app-data /data rw,relatime - ext4 /dev/sda1 rw\n\
+             902 900 0:60 / /proc rw - proc proc rw\n",
+        )
+        .unwrap();
+        std::fs::write(
+            proc_root.path().join("self").join("mountinfo"),
+            "22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(proc_root.path().join("77")).unwrap();
+        std::fs::write(
+            proc_root.path().join("77").join("cgroup"),
+            "0::/user.slice\n",
+        )
+        .unwrap();
+
+        let mut resolver = ContainerResolver::new()
+            .with_proc_root(proc_root.path())
+            .with_docker_socket(proc_root.path().join("missing.sock"));
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let upper = resolver
+                .resolve(Path::new("/var/lib/docker/overlay2/abc/diff/etc/passwd"))
+                .await
+                .unwrap();
+            assert_eq!(upper.id, id);
+            assert_eq!(upper.runtime, "docker");
+            assert_eq!(upper.name, None);
+
+            assert!(resolver
+                .lookup(Path::new("/var/lib/docker/overlay2/abc/merged/etc/passwd"))
+                .is_some());
+            assert!(resolver
+                .lookup(Path::new("/srv/app-data/uploads/x.bin"))
+                .is_some());
+            assert!(resolver
+                .lookup(&proc_root.path().join("4242/root/tmp/x"))
+                .is_some());
+            assert!(resolver.lookup(Path::new("/home/user/file")).is_none());
+        });
+    }
+
+    #[test]
+    fn test_parse_container_id_layouts() {
+        let id = "0123456789abcdef".repeat(4);
+        assert_eq!(
+            container::parse_container_id(&format!("12:pids:/docker/{}\n", id)),
+            Some(("docker".to_string(), id.clone()))
+        );
+        assert_eq!(
+            container::parse_container_id(&format!(
+                "0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-{}.scope\n",
+                id
+            )),
+            Some(("containerd".to_string(), id.clone()))
+        );
+        assert_eq!(
+            container::parse_container_id("0::/user.slice/user-1000.slice\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_glob_filters_drop_events() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor
+                .add_filter(FilterKind::Exclude, "*.tmp")
+                .await
+                .unwrap();
+            monitor
+                .add_filter(FilterKind::Exclude, "**/target/**")
+                .await
+                .unwrap();
+
+            let root = temp_dir.path();
+            for path in [
+                root.join("scratch.tmp"),
+                root.join("target").join("debug").join("app"),
+                root.join("src").join("main.rs"),
+            ] {
+                monitor
+                    .handle_event(path, FileEvent::Modified)
+                    .await
+                    .unwrap();
+            }
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].path, root.join("src").join("main.rs"));
+
+            monitor
+                .add_filter(FilterKind::Include, "src/*.rs")
+                .await
+                .unwrap();
+            monitor
+                .handle_event(root.join("README.md"), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(root.join("src").join("lib.rs"), FileEvent::Modified)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await.len(), 2);
+            assert_eq!(
+                monitor.get_stats().await.get(&FileEvent::Modified),
+                Some(&2)
+            );
+
+            assert_eq!(monitor.get_filters().await.len(), 3);
+            monitor.remove_filter("*.tmp").await.unwrap();
+            assert!(monitor.remove_filter("*.tmp").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_saved_history_supports_offline_queries() {
+        use query::{aggregate, diff, read_history_file, GroupBy, HistoryQuery};
+
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+        let root = temp_dir.path();
+
+        rt.block_on(async {
+            for (name, event) in [
+                ("a.txt", FileEvent::Modified),
+                ("a.txt", FileEvent::Modified),
+                ("b.txt", FileEvent::Created),
+            ] {
+                monitor.handle_event(root.join(name), event).await.unwrap();
+            }
+            monitor
+                .save_history(root.join("before.jsonl"))
+                .await
+                .unwrap();
+            monitor
+                .handle_event(root.join("b.txt"), FileEvent::Deleted)
+                .await
+                .unwrap();
+            monitor
+                .save_history(root.join("after.jsonl"))
+                .await
+                .unwrap();
+        });
+
+        let before = read_history_file(root.join("before.jsonl")).unwrap();
+        let after = read_history_file(root.join("after.jsonl")).unwrap();
+        assert_eq!(before.len(), 3);
+        assert_eq!(before, after[..3]);
+
+        let modified = HistoryQuery {
+            event: Some("Modified".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(modified.apply(&after).len(), 2);
+        assert_eq!(
+            aggregate(&after, GroupBy::Path)[0],
+            (root.join("a.txt").display().to_string(), 2)
+        );
+        assert_eq!(
+            aggregate(&after, GroupBy::Event),
+            vec![
+                ("modified".to_string(), 2),
+                ("created".to_string(), 1),
+                ("deleted".to_string(), 1)
+            ]
+        );
+
+        let changes = diff(&before, &after, GroupBy::Event);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].key, "deleted");
+        assert_eq!((changes[0].left, changes[0].right), (0, 1));
+    }
+
+    #[test]
+    fn test_subscribers_receive_recorded_events_and_alerts() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .rule(IgnoreDeletes)
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let mut first = monitor.subscribe();
+            let mut second = monitor.subscribe();
+            let path = temp_dir.path().join("new.txt");
+            monitor
+                .handle_event(path.clone(), FileEvent::Deleted)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+
+            match first.recv().await {
+                Some(MonitorEvent::File(record)) => {
+                    assert_eq!(record.path, path);
+                    assert_eq!(record.event, FileEvent::Created);
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+            match first.recv().await {
+                Some(MonitorEvent::Alert { rule, reason, .. }) => {
+                    assert_eq!(rule, "ignore-deletes");
+                    assert_eq!(reason, "file appeared");
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+            assert!(first.try_recv().is_none());
+            assert!(matches!(second.try_recv(), Some(MonitorEvent::File(_))));
+        });
+    }
+
+    #[test]
+    fn test_event_hooks_run_for_recorded_events() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .rule(IgnoreDeletes)
+            .build();
+        let rt = Runtime::new().unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut calls = 0;
+
+        rt.block_on(async {
+            let hook_seen = seen.clone();
+            monitor
+                .on_event(move |record| hook_seen.lock().unwrap().push(record.event.clone()))
+                .await;
+            monitor
+                .on_event(move |_| {
+                    calls += 1;
+                    assert!(calls <= 2);
+                })
+                .await;
+
+            let path = temp_dir.path().join("file.txt");
+            for event in [FileEvent::Created, FileEvent::Deleted, FileEvent::Modified] {
+                monitor.handle_event(path.clone(), event).await.unwrap();
+            }
+        });
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![FileEvent::Created, FileEvent::Modified]
+        );
+    }
+
+    async fn control_request(
+        addr: std::net::SocketAddr,
+        request: &str,
+    ) -> (String, serde_json::Value) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (
+            head.lines().next().unwrap().to_string(),
+            serde_json::from_str(body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_control_server_requires_token_and_applies_commands() {
+        let temp_dir = tempdir().unwrap();
+        let extra = tempdir().unwrap();
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ControlServer::new(Arc::clone(&monitor), "s3cret").unwrap();
+            tokio::spawn(server.serve(listener));
+
+            let (status, _) =
+                control_request(addr, "POST /pause HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n")
+                    .await;
+            assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+            assert!(!monitor.is_paused().await);
+
+            let (status, body) =
+                control_request(addr, "POST /pause HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n")
+                    .await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert_eq!(body["paused"], true);
+            assert!(monitor.is_paused().await);
+
+            let watch = format!(r#"{{"path": {:?}}}"#, extra.path());
+            let (status, _) = control_request(
+                addr,
+                &format!(
+                    "POST /watches HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{}",
+                    watch.len(),
+                    watch
+                ),
+            )
+            .await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+
+            let (_, body) =
+                control_request(addr, "GET /status HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n")
+                    .await;
+            assert_eq!(body["paused"], true);
+            assert_eq!(body["watches"].as_array().unwrap().len(), 2);
+
+            let (status, _) =
+                control_request(addr, "GET /pause HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n")
+                    .await;
+            assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+
+            monitor.resume().await.unwrap();
+            monitor
+                .handle_event(temp_dir.path().join("a.txt"), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(temp_dir.path().join("a.txt"), FileEvent::Modified)
+                .await
+                .unwrap();
+            let (_, body) =
+                control_request(addr, "GET /stats HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n")
+                    .await;
+            assert_eq!(body["events"]["created"], 1);
+            assert_eq!(body["events"]["modified"], 1);
+
+            let (_, body) = control_request(
+                addr,
+                "GET /history?limit=1 HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+            )
+            .await;
+            let history = body.as_array().unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0]["event"], "Modified");
+
+            // Query values are percent-decoded.
+            let (_, body) = control_request(
+                addr,
+                "GET /history?kind=cr%65ated HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+            )
+            .await;
+            let history = body.as_array().unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0]["event"], "Created");
+
+            // Oversized headers are refused before the key is checked.
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let padding = "x".repeat(32 * 1024);
+            let request = format!("GET /status HTTP/1.1\r\nX-Padding: {}\r\n\r\n", padding);
+            let _ = tokio::io::AsyncWriteExt::write_all(&mut stream, request.as_bytes()).await;
+            let mut response = String::new();
+            let _ = tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response).await;
+            assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+            let new_path = format!(r#"{{"path": {:?}}}"#, extra.path());
+            let (status, _) = control_request(
+                addr,
+                &format!(
+                    "POST /path HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{}",
+                    new_path.len(),
+                    new_path
+                ),
+            )
+            .await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+        });
+    }
+
+    #[test]
+    fn test_control_server_streams_events_over_websocket() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+        async fn open_event_stream(
+            addr: std::net::SocketAddr,
+        ) -> (String, tokio::io::BufReader<tokio::net::TcpStream>) {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let mut stream = tokio::io::BufReader::new(stream);
+            stream
+                .write_all(
+                    b"GET /events?token=s3cret HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            (head, stream)
+        }
+
+        let temp_dir = tempdir().unwrap();
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ControlServer::new(Arc::clone(&monitor), "s3cret").unwrap();
+            tokio::spawn(server.serve(listener));
+
+            let (head, mut stream) = open_event_stream(addr).await;
+            assert!(head.starts_with("HTTP/1.1 101 Switching Protocols"));
+            // Accept key from the RFC 6455 example handshake.
+            assert!(head.lines().any(|line| line.split_once(": ").is_some_and(
+                |(name, value)| name.eq_ignore_ascii_case("sec-websocket-accept")
+                    && value == "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+            )));
+
+            let path = temp_dir.path().join("live.txt");
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x81);
+            let len = match header[1] & 0x7F {
+                126 => stream.read_u16().await.unwrap() as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0u8; len];
+            stream.read_exact(&mut payload).await.unwrap();
+            let record: FileEventRecord = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(record.path, path);
+            assert_eq!(record.event, FileEvent::Created);
+
+            // Masked close frame with an empty payload.
+            stream.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0x88, 0x00]);
+
+            // A frame announcing more than the message limit ends the stream before its
+            // payload is read.
+            let (_, mut stream) = open_event_stream(addr).await;
+            let mut frame = vec![0x81, 0xFF];
+            frame.extend_from_slice(&(1u64 << 20).to_be_bytes());
+            frame.extend_from_slice(&[1, 2, 3, 4]);
+            stream.write_all(&frame).await.unwrap();
+            let mut rest = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+                .await
+                .unwrap()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_maintenance_window_tags_events_and_downgrades_alerts() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .rule(IgnoreDeletes)
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let mut subscription = monitor.subscribe();
+            let path = temp_dir.path().join("app.conf");
+
+            monitor.start_maintenance("deploy-42", true).await.unwrap();
+            assert!(monitor.start_maintenance("other", false).await.is_err());
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+            let window = monitor.stop_maintenance().await.unwrap();
+            assert_eq!(window.label, "deploy-42");
+            assert!(window.end.is_some());
+            assert!(monitor.stop_maintenance().await.is_err());
+
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 2);
+            assert_eq!(history[0].maintenance.as_deref(), Some("deploy-42"));
+            assert_eq!(history[1].maintenance, None);
+
+            // Only the event outside the window raises an alert.
+            let mut alerts = 0;
+            while let Some(event) = subscription.try_recv() {
+                if let MonitorEvent::Alert { record, .. } = event {
+                    assert_eq!(record.maintenance, None);
+                    alerts += 1;
+                }
+            }
+            assert_eq!(alerts, 1);
+            assert_eq!(monitor.get_maintenance_windows().await.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_event_line_is_single_line_json() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let path = temp_dir.path().join("old.txt");
+            let target = temp_dir.path().join("new.txt");
+            monitor
+                .add_path_substitution(&path, &temp_dir.path().join("shown.txt"))
+                .await
+                .unwrap();
+            monitor
+                .handle_event(
+                    path.clone(),
+                    FileEvent::Renamed {
+                        from: path.clone(),
+                        to: target.clone(),
+                    },
+                )
+                .await
+                .unwrap();
+            let record = monitor.get_history().await.pop().unwrap();
+            let substituted = monitor.get_substituted_path(&record.path).await;
+
+            let line = export::event_line(&record, &substituted);
+            assert!(!line.contains('\n'));
+            let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(value["event"], "renamed");
+            assert_eq!(value["path"], path.to_string_lossy().as_ref());
+            assert_eq!(
+                value["substituted_path"],
+                temp_dir.path().join("shown.txt").to_string_lossy().as_ref()
+            );
+            assert_eq!(value["renamed_to"], target.to_string_lossy().as_ref());
+            assert!(value["timestamp"].is_string());
+        });
+    }
+
+    #[test]
+    fn test_config_file_is_parsed_and_applied() {
+        let temp_dir = tempdir().unwrap();
+        let extra = tempdir().unwrap();
+        let config = MonitorConfig::parse(&format!(
+            r#"
+# Monitor settings
+path = {:?}
+watches = [
+    {:?}, # the extra tree
+]
+history_size = 2
+debounce_ms = 1_000
+log_level = 'warn'
+priority_paths = ["/etc/shadow"]
+priority_channel_capacity = 16
+
+[[substitutions]]
+original = "/srv/app/current"
+substitute = "/srv/app/releases/42"
+
+[[filters]]
+kind = "exclude"
+pattern = "**/*.swp"
+
+[[enrichers]]
+name = "cmdb"
+command = """
+/usr/bin/env \
+    true"""
+"#,
+            temp_dir.path(),
+            extra.path()
+        ))
+        .unwrap();
+        assert_eq!(config.path.as_deref(), Some(temp_dir.path()));
+        assert_eq!(config.log_level.as_deref(), Some("warn"));
+        assert_eq!(config.debounce_ms, Some(1000));
+        assert_eq!(config.substitutions.len(), 1);
+        assert_eq!(config.enrichers[0].command, "/usr/bin/env true");
+
+        let monitor = config
+            .apply(FileMonitor::builder(config.path.clone().unwrap()))
+            .unwrap()
+            .build();
+        assert_eq!(monitor.priority_paths, vec![PathBuf::from("/etc/shadow")]);
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            assert_eq!(monitor.get_watches().await.len(), 2);
+            assert_eq!(
+                monitor.get_substituted_path("/srv/app/current").await,
+                PathBuf::from("/srv/app/releases/42")
+            );
+
+            monitor
+                .handle_event(temp_dir.path().join("a.swp"), FileEvent::Modified)
+                .await
+                .unwrap();
+            for name in ["a.txt", "b.txt", "c.txt"] {
+                monitor
+                    .handle_event(temp_dir.path().join(name), FileEvent::Modified)
+                    .await
+                    .unwrap();
+            }
+            // Debounced: same path and event within the window.
+            monitor
+                .handle_event(temp_dir.path().join("c.txt"), FileEvent::Modified)
+                .await
+                .unwrap();
+            let history = monitor.get_history().await;
+            let paths: Vec<_> = history.iter().map(|record| record.path.clone()).collect();
+            assert_eq!(
+                paths,
+                vec![temp_dir.path().join("b.txt"), temp_dir.path().join("c.txt")]
+            );
+            assert_eq!(monitor.get_stats().await[&FileEvent::Modified], 3);
+        });
+
+        let error = MonitorConfig::parse("history_size = 10\nunknown = true\n").unwrap_err();
+        assert!(error.to_string().contains("unknown"));
+        let error = MonitorConfig::parse("path = \"/srv\"\nwatches = [\"/a\"\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+    }
+
+    #[test]
+    fn test_export_history_and_stats() {
+        let temp_dir = tempdir().unwrap();
+        let out_dir = tempdir().unwrap();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let path = temp_dir.path().join("a,b.txt");
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+
+            let json_path = out_dir.path().join("history.json");
+            monitor
+                .export_history(ExportFormat::Json, &json_path)
+                .await
+                .unwrap();
+            let exported: EventHistory =
+                serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+            assert_eq!(exported, monitor.get_history().await);
+
+            let csv_path = out_dir.path().join("history.csv");
+            monitor
+                .export_history(ExportFormat::Csv, &csv_path)
+                .await
+                .unwrap();
+            let csv = std::fs::read_to_string(&csv_path).unwrap();
+            let lines: Vec<_> = csv.lines().collect();
+            assert_eq!(lines.len(), 4);
+            assert!(lines[0].starts_with("time,watch,path,event"));
+            assert!(lines[1].contains(&format!("\"{}\",created", path.display())));
+
+            let stats_path = out_dir.path().join("stats.csv");
+            monitor
+                .export_stats("CSV".parse().unwrap(), &stats_path)
+                .await
+                .unwrap();
+            let stats = std::fs::read_to_string(&stats_path).unwrap();
+            let watch = temp_dir.path().canonicalize().unwrap();
+            assert_eq!(
+                stats,
+                format!(
+                    "watch,event,count\n{0},created,1\n{0},modified,2\n",
+                    watch.display()
+                )
+            );
+            assert!("xml".parse::<ExportFormat>().is_err());
+        });
+    }
+
+    #[test]
+    fn test_policy_reload_requires_signed_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let config_dir = tempdir().unwrap();
+        let config_root = config_dir.path().canonicalize().unwrap();
+        let policy = config_root.join("policy.json");
+        let manifest = config_root.join("manifest.json");
+        let key = b"operator key".to_vec();
+        let sign = |key: &[u8]| {
+            ConfigManifest::create(&[&policy], key)
+                .unwrap()
+                .save(&manifest)
+                .unwrap()
+        };
+
+        std::fs::write(
+            &policy,
+            r#"{"filters": [{"kind": "exclude", "pattern": "*.tmp"}]}"#,
+        )
+        .unwrap();
+        sign(&key);
+
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .policy_file(&policy)
+            .config_manifest(&manifest, key.clone())
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let mut subscription = monitor.subscribe();
+            monitor.check_config().await;
+            let applied = vec![(FilterKind::Exclude, "*.tmp".to_string())];
+            assert_eq!(monitor.get_filters().await, applied);
+            assert!(subscription.try_recv().is_none());
+
+            // Changed without re-signing.
+            std::fs::write(
+                &policy,
+                r#"{"filters": [{"kind": "include", "pattern": "*.log"}]}"#,
+            )
+            .unwrap();
+            monitor.check_config().await;
+            match subscription.try_recv() {
+                Some(MonitorEvent::Critical { reason }) => {
+                    assert!(reason.contains("Unauthorized modification"), "{}", reason)
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+            assert_eq!(monitor.get_filters().await, applied);
+
+            // Re-signed with the wrong key.
+            sign(b"attacker key");
+            monitor.check_config().await;
+            assert!(matches!(
+                subscription.try_recv(),
+                Some(MonitorEvent::Critical { reason }) if reason.contains("invalid signature")
+            ));
+
+            sign(&key);
+            monitor.check_config().await;
+            assert_eq!(
+                monitor.get_filters().await,
+                vec![(FilterKind::Include, "*.log".to_string())]
+            );
+
+            // Signed but malformed.
+            std::fs::write(&policy, "{ not json").unwrap();
+            sign(&key);
+            monitor.check_config().await;
+            assert!(matches!(
+                subscription.try_recv(),
+                Some(MonitorEvent::Critical { reason }) if reason.contains("invalid")
+            ));
+            assert_eq!(monitor.get_filters().await.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_webhooks_filter_events_and_retry() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .webhook_retry(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: std::time::Duration::from_millis(10),
+            })
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            // Fails the first request, then accepts.
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (body_tx, mut body_rx) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                for status in ["500 Internal Server Error", "204 No Content"] {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    loop {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request);
+                        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                            let length: usize = head
+                                .lines()
+                                .find_map(|line| line.strip_prefix("Content-Length: "))
+                                .unwrap()
+                                .parse()
+                                .unwrap();
+                            if body.len() >= length {
+                                body_tx.send(body.to_string()).unwrap();
+                                break;
+                            }
+                        }
+                    }
+                    stream
+                        .write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes())
+                        .await
+                        .unwrap();
+                }
+            });
+
+            assert!(monitor
+                .add_webhook("https://example.com", vec![])
+                .await
+                .is_err());
+            monitor
+                .add_webhook(
+                    &format!("http://{}/hook", addr),
+                    vec!["Deleted".to_string()],
+                )
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_webhooks().await[0].events, vec!["deleted"]);
+
+            let path = temp_dir.path().join("gone.txt");
+            monitor
+                .handle_event(path.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path.clone(), FileEvent::Deleted)
+                .await
+                .unwrap();
+
+            let first: serde_json::Value =
+                serde_json::from_str(&body_rx.recv().await.unwrap()).unwrap();
+            let retried: serde_json::Value =
+                serde_json::from_str(&body_rx.recv().await.unwrap()).unwrap();
+            assert_eq!(first, retried);
+            assert_eq!(retried["event"], "deleted");
+            assert_eq!(retried["path"], path.to_string_lossy().as_ref());
+
+            monitor
+                .remove_webhook(&format!("http://{}/hook", addr))
+                .await
+                .unwrap();
+            assert!(monitor.get_webhooks().await.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_post_json_decodes_chunked_and_caps_responses() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (host_tx, mut host_rx) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                let oversized = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                    4 * 1024 * 1024
+                );
+                let responses = [
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                     7\r\n{\"ok\": \r\n5\r\ntrue}\r\n0\r\n\r\n"
+                        .to_string(),
+                    oversized,
+                ];
+                for response in responses {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let mut stream = tokio::io::BufReader::new(stream);
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap() > 2 {
+                        if let Some(host) = line.strip_prefix("Host: ") {
+                            host_tx.send(host.trim().to_string()).unwrap();
+                        }
+                        line.clear();
+                    }
+                    let stream = stream.get_mut();
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    // The oversized body is streamed until the client hangs up.
+                    let chunk = vec![b'x'; 64 * 1024];
+                    while response.contains("Content-Length")
+                        && stream.write_all(&chunk).await.is_ok()
+                    {}
+                }
+            });
+
+            let url = format!("http://{}/edr", addr);
+            let body = webhook::post_json(&url, b"{}").await.unwrap();
+            assert_eq!(body, r#"{"ok": true}"#);
+            assert_eq!(host_rx.recv().await.unwrap(), addr.to_string());
+            assert!(webhook::post_json(&url, b"{}").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_coverage_accounts_for_pauses_and_downtime() {
+        let temp_dir = tempdir().unwrap();
+        let coverage_file = temp_dir.path().join("coverage.json");
+        let start = Local::now() - chrono::Duration::hours(2);
+        std::fs::write(
+            &coverage_file,
+            format!(
+                r#"{{"first_start": "{}", "last_heartbeat": "{}", "gaps": []}}"#,
+                start.to_rfc3339(),
+                (start + chrono::Duration::hours(1)).to_rfc3339()
+            ),
+        )
+        .unwrap();
+
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .coverage_file(&coverage_file)
+            .build();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let report = monitor.get_coverage().await;
+            assert_eq!(report.gaps.len(), 1);
+            assert_eq!(report.gaps[0].kind, GapKind::ProcessDown);
+            assert!((report.coverage_percent - 50.0).abs() < 1.0);
+
+            monitor.pause().await.unwrap();
+            monitor.pause().await.unwrap();
+            let report = monitor.get_coverage().await;
+            assert_eq!(report.gaps.len(), 2);
+            assert_eq!(report.gaps[1].kind, GapKind::Paused);
+            assert!(report.gaps[1].end.is_none());
+            monitor.resume().await.unwrap();
+            assert!(monitor.get_coverage().await.gaps[1].end.is_some());
+        });
+
+        let persisted = std::fs::read_to_string(&coverage_file).unwrap();
+        assert!(persisted.contains("Paused"));
+        assert!(persisted.contains("ProcessDown"));
+    }
+
+    struct IgnoreDeletes;
+
+    impl EventRule for IgnoreDeletes {
+        fn name(&self) -> &str {
+            "ignore-deletes"
+        }
+
+        fn evaluate(&self, record: &FileEventRecord) -> Verdict {
+            match record.event {
+                FileEvent::Deleted => Verdict::Suppress,
+                FileEvent::Created => Verdict::Alert("file appeared".to_string()),
+                _ => Verdict::Pass,
+            }
+        }
+    }
+
+    #[test]
+    fn test_rules_suppress_events() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = FileMonitor::builder(temp_dir.path())
+            .rule(IgnoreDeletes)
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let path = temp_dir.path().to_path_buf();
+            monitor
+                .handle_event(path.clone(), FileEvent::Deleted)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(path, FileEvent::Modified)
+                .await
+                .unwrap();
+
+            let stats = monitor.get_stats().await;
+            assert_eq!(stats.get(&FileEvent::Deleted), None);
+            assert_eq!(stats.get(&FileEvent::Created), Some(&1));
+            assert_eq!(stats.get(&FileEvent::Modified), Some(&1));
+        });
+    }
+
+    #[test]
+    fn test_wasm_plugins_run_within_limits() {
+        let plugin_dir = tempdir().unwrap();
+        std::fs::write(
+            plugin_dir.path().join("alert.wat"),
+            r#"(module
+                (import "env" "reason" (func $reason (param i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "created by plugin")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "evaluate") (param $ptr i32) (param $len i32) (result i32)
+                    (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 123))
+                        (then (return (i32.const 0))))
+                    (call $reason (i32.const 16) (i32.const 17))
+                    (i32.const 1)))"#,
+        )
+        .unwrap();
+        // Suppresses events only if it can grow its memory by 64 MiB.
+        std::fs::write(
+            plugin_dir.path().join("grow.wat"),
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "evaluate") (param i32 i32) (result i32)
+                    (if (result i32) (i32.eq (memory.grow (i32.const 1024)) (i32.const -1))
+                        (then (i32.const 0))
+                        (else (i32.const 2)))))"#,
+        )
+        .unwrap();
+        std::fs::write(
+            plugin_dir.path().join("spin.wat"),
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "evaluate") (param i32 i32) (result i32)
+                    (loop $forever (br $forever))
+                    (i32.const 2)))"#,
+        )
+        .unwrap();
+        std::fs::write(plugin_dir.path().join("README.txt"), "not a plugin").unwrap();
+
+        let record = FileEventRecord {
+            id: 0,
+            time: Local::now(),
+            occurred_at: None,
+            metadata: None,
+            size_change: None,
+            appended_lines: None,
+            enrichment: BTreeMap::new(),
+            process: None,
+            via_symlink: None,
+            watchset: None,
+            watch: plugin_dir.path().to_path_buf(),
+            path: plugin_dir.path().join("new.txt"),
+            event: FileEvent::Created,
+            git: None,
+            container: None,
+            maintenance: None,
+            content: None,
+        };
+        let limits = PluginLimits::default();
+        let plugins = plugins::load_plugins(plugin_dir.path(), limits).unwrap();
+        let verdicts: Vec<(&str, Verdict)> = plugins
+            .iter()
+            .map(|plugin| (plugin.name(), plugin.evaluate(&record)))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ("alert", Verdict::Alert("created by plugin".to_string())),
+                // Over the memory limit, so the growth fails.
+                ("grow", Verdict::Pass),
+                // Runs out of fuel.
+                ("spin", Verdict::Pass),
+            ]
+        );
+
+        let generous = WasmRule::load(
+            plugin_dir.path().join("grow.wat"),
+            PluginLimits {
+                memory_bytes: 128 * 1024 * 1024,
+                ..limits
+            },
+        )
+        .unwrap();
+        assert_eq!(generous.evaluate(&record), Verdict::Suppress);
+
+        let truncated = PluginLimits {
+            max_record_bytes: 16,
+            ..limits
+        };
+        let alert = WasmRule::load(plugin_dir.path().join("alert.wat"), truncated).unwrap();
+        assert_eq!(alert.evaluate(&record), Verdict::Pass);
+
+        std::fs::write(
+            plugin_dir.path().join("broken.wat"),
+            r#"(module (memory (export "memory") 1))"#,
+        )
+        .unwrap();
+        assert!(plugins::load_plugins(plugin_dir.path(), limits).is_err());
+
+        let config = MonitorConfig::parse(&format!(
+            "[plugins]\ndir = {:?}\nfuel = 1000\n",
+            plugin_dir.path()
+        ))
+        .unwrap();
+        assert_eq!(config.plugins.as_ref().unwrap().limits().fuel, 1000);
+        assert!(config
+            .apply(FileMonitor::builder(plugin_dir.path()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_supervisor_restarts_failed_tasks_with_backoff() {
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let supervisor = Supervisor::new();
+            let policy = RestartPolicy {
+                max_restarts: Some(3),
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(40),
+            };
+
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&attempts);
+            let flaky = supervisor.spawn("flaky", policy, move || {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match attempt {
+                        0 => panic!("first attempt"),
+                        1 => Err(monitor_error!("second attempt")),
+                        _ => Ok(()),
+                    }
+                }
+            });
+            let broken = supervisor.spawn("broken", policy, || async {
+                Err(monitor_error!("always fails"))
+            });
+
+            assert!(flaky.await.unwrap().is_ok());
+            assert!(broken.await.unwrap().is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+            let status = supervisor.status().await;
+            assert_eq!(status.len(), 2);
+            assert_eq!(status[0].name, "broken");
+            assert_eq!(status[0].state, TaskState::GaveUp);
+            assert_eq!(status[0].restarts, 3);
+            assert_eq!(status[0].last_error.as_deref(), Some("always fails"));
+            assert_eq!(status[1].name, "flaky");
+            assert_eq!(status[1].state, TaskState::Finished);
+            assert_eq!(status[1].restarts, 2);
+            assert_eq!(status[1].last_error.as_deref(), Some("second attempt"));
+        });
+    }
+
+    #[test]
+    fn test_config_file_changes_are_applied_live() {
+        let temp_dir = tempdir().unwrap();
+        let extra = tempdir().unwrap();
+        let config_path = temp_dir.path().join("monitor.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "watches = [{:?}]\n[[substitutions]]\noriginal = \"/a\"\nsubstitute = \"/b\"\n",
+                extra.path()
+            ),
+        )
+        .unwrap();
+        let config = MonitorConfig::load(&config_path).unwrap();
+        let monitor = config
+            .apply(FileMonitor::builder(temp_dir.path()))
+            .unwrap()
+            .config_file(&config_path, config)
+            .build();
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            assert!(!monitor.reload_config().await.unwrap());
+
+            std::fs::write(
+                &config_path,
+                "[[substitutions]]\noriginal = \"/a\"\nsubstitute = \"/c\"\n\n\
+                 [[filters]]\nkind = \"exclude\"\npattern = \"**/*.tmp\"\n",
+            )
+            .unwrap();
+            assert!(monitor.reload_config().await.unwrap());
+            assert_eq!(monitor.get_watches().await.len(), 1);
+            assert_eq!(
+                monitor.get_substituted_path("/a").await,
+                PathBuf::from("/c")
+            );
+            assert_eq!(
+                monitor.get_filters().await,
+                vec![(FilterKind::Exclude, "**/*.tmp".to_string())]
+            );
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].event, FileEvent::ConfigReloaded);
+            assert_eq!(
+                history[0].path,
+                std::fs::canonicalize(&config_path).unwrap()
+            );
+
+            // Settings that need a restart are named, live ones are not.
+            let applied = MonitorConfig::load(&config_path).unwrap();
+            let changed = MonitorConfig::parse(
+                "debounce_ms = 50\n\n[[watchsets]]\nname = \"web\"\npaths = [\"/srv\"]\n\n\
+                 [[filters]]\nkind = \"exclude\"\npattern = \"**/*.swp\"\n",
+            )
+            .unwrap();
+            assert_eq!(
+                changed.restart_required(&applied),
+                vec!["watchsets", "debounce_ms"]
+            );
+            assert!(applied.restart_required(&applied).is_empty());
+
+            // An invalid file keeps the current settings.
+            std::fs::write(&config_path, "[[filters]]\nkind = \"bogus\"\n").unwrap();
+            assert!(monitor.reload_config().await.is_err());
+            assert_eq!(monitor.get_filters().await.len(), 1);
+            assert_eq!(monitor.get_history().await.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_shutdown_stops_supervised_tasks_within_deadline() {
+        let parent = ShutdownToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        grandchild.cancel();
+        assert!(!child.is_cancelled());
+        parent.cancel();
+        assert!(child.is_cancelled());
+        assert!(parent.child().is_cancelled());
+
+        let temp_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let shutdown = ShutdownToken::new();
+            let supervisor = Supervisor::new().with_shutdown(shutdown.clone());
+            let monitor = Arc::new(
+                FileMonitor::builder(temp_dir.path())
+                    .shutdown_token(shutdown.child())
+                    .build(),
+            );
+            let watcher = Arc::clone(&monitor);
+            let handle = supervisor.spawn("watcher", RestartPolicy::default(), move || {
+                let monitor = Arc::clone(&watcher);
+                async move { monitor.monitor().await }
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(supervisor.shutdown(Duration::from_secs(5)).await);
+            assert!(handle.await.unwrap().is_ok());
+            assert_eq!(supervisor.status().await[0].state, TaskState::Stopped);
+
+            // A task that ignores the token is aborted once the deadline passes.
+            let stubborn = Supervisor::new();
+            stubborn.spawn("stubborn", RestartPolicy::default(), || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok::<_, MonitorError>(())
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(!stubborn.shutdown(Duration::from_millis(50)).await);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(stubborn.status().await[0].state, TaskState::Stopped);
+        });
+    }
+
+    #[test]
+    fn test_rate_limiter_and_circuit_breaker() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 0.0,
+            burst: 2,
+        });
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        let metrics = limiter.metrics();
+        assert_eq!((metrics.allowed, metrics.throttled), (2, 1));
+
+        let breaker = CircuitBreaker::new(
+            "test",
+            BreakerConfig {
+                failure_threshold: 2,
+                open_secs: 0,
+            },
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for _ in 0..2 {
+                let failed: Result<()> = Err(monitor_error!("down"));
+                assert!(breaker.call(async { failed }).await.is_err());
+            }
+        });
+        assert_eq!(breaker.state(), BreakerState::Open);
+        // With a zero open period the next call is let through as a trial and closes the
+        // breaker again on success.
+        assert!(rt
+            .block_on(breaker.call(async { Ok::<_, MonitorError>(()) }))
+            .is_ok());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        let metrics = breaker.metrics();
+        assert_eq!(
+            (metrics.failures, metrics.successes, metrics.trips),
+            (2, 1, 1)
+        );
+
+        let open = CircuitBreaker::new(
+            "open",
+            BreakerConfig {
+                failure_threshold: 1,
+                open_secs: 60,
+            },
+        );
+        open.record_failure();
+        assert!(!open.allow());
+        assert_eq!(open.metrics().rejected, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_control_socket_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("monitor.sock");
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let shutdown = ShutdownToken::new();
+            let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+            let server = tokio::spawn(ControlSocket::new(&path, shutdown.clone()).run(tx));
+            tokio::spawn(async move {
+                while let Some(request) = rx.recv().await {
+                    let _ = request.reply.send(format!("ran {}\n", request.command));
+                }
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let output = ctl::send(&path, "stats").await.unwrap();
+            assert_eq!(output, "ran stats\n");
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            // A second instance must not take over the socket of a running one.
+            let (other, _) = tokio::sync::mpsc::channel(1);
+            assert!(ControlSocket::new(&path, ShutdownToken::new())
+                .run(other)
+                .await
+                .is_err());
+
+            shutdown.cancel();
+            server.await.unwrap().unwrap();
+            assert!(!path.exists());
+            assert!(ctl::send(&path, "stats").await.is_err());
+
+            // Other users could swap the socket in a directory they can write to.
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let shared = temp_dir.path().join("shared");
+                std::fs::create_dir(&shared).unwrap();
+                std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+                let (other, _) = tokio::sync::mpsc::channel(1);
+                assert!(
+                    ControlSocket::new(shared.join("monitor.sock"), ShutdownToken::new())
+                        .run(other)
+                        .await
+                        .is_err()
+                );
+                assert!(!shared.join("monitor.sock").exists());
+            }
+        });
+    }
+
+    #[test]
+    fn test_startup_profile_waits_for_watcher() {
+        let temp_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut profile = StartupProfile::new();
+            let monitor = Arc::new(FileMonitor::builder(temp_dir.path()).build());
+            profile.phase("monitor build");
+            let watcher = Arc::clone(&monitor);
+            let handle = tokio::spawn(async move { watcher.monitor().await });
+            tokio::time::timeout(Duration::from_secs(5), monitor.wait_until_watching())
+                .await
+                .expect("watcher should start");
+            profile.phase("watcher setup");
+
+            let names: Vec<_> = profile
+                .phases()
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            assert_eq!(names, ["monitor build", "watcher setup"]);
+            assert_eq!(
+                profile.total(),
+                profile.phases().iter().map(|(_, duration)| *duration).sum()
+            );
+            let report = profile.report();
+            assert!(report.contains("watcher setup"));
+            assert!(report.contains("memory:"));
+            handle.abort();
+        });
+    }
+
+    #[test]
+    fn test_history_retention_and_queries() {
+        let temp_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor = FileMonitor::builder(temp_dir.path())
+                .history_size(3)
+                .history_max_age(Duration::from_secs(3600))
+                .build();
+            let now = Local::now();
+            let record = |age_minutes: i64, event: FileEvent| FileEventRecord {
+                id: 0,
+                time: now - chrono::Duration::minutes(age_minutes),
+                occurred_at: None,
+                metadata: None,
+                size_change: None,
+                appended_lines: None,
+                enrichment: BTreeMap::new(),
+                process: None,
+                via_symlink: None,
+                watchset: None,
+                watch: temp_dir.path().to_path_buf(),
+                path: temp_dir.path().join("file.txt"),
+                event,
+                git: None,
+                container: None,
+                maintenance: None,
+                content: None,
+            };
+            monitor
+                .update_history(record(120, FileEvent::Created))
+                .await;
+            monitor
+                .update_history(record(30, FileEvent::Modified))
+                .await;
+            // The two-hour-old record has expired.
+            assert_eq!(monitor.get_history().await.len(), 1);
+
+            for event in [FileEvent::Deleted, FileEvent::Created, FileEvent::Deleted] {
+                monitor.update_history(record(10, event)).await;
+            }
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 3);
+            assert_eq!(history[0].event, FileEvent::Deleted);
+
+            assert_eq!(monitor.get_history_filtered("deleted").await.len(), 2);
+            assert_eq!(monitor.get_history_filtered("Created").await.len(), 1);
+            assert!(monitor.get_history_filtered("modified").await.is_empty());
+
+            let recent = monitor
+                .get_history_range(now - chrono::Duration::minutes(15), now)
+                .await;
+            assert_eq!(recent.len(), 3);
+            assert!(monitor
+                .get_history_range(
+                    now - chrono::Duration::minutes(60),
+                    now - chrono::Duration::minutes(15)
+                )
+                .await
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_control_server_enforces_api_key_scopes_and_audits_calls() {
+        let temp_dir = tempdir().unwrap();
+        let keys_file = temp_dir.path().join("api-keys.json");
+        let audit_log = temp_dir.path().join("api-audit.jsonl");
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let keys = Arc::new(ApiKeyStore::load(&keys_file).unwrap());
+        let reader = keys.add("dashboard", ApiScope::Read).unwrap();
+        let operator = keys.add("ci", ApiScope::Control).unwrap();
+        assert!(keys.add("ci", ApiScope::Read).is_err());
+        // Only hashes are persisted.
+        let saved = std::fs::read_to_string(&keys_file).unwrap();
+        assert!(saved.contains("dashboard") && !saved.contains(&reader));
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ControlServer::with_api_keys(Arc::clone(&monitor), Arc::clone(&keys))
+                .with_audit_log(&audit_log);
+            tokio::spawn(server.serve(listener));
+            let request = |method: &str, key: &str| {
+                format!(
+                    "{} /{} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+                    method,
+                    if method == "GET" { "status" } else { "pause" },
+                    key
+                )
+            };
+
+            let (status, _) = control_request(addr, &request("GET", &reader)).await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            let (status, _) = control_request(addr, &request("POST", &reader)).await;
+            assert_eq!(status, "HTTP/1.1 403 Forbidden");
+            assert!(!monitor.is_paused().await);
+            let (status, _) = control_request(addr, &request("POST", &operator)).await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert!(monitor.is_paused().await);
+
+            // Revoked keys stop working immediately, also for a reloaded store.
+            assert!(keys.remove("ci").unwrap());
+            let (status, _) = control_request(addr, &request("POST", &operator)).await;
+            assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+            let reloaded = ApiKeyStore::load(&keys_file).unwrap();
+            assert!(reloaded.authenticate(&operator).is_none());
+            assert_eq!(reloaded.authenticate(&reader).unwrap().name, "dashboard");
+
+            // A token with surrounding whitespace, e.g. read from a file, still matches.
+            reloaded
+                .insert_ephemeral("legacy", " token-from-env\n", ApiScope::Control)
+                .unwrap();
+            assert_eq!(
+                reloaded.authenticate("token-from-env").unwrap().name,
+                "legacy"
+            );
+            assert!(reloaded
+                .insert_ephemeral("blank", " \n", ApiScope::Control)
+                .is_err());
+        });
+
+        let calls: Vec<ApiCall> = std::fs::read_to_string(&audit_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<_> = calls
+            .iter()
+            .map(|call| (call.key.as_deref(), call.method.as_str(), call.status))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some("dashboard"), "GET", 200),
+                (Some("dashboard"), "POST", 403),
+                (Some("ci"), "POST", 200),
+                (None, "POST", 401),
+            ]
+        );
+    }
+
+    /// Sends a GET over TLS; returns the status line and the server's leaf certificate.
+    async fn tls_request(
+        addr: std::net::SocketAddr,
+        client_cert: Option<(&str, &str)>,
+        route: &str,
+    ) -> Result<(String, Vec<u8>)> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::pki_types::ServerName;
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut include_str!("../testdata/tls/ca.pem").as_bytes()) {
+            roots.add(cert?)?;
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(
+            tokio_rustls::rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+        let config = match client_cert {
+            Some((cert, key)) => builder.with_client_auth_cert(
+                rustls_pemfile::certs(&mut cert.as_bytes()).collect::<std::io::Result<_>>()?,
+                rustls_pemfile::private_key(&mut key.as_bytes())?.unwrap(),
+            )?,
+            None => builder.with_no_client_auth(),
+        };
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let tcp = tokio::net::TcpStream::connect(addr).await?;
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await?;
+        let leaf = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+                    route
+                )
+                .as_bytes(),
+            )
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        let status = response.lines().next().unwrap_or_default().to_string();
+        Ok((status, leaf))
+    }
+
+    #[test]
+    fn test_control_server_tls_with_client_certs_and_reload() {
+        let temp_dir = tempdir().unwrap();
+        let cert = temp_dir.path().join("server.pem");
+        let key = temp_dir.path().join("server.key");
+        let ca = temp_dir.path().join("ca.pem");
+        std::fs::write(&cert, include_str!("../testdata/tls/server1.pem")).unwrap();
+        std::fs::write(&key, include_str!("../testdata/tls/server1.key")).unwrap();
+        std::fs::write(&ca, include_str!("../testdata/tls/ca.pem")).unwrap();
+        let leaf = |pem: &str| {
+            rustls_pemfile::certs(&mut pem.as_bytes())
+                .next()
+                .unwrap()
+                .unwrap()
+                .to_vec()
+        };
+        let client = Some((
+            include_str!("../testdata/tls/client.pem"),
+            include_str!("../testdata/tls/client.key"),
+        ));
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let tls = ReloadableTls::new(TlsSettings::new(&cert, &key).client_ca(&ca)).unwrap();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = ControlServer::new(Arc::clone(&monitor), "s3cret")
+                .unwrap()
+                .with_tls(Arc::new(tls));
+            tokio::spawn(server.serve(listener));
+
+            let (status, served) = tls_request(addr, client, "/status").await.unwrap();
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert_eq!(served, leaf(include_str!("../testdata/tls/server1.pem")));
+
+            // Without a client certificate the server refuses the connection.
+            let anonymous = tls_request(addr, None, "/status").await;
+            assert!(!matches!(anonymous, Ok((status, _)) if status.contains("200")));
+
+            // Plain HTTP is not served on a TLS listener.
+            let response = tokio::time::timeout(Duration::from_secs(5), async {
+                use tokio::io::{AsyncReadExt, AsyncWriteExt};
+                let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                let _ = stream
+                    .write_all(b"GET /status HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n")
+                    .await;
+                let mut response = Vec::new();
+                let _ = stream.read_to_end(&mut response).await;
+                String::from_utf8_lossy(&response).to_string()
+            })
+            .await
+            .unwrap();
+            assert!(!response.starts_with("HTTP/1.1 200"));
+
+            // A renewed certificate is picked up without restarting the server.
+            std::fs::write(&key, include_str!("../testdata/tls/server2.key")).unwrap();
+            std::fs::write(&cert, include_str!("../testdata/tls/server2.pem")).unwrap();
+            let renewed = leaf(include_str!("../testdata/tls/server2.pem"));
+            let mut served = Vec::new();
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if let Ok((_, leaf)) = tls_request(addr, client, "/status").await {
+                    served = leaf;
+                    if served == renewed {
+                        break;
+                    }
+                }
+            }
+            assert_eq!(served, renewed);
+        });
+    }
+
+    #[test]
+    fn test_event_rates_over_rolling_windows() {
+        let mut rates = EventRates::new();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        for second in 0..10 {
+            rates.record_at("modified", at(second));
+        }
+        // A burst of modifications two minutes later.
+        for _ in 0..30 {
+            rates.record_at("modified", at(120));
+        }
+        rates.record_at("deleted", at(121));
+
+        let last_minute = rates.rate_at("Modified", Duration::from_secs(60), at(125));
+        assert_eq!(last_minute.count, 30);
+        assert_eq!(last_minute.per_minute, 30.0);
+        let last_five = rates.rate_at("modified", Duration::from_secs(300), at(125));
+        assert_eq!(last_five.count, 40);
+        assert_eq!(last_five.per_minute, 8.0);
+        let all = rates.rates_at(Duration::from_secs(10), at(125));
+        assert_eq!(
+            all.keys().copied().collect::<Vec<_>>(),
+            vec!["deleted", "modified"]
+        );
+
+        // Counts older than an hour are dropped, and longer windows are clamped.
+        rates.record_at("created", at(3700));
+        let hour = rates.rate_at("modified", Duration::from_secs(7200), at(3700));
+        assert_eq!((hour.count, hour.window_secs), (30, 3600));
+
+        let temp_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor = FileMonitor::new(temp_dir.path());
+            for name in ["a.txt", "b.txt", "c.txt"] {
+                monitor
+                    .handle_event(temp_dir.path().join(name), FileEvent::Modified)
+                    .await
+                    .unwrap();
+            }
+            let rate = monitor.get_rate("modified", Duration::from_secs(60)).await;
+            assert_eq!(rate.count, 3);
+            assert_eq!(
+                monitor
+                    .get_rate("deleted", Duration::from_secs(60))
+                    .await
+                    .count,
+                0
+            );
+        });
+    }
+
+    #[test]
+    fn test_rate_alert_fires_once_per_burst() {
+        let temp_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor = FileMonitor::builder(temp_dir.path())
+                .rate_alert(
+                    RateAlertRule::new("mass-delete", "Deleted", 2, Duration::from_secs(60))
+                        .unwrap(),
+                )
+                .build();
+            let mut subscription = monitor.subscribe();
+            let hooked = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let hooked_clone = Arc::clone(&hooked);
+            monitor
+                .on_event(move |record| {
+                    hooked_clone
+                        .lock()
+                        .unwrap()
+                        .push(record.event.kind().to_string())
+                })
+                .await;
+
+            for i in 0..5 {
+                monitor
+                    .handle_event(
+                        temp_dir.path().join(format!("{}.txt", i)),
+                        FileEvent::Deleted,
+                    )
+                    .await
+                    .unwrap();
+            }
+            monitor
+                .handle_event(temp_dir.path().join("a.txt"), FileEvent::Modified)
+                .await
+                .unwrap();
+
+            let alerts = monitor.get_history_filtered("rate_alert").await;
+            assert_eq!(alerts.len(), 1);
+            assert_eq!(
+                alerts[0].event,
+                FileEvent::RateAlert {
+                    rule: "mass-delete".to_string(),
+                    count: 3,
+                    window_secs: 60,
+                }
+            );
+            assert_eq!(alerts[0].path, temp_dir.path().join("2.txt"));
+            assert_eq!(
+                hooked
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|kind| *kind == "rate_alert")
+                    .count(),
+                1
+            );
+            let mut alert_events = Vec::new();
+            while let Some(event) = subscription.try_recv() {
+                if let MonitorEvent::Alert { rule, reason, .. } = event {
+                    alert_events.push((rule, reason));
+                }
+            }
+            assert_eq!(
+                alert_events,
+                vec![(
+                    "mass-delete".to_string(),
+                    "3 deleted events in 60s, threshold 2".to_string()
+                )]
+            );
+            assert!(monitor.get_rate_alerts().await[0].firing);
+
+            assert!(monitor
+                .add_rate_alert(
+                    RateAlertRule::new("mass-delete", "created", 1, Duration::from_secs(1))
+                        .unwrap()
+                )
+                .await
+                .is_err());
+            assert!(RateAlertRule::new("hourly", "created", 1, Duration::from_secs(7200)).is_err());
+            assert!(monitor.remove_rate_alert("mass-delete").await);
+            assert!(monitor.get_rate_alerts().await.is_empty());
+        });
+
+        let config = MonitorConfig::parse(
+            "[[rate_alerts]]\nname = \"burst\"\nevent = \"modified\"\nthreshold = 100\nwindow_secs = 10\n",
+        )
+        .unwrap();
+        assert_eq!(config.rate_alerts[0].threshold, 100);
+    }
+
+    #[test]
+    fn test_atomic_save_coalescer() {
+        assert!(atomic_save::is_temp_file(Path::new(
+            "/srv/.goutputstream-X1Y2Z3"
+        )));
+        assert!(atomic_save::is_temp_file(Path::new(
+            "/srv/app.conf___jb_tmp___"
+        )));
+        assert!(atomic_save::is_temp_file(Path::new("/srv/sedAb12Cd")));
+        assert!(!atomic_save::is_temp_file(Path::new("/srv/sediment.txt")));
+        assert!(!atomic_save::is_temp_file(Path::new("/srv/app.conf")));
+
+        let start = Instant::now();
+        let mut coalescer = AtomicSaveCoalescer::new(Duration::from_secs(1));
+        let temp = PathBuf::from("/srv/app.conf.tmp");
+        let target = PathBuf::from("/srv/app.conf");
+        let create = ReceivedEvent::now(
+            Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(temp.clone()),
+        );
+        assert!(coalescer.push(create.clone(), start).is_empty());
+        // A backend that only reports the rename as one event with both paths.
+        let both = ReceivedEvent::now(
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(temp.clone())
+                .add_path(target.clone()),
+        );
+        let received = both.received;
+        assert_eq!(
+            coalescer.push(both, start),
+            vec![Coalesced::Replaced {
+                temp: temp.clone(),
+                target: target.clone(),
+                received
+            }]
+        );
+        // Nothing was left behind for the temp file.
+        assert!(coalescer.drain().is_empty());
+
+        // A temp file that is never renamed is recorded once the window has passed.
+        assert!(coalescer.push(create.clone(), start).is_empty());
+        let other = ReceivedEvent::now(
+            Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(target.clone()),
+        );
+        assert_eq!(
+            coalescer.push(other.clone(), start + Duration::from_millis(500)),
+            vec![Coalesced::Event(other)]
+        );
+        assert_eq!(
+            coalescer.expire(start + Duration::from_secs(1)),
+            vec![Coalesced::Event(create)]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_atomic_save_recorded_as_single_replace() {
+        let temp_dir = tempdir().unwrap();
+        let target = temp_dir.path().join("app.conf");
+        std::fs::write(&target, "old").unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .atomic_save_window(Duration::from_millis(300))
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor_clone = Arc::clone(&monitor);
+            let handle = tokio::spawn(async move { monitor_clone.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let temp = temp_dir.path().join(".goutputstream-A1B2C3");
+            std::fs::write(&temp, "new").unwrap();
+            std::fs::rename(&temp, &target).unwrap();
+            let scratch = temp_dir.path().join("scratch.tmp");
+            std::fs::write(&scratch, "kept").unwrap();
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+            handle.abort();
+
+            let history = monitor.get_history().await;
+            let on_target: Vec<_> = history
+                .iter()
+                .filter(|record| record.path == target)
+                .map(|record| record.event.clone())
+                .collect();
+            assert_eq!(on_target, vec![FileEvent::Replaced(temp.clone())]);
+            assert!(history.iter().all(|record| record.path != temp));
+            // Temp files that are not renamed are still recorded, just later.
+            assert!(history
+                .iter()
+                .any(|record| record.path == scratch && record.event == FileEvent::Created));
+        });
+    }
+
+    #[test]
+    fn test_content_hashing_strategies_by_size() {
+        use sha2::Digest;
+        use std::io::Write;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("data.log");
+        let sha256 = |content: &[u8]| config_guard::to_hex(&sha2::Sha256::digest(content));
+        let mut hasher = ContentHasher::new(HashPolicy {
+            full_max_bytes: 1024,
+            sampled_max_bytes: 1024 * 1024,
+            incremental: true,
+        });
+
+        std::fs::write(&path, [b'a'; 100]).unwrap();
+        let small = hasher.hash(&path).unwrap();
+        assert_eq!(small.strategy, HashStrategy::Full);
+        assert_eq!(small.sha256, Some(sha256(&[b'a'; 100])));
+
+        // Appends to a file above the full-hash limit only read the new bytes.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[b'b'; 2000]).unwrap();
+        let grown = hasher.hash(&path).unwrap();
+        assert_eq!(
+            (grown.strategy, grown.size),
+            (HashStrategy::Incremental, 2100)
+        );
+        file.write_all(b"more").unwrap();
+        let appended = hasher.hash(&path).unwrap();
+        assert_eq!(appended.strategy, HashStrategy::Incremental);
+        assert_ne!(appended.sha256, grown.sha256);
+
+        // A rewrite of the hashed part is not an append.
+        let mut content = std::fs::read(&path).unwrap();
+        content[2050] = b'x';
+        content.extend_from_slice(b"tail");
+        std::fs::write(&path, &content).unwrap();
+        assert_eq!(hasher.hash(&path).unwrap().strategy, HashStrategy::Sampled);
+
+        std::fs::write(&path, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let huge = hasher.hash(&path).unwrap();
+        assert_eq!((huge.strategy, huge.sha256), (HashStrategy::Metadata, None));
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor = FileMonitor::builder(temp_dir.path())
+                .content_hashing(temp_dir.path(), HashPolicy::default())
+                .build();
+            let config = temp_dir.path().join("app.conf");
+            std::fs::write(&config, "port = 80").unwrap();
+            monitor
+                .handle_event(config.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(temp_dir.path().join("gone.txt"), FileEvent::Created)
+                .await
+                .unwrap();
+            let history = monitor.get_history().await;
+            let content = history[0].content.as_ref().unwrap();
+            assert_eq!(content.strategy, HashStrategy::Full);
+            assert_eq!(content.sha256, Some(sha256(b"port = 80")));
+            assert_eq!(history[1].content, None);
+        });
+    }
+
+    #[test]
+    fn test_baseline_create_and_verify_reports_drift() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("a.txt"), "alpha").unwrap();
+        std::fs::write(root.join("b.txt"), "beta").unwrap();
+        std::fs::write(root.join("a.swp"), "swap").unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub").join("c.txt"), "nested").unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let monitor = FileMonitor::new(root);
+            monitor
+                .add_filter(FilterKind::Exclude, "*.swp")
+                .await
+                .unwrap();
+            let baseline = monitor.create_baseline().await.unwrap();
+            // Not recursive, so only the direct children; the filter still applies.
+            assert_eq!(
+                baseline.files.keys().cloned().collect::<Vec<_>>(),
+                vec![root.join("a.txt"), root.join("b.txt")]
+            );
+            let baseline_path = root.join("sub").join("baseline.json");
+            baseline.save(&baseline_path).unwrap();
+            let baseline = Baseline::load(&baseline_path).unwrap();
+            assert!(monitor.verify_baseline(&baseline).await.unwrap().is_empty());
+
+            std::fs::write(root.join("a.txt"), "tampered").unwrap();
+            std::fs::remove_file(root.join("b.txt")).unwrap();
+            std::fs::write(root.join("d.txt"), "new").unwrap();
+            let drift = monitor.verify_baseline(&baseline).await.unwrap();
+            assert_eq!(drift.len(), 3);
+            match &drift[0] {
+                (path, Drift::Changed(fields)) => {
+                    assert_eq!(path, &root.join("a.txt"));
+                    assert!(fields.contains(&"size".to_string()));
+                    assert!(fields.contains(&"content".to_string()));
+                }
+                other => panic!("unexpected drift {:?}", other),
+            }
+            assert_eq!(drift[1], (root.join("b.txt"), Drift::Removed));
+            assert_eq!(drift[2], (root.join("d.txt"), Drift::Added));
+
+            let recorded = monitor.get_history_filtered("baseline_drift").await;
+            assert_eq!(recorded.len(), 3);
+            assert_eq!(recorded[1].event, FileEvent::BaselineDrift(Drift::Removed));
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_bench_reports_throughput_and_latency() {
+        let rt = Runtime::new().unwrap();
+        let report = rt
+            .block_on(bench::run(BenchConfig {
+                files: 5,
+                events_per_sec: 200,
+                duration: Duration::from_millis(500),
+                ..BenchConfig::default()
+            }))
+            .unwrap();
+        assert!(report.generated > 0);
+        assert!(report.recorded > 0);
+        assert!(!report.latencies.is_empty());
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+        assert!(report.summary().contains("latency p50"));
+        assert!(rt
+            .block_on(bench::run(BenchConfig {
+                files: 0,
+                ..BenchConfig::default()
+            }))
+            .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_modified_text_files_get_unified_diffs() {
+        use std::io::Write;
+
+        Runtime::new().unwrap().block_on(async {
+            let temp_dir = tempdir().unwrap();
+            let file_path = temp_dir.path().join("app.conf");
+            std::fs::write(&file_path, "port = 80\nworkers = 4\n").unwrap();
+            let monitor = Arc::new(FileMonitor::builder(&file_path).text_diffs(1024).build());
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // Overwritten in place: truncating first could let the monitor snapshot the
+            // empty file in between.
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&file_path)
+                .unwrap()
+                .write_all(b"port = 8080\nworkers = 4\n")
+                .unwrap();
+            let mut detail = None;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let history = monitor.get_history_filtered("modified").await;
+                for record in history {
+                    if let Some(found) = monitor.get_history_detail(record.id).await {
+                        if found.diff.is_some() {
+                            detail = Some(found);
+                        }
+                    }
+                }
+                if detail.is_some() {
+                    break;
+                }
+            }
+            task.abort();
+
+            let detail = detail.expect("no diff recorded");
+            assert_eq!(detail.record.path, file_path);
+            let diff = detail.diff.unwrap();
+            assert!(diff.contains("-port = 80\n"), "{}", diff);
+            assert!(diff.contains("+port = 8080\n"), "{}", diff);
+            assert!(diff.contains(" workers = 4"), "{}", diff);
+            assert!(monitor.get_history_detail(u64::MAX).await.is_none());
+
+            // Binary and oversized files are not kept.
+            let mut snapshots = TextSnapshots::new(16);
+            let binary = temp_dir.path().join("blob.bin");
+            std::fs::write(&binary, b"a\0b").unwrap();
+            snapshots.remember(&binary);
+            std::fs::write(&binary, b"a\0c").unwrap();
+            assert_eq!(snapshots.update(&binary), None);
+            std::fs::write(&file_path, "x".repeat(17)).unwrap();
+            snapshots.remember(&file_path);
+            assert_eq!(snapshots.update(&file_path), None);
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_backups_version_modified_files_and_restore() {
+        Runtime::new().unwrap().block_on(async {
+            let temp_dir = tempdir().unwrap();
+            let watched = temp_dir.path().join("watched");
+            std::fs::create_dir(&watched).unwrap();
+            let file_path = watched.join("app.conf");
+            std::fs::write(&file_path, "v1").unwrap();
+            let backup_dir = watched.join(".fm-backups");
+            let monitor = Arc::new(
+                FileMonitor::builder(&watched)
+                    .backups(BackupPolicy {
+                        max_versions: 2,
+                        ..BackupPolicy::new(&backup_dir)
+                    })
+                    .build(),
+            );
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // A write may be seen as several modifications (truncate, then data), so wait
+            // for the version with the written content.
+            let latest_is = |content: &'static [u8]| {
+                let monitor = Arc::clone(&monitor);
+                let file_path = file_path.clone();
+                let backup_dir = backup_dir.clone();
+                async move {
+                    for _ in 0..50 {
+                        let versions = monitor.get_backups(&file_path).await.unwrap();
+                        if let Some(latest) = versions.last() {
+                            if std::fs::read(backup_dir.join(&latest.id)).unwrap() == content {
+                                return versions;
+                            }
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    panic!("no version with {:?}", content);
+                }
+            };
+            for content in [&b"v2"[..], b"v3"] {
+                std::fs::write(&file_path, content).unwrap();
+                latest_is(content).await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            std::fs::write(&file_path, "v4").unwrap();
+            // Only the newest two are kept.
+            let versions = latest_is(b"v4").await;
+            assert_eq!(versions.len(), 2);
+            assert_eq!(
+                std::fs::read(backup_dir.join(&versions[0].id)).unwrap(),
+                b"v3"
+            );
+
+            std::fs::remove_file(&file_path).unwrap();
+            let restored = monitor.restore_backup(&versions[0].id).await.unwrap();
+            assert_eq!(restored, file_path);
+            assert_eq!(std::fs::read(&file_path).unwrap(), b"v3");
+            assert!(monitor
+                .restore_backup("../etc/passwd.20240101T000000000")
+                .await
+                .is_err());
+            task.abort();
+
+            // The backup directory's own changes are not recorded.
+            assert!(monitor
+                .get_history()
+                .await
+                .iter()
+                .all(|record| !record.path.starts_with(&backup_dir)));
+        });
+    }
+
+    #[test]
+    fn test_poll_backend_per_watch() {
+        let temp_dir = tempdir().unwrap();
+        let polled = temp_dir.path().join("share");
+        std::fs::create_dir(&polled).unwrap();
+        let config = MonitorConfig::parse(&format!(
+            "[[backends]]\nwatch = {:?}\nkind = \"poll\"\npoll_interval_ms = 100\n",
+            polled
+        ))
+        .unwrap();
+        assert!(
+            MonitorConfig::parse("[[backends]]\nwatch = \"/a\"\npoll_interval_ms = 100\n")
+                .unwrap()
+                .apply(FileMonitor::builder(temp_dir.path()))
+                .is_err()
+        );
+
+        let monitor = Arc::new(
+            config
+                .apply(FileMonitor::builder(temp_dir.path()).watch(&polled))
+                .unwrap()
+                .build(),
+        );
+        assert_eq!(
+            monitor.get_watch_backend(temp_dir.path()),
+            WatchBackend::Native
+        );
+        assert_eq!(
+            monitor.get_watch_backend(&polled),
+            WatchBackend::poll(Duration::from_millis(100))
+        );
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let file_path = polled.join("remote.txt");
+            std::fs::write(&file_path, "data").unwrap();
+            let mut seen = false;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if monitor
+                    .get_history()
+                    .await
+                    .iter()
+                    .any(|record| record.path == file_path)
+                {
+                    seen = true;
+                    break;
+                }
+            }
+            assert!(seen, "the poll watcher did not report the new file");
+
+            monitor.remove_watch(&polled).await.unwrap();
+            task.abort();
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_shell_hooks_run_per_event_kind() {
+        let temp_dir = tempdir().unwrap();
+        let out_dir = tempdir().unwrap();
+        let config = MonitorConfig::parse(&format!(
+            r#"
+[shell_hooks]
+on_created = "cp {{path}} '{}'"
+on_deleted = "sh -c 'echo \"$1 $2\" >> \"$3\"' sh {{event}} {{path}} '{}'"
+max_concurrent = 2
+"#,
+            out_dir.path().display(),
+            out_dir.path().join("deleted.log").display()
+        ))
+        .unwrap();
+        let monitor = config
+            .apply(FileMonitor::builder(temp_dir.path()))
+            .unwrap()
+            .build();
+
+        // Values are substituted once and stay single arguments.
+        let hook = ShellHook::new("Modified", "echo {path} '{event} x'").unwrap();
+        let record = FileEventRecord {
+            id: 0,
+            time: Local::now(),
+            occurred_at: None,
+            metadata: None,
+            size_change: None,
+            appended_lines: None,
+            enrichment: BTreeMap::new(),
+            process: None,
+            via_symlink: None,
+            watchset: None,
+            watch: temp_dir.path().to_path_buf(),
+            path: PathBuf::from("/srv/a b {event}; rm -rf"),
+            event: FileEvent::Modified,
+            git: None,
+            container: None,
+            maintenance: None,
+            content: None,
+        };
+        assert_eq!(
+            hook.argv(&record),
+            vec!["echo", "/srv/a b {event}; rm -rf", "modified x"]
+        );
+        assert!(ShellHook::new("exploded", "true").is_err());
+        assert!(ShellHook::new("created", "echo 'unterminated").is_err());
+        assert!(
+            MonitorConfig::parse("[shell_hooks]\nafter_created = \"true\"\n")
+                .unwrap()
+                .apply(FileMonitor::builder(temp_dir.path()))
+                .is_err()
+        );
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let file_path = temp_dir.path().join("report.txt");
+            std::fs::write(&file_path, "data").unwrap();
+            monitor
+                .handle_event(file_path.clone(), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(file_path.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(file_path.clone(), FileEvent::Deleted)
+                .await
+                .unwrap();
+
+            let copy = out_dir.path().join("report.txt");
+            let log = out_dir.path().join("deleted.log");
+            for _ in 0..50 {
+                if copy.exists() && log.exists() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert_eq!(std::fs::read_to_string(&copy).unwrap(), "data");
+            assert_eq!(
+                std::fs::read_to_string(&log).unwrap(),
+                format!("deleted {}\n", file_path.display())
+            );
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_records_carry_occurrence_time_and_latency() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .debounce(Duration::from_millis(100))
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Events the monitor generates itself have no occurrence time.
+            monitor
+                .handle_event(temp_dir.path().join("manual"), FileEvent::Modified)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await[0].occurred_at, None);
+            assert_eq!(monitor.get_latency().await.count, 0);
+
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            let file_path = temp_dir.path().join("data.txt");
+            let before = Local::now();
+            std::fs::write(&file_path, "x").unwrap();
+            let mut record = None;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                record = monitor
+                    .get_history()
+                    .await
+                    .into_iter()
+                    .find(|record| record.path == file_path);
+                if record.is_some() {
+                    break;
+                }
+            }
+            task.abort();
+
+            let record = record.expect("no event for the written file");
+            let occurred_at = record.occurred_at.unwrap();
+            // The write's mtime, which may be rounded down to the filesystem's clock tick.
+            assert!(occurred_at >= before - chrono::Duration::milliseconds(20));
+            assert!(occurred_at <= record.time);
+            let latency = monitor.get_latency().await;
+            assert!(latency.count >= 1);
+            assert!(latency.max >= latency.mean().unwrap());
+            assert!(export::event_line(&record, &record.path).contains("occurred_at"));
+        });
+    }
+
+    #[test]
+    fn test_rename_halves_are_paired() {
+        let start = Instant::now();
+        let mut renames = RenamePairer::default();
+        let from = PathBuf::from("/srv/old.txt");
+        let to = PathBuf::from("/srv/new.txt");
+        let half = |mode, path: &PathBuf| {
+            ReceivedEvent::now(
+                Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+                    .add_path(path.clone())
+                    .set_tracker(7),
+            )
+        };
+        assert!(renames
+            .push(half(RenameMode::From, &from), start)
+            .is_empty());
+        assert_eq!(renames.deadline(), Some(start + rename::RENAME_WINDOW));
+        let paired = renames.push(half(RenameMode::To, &to), start);
+        assert_eq!(paired.len(), 1);
+        assert_eq!(paired[0].paths, vec![from.clone(), to.clone()]);
+        // The backend's own event for the same rename is not recorded twice.
+        let both = ReceivedEvent::now(
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from.clone())
+                .add_path(to.clone())
+                .set_tracker(7),
+        );
+        assert!(renames.push(both, start).is_empty());
+        assert_eq!(renames.deadline(), None);
+
+        // A file moved out of scope is released on its own after the window.
+        let moved_out = half(RenameMode::From, &from);
+        assert!(renames.push(moved_out.clone(), start).is_empty());
+        assert!(renames.expire(start + Duration::from_millis(50)).is_empty());
+        assert_eq!(
+            renames.expire(start + rename::RENAME_WINDOW),
+            vec![moved_out]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_recorded_with_both_paths() {
+        let temp_dir = tempdir().unwrap();
+        let from = temp_dir.path().join("old.txt");
+        let to = temp_dir.path().join("new.txt");
+        std::fs::write(&from, "x").unwrap();
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            std::fs::rename(&from, &to).unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            task.abort();
+
+            let events: Vec<FileEvent> = monitor
+                .get_history()
+                .await
+                .into_iter()
+                .map(|record| record.event)
+                .collect();
+            assert_eq!(
+                events,
+                vec![FileEvent::Renamed {
+                    from: from.clone(),
+                    to: to.clone()
+                }]
+            );
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_metadata_changes_recorded_by_kind() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("secret.key");
+        std::fs::write(&file_path, "x").unwrap();
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            monitor.wait_until_scanned().await;
+            std::fs::set_permissions(&file_path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            // Like `touch -d`: both times at once, which inotify reports as IN_ATTRIB.
+            let epoch = std::time::SystemTime::UNIX_EPOCH;
+            std::fs::File::open(&file_path)
+                .unwrap()
+                .set_times(
+                    std::fs::FileTimes::new()
+                        .set_accessed(epoch)
+                        .set_modified(epoch),
+                )
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            task.abort();
+
+            let events: Vec<FileEvent> = monitor
+                .get_history()
+                .await
+                .into_iter()
+                .filter(|record| record.path == file_path)
+                .map(|record| record.event)
+                .collect();
+            assert!(
+                events.contains(&FileEvent::PermissionsChanged),
+                "{:?}",
+                events
+            );
+            assert!(
+                events.contains(&FileEvent::TimestampChanged),
+                "{:?}",
+                events
+            );
+            assert!(!events.contains(&FileEvent::Modified), "{:?}", events);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_records_keep_file_metadata() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("report.csv");
+        std::fs::write(&file_path, "a,b,c\n").unwrap();
+        std::fs::set_permissions(&file_path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let uid = std::fs::metadata(&file_path).unwrap().uid();
+        let monitor = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            monitor
+                .handle_event(file_path.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            std::fs::remove_file(&file_path).unwrap();
+            monitor
+                .handle_event(file_path.clone(), FileEvent::Deleted)
+                .await
+                .unwrap();
+
+            let history = monitor.get_history().await;
+            for record in &history {
+                let metadata = record.metadata.as_ref().expect("no metadata");
+                assert_eq!(metadata.size, 6);
+                assert_eq!(metadata.mode & 0o777, 0o640);
+                assert_eq!(metadata.uid, Some(uid));
+                assert!(metadata.modified.is_some());
+            }
+            // Nothing is known about a file the monitor never saw.
+            monitor
+                .handle_event(temp_dir.path().join("ghost"), FileEvent::Deleted)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await[2].metadata, None);
+            let line = export::event_line(&history[1], &file_path);
+            assert!(line.contains("\"metadata\""));
+        });
+    }
+
+    #[test]
+    fn test_watchset_managed_as_a_unit() {
+        let primary = tempdir().unwrap();
+        let nginx = tempdir().unwrap();
+        let ssl = tempdir().unwrap();
+        let monitor = FileMonitor::new(primary.path());
+        let rt = Runtime::new().unwrap();
+
+        rt.block_on(async {
+            monitor
+                .add_to_watchset("prod-configs", &[nginx.path(), ssl.path()])
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_watches().await.len(), 3);
+            assert!(monitor
+                .add_to_watchset("other", &[ssl.path()])
+                .await
+                .is_err());
+            monitor
+                .add_watchset_filter("prod-configs", FilterKind::Exclude, "*.bak")
+                .await
+                .unwrap();
+
+            monitor
+                .handle_event(nginx.path().join("nginx.conf"), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(ssl.path().join("cert.pem"), FileEvent::Created)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(ssl.path().join("cert.pem.bak"), FileEvent::Created)
+                .await
+                .unwrap();
+            // The watchset filter does not apply outside the set.
+            monitor
+                .handle_event(primary.path().join("app.bak"), FileEvent::Created)
+                .await
+                .unwrap();
+
+            let stats = monitor.get_watchset_stats("prod-configs").await.unwrap();
+            assert_eq!(stats.get(&FileEvent::Modified), Some(&1));
+            assert_eq!(stats.get(&FileEvent::Created), Some(&1));
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 3);
+            assert_eq!(history[0].watchset.as_deref(), Some("prod-configs"));
+            assert_eq!(history[2].watchset, None);
+
+            monitor.pause_watchset("prod-configs").await.unwrap();
+            monitor
+                .handle_event(nginx.path().join("nginx.conf"), FileEvent::Modified)
+                .await
+                .unwrap();
+            monitor
+                .handle_event(primary.path().join("app.log"), FileEvent::Modified)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await.len(), 4);
+            monitor.resume_watchset("prod-configs").await.unwrap();
+            monitor
+                .handle_event(nginx.path().join("nginx.conf"), FileEvent::Modified)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await.len(), 5);
+
+            let export_path = primary.path().join("watchsets.csv");
+            monitor
+                .export_watchset_stats(ExportFormat::Csv, &export_path)
+                .await
+                .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&export_path).unwrap(),
+                "watchset,event,count\nprod-configs,created,1\nprod-configs,modified,2\n"
+            );
+
+            monitor.remove_watchset("prod-configs").await.unwrap();
+            assert_eq!(monitor.get_watches().await.len(), 1);
+            assert!(monitor.get_watchsets().await.is_empty());
+            assert!(monitor.pause_watchset("prod-configs").await.is_err());
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fanotify_backend_attributes_events_to_processes() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .backend(temp_dir.path(), WatchBackend::Fanotify)
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let mut task = tokio::spawn(async move { task_monitor.monitor().await });
+            tokio::select! {
+                _ = monitor.wait_until_watching() => {}
+                result = &mut task => {
+                    // fanotify needs CAP_SYS_ADMIN, which test environments may not have.
+                    eprintln!("Skipping, fanotify not usable: {:?}", result.unwrap());
+                    return;
+                }
+            }
+
+            let file_path = temp_dir.path().join("audit.log");
+            let renamed = temp_dir.path().join("audit.log.1");
+            std::fs::write(&file_path, "entry\n").unwrap();
+            std::fs::rename(&file_path, &renamed).unwrap();
+            let mut history = Vec::new();
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                history = monitor.get_history().await;
+                if history
+                    .iter()
+                    .any(|record| matches!(record.event, FileEvent::Renamed { .. }))
+                {
+                    break;
+                }
+            }
+
+            let modified = history
+                .iter()
+                .find(|record| record.path == file_path && record.event == FileEvent::Modified)
+                .expect("no modification recorded");
+            let process = modified.process.as_ref().expect("no process attached");
+            assert_eq!(process.pid, std::process::id());
+            assert_eq!(
+                process.executable.as_deref(),
+                Some(std::env::current_exe().unwrap().as_path())
+            );
+            assert!(history.iter().any(|record| record.event
+                == FileEvent::Renamed {
+                    from: file_path.clone(),
+                    to: renamed.clone(),
+                }
+                && record.process.is_some()));
+            task.abort();
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fanotify_backend_follows_new_directories_in_recursive_watches() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .watch_mode(WatchMode::Recursive { max_depth: None })
+                .backend(temp_dir.path(), WatchBackend::Fanotify)
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let mut task = tokio::spawn(async move { task_monitor.monitor().await });
+            tokio::select! {
+                _ = monitor.wait_until_watching() => {}
+                result = &mut task => {
+                    eprintln!("Skipping, fanotify not usable: {:?}", result.unwrap());
+                    return;
+                }
+            }
+
+            // Marking the new directory used to deadlock the reader thread.
+            let dir = temp_dir.path().join("releases");
+            std::fs::create_dir(&dir).unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let file_path = dir.join("v2.conf");
+            std::fs::write(&file_path, "version = 2\n").unwrap();
+            let mut recorded = false;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                recorded = monitor
+                    .get_history()
+                    .await
+                    .iter()
+                    .any(|record| record.path == file_path);
+                if recorded {
+                    break;
+                }
+            }
+            assert!(recorded, "no event from inside the new directory");
+        });
+    }
+
+    #[test]
+    fn test_initial_scan_runs_alongside_watching() {
+        use std::io::Write;
+
+        let temp_dir = tempdir().unwrap();
+        for dir in ["a", "b", "c"] {
+            std::fs::create_dir(temp_dir.path().join(dir)).unwrap();
+            for i in 0..20 {
+                std::fs::write(temp_dir.path().join(dir).join(format!("{}.log", i)), "x").unwrap();
+            }
+        }
+        let log_path = temp_dir.path().join("a").join("big.log");
+        std::fs::write(&log_path, vec![b'x'; 4096]).unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .watch_mode(WatchMode::Recursive { max_depth: None })
+                .content_hashing(
+                    temp_dir.path(),
+                    HashPolicy {
+                        full_max_bytes: 1024,
+                        sampled_max_bytes: 1024 * 1024,
+                        incremental: true,
+                    },
+                )
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            monitor.wait_until_scanned().await;
+
+            let scans = monitor.get_scans();
+            assert_eq!(scans.len(), 1);
+            // The root, three directories and their files.
+            assert_eq!(scans[0].files_total, Some(65));
+            assert_eq!(scans[0].files_scanned, 65);
+            assert_eq!(scans[0].percent(), Some(100.0));
+            assert!(scans[0].is_finished());
+
+            // The scan fingerprinted the file, so an append is hashed incrementally.
+            std::fs::OpenOptions::new()
+                .append(true)
+                .open(&log_path)
+                .unwrap()
+                .write_all(b"appended")
+                .unwrap();
+            let mut content = None;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                content = monitor
+                    .get_history()
+                    .await
+                    .into_iter()
+                    .find(|record| record.path == log_path)
+                    .and_then(|record| record.content);
+                if content.is_some() {
+                    break;
+                }
+            }
+            assert_eq!(
+                content.expect("append not recorded").strategy,
+                HashStrategy::Incremental
+            );
+            task.abort();
+        });
+
+        // What a live event reported during the scan is left as the event handling saw it.
+        let attributes = Arc::new(std::sync::Mutex::new(AttributeTracker::default()));
+        let scan = Scan::new(temp_dir.path());
+        let touched = temp_dir.path().join("b").join("1.log");
+        scan.touch(&touched);
+        scan.run(
+            &ScanTargets {
+                attributes: Arc::clone(&attributes),
+                hasher: None,
+            },
+            RecursiveMode::Recursive,
+        );
+        let attributes = attributes.lock().unwrap();
+        assert!(attributes.last_known(&touched).is_none());
+        assert!(attributes
+            .last_known(&temp_dir.path().join("b").join("2.log"))
+            .is_some());
+    }
+
+    #[test]
+    fn test_default_backend_applies_to_watches_without_their_own() {
+        let temp_dir = tempdir().unwrap();
+        let native = temp_dir.path().join("local");
+        std::fs::create_dir(&native).unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .default_backend(WatchBackend::poll(Duration::from_millis(100)))
+                .backend(&native, WatchBackend::Native)
+                .watch(&native)
+                .build(),
+        );
+        assert_eq!(
+            monitor.get_watch_backend(temp_dir.path()),
+            WatchBackend::poll(Duration::from_millis(100))
+        );
+        assert_eq!(monitor.get_watch_backend(&native), WatchBackend::Native);
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let polled_file = temp_dir.path().join("remote.txt");
+            let native_file = native.join("local.txt");
+            std::fs::write(&polled_file, "data").unwrap();
+            std::fs::write(&native_file, "data").unwrap();
+            let mut seen = (false, false);
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let history = monitor.get_history().await;
+                seen = (
+                    history.iter().any(|record| record.path == polled_file),
+                    history.iter().any(|record| record.path == native_file),
+                );
+                if seen == (true, true) {
+                    break;
+                }
+            }
+            assert_eq!(seen, (true, true));
+
+            monitor.remove_watch(&native).await.unwrap();
+            assert!(monitor.remove_watch(&native).await.is_err());
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_follow_names_rewatches_recreated_files() {
         let temp_dir = tempdir().unwrap();
-        let monitor = FileMonitor::new(temp_dir.path());
+        let log = temp_dir.path().join("app.log");
+        std::fs::write(&log, "first\n").unwrap();
+        let monitor = Arc::new(FileMonitor::builder(&log).follow_names(true).build());
+
         let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let count = |history: &[FileEventRecord], kind: &str| {
+                history
+                    .iter()
+                    .filter(|record| record.event.kind() == kind)
+                    .count()
+            };
+            let wait_for = |kind: &'static str, expected: usize| {
+                let monitor = Arc::clone(&monitor);
+                async move {
+                    for _ in 0..50 {
+                        if count(&monitor.get_history().await, kind) >= expected {
+                            return true;
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    false
+                }
+            };
+
+            // Log rotation: the file is renamed away and a new one takes its name.
+            std::fs::rename(&log, temp_dir.path().join("app.log.1")).unwrap();
+            assert!(wait_for("deleted", 1).await);
+            std::fs::write(&log, "second\n").unwrap();
+            assert!(wait_for("recreated", 1).await);
+
+            // Atomic save: a temp file is written and renamed over it.
+            let temp = temp_dir.path().join("app.log.tmp");
+            std::fs::write(&temp, "third\n").unwrap();
+            std::fs::rename(&temp, &log).unwrap();
+            assert!(wait_for("recreated", 2).await);
+
+            let modified = count(&monitor.get_history().await, "modified");
+            std::fs::write(&log, "fourth\n").unwrap();
+            assert!(wait_for("modified", modified + 1).await);
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_modifications_tell_appends_from_truncation() {
+        use std::io::Write;
 
+        let temp_dir = tempdir().unwrap();
+        let log = temp_dir.path().join("app.log");
+        std::fs::write(&log, "first line\n").unwrap();
+        let monitor = Arc::new(FileMonitor::new(&log));
+
+        let rt = Runtime::new().unwrap();
         rt.block_on(async {
-            assert!(monitor.current_path.lock().await.exists());
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            monitor.wait_until_scanned().await;
+
+            let size_changes = || async {
+                monitor
+                    .get_history()
+                    .await
+                    .iter()
+                    .filter_map(|record| record.size_change)
+                    .collect::<Vec<_>>()
+            };
+            let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+            file.write_all(b"second line\n").unwrap();
+            drop(file);
+            for _ in 0..50 {
+                if !size_changes().await.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert_eq!(size_changes().await, vec![SizeChange::Appended(12)]);
+
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&log)
+                .unwrap()
+                .set_len(0)
+                .unwrap();
+            for _ in 0..50 {
+                if size_changes().await.len() > 1 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert_eq!(
+                size_changes().await,
+                vec![SizeChange::Appended(12), SizeChange::Truncated]
+            );
+            task.abort();
         });
     }
 
     #[test]
-    fn test_add_path_substitution() {
+    fn test_callback_thread_survives_panics() {
+        let (delivered_tx, delivered) = std::sync::mpsc::channel();
+        let (panicked_tx, panicked) = std::sync::mpsc::channel();
+        let thread = CallbackThread::spawn(
+            16,
+            move |event: notify::Result<Event>| {
+                let event = event.unwrap();
+                assert!(!event.paths[0].ends_with("poison"), "poisoned event");
+                delivered_tx.send(event.paths[0].clone()).unwrap();
+            },
+            move || panicked_tx.send(()).unwrap(),
+            || {},
+        )
+        .unwrap();
+        let event = |path: &str| Ok(Event::new(EventKind::Any).add_path(PathBuf::from(path)));
+        thread.send(event("/tmp/poison"));
+        thread.send(event("/tmp/after"));
+
+        let timeout = Duration::from_secs(5);
+        assert!(panicked.recv_timeout(timeout).is_ok());
+        assert_eq!(
+            delivered.recv_timeout(timeout).unwrap(),
+            PathBuf::from("/tmp/after")
+        );
+
+        // A stuck callback holds one event and queues one more; the rest are dropped.
+        let (release_tx, release) = std::sync::mpsc::channel::<()>();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counted = Arc::clone(&dropped);
+        let stuck = CallbackThread::spawn(
+            1,
+            move |_| {
+                let _ = release.recv();
+            },
+            || {},
+            move || {
+                counted.fetch_add(1, Ordering::Relaxed);
+            },
+        )
+        .unwrap();
+        stuck.send(event("/tmp/held"));
+        std::thread::sleep(Duration::from_millis(100));
+        for _ in 0..5 {
+            stuck.send(event("/tmp/burst"));
+        }
+        assert_eq!(dropped.load(Ordering::Relaxed), 4);
+        drop(release_tx);
+
         let temp_dir = tempdir().unwrap();
-        let monitor = FileMonitor::new(temp_dir.path());
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .isolate_callback(true)
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let file = temp_dir.path().join("isolated.txt");
+            std::fs::write(&file, "data").unwrap();
+            let mut seen = false;
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                seen = monitor
+                    .get_history()
+                    .await
+                    .iter()
+                    .any(|record| record.path == file);
+                if seen {
+                    break;
+                }
+            }
+            assert!(seen);
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_tailed_modifications_carry_appended_lines() {
+        use std::io::Write;
+
+        let temp_dir = tempdir().unwrap();
+        let log = temp_dir.path().join("app.log");
+        std::fs::write(&log, "old line\n").unwrap();
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+
         let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            monitor.wait_until_scanned().await;
+
+            let append = |text: &str| {
+                let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+                file.write_all(text.as_bytes()).unwrap();
+            };
+            let appends = || async {
+                monitor
+                    .get_history()
+                    .await
+                    .into_iter()
+                    .filter(|record| record.size_change.is_some())
+                    .map(|record| record.appended_lines)
+                    .collect::<Vec<_>>()
+            };
+            let wait_for = |count: usize| async move {
+                for _ in 0..50 {
+                    if appends().await.len() >= count {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            };
+
+            append("not tailed\n");
+            wait_for(1).await;
+            monitor.tail(&log).await.unwrap();
+            assert!(monitor.tail(&log).await.is_err());
+            append("first\r\nsecond\n");
+            wait_for(2).await;
+            assert_eq!(
+                appends().await,
+                vec![None, Some(vec!["first".to_string(), "second".to_string()])]
+            );
 
+            monitor.untail(&log).await.unwrap();
+            assert!(monitor.get_tailed().await.is_empty());
+            task.abort();
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_enrichers_add_fields_and_are_isolated() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let scripts = tempdir().unwrap();
+        let lookup = scripts.path().join("cmdb-lookup");
+        // Answers only once it has read the event from its standard input.
+        std::fs::write(
+            &lookup,
+            "#!/bin/sh\ngrep -q '\"Created\"' && echo '{\"asset\": \"web-01\"}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&lookup, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = MonitorConfig::parse(&format!(
+            r#"
+[[enrichers]]
+name = "cmdb"
+command = {:?}
+
+[[enrichers]]
+name = "broken"
+command = "sh -c 'exit 3'"
+"#,
+            lookup
+        ))
+        .unwrap();
+        let builder = FileMonitor::builder(temp_dir.path())
+            .enricher(Enricher::function(
+                "owner",
+                |record: FileEventRecord| async move {
+                    let mut fields = serde_json::Map::new();
+                    fields.insert("event".to_string(), record.event.kind().into());
+                    fields.insert("team".to_string(), "payments".into());
+                    Ok(fields)
+                },
+            ))
+            .enricher(Enricher::function("panics", |_| async {
+                panic!("lookup exploded")
+            }))
+            .enricher(
+                Enricher::function("slow", |_| async {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    Ok(serde_json::Map::new())
+                })
+                .timeout(Duration::from_millis(100)),
+            );
+        let monitor = config.apply(builder).unwrap().build();
+
+        Runtime::new().unwrap().block_on(async {
+            let started = Instant::now();
+            monitor
+                .handle_event(temp_dir.path().join("a.txt"), FileEvent::Created)
+                .await
+                .unwrap();
+            assert!(started.elapsed() < Duration::from_secs(5));
+            let history = monitor.get_history().await;
+            assert_eq!(history.len(), 1);
+            let enrichment = &history[0].enrichment;
+            assert_eq!(enrichment.keys().collect::<Vec<_>>(), vec!["cmdb", "owner"]);
+            assert_eq!(enrichment["cmdb"]["asset"], "web-01");
+            assert_eq!(enrichment["owner"]["event"], "created");
+            assert_eq!(enrichment["owner"]["team"], "payments");
+        });
+    }
+
+    #[test]
+    fn test_full_queue_drops_are_counted_and_recorded() {
+        let temp_dir = tempdir().unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .channel_capacity(1)
+                .drop_when_full(true)
+                .history_size(1000)
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
         rt.block_on(async {
+            // A slow hook keeps the monitor busy, so the queue fills up.
             monitor
-                .add_path_substitution("/original/path", "/substituted/path")
+                .on_event(|_| std::thread::sleep(Duration::from_millis(20)))
+                .await;
+            let watcher = Arc::clone(&monitor);
+            tokio::spawn(async move { watcher.monitor().await });
+            monitor.wait_until_watching().await;
+
+            for i in 0..100 {
+                std::fs::write(temp_dir.path().join(format!("{}.txt", i)), "x").unwrap();
+            }
+            let reported = || async {
+                monitor
+                    .get_history()
+                    .await
+                    .iter()
+                    .filter_map(|record| match record.event {
+                        FileEvent::EventsDropped(dropped) => Some(dropped),
+                        _ => None,
+                    })
+                    .sum::<u64>()
+            };
+            let deadline = Instant::now() + Duration::from_secs(20);
+            let (mut dropped, mut recorded) = (0, 0);
+            while Instant::now() < deadline {
+                dropped = monitor.get_dropped_events();
+                recorded = reported().await;
+                if dropped > 0 && recorded == dropped {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert!(dropped > 0);
+            assert_eq!(recorded, dropped);
+            let record = monitor
+                .get_history()
                 .await
+                .into_iter()
+                .find(|record| matches!(record.event, FileEvent::EventsDropped(_)))
                 .unwrap();
-            let substitutions = monitor.path_substitutions.lock().await;
+            assert_eq!(record.path, temp_dir.path().canonicalize().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_shutdown_stops_monitor_after_handling_queued_events() {
+        let temp_dir = tempdir().unwrap();
+        let idle = FileMonitor::new(temp_dir.path());
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Not running: returns right away.
+            tokio::time::timeout(Duration::from_secs(1), idle.shutdown())
+                .await
+                .unwrap();
+
+            let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+            let watcher = Arc::clone(&monitor);
+            let running = tokio::spawn(async move { watcher.monitor().await });
+            monitor.wait_until_watching().await;
+            std::fs::write(temp_dir.path().join("before.txt"), "x").unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while monitor.get_history().await.is_empty() && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+
+            tokio::time::timeout(Duration::from_secs(5), monitor.shutdown())
+                .await
+                .unwrap();
+            tokio::time::timeout(Duration::from_secs(1), running)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert!(monitor.shutdown_token().is_cancelled());
+            let recorded = monitor.get_history().await.len();
+            assert!(recorded > 0);
+
+            std::fs::write(temp_dir.path().join("after.txt"), "x").unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert_eq!(monitor.get_history().await.len(), recorded);
+        });
+    }
+
+    #[test]
+    fn test_monitor_manager_runs_named_monitors_independently() {
+        let app_dir = tempdir().unwrap();
+        let db_dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let manager = MonitorManager::new();
+            let app = manager.add_path("app", app_dir.path()).await.unwrap();
+            manager.add_path("db", db_dir.path()).await.unwrap();
+            assert!(manager.add_path("app", db_dir.path()).await.is_err());
+            assert!(manager.add_path("bad name", db_dir.path()).await.is_err());
+            manager.start("app").await.unwrap();
+            manager.start("db").await.unwrap();
+            app.wait_until_watching().await;
+            manager.get("db").await.unwrap().wait_until_watching().await;
+
+            manager.pause("db").await.unwrap();
+            std::fs::write(app_dir.path().join("app.txt"), "x").unwrap();
+            std::fs::write(db_dir.path().join("db.txt"), "x").unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while app.get_history().await.is_empty() && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let history = manager.get_history().await;
+            assert!(!history.is_empty());
+            assert!(history.iter().all(|(name, _)| name == "app"));
+            let status = manager.status().await;
+            assert_eq!(status.len(), 2);
+            assert!(status[0].running && !status[0].paused && status[0].events > 0);
+            assert!(status[1].running && status[1].paused && status[1].events == 0);
             assert_eq!(
-                substitutions.get(Path::new("/original/path")),
-                Some(&PathBuf::from("/substituted/path"))
+                manager.get_stats().await.values().sum::<usize>(),
+                status[0].events
             );
+
+            // A stopped monitor starts over with a new one.
+            manager.stop("app").await.unwrap();
+            assert!(!manager.status().await[0].running);
+            manager.start("app").await.unwrap();
+            let restarted = manager.get("app").await.unwrap();
+            assert!(!Arc::ptr_eq(&app, &restarted));
+            restarted.wait_until_watching().await;
+            assert!(manager.status().await[0].running);
+
+            manager.remove("db").await.unwrap();
+            assert_eq!(manager.names().await, ["app"]);
+            assert!(manager.start("db").await.is_err());
+            tokio::time::timeout(Duration::from_secs(5), manager.shutdown())
+                .await
+                .unwrap();
+            assert!(restarted.shutdown_token().is_cancelled());
         });
     }
 
     #[test]
-    fn test_get_substituted_path() {
+    fn test_trashed_files_are_told_apart_and_restored() {
         let temp_dir = tempdir().unwrap();
-        let monitor = FileMonitor::new(temp_dir.path());
+        let watched = temp_dir.path().join("watched");
+        let trash_dir = temp_dir.path().join("Trash");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::create_dir_all(trash_dir.join("files")).unwrap();
+        std::fs::create_dir_all(trash_dir.join("info")).unwrap();
+        let report = watched.join("report.txt");
+        let scratch = watched.join("scratch.txt");
+        std::fs::write(&report, "quarterly numbers").unwrap();
+        std::fs::write(&scratch, "notes").unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(&watched)
+                .trash(Trash::Xdg(trash_dir.clone()))
+                .build(),
+        );
+
         let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // What a file manager does: record the original path, then move the file.
+            let info = trash_dir.join("info/report.txt.trashinfo");
+            std::fs::write(
+                &info,
+                format!(
+                    "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                    report.display(),
+                    Local::now().format("%Y-%m-%dT%H:%M:%S")
+                ),
+            )
+            .unwrap();
+            let location = trash_dir.join("files/report.txt");
+            std::fs::rename(&report, &location).unwrap();
+            std::fs::remove_file(&scratch).unwrap();
 
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let history = loop {
+                let history = monitor.get_history().await;
+                let kinds: Vec<_> = history.iter().map(|record| record.event.kind()).collect();
+                if (kinds.contains(&"trashed") && kinds.contains(&"deleted"))
+                    || Instant::now() > deadline
+                {
+                    break history;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            };
+            let event_for = |path: &Path| {
+                history
+                    .iter()
+                    .rev()
+                    .find(|record| record.path == path)
+                    .map(|record| record.event.clone())
+            };
+            assert_eq!(
+                event_for(&report),
+                Some(FileEvent::Trashed(location.clone()))
+            );
+            assert_eq!(event_for(&scratch), Some(FileEvent::Deleted));
+
+            assert_eq!(monitor.restore_from_trash(&report).await.unwrap(), report);
+            assert_eq!(
+                std::fs::read_to_string(&report).unwrap(),
+                "quarterly numbers"
+            );
+            assert!(!info.exists());
+            assert!(monitor.restore_from_trash(&report).await.is_err());
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_paused_path_is_silenced_while_others_are_recorded() {
+        let temp_dir = tempdir().unwrap();
+        let logs = temp_dir.path().join("logs");
+        let config = temp_dir.path().join("app.conf");
+        std::fs::create_dir_all(&logs).unwrap();
+        let noisy = logs.join("debug.log");
+        let monitor = FileMonitor::new(temp_dir.path());
+
+        let rt = Runtime::new().unwrap();
         rt.block_on(async {
+            assert!(monitor.pause_path("/definitely/not/watched").await.is_err());
+            monitor.pause_path(&logs).await.unwrap();
+            assert_eq!(monitor.get_paused_paths().await, vec![logs.clone()]);
+
             monitor
-                .add_path_substitution("/original/path", "/substituted/path")
+                .handle_event(noisy.clone(), FileEvent::Modified)
                 .await
                 .unwrap();
-            let substituted = monitor.get_substituted_path("/original/path").await;
-            assert_eq!(substituted, PathBuf::from("/substituted/path"));
+            monitor
+                .handle_event(config.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            let paths: Vec<_> = monitor
+                .get_history()
+                .await
+                .into_iter()
+                .map(|record| record.path)
+                .collect();
+            assert_eq!(paths, vec![config.clone()]);
+            assert!(!monitor.is_paused().await);
+
+            monitor.resume_path(&logs).await.unwrap();
+            assert!(monitor.resume_path(&logs).await.is_err());
+            monitor
+                .handle_event(noisy.clone(), FileEvent::Modified)
+                .await
+                .unwrap();
+            assert_eq!(monitor.get_history().await.last().unwrap().path, noisy);
         });
     }
 
     #[test]
-    fn test_open_file() {
+    fn test_timed_pause_resumes_and_records_window() {
         let temp_dir = tempdir().unwrap();
-        let monitor = FileMonitor::new(temp_dir.path());
+        let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+
         let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            monitor.pause_for(Duration::from_millis(300)).await.unwrap();
+            assert!(monitor.is_paused().await);
+            assert!(monitor.get_resume_time().is_some());
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while monitor.is_paused().await && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(!monitor.is_paused().await);
+            assert_eq!(monitor.get_resume_time(), None);
+            let history = monitor.get_history().await;
+            let Some(FileEvent::Paused { since, until }) =
+                history.last().map(|record| record.event.clone())
+            else {
+                panic!("pause window not recorded: {:?}", history);
+            };
+            assert!((until - since).num_milliseconds() >= 300);
+
+            // Resuming early ends the timed pause, with a window of its own.
+            monitor.pause_for(Duration::from_secs(3600)).await.unwrap();
+            monitor.resume().await.unwrap();
+            assert_eq!(monitor.get_history_filtered("paused").await.len(), 2);
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_unacknowledged_alerts_are_escalated_until_acknowledged() {
+        let temp_dir = tempdir().unwrap();
+        let store = temp_dir.path().join("alerts.json");
+        let mailed = temp_dir.path().join("mailed.json");
+        let config = MonitorConfig::parse(&format!(
+            r#"
+[escalation]
+store = "{}"
+rules = ["ignore-deletes"]
+
+[[escalation.steps]]
+after_mins = 60
+webhook = "http://127.0.0.1:9/pager"
 
+[[escalation.steps]]
+after_mins = 0
+command = "sh -c 'cat > \"$1\"' sh {}"
+"#,
+            store.display(),
+            mailed.display()
+        ))
+        .unwrap();
+        let policy = config.escalation.unwrap().policy().unwrap();
+        assert_eq!(policy.steps[0].after, Duration::ZERO);
+        let build = || {
+            FileMonitor::builder(temp_dir.path())
+                .rule(IgnoreDeletes)
+                .escalation(policy.clone())
+                .build()
+        };
+
+        let rt = Runtime::new().unwrap();
         rt.block_on(async {
-            let file_path = temp_dir.path().join("test_file.txt");
-            File::create(&file_path).unwrap();
+            let monitor = build();
+            monitor
+                .handle_event(temp_dir.path().join("dropper.sh"), FileEvent::Created)
+                .await
+                .unwrap();
+            let open = monitor.get_open_alerts().await;
+            assert_eq!(open.len(), 1);
+            assert_eq!(open[0].rule.as_deref(), Some("ignore-deletes"));
 
-            let opened_file = monitor.open_file(&file_path).await;
-            assert!(opened_file.is_ok());
+            // Only the first step is due; the pager is an hour away.
+            assert_eq!(monitor.escalate_alerts().await, 1);
+            assert_eq!(monitor.escalate_alerts().await, 0);
+            let mailed: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&mailed).unwrap()).unwrap();
+            assert_eq!(mailed["alert"]["id"], open[0].id.as_str());
+            assert_eq!(mailed["escalation"], 1);
+
+            // Tracked across restarts.
+            let monitor = build();
+            let open = monitor.get_open_alerts().await;
+            assert_eq!(open.len(), 1);
+            assert_eq!(open[0].escalations, 1);
+            monitor
+                .acknowledge_alert(&open[0].id, "alice")
+                .await
+                .unwrap();
+            assert!(monitor.acknowledge_alert(&open[0].id, "bob").await.is_err());
+            assert!(monitor.get_open_alerts().await.is_empty());
+            assert_eq!(monitor.escalate_alerts().await, 0);
+        });
+    }
+
+    #[test]
+    fn test_full_enrichment_hashes_and_diffs_every_watch() {
+        Runtime::new().unwrap().block_on(async {
+            let temp_dir = tempdir().unwrap();
+            let primary = temp_dir.path().join("primary");
+            std::fs::create_dir(&primary).unwrap();
+            let file_path = temp_dir.path().join("app.conf");
+            std::fs::write(&file_path, "port = 80\n").unwrap();
+            let monitor = Arc::new(
+                FileMonitor::builder(&primary)
+                    .watch(&file_path)
+                    .full_enrichment()
+                    .build(),
+            );
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // Extra watches are snapshotted in the background, so keep changing the file
+            // until a change has a diff.
+            let mut detail = None;
+            for port in 8080..8130 {
+                std::fs::write(&file_path, format!("port = {}\n", port)).unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                for record in monitor.get_history_filtered("modified").await {
+                    if let Some(found) = monitor.get_history_detail(record.id).await {
+                        if found.record.content.is_some() && found.diff.is_some() {
+                            detail = Some(found);
+                        }
+                    }
+                }
+                if detail.is_some() {
+                    break;
+                }
+            }
+            task.abort();
+
+            let detail = detail.expect("no hashed modification recorded");
+            assert_eq!(detail.record.path, file_path);
+            assert!(detail.record.content.unwrap().sha256.is_some());
+            assert!(detail.diff.unwrap().contains("+port = 80"));
+        });
+    }
+
+    #[test]
+    fn test_errors_tell_what_went_wrong() {
+        Runtime::new().unwrap().block_on(async {
+            let temp_dir = tempdir().unwrap();
+            let extra = temp_dir.path().join("extra");
+            std::fs::create_dir(&extra).unwrap();
+            let monitor = Arc::new(FileMonitor::new(temp_dir.path()));
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            monitor.add_watch(&extra).await.unwrap();
+            assert!(matches!(
+                monitor.add_watch(&extra).await,
+                Err(MonitorError::AlreadyWatching(path)) if path == extra
+            ));
+            assert!(matches!(
+                monitor.add_watch(temp_dir.path().join("missing")).await,
+                Err(MonitorError::WatchFailed { .. })
+            ));
+            monitor.remove_watch(&extra).await.unwrap();
+            assert!(matches!(
+                monitor.remove_watch(&extra).await,
+                Err(MonitorError::NotWatching(_))
+            ));
+            assert!(matches!(
+                monitor.substitute_path("/elsewhere", "/display").await,
+                Err(MonitorError::SubstitutionMismatch { current, .. }) if current == temp_dir.path()
+            ));
+            assert!(matches!(
+                monitor.restore_backup("v1").await,
+                Err(MonitorError::NotEnabled(_))
+            ));
+            let error = monitor.get_watchset_stats("web").await.unwrap_err();
+            assert!(matches!(error, MonitorError::NotFound { .. }));
+            assert_eq!(error.to_string(), "No watchset named web");
+            task.abort();
+        });
+    }
+
+    #[test]
+    fn test_spill_queue_compacts_and_clears() {
+        let spill_dir = tempdir().unwrap();
+        let event = |i: usize| {
+            ReceivedEvent::now(
+                Event::new(EventKind::Any).add_path(PathBuf::from(format!("/tmp/{:04}", i))),
+            )
+        };
+        let line = serde_json::to_vec(&event(0)).unwrap().len() as u64 + 1;
+        let mut spill = SpillQueue::create(spill_dir.path(), line * 10).unwrap();
+        let size = || {
+            std::fs::metadata(
+                spill_dir
+                    .path()
+                    .read_dir()
+                    .unwrap()
+                    .next()
+                    .unwrap()
+                    .unwrap()
+                    .path(),
+            )
+            .unwrap()
+            .len()
+        };
+
+        // Never drained completely, yet it keeps taking events once the head is read.
+        let mut next = 0;
+        let mut expected = 0;
+        for _ in 0..5 {
+            while spill.push(&event(next)).unwrap() {
+                next += 1;
+            }
+            assert_eq!(size(), line * 10);
+            for popped in spill.pop(6).unwrap() {
+                assert_eq!(
+                    popped.paths,
+                    vec![PathBuf::from(format!("/tmp/{:04}", expected))]
+                );
+                expected += 1;
+            }
+            assert_eq!(size(), line * 4);
+        }
+
+        assert_eq!(spill.clear(), 4);
+        assert!(spill.is_empty());
+        assert_eq!(size(), 0);
+        assert!(spill.pop(6).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bounded_memory_spills_events_to_disk_in_order() {
+        let temp_dir = tempdir().unwrap();
+        let spill_dir = tempdir().unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(temp_dir.path())
+                .memory_limits(MemoryLimits {
+                    queued_events: 1,
+                    ..MemoryLimits::new(spill_dir.path())
+                })
+                .history_size(1000)
+                .build(),
+        );
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // A slow hook keeps the monitor busy, so the queue fills up.
+            monitor
+                .on_event(|_| std::thread::sleep(Duration::from_millis(5)))
+                .await;
+            let watcher = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { watcher.monitor().await });
+            monitor.wait_until_watching().await;
+
+            let root = temp_dir.path().canonicalize().unwrap();
+            let files: Vec<PathBuf> = (0..100).map(|i| root.join(format!("{}.txt", i))).collect();
+            for file in &files {
+                std::fs::write(file, "x").unwrap();
+            }
+            let created = || async {
+                monitor
+                    .get_history()
+                    .await
+                    .into_iter()
+                    .filter(|record| record.event == FileEvent::Created)
+                    .map(|record| record.path)
+                    .collect::<Vec<_>>()
+            };
+            let spill_file = std::fs::read_dir(spill_dir.path())
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+                .path();
+            let spilled_bytes = || std::fs::metadata(&spill_file).unwrap().len();
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while (created().await.len() < files.len() || spilled_bytes() > 0)
+                && Instant::now() < deadline
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert_eq!(created().await, files);
+            assert!(monitor.get_spilled_events() > 0);
+            assert_eq!(monitor.get_dropped_events(), 0);
+
+            // The spill file is emptied once drained and removed with the monitor.
+            assert_eq!(spilled_bytes(), 0);
+            monitor.shutdown().await;
+            task.await.unwrap().unwrap();
+            drop(Arc::try_unwrap(monitor).ok().unwrap());
+            // The backend thread lets go of its watcher callback, and with it the spill
+            // queue, shortly after the monitor.
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while spill_file.exists() && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(!spill_file.exists());
+        });
+
+        // Snapshots beyond the total limit are not kept, so they get no diffs.
+        let small = temp_dir.path().join("small.txt");
+        let large = temp_dir.path().join("large.txt");
+        std::fs::write(&small, "a".repeat(10)).unwrap();
+        std::fs::write(&large, "b".repeat(20)).unwrap();
+        let mut snapshots = TextSnapshots::new(100).with_total_limit(25);
+        snapshots.remember(&small);
+        snapshots.remember(&large);
+        assert_eq!(snapshots.total_bytes(), 10);
+        std::fs::write(&large, "c".repeat(20)).unwrap();
+        assert_eq!(snapshots.update(&large), None);
+        snapshots.forget(&small);
+        snapshots.remember(&large);
+        assert_eq!(snapshots.total_bytes(), 20);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_update_path_validates_and_resolves_paths() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let first = root.join("first");
+        let real = root.join("real");
+        std::fs::create_dir(&first).unwrap();
+        std::fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, root.join("link")).unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(root.join("socket")).unwrap();
+
+        Runtime::new().unwrap().block_on(async {
+            let strict = FileMonitor::builder(&first)
+                .canonicalize_paths(true)
+                .build();
+            let missing = root.join("missing");
+            match strict.update_path(&missing).await {
+                Err(MonitorError::PathNotFound(path)) => assert_eq!(path, missing),
+                result => panic!("expected PathNotFound, got {:?}", result),
+            }
+            assert!(matches!(
+                strict.update_path(root.join("socket")).await,
+                Err(MonitorError::WrongPathType { .. })
+            ));
+            assert_eq!(strict.get_watches().await[0], first);
+            strict.update_path(first.join("../link/.")).await.unwrap();
+            assert_eq!(strict.get_watches().await[0], real);
+
+            // Without canonicalization `..` is still resolved, but symlinks are kept.
+            let lexical = FileMonitor::new(&first);
+            lexical.update_path(real.join("../link")).await.unwrap();
+            assert_eq!(lexical.get_watches().await[0], root.join("link"));
+
+            // A missing path is waited for and watched once it appears.
+            let monitor = Arc::new(FileMonitor::builder(&first).allow_missing(true).build());
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+            monitor.update_path(&missing).await.unwrap();
+            assert_eq!(monitor.get_watches().await[0], missing);
+            std::fs::create_dir(&missing).unwrap();
+            let mut appeared = false;
+            for _ in 0..50 {
+                let history = monitor.get_history().await;
+                if history
+                    .iter()
+                    .any(|record| record.path == missing && record.event == FileEvent::Created)
+                {
+                    appeared = true;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(appeared);
+            monitor.shutdown().await;
+            task.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_missing_path_is_watched_once_created() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let log = root.join("service.log");
+        let monitor = Arc::new(FileMonitor::builder(&log).allow_missing(true).build());
+        assert!(matches!(
+            Runtime::new()
+                .unwrap()
+                .block_on(FileMonitor::new(root.join("gone/service.log")).monitor()),
+            Err(MonitorError::PathNotFound(_))
+        ));
+
+        Runtime::new().unwrap().block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // Other entries of the parent directory are not events of the watch.
+            std::fs::write(root.join("other.log"), "noise").unwrap();
+            std::fs::write(&log, "started\n").unwrap();
+            let wait_for = |event: FileEvent| {
+                let monitor = Arc::clone(&monitor);
+                async move {
+                    for _ in 0..50 {
+                        if monitor
+                            .get_history()
+                            .await
+                            .iter()
+                            .any(|record| record.event == event)
+                        {
+                            return true;
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    false
+                }
+            };
+            assert!(wait_for(FileEvent::Created).await);
+            std::fs::write(&log, "started\nready\n").unwrap();
+            assert!(wait_for(FileEvent::Modified).await);
+
+            let history = monitor.get_history().await;
+            assert!(history.iter().all(|record| record.path == log));
+            assert_eq!(
+                history
+                    .iter()
+                    .filter(|record| record.event == FileEvent::Created)
+                    .count(),
+                1
+            );
+            monitor.shutdown().await;
+            task.await.unwrap().unwrap();
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_symlink_policy_both_watches_target_and_link() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let target = root.join("service-1.log");
+        let link = root.join("service.log");
+        std::fs::write(&target, "started\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let monitor = Arc::new(
+            FileMonitor::builder(&link)
+                .symlink_policy(SymlinkPolicy::Both)
+                .build(),
+        );
+
+        Runtime::new().unwrap().block_on(async {
+            let task_monitor = Arc::clone(&monitor);
+            let task = tokio::spawn(async move { task_monitor.monitor().await });
+            monitor.wait_until_watching().await;
+
+            // Other entries of the link's directory are not events of the watch.
+            std::fs::write(root.join("other.log"), "noise").unwrap();
+            std::fs::write(&target, "started\nready\n").unwrap();
+            let wait_for = |event: FileEvent| {
+                let monitor = Arc::clone(&monitor);
+                async move {
+                    for _ in 0..50 {
+                        if let Some(record) = monitor
+                            .get_history()
+                            .await
+                            .into_iter()
+                            .find(|record| record.event == event)
+                        {
+                            return Some(record);
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    None
+                }
+            };
+            let modified = wait_for(FileEvent::Modified).await.unwrap();
+            assert_eq!(modified.path, link);
+            assert_eq!(modified.via_symlink, Some(link.clone()));
+
+            // Removing the link leaves the target alone, so only the link's directory
+            // sees it.
+            std::fs::remove_file(&link).unwrap();
+            let deleted = wait_for(FileEvent::Deleted).await.unwrap();
+            assert_eq!(deleted.path, link);
+
+            let history = monitor.get_history().await;
+            assert!(history.iter().all(|record| record.path == link));
+            monitor.shutdown().await;
+            task.await.unwrap().unwrap();
         });
     }
 }
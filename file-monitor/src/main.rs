@@ -1,60 +1,615 @@
 use anyhow::Result;
-use clap::Parser;
-use file_monitor_core::FileMonitor;
-use log::error;
+use clap::{Parser, Subcommand, ValueEnum};
+use file_monitor_core::diff::DEFAULT_DIFF_MAX_BYTES;
+use file_monitor_core::maintenance::DEFAULT_MAINTENANCE_LABEL;
+use file_monitor_core::{
+    bench, ctl, export, shutdown, ApiKeyStore, ApiScope, BackupPolicy, Baseline, BenchConfig,
+    ConfigManifest, ControlServer, ControlSocket, Drift, ExportFormat, FileEvent, FileMonitor,
+    FilterKind, GitFileStatus, GitStatusRule, HashPolicy, MemoryLimits, MonitorConfig,
+    MonitorEvent, MonitorManager, RateAlertRule, RateLimit, ReloadableTls, RestartPolicy,
+    ShutdownToken, StartupProfile, Supervisor, SymlinkPolicy, TlsSettings, Verdict, WatchBackend,
+    WatchMode,
+};
+use log::{error, info, warn};
+use std::fmt::Write;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::select;
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable log lines
+    Text,
+    /// One JSON object per event on stdout
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BackendArg {
+    /// The platform's change notifications
+    Native,
+    /// Rescan the watched paths every --interval, for NFS, SMB and FUSE mounts
+    Poll,
+    /// fanotify on Linux, recording the process behind each change; needs CAP_SYS_ADMIN
+    Fanotify,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymlinksArg {
+    /// Watch what the link points to
+    Follow,
+    /// Watch the link itself: it being created, replaced or removed
+    Link,
+    /// Watch both the link and what it points to
+    Both,
+}
+
+/// Parses `500ms`, `2s`, `5m` or a number of seconds.
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval {:?}", value))?;
+    let interval = match unit {
+        "ms" => Duration::from_millis(number),
+        "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        "h" => Duration::from_secs(number * 3600),
+        _ => return Err(format!("invalid interval unit in {:?}", value)),
+    };
+    if interval.is_zero() {
+        return Err("interval must be positive".to_string());
+    }
+    Ok(interval)
+}
+
+/// Parses a positive, finite number of requests per second.
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid rate {:?}", value))?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err("rate must be a positive number".to_string());
+    }
+    Ok(rate)
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send a command (as typed at the interactive prompt) to a running monitor
+    Ctl {
+        /// Control socket of the monitor; defaults to the path used by --control-socket
+        #[arg(long)]
+        socket: Option<PathBuf>,
+
+        #[arg(required = true, num_args = 1..)]
+        command: Vec<String>,
+    },
+    /// Generate synthetic load against a temp directory and report pipeline throughput,
+    /// latency percentiles and unrecorded events
+    Bench {
+        /// Number of files the writes are spread over
+        #[arg(long, default_value_t = 100)]
+        files: usize,
+
+        /// Writes per second
+        #[arg(long, default_value_t = 1000)]
+        rate: u64,
+
+        /// How long to generate load, in seconds
+        #[arg(long, default_value_t = 5)]
+        seconds: u64,
+
+        /// Debounce window of the benchmarked monitor in milliseconds
+        #[arg(long, default_value_t = 0)]
+        debounce_ms: u64,
+
+        /// Event queue capacity of the benchmarked monitor
+        #[arg(long, default_value_t = file_monitor_core::builder::DEFAULT_CHANNEL_CAPACITY)]
+        channel_capacity: usize,
+    },
+}
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Path to the file to monitor
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the file to monitor; may instead be set in the config file
     #[arg(short, long)]
-    path: PathBuf,
+    path: Option<PathBuf>,
+
+    /// TOML file with watched paths, substitutions, filters, history size, debounce window
+    /// and log level; changes to paths, substitutions and filters are applied live
+    #[arg(long)]
+    config: Option<PathBuf>,
 
     /// Enable debug logging
     #[arg(short, long)]
     debug: bool,
+
+    /// How events are printed; json prints them on stdout and keeps only warnings in the log
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Path whose events get a dedicated high-priority lane (repeatable), in addition to
+    /// the config file's `priority_paths`
+    #[arg(long = "priority-path")]
+    priority_paths: Vec<PathBuf>,
+
+    /// File, or directory of files, whose appended lines are printed and attached to
+    /// its events (repeatable)
+    #[arg(long = "tail")]
+    tailed: Vec<PathBuf>,
+
+    /// Capacity of the regular event queue
+    #[arg(long, default_value_t = file_monitor_core::builder::DEFAULT_CHANNEL_CAPACITY)]
+    channel_capacity: usize,
+
+    /// Drop events when the event queue is full instead of holding up the watcher, and
+    /// record how many were lost
+    #[arg(long)]
+    drop_when_full: bool,
+
+    /// Run with bounded memory: events beyond the queue are spilled to a temporary file
+    /// in this directory instead of being dropped or waited for
+    #[arg(long)]
+    spill_dir: Option<PathBuf>,
+
+    /// Watch the whole directory tree below the path
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// How paths are watched, unless the config file sets a backend for them
+    #[arg(long, value_enum, default_value_t = BackendArg::Native)]
+    backend: BackendArg,
+
+    /// Rescan interval of --backend poll, e.g. 500ms, 2s or 1m
+    #[arg(long, value_parser = parse_interval)]
+    interval: Option<Duration>,
+
+    /// What to watch for watched paths that are symlinks, unless the config file sets it
+    #[arg(long, value_enum)]
+    symlinks: Option<SymlinksArg>,
+
+    /// Keep watching a path that is deleted or renamed away once it is recreated, e.g.
+    /// by log rotation or an atomic save
+    #[arg(long)]
+    follow_name: bool,
+
+    /// Accept a path that does not exist yet, at startup or with `update`, and watch it
+    /// once it appears
+    #[arg(long)]
+    allow_missing: bool,
+
+    /// Resolve symlinks in paths passed to `update`, watching their real location
+    #[arg(long)]
+    canonicalize: bool,
+
+    /// Run the watcher callback on a dedicated thread that survives panics and rebuilds
+    /// the watcher after one
+    #[arg(long)]
+    isolate_callback: bool,
+
+    /// Record files moved to the trash as deleted rather than as trashed
+    #[arg(long)]
+    no_trash_detection: bool,
+
+    /// Maximum depth below the path to report events for (implies --recursive)
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Record a content fingerprint of changed files under the path, hashed in full,
+    /// sampled or by size and mtime depending on file size
+    #[arg(long)]
+    hash_content: bool,
+
+    /// Copy each created or modified file to a new version in this directory, for the
+    /// restore command
+    #[arg(long)]
+    backup_dir: Option<PathBuf>,
+
+    /// Versions kept per file in --backup-dir
+    #[arg(long, default_value_t = file_monitor_core::backup::DEFAULT_BACKUP_VERSIONS)]
+    backup_versions: usize,
+
+    /// Keep the content of watched text files and record a unified diff of each change
+    #[arg(long)]
+    diffs: bool,
+
+    /// Enrich events with git repository state and alert on new untracked executables
+    #[arg(long)]
+    git: bool,
+
+    /// Tag events on container filesystems with the owning container
+    #[arg(long)]
+    containers: bool,
+
+    /// File that persists monitoring coverage (pauses, watcher errors, downtime)
+    #[arg(long)]
+    coverage_file: Option<PathBuf>,
+
+    /// Address of the HTTP control endpoint; requests authenticate with an API key from
+    /// --api-keys-file, or with FILE_MONITOR_CONTROL_TOKEN which has the control scope
+    #[arg(long)]
+    control_addr: Option<SocketAddr>,
+
+    /// JSON file with hashed API keys for the control endpoint, managed with the apikey
+    /// command
+    #[arg(long)]
+    api_keys_file: Option<PathBuf>,
+
+    /// File that every control endpoint request is appended to as a JSON line
+    #[arg(long, requires = "control_addr")]
+    api_audit_log: Option<PathBuf>,
+
+    /// PEM certificate chain for serving the control endpoint over HTTPS; reloaded when it
+    /// changes
+    #[arg(long, requires_all = ["control_addr", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM CA certificates; control clients must present a certificate issued by one
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Control requests allowed per second (bursts of twice that); excess requests get 429
+    #[arg(long, default_value_t = 20.0, value_parser = parse_rate)]
+    control_rate_limit: f64,
+
+    /// Accept commands from `file_monitor ctl` on a Unix socket (named pipe on Windows),
+    /// by default $XDG_RUNTIME_DIR/file-monitor.sock; keeps running when stdin is closed
+    #[arg(long)]
+    control_socket: Option<Option<PathBuf>>,
+
+    /// JSON policy file with filters; reloaded automatically when it changes
+    #[arg(long)]
+    policy_file: Option<PathBuf>,
+
+    /// Signed manifest of allowed policy file hashes; the key is read from FILE_MONITOR_MANIFEST_KEY
+    #[arg(long, requires = "policy_file")]
+    manifest: Option<PathBuf>,
+
+    /// Write a signed manifest for the current policy file to --manifest and exit
+    #[arg(long, requires = "manifest")]
+    sign_manifest: bool,
+
+    /// Capacity of the high-priority event queue, unless the config file sets it
+    #[arg(long)]
+    priority_channel_capacity: Option<usize>,
+
+    /// Seconds to wait on exit (quit, Ctrl-C, SIGTERM) for queued events to be handled
+    /// before remaining tasks are aborted
+    #[arg(long, default_value_t = 10)]
+    shutdown_timeout: u64,
+
+    /// Print how long each startup phase took, the memory footprint and binary size once
+    /// the watcher is running
+    #[arg(long)]
+    profile_startup: bool,
 }
 
+/// How long --profile-startup waits for the watcher before reporting without it.
+const STARTUP_PROFILE_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    if let Some(Command::Ctl { socket, command }) = &cli.command {
+        let socket = match socket {
+            Some(socket) => socket.clone(),
+            None => ctl::default_socket_path()?,
+        };
+        print!("{}", ctl::send(&socket, &command.join(" ")).await?);
+        return Ok(());
+    }
+    if let Some(Command::Bench {
+        files,
+        rate,
+        seconds,
+        debounce_ms,
+        channel_capacity,
+    }) = cli.command
+    {
+        let report = bench::run(BenchConfig {
+            files,
+            events_per_sec: rate,
+            duration: Duration::from_secs(seconds),
+            channel_capacity,
+            debounce: Duration::from_millis(debounce_ms),
+        })
+        .await?;
+        print!("{}", report.summary());
+        return Ok(());
+    }
+    let mut profile = StartupProfile::new();
+    let config = match &cli.config {
+        Some(path) => MonitorConfig::load(path)?,
+        None => MonitorConfig::default(),
+    };
+
+    let default_log_level = match (cli.debug, cli.output, &config.log_level) {
+        (true, _, _) => "debug",
+        (false, _, Some(level)) => level.as_str(),
+        (false, OutputFormat::Text, None) => "info",
+        // Events are printed as JSON, the human-readable event lines would duplicate them.
+        (false, OutputFormat::Json, None) => "warn",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_log_level))
+        .init();
+    profile.phase("config load");
+    let Some(path) = cli.path.clone().or_else(|| config.path.clone()) else {
+        return Err(anyhow::anyhow!(
+            "No path to monitor: pass --path or set path in the config file"
+        ));
+    };
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(if cli.debug {
-        "debug"
-    } else {
-        "info"
-    }))
-    .init();
+    let manifest_key = match &cli.manifest {
+        Some(_) => Some(std::env::var("FILE_MONITOR_MANIFEST_KEY").map_err(|_| {
+            anyhow::anyhow!("--manifest requires FILE_MONITOR_MANIFEST_KEY to be set")
+        })?),
+        None => None,
+    };
+    if cli.sign_manifest {
+        if let (Some(policy), Some(manifest), Some(key)) =
+            (&cli.policy_file, &cli.manifest, &manifest_key)
+        {
+            ConfigManifest::create(&[std::fs::canonicalize(policy)?], key.as_bytes())?
+                .save(manifest)?;
+            println!("Signed manifest written to {}", manifest.display());
+        }
+        return Ok(());
+    }
 
-    let monitor = Arc::new(FileMonitor::new(cli.path));
+    let mut builder = config.apply(
+        FileMonitor::builder(&path)
+            .channel_capacity(cli.channel_capacity)
+            .drop_when_full(cli.drop_when_full),
+    )?;
+    if let Some(capacity) = cli.priority_channel_capacity {
+        builder = builder.priority_channel_capacity(capacity);
+    }
+    if let Some(spill_dir) = &cli.spill_dir {
+        builder = builder.memory_limits(MemoryLimits {
+            queued_events: cli.channel_capacity,
+            ..MemoryLimits::new(spill_dir)
+        });
+    }
+    if let Some(config_path) = &cli.config {
+        builder = builder.config_file(config_path, config.clone());
+    }
+    let shutdown = ShutdownToken::new();
+    builder = builder.shutdown_token(shutdown.child());
+    builder = builder.default_backend(match (cli.backend, cli.interval) {
+        (BackendArg::Poll, interval) => WatchBackend::poll(
+            interval.unwrap_or(file_monitor_core::backend::DEFAULT_POLL_INTERVAL),
+        ),
+        (_, Some(_)) => anyhow::bail!("--interval needs --backend poll"),
+        (BackendArg::Native, None) => WatchBackend::Native,
+        (BackendArg::Fanotify, None) => WatchBackend::Fanotify,
+    });
+    if let Some(symlinks) = cli.symlinks {
+        builder = builder.symlink_policy(match symlinks {
+            SymlinksArg::Follow => SymlinkPolicy::Follow,
+            SymlinksArg::Link => SymlinkPolicy::Link,
+            SymlinksArg::Both => SymlinkPolicy::Both,
+        });
+    }
+    builder = builder
+        .follow_names(cli.follow_name)
+        .allow_missing(cli.allow_missing)
+        .canonicalize_paths(cli.canonicalize)
+        .isolate_callback(cli.isolate_callback)
+        .trash_detection(!cli.no_trash_detection);
+    if cli.recursive || cli.max_depth.is_some() {
+        builder = builder.watch_mode(WatchMode::Recursive {
+            max_depth: cli.max_depth,
+        });
+    }
+    for priority_path in cli.priority_paths {
+        builder = builder.priority_path(priority_path);
+    }
+    for tailed in cli.tailed {
+        builder = builder.tail(tailed);
+    }
+    if let Some(coverage_file) = cli.coverage_file {
+        builder = builder.coverage_file(coverage_file);
+    }
+    if let Some(policy_file) = cli.policy_file {
+        builder = builder.policy_file(policy_file);
+    }
+    if let (Some(manifest), Some(key)) = (cli.manifest, manifest_key) {
+        builder = builder.config_manifest(manifest, key);
+    }
+    if cli.containers {
+        builder = builder.container_awareness(true);
+    }
+    if cli.hash_content {
+        builder = builder.content_hashing(&path, HashPolicy::default());
+    }
+    if let Some(dir) = &cli.backup_dir {
+        builder = builder.backups(BackupPolicy {
+            max_versions: cli.backup_versions,
+            ..BackupPolicy::new(dir)
+        });
+    }
+    if cli.diffs && config.diff_max_kb.is_none() {
+        builder = builder.text_diffs(DEFAULT_DIFF_MAX_BYTES);
+    }
+    if cli.git {
+        builder = builder.git_integration(true).rule(
+            GitStatusRule::new(
+                "untracked-executable",
+                GitFileStatus::Untracked,
+                Verdict::Alert("untracked executable appeared in repository".to_string()),
+            )
+            .on_event(FileEvent::Created)
+            .executable_only(),
+        );
+    }
+    let monitor = Arc::new(builder.try_build()?);
+    profile.phase("monitor build");
+    // Stopped only after the monitor, so events drained during shutdown are still printed.
+    let sink_shutdown = ShutdownToken::new();
+    let sink = (cli.output == OutputFormat::Json).then(|| {
+        let mut events = monitor.subscribe();
+        let monitor = Arc::clone(&monitor);
+        let sink_shutdown = sink_shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = select! {
+                    event = events.recv() => event,
+                    _ = sink_shutdown.cancelled() => events.try_recv(),
+                };
+                match event {
+                    Some(MonitorEvent::File(record)) => {
+                        let substituted_path = monitor.get_substituted_path(&record.path).await;
+                        println!("{}", export::event_line(&record, &substituted_path));
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        })
+    });
+    // In JSON output the lines are part of the event objects already.
+    if cli.output == OutputFormat::Text {
+        let mut events = monitor.subscribe();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let MonitorEvent::File(record) = event {
+                    for line in record.appended_lines.iter().flatten() {
+                        println!("{}: {}", record.path.display(), line);
+                    }
+                }
+            }
+        });
+    }
+    let supervisor = Supervisor::new().with_shutdown(shutdown);
+    let manager = MonitorManager::new();
     let monitor_clone = Arc::clone(&monitor);
-    let mut monitor_handle = tokio::spawn(async move { monitor_clone.monitor().await });
+    let mut monitor_handle = supervisor.spawn("watcher", RestartPolicy::default(), move || {
+        let monitor = Arc::clone(&monitor_clone);
+        async move { monitor.monitor().await }
+    });
+    let api_keys = Arc::new(match &cli.api_keys_file {
+        Some(path) => ApiKeyStore::load(path)?,
+        None => ApiKeyStore::new(),
+    });
+    if let Some(addr) = cli.control_addr {
+        match std::env::var("FILE_MONITOR_CONTROL_TOKEN") {
+            Ok(token) => api_keys.insert_ephemeral("default", &token, ApiScope::Control)?,
+            Err(_) if cli.api_keys_file.is_some() => {}
+            Err(_) => {
+                return Err(anyhow::anyhow!(
+                    "--control-addr requires --api-keys-file or FILE_MONITOR_CONTROL_TOKEN"
+                ))
+            }
+        }
+        let mut server = ControlServer::with_api_keys(Arc::clone(&monitor), Arc::clone(&api_keys))
+            .with_supervisor(supervisor.clone())
+            .with_rate_limit(RateLimit {
+                per_second: cli.control_rate_limit,
+                burst: (cli.control_rate_limit * 2.0).ceil() as u32,
+            });
+        if let Some(audit_log) = &cli.api_audit_log {
+            server = server.with_audit_log(audit_log);
+        }
+        if let (Some(cert), Some(key)) = (&cli.tls_cert, &cli.tls_key) {
+            let mut settings = TlsSettings::new(cert, key);
+            if let Some(client_ca) = &cli.tls_client_ca {
+                settings = settings.client_ca(client_ca);
+            }
+            server = server.with_tls(Arc::new(ReloadableTls::new(settings)?));
+        }
+        supervisor.spawn("control-server", RestartPolicy::default(), move || {
+            server.clone().run(addr)
+        });
+    }
+
+    let (ctl_tx, mut ctl_rx) = mpsc::channel(16);
+    if let Some(socket) = &cli.control_socket {
+        let socket = match socket {
+            Some(socket) => socket.clone(),
+            None => ctl::default_socket_path()?,
+        };
+        let socket = ControlSocket::new(socket, supervisor.shutdown_token());
+        supervisor.spawn("control-socket", RestartPolicy::default(), move || {
+            socket.clone().run(ctl_tx.clone())
+        });
+    }
+
+    if cli.profile_startup {
+        match tokio::time::timeout(STARTUP_PROFILE_TIMEOUT, monitor.wait_until_watching()).await {
+            Ok(()) => profile.phase("watcher setup"),
+            Err(_) => warn!(
+                "Watcher did not start within {:?}, profiling without it",
+                STARTUP_PROFILE_TIMEOUT
+            ),
+        }
+        // Stderr, so the report does not mix with --output json events.
+        eprint!("{}", profile.report());
+    }
 
-    println!("File monitor started. Type 'help' for available commands.");
+    if cli.output == OutputFormat::Text {
+        println!("File monitor started. Type 'help' for available commands.");
+    }
 
     let mut reader = BufReader::new(tokio::io::stdin()).lines();
+    let signal = shutdown::signal();
+    tokio::pin!(signal);
+    let mut stdin_open = true;
 
     loop {
         select! {
+            result = &mut signal => {
+                match result {
+                    Ok(()) => info!("Shutdown requested"),
+                    Err(e) => error!("Failed to listen for shutdown signals: {}", e),
+                }
+                break;
+            }
             result = &mut monitor_handle => {
                 match result {
                     Ok(Ok(())) => println!("Monitor finished successfully"),
-                    Ok(Err(e)) => error!("Monitor error: {}", e),
+                    Ok(Err(e)) => error!("Monitor stopped: {}", e),
                     Err(e) => error!("Monitor task error: {}", e),
                 }
                 break;
             }
-            result = reader.next_line() => {
+            Some(request) = ctl_rx.recv() => {
+                let mut out = String::new();
+                let keep_running =
+                    handle_command(&monitor, &manager, &supervisor, &api_keys, &request.command, &mut out).await?;
+                let _ = request.reply.send(out);
+                if !keep_running {
+                    break;
+                }
+            }
+            result = reader.next_line(), if stdin_open => {
                 match result {
                     Ok(Some(line)) => {
-                        if !handle_command(&monitor, line.trim()).await? {
+                        let mut out = String::new();
+                        let keep_running =
+                            handle_command(&monitor, &manager, &supervisor, &api_keys, line.trim(), &mut out).await?;
+                        print!("{}", out);
+                        if !keep_running {
                             break;
                         }
                     }
+                    // A daemon without a terminal is still controlled through the socket.
+                    Ok(None) if cli.control_socket.is_some() => stdin_open = false,
                     Ok(None) => break,
                     Err(e) => {
                         error!("Failed to read line: {}", e);
@@ -65,33 +620,234 @@ async fn main() -> Result<()> {
         }
     }
 
+    // The monitor first, so its queued events reach the sinks that are still running.
+    let timeout = Duration::from_secs(cli.shutdown_timeout);
+    let deadline = tokio::time::Instant::now() + timeout;
+    if tokio::time::timeout(timeout, monitor.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("Monitor did not stop within {:?}", timeout);
+    }
+    let timeout = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if tokio::time::timeout(timeout, manager.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("Managed monitors did not stop within {:?}", timeout);
+    }
+    let timeout = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if !supervisor.shutdown(timeout).await {
+        warn!("Tasks did not stop within {:?} and were aborted", timeout);
+    }
+    if let Some(sink) = sink {
+        sink_shutdown.cancel();
+        let _ = sink.await;
+    }
+
     Ok(())
 }
 
-async fn handle_command(monitor: &Arc<FileMonitor>, command: &str) -> Result<bool> {
+async fn handle_command(
+    monitor: &Arc<FileMonitor>,
+    manager: &MonitorManager,
+    supervisor: &Supervisor,
+    api_keys: &ApiKeyStore,
+    command: &str,
+    out: &mut String,
+) -> Result<bool> {
     match command.split_whitespace().collect::<Vec<_>>().as_slice() {
         ["help"] => {
-            println!("Available commands:");
-            println!("  update <new_path> - Update the monitored file path");
-            println!("  substitute <old_path> <new_path> - Substitute displayed path");
-            println!(
+            writeln!(out, "Available commands:")?;
+            writeln!(out, "  update <new_path> - Update the monitored file path")?;
+            writeln!(
+                out,
+                "  substitute <old_path> <new_path> - Substitute displayed path"
+            )?;
+            writeln!(
+                out,
                 "  add_substitution <original_path> <substitute_path> - Add a path substitution"
-            );
-            println!("  remove_substitution <original_path> - Remove a path substitution");
-            println!("  pause - Pause monitoring");
-            println!("  resume - Resume monitoring");
-            println!("  stats - Show event statistics");
-            println!("  history - Show recent event history");
-            println!("  quit - Exit the program");
+            )?;
+            writeln!(
+                out,
+                "  remove_substitution <original_path> - Remove a path substitution"
+            )?;
+            writeln!(
+                out,
+                "  add_filter <include|exclude> <glob> - Only record (or drop) matching paths"
+            )?;
+            writeln!(out, "  remove_filter <glob> - Remove a filter")?;
+            writeln!(out, "  filters - Show active filters")?;
+            writeln!(out,
+                "  webhook add <url> [event...] - POST matching events (all if none given) to a URL"
+            )?;
+            writeln!(out, "  webhook remove <url> - Remove a webhook")?;
+            writeln!(out, "  webhook list - Show registered webhooks")?;
+            writeln!(out, "  pause - Pause monitoring")?;
+            writeln!(out, "  resume - Resume monitoring")?;
+            writeln!(
+                out,
+                "  pause for <duration> - Pause monitoring and resume after e.g. 30m or 2h"
+            )?;
+            writeln!(
+                out,
+                "  pause <path> / resume <path> - Pause or resume one watched path"
+            )?;
+            writeln!(
+                out,
+                "  maintenance start [label] - Tag events as planned maintenance"
+            )?;
+            writeln!(
+                out,
+                "  maintenance quiet [label] - Like start, but only log alerts during the window"
+            )?;
+            writeln!(out, "  maintenance stop - End the maintenance window")?;
+            writeln!(out, "  maintenance - Show maintenance windows")?;
+            writeln!(out, "  stats - Show event statistics")?;
+            writeln!(
+                out,
+                "  rates [seconds] - Show events per minute over the last minute or window"
+            )?;
+            writeln!(
+                out,
+                "  baseline create <file> - Record size, mtime and hash of all watched files"
+            )?;
+            writeln!(
+                out,
+                "  baseline verify <file> - Report files that differ from a baseline"
+            )?;
+            writeln!(
+                out,
+                "  alerts - Show unacknowledged escalated alerts and rate alert rules"
+            )?;
+            writeln!(
+                out,
+                "  ack <id> - Acknowledge an alert, stopping its escalation"
+            )?;
+            writeln!(
+                out,
+                "  alert add <name> <kind> <threshold> <seconds> - Alert on more than threshold events in the window"
+            )?;
+            writeln!(out, "  alert remove <name> - Remove a rate alert rule")?;
+            writeln!(
+                out,
+                "  coverage - Show gaps in monitoring and coverage percentage"
+            )?;
+            writeln!(
+                out,
+                "  history [kind] - Show recent event history, optionally only one event kind"
+            )?;
+            writeln!(
+                out,
+                "  diff <n> - Show event #n from the history with its diff (needs --diffs)"
+            )?;
+            writeln!(
+                out,
+                "  backups <path> - List backed-up versions of a file (needs --backup-dir)"
+            )?;
+            writeln!(
+                out,
+                "  restore <version> - Roll a file back to a version listed by backups"
+            )?;
+            writeln!(
+                out,
+                "  untrash <path> - Move a file last moved to the trash back to its path"
+            )?;
+            writeln!(
+                out,
+                "  save_history <file> - Save history as JSON lines for fm-query"
+            )?;
+            writeln!(
+                out,
+                "  export history <json|csv> <file> - Export event history"
+            )?;
+            writeln!(
+                out,
+                "  export stats <json|csv> <file> - Export per-path event statistics"
+            )?;
+            writeln!(
+                out,
+                "  export watchsets <json|csv> <file> - Export per-watchset event statistics"
+            )?;
+            writeln!(
+                out,
+                "  follow <on|off> - Follow the file when it is moved out of the watched scope"
+            )?;
+            writeln!(
+                out,
+                "  follow name <on|off> - Watch paths again once they are recreated after a delete or rename"
+            )?;
+            writeln!(
+                out,
+                "  lineage - Show locations the file was followed through"
+            )?;
+            writeln!(out, "  watch <path> - Watch an additional path")?;
+            writeln!(out, "  unwatch <path> - Stop watching an additional path")?;
+            writeln!(
+                out,
+                "  watches - Show watched paths with per-path statistics"
+            )?;
+            writeln!(
+                out,
+                "  tail [path] - Print lines appended to a file, or list tailed paths"
+            )?;
+            writeln!(out, "  untail <path> - Stop printing appended lines")?;
+            writeln!(
+                out,
+                "  scans - Show the progress of the initial scans of the watches"
+            )?;
+            writeln!(
+                out,
+                "  watchset add <name> <path...> - Add paths to a watchset, creating it if needed"
+            )?;
+            writeln!(
+                out,
+                "  watchset remove <name> - Remove a watchset and stop watching its paths"
+            )?;
+            writeln!(
+                out,
+                "  watchset <pause|resume> <name> - Pause or resume all paths of a watchset"
+            )?;
+            writeln!(
+                out,
+                "  watchset filter <name> <include|exclude> <pattern> - Filter within a watchset"
+            )?;
+            writeln!(
+                out,
+                "  watchsets - Show watchsets with their paths and statistics"
+            )?;
+            writeln!(
+                out,
+                "  monitor add <name> <path> - Start an independent monitor for a path"
+            )?;
+            writeln!(
+                out,
+                "  monitor <start|stop|pause|resume|remove> <name> - Control a named monitor"
+            )?;
+            writeln!(
+                out,
+                "  monitors [history] - Show named monitors with their combined statistics or history"
+            )?;
+            writeln!(
+                out,
+                "  tasks - Show supervised tasks with their state and restart count"
+            )?;
+            writeln!(
+                out,
+                "  apikey add <name> <read|control> - Create a control endpoint API key"
+            )?;
+            writeln!(out, "  apikey remove <name> - Revoke an API key")?;
+            writeln!(out, "  apikey list - Show API keys and their scopes")?;
+            writeln!(out, "  quit - Exit the program")?;
         }
         ["update", new_path] => {
             if let Err(e) = monitor.update_path(new_path).await {
-                error!("Failed to update path: {}", e);
+                writeln!(out, "Failed to update path: {}", e)?;
             }
         }
         ["substitute", old_path, new_path] => {
             if let Err(e) = monitor.substitute_path(old_path, new_path).await {
-                error!("Failed to substitute path: {}", e);
+                writeln!(out, "Failed to substitute path: {}", e)?;
             }
         }
         ["add_substitution", original_path, substitute_path] => {
@@ -99,40 +855,625 @@ async fn handle_command(monitor: &Arc<FileMonitor>, command: &str) -> Result<boo
                 .add_path_substitution(original_path, substitute_path)
                 .await
             {
-                error!("Failed to add path substitution: {}", e);
+                writeln!(out, "Failed to add path substitution: {}", e)?;
             }
         }
         ["remove_substitution", original_path] => {
             if let Err(e) = monitor.remove_path_substitution(original_path).await {
-                error!("Failed to remove path substitution: {}", e);
+                writeln!(out, "Failed to remove path substitution: {}", e)?;
+            }
+        }
+        ["add_filter", kind @ ("include" | "exclude"), pattern] => {
+            let kind = if *kind == "include" {
+                FilterKind::Include
+            } else {
+                FilterKind::Exclude
+            };
+            if let Err(e) = monitor.add_filter(kind, pattern).await {
+                writeln!(out, "Failed to add filter: {}", e)?;
+            }
+        }
+        ["remove_filter", pattern] => {
+            if let Err(e) = monitor.remove_filter(pattern).await {
+                writeln!(out, "Failed to remove filter: {}", e)?;
+            }
+        }
+        ["filters"] => {
+            writeln!(out, "Active filters:")?;
+            for (kind, pattern) in monitor.get_filters().await {
+                writeln!(out, "  {} {}", kind, pattern)?;
+            }
+        }
+        ["webhook", "add", url, events @ ..] => {
+            let events = events.iter().map(|event| event.to_string()).collect();
+            if let Err(e) = monitor.add_webhook(url, events).await {
+                writeln!(out, "Failed to add webhook: {}", e)?;
+            }
+        }
+        ["webhook", "remove", url] => {
+            if let Err(e) = monitor.remove_webhook(url).await {
+                writeln!(out, "Failed to remove webhook: {}", e)?;
+            }
+        }
+        ["webhook", "list"] => {
+            writeln!(out, "Webhooks:")?;
+            for webhook in monitor.get_webhooks().await {
+                if webhook.events.is_empty() {
+                    writeln!(out, "  {} (all events)", webhook.url)?;
+                } else {
+                    writeln!(out, "  {} ({})", webhook.url, webhook.events.join(", "))?;
+                }
+                let limiter = webhook.rate_limiter().metrics();
+                let breaker = webhook.circuit_breaker().metrics();
+                writeln!(
+                    out,
+                    "    {} sent, {} rate limited; circuit {:?}: {} failures, {} rejected",
+                    limiter.allowed,
+                    limiter.throttled,
+                    breaker.state,
+                    breaker.failures,
+                    breaker.rejected
+                )?;
             }
         }
         ["pause"] => {
             if let Err(e) = monitor.pause().await {
-                error!("Failed to pause monitoring: {}", e);
+                writeln!(out, "Failed to pause monitoring: {}", e)?;
             }
         }
         ["resume"] => {
             if let Err(e) = monitor.resume().await {
-                error!("Failed to resume monitoring: {}", e);
+                writeln!(out, "Failed to resume monitoring: {}", e)?;
+            }
+        }
+        ["pause", "for", duration] => match parse_interval(duration) {
+            Ok(duration) => {
+                if let Err(e) = monitor.pause_for(duration).await {
+                    writeln!(out, "Failed to pause monitoring: {}", e)?;
+                }
+            }
+            Err(e) => writeln!(out, "{}", e)?,
+        },
+        ["pause", path] => {
+            if let Err(e) = monitor.pause_path(path).await {
+                writeln!(out, "Failed to pause {}: {}", path, e)?;
+            }
+        }
+        ["resume", path] => {
+            if let Err(e) = monitor.resume_path(path).await {
+                writeln!(out, "Failed to resume {}: {}", path, e)?;
             }
         }
         ["stats"] => {
             let stats = monitor.get_stats().await;
-            println!("Event statistics:");
+            writeln!(out, "Event statistics:")?;
             for (event, count) in stats {
-                println!("  {:?}: {}", event, count);
+                writeln!(out, "  {:?}: {}", event, count)?;
+            }
+            let dropped = monitor.get_dropped_events();
+            if dropped > 0 {
+                writeln!(out, "Dropped with the event queue full: {}", dropped)?;
+            }
+            let spilled = monitor.get_spilled_events();
+            if spilled > 0 {
+                writeln!(
+                    out,
+                    "Spilled to disk with the event queue full: {}",
+                    spilled
+                )?;
+            }
+            let coverage = monitor.get_coverage().await;
+            writeln!(
+                out,
+                "Coverage since {}: {:.2}%",
+                coverage.since, coverage.coverage_percent
+            )?;
+            let latency = monitor.get_latency().await;
+            if let Some(mean) = latency.mean() {
+                writeln!(
+                    out,
+                    "Processing latency over {} events: mean {:?}, max {:?}, last {:?}",
+                    latency.count, mean, latency.max, latency.last
+                )?;
+            }
+        }
+        ["rates", window @ ..] if window.len() <= 1 => {
+            match window
+                .first()
+                .map_or(Ok(60), |window| window.parse::<u64>())
+            {
+                Ok(window) => {
+                    let rates = monitor.get_rates(Duration::from_secs(window)).await;
+                    writeln!(out, "Event rates over the last {}s:", window)?;
+                    for (kind, rate) in rates {
+                        writeln!(
+                            out,
+                            "  {}: {} ({:.1}/min)",
+                            kind, rate.count, rate.per_minute
+                        )?;
+                    }
+                }
+                Err(_) => writeln!(out, "Window must be a number of seconds")?,
+            }
+        }
+        ["baseline", "create", file] => {
+            let result = match monitor.create_baseline().await {
+                Ok(baseline) => baseline.save(file).map(|_| baseline.files.len()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(count) => writeln!(out, "Baseline of {} files written to {}", count, file)?,
+                Err(e) => writeln!(out, "Failed to create baseline: {}", e)?,
+            }
+        }
+        ["baseline", "verify", file] => {
+            let result = match Baseline::load(file) {
+                Ok(baseline) => monitor.verify_baseline(&baseline).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(drift) if drift.is_empty() => writeln!(out, "No drift from baseline")?,
+                Ok(drift) => {
+                    writeln!(out, "{} files drifted from baseline:", drift.len())?;
+                    for (path, change) in drift {
+                        match change {
+                            Drift::Added => writeln!(out, "  added: {}", path.display())?,
+                            Drift::Removed => writeln!(out, "  removed: {}", path.display())?,
+                            Drift::Changed(fields) => writeln!(
+                                out,
+                                "  changed ({}): {}",
+                                fields.join(", "),
+                                path.display()
+                            )?,
+                        }
+                    }
+                }
+                Err(e) => writeln!(out, "Failed to verify baseline: {}", e)?,
+            }
+        }
+        ["alerts"] => {
+            let open = monitor.get_open_alerts().await;
+            if !open.is_empty() {
+                writeln!(out, "Unacknowledged alerts:")?;
+                for alert in open {
+                    writeln!(
+                        out,
+                        "  {} {} [{}] {} (escalated {}x)",
+                        alert.id,
+                        alert.raised_at.format("%Y-%m-%d %H:%M:%S"),
+                        alert.rule.as_deref().unwrap_or("critical"),
+                        alert.reason,
+                        alert.escalations
+                    )?;
+                }
+            }
+            writeln!(out, "Rate alerts:")?;
+            for state in monitor.get_rate_alerts().await {
+                writeln!(
+                    out,
+                    "  {}: more than {} {} events in {}s{}",
+                    state.rule.name,
+                    state.rule.threshold,
+                    state.rule.event,
+                    state.rule.window_secs,
+                    if state.firing { " [firing]" } else { "" }
+                )?;
+            }
+        }
+        ["ack", id] => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "console".to_string());
+            match monitor.acknowledge_alert(id, &user).await {
+                Ok(()) => writeln!(out, "Alert {} acknowledged", id)?,
+                Err(e) => writeln!(out, "Failed to acknowledge {}: {}", id, e)?,
+            }
+        }
+        ["alert", "add", name, kind, threshold, window] => {
+            match (threshold.parse::<usize>(), window.parse::<u64>()) {
+                (Ok(threshold), Ok(window)) => {
+                    let rule =
+                        RateAlertRule::new(name, kind, threshold, Duration::from_secs(window));
+                    let result = match rule {
+                        Ok(rule) => monitor.add_rate_alert(rule).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        writeln!(out, "Failed to add rate alert: {}", e)?;
+                    }
+                }
+                _ => writeln!(out, "Threshold and window must be numbers")?,
+            }
+        }
+        ["alert", "remove", name] => {
+            if !monitor.remove_rate_alert(name).await {
+                writeln!(out, "No rate alert named {}", name)?;
+            }
+        }
+        ["scans"] => {
+            writeln!(out, "Initial scans:")?;
+            for scan in monitor.get_scans() {
+                let total = scan
+                    .files_total
+                    .map_or_else(|| "counting".to_string(), |total| total.to_string());
+                match (scan.finished, scan.eta) {
+                    (Some(finished), _) => writeln!(
+                        out,
+                        "  {} - {} files, finished {}",
+                        scan.watch.display(),
+                        scan.files_scanned,
+                        finished.format("%Y-%m-%d %H:%M:%S")
+                    )?,
+                    (None, eta) => writeln!(
+                        out,
+                        "  {} - {} of {} files{}",
+                        scan.watch.display(),
+                        scan.files_scanned,
+                        total,
+                        eta.map(|eta| format!(", about {}s left", eta.as_secs()))
+                            .unwrap_or_default()
+                    )?,
+                }
+            }
+        }
+        ["coverage"] => {
+            let coverage = monitor.get_coverage().await;
+            writeln!(
+                out,
+                "Coverage from {} to {}: {:.2}%",
+                coverage.since, coverage.until, coverage.coverage_percent
+            )?;
+            for gap in coverage.gaps {
+                match gap.end {
+                    Some(end) => writeln!(out, "  {:?}: {} - {}", gap.kind, gap.start, end)?,
+                    None => writeln!(out, "  {:?}: {} - ongoing", gap.kind, gap.start)?,
+                }
+            }
+        }
+        ["maintenance", action @ ("start" | "quiet"), label @ ..] if label.len() <= 1 => {
+            let label = label.first().copied().unwrap_or(DEFAULT_MAINTENANCE_LABEL);
+            if let Err(e) = monitor.start_maintenance(label, *action == "quiet").await {
+                writeln!(out, "Failed to start maintenance: {}", e)?;
+            }
+        }
+        ["maintenance", "stop"] => match monitor.stop_maintenance().await {
+            Ok(window) => writeln!(
+                out,
+                "Maintenance window {} ended ({} - {})",
+                window.label,
+                window.start,
+                window.end.unwrap_or(window.start)
+            )?,
+            Err(e) => writeln!(out, "Failed to stop maintenance: {}", e)?,
+        },
+        ["maintenance"] => {
+            writeln!(out, "Maintenance windows:")?;
+            for window in monitor.get_maintenance_windows().await {
+                let end = window
+                    .end
+                    .map_or_else(|| "active".to_string(), |end| end.to_string());
+                writeln!(
+                    out,
+                    "  {}: {} - {}{}",
+                    window.label,
+                    window.start,
+                    end,
+                    if window.downgrade_alerts {
+                        " (alerts downgraded)"
+                    } else {
+                        ""
+                    }
+                )?;
+            }
+        }
+        ["history", kind @ ..] if kind.len() <= 1 => {
+            let history = match kind.first() {
+                Some(kind) => monitor.get_history_filtered(kind).await,
+                None => monitor.get_history().await,
+            };
+            writeln!(out, "Recent event history:")?;
+            for record in history.iter().rev().take(10) {
+                match &record.maintenance {
+                    Some(label) => writeln!(
+                        out,
+                        "  #{} {} - {:?} - {} [maintenance: {}]",
+                        record.id,
+                        record.time,
+                        record.event,
+                        record.path.display(),
+                        label
+                    )?,
+                    None => writeln!(
+                        out,
+                        "  #{} {} - {:?} - {}",
+                        record.id,
+                        record.time,
+                        record.event,
+                        record.path.display()
+                    )?,
+                }
+            }
+        }
+        ["backups", path] => match monitor.get_backups(path).await {
+            Ok(versions) if versions.is_empty() => writeln!(out, "No backups of {}", path)?,
+            Ok(versions) => {
+                writeln!(out, "Backups of {}:", path)?;
+                for version in versions {
+                    writeln!(
+                        out,
+                        "  {} - {} bytes - {}",
+                        version.time, version.size, version.id
+                    )?;
+                }
+            }
+            Err(e) => writeln!(out, "Failed to list backups: {}", e)?,
+        },
+        ["restore", version] => match monitor.restore_backup(version).await {
+            Ok(path) => writeln!(out, "Restored {} from {}", path.display(), version)?,
+            Err(e) => writeln!(out, "Failed to restore {}: {}", version, e)?,
+        },
+        ["untrash", path] => match monitor.restore_from_trash(path).await {
+            Ok(path) => writeln!(out, "Restored {} from trash", path.display())?,
+            Err(e) => writeln!(out, "Failed to restore {}: {}", path, e)?,
+        },
+        ["diff", id] => match id.trim_start_matches('#').parse() {
+            Ok(id) => match monitor.get_history_detail(id).await {
+                Some(detail) => {
+                    writeln!(
+                        out,
+                        "#{} {} - {:?} - {}",
+                        id,
+                        detail.record.time,
+                        detail.record.event,
+                        detail.record.path.display()
+                    )?;
+                    match detail.diff {
+                        Some(diff) => write!(out, "{}", diff)?,
+                        None => writeln!(out, "No diff recorded for this event")?,
+                    }
+                }
+                None => writeln!(out, "No event #{} in the history", id)?,
+            },
+            Err(_) => writeln!(out, "Invalid event number: {}", id)?,
+        },
+        ["save_history", file] => {
+            if let Err(e) = monitor.save_history(file).await {
+                writeln!(out, "Failed to save history: {}", e)?;
+            }
+        }
+        ["export", what @ ("history" | "stats" | "watchsets"), format, file] => {
+            let result = match format.parse::<ExportFormat>() {
+                Ok(format) if *what == "history" => monitor.export_history(format, file).await,
+                Ok(format) if *what == "watchsets" => {
+                    monitor.export_watchset_stats(format, file).await
+                }
+                Ok(format) => monitor.export_stats(format, file).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                writeln!(out, "Failed to export {}: {}", what, e)?;
+            }
+        }
+        ["follow", mode @ ("on" | "off")] => {
+            if let Err(e) = monitor.set_follow_moves(*mode == "on").await {
+                writeln!(out, "Failed to change move following: {}", e)?;
+            }
+        }
+        ["follow", "name", mode @ ("on" | "off")] => {
+            monitor.set_follow_names(*mode == "on").await;
+        }
+        ["lineage"] => {
+            let lineage = monitor.get_path_lineage().await;
+            writeln!(out, "Path lineage:")?;
+            for hop in lineage {
+                writeln!(
+                    out,
+                    "  {} - {} -> {}",
+                    hop.time,
+                    hop.from.display(),
+                    hop.to.display()
+                )?;
+            }
+        }
+        ["watch", path] => {
+            if let Err(e) = monitor.add_watch(path).await {
+                writeln!(out, "Failed to add watch: {}", e)?;
+            }
+        }
+        ["unwatch", path] => {
+            if let Err(e) = monitor.remove_watch(path).await {
+                writeln!(out, "Failed to remove watch: {}", e)?;
+            }
+        }
+        ["watches"] => {
+            writeln!(out, "Watched paths:")?;
+            for watch in monitor.get_watches().await {
+                let stats = monitor.get_watch_stats(&watch).await;
+                writeln!(
+                    out,
+                    "  {} - {} events ({})",
+                    watch.display(),
+                    stats.values().sum::<usize>(),
+                    monitor.get_watch_backend(&watch)
+                )?;
+                for (event, count) in stats {
+                    writeln!(out, "    {:?}: {}", event, count)?;
+                }
+            }
+        }
+        ["tail"] => {
+            writeln!(out, "Tailed paths:")?;
+            for path in monitor.get_tailed().await {
+                writeln!(out, "  {}", path.display())?;
+            }
+        }
+        ["tail", path] => {
+            if let Err(e) = monitor.tail(path).await {
+                writeln!(out, "Failed to tail: {}", e)?;
+            }
+        }
+        ["untail", path] => {
+            if let Err(e) = monitor.untail(path).await {
+                writeln!(out, "Failed to stop tailing: {}", e)?;
+            }
+        }
+        ["watchset", "add", name, paths @ ..] if !paths.is_empty() => {
+            if let Err(e) = monitor.add_to_watchset(name, paths).await {
+                writeln!(out, "Failed to add to watchset: {}", e)?;
+            }
+        }
+        ["watchset", "remove", name] => {
+            if let Err(e) = monitor.remove_watchset(name).await {
+                writeln!(out, "Failed to remove watchset: {}", e)?;
+            }
+        }
+        ["watchset", action @ ("pause" | "resume"), name] => {
+            let result = if *action == "pause" {
+                monitor.pause_watchset(name).await
+            } else {
+                monitor.resume_watchset(name).await
+            };
+            if let Err(e) = result {
+                writeln!(out, "Failed to {} watchset: {}", action, e)?;
+            }
+        }
+        ["watchset", "filter", name, kind @ ("include" | "exclude"), pattern] => {
+            let kind = if *kind == "include" {
+                FilterKind::Include
+            } else {
+                FilterKind::Exclude
+            };
+            if let Err(e) = monitor.add_watchset_filter(name, kind, pattern).await {
+                writeln!(out, "Failed to add watchset filter: {}", e)?;
+            }
+        }
+        ["watchsets"] => {
+            writeln!(out, "Watchsets:")?;
+            for watchset in monitor.get_watchsets().await {
+                let stats = monitor
+                    .get_watchset_stats(&watchset.name)
+                    .await
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    "  {} - {} paths, {} events{}",
+                    watchset.name,
+                    watchset.paths.len(),
+                    stats.values().sum::<usize>(),
+                    if watchset.paused { " (paused)" } else { "" }
+                )?;
+                for path in &watchset.paths {
+                    writeln!(out, "    {}", path.display())?;
+                }
+                for (kind, pattern) in watchset.filters.list() {
+                    writeln!(out, "    {} {}", kind, pattern)?;
+                }
+                for (event, count) in stats {
+                    writeln!(out, "    {:?}: {}", event, count)?;
+                }
             }
         }
-        ["history"] => {
-            let history = monitor.get_history().await;
-            println!("Recent event history:");
-            for (time, event) in history.iter().rev().take(10) {
-                println!("  {} - {:?}", time, event);
+        ["apikey", "add", name, scope] => match scope.parse::<ApiScope>() {
+            Ok(scope) => match api_keys.add(name, scope) {
+                Ok(secret) => {
+                    writeln!(out, "API key {} ({:?}): {}", name, scope, secret)?;
+                    writeln!(out, "Store it now, it cannot be shown again")?;
+                }
+                Err(e) => writeln!(out, "Failed to add API key: {}", e)?,
+            },
+            Err(e) => writeln!(out, "{}", e)?,
+        },
+        ["apikey", "remove", name] => match api_keys.remove(name) {
+            Ok(true) => writeln!(out, "API key {} revoked", name)?,
+            Ok(false) => writeln!(out, "No API key named {}", name)?,
+            Err(e) => writeln!(out, "Failed to remove API key: {}", e)?,
+        },
+        ["apikey", "list"] => {
+            writeln!(out, "API keys:")?;
+            for key in api_keys.list() {
+                writeln!(
+                    out,
+                    "  {} - {:?}, created {}{}",
+                    key.name,
+                    key.scope,
+                    key.created.format("%Y-%m-%d %H:%M:%S"),
+                    if key.ephemeral { " (not saved)" } else { "" }
+                )?;
+            }
+        }
+        ["monitor", "add", name, path] => {
+            let added = match manager.add_path(name, path).await {
+                Ok(_) => manager.start(name).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = added {
+                writeln!(out, "Failed to add monitor: {}", e)?;
+            }
+        }
+        ["monitor", action @ ("start" | "stop" | "pause" | "resume" | "remove"), name] => {
+            let result = match *action {
+                "start" => manager.start(name).await,
+                "stop" => manager.stop(name).await,
+                "pause" => manager.pause(name).await,
+                "resume" => manager.resume(name).await,
+                _ => manager.remove(name).await,
+            };
+            if let Err(e) = result {
+                writeln!(out, "Failed to {} monitor: {}", action, e)?;
+            }
+        }
+        ["monitors"] => {
+            writeln!(out, "Monitors:")?;
+            for status in manager.status().await {
+                let state = match (status.running, status.paused) {
+                    (false, _) => "stopped",
+                    (true, true) => "paused",
+                    (true, false) => "running",
+                };
+                writeln!(
+                    out,
+                    "  {} - {}, {} events",
+                    status.name, state, status.events
+                )?;
+                for watch in &status.watches {
+                    writeln!(out, "    {}", watch.display())?;
+                }
+            }
+            writeln!(out, "Combined statistics:")?;
+            for (event, count) in manager.get_stats().await {
+                writeln!(out, "  {:?}: {}", event, count)?;
+            }
+        }
+        ["monitors", "history"] => {
+            writeln!(out, "Recent event history of all monitors:")?;
+            for (name, record) in manager.get_history().await.iter().rev().take(10) {
+                writeln!(
+                    out,
+                    "  {} #{} {} - {:?} - {}",
+                    name,
+                    record.id,
+                    record.time,
+                    record.event,
+                    record.path.display()
+                )?;
+            }
+        }
+        ["tasks"] => {
+            writeln!(out, "Supervised tasks:")?;
+            for task in supervisor.status().await {
+                writeln!(
+                    out,
+                    "  {} - {:?}, {} restarts, up since {}",
+                    task.name,
+                    task.state,
+                    task.restarts,
+                    task.started_at.format("%Y-%m-%d %H:%M:%S")
+                )?;
+                if let Some(error) = task.last_error {
+                    writeln!(out, "    last error: {}", error)?;
+                }
             }
         }
         ["quit"] => return Ok(false),
-        _ => println!("Unknown command. Type 'help' for available commands."),
+        _ => writeln!(out, "Unknown command. Type 'help' for available commands.")?,
     }
     Ok(true)
 }
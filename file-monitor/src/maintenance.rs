@@ -0,0 +1,31 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAINTENANCE_LABEL: &str = "maintenance";
+
+/// A period of planned changes. Events inside it are still recorded, but tagged with the
+/// label so they can be told apart from unexpected activity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub label: String,
+    pub start: DateTime<Local>,
+    /// `None` while the window is still active.
+    pub end: Option<DateTime<Local>>,
+    /// Rule alerts raised during the window are only logged, not raised as alerts.
+    pub downgrade_alerts: bool,
+}
+
+impl MaintenanceWindow {
+    pub(crate) fn start(label: &str, downgrade_alerts: bool) -> Self {
+        Self {
+            label: label.to_string(),
+            start: Local::now(),
+            end: None,
+            downgrade_alerts,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.end.is_none()
+    }
+}
@@ -0,0 +1,226 @@
+use crate::error::{monitor_error, MonitorError, Result};
+use crate::{FileEvent, FileEventRecord, FileMonitor};
+use log::{error, info};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+type BuildFn = Box<dyn Fn() -> FileMonitor + Send + Sync>;
+
+struct Managed {
+    build: BuildFn,
+    monitor: Arc<FileMonitor>,
+    task: Option<JoinHandle<()>>,
+    /// A monitor that was shut down cannot run again; the next start builds a new one.
+    stopped: bool,
+}
+
+impl Managed {
+    fn is_running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+}
+
+/// State of one managed monitor, see [`MonitorManager::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedStatus {
+    pub name: String,
+    pub watches: Vec<PathBuf>,
+    pub running: bool,
+    pub paused: bool,
+    pub events: usize,
+}
+
+/// Owns independent, named monitors, e.g. one per application with its own filters and
+/// history, and starts, stops and pauses them by name.
+///
+/// Stopping a monitor shuts it down; starting it again builds a new one from the
+/// constructor it was added with, so its history and statistics start over.
+#[derive(Default)]
+pub struct MonitorManager {
+    monitors: Mutex<BTreeMap<String, Managed>>,
+}
+
+impl MonitorManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a monitor built by `build`, without starting it.
+    pub async fn add<F>(&self, name: &str, build: F) -> Result<Arc<FileMonitor>>
+    where
+        F: Fn() -> FileMonitor + Send + Sync + 'static,
+    {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(monitor_error!("Invalid monitor name {:?}", name));
+        }
+        let mut monitors = self.monitors.lock().await;
+        if monitors.contains_key(name) {
+            return Err(MonitorError::AlreadyExists {
+                kind: "Monitor",
+                name: name.to_string(),
+            });
+        }
+        let monitor = Arc::new(build());
+        monitors.insert(
+            name.to_string(),
+            Managed {
+                build: Box::new(build),
+                monitor: Arc::clone(&monitor),
+                task: None,
+                stopped: false,
+            },
+        );
+        Ok(monitor)
+    }
+
+    /// Adds a monitor with default settings watching `path`, without starting it.
+    pub async fn add_path<P: AsRef<Path>>(&self, name: &str, path: P) -> Result<Arc<FileMonitor>> {
+        let path = path.as_ref().to_path_buf();
+        self.add(name, move || FileMonitor::new(&path)).await
+    }
+
+    /// Stops the monitor if it is running and forgets it.
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        self.stop(name).await?;
+        self.monitors.lock().await.remove(name);
+        Ok(())
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Arc<FileMonitor>> {
+        let monitors = self.monitors.lock().await;
+        monitors
+            .get(name)
+            .map(|managed| Arc::clone(&managed.monitor))
+    }
+
+    pub async fn names(&self) -> Vec<String> {
+        self.monitors.lock().await.keys().cloned().collect()
+    }
+
+    /// Runs the monitor on a task of its own. Does nothing if it is already running.
+    pub async fn start(&self, name: &str) -> Result<()> {
+        let mut monitors = self.monitors.lock().await;
+        let managed = monitors.get_mut(name).ok_or_else(|| no_monitor(name))?;
+        if managed.is_running() {
+            return Ok(());
+        }
+        if managed.stopped {
+            managed.monitor = Arc::new((managed.build)());
+            managed.stopped = false;
+        }
+        let monitor = Arc::clone(&managed.monitor);
+        let task_name = name.to_string();
+        managed.task = Some(tokio::spawn(async move {
+            if let Err(e) = monitor.monitor().await {
+                error!("Monitor {} stopped: {}", task_name, e);
+            }
+        }));
+        info!("Started monitor {}", name);
+        Ok(())
+    }
+
+    /// Shuts the monitor down, see [`FileMonitor::shutdown`], and waits for its task.
+    pub async fn stop(&self, name: &str) -> Result<()> {
+        let (monitor, task) = {
+            let mut monitors = self.monitors.lock().await;
+            let managed = monitors.get_mut(name).ok_or_else(|| no_monitor(name))?;
+            let Some(task) = managed.task.take() else {
+                return Ok(());
+            };
+            managed.stopped = true;
+            (Arc::clone(&managed.monitor), task)
+        };
+        monitor.shutdown().await;
+        let _ = task.await;
+        info!("Stopped monitor {}", name);
+        Ok(())
+    }
+
+    pub async fn pause(&self, name: &str) -> Result<()> {
+        self.monitor(name).await?.pause().await
+    }
+
+    pub async fn resume(&self, name: &str) -> Result<()> {
+        self.monitor(name).await?.resume().await
+    }
+
+    /// Stops every running monitor.
+    pub async fn shutdown(&self) {
+        for name in self.names().await {
+            // Removed concurrently is as good as stopped.
+            let _ = self.stop(&name).await;
+        }
+    }
+
+    pub async fn status(&self) -> Vec<ManagedStatus> {
+        let monitors = self.monitors.lock().await;
+        let mut status = Vec::with_capacity(monitors.len());
+        for (name, managed) in monitors.iter() {
+            status.push(ManagedStatus {
+                name: name.clone(),
+                watches: managed.monitor.get_watches().await,
+                running: managed.is_running(),
+                paused: managed.monitor.is_paused().await,
+                events: managed.monitor.get_stats().await.values().sum(),
+            });
+        }
+        status
+    }
+
+    /// Event counts of all monitors added up.
+    pub async fn get_stats(&self) -> HashMap<FileEvent, usize> {
+        let mut stats = HashMap::new();
+        for monitor in self.monitors().await {
+            for (event, count) in monitor.get_stats().await {
+                *stats.entry(event).or_insert(0) += count;
+            }
+        }
+        stats
+    }
+
+    /// The histories of all monitors merged in the order the events were recorded, each
+    /// with the name of its monitor.
+    pub async fn get_history(&self) -> Vec<(String, FileEventRecord)> {
+        let monitors: Vec<_> = {
+            let monitors = self.monitors.lock().await;
+            monitors
+                .iter()
+                .map(|(name, managed)| (name.clone(), Arc::clone(&managed.monitor)))
+                .collect()
+        };
+        let mut history = Vec::new();
+        for (name, monitor) in monitors {
+            history.extend(
+                monitor
+                    .get_history()
+                    .await
+                    .into_iter()
+                    .map(|record| (name.clone(), record)),
+            );
+        }
+        history.sort_by_key(|(_, record)| record.time);
+        history
+    }
+
+    async fn monitor(&self, name: &str) -> Result<Arc<FileMonitor>> {
+        self.get(name).await.ok_or_else(|| no_monitor(name))
+    }
+
+    async fn monitors(&self) -> Vec<Arc<FileMonitor>> {
+        let monitors = self.monitors.lock().await;
+        monitors
+            .values()
+            .map(|managed| Arc::clone(&managed.monitor))
+            .collect()
+    }
+}
+
+fn no_monitor(name: &str) -> MonitorError {
+    MonitorError::NotFound {
+        kind: "monitor named",
+        name: name.to_string(),
+    }
+}
@@ -0,0 +1,206 @@
+use crate::error::{invalid_config, monitor_error, Result};
+use crate::rules::{EventRule, Verdict};
+use crate::FileEventRecord;
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use std::path::Path;
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+const VERDICT_PASS: i32 = 0;
+const VERDICT_ALERT: i32 = 1;
+const VERDICT_SUPPRESS: i32 = 2;
+const MAX_REASON_BYTES: usize = 1024;
+const REQUIRED_EXPORTS: [&str; 3] = ["memory", "alloc", "evaluate"];
+
+/// Resources a plugin may use for one evaluation. Exceeding any of them ends the
+/// evaluation with a `Pass` verdict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// Fuel (roughly, WebAssembly instructions) per evaluation.
+    pub fuel: u64,
+    /// Linear memory an instance may grow to.
+    pub memory_bytes: usize,
+    /// Larger event records are not passed to plugins.
+    pub max_record_bytes: usize,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            memory_bytes: 16 * 1024 * 1024,
+            max_record_bytes: 64 * 1024,
+        }
+    }
+}
+
+struct PluginState {
+    limits: StoreLimits,
+    reason: Option<String>,
+}
+
+/// An [`EventRule`] implemented by a WebAssembly module.
+///
+/// The module exports `memory`, `alloc(len: i32) -> i32` and
+/// `evaluate(ptr: i32, len: i32) -> i32`. The event record is written as JSON to the
+/// buffer returned by `alloc`, and `evaluate` returns 0 to pass, 1 to alert or 2 to
+/// suppress the event. Before returning 1 the module may call the imported
+/// `env.reason(ptr: i32, len: i32)` with a UTF-8 reason for the alert. Every evaluation
+/// runs in a fresh instance, so modules keep no state between events.
+pub struct WasmRule {
+    name: String,
+    engine: Engine,
+    instance_pre: InstancePre<PluginState>,
+    limits: PluginLimits,
+}
+
+impl WasmRule {
+    /// Compiles the module at `path`, binary or text format. The rule is named after the
+    /// file stem.
+    pub fn load<P: AsRef<Path>>(path: P, limits: PluginLimits) -> Result<Self> {
+        Self::load_with_engine(&plugin_engine()?, path.as_ref(), limits)
+    }
+
+    fn load_with_engine(engine: &Engine, path: &Path, limits: PluginLimits) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let module = Module::from_file(engine, path)
+            .map_err(|e| invalid_config!("Plugin {}: {:#}", path.display(), e))?;
+        if let Some(missing) = REQUIRED_EXPORTS
+            .iter()
+            .find(|export| module.get_export(export).is_none())
+        {
+            return Err(invalid_config!(
+                "Plugin {} does not export {}",
+                path.display(),
+                missing
+            ));
+        }
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap("env", "reason", read_reason)
+            .map_err(|e| monitor_error!("Failed to link plugin {}: {}", name, e))?;
+        let instance_pre = linker
+            .instantiate_pre(&module)
+            .map_err(|e| invalid_config!("Plugin {}: {:#}", path.display(), e))?;
+        Ok(Self {
+            name,
+            engine: engine.clone(),
+            instance_pre,
+            limits,
+        })
+    }
+
+    fn run(&self, record: &FileEventRecord) -> anyhow::Result<Verdict> {
+        let input = serde_json::to_vec(record)?;
+        if input.len() > self.limits.max_record_bytes {
+            bail!("event record is {} bytes", input.len());
+        }
+        let mut store = Store::new(
+            &self.engine,
+            PluginState {
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.limits.memory_bytes)
+                    .instances(1)
+                    .build(),
+                reason: None,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel)?;
+
+        let instance = self.instance_pre.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("no exported memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let evaluate = instance.get_typed_func::<(i32, i32), i32>(&mut store, "evaluate")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, &input)?;
+        match evaluate.call(&mut store, (ptr, len))? {
+            VERDICT_PASS => Ok(Verdict::Pass),
+            VERDICT_ALERT => Ok(Verdict::Alert(
+                store
+                    .data_mut()
+                    .reason
+                    .take()
+                    .unwrap_or_else(|| format!("plugin {}", self.name)),
+            )),
+            VERDICT_SUPPRESS => Ok(Verdict::Suppress),
+            other => bail!("unknown verdict {}", other),
+        }
+    }
+}
+
+impl EventRule for WasmRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn evaluate(&self, record: &FileEventRecord) -> Verdict {
+        self.run(record).unwrap_or_else(|e| {
+            warn!(
+                "Plugin {} failed on {}: {:#}",
+                self.name,
+                record.path.display(),
+                e
+            );
+            Verdict::Pass
+        })
+    }
+}
+
+fn read_reason(mut caller: Caller<'_, PluginState>, ptr: i32, len: i32) {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return;
+    };
+    let len = (len as u32 as usize).min(MAX_REASON_BYTES);
+    let mut reason = vec![0u8; len];
+    if memory
+        .read(&caller, ptr as u32 as usize, &mut reason)
+        .is_ok()
+    {
+        caller.data_mut().reason = Some(String::from_utf8_lossy(&reason).into_owned());
+    }
+}
+
+fn plugin_engine() -> Result<Engine> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config).map_err(|e| monitor_error!("Failed to start WebAssembly engine: {}", e))
+}
+
+/// Loads every `.wasm` and `.wat` module in `dir`, in file name order. A module that
+/// does not compile or lacks the expected imports and exports fails the whole load.
+pub fn load_plugins<P: AsRef<Path>>(dir: P, limits: PluginLimits) -> Result<Vec<WasmRule>> {
+    let mut paths = std::fs::read_dir(dir.as_ref())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|extension| extension.to_str()),
+                    Some("wasm" | "wat")
+                )
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let engine = plugin_engine()?;
+    let plugins = paths
+        .iter()
+        .map(|path| WasmRule::load_with_engine(&engine, path, limits))
+        .collect::<Result<Vec<_>>>()?;
+    info!(
+        "Loaded {} rule plugins from {}",
+        plugins.len(),
+        dir.as_ref().display()
+    );
+    Ok(plugins)
+}
@@ -0,0 +1,109 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Timing of a binary's startup phases for `--profile-startup`, with the memory footprint
+/// and binary size once started, to size deployments on low-power edge devices.
+pub struct StartupProfile {
+    started: Instant,
+    last: Instant,
+    phases: Vec<(String, Duration)>,
+}
+
+impl Default for StartupProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StartupProfile {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Records the time since the previous phase ended (or since the profile was created)
+    /// as phase `name`.
+    pub fn phase(&mut self, name: &str) {
+        let now = Instant::now();
+        self.phases.push((name.to_string(), now - self.last));
+        self.last = now;
+    }
+
+    pub fn phases(&self) -> &[(String, Duration)] {
+        &self.phases
+    }
+
+    pub fn total(&self) -> Duration {
+        self.last - self.started
+    }
+
+    /// Phase timings, total, memory footprint and binary size, one per line.
+    pub fn report(&self) -> String {
+        let mut report = String::from("Startup profile:\n");
+        let width = self
+            .phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+        for (name, duration) in &self.phases {
+            let _ = writeln!(report, "  {:<width$}  {:?}", name, duration);
+        }
+        let _ = writeln!(report, "  {:<width$}  {:?}", "total", self.total());
+        let memory = MemoryFootprint::current();
+        match (memory.resident_kb, memory.peak_resident_kb) {
+            (Some(resident), Some(peak)) => {
+                let _ = writeln!(
+                    report,
+                    "  memory: {} KiB resident, {} KiB peak",
+                    resident, peak
+                );
+            }
+            (Some(resident), None) => {
+                let _ = writeln!(report, "  memory: {} KiB resident", resident);
+            }
+            _ => report.push_str("  memory: unavailable on this platform\n"),
+        }
+        if let Some(size) = binary_size() {
+            let _ = writeln!(report, "  binary: {} KiB", size / 1024);
+        }
+        report
+    }
+}
+
+/// Resident memory of the current process, where the platform reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    pub resident_kb: Option<u64>,
+    pub peak_resident_kb: Option<u64>,
+}
+
+impl MemoryFootprint {
+    /// Reads `/proc/self/status` on Linux; empty elsewhere.
+    pub fn current() -> Self {
+        let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+            return Self::default();
+        };
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+        };
+        Self {
+            resident_kb: field("VmRSS:"),
+            peak_resident_kb: field("VmHWM:"),
+        }
+    }
+}
+
+/// Size of the running executable in bytes.
+pub fn binary_size() -> Option<u64> {
+    let exe = std::env::current_exe().ok()?;
+    std::fs::metadata(exe).ok().map(|metadata| metadata.len())
+}
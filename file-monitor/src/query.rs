@@ -0,0 +1,121 @@
+use crate::error::Result;
+use crate::{EventHistory, FileEventRecord};
+use chrono::{DateTime, Local};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Writes history as JSON lines, one [`FileEventRecord`] per line.
+pub fn write_history_file<P: AsRef<Path>>(history: &[FileEventRecord], path: P) -> Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    for record in history {
+        serde_json::to_writer(&mut file, record)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Reads a history file written as JSON lines or as a single JSON array.
+pub fn read_history_file<P: AsRef<Path>>(path: P) -> Result<EventHistory> {
+    let content = std::fs::read_to_string(path)?;
+    if content.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(&content)?);
+    }
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Selects records by event kind, path prefix and time range.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub event: Option<String>,
+    pub path_prefix: Option<PathBuf>,
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, record: &FileEventRecord) -> bool {
+        self.event
+            .as_deref()
+            .is_none_or(|event| record.event.kind().eq_ignore_ascii_case(event))
+            && self
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| record.path.starts_with(prefix))
+            && self.since.is_none_or(|since| record.time >= since)
+            && self.until.is_none_or(|until| record.time < until)
+    }
+
+    pub fn apply<'a>(&self, history: &'a [FileEventRecord]) -> Vec<&'a FileEventRecord> {
+        history
+            .iter()
+            .filter(|record| self.matches(record))
+            .collect()
+    }
+}
+
+/// What records are grouped by when aggregating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Event,
+    Path,
+    Watch,
+    Hour,
+}
+
+impl GroupBy {
+    pub fn key(&self, record: &FileEventRecord) -> String {
+        match self {
+            GroupBy::Event => record.event.kind().to_string(),
+            GroupBy::Path => record.path.display().to_string(),
+            GroupBy::Watch => record.watch.display().to_string(),
+            GroupBy::Hour => record.time.format("%Y-%m-%d %H:00").to_string(),
+        }
+    }
+}
+
+/// Counts records per group, largest first (ties broken by key).
+pub fn aggregate<'a, I>(records: I, group_by: GroupBy) -> Vec<(String, usize)>
+where
+    I: IntoIterator<Item = &'a FileEventRecord>,
+{
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for record in records {
+        *counts.entry(group_by.key(record)).or_insert(0) += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// Per-group counts in two history files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub key: String,
+    pub left: usize,
+    pub right: usize,
+}
+
+/// Groups whose counts differ between `left` and `right`, sorted by key.
+pub fn diff(
+    left: &[FileEventRecord],
+    right: &[FileEventRecord],
+    group_by: GroupBy,
+) -> Vec<DiffEntry> {
+    let left: HashMap<_, _> = aggregate(left, group_by).into_iter().collect();
+    let right: HashMap<_, _> = aggregate(right, group_by).into_iter().collect();
+    let keys: BTreeSet<_> = left.keys().chain(right.keys()).cloned().collect();
+    keys.into_iter()
+        .map(|key| DiffEntry {
+            left: left.get(&key).copied().unwrap_or(0),
+            right: right.get(&key).copied().unwrap_or(0),
+            key,
+        })
+        .filter(|entry| entry.left != entry.right)
+        .collect()
+}
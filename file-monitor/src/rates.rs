@@ -0,0 +1,127 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Longest window rates can be asked for; older counts are dropped.
+pub const MAX_RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// How many events of one kind happened within a recent window.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EventRate {
+    pub count: usize,
+    pub window_secs: u64,
+    pub per_minute: f64,
+}
+
+impl EventRate {
+    fn new(count: usize, window_secs: u64) -> Self {
+        Self {
+            count,
+            window_secs,
+            per_minute: count as f64 * 60.0 / window_secs as f64,
+        }
+    }
+}
+
+/// Event counts per kind in one-second buckets over the last [`MAX_RATE_WINDOW`], so
+/// bursts show up in rolling rates instead of disappearing in lifetime totals.
+#[derive(Debug)]
+pub struct EventRates {
+    origin: Instant,
+    /// Second since `origin` and the counts per event kind within it, oldest first.
+    buckets: VecDeque<(u64, HashMap<&'static str, usize>)>,
+}
+
+impl Default for EventRates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventRates {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, kind: &'static str) {
+        self.record_at(kind, Instant::now());
+    }
+
+    pub fn record_at(&mut self, kind: &'static str, at: Instant) {
+        let second = self.second(at);
+        match self.buckets.back_mut() {
+            // Events are recorded in order, so an earlier second can only come from clock
+            // granularity and is counted with the latest bucket.
+            Some((last, counts)) if *last >= second => *counts.entry(kind).or_insert(0) += 1,
+            _ => self.buckets.push_back((second, HashMap::from([(kind, 1)]))),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(first, _)| first + MAX_RATE_WINDOW.as_secs() <= second)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Events of `kind` (case-insensitive, as named by [`crate::FileEvent::kind`]) within
+    /// the last `window`, which is clamped to between one second and [`MAX_RATE_WINDOW`].
+    pub fn rate(&self, kind: &str, window: Duration) -> EventRate {
+        self.rate_at(kind, window, Instant::now())
+    }
+
+    pub fn rate_at(&self, kind: &str, window: Duration, now: Instant) -> EventRate {
+        let window_secs = clamp_window(window);
+        let count = self
+            .recent(window_secs, now)
+            .flat_map(|counts| counts.iter())
+            .filter(|(recorded, _)| recorded.eq_ignore_ascii_case(kind))
+            .map(|(_, count)| count)
+            .sum();
+        EventRate::new(count, window_secs)
+    }
+
+    /// Rates of every event kind seen within the last `window`.
+    pub fn rates(&self, window: Duration) -> BTreeMap<&'static str, EventRate> {
+        self.rates_at(window, Instant::now())
+    }
+
+    pub fn rates_at(&self, window: Duration, now: Instant) -> BTreeMap<&'static str, EventRate> {
+        let window_secs = clamp_window(window);
+        let mut counts = BTreeMap::new();
+        for (kind, count) in self
+            .recent(window_secs, now)
+            .flat_map(|counts| counts.iter())
+        {
+            *counts.entry(*kind).or_insert(0) += count;
+        }
+        counts
+            .into_iter()
+            .map(|(kind, count)| (kind, EventRate::new(count, window_secs)))
+            .collect()
+    }
+
+    fn recent(
+        &self,
+        window_secs: u64,
+        now: Instant,
+    ) -> impl Iterator<Item = &HashMap<&'static str, usize>> {
+        let now = self.second(now);
+        self.buckets
+            .iter()
+            .rev()
+            .take_while(move |(second, _)| second + window_secs > now)
+            .map(|(_, counts)| counts)
+    }
+
+    fn second(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.origin).as_secs()
+    }
+}
+
+fn clamp_window(window: Duration) -> u64 {
+    window.as_secs().clamp(1, MAX_RATE_WINDOW.as_secs())
+}
@@ -0,0 +1,107 @@
+use crate::timing::ReceivedEvent;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use std::time::{Duration, Instant};
+
+/// How long the first half of a rename waits for the second one. inotify reports both
+/// halves together, within its own 10 ms pairing delay.
+pub(crate) const RENAME_WINDOW: Duration = Duration::from_millis(100);
+
+/// Pairs the two halves of a rename (`Name(From)` and `Name(To)`) into one
+/// `Name(Both)` event with the old and new path, using the tracker (the inotify cookie)
+/// when the backend sets one and otherwise the order of events. A `Name(Both)` that the
+/// backend sends itself after the two halves is dropped. A `From` that finds no
+/// partner within [`RENAME_WINDOW`] is released as it was: the file moved out of scope.
+#[derive(Debug, Default)]
+pub(crate) struct RenamePairer {
+    pending: Vec<(Instant, ReceivedEvent)>,
+    /// Trackers of renames already paired, whose trailing `Both` event is dropped.
+    paired: Vec<usize>,
+}
+
+impl RenamePairer {
+    pub(crate) fn push(&mut self, event: ReceivedEvent, now: Instant) -> Vec<ReceivedEvent> {
+        let mut out = self.expire(now);
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) if event.paths.len() == 1 => {
+                self.pending.push((now, event));
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) if event.paths.len() == 1 => {
+                let tracker = event.tracker();
+                let partner = self
+                    .pending
+                    .iter()
+                    .rposition(|(_, from)| from.tracker() == tracker);
+                match partner {
+                    Some(index) => {
+                        let (_, from) = self.pending.remove(index);
+                        if let Some(tracker) = tracker {
+                            self.paired.push(tracker);
+                        }
+                        out.push(both(from, event));
+                    }
+                    None => out.push(event),
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                let tracker = event.tracker();
+                match self
+                    .paired
+                    .iter()
+                    .position(|paired| Some(*paired) == tracker)
+                {
+                    Some(index) => {
+                        self.paired.remove(index);
+                    }
+                    None => out.push(event),
+                }
+            }
+            _ => out.push(event),
+        }
+        out
+    }
+
+    /// When the oldest unpaired `From` is due to be released.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.pending.first().map(|(held, _)| *held + RENAME_WINDOW)
+    }
+
+    /// Releases the halves that waited longer than the window.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<ReceivedEvent> {
+        let expired = self
+            .pending
+            .iter()
+            .take_while(|(held, _)| now.duration_since(*held) >= RENAME_WINDOW)
+            .count();
+        self.pending
+            .drain(..expired)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    /// Releases everything waiting, e.g. when the monitor stops.
+    pub(crate) fn drain(&mut self) -> Vec<ReceivedEvent> {
+        self.paired.clear();
+        self.pending.drain(..).map(|(_, event)| event).collect()
+    }
+}
+
+/// The two halves as one event, received when the second half was.
+fn both(from: ReceivedEvent, to: ReceivedEvent) -> ReceivedEvent {
+    let mut event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+        .add_path(from.event.paths[0].clone())
+        .add_path(to.event.paths[0].clone());
+    if let Some(tracker) = to.tracker() {
+        event = event.set_tracker(tracker);
+    }
+    if let Some(pid) = to.attrs.process_id() {
+        event = event.set_process_id(pid);
+    }
+    if let Some(executable) = to.info() {
+        event = event.set_info(executable);
+    }
+    ReceivedEvent {
+        event,
+        received: to.received,
+    }
+}
@@ -0,0 +1,107 @@
+use crate::git::GitFileStatus;
+use crate::{FileEvent, FileEventRecord};
+
+/// Outcome of running an [`EventRule`] against an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The rule has no opinion; the event is recorded as usual.
+    Pass,
+    /// The event is recorded and reported as an alert with the given reason.
+    Alert(String),
+    /// The event is dropped before it reaches history and stats.
+    Suppress,
+}
+
+/// Classification logic applied to every event before it is recorded.
+///
+/// Rules are evaluated in registration order; the first verdict other than `Pass` wins.
+pub trait EventRule: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn evaluate(&self, record: &FileEventRecord) -> Verdict;
+}
+
+/// Matches events on files with a given git status, e.g. an untracked executable
+/// appearing in a repository. Events outside a repository never match.
+pub struct GitStatusRule {
+    name: String,
+    file_status: GitFileStatus,
+    event: Option<FileEvent>,
+    executable_only: bool,
+    verdict: Verdict,
+}
+
+impl GitStatusRule {
+    pub fn new(name: &str, file_status: GitFileStatus, verdict: Verdict) -> Self {
+        Self {
+            name: name.to_string(),
+            file_status,
+            event: None,
+            executable_only: false,
+            verdict,
+        }
+    }
+
+    /// Only match this kind of event.
+    pub fn on_event(mut self, event: FileEvent) -> Self {
+        self.event = Some(event);
+        self
+    }
+
+    /// Only match files with an executable bit set.
+    pub fn executable_only(mut self) -> Self {
+        self.executable_only = true;
+        self
+    }
+}
+
+impl EventRule for GitStatusRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn evaluate(&self, record: &FileEventRecord) -> Verdict {
+        let Some(git) = &record.git else {
+            return Verdict::Pass;
+        };
+        if git.file_status != self.file_status
+            || self
+                .event
+                .as_ref()
+                .is_some_and(|event| *event != record.event)
+            || (self.executable_only && !is_executable(&record.path))
+        {
+            return Verdict::Pass;
+        }
+        self.verdict.clone()
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("exe" | "bat" | "cmd" | "ps1")
+    )
+}
+
+pub(crate) fn evaluate_rules(
+    rules: &[Box<dyn EventRule>],
+    record: &FileEventRecord,
+) -> (Verdict, Option<String>) {
+    for rule in rules {
+        let verdict = rule.evaluate(record);
+        if verdict != Verdict::Pass {
+            return (verdict, Some(rule.name().to_string()));
+        }
+    }
+    (Verdict::Pass, None)
+}
@@ -0,0 +1,174 @@
+use crate::attributes::{AttributeTracker, FileMetadata};
+use crate::hashing::ContentHasher;
+use chrono::{DateTime, Local};
+use notify::RecursiveMode;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Progress of the initial scan of a watch, which learns the metadata of the files
+/// already there and, with content hashing, their fingerprints.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanProgress {
+    pub watch: PathBuf,
+    pub started: DateTime<Local>,
+    pub files_scanned: usize,
+    /// Files to scan, `None` while they are still being counted.
+    pub files_total: Option<usize>,
+    pub finished: Option<DateTime<Local>>,
+    /// Time left at the current rate, while running.
+    #[serde(serialize_with = "serialize_secs")]
+    pub eta: Option<Duration>,
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    eta: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_some(&eta.map(|eta| eta.as_secs()))
+}
+
+impl ScanProgress {
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    pub fn percent(&self) -> Option<f64> {
+        match (self.finished, self.files_total) {
+            (Some(_), _) => Some(100.0),
+            (None, Some(0)) | (None, None) => None,
+            (None, Some(total)) => Some(self.files_scanned as f64 * 100.0 / total as f64),
+        }
+    }
+}
+
+/// What a scan fills in for its watch.
+pub(crate) struct ScanTargets {
+    pub(crate) attributes: Arc<Mutex<AttributeTracker>>,
+    pub(crate) hasher: Option<Arc<Mutex<ContentHasher>>>,
+}
+
+/// A scan running alongside live watching. Live events mark the paths they touch, and
+/// the scan leaves those alone: what the event handling learned is newer than what the
+/// scan read.
+pub(crate) struct Scan {
+    watch: PathBuf,
+    started: Instant,
+    progress: Mutex<ScanProgress>,
+    touched: Mutex<HashSet<PathBuf>>,
+    cancelled: AtomicBool,
+}
+
+impl Scan {
+    pub(crate) fn new(watch: &Path) -> Self {
+        Self {
+            watch: watch.to_path_buf(),
+            started: Instant::now(),
+            progress: Mutex::new(ScanProgress {
+                watch: watch.to_path_buf(),
+                started: Local::now(),
+                files_scanned: 0,
+                files_total: None,
+                finished: None,
+                eta: None,
+            }),
+            touched: Mutex::new(HashSet::new()),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn watch(&self) -> &Path {
+        &self.watch
+    }
+
+    pub(crate) fn progress(&self) -> ScanProgress {
+        let mut progress = self.progress.lock().unwrap().clone();
+        if let (None, Some(total)) = (progress.finished, progress.files_total) {
+            if progress.files_scanned > 0 {
+                let per_file = self.started.elapsed() / progress.files_scanned as u32;
+                progress.eta = Some(per_file * total.saturating_sub(progress.files_scanned) as u32);
+            }
+        }
+        progress
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.progress.lock().unwrap().finished.is_none()
+    }
+
+    /// Notes that a live event reported `path`, if the scan is still running below it.
+    pub(crate) fn touch(&self, path: &Path) {
+        if path.starts_with(&self.watch) && self.is_running() {
+            self.touched.lock().unwrap().insert(path.to_path_buf());
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn is_touched(&self, path: &Path) -> bool {
+        self.touched.lock().unwrap().contains(path)
+    }
+
+    /// Counts the files, then scans them. Blocks until done or cancelled.
+    pub(crate) fn run(&self, targets: &ScanTargets, mode: RecursiveMode) {
+        let mut total = 0;
+        walk(&self.watch, mode, &self.cancelled, &mut |_| total += 1);
+        self.progress.lock().unwrap().files_total = Some(total);
+        walk(&self.watch, mode, &self.cancelled, &mut |path| {
+            self.scan_file(path, targets);
+            self.progress.lock().unwrap().files_scanned += 1;
+        });
+        self.progress.lock().unwrap().finished = Some(Local::now());
+        self.touched.lock().unwrap().clear();
+    }
+
+    fn scan_file(&self, path: &Path, targets: &ScanTargets) {
+        let Some(metadata) = FileMetadata::read(path) else {
+            return;
+        };
+        let is_file = std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file());
+        {
+            // Checked under the tracker's lock, as live events mark a path before they
+            // update the tracker.
+            let mut attributes = targets.attributes.lock().unwrap();
+            if self.is_touched(path) {
+                return;
+            }
+            attributes.insert(path, metadata);
+        }
+        if let Some(hasher) = targets.hasher.as_ref().filter(|_| is_file) {
+            let mut hasher = hasher.lock().unwrap();
+            if !self.is_touched(path) {
+                let _ = hasher.hash(path);
+            }
+        }
+    }
+}
+
+/// Calls `visit` with `root` and everything below it, down to `mode`.
+fn walk(root: &Path, mode: RecursiveMode, cancelled: &AtomicBool, visit: &mut dyn FnMut(&Path)) {
+    if cancelled.load(Ordering::Relaxed) {
+        return;
+    }
+    visit(root);
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let path = entry.path();
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if is_dir && mode == RecursiveMode::Recursive {
+            walk(&path, mode, cancelled, visit);
+        } else {
+            visit(&path);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::error::{monitor_error, Result};
+use crate::{FileEvent, FileEventRecord};
+use log::{debug, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_SHELL_HOOK_CONCURRENCY: usize = 4;
+pub const DEFAULT_SHELL_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+/// Commands waiting for a slot, per allowed concurrent command, before new ones are
+/// dropped.
+const QUEUED_PER_SLOT: usize = 16;
+
+/// A command run when an event of one kind is recorded, e.g. `rsync {path} backup:/srv/`
+/// for `modified`. The template is split into arguments like a shell would, but the
+/// program is run directly: `{path}`, `{event}`, `{time}` (RFC 3339) and `{watch}` are
+/// substituted within arguments, so file names never become shell syntax. For pipes or
+/// redirections, pass values as positional arguments: `sh -c 'gzip -c "$1" > "$1.gz"' sh {path}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellHook {
+    pub event: String,
+    pub command: String,
+    argv: Vec<String>,
+}
+
+impl ShellHook {
+    pub fn new(event: &str, command: &str) -> Result<Self> {
+        let event = event.to_ascii_lowercase();
+        if !FileEvent::KINDS.contains(&event.as_str()) {
+            return Err(monitor_error!(
+                "Unknown event kind {} for a shell hook, expected one of {}",
+                event,
+                FileEvent::KINDS.join(", ")
+            ));
+        }
+        let argv = shlex::split(command)
+            .filter(|argv| !argv.is_empty())
+            .ok_or_else(|| monitor_error!("Shell hook for {} is empty or badly quoted", event))?;
+        Ok(Self {
+            event,
+            command: command.to_string(),
+            argv,
+        })
+    }
+
+    /// Program and arguments for `record`.
+    pub fn argv(&self, record: &FileEventRecord) -> Vec<String> {
+        let path = record.path.display().to_string();
+        let watch = record.watch.display().to_string();
+        let time = record.time.to_rfc3339();
+        let values = [
+            ("{path}", path.as_str()),
+            ("{event}", record.event.kind()),
+            ("{time}", time.as_str()),
+            ("{watch}", watch.as_str()),
+        ];
+        self.argv
+            .iter()
+            .map(|arg| substitute(arg, &values))
+            .collect()
+    }
+}
+
+/// Replaces placeholders in one pass, so substituted values are never expanded again.
+pub fn substitute(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                result.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Runs [`ShellHook`]s in the background, at most `max_concurrent` at a time. Commands
+/// are killed after `timeout`; when too many are waiting, new ones are dropped.
+#[derive(Debug)]
+pub struct ShellHooks {
+    hooks: Vec<ShellHook>,
+    slots: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+    timeout: Duration,
+}
+
+impl ShellHooks {
+    pub fn new(hooks: Vec<ShellHook>, max_concurrent: usize, timeout: Duration) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            hooks,
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            max_queued: max_concurrent * QUEUED_PER_SLOT,
+            timeout,
+        }
+    }
+
+    pub fn hooks(&self) -> &[ShellHook] {
+        &self.hooks
+    }
+
+    /// Starts the hooks for the record's event kind without waiting for them.
+    pub fn run(&self, record: &FileEventRecord) {
+        for hook in self
+            .hooks
+            .iter()
+            .filter(|hook| hook.event == record.event.kind())
+        {
+            if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                warn!(
+                    "Too many shell hooks pending, dropping `{}` for {}",
+                    hook.command,
+                    record.path.display()
+                );
+                continue;
+            }
+            let argv = hook.argv(record);
+            let slots = Arc::clone(&self.slots);
+            let queued = Arc::clone(&self.queued);
+            let timeout = self.timeout;
+            tokio::spawn(async move {
+                let permit = slots.acquire_owned().await;
+                queued.fetch_sub(1, Ordering::SeqCst);
+                if permit.is_err() {
+                    return;
+                }
+                if let Err(e) = execute(&argv, timeout).await {
+                    warn!("Shell hook {:?} failed: {}", argv, e);
+                }
+            });
+        }
+    }
+}
+
+async fn execute(argv: &[String], timeout: Duration) -> Result<()> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| monitor_error!("empty command"))?;
+    let child = Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(timeout, child)
+        .await
+        .map_err(|_| monitor_error!("timed out after {:?}", timeout))??;
+    if !output.status.success() {
+        return Err(monitor_error!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    debug!("Shell hook {:?} done", argv);
+    Ok(())
+}
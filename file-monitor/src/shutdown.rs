@@ -0,0 +1,89 @@
+use crate::error::Result;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::watch;
+
+/// Cancellation signal for long-running tasks. Tokens form a tree: cancelling one also
+/// cancels every token created from it with [`ShutdownToken::child`], so a subsystem can
+/// be stopped on its own or together with everything else.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    node: Arc<Node>,
+}
+
+struct Node {
+    cancelled: watch::Sender<bool>,
+    children: Mutex<Vec<Weak<Node>>>,
+}
+
+impl Node {
+    fn cancel(&self) {
+        // The flag is set before the children are taken, so a child created concurrently
+        // is either in the list or sees its parent already cancelled.
+        self.cancelled.send_replace(true);
+        let children = std::mem::take(&mut *self.children.lock().unwrap());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl Default for ShutdownToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownToken {
+    pub fn new() -> Self {
+        Self {
+            node: Arc::new(Node {
+                cancelled: watch::Sender::new(false),
+                children: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// A token cancelled together with this one, but which can also be cancelled alone.
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut children = self.node.children.lock().unwrap();
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.node));
+        }
+        child
+    }
+
+    pub fn cancel(&self) {
+        self.node.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.node.cancelled.borrow()
+    }
+
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.node.cancelled.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on Unix.
+pub async fn signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
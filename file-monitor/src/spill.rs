@@ -0,0 +1,171 @@
+use crate::error::{monitor_error, Result};
+use crate::timing::ReceivedEvent;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_MEMORY_QUEUED_EVENTS: usize = 64;
+pub const DEFAULT_MEMORY_DEDUP_ENTRIES: usize = 1024;
+pub const DEFAULT_MEMORY_SNAPSHOT_BYTES: u64 = 4 * 1024 * 1024;
+pub const DEFAULT_SPILL_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Hard caps on what the event pipeline keeps in memory, for small VMs and embedded
+/// boxes. Events beyond the queue cap go to a temporary file in `spill_dir` instead of
+/// being dropped, and are handled in order once the queue has room again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryLimits {
+    /// Events queued in memory; more are spilled to disk.
+    pub queued_events: usize,
+    /// Entries of the debounce cache; the oldest is evicted to make room.
+    pub dedup_entries: usize,
+    /// Bytes of file content kept for diffs across all files; files that do not fit
+    /// get no diffs until others are forgotten.
+    pub snapshot_bytes: u64,
+    pub spill_dir: PathBuf,
+    /// Events that would grow the spill file beyond this are dropped and counted.
+    pub spill_max_bytes: u64,
+}
+
+impl MemoryLimits {
+    pub fn new<P: AsRef<Path>>(spill_dir: P) -> Self {
+        Self {
+            queued_events: DEFAULT_MEMORY_QUEUED_EVENTS,
+            dedup_entries: DEFAULT_MEMORY_DEDUP_ENTRIES,
+            snapshot_bytes: DEFAULT_MEMORY_SNAPSHOT_BYTES,
+            spill_dir: spill_dir.as_ref().to_path_buf(),
+            spill_max_bytes: DEFAULT_SPILL_MAX_BYTES,
+        }
+    }
+}
+
+/// First-in first-out queue of events in a temporary file, one JSON line each. The
+/// file is emptied whenever the queue runs dry, compacted once more than half of its
+/// limit has been read, and removed when the queue is dropped.
+#[derive(Debug)]
+pub(crate) struct SpillQueue {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+    read_offset: u64,
+    write_offset: u64,
+    len: usize,
+}
+
+impl SpillQueue {
+    pub(crate) fn create(dir: &Path, max_bytes: u64) -> Result<Self> {
+        let mut suffix = [0u8; 8];
+        getrandom::getrandom(&mut suffix).map_err(|e| monitor_error!("{}", e))?;
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "file-monitor-spill-{}.jsonl",
+            crate::config_guard::to_hex(&suffix)
+        ));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            max_bytes,
+            read_offset: 0,
+            write_offset: 0,
+            len: 0,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `event`. Returns `false`, keeping the queue as it was, if the file would
+    /// grow beyond its limit.
+    pub(crate) fn push(&mut self, event: &ReceivedEvent) -> Result<bool> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        if self.write_offset + line.len() as u64 > self.max_bytes {
+            return Ok(false);
+        }
+        self.file.seek(SeekFrom::Start(self.write_offset))?;
+        self.file.write_all(&line)?;
+        self.write_offset += line.len() as u64;
+        self.len += 1;
+        Ok(true)
+    }
+
+    /// Takes up to `max` of the oldest events. Blocks while reading.
+    pub(crate) fn pop(&mut self, max: usize) -> Result<Vec<ReceivedEvent>> {
+        let mut events = Vec::new();
+        if self.is_empty() {
+            return Ok(events);
+        }
+        self.file.seek(SeekFrom::Start(self.read_offset))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut line = String::new();
+        while events.len() < max.min(self.len) {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                return Err(monitor_error!(
+                    "Spill file {} ended before its {} events",
+                    self.path.display(),
+                    self.len
+                ));
+            }
+            self.read_offset += read as u64;
+            events.push(serde_json::from_str(&line)?);
+        }
+        self.len -= events.len();
+        if self.is_empty() {
+            self.file.set_len(0)?;
+            self.read_offset = 0;
+            self.write_offset = 0;
+        } else if self.read_offset > self.max_bytes / 2 {
+            // A queue that never drains completely would otherwise hit the limit with
+            // most of the file already handled.
+            self.compact()?;
+        }
+        Ok(events)
+    }
+
+    /// Empties the queue after it failed, e.g. on a full or broken disk, and returns how
+    /// many events were lost with it.
+    pub(crate) fn clear(&mut self) -> usize {
+        let _ = self.file.set_len(0);
+        self.read_offset = 0;
+        self.write_offset = 0;
+        std::mem::take(&mut self.len)
+    }
+
+    /// Moves the unread events to the start of the file and cuts off the rest.
+    fn compact(&mut self) -> Result<()> {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut from = self.read_offset;
+        let mut to = 0;
+        while from < self.write_offset {
+            let chunk = buffer.len().min((self.write_offset - from) as usize);
+            self.file.seek(SeekFrom::Start(from))?;
+            self.file.read_exact(&mut buffer[..chunk])?;
+            self.file.seek(SeekFrom::Start(to))?;
+            self.file.write_all(&buffer[..chunk])?;
+            from += chunk as u64;
+            to += chunk as u64;
+        }
+        self.file.set_len(to)?;
+        self.read_offset = 0;
+        self.write_offset = to;
+        Ok(())
+    }
+}
+
+impl Drop for SpillQueue {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
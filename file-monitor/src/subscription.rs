@@ -0,0 +1,60 @@
+use crate::FileEventRecord;
+use log::warn;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+/// Something a subscriber is told about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorEvent {
+    /// An event was recorded in history and stats.
+    File(FileEventRecord),
+    /// A rule raised an alert for a recorded event.
+    Alert {
+        rule: String,
+        reason: String,
+        record: FileEventRecord,
+    },
+    /// The monitor's own configuration was changed in an invalid or unauthorized way.
+    /// The previous configuration stays in effect.
+    Critical { reason: String },
+}
+
+/// A live feed of [`MonitorEvent`]s, returned by [`crate::FileMonitor::subscribe`].
+///
+/// Each subscriber has its own bounded buffer. A subscriber that falls behind loses the
+/// oldest events rather than slowing the monitor down.
+pub struct EventSubscription {
+    receiver: broadcast::Receiver<MonitorEvent>,
+}
+
+impl EventSubscription {
+    pub(crate) fn new(receiver: broadcast::Receiver<MonitorEvent>) -> Self {
+        Self { receiver }
+    }
+
+    /// Waits for the next event. Returns `None` once the monitor has been dropped.
+    pub async fn recv(&mut self) -> Option<MonitorEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Subscriber lagged behind, skipped {} events", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the next event if one is already buffered.
+    pub fn try_recv(&mut self) -> Option<MonitorEvent> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    warn!("Subscriber lagged behind, skipped {} events", skipped)
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::error::{MonitorError, Result};
+use crate::shutdown::ShutdownToken;
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::{AbortHandle, JoinHandle};
+
+/// How a supervised task is restarted after it panics or returns an error. A task that
+/// returns `Ok` is finished and is not restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Restarts before giving up; `None` restarts forever.
+    pub max_restarts: Option<u32>,
+    pub initial_backoff: Duration,
+    /// Backoff doubles after each failure up to this cap. A task that stayed up for at
+    /// least this long starts again from `initial_backoff`.
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Failed and waiting out the backoff before the next restart.
+    BackingOff,
+    Finished,
+    /// Failed more often than the restart policy allows.
+    GaveUp,
+    /// Returned or was aborted during a shutdown.
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Local>,
+}
+
+/// Owns long-running tasks (watcher loops, device loops, sinks, servers), restarting them
+/// on panic or error with exponential backoff and keeping their health for status APIs.
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    shared: Arc<Shared>,
+    shutdown: ShutdownToken,
+}
+
+#[derive(Default)]
+struct Shared {
+    tasks: Mutex<BTreeMap<String, TaskStatus>>,
+    /// The current run of each task, for aborting tasks that miss the shutdown deadline.
+    runs: Mutex<BTreeMap<String, AbortHandle>>,
+    changed: Notify,
+}
+
+impl Shared {
+    async fn update(&self, name: &str, state: TaskState, restarts: u32, error: Option<&str>) {
+        let mut tasks = self.tasks.lock().await;
+        let status = tasks.entry(name.to_string()).or_insert_with(|| TaskStatus {
+            name: name.to_string(),
+            state,
+            restarts,
+            last_error: None,
+            started_at: Local::now(),
+        });
+        if state == TaskState::Running {
+            status.started_at = Local::now();
+        }
+        status.state = state;
+        status.restarts = restarts;
+        if let Some(error) = error {
+            status.last_error = Some(error.to_string());
+        }
+        self.changed.notify_waiters();
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops restarting tasks once `token` is cancelled, see [`Supervisor::shutdown`].
+    pub fn with_shutdown(mut self, token: ShutdownToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// Cancelled when the supervisor shuts down; tasks should watch a child of it and
+    /// return once it is cancelled.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.child()
+    }
+
+    /// Runs the future returned by `factory` as task `name`, calling `factory` again for
+    /// each restart. The returned handle completes once the task finishes, is stopped by a
+    /// shutdown or the policy gives up, with the last error in the latter case.
+    pub fn spawn<F, Fut, E>(
+        &self,
+        name: &str,
+        policy: RestartPolicy,
+        factory: F,
+    ) -> JoinHandle<Result<()>>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display + Send + 'static,
+    {
+        let shared = Arc::clone(&self.shared);
+        let shutdown = self.shutdown.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let mut restarts = 0;
+            let mut backoff = policy.initial_backoff;
+            loop {
+                shared
+                    .update(&name, TaskState::Running, restarts, None)
+                    .await;
+                let started = Instant::now();
+                let run = tokio::spawn(factory());
+                shared
+                    .runs
+                    .lock()
+                    .await
+                    .insert(name.clone(), run.abort_handle());
+                let error = match run.await {
+                    Ok(Ok(())) => {
+                        let state = if shutdown.is_cancelled() {
+                            TaskState::Stopped
+                        } else {
+                            info!("Task {} finished", name);
+                            TaskState::Finished
+                        };
+                        shared.update(&name, state, restarts, None).await;
+                        return Ok(());
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) if e.is_panic() => {
+                        format!("panicked: {}", panic_message(e.into_panic()))
+                    }
+                    Err(e) => e.to_string(),
+                };
+
+                if shutdown.is_cancelled() {
+                    shared
+                        .update(&name, TaskState::Stopped, restarts, Some(&error))
+                        .await;
+                    return Ok(());
+                }
+                if policy.max_restarts.is_some_and(|max| restarts >= max) {
+                    error!(
+                        "Task {} failed, giving up after {} restarts: {}",
+                        name, restarts, error
+                    );
+                    shared
+                        .update(&name, TaskState::GaveUp, restarts, Some(&error))
+                        .await;
+                    return Err(MonitorError::TaskFailed { name, error });
+                }
+                if started.elapsed() >= policy.max_backoff {
+                    backoff = policy.initial_backoff;
+                }
+                warn!(
+                    "Task {} failed, restarting in {:?}: {}",
+                    name, backoff, error
+                );
+                shared
+                    .update(&name, TaskState::BackingOff, restarts, Some(&error))
+                    .await;
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown.cancelled() => {
+                        shared.update(&name, TaskState::Stopped, restarts, None).await;
+                        return Ok(());
+                    }
+                }
+                backoff = (backoff * 2).min(policy.max_backoff);
+                restarts += 1;
+            }
+        })
+    }
+
+    /// Health of every task spawned so far, by name.
+    pub async fn status(&self) -> Vec<TaskStatus> {
+        self.shared.tasks.lock().await.values().cloned().collect()
+    }
+
+    /// Cancels the shutdown token and waits up to `deadline` for every task to return,
+    /// aborting the ones still running after that. Returns whether all tasks stopped on
+    /// their own.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.shutdown.cancel();
+        let stopped = tokio::time::timeout(deadline, async {
+            loop {
+                let changed = self.shared.changed.notified();
+                if self.running().await.is_empty() {
+                    return;
+                }
+                changed.await;
+            }
+        })
+        .await
+        .is_ok();
+        if !stopped {
+            let runs = self.shared.runs.lock().await;
+            for name in self.running().await {
+                warn!("Task {} missed the shutdown deadline, aborting", name);
+                if let Some(run) = runs.get(&name) {
+                    run.abort();
+                }
+            }
+        }
+        stopped
+    }
+
+    async fn running(&self) -> Vec<String> {
+        self.shared
+            .tasks
+            .lock()
+            .await
+            .values()
+            .filter(|task| matches!(task.state, TaskState::Running | TaskState::BackingOff))
+            .map(|task| task.name.clone())
+            .collect()
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+            || "unknown panic".to_string(),
+            |message| message.to_string(),
+        ),
+    }
+}
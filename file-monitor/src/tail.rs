@@ -0,0 +1,22 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Most bytes of one append attached to its event; of a larger append, only the end is
+/// kept, like `tail`.
+pub const MAX_TAIL_BYTES: u64 = 64 * 1024;
+
+/// Lines written to `path` between lengths `from` and `to`. A line still being written
+/// is returned as it is, and its rest comes with the next append.
+pub(crate) fn read_appended(path: &Path, from: u64, to: u64) -> std::io::Result<Vec<String>> {
+    let start = from.max(to.saturating_sub(MAX_TAIL_BYTES));
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut appended = Vec::new();
+    file.take(to - start).read_to_end(&mut appended)?;
+    let text = String::from_utf8_lossy(&appended);
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    Ok(text
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect())
+}
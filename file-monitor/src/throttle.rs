@@ -0,0 +1,236 @@
+use crate::error::MonitorError;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket settings: up to `burst` operations at once, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimiterMetrics {
+    pub allowed: u64,
+    pub throttled: u64,
+}
+
+/// Limits how often an operation runs, e.g. deliveries to one webhook or requests to an
+/// HTTP API.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+    metrics: Mutex<RateLimiterMetrics>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.burst as f64,
+                refilled_at: Instant::now(),
+            }),
+            metrics: Mutex::new(RateLimiterMetrics::default()),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a token if one is available; callers drop or reject the operation otherwise.
+    pub fn try_acquire(&self) -> bool {
+        let acquired = self.reserve().is_ok();
+        let mut metrics = self.metrics.lock().unwrap();
+        if acquired {
+            metrics.allowed += 1;
+        } else {
+            metrics.throttled += 1;
+        }
+        acquired
+    }
+
+    /// Waits until a token is available.
+    pub async fn acquire(&self) {
+        let mut throttled = false;
+        while let Err(wait) = self.reserve() {
+            throttled = true;
+            tokio::time::sleep(wait).await;
+        }
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.allowed += 1;
+        if throttled {
+            metrics.throttled += 1;
+        }
+    }
+
+    pub fn metrics(&self) -> RateLimiterMetrics {
+        *self.metrics.lock().unwrap()
+    }
+
+    /// Takes a token, or returns how long until the next one is available.
+    fn reserve(&self) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.limit.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.limit.burst as f64);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.limit.per_second > 0.0 {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.per_second,
+            ))
+        } else {
+            Err(Duration::from_secs(1))
+        }
+    }
+}
+
+/// Circuit breaker settings: after `failure_threshold` consecutive failures calls are
+/// rejected for `open_secs`, then a single trial call decides whether to close again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub open_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    /// Calls are rejected without being attempted.
+    Open,
+    /// The open period passed and a trial call is in flight.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerMetrics {
+    pub state: BreakerState,
+    pub successes: u64,
+    pub failures: u64,
+    pub rejected: u64,
+    /// How often the breaker opened.
+    pub trips: u64,
+}
+
+/// Stops calling a failing dependency for a while instead of retrying it on every
+/// operation, e.g. a webhook endpoint or audit collector that is down.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    config: BreakerConfig,
+    inner: Mutex<BreakerInner>,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+    metrics: CircuitBreakerMetrics,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: impl Into<String>, config: BreakerConfig) -> Self {
+        Self {
+            name: name.into(),
+            config,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+                metrics: CircuitBreakerMetrics {
+                    state: BreakerState::Closed,
+                    successes: 0,
+                    failures: 0,
+                    rejected: 0,
+                    trips: 0,
+                },
+            }),
+        }
+    }
+
+    /// Whether a call may be attempted now. Every allowed call must be followed by
+    /// [`CircuitBreaker::record_success`] or [`CircuitBreaker::record_failure`].
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let allowed = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open
+                if inner.opened_at.elapsed() >= Duration::from_secs(self.config.open_secs) =>
+            {
+                inner.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open | BreakerState::HalfOpen => false,
+        };
+        if !allowed {
+            inner.metrics.rejected += 1;
+        }
+        allowed
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = BreakerState::Closed;
+        inner.consecutive_failures = 0;
+        inner.metrics.successes += 1;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.metrics.failures += 1;
+        inner.consecutive_failures += 1;
+        let trip = inner.state == BreakerState::HalfOpen
+            || (inner.state == BreakerState::Closed
+                && inner.consecutive_failures >= self.config.failure_threshold);
+        if trip {
+            inner.state = BreakerState::Open;
+            inner.opened_at = Instant::now();
+            inner.metrics.trips += 1;
+        }
+    }
+
+    /// Runs `call` unless the breaker is open, recording its outcome. An open breaker
+    /// fails with [`MonitorError::CircuitOpen`].
+    pub async fn call<T, E, F>(&self, call: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+        E: From<MonitorError>,
+    {
+        if !self.allow() {
+            return Err(MonitorError::CircuitOpen(self.name.clone()).into());
+        }
+        let result = call.await;
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(_) => self.record_failure(),
+        }
+        result
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn metrics(&self) -> CircuitBreakerMetrics {
+        let inner = self.inner.lock().unwrap();
+        CircuitBreakerMetrics {
+            state: inner.state,
+            ..inner.metrics
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::FileEvent;
+use chrono::{DateTime, Local};
+use notify::Event;
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::path::Path;
+use std::time::Duration;
+
+/// A file's mtime is taken as the time of a write only if it is at most this much older
+/// than the event; older ones were set explicitly (`touch -d`, archive extraction).
+const MAX_MTIME_AGE: Duration = Duration::from_secs(2);
+
+/// A watcher event and when the backend delivered it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ReceivedEvent {
+    pub(crate) event: Event,
+    pub(crate) received: DateTime<Local>,
+}
+
+impl ReceivedEvent {
+    pub(crate) fn now(event: Event) -> Self {
+        Self {
+            event,
+            received: Local::now(),
+        }
+    }
+}
+
+impl Deref for ReceivedEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Event {
+        &self.event
+    }
+}
+
+/// When the change behind an event happened, as closely as it can be told. notify does
+/// not pass on kernel timestamps, so this is the file's mtime for writes that the kernel
+/// stamped just before the event was received, and otherwise the time of receipt.
+pub(crate) fn occurred_at(
+    path: &Path,
+    event: &FileEvent,
+    received: DateTime<Local>,
+) -> DateTime<Local> {
+    if !matches!(
+        event,
+        FileEvent::Created | FileEvent::Modified | FileEvent::Replaced(_)
+    ) {
+        return received;
+    }
+    let Some(modified) = std::fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(DateTime::<Local>::from)
+    else {
+        return received;
+    };
+    match (received - modified).to_std() {
+        Ok(age) if age <= MAX_MTIME_AGE => modified,
+        _ => received,
+    }
+}
+
+/// Delay between an event occurring and the monitor recording it, over all events that
+/// came from the watcher: queueing, atomic-save holding, hashing and the like.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyMetrics {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+    pub last: Duration,
+}
+
+impl LatencyMetrics {
+    pub fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+        self.last = latency;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0)
+            .then(|| Duration::from_secs_f64(self.total.as_secs_f64() / self.count as f64))
+    }
+}
@@ -0,0 +1,365 @@
+use crate::config_guard::to_hex;
+use crate::error::{monitor_error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// PEM files for a TLS listener. With `client_ca`, clients must present a certificate
+/// issued by one of its CAs before any request is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: None,
+        }
+    }
+
+    /// Requires client certificates issued by the CAs in `client_ca`.
+    pub fn client_ca(mut self, client_ca: impl Into<PathBuf>) -> Self {
+        self.client_ca = Some(client_ca.into());
+        self
+    }
+
+    /// Builds a server config from the current contents of the files.
+    pub fn load(&self) -> Result<ServerConfig> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(client_ca)? {
+                    roots.add(cert)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| monitor_error!("Invalid client CA: {}", e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(builder.with_single_cert(read_certs(&self.cert)?, read_key(&self.key)?)?)
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect()
+    }
+}
+
+/// A TLS acceptor whose certificates can be replaced while the listener keeps running,
+/// e.g. after a certificate renewal. Connections already open keep their session.
+pub struct ReloadableTls {
+    settings: TlsSettings,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl ReloadableTls {
+    pub fn new(settings: TlsSettings) -> Result<Self> {
+        let config = settings.load()?;
+        Ok(Self {
+            settings,
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    pub fn settings(&self) -> &TlsSettings {
+        &self.settings
+    }
+
+    /// Acceptor for the certificates loaded last.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::clone(&self.config.read().unwrap()))
+    }
+
+    /// Loads the files again, keeping the previous certificates if they are invalid.
+    pub fn reload(&self) -> Result<()> {
+        let config = self.settings.load()?;
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    /// Signals `tx` whenever one of the certificate or key files changes.
+    pub(crate) fn watch(
+        &self,
+        tx: tokio::sync::mpsc::Sender<()>,
+    ) -> Result<notify::RecommendedWatcher> {
+        crate::watch_files(self.settings.files(), tx)
+    }
+}
+
+/// How a client authenticates a TLS server and itself, e.g. the guardian's connection to
+/// its audit collector.
+///
+/// The server must chain to `ca_cert`, or, without it, to a certificate listed in
+/// `pinned_sha256`. With both, it must do both. `client_cert` and `client_key` enable
+/// mutual TLS.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsClientSettings {
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Hex SHA-256 fingerprints of accepted server certificates (DER).
+    #[serde(default)]
+    pub pinned_sha256: Vec<String>,
+    /// Name to verify the server certificate against; defaults to the host connected to.
+    pub server_name: Option<String>,
+}
+
+impl TlsClientSettings {
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        serde_json::from_str(&content)
+            .map_err(|e| monitor_error!("{}: {}", path.as_ref().display(), e))
+    }
+
+    /// Builds a client config from the current contents of the files.
+    pub fn load(&self) -> Result<ClientConfig> {
+        if self.ca_cert.is_none() && self.pinned_sha256.is_empty() {
+            return Err(monitor_error!(
+                "TLS client settings need a CA certificate or pinned server certificates"
+            ));
+        }
+        let provider = Arc::new(ring::default_provider());
+        let ca_verifier = match &self.ca_cert {
+            Some(ca_cert) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(ca_cert)? {
+                    roots.add(cert)?;
+                }
+                Some(
+                    WebPkiServerVerifier::builder_with_provider(
+                        Arc::new(roots),
+                        Arc::clone(&provider),
+                    )
+                    .build()
+                    .map_err(|e| monitor_error!("Invalid CA: {}", e))?,
+                )
+            }
+            None => None,
+        };
+        let pins = self
+            .pinned_sha256
+            .iter()
+            .map(|pin| pin.replace(':', "").to_ascii_lowercase())
+            .collect();
+        let verifier = Arc::new(PinningVerifier {
+            ca_verifier,
+            pins,
+            provider: Arc::clone(&provider),
+        });
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+        Ok(match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(read_certs(cert)?, read_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(monitor_error!(
+                    "client_cert and client_key must be set together"
+                ))
+            }
+        })
+    }
+
+    fn files(&self) -> Vec<&PathBuf> {
+        [&self.ca_cert, &self.client_cert, &self.client_key]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// Hex SHA-256 of a DER certificate, as used in [`TlsClientSettings::pinned_sha256`].
+pub fn certificate_fingerprint(cert: &[u8]) -> String {
+    to_hex(&Sha256::digest(cert))
+}
+
+/// A TLS connector that picks up rotated certificate and key files before each new
+/// connection, keeping the previous config if the new files are invalid.
+pub struct TlsClient {
+    settings: TlsClientSettings,
+    current: Mutex<(Vec<Option<SystemTime>>, Arc<ClientConfig>)>,
+}
+
+impl TlsClient {
+    pub fn new(settings: TlsClientSettings) -> Result<Self> {
+        let config = settings.load()?;
+        let modified = modification_times(&settings);
+        Ok(Self {
+            settings,
+            current: Mutex::new((modified, Arc::new(config))),
+        })
+    }
+
+    pub fn settings(&self) -> &TlsClientSettings {
+        &self.settings
+    }
+
+    /// Runs the TLS handshake over `stream` with the server at `host`.
+    pub async fn connect<S>(&self, host: &str, stream: S) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let name = self.settings.server_name.as_deref().unwrap_or(host);
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| monitor_error!("Invalid TLS server name {}: {}", name, e))?;
+        Ok(TlsConnector::from(self.config())
+            .connect(server_name, stream)
+            .await?)
+    }
+
+    fn config(&self) -> Arc<ClientConfig> {
+        let mut current = self.current.lock().unwrap();
+        let modified = modification_times(&self.settings);
+        if modified != current.0 {
+            match self.settings.load() {
+                Ok(config) => {
+                    log::info!("Reloaded TLS client certificates");
+                    *current = (modified, Arc::new(config));
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to reload TLS client certificates, keeping current ones: {}",
+                        e
+                    )
+                }
+            }
+        }
+        Arc::clone(&current.1)
+    }
+}
+
+fn modification_times(settings: &TlsClientSettings) -> Vec<Option<SystemTime>> {
+    settings
+        .files()
+        .into_iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct PinningVerifier {
+    ca_verifier: Option<Arc<WebPkiServerVerifier>>,
+    /// Lower-case hex SHA-256 of accepted certificates; empty accepts any CA-issued one.
+    pins: Vec<String>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, TlsError> {
+        if let Some(ca_verifier) = &self.ca_verifier {
+            ca_verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if !self.pins.is_empty() && !self.pins.contains(&certificate_fingerprint(end_entity)) {
+            return Err(TlsError::General(
+                "server certificate does not match any pinned fingerprint".to_string(),
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        tokio_rustls::rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, TlsError> {
+        tokio_rustls::rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file =
+        std::fs::File::open(path).map_err(|e| monitor_error!("{}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| monitor_error!("{}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(monitor_error!("{}: no certificates found", path.display()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file =
+        std::fs::File::open(path).map_err(|e| monitor_error!("{}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| monitor_error!("{}: {}", path.display(), e))?
+        .ok_or_else(|| monitor_error!("{}: no private key found", path.display()))
+}
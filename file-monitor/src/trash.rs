@@ -0,0 +1,316 @@
+use crate::error::{monitor_error, Result};
+use crate::FileMetadata;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use std::path::{Path, PathBuf};
+
+/// A file found in a trash counts as the one that just vanished if it was trashed no
+/// longer ago than this.
+const MATCH_WINDOW_SECS: i64 = 60;
+/// Seconds between 1601-01-01, the epoch of Windows `FILETIME`, and the Unix epoch.
+const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+/// A trash (recycle bin) files may be moved to instead of being deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trash {
+    /// A freedesktop.org trash directory, holding `files/` and a `.trashinfo` per file in
+    /// `info/` with the original path and when it was trashed.
+    Xdg(PathBuf),
+    /// `~/.Trash` on macOS. Finder records no original path, so files are matched by name
+    /// and by the size and modification time they had.
+    MacOs(PathBuf),
+    /// A drive's `$Recycle.Bin`, with a folder per user holding `$R` files and `$I`
+    /// records of their original path.
+    RecycleBin(PathBuf),
+}
+
+/// A file in a trash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// Where the file was before it was trashed.
+    pub original: PathBuf,
+    /// Where it is in the trash.
+    pub location: PathBuf,
+    /// The trash's record of the file, removed along with it on restore.
+    pub info: Option<PathBuf>,
+    pub trashed_at: Option<DateTime<Local>>,
+}
+
+impl TrashEntry {
+    /// Moves the file back to its original path and drops the trash's record of it.
+    /// Refuses to overwrite a file created at that path since.
+    pub fn restore(&self) -> Result<PathBuf> {
+        if std::fs::symlink_metadata(&self.original).is_ok() {
+            return Err(monitor_error!("{} already exists", self.original.display()));
+        }
+        if let Some(parent) = self.original.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&self.location, &self.original)?;
+        if let Some(info) = &self.info {
+            if let Err(e) = std::fs::remove_file(info) {
+                log::warn!("Failed to remove trash record {}: {}", info.display(), e);
+            }
+        }
+        Ok(self.original.clone())
+    }
+}
+
+impl Trash {
+    /// The trashes a file at `path` is moved to by the platform's file manager: the
+    /// user's home trash, plus on Linux the `.Trash/<uid>` and `.Trash-<uid>` trashes of
+    /// the directories above `path`, used for files on other mounts.
+    pub fn defaults_for(path: &Path) -> Vec<Trash> {
+        let mut trashes = Vec::new();
+        #[cfg(target_os = "macos")]
+        if let Some(home) = std::env::var_os("HOME") {
+            trashes.push(Trash::MacOs(PathBuf::from(home).join(".Trash")));
+        }
+        #[cfg(windows)]
+        if let Some(drive) = path.ancestors().last() {
+            trashes.push(Trash::RecycleBin(drive.join("$Recycle.Bin")));
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            let data_home = std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share"))
+                });
+            if let Some(data_home) = data_home {
+                trashes.push(Trash::Xdg(data_home.join("Trash")));
+            }
+            #[cfg(target_os = "linux")]
+            {
+                // SAFETY: getuid cannot fail and has no side effects.
+                let uid = unsafe { libc::getuid() };
+                for dir in path.ancestors().skip(1) {
+                    for trash in [
+                        dir.join(".Trash").join(uid.to_string()),
+                        dir.join(format!(".Trash-{}", uid)),
+                    ] {
+                        if trash.is_dir() {
+                            trashes.push(Trash::Xdg(trash));
+                        }
+                    }
+                }
+            }
+        }
+        #[cfg(not(any(windows, target_os = "linux")))]
+        let _ = path;
+        trashes
+    }
+
+    /// The directory trashed files are kept in.
+    pub fn files_dir(&self) -> PathBuf {
+        match self {
+            Trash::Xdg(dir) => dir.join("files"),
+            Trash::MacOs(dir) | Trash::RecycleBin(dir) => dir.clone(),
+        }
+    }
+
+    /// Whether `path` is inside the trash.
+    pub fn holds(&self, path: &Path) -> bool {
+        path.starts_with(self.files_dir())
+    }
+
+    /// The files in the trash that were at `original`, most recently trashed first.
+    pub fn entries_for(&self, original: &Path) -> Vec<TrashEntry> {
+        let mut entries = match self {
+            Trash::Xdg(dir) => xdg_entries(dir, original),
+            Trash::MacOs(dir) => macos_entries(dir, original),
+            Trash::RecycleBin(dir) => recycle_bin_entries(dir, original),
+        };
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.trashed_at));
+        entries
+    }
+}
+
+/// Finds the file that just vanished from `original` in one of `trashes`: an entry for
+/// that path trashed within the last minute that, if `last_known` is given, has the size
+/// and modification time the file had, since moving keeps both.
+pub fn locate(
+    trashes: &[Trash],
+    original: &Path,
+    last_known: Option<&FileMetadata>,
+) -> Option<TrashEntry> {
+    let since = Local::now() - Duration::seconds(MATCH_WINDOW_SECS);
+    trashes
+        .iter()
+        .flat_map(|trash| trash.entries_for(original))
+        .filter(|entry| entry.trashed_at.is_some_and(|at| at >= since))
+        .find(
+            |entry| match (last_known, FileMetadata::read(&entry.location)) {
+                (Some(known), Some(found)) => {
+                    known.size == found.size
+                        && (known.modified.is_none() || known.modified == found.modified)
+                }
+                (None, found) => found.is_some(),
+                (Some(_), None) => false,
+            },
+        )
+}
+
+fn xdg_entries(dir: &Path, original: &Path) -> Vec<TrashEntry> {
+    let Ok(infos) = std::fs::read_dir(dir.join("info")) else {
+        return Vec::new();
+    };
+    infos
+        .flatten()
+        .filter_map(|info| {
+            let info = info.path();
+            let name = info
+                .file_name()?
+                .to_str()?
+                .strip_suffix(".trashinfo")?
+                .to_string();
+            let (path, trashed_at) = read_trashinfo(&info)?;
+            (path == original).then(|| TrashEntry {
+                original: path,
+                location: dir.join("files").join(name),
+                info: Some(info),
+                trashed_at,
+            })
+        })
+        .collect()
+}
+
+/// Original path and deletion date of a `.trashinfo` file. Relative paths are relative
+/// to the directory holding the trash.
+fn read_trashinfo(info: &Path) -> Option<(PathBuf, Option<DateTime<Local>>)> {
+    let content = std::fs::read_to_string(info).ok()?;
+    let mut path = None;
+    let mut trashed_at = None;
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("Path=") {
+            path = Some(PathBuf::from(percent_decode(value)?));
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            trashed_at = NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%M:%S")
+                .ok()
+                .and_then(|date| Local.from_local_datetime(&date).earliest());
+        }
+    }
+    let path = path?;
+    let path = if path.is_absolute() {
+        path
+    } else {
+        info.parent()?.parent()?.parent()?.join(path)
+    };
+    Some((path, trashed_at))
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Finder keeps the name, adding the time of day when the trash already holds one,
+/// e.g. `report 10.23.45.txt`. The time trashed is when the entry last changed.
+fn macos_entries(dir: &Path, original: &Path) -> Vec<TrashEntry> {
+    let (Some(name), Ok(files)) = (original.file_name(), std::fs::read_dir(dir)) else {
+        return Vec::new();
+    };
+    let name = name.to_string_lossy();
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name.as_ref(), String::new()),
+    };
+    files
+        .flatten()
+        .filter(|file| {
+            let candidate = file.file_name().to_string_lossy().into_owned();
+            candidate == name
+                || candidate
+                    .strip_prefix(stem)
+                    .and_then(|rest| rest.strip_suffix(extension.as_str()))
+                    .is_some_and(|rest| rest.starts_with(' '))
+        })
+        .map(|file| TrashEntry {
+            original: original.to_path_buf(),
+            location: file.path(),
+            info: None,
+            trashed_at: changed_at(&file.path()),
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn changed_at(path: &Path) -> Option<DateTime<Local>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    Local.timestamp_opt(metadata.ctime(), 0).single()
+}
+
+#[cfg(not(unix))]
+fn changed_at(path: &Path) -> Option<DateTime<Local>> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    metadata.modified().ok().map(DateTime::<Local>::from)
+}
+
+fn recycle_bin_entries(dir: &Path, original: &Path) -> Vec<TrashEntry> {
+    let Ok(users) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    users
+        .flatten()
+        .filter_map(|user| std::fs::read_dir(user.path()).ok())
+        .flat_map(|files| files.flatten())
+        .filter_map(|file| {
+            let info = file.path();
+            let id = file
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix("$I")?
+                .to_string();
+            let (path, trashed_at) = read_recycle_record(&info)?;
+            (path == original).then(|| TrashEntry {
+                original: path,
+                location: info.with_file_name(format!("$R{}", id)),
+                info: Some(info),
+                trashed_at,
+            })
+        })
+        .collect()
+}
+
+/// Original path and deletion time of a `$I` record: version, size and `FILETIME` as
+/// 64-bit little-endian integers, then the UTF-16 path, fixed at 260 characters in
+/// version 1 and preceded by its length in version 2.
+fn read_recycle_record(info: &Path) -> Option<(PathBuf, Option<DateTime<Local>>)> {
+    let data = std::fs::read(info).ok()?;
+    let u64_at = |offset: usize| {
+        Some(u64::from_le_bytes(
+            data.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+    let (start, length) = match u64_at(0)? {
+        1 => (24, 260),
+        2 => (
+            28,
+            u32::from_le_bytes(data.get(24..28)?.try_into().ok()?) as usize,
+        ),
+        _ => return None,
+    };
+    let units: Vec<u16> = data
+        .get(start..start + length * 2)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    let path = PathBuf::from(String::from_utf16(&units).ok()?);
+    let trashed_at = i64::try_from(u64_at(16)? / 10_000_000)
+        .ok()
+        .and_then(|secs| Local.timestamp_opt(secs - FILETIME_UNIX_OFFSET, 0).single());
+    Some((path, trashed_at))
+}
@@ -0,0 +1,61 @@
+use crate::error::{invalid_config, Result};
+use crate::filter::{FilterKind, PathFilter};
+use crate::FileEvent;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Named group of watched paths that is added, paused and removed as a unit, e.g. all
+/// config directories of one service. Its filters apply only to events under its paths,
+/// on top of the monitor-wide ones, and its events are counted together.
+///
+/// A path belongs to at most one watchset.
+#[derive(Debug, Clone)]
+pub struct Watchset {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub filters: PathFilter,
+    pub paused: bool,
+}
+
+impl Watchset {
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid_config!("Invalid watchset name {:?}", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            paths: Vec::new(),
+            filters: PathFilter::default(),
+            paused: false,
+        })
+    }
+
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn filter(mut self, kind: FilterKind, pattern: &str) -> Result<Self> {
+        self.filters.add(kind, pattern)?;
+        Ok(self)
+    }
+
+    /// Whether `watch`, a watched path, is one of the set's paths.
+    pub fn contains(&self, watch: &Path) -> bool {
+        self.paths.iter().any(|path| path == watch)
+    }
+
+    /// Event counts of the set's paths added up.
+    pub fn stats(
+        &self,
+        watch_stats: &HashMap<PathBuf, HashMap<FileEvent, usize>>,
+    ) -> HashMap<FileEvent, usize> {
+        let mut stats = HashMap::new();
+        for path in &self.paths {
+            for (event, count) in watch_stats.get(path).into_iter().flatten() {
+                *stats.entry(event.clone()).or_insert(0) += count;
+            }
+        }
+        stats
+    }
+}
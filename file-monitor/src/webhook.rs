@@ -0,0 +1,224 @@
+use crate::error::{monitor_error, Result};
+use crate::throttle::{BreakerConfig, CircuitBreaker, RateLimit, RateLimiter};
+use crate::FileEventRecord;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use log::{debug, warn};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+pub const DEFAULT_WEBHOOK_ATTEMPTS: u32 = 5;
+pub const DEFAULT_WEBHOOK_BACKOFF: Duration = Duration::from_millis(500);
+pub const DEFAULT_WEBHOOK_RATE_LIMIT: RateLimit = RateLimit {
+    per_second: 10.0,
+    burst: 20,
+};
+pub const DEFAULT_WEBHOOK_BREAKER: BreakerConfig = BreakerConfig {
+    failure_threshold: 5,
+    open_secs: 60,
+};
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longer response bodies fail the request rather than fill memory.
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// A URL notified about events of the given kinds (all kinds when empty).
+///
+/// Events beyond the webhook's rate limit are dropped, and while its circuit breaker is
+/// open (the endpoint kept failing) events are not delivered at all.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub events: Vec<String>,
+    limiter: Arc<RateLimiter>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl PartialEq for Webhook {
+    fn eq(&self, other: &Self) -> bool {
+        self.url == other.url && self.events == other.events
+    }
+}
+
+impl Eq for Webhook {}
+
+impl Webhook {
+    pub fn new(url: &str, events: Vec<String>) -> Result<Self> {
+        HttpUrl::parse(url)?;
+        Ok(Self {
+            url: url.to_string(),
+            events: events
+                .into_iter()
+                .map(|event| event.to_ascii_lowercase())
+                .collect(),
+            limiter: Arc::new(RateLimiter::new(DEFAULT_WEBHOOK_RATE_LIMIT)),
+            breaker: Arc::new(CircuitBreaker::new(
+                format!("webhook {}", url),
+                DEFAULT_WEBHOOK_BREAKER,
+            )),
+        })
+    }
+
+    pub fn with_limits(mut self, rate_limit: RateLimit, breaker: BreakerConfig) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(rate_limit));
+        self.breaker = Arc::new(CircuitBreaker::new(
+            format!("webhook {}", self.url),
+            breaker,
+        ));
+        self
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    pub fn matches(&self, record: &FileEventRecord) -> bool {
+        self.events.is_empty() || self.events.iter().any(|event| event == record.event.kind())
+    }
+}
+
+/// How failed deliveries are retried: up to `max_attempts` tries, waiting `initial_backoff`
+/// after the first failure and doubling the wait after each further one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_WEBHOOK_ATTEMPTS,
+            initial_backoff: DEFAULT_WEBHOOK_BACKOFF,
+        }
+    }
+}
+
+pub(crate) fn payload(record: &FileEventRecord) -> serde_json::Value {
+    json!({
+        "path": record.path,
+        "watch": record.watch,
+        "event": record.event.kind(),
+        "timestamp": record.time.to_rfc3339(),
+    })
+}
+
+/// Posts `body` to `url`, retrying with backoff. Returns the number of attempts it took.
+pub(crate) async fn deliver(url: &str, body: &[u8], retry: RetryPolicy) -> Result<u32> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match post_json(url, body).await {
+            Ok(_) => return Ok(attempt),
+            Err(e) if attempt < retry.max_attempts => {
+                debug!(
+                    "Webhook {} attempt {} failed: {}, retrying in {:?}",
+                    url, attempt, e, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "Giving up on webhook {} after {} attempts: {}",
+                    url, attempt, e
+                );
+                return Err(e);
+            }
+        }
+    }
+}
+
+pub(crate) struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpUrl {
+    /// The `Host` header value; the port is left out only if it is the default.
+    fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+
+    /// Only plain `http://` URLs are supported.
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| monitor_error!("Unsupported webhook URL {} (expected http://)", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| monitor_error!("Webhook URL {} has an invalid port", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(monitor_error!("Webhook URL {} has no host", url));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Posts `body` as JSON to a plain `http://` URL once, returning the response body of a
+/// 2xx response. Bodies over [`MAX_RESPONSE_BYTES`] fail the request.
+pub async fn post_json(url: &str, body: &[u8]) -> Result<String> {
+    let url = HttpUrl::parse(url)?;
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        // Title case for endpoints that match header names literally.
+        let (mut sender, connection) = hyper::client::conn::http1::Builder::new()
+            .title_case_headers(true)
+            .handshake(TokioIo::new(stream))
+            .await
+            .map_err(|e| monitor_error!("HTTP handshake failed: {}", e))?;
+        // Ends once the response is read and `sender` is dropped.
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("HTTP connection failed: {}", e);
+            }
+        });
+        let request = Request::post(url.path.as_str())
+            .header(HOST, url.authority())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::copy_from_slice(body)))
+            .map_err(|e| monitor_error!("Invalid request: {}", e))?;
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|e| monitor_error!("HTTP request failed: {}", e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(monitor_error!("HTTP status {}", status.as_u16()));
+        }
+        let body = Limited::new(response.into_body(), MAX_RESPONSE_BYTES)
+            .collect()
+            .await
+            .map_err(|e| monitor_error!("Failed to read HTTP response: {}", e))?
+            .to_bytes();
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    })
+    .await
+    .map_err(|_| monitor_error!("Request timed out"))?
+}
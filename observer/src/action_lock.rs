@@ -0,0 +1,220 @@
+use crate::effect::POSTURE_CHANGING_COMMANDS;
+use crate::result::{CommandResult, ResultCode};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::fs::{File, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_WAIT_SECS: u64 = 30;
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What to do with a command while another agent holds the lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyPolicy {
+    /// Wait up to `wait_secs` for the lock, then fail.
+    #[default]
+    Wait,
+    Fail,
+}
+
+/// An advisory lock file shared with other automation agents, e.g. configuration
+/// management, that change network or USB posture. The lock is an OS file lock (`flock`
+/// on Unix), so it goes away with the process holding it and is never stale.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionLockConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub on_busy: BusyPolicy,
+    #[serde(default = "default_wait_secs")]
+    pub wait_secs: u64,
+    /// Commands run under the lock; the posture-changing commands by default.
+    #[serde(default = "default_commands")]
+    pub commands: Vec<String>,
+}
+
+fn default_wait_secs() -> u64 {
+    DEFAULT_WAIT_SECS
+}
+
+fn default_commands() -> Vec<String> {
+    POSTURE_CHANGING_COMMANDS
+        .iter()
+        .map(|command| command.to_string())
+        .collect()
+}
+
+impl ActionLockConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// Content of the lock file: who holds it and why. Other agents take the lock with an
+/// exclusive `flock` on the file (e.g. `flock(1)` in a shell) and may write `agent` into
+/// it; the content only informs, the OS lock decides.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub agent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acquired_at: Option<DateTime<Local>>,
+}
+
+/// The lock could not be taken; `holder` is `None` if its file could not be read.
+/// `error` is set if the lock file itself failed, in which case the command is refused
+/// all the same rather than run unlocked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockBusy {
+    pub holder: Option<LockHolder>,
+    pub waited: Duration,
+    pub error: Option<String>,
+}
+
+impl LockBusy {
+    /// The result of a command refused because of the lock.
+    pub fn to_result(&self, command: &str) -> CommandResult {
+        CommandResult::new(
+            ResultCode::ActionLocked,
+            format!("Command {} not run: {}", command, self),
+            json!({
+                "lock_holder": self.holder,
+                "waited_secs": self.waited.as_secs(),
+                "lock_error": self.error,
+            }),
+        )
+    }
+}
+
+impl fmt::Display for LockBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(error) = &self.error {
+            return write!(f, "action lock could not be taken: {}", error);
+        }
+        match &self.holder {
+            Some(holder) => write!(f, "action lock is held by {}", holder.agent)?,
+            None => write!(f, "action lock is held")?,
+        }
+        if let Some(command) = self.holder.as_ref().and_then(|h| h.command.as_ref()) {
+            write!(f, " for {}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LockBusy {}
+
+/// Takes the shared lock around posture changes so guardian and other agents do not
+/// change the same settings at once.
+pub struct ActionLock {
+    config: ActionLockConfig,
+    agent: String,
+}
+
+impl ActionLock {
+    /// `agent` is how guardian is named in the lock file.
+    pub fn new(config: ActionLockConfig, agent: &str) -> Self {
+        Self {
+            config,
+            agent: agent.to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &ActionLockConfig {
+        &self.config
+    }
+
+    /// Whether `command` runs under the lock.
+    pub fn covers(&self, command: &str) -> bool {
+        self.config.commands.iter().any(|c| c == command)
+    }
+
+    /// Who holds the lock, if anyone.
+    pub fn holder(&self) -> Option<LockHolder> {
+        let file = File::open(&self.config.path).ok()?;
+        match file.try_lock() {
+            // Whatever the file says was left by a holder that is gone.
+            Ok(()) => None,
+            Err(_) => read_holder(&self.config.path),
+        }
+    }
+
+    /// Takes the lock for `command`. While it is held, fails or waits as the policy says;
+    /// if the lock file cannot be opened or locked, fails without running the command.
+    pub async fn acquire(&self, command: &str) -> Result<ActionLockGuard, LockBusy> {
+        let started = Instant::now();
+        let wait = match self.config.on_busy {
+            BusyPolicy::Wait => Duration::from_secs(self.config.wait_secs),
+            BusyPolicy::Fail => Duration::ZERO,
+        };
+        loop {
+            let holder = LockHolder {
+                agent: self.agent.clone(),
+                pid: Some(std::process::id()),
+                command: Some(command.to_string()),
+                acquired_at: Some(Local::now()),
+            };
+            let failed = |error: std::io::Error| LockBusy {
+                holder: None,
+                waited: started.elapsed(),
+                error: Some(format!("{}: {}", self.config.path.display(), error)),
+            };
+            // The file is never removed, so every agent locks the same inode.
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&self.config.path)
+                .map_err(failed)?;
+            match file.try_lock() {
+                Ok(()) => return ActionLockGuard::new(file, &holder).map_err(failed),
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => return Err(failed(e)),
+            }
+            if started.elapsed() >= wait {
+                return Err(LockBusy {
+                    holder: self.holder(),
+                    waited: started.elapsed(),
+                    error: None,
+                });
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+fn read_holder(path: &Path) -> Option<LockHolder> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+/// Holds the lock until dropped, which empties the file and unlocks it.
+pub struct ActionLockGuard {
+    file: File,
+}
+
+impl ActionLockGuard {
+    fn new(mut file: File, holder: &LockHolder) -> std::io::Result<Self> {
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&serde_json::to_vec(holder)?)?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for ActionLockGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.set_len(0) {
+            println!("Failed to clear action lock holder: {}", e);
+        }
+        let _ = self.file.unlock();
+    }
+}
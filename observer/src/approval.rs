@@ -0,0 +1,97 @@
+use crate::user_session::{console_user, list_user_sessions};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const DEFAULT_APPROVAL_TIMEOUT_SECS: u64 = 60;
+
+/// Commands that a logged-in local administrator has to approve before they run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    pub commands: Vec<String>,
+    /// Users allowed to approve.
+    pub admins: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_APPROVAL_TIMEOUT_SECS
+}
+
+impl ApprovalPolicy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn requires_approval(&self, command: &str) -> bool {
+        self.commands.iter().any(|c| c == command)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalOutcome {
+    Approved { user: String },
+    Denied(String),
+    TimedOut,
+}
+
+/// Asks a local administrator whether a command may run.
+#[async_trait]
+pub trait ApprovalPrompt: Send + Sync {
+    async fn request(&self, command: &str, policy: &ApprovalPolicy) -> Result<ApprovalOutcome>;
+}
+
+/// Asks on guardian's console. Only the user owning the active, unlocked console session can
+/// approve, and only if listed as an administrator.
+pub struct ConsoleApprovalPrompt;
+
+#[async_trait]
+impl ApprovalPrompt for ConsoleApprovalPrompt {
+    async fn request(&self, command: &str, policy: &ApprovalPolicy) -> Result<ApprovalOutcome> {
+        let sessions = list_user_sessions().await?;
+        let Some(console) = console_user(&sessions) else {
+            return Ok(ApprovalOutcome::Denied(
+                "no administrator is logged in at the console".to_string(),
+            ));
+        };
+        if !policy.admins.contains(&console.user) {
+            return Ok(ApprovalOutcome::Denied(format!(
+                "console user {} is not an administrator",
+                console.user
+            )));
+        }
+        if console.locked {
+            return Ok(ApprovalOutcome::Denied(
+                "console session is locked".to_string(),
+            ));
+        }
+
+        println!(
+            "Approve {} as {}? Answer within {} seconds [y/N]",
+            command, console.user, policy.timeout_secs
+        );
+        let mut answer = String::new();
+        let mut stdin = BufReader::new(tokio::io::stdin());
+        match tokio::time::timeout(policy.timeout(), stdin.read_line(&mut answer)).await {
+            Ok(Ok(_)) if matches!(answer.trim(), "y" | "Y" | "yes") => {
+                Ok(ApprovalOutcome::Approved {
+                    user: console.user.clone(),
+                })
+            }
+            Ok(Ok(_)) => Ok(ApprovalOutcome::Denied(format!(
+                "{} declined",
+                console.user
+            ))),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Ok(ApprovalOutcome::TimedOut),
+        }
+    }
+}
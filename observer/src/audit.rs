@@ -0,0 +1,312 @@
+use crate::audit_forward::AuditForwarder;
+use crate::connector::fingerprint::DeviceFingerprint;
+use crate::effect::EffectDelta;
+use crate::outbox::{Outbox, Outgoing};
+use crate::policy::PolicyContext;
+use crate::result::{CommandResult, ResultCode};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// One entry of the guardian audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Local>,
+    pub host_id: String,
+    pub command: String,
+    pub mode: String,
+    pub executed: bool,
+    pub code: ResultCode,
+    pub human_message: String,
+    /// What caused the command when it did not come from the operator, e.g. a panic file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+    /// Host state the command's policy conditions were evaluated against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_context: Option<PolicyContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_fingerprint: Option<String>,
+    /// Local administrator who approved the command, for commands that need approval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    /// Measured before/after state, for posture-changing commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<EffectDelta>,
+    /// SHA-256 of the previous line of the log, set by [`AuditLog::record`]. Chains the
+    /// records so that edited or removed entries are detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        host_id: &str,
+        command: &str,
+        mode: &str,
+        executed: bool,
+        result: &CommandResult,
+    ) -> Self {
+        Self {
+            timestamp: Local::now(),
+            host_id: host_id.to_string(),
+            command: command.to_string(),
+            mode: mode.to_string(),
+            executed,
+            code: result.code,
+            human_message: result.human_message.clone(),
+            trigger: None,
+            policy_context: None,
+            device_fingerprint: None,
+            approved_by: None,
+            effect: result
+                .data
+                .get("effect")
+                .and_then(|effect| serde_json::from_value(effect.clone()).ok()),
+            prev_hash: None,
+        }
+    }
+
+    /// A host event that did not originate from a command, e.g. a session lock.
+    pub fn event(host_id: &str, event: &str, mode: &str, human_message: String) -> Self {
+        Self {
+            timestamp: Local::now(),
+            host_id: host_id.to_string(),
+            command: event.to_string(),
+            mode: mode.to_string(),
+            executed: false,
+            code: ResultCode::Ok,
+            human_message,
+            trigger: None,
+            policy_context: None,
+            device_fingerprint: None,
+            approved_by: None,
+            effect: None,
+            prev_hash: None,
+        }
+    }
+
+    pub fn with_trigger(mut self, trigger: &str) -> Self {
+        self.trigger = Some(trigger.to_string());
+        self
+    }
+
+    pub fn with_device_fingerprint(mut self, fingerprint: Option<&DeviceFingerprint>) -> Self {
+        self.device_fingerprint = fingerprint.map(|fingerprint| fingerprint.to_string());
+        self
+    }
+
+    pub fn with_approved_by(mut self, user: Option<&str>) -> Self {
+        self.approved_by = user.map(str::to_string);
+        self
+    }
+
+    pub fn with_policy_context(mut self, context: PolicyContext) -> Self {
+        self.policy_context = Some(context);
+        self
+    }
+}
+
+/// Append-only JSON-lines audit log.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Hash of the last line written, loaded from the file on the first write.
+    last_hash: Mutex<Option<LastHash>>,
+    forwarder: Option<Arc<AuditForwarder>>,
+    outbox: Option<Arc<Outbox>>,
+}
+
+struct LastHash(Option<String>);
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            last_hash: Mutex::new(None),
+            forwarder: None,
+            outbox: None,
+        }
+    }
+
+    /// Records that cannot be written are spooled to `outbox` instead of failing, and
+    /// written ahead of the next record once the log is writable again.
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Also spools every record for delivery to a remote collector.
+    pub fn with_forwarder(mut self, forwarder: Arc<AuditForwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn record(&self, record: &AuditRecord) -> Result<()> {
+        let written = match &self.outbox {
+            None => self.append(record).await?,
+            Some(outbox) => {
+                if outbox.has_pending(is_audit).await {
+                    // Best effort: while the log is still unwritable, new records queue up
+                    // behind the spooled ones.
+                    let _ = self.redeliver_spooled().await;
+                }
+                if outbox.has_pending(is_audit).await {
+                    return self.spool(outbox, record, None).await;
+                }
+                match self.append(record).await {
+                    Ok(written) => written,
+                    Err(e) => return self.spool(outbox, record, Some(e)).await,
+                }
+            }
+        };
+        self.forward(&written).await
+    }
+
+    /// Whether records spooled while the log was unwritable are still waiting.
+    pub async fn has_spooled(&self) -> bool {
+        match &self.outbox {
+            Some(outbox) => outbox.has_pending(is_audit).await,
+            None => false,
+        }
+    }
+
+    /// Writes records spooled while the log was unwritable, returning how many.
+    pub async fn redeliver_spooled(&self) -> Result<usize> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(0);
+        };
+        outbox
+            .redeliver(|message| async move {
+                match message {
+                    Outgoing::Audit { record } => Some(match self.append(&record).await {
+                        Ok(written) => {
+                            if let Err(e) = self.forward(&written).await {
+                                println!("Failed to spool audit record for forwarding: {}", e);
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    }),
+                    _ => None,
+                }
+            })
+            .await
+    }
+
+    async fn spool(
+        &self,
+        outbox: &Outbox,
+        record: &AuditRecord,
+        error: Option<anyhow::Error>,
+    ) -> Result<()> {
+        if let Some(e) = error {
+            println!("Failed to write audit record, spooling it: {}", e);
+        }
+        outbox
+            .push(Outgoing::Audit {
+                record: Box::new(record.clone()),
+            })
+            .await
+    }
+
+    /// Appends `record` to the log file, returning it as written, chained to the line
+    /// before it.
+    async fn append(&self, record: &AuditRecord) -> Result<AuditRecord> {
+        let mut last_hash = self.last_hash.lock().await;
+        if last_hash.is_none() {
+            let content = self.read_content().await?;
+            *last_hash = Some(LastHash(
+                content
+                    .lines()
+                    .rfind(|line| !line.trim().is_empty())
+                    .map(line_hash),
+            ));
+        }
+        let prev_hash = last_hash.as_ref().and_then(|hash| hash.0.clone());
+        let record = AuditRecord {
+            prev_hash,
+            ..record.clone()
+        };
+        let mut line = serde_json::to_string(&record)?;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let hash = line_hash(&line);
+        line.push('\n');
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        *last_hash = Some(LastHash(Some(hash)));
+        Ok(record)
+    }
+
+    async fn forward(&self, record: &AuditRecord) -> Result<()> {
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.enqueue(record).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn read_all(&self) -> Result<Vec<AuditRecord>> {
+        self.read_content()
+            .await?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Checks that every record links to the line before it. Records written before
+    /// chaining was introduced are accepted, but once the chain starts it must not break.
+    /// Returns the number of chained records.
+    pub async fn verify_chain(&self) -> Result<usize> {
+        let content = self.read_content().await?;
+        let mut previous: Option<&str> = None;
+        let mut chained = 0;
+        for (index, line) in content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+        {
+            let record: AuditRecord = serde_json::from_str(line)
+                .map_err(|e| anyhow!("Audit record {} is unreadable: {}", index + 1, e))?;
+            let expected = previous.map(line_hash);
+            match (&record.prev_hash, &expected) {
+                (Some(given), Some(expected)) if given == expected => chained += 1,
+                (None, _) if chained == 0 => {}
+                _ => return Err(anyhow!("Audit log chain is broken at record {}", index + 1)),
+            }
+            previous = Some(line);
+        }
+        Ok(chained)
+    }
+
+    async fn read_content(&self) -> Result<String> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => Ok(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn is_audit(message: &Outgoing) -> bool {
+    matches!(message, Outgoing::Audit { .. })
+}
+
+fn line_hash(line: &str) -> String {
+    Sha256::digest(line.trim_end().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
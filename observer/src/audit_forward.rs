@@ -0,0 +1,227 @@
+use crate::audit::AuditRecord;
+use anyhow::{anyhow, Result};
+use file_monitor_core::{BreakerConfig, CircuitBreaker, TlsClient};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+pub const SPOOL_FILE_NAME: &str = "audit-spool.jsonl";
+pub const CURSOR_FILE_NAME: &str = "audit-cursor.json";
+const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+/// After three failed flushes the collector is left alone for five minutes.
+pub const DEFAULT_FORWARD_BREAKER: BreakerConfig = BreakerConfig {
+    failure_threshold: 3,
+    open_secs: 300,
+};
+
+/// An audit record waiting in the local spool for the collector to acknowledge it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpooledRecord {
+    pub seq: u64,
+    pub record: AuditRecord,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Cursor {
+    /// Highest sequence number the collector has acknowledged.
+    acked: u64,
+    /// Sequence number the next spooled record gets.
+    next: u64,
+}
+
+/// Ships audit records to a remote collector with at-least-once delivery.
+///
+/// Records are spooled to disk first and only dropped from the spool once the collector
+/// has acknowledged them, so an outage (or a crash) just delays delivery. The wire format
+/// is one `SpooledRecord` JSON line per record, answered by an `ACK <seq>` line, over
+/// plain TCP or, with [`AuditForwarder::with_tls`], (mutual) TLS.
+pub struct AuditForwarder {
+    collector: String,
+    spool_path: PathBuf,
+    cursor_path: PathBuf,
+    ack_timeout: Duration,
+    cursor: Mutex<Cursor>,
+    breaker: CircuitBreaker,
+    tls: Option<TlsClient>,
+}
+
+impl AuditForwarder {
+    pub async fn open<P: AsRef<Path>>(collector: &str, spool_dir: P) -> Result<Self> {
+        let spool_dir = spool_dir.as_ref();
+        tokio::fs::create_dir_all(spool_dir).await?;
+        let cursor_path = spool_dir.join(CURSOR_FILE_NAME);
+        let cursor = match tokio::fs::read(&cursor_path).await {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Cursor { acked: 0, next: 1 },
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            collector: collector.to_string(),
+            spool_path: spool_dir.join(SPOOL_FILE_NAME),
+            cursor_path,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            cursor: Mutex::new(cursor),
+            breaker: CircuitBreaker::new(
+                format!("audit collector {}", collector),
+                DEFAULT_FORWARD_BREAKER,
+            ),
+            tls: None,
+        })
+    }
+
+    pub fn with_ack_timeout(mut self, ack_timeout: Duration) -> Self {
+        self.ack_timeout = ack_timeout;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker = CircuitBreaker::new(format!("audit collector {}", self.collector), config);
+        self
+    }
+
+    /// Connects to the collector over TLS. Rotated certificates and keys are picked up on
+    /// the next connection.
+    pub fn with_tls(mut self, tls: TlsClient) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    pub fn collector(&self) -> &str {
+        &self.collector
+    }
+
+    /// Adds a record to the spool. It is delivered on the next successful `flush`.
+    pub async fn enqueue(&self, record: &AuditRecord) -> Result<u64> {
+        let mut cursor = self.cursor.lock().await;
+        let spooled = SpooledRecord {
+            seq: cursor.next,
+            record: record.clone(),
+        };
+        let mut line = serde_json::to_string(&spooled)?;
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool_path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        cursor.next += 1;
+        self.save_cursor(&cursor).await?;
+        Ok(spooled.seq)
+    }
+
+    /// Records spooled but not yet acknowledged, oldest first.
+    pub async fn pending(&self) -> Result<Vec<SpooledRecord>> {
+        let cursor = self.cursor.lock().await;
+        self.read_pending(&cursor).await
+    }
+
+    /// Sends every pending record to the collector, advancing the cursor after each
+    /// acknowledgment. Returns how many records were acknowledged. On failure the
+    /// unacknowledged records stay spooled and are replayed by the next flush. While the
+    /// circuit breaker is open after repeated failures, the collector is not contacted.
+    pub async fn flush(&self) -> Result<usize> {
+        let mut cursor = self.cursor.lock().await;
+        let pending = self.read_pending(&cursor).await?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        self.breaker.call(self.send(&mut cursor, &pending)).await
+    }
+
+    async fn send(&self, cursor: &mut Cursor, pending: &[SpooledRecord]) -> Result<usize> {
+        let stream = tokio::time::timeout(self.ack_timeout, TcpStream::connect(&self.collector))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to {}", self.collector))??;
+        let Some(tls) = &self.tls else {
+            return self.exchange(stream, cursor, pending).await;
+        };
+        let host = self
+            .collector
+            .rsplit_once(':')
+            .map_or(self.collector.as_str(), |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let stream = tokio::time::timeout(self.ack_timeout, tls.connect(host, stream))
+            .await
+            .map_err(|_| anyhow!("Timed out in TLS handshake with {}", self.collector))?
+            .map_err(|e| anyhow!("TLS handshake with {} failed: {}", self.collector, e))?;
+        self.exchange(stream, cursor, pending).await
+    }
+
+    async fn exchange<S>(
+        &self,
+        stream: S,
+        cursor: &mut Cursor,
+        pending: &[SpooledRecord],
+    ) -> Result<usize>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        let mut delivered = 0;
+        for spooled in pending {
+            let mut line = serde_json::to_string(spooled)?;
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+
+            let mut ack = String::new();
+            let read = tokio::time::timeout(self.ack_timeout, reader.read_line(&mut ack))
+                .await
+                .map_err(|_| anyhow!("Timed out waiting for ack of record {}", spooled.seq))??;
+            if read == 0 {
+                return Err(anyhow!(
+                    "Collector closed the connection before acknowledging record {}",
+                    spooled.seq
+                ));
+            }
+            if ack.trim() != format!("ACK {}", spooled.seq) {
+                return Err(anyhow!("Unexpected reply from collector: {}", ack.trim()));
+            }
+
+            cursor.acked = spooled.seq;
+            self.save_cursor(cursor).await?;
+            delivered += 1;
+        }
+
+        // Everything spooled so far is acknowledged, so the spool can start over.
+        if cursor.acked + 1 == cursor.next {
+            tokio::fs::write(&self.spool_path, b"").await?;
+        }
+        Ok(delivered)
+    }
+
+    async fn read_pending(&self, cursor: &Cursor) -> Result<Vec<SpooledRecord>> {
+        let content = match tokio::fs::read_to_string(&self.spool_path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pending = Vec::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let spooled: SpooledRecord = serde_json::from_str(line)?;
+            if spooled.seq > cursor.acked {
+                pending.push(spooled);
+            }
+        }
+        Ok(pending)
+    }
+
+    async fn save_cursor(&self, cursor: &Cursor) -> Result<()> {
+        tokio::fs::write(&self.cursor_path, serde_json::to_vec(cursor)?).await?;
+        Ok(())
+    }
+}
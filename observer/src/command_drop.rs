@@ -0,0 +1,336 @@
+use crate::connector::enrollment::to_hex;
+use crate::dispatcher::CommandDispatcher;
+use crate::native::NativeCommands;
+use crate::protocol::parse_command;
+use crate::result::CommandResult;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local};
+use file_monitor_core::{FileMonitor, MonitorEvent, ShutdownToken};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+/// Command files must end in this suffix. Writers should create them under another name
+/// and rename them into place, so guardian never reads a half-written file.
+pub const COMMAND_FILE_SUFFIX: &str = ".cmd.json";
+pub const RESULT_FILE_SUFFIX: &str = ".result.json";
+/// Audit trigger for commands that arrived through the drop directory.
+pub const COMMAND_DROP_TRIGGER: &str = "COMMAND_DROP";
+
+const DEFAULT_MAX_AGE_SECS: u64 = 300;
+
+/// A command file, authenticated with HMAC-SHA256 under the command-signing key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedCommand {
+    pub host_id: String,
+    pub command: String,
+    /// Unique per command; a nonce is only accepted once.
+    pub nonce: String,
+    pub issued_at: DateTime<Local>,
+    pub signature: String,
+}
+
+impl SignedCommand {
+    pub fn sign(host_id: &str, command: &str, nonce: &str, key: &[u8]) -> Self {
+        Self::sign_at(host_id, command, nonce, Local::now(), key)
+    }
+
+    pub fn sign_at(
+        host_id: &str,
+        command: &str,
+        nonce: &str,
+        issued_at: DateTime<Local>,
+        key: &[u8],
+    ) -> Self {
+        let mut signed = Self {
+            host_id: host_id.to_string(),
+            command: command.to_string(),
+            nonce: nonce.to_string(),
+            issued_at,
+            signature: String::new(),
+        };
+        signed.signature = hmac_hex(key, &signed.signing_payload());
+        signed
+    }
+
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let expected = hmac_hex(key, &self.signing_payload());
+        bool::from(
+            self.signature
+                .to_ascii_lowercase()
+                .as_bytes()
+                .ct_eq(expected.as_bytes()),
+        )
+    }
+
+    /// Millisecond timestamp rather than RFC 3339, so the payload does not depend on the
+    /// time zone the file was parsed in.
+    fn signing_payload(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}",
+            self.host_id,
+            self.command,
+            self.nonce,
+            self.issued_at.timestamp_millis()
+        )
+    }
+}
+
+/// Why a dropped command was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DropRejection {
+    Malformed(String),
+    WrongHost(String),
+    BadSignature,
+    Expired,
+    Replayed,
+    NotPermitted,
+}
+
+impl DropRejection {
+    /// Stable reason code for audit records.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            DropRejection::Malformed(_) => "MALFORMED",
+            DropRejection::WrongHost(_) => "WRONG_HOST",
+            DropRejection::BadSignature => "BAD_SIGNATURE",
+            DropRejection::Expired => "EXPIRED",
+            DropRejection::Replayed => "REPLAYED",
+            DropRejection::NotPermitted => "NOT_PERMITTED",
+        }
+    }
+}
+
+impl fmt::Display for DropRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropRejection::Malformed(e) => write!(f, "Malformed command file: {}", e),
+            DropRejection::WrongHost(host) => write!(f, "Command is addressed to host {}", host),
+            DropRejection::BadSignature => write!(f, "Command signature is invalid"),
+            DropRejection::Expired => write!(f, "Command is too old or issued in the future"),
+            DropRejection::Replayed => write!(f, "Command nonce was already used"),
+            DropRejection::NotPermitted => {
+                write!(f, "Command is not permitted through the drop directory")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DropRejection {}
+
+/// Host-side settings of the command drop directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandDropConfig {
+    pub dir: PathBuf,
+    /// Commands accepted from the drop directory. Empty means none: every command must be
+    /// allowed explicitly.
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+    /// Commands issued longer ago than this are refused.
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+}
+
+fn default_max_age_secs() -> u64 {
+    DEFAULT_MAX_AGE_SECS
+}
+
+impl CommandDropConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// Accepts signed command files dropped into a watched directory, as an alternative to
+/// delivering them on a USB key.
+pub struct CommandDrop {
+    config: CommandDropConfig,
+    host_id: String,
+    key: [u8; 32],
+    nonce_path: PathBuf,
+    /// Nonces seen within the max age window, with the time their command was issued.
+    nonces: Mutex<HashMap<String, DateTime<Local>>>,
+}
+
+impl CommandDrop {
+    pub async fn open<P: AsRef<Path>>(
+        config: CommandDropConfig,
+        host_id: &str,
+        key: [u8; 32],
+        nonce_path: P,
+    ) -> Result<Self> {
+        let nonce_path = nonce_path.as_ref().to_path_buf();
+        let nonces = match tokio::fs::read(&nonce_path).await {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            config,
+            host_id: host_id.to_string(),
+            key,
+            nonce_path,
+            nonces: Mutex::new(nonces),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    /// Checks host, signature, age, nonce and policy, parsing the command with the
+    /// `native` commands of the dispatcher. The nonce is consumed on success.
+    pub async fn validate(
+        &self,
+        command: &SignedCommand,
+        native: &NativeCommands,
+    ) -> Result<(), DropRejection> {
+        if command.host_id != self.host_id {
+            return Err(DropRejection::WrongHost(command.host_id.clone()));
+        }
+        if !command.verify(&self.key) {
+            return Err(DropRejection::BadSignature);
+        }
+        let now = Local::now();
+        let max_age = Duration::seconds(self.config.max_age_secs as i64);
+        // Allow a little clock skew between the issuing machine and this host.
+        if now - command.issued_at > max_age || command.issued_at - now > Duration::seconds(30) {
+            return Err(DropRejection::Expired);
+        }
+        if let Err(e) = parse_command(command.command.as_bytes(), native) {
+            return Err(DropRejection::Malformed(e.to_string()));
+        }
+        if !self.config.allowed_commands.contains(&command.command) {
+            return Err(DropRejection::NotPermitted);
+        }
+
+        let mut nonces = self.nonces.lock().await;
+        // Older nonces cannot be replayed anyway, their commands have expired.
+        nonces.retain(|_, issued_at| now - *issued_at <= max_age);
+        if nonces.contains_key(&command.nonce) {
+            return Err(DropRejection::Replayed);
+        }
+        nonces.insert(command.nonce.clone(), command.issued_at);
+        if let Err(e) = self.save_nonces(&nonces).await {
+            println!("Failed to persist command drop nonces: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Consumes one command file and writes its result next to it. Returns `None` for
+    /// files that are not command files or were already consumed.
+    pub async fn process_file(
+        &self,
+        path: &Path,
+        dispatcher: &CommandDispatcher,
+    ) -> Result<Option<CommandResult>> {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(None);
+        };
+        let Some(stem) = name.strip_suffix(COMMAND_FILE_SUFFIX) else {
+            return Ok(None);
+        };
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // Consume the file first, so it is never run twice whatever happens next.
+        tokio::fs::remove_file(path).await?;
+
+        let result = match serde_json::from_slice::<SignedCommand>(&data) {
+            Ok(command) => match self.validate(&command, dispatcher.native_commands()).await {
+                Ok(()) => {
+                    dispatcher
+                        .dispatch_unattended(&command.command, COMMAND_DROP_TRIGGER)
+                        .await
+                }
+                Err(rejection) => {
+                    dispatcher
+                        .reject_unattended(&command.command, COMMAND_DROP_TRIGGER, &rejection)
+                        .await
+                }
+            },
+            Err(e) => {
+                let rejection = DropRejection::Malformed(e.to_string());
+                dispatcher
+                    .reject_unattended(name, COMMAND_DROP_TRIGGER, &rejection)
+                    .await
+            }
+        };
+
+        let result_path = path.with_file_name(format!("{}{}", stem, RESULT_FILE_SUFFIX));
+        tokio::fs::write(&result_path, serde_json::to_vec_pretty(&result)?).await?;
+        Ok(Some(result))
+    }
+
+    /// Processes every command file currently in the directory.
+    pub async fn process_pending(&self, dispatcher: &CommandDispatcher) -> Result<()> {
+        let mut entries = tokio::fs::read_dir(self.dir()).await?;
+        let mut paths = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        paths.sort();
+        for path in paths {
+            if let Err(e) = self.process_file(&path, dispatcher).await {
+                println!("Failed to process {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Watches the drop directory with the file monitor and processes command files as
+    /// they appear, until `shutdown` is cancelled.
+    pub async fn watch(
+        self: Arc<Self>,
+        dispatcher: Arc<CommandDispatcher>,
+        shutdown: ShutdownToken,
+    ) -> Result<()> {
+        let monitor = Arc::new(
+            FileMonitor::builder(self.dir())
+                .shutdown_token(shutdown.child())
+                .build(),
+        );
+        let mut events = monitor.subscribe();
+        let watcher = Arc::clone(&monitor);
+        tokio::spawn(async move {
+            if let Err(e) = watcher.monitor().await {
+                println!("Command drop watcher stopped: {}", e);
+            }
+        });
+
+        self.process_pending(&dispatcher).await?;
+        // Rescan on any event: a file renamed into place may be reported under its old name.
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = shutdown.cancelled() => break,
+            };
+            match event {
+                Some(MonitorEvent::File(_)) => self.process_pending(&dispatcher).await?,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_nonces(&self, nonces: &HashMap<String, DateTime<Local>>) -> Result<()> {
+        tokio::fs::write(&self.nonce_path, serde_json::to_vec(nonces)?).await?;
+        Ok(())
+    }
+}
+
+/// Hex HMAC-SHA256 of `payload` under `key`.
+pub(crate) fn hmac_hex(key: &[u8], payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
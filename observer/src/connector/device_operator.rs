@@ -1,8 +1,12 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use sha2::{Digest, Sha256};
 use std::any::Any;
 use std::time::Duration;
 
+/// Attempts made by verified writes before giving up.
+pub const VERIFIED_WRITE_ATTEMPTS: usize = 3;
+
 #[async_trait]
 pub trait Device: Send + Sync {
     async fn connect(&mut self) -> Result<()>;
@@ -11,21 +15,65 @@ pub trait Device: Send + Sync {
     async fn write(&self, data: &[u8]) -> Result<()>;
     async fn get_info(&self) -> Result<DeviceInfo>;
     async fn wait_for_command(&self, timeout: Duration) -> Result<String>;
+
+    /// Reads a named file from the device's storage area. `None` if it does not exist or the
+    /// device has no file storage.
+    async fn read_file(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Writes a named file to the device's storage area, replacing any previous content.
+    async fn write_file(&self, _name: &str, _data: &[u8]) -> Result<()> {
+        Err(anyhow!("Device has no file storage"))
+    }
+
+    /// Writes a file, reads it back and compares hashes, retrying up to `attempts` times.
+    /// Cheap flash media can silently corrupt writes, so anything that must survive on the
+    /// key goes through here rather than `write_file`.
+    async fn write_verified(&self, name: &str, data: &[u8], attempts: usize) -> Result<()> {
+        let expected = Sha256::digest(data);
+        let mut last_error = anyhow!("No write attempted for {}", name);
+        for attempt in 1..=attempts.max(1) {
+            if let Err(e) = self.write_file(name, data).await {
+                last_error = e;
+                continue;
+            }
+            match self.read_file(name).await {
+                Ok(Some(written)) if Sha256::digest(&written) == expected => return Ok(()),
+                Ok(Some(_)) => {
+                    last_error =
+                        anyhow!("Read-back of {} did not match (attempt {})", name, attempt)
+                }
+                Ok(None) => {
+                    last_error = anyhow!("{} missing after write (attempt {})", name, attempt)
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DeviceInfo {
     pub name: String,
     pub id: String,
     pub device_type: DeviceType,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial: Option<String>,
+    /// Interfaces or features the device exposes, e.g. `mass_storage`.
+    pub capabilities: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum DeviceType {
     USB,
     Disk,
+    #[default]
     Other,
 }
 
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Version};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use subtle::ConstantTimeEq;
+
+const SALT_SIZE: usize = 16;
+const HASH_OUTPUT_SIZE: usize = 32;
+/// Upper bound on the memory a single verification may use, so a bad configuration or
+/// enrollment file cannot exhaust the host.
+const MAX_HASH_MEMORY: u64 = 1 << 30;
+const MAX_PARALLELISM: u32 = 16;
+
+/// Cost parameters for scrypt. The defaults take roughly a tenth of a second and 16 MiB
+/// per verification, which is negligible for a key insertion and expensive to brute force.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    fn default() -> Self {
+        Self {
+            log_n: 14,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl ScryptParams {
+    fn to_params(self) -> Result<scrypt::Params> {
+        if self.log_n == 0 || self.log_n >= 32 || self.r == 0 {
+            bail!("scrypt log_n must be 1-31 and r at least 1");
+        }
+        if self.p == 0 || self.p > MAX_PARALLELISM {
+            bail!("scrypt p must be 1-{}", MAX_PARALLELISM);
+        }
+        let memory = u64::from(self.r).saturating_mul(128 << self.log_n);
+        if memory > MAX_HASH_MEMORY {
+            bail!("scrypt parameters need more than {} bytes", MAX_HASH_MEMORY);
+        }
+        scrypt::Params::new(self.log_n, self.r, self.p, HASH_OUTPUT_SIZE)
+            .map_err(|e| anyhow!("Invalid scrypt parameters: {}", e))
+    }
+}
+
+/// Cost parameters for Argon2id. The defaults are the OWASP recommendation of 19 MiB and
+/// two passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl Argon2Params {
+    fn to_params(self) -> Result<argon2::Params> {
+        if u64::from(self.memory_kib) * 1024 > MAX_HASH_MEMORY {
+            bail!("Argon2 parameters need more than {} bytes", MAX_HASH_MEMORY);
+        }
+        if self.parallelism > MAX_PARALLELISM {
+            bail!("Argon2 parallelism must be at most {}", MAX_PARALLELISM);
+        }
+        argon2::Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(HASH_OUTPUT_SIZE),
+        )
+        .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))
+    }
+}
+
+/// How newly enrolled (or migrated) key material is hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum KeyHashing {
+    #[default]
+    Sha256,
+    Scrypt(ScryptParams),
+    Argon2id(Argon2Params),
+}
+
+impl KeyHashing {
+    /// Loads the hashing configuration from a JSON file such as
+    /// `{"algorithm": "scrypt", "log_n": 15, "r": 8, "p": 1}` or
+    /// `{"algorithm": "argon2id", "memory_kib": 19456, "iterations": 2, "parallelism": 1}`.
+    /// Out-of-range parameters are rejected.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let hashing: Self = serde_json::from_str(&content)?;
+        hashing.validate()?;
+        Ok(hashing)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            KeyHashing::Sha256 => Ok(()),
+            KeyHashing::Scrypt(params) => params.to_params().map(drop),
+            KeyHashing::Argon2id(params) => params.to_params().map(drop),
+        }
+    }
+}
+
+/// The stored verifier for an enrolled key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum EnrolledKey {
+    Sha256 {
+        hash: String,
+    },
+    Scrypt {
+        #[serde(flatten)]
+        params: ScryptParams,
+        salt: String,
+        hash: String,
+    },
+    Argon2id {
+        #[serde(flatten)]
+        params: Argon2Params,
+        salt: String,
+        hash: String,
+    },
+}
+
+impl EnrolledKey {
+    /// Hashes `key_material` according to `hashing`, with a fresh salt where applicable.
+    pub fn enroll(hashing: &KeyHashing, key_material: &[u8]) -> Result<Self> {
+        match hashing {
+            KeyHashing::Sha256 => Ok(EnrolledKey::Sha256 {
+                hash: format!("{:x}", Sha256::digest(key_material)),
+            }),
+            KeyHashing::Scrypt(params) => {
+                let salt = random_salt()?;
+                Ok(EnrolledKey::Scrypt {
+                    params: *params,
+                    hash: to_hex(&scrypt_hash(params, &salt, key_material)?),
+                    salt: to_hex(&salt),
+                })
+            }
+            KeyHashing::Argon2id(params) => {
+                let salt = random_salt()?;
+                Ok(EnrolledKey::Argon2id {
+                    params: *params,
+                    hash: to_hex(&argon2id_hash(params, &salt, key_material)?),
+                    salt: to_hex(&salt),
+                })
+            }
+        }
+    }
+
+    /// Loads the entry at `path`, or `None` if there is none. Entries with out-of-range
+    /// parameters are rejected.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let enrolled_key: Self = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match &enrolled_key {
+            EnrolledKey::Sha256 { .. } => {}
+            EnrolledKey::Scrypt { params, .. } => {
+                params.to_params()?;
+            }
+            EnrolledKey::Argon2id { params, .. } => {
+                params.to_params()?;
+            }
+        }
+        Ok(Some(enrolled_key))
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Checks `key_material` against the stored hash in constant time.
+    pub fn matches(&self, key_material: &[u8]) -> bool {
+        let (expected, actual) = match self {
+            EnrolledKey::Sha256 { hash } => (
+                hash.to_ascii_lowercase(),
+                format!("{:x}", Sha256::digest(key_material)),
+            ),
+            EnrolledKey::Scrypt { params, salt, hash } => {
+                let Some(actual) =
+                    from_hex(salt).and_then(|salt| scrypt_hash(params, &salt, key_material).ok())
+                else {
+                    return false;
+                };
+                (hash.to_ascii_lowercase(), to_hex(&actual))
+            }
+            EnrolledKey::Argon2id { params, salt, hash } => {
+                let Some(actual) =
+                    from_hex(salt).and_then(|salt| argon2id_hash(params, &salt, key_material).ok())
+                else {
+                    return false;
+                };
+                (hash.to_ascii_lowercase(), to_hex(&actual))
+            }
+        };
+        bool::from(actual.as_bytes().ct_eq(expected.as_bytes()))
+    }
+
+    /// Whether this entry should be re-hashed to follow the configured `hashing`.
+    pub fn needs_migration(&self, hashing: &KeyHashing) -> bool {
+        match (self, hashing) {
+            (EnrolledKey::Sha256 { .. }, KeyHashing::Sha256) => false,
+            (EnrolledKey::Scrypt { params, .. }, KeyHashing::Scrypt(target)) => params != target,
+            (EnrolledKey::Argon2id { params, .. }, KeyHashing::Argon2id(target)) => {
+                params != target
+            }
+            _ => true,
+        }
+    }
+}
+
+fn random_salt() -> Result<[u8; SALT_SIZE]> {
+    let mut salt = [0u8; SALT_SIZE];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow!("Failed to generate salt: {}", e))?;
+    Ok(salt)
+}
+
+fn scrypt_hash(
+    params: &ScryptParams,
+    salt: &[u8],
+    key_material: &[u8],
+) -> Result<[u8; HASH_OUTPUT_SIZE]> {
+    let mut output = [0u8; HASH_OUTPUT_SIZE];
+    scrypt::scrypt(key_material, salt, &params.to_params()?, &mut output)
+        .map_err(|e| anyhow!("scrypt failed: {}", e))?;
+    Ok(output)
+}
+
+fn argon2id_hash(
+    params: &Argon2Params,
+    salt: &[u8],
+    key_material: &[u8],
+) -> Result<[u8; HASH_OUTPUT_SIZE]> {
+    let mut output = [0u8; HASH_OUTPUT_SIZE];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params.to_params()?)
+        .hash_password_into(key_material, salt, &mut output)
+        .map_err(|e| anyhow!("Argon2id failed: {}", e))?;
+    Ok(output)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
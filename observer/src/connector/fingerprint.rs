@@ -0,0 +1,44 @@
+use crate::connector::device_operator::DeviceInfo;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Stable identity of a physical device, independent of the port it is attached to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeviceFingerprint(pub String);
+
+impl DeviceFingerprint {
+    /// Hashes vendor, product, serial, device type and capabilities. Capabilities are sorted
+    /// so enumeration order does not change the fingerprint.
+    pub fn compute(info: &DeviceInfo) -> Self {
+        let mut capabilities = info.capabilities.clone();
+        capabilities.sort();
+
+        let mut hasher = Sha256::new();
+        for part in [
+            info.vendor_id
+                .map(|id| format!("{:04x}", id))
+                .unwrap_or_default(),
+            info.product_id
+                .map(|id| format!("{:04x}", id))
+                .unwrap_or_default(),
+            info.serial.clone().unwrap_or_else(|| info.id.clone()),
+            format!("{:?}", info.device_type),
+            capabilities.join(","),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        Self(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DeviceFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,190 @@
+use crate::connector::enrollment::{from_hex, to_hex};
+use crate::policy::CommandConditions;
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+const SECTION_KEY_SIZE: usize = 32;
+
+/// Name of the key file a host writes its own section back to after consuming commands,
+/// so the counters travel with the key.
+pub fn section_file_name(host_id: &str) -> String {
+    format!("GUARDIAN_HOST_{}.json", host_id)
+}
+
+/// Provisioning document for a key that administers several machines. Each host finds its
+/// own section by host ID and ignores the others.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MultiHostKey {
+    pub hosts: HashMap<String, HostSection>,
+}
+
+/// What a key is for on a host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRole {
+    #[default]
+    Operator,
+    /// For practicing the workflow on production hosts: commands are authenticated,
+    /// checked and audited, but never executed, whatever mode guardian runs in.
+    Training,
+    /// Switches the host into forensic mode when authenticated, see
+    /// [`crate::forensics::ForensicMode`].
+    Forensics,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostSection {
+    /// Secret whose hash the host expects, replacing the raw key data of single-host keys.
+    pub credential: String,
+    #[serde(default)]
+    pub role: KeyRole,
+    /// Commands this host accepts from the key. Empty means none.
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+    /// Extra conditions per command, checked against the host state at command time.
+    #[serde(default)]
+    pub conditions: HashMap<String, CommandConditions>,
+    /// How many times this host has consumed each command from the key.
+    #[serde(default)]
+    pub command_counters: HashMap<String, u64>,
+    /// HMAC-SHA256 of the rest of the section and the host ID, in hex, under the host's
+    /// [`SectionKey`]. Sections without a valid MAC are refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+/// One consumption of a command by a host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostUsage {
+    pub host_id: String,
+    pub command: String,
+    pub count: u64,
+}
+
+impl MultiHostKey {
+    /// Parses key data as a multi-host document. Returns `None` for legacy single-host keys.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let text = text.trim_end_matches('\0').trim();
+        if !text.starts_with('{') {
+            return None;
+        }
+        serde_json::from_str(text).ok()
+    }
+
+    pub fn section(&self, host_id: &str) -> Result<&HostSection> {
+        self.hosts
+            .get(host_id)
+            .ok_or_else(|| anyhow!("Key has no section for host {}", host_id))
+    }
+}
+
+impl HostSection {
+    pub fn allows(&self, command: &str) -> bool {
+        self.allowed_commands.iter().any(|c| c == command)
+    }
+
+    pub fn conditions_for(&self, command: &str) -> Option<&CommandConditions> {
+        self.conditions.get(command)
+    }
+
+    pub fn record_consumption(&mut self, host_id: &str, command: &str) -> HostUsage {
+        let count = self
+            .command_counters
+            .entry(command.to_string())
+            .or_insert(0);
+        *count += 1;
+        HostUsage {
+            host_id: host_id.to_string(),
+            command: command.to_string(),
+            count: *count,
+        }
+    }
+}
+
+/// Secret kept on the host that seals its section of multi-host keys. Only the host can
+/// issue or change the policy in its section, so holding the key does not let anyone
+/// widen what the key may do.
+pub struct SectionKey(Vec<u8>);
+
+impl SectionKey {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self(secret)
+    }
+
+    /// Loads the key at `path`, or `None` if there is none.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        match std::fs::read(path.as_ref()) {
+            Ok(secret) if secret.is_empty() => {
+                Err(anyhow!("Section key {} is empty", path.as_ref().display()))
+            }
+            Ok(secret) => Ok(Some(Self(secret))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read section key {}", path.as_ref().display())),
+        }
+    }
+
+    /// Loads the key at `path`, first creating a random one readable only by its owner if
+    /// there is none.
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if let Some(key) = Self::load(&path)? {
+            return Ok(key);
+        }
+        let mut secret = vec![0u8; SECTION_KEY_SIZE];
+        getrandom::getrandom(&mut secret)
+            .map_err(|e| anyhow!("Failed to generate section key: {}", e))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path.as_ref())?.write_all(&secret)?;
+        Ok(Self(secret))
+    }
+
+    fn mac(&self, host_id: &str, section: &HostSection) -> Result<Hmac<Sha256>> {
+        let unsealed = HostSection {
+            mac: None,
+            ..section.clone()
+        };
+        // Through a `Value`, whose maps are sorted, so the encoding does not depend on
+        // hash map order.
+        let canonical = serde_json::to_string(&serde_json::to_value(&unsealed)?)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0)
+            .map_err(|e| anyhow!("Invalid section key: {}", e))?;
+        mac.update(host_id.as_bytes());
+        mac.update(b"\n");
+        mac.update(canonical.as_bytes());
+        Ok(mac)
+    }
+
+    /// Sets the MAC of `section` for `host_id`, after changing its policy or counters.
+    pub fn seal(&self, host_id: &str, section: &mut HostSection) -> Result<()> {
+        section.mac = Some(to_hex(&self.mac(host_id, section)?.finalize().into_bytes()));
+        Ok(())
+    }
+
+    /// Checks that `section` was sealed for `host_id` with this key and not changed since.
+    pub fn verify(&self, host_id: &str, section: &HostSection) -> Result<()> {
+        let tag = section
+            .mac
+            .as_deref()
+            .and_then(from_hex)
+            .ok_or_else(|| anyhow!("Section for host {} is not sealed", host_id))?;
+        self.mac(host_id, section)?
+            .verify_slice(&tag)
+            .map_err(|_| anyhow!("Section for host {} has an invalid MAC", host_id))
+    }
+}
+
+impl fmt::Debug for SectionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SectionKey(..)")
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in milliseconds. The last bucket is unbounded.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// One count per entry of `LATENCY_BUCKETS_MS`, plus an overflow bucket.
+    pub counts: Vec<u64>,
+    pub total_ms: u64,
+    pub max_ms: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean_ms(&self) -> Option<f64> {
+        let samples = self.samples();
+        (samples > 0).then(|| self.total_ms as f64 / samples as f64)
+    }
+}
+
+/// I/O counters for one device, used to spot slow or failing flash media.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceMetrics {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub read_errors: u64,
+    pub write_errors: u64,
+    pub read_latency: LatencyHistogram,
+    pub write_latency: LatencyHistogram,
+}
+
+impl DeviceMetrics {
+    pub fn record_read(&mut self, latency: Duration, bytes: Option<usize>) {
+        self.reads += 1;
+        self.read_latency.record(latency);
+        match bytes {
+            Some(bytes) => self.bytes_read += bytes as u64,
+            None => self.read_errors += 1,
+        }
+    }
+
+    pub fn record_write(&mut self, latency: Duration, bytes: Option<usize>) {
+        self.writes += 1;
+        self.write_latency.record(latency);
+        match bytes {
+            Some(bytes) => self.bytes_written += bytes as u64,
+            None => self.write_errors += 1,
+        }
+    }
+}
@@ -1,7 +1,19 @@
 pub mod device_operator;
+pub mod enrollment;
+pub mod fingerprint;
+pub mod host_key;
+pub mod metrics;
 pub mod security;
+#[cfg(feature = "simulation")]
+pub mod simulation;
 pub mod usb_key;
 
 pub use device_operator::*;
+pub use enrollment::*;
+pub use fingerprint::*;
+pub use host_key::*;
+pub use metrics::*;
 pub use security::*;
+#[cfg(feature = "simulation")]
+pub use simulation::*;
 pub use usb_key::*;
@@ -1,34 +1,375 @@
+use crate::connector::enrollment::{EnrolledKey, KeyHashing};
+use crate::connector::host_key::{section_file_name, HostSection, MultiHostKey, SectionKey};
 use crate::connector::usb_key::UsbKey;
-use anyhow::{anyhow, Result};
-use sha2::{Digest, Sha256};
+use anyhow::{anyhow, Context, Result};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+const LEGACY_KEY_DATA_SIZE: usize = 1024;
+const KEY_DATA_READ_SIZE: usize = 16 * 1024;
+const HKDF_SALT: &[u8] = b"guardian-hkdf-v1";
+const MASTER_SECRET_SIZE: usize = 32;
+
+/// What a derived key is used for. Each purpose gets its own HKDF info string, so rotating
+/// or leaking one key says nothing about the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyPurpose {
+    CommandSigning,
+    ChannelEncryption,
+    ResultAuthentication,
+    EvidenceEncryption,
+}
+
+impl KeyPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyPurpose::CommandSigning => "command-signing",
+            KeyPurpose::ChannelEncryption => "channel-encryption",
+            KeyPurpose::ResultAuthentication => "result-authentication",
+            KeyPurpose::EvidenceEncryption => "evidence-encryption",
+        }
+    }
+}
+
+/// Master secret for the key material `key_material`: its HKDF-SHA256 extract. Only
+/// whoever holds the key material can compute it, so the key side derives its keys the
+/// same way the host does, and nothing stored on the host reveals it.
+pub fn master_secret(key_material: &[u8]) -> [u8; MASTER_SECRET_SIZE] {
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(HKDF_SALT), key_material);
+    prk.into()
+}
+
+/// Derives the key for `purpose` at `generation` from a master secret via HKDF-SHA256.
+pub fn derive_purpose_key(
+    master_secret: &[u8; MASTER_SECRET_SIZE],
+    purpose: KeyPurpose,
+    generation: u32,
+) -> Result<[u8; 32]> {
+    let info = format!("guardian/{}/gen{}", purpose.as_str(), generation);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::from_prk(master_secret)
+        .map_err(|e| anyhow!("Invalid master secret: {}", e))?
+        .expand(info.as_bytes(), &mut key)
+        .map_err(|e| anyhow!("Failed to derive {} key: {}", purpose.as_str(), e))?;
+    Ok(key)
+}
+
+/// The part of a single-host key that is hashed during verification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyRegion {
+    pub offset: usize,
+    /// Exact number of bytes required. `None` hashes whatever the device returns, up to
+    /// 1024 bytes, which is how keys were verified before regions were configurable.
+    pub length: Option<usize>,
+}
+
+impl KeyRegion {
+    fn end(&self) -> usize {
+        self.offset + self.length.unwrap_or(LEGACY_KEY_DATA_SIZE)
+    }
+}
+
+/// Why a key failed verification. Kept structured so the audit log and any lockout
+/// policy can tell a flaky read apart from a wrong key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyVerificationFailure {
+    /// The device returned fewer bytes than the key region needs.
+    ShortRead { expected: usize, actual: usize },
+    /// The key data was read in full but does not hash to the enrolled value.
+    HashMismatch,
+    /// A multi-host key has no usable section for this host.
+    NoHostSection(String),
+    /// The device could not be read at all.
+    ReadFailed(String),
+}
+
+impl KeyVerificationFailure {
+    /// Stable reason code for audit records.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            KeyVerificationFailure::ShortRead { .. } => "SHORT_READ",
+            KeyVerificationFailure::HashMismatch => "HASH_MISMATCH",
+            KeyVerificationFailure::NoHostSection(_) => "NO_HOST_SECTION",
+            KeyVerificationFailure::ReadFailed(_) => "READ_FAILED",
+        }
+    }
+}
+
+impl fmt::Display for KeyVerificationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyVerificationFailure::ShortRead { expected, actual } => write!(
+                f,
+                "Short read from key: expected {} bytes, got {}",
+                expected, actual
+            ),
+            KeyVerificationFailure::HashMismatch => write!(f, "Key hash does not match"),
+            KeyVerificationFailure::NoHostSection(e) => write!(f, "No usable host section: {}", e),
+            KeyVerificationFailure::ReadFailed(e) => write!(f, "Failed to read key: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KeyVerificationFailure {}
 
 pub struct SecurityManager {
-    expected_key_hash: String,
+    enrolled_key: RwLock<EnrolledKey>,
+    key_hashing: KeyHashing,
+    enrollment_path: Option<PathBuf>,
+    host_id: Option<String>,
+    section_key: Option<SectionKey>,
+    key_region: KeyRegion,
+    key_generations: HashMap<KeyPurpose, u32>,
+    master_secret: RwLock<Option<[u8; MASTER_SECRET_SIZE]>>,
+    master_secret_path: Option<PathBuf>,
 }
 
 impl SecurityManager {
     pub fn new(expected_key_hash: String) -> Self {
-        Self { expected_key_hash }
+        Self::with_enrolled_key(EnrolledKey::Sha256 {
+            hash: expected_key_hash,
+        })
     }
 
-    pub async fn verify_key(&self, usb_key: &UsbKey) -> Result<bool> {
-        let key_data = usb_key.read_data(1024).await?;
-        let key_hash = self.hash_data(&key_data);
+    pub fn with_enrolled_key(enrolled_key: EnrolledKey) -> Self {
+        Self {
+            enrolled_key: RwLock::new(enrolled_key),
+            key_hashing: KeyHashing::default(),
+            enrollment_path: None,
+            host_id: None,
+            section_key: None,
+            key_region: KeyRegion::default(),
+            key_generations: HashMap::new(),
+            master_secret: RwLock::new(None),
+            master_secret_path: None,
+        }
+    }
 
-        Ok(key_hash == self.expected_key_hash)
+    /// Hashing used for enrolled key material. Entries hashed differently are migrated on
+    /// the next successful authentication, provided an enrollment path is set.
+    pub fn with_key_hashing(mut self, key_hashing: KeyHashing) -> Self {
+        self.key_hashing = key_hashing;
+        self
     }
 
-    fn hash_data(&self, data: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        format!("{:x}", hasher.finalize())
+    /// Where migrated enrollment entries are persisted.
+    pub fn with_enrollment_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.enrollment_path = Some(path.as_ref().to_path_buf());
+        self
     }
 
-    pub async fn authenticate_key(&self, usb_key: &UsbKey) -> Result<()> {
-        if self.verify_key(usb_key).await? {
-            Ok(())
-        } else {
-            Err(anyhow!("Key authentication failed"))
+    /// Where the master secret of the last authenticated key is kept, so derived keys are
+    /// available after a restart before any key is presented. See [`Self::load_master_secret`].
+    pub fn with_master_secret_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.master_secret_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Loads the master secret persisted by an earlier authentication. Returns whether
+    /// there was one.
+    pub fn load_master_secret(&self) -> Result<bool> {
+        let Some(path) = &self.master_secret_path else {
+            return Ok(false);
+        };
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read master secret {}", path.display()))
+            }
+        };
+        let secret: [u8; MASTER_SECRET_SIZE] = data
+            .try_into()
+            .map_err(|_| anyhow!("Master secret {} has the wrong size", path.display()))?;
+        *self.master_secret.write().unwrap() = Some(secret);
+        Ok(true)
+    }
+
+    pub fn enrolled_key(&self) -> EnrolledKey {
+        self.enrolled_key.read().unwrap().clone()
+    }
+
+    /// Rotates the key for one purpose. Generations start at 0; other purposes are unaffected.
+    pub fn with_key_generation(mut self, purpose: KeyPurpose, generation: u32) -> Self {
+        self.key_generations.insert(purpose, generation);
+        self
+    }
+
+    /// Sets which bytes of a single-host key are hashed.
+    pub fn with_key_region(mut self, key_region: KeyRegion) -> Self {
+        self.key_region = key_region;
+        self
+    }
+
+    /// Sets the ID used to select this host's section on multi-host keys.
+    pub fn with_host_id(mut self, host_id: String) -> Self {
+        self.host_id = Some(host_id);
+        self
+    }
+
+    /// Sets the host-held key that multi-host key sections must be sealed with. Without
+    /// one, multi-host keys are refused.
+    pub fn with_section_key(mut self, section_key: SectionKey) -> Self {
+        self.section_key = Some(section_key);
+        self
+    }
+
+    pub fn host_id(&self) -> Option<&str> {
+        self.host_id.as_deref()
+    }
+
+    pub async fn verify_key(&self, usb_key: &UsbKey) -> Result<(), KeyVerificationFailure> {
+        let key_data = usb_key
+            .read_data(KEY_DATA_READ_SIZE.max(self.key_region.end()))
+            .await
+            .map_err(|e| KeyVerificationFailure::ReadFailed(e.to_string()))?;
+
+        if let Some(multi_host_key) = MultiHostKey::parse(&key_data) {
+            let section = self
+                .select_section(&multi_host_key)
+                .map_err(|e| KeyVerificationFailure::NoHostSection(e.to_string()))?;
+            return self.check_enrolled(section.credential.as_bytes()).await;
+        }
+
+        let region = &self.key_region;
+        let available = key_data.len().saturating_sub(region.offset);
+        let length = match region.length {
+            Some(length) if available < length => {
+                return Err(KeyVerificationFailure::ShortRead {
+                    expected: length,
+                    actual: available,
+                })
+            }
+            Some(length) => length,
+            None if available == 0 => {
+                return Err(KeyVerificationFailure::ShortRead {
+                    expected: 1,
+                    actual: 0,
+                })
+            }
+            None => available.min(LEGACY_KEY_DATA_SIZE),
+        };
+        self.check_enrolled(&key_data[region.offset..region.offset + length])
+            .await
+    }
+
+    async fn check_enrolled(&self, key_material: &[u8]) -> Result<(), KeyVerificationFailure> {
+        let enrolled_key = self.enrolled_key();
+        if !enrolled_key.matches(key_material) {
+            return Err(KeyVerificationFailure::HashMismatch);
+        }
+        self.remember_master_secret(key_material).await;
+
+        if enrolled_key.needs_migration(&self.key_hashing) {
+            if let Some(path) = &self.enrollment_path {
+                if let Err(e) = self.migrate(path, key_material).await {
+                    println!("Failed to migrate enrolled key hash: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn remember_master_secret(&self, key_material: &[u8]) {
+        let secret = master_secret(key_material);
+        if *self.master_secret.read().unwrap() == Some(secret) {
+            return;
+        }
+        *self.master_secret.write().unwrap() = Some(secret);
+        if let Some(path) = &self.master_secret_path {
+            if let Err(e) = write_secret(path, &secret).await {
+                println!("Failed to persist master secret: {}", e);
+            }
         }
     }
+
+    async fn migrate(&self, path: &Path, key_material: &[u8]) -> Result<()> {
+        let migrated = EnrolledKey::enroll(&self.key_hashing, key_material)?;
+        migrated.save(path).await?;
+        *self.enrolled_key.write().unwrap() = migrated;
+        Ok(())
+    }
+
+    /// Returns this host's section of a multi-host key, or `None` for single-host keys.
+    /// Counters come from the copy of the section this host last wrote back to the key.
+    pub async fn host_section(&self, usb_key: &UsbKey) -> Result<Option<HostSection>> {
+        let key_data = usb_key.read_data(KEY_DATA_READ_SIZE).await?;
+        let Some(multi_host_key) = MultiHostKey::parse(&key_data) else {
+            return Ok(None);
+        };
+        let mut section = self.select_section(&multi_host_key)?.clone();
+        let (host_id, section_key) = self.section_context()?;
+        if let Some(data) = usb_key.read_file(&section_file_name(host_id)).await? {
+            match serde_json::from_slice::<HostSection>(&data) {
+                Ok(written) if section_key.verify(host_id, &written).is_ok() => {
+                    section.command_counters = written.command_counters;
+                }
+                _ => println!("Ignoring unsealed or unreadable usage counters on the key"),
+            }
+        }
+        Ok(Some(section))
+    }
+
+    /// Writes `section` back to the key, sealed, so its counters persist.
+    pub async fn store_section(&self, usb_key: &UsbKey, section: &HostSection) -> Result<()> {
+        let (host_id, section_key) = self.section_context()?;
+        let mut section = section.clone();
+        section_key.seal(host_id, &mut section)?;
+        usb_key
+            .write_verified(&section_file_name(host_id), &serde_json::to_vec(&section)?)
+            .await
+    }
+
+    fn section_context(&self) -> Result<(&str, &SectionKey)> {
+        let host_id = self
+            .host_id
+            .as_deref()
+            .ok_or_else(|| anyhow!("Multi-host key presented but no host ID is configured"))?;
+        let section_key = self
+            .section_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Multi-host key presented but no section key is configured"))?;
+        Ok((host_id, section_key))
+    }
+
+    fn select_section<'a>(&self, multi_host_key: &'a MultiHostKey) -> Result<&'a HostSection> {
+        let (host_id, section_key) = self.section_context()?;
+        let section = multi_host_key.section(host_id)?;
+        section_key.verify(host_id, section)?;
+        Ok(section)
+    }
+
+    /// Derives the key for `purpose` from the master secret of the authenticated key. The
+    /// secret comes from the key material itself, never from the enrolled hash, so keys
+    /// stay the same when that hash is migrated. Fails until a key has authenticated on
+    /// this host, or a secret persisted by an earlier authentication has been loaded.
+    pub fn derive_key(&self, purpose: KeyPurpose) -> Result<[u8; 32]> {
+        let secret = self
+            .master_secret
+            .read()
+            .unwrap()
+            .ok_or_else(|| anyhow!("No key has authenticated on this host yet"))?;
+        let generation = self.key_generations.get(&purpose).copied().unwrap_or(0);
+        derive_purpose_key(&secret, purpose, generation)
+    }
+
+    pub async fn authenticate_key(&self, usb_key: &UsbKey) -> Result<()> {
+        self.verify_key(usb_key).await.map_err(anyhow::Error::new)
+    }
+}
+
+async fn write_secret(path: &Path, secret: &[u8]) -> Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, secret).await?;
+    file.sync_all().await?;
+    Ok(())
 }
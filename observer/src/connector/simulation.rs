@@ -0,0 +1,206 @@
+use crate::connector::device_operator::{Device, DeviceInfo};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::any::Any;
+use std::io::{Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+struct FaultPlan {
+    failing_reads: usize,
+    read_error: Option<ErrorKind>,
+    failing_writes: usize,
+    write_error: Option<ErrorKind>,
+    corrupt_reads: usize,
+    corrupt_writes: usize,
+    corrupt_offsets: Vec<usize>,
+    latency: Duration,
+}
+
+/// Runtime control over the faults a [`FaultInjectingDevice`] produces.
+#[derive(Clone, Default)]
+pub struct FaultHandle {
+    plan: Arc<Mutex<FaultPlan>>,
+}
+
+impl FaultHandle {
+    /// Fails the next `count` reads (including file reads) with the given error kind.
+    pub fn fail_next_reads(&self, count: usize, kind: ErrorKind) {
+        let mut plan = self.plan.lock().unwrap();
+        plan.failing_reads = count;
+        plan.read_error = Some(kind);
+    }
+
+    /// Fails the next `count` writes (including file writes) with the given error kind.
+    pub fn fail_next_writes(&self, count: usize, kind: ErrorKind) {
+        let mut plan = self.plan.lock().unwrap();
+        plan.failing_writes = count;
+        plan.write_error = Some(kind);
+    }
+
+    /// Flips the bytes at `offsets` in the data returned by the next `count` reads.
+    pub fn corrupt_next_reads(&self, count: usize, offsets: Vec<usize>) {
+        let mut plan = self.plan.lock().unwrap();
+        plan.corrupt_reads = count;
+        plan.corrupt_offsets = offsets;
+    }
+
+    /// Flips the bytes at `offsets` in the data stored by the next `count` writes.
+    pub fn corrupt_next_writes(&self, count: usize, offsets: Vec<usize>) {
+        let mut plan = self.plan.lock().unwrap();
+        plan.corrupt_writes = count;
+        plan.corrupt_offsets = offsets;
+    }
+
+    /// Delay added before every I/O operation.
+    pub fn set_latency(&self, latency: Duration) {
+        self.plan.lock().unwrap().latency = latency;
+    }
+
+    pub fn clear(&self) {
+        *self.plan.lock().unwrap() = FaultPlan::default();
+    }
+
+    fn take_read_fault(&self) -> Option<IoError> {
+        let mut plan = self.plan.lock().unwrap();
+        let kind = plan.read_error;
+        take_fault(&mut plan.failing_reads, kind, "read")
+    }
+
+    fn take_write_fault(&self) -> Option<IoError> {
+        let mut plan = self.plan.lock().unwrap();
+        let kind = plan.write_error;
+        take_fault(&mut plan.failing_writes, kind, "write")
+    }
+
+    fn corrupt_read(&self, data: &mut [u8]) {
+        let mut plan = self.plan.lock().unwrap();
+        if plan.corrupt_reads > 0 {
+            plan.corrupt_reads -= 1;
+            flip(data, &plan.corrupt_offsets);
+        }
+    }
+
+    fn corrupt_write(&self, data: &mut [u8]) {
+        let mut plan = self.plan.lock().unwrap();
+        if plan.corrupt_writes > 0 {
+            plan.corrupt_writes -= 1;
+            flip(data, &plan.corrupt_offsets);
+        }
+    }
+
+    async fn delay(&self) {
+        let latency = self.plan.lock().unwrap().latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+}
+
+fn take_fault(remaining: &mut usize, kind: Option<ErrorKind>, operation: &str) -> Option<IoError> {
+    if *remaining == 0 {
+        return None;
+    }
+    *remaining -= 1;
+    let kind = kind.unwrap_or(ErrorKind::Other);
+    Some(IoError::new(kind, format!("injected {} fault", operation)))
+}
+
+fn flip(data: &mut [u8], offsets: &[usize]) {
+    for offset in offsets {
+        if let Some(byte) = data.get_mut(*offset) {
+            *byte ^= 0xff;
+        }
+    }
+}
+
+/// Wraps a device and injects errors, latency and corruption, so retry, integrity and
+/// session-recovery logic can be exercised without flaky hardware.
+pub struct FaultInjectingDevice {
+    inner: Box<dyn Device>,
+    faults: FaultHandle,
+}
+
+impl FaultInjectingDevice {
+    pub fn new(inner: Box<dyn Device>) -> Self {
+        Self {
+            inner,
+            faults: FaultHandle::default(),
+        }
+    }
+
+    pub fn faults(&self) -> FaultHandle {
+        self.faults.clone()
+    }
+}
+
+#[async_trait]
+impl Device for FaultInjectingDevice {
+    async fn connect(&mut self) -> Result<()> {
+        self.faults.delay().await;
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.inner.disconnect().await
+    }
+
+    async fn read(&self, size: usize) -> Result<Vec<u8>> {
+        self.faults.delay().await;
+        if let Some(error) = self.faults.take_read_fault() {
+            return Err(error.into());
+        }
+        let mut data = self.inner.read(size).await?;
+        self.faults.corrupt_read(&mut data);
+        Ok(data)
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        self.faults.delay().await;
+        if let Some(error) = self.faults.take_write_fault() {
+            return Err(error.into());
+        }
+        let mut data = data.to_vec();
+        self.faults.corrupt_write(&mut data);
+        self.inner.write(&data).await
+    }
+
+    async fn get_info(&self) -> Result<DeviceInfo> {
+        self.inner.get_info().await
+    }
+
+    async fn wait_for_command(&self, timeout: Duration) -> Result<String> {
+        self.inner.wait_for_command(timeout).await
+    }
+
+    async fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.faults.delay().await;
+        if let Some(error) = self.faults.take_read_fault() {
+            return Err(error.into());
+        }
+        let mut data = self.inner.read_file(name).await?;
+        if let Some(data) = data.as_mut() {
+            self.faults.corrupt_read(data);
+        }
+        Ok(data)
+    }
+
+    async fn write_file(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.faults.delay().await;
+        if let Some(error) = self.faults.take_write_fault() {
+            return Err(error.into());
+        }
+        let mut data = data.to_vec();
+        self.faults.corrupt_write(&mut data);
+        self.inner.write_file(name, &data).await
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
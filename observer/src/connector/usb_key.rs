@@ -1,17 +1,54 @@
-use crate::connector::device_operator::{Device, DeviceInfo, DeviceType};
+use crate::connector::device_operator::{Device, DeviceInfo, DeviceType, VERIFIED_WRITE_ATTEMPTS};
+use crate::connector::fingerprint::DeviceFingerprint;
+use crate::connector::metrics::DeviceMetrics;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use file_monitor_core::{BreakerConfig, CircuitBreaker};
 use std::any::Any;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A key that keeps failing verified writes is not written to again for half a minute.
+pub const DEFAULT_WRITE_BREAKER: BreakerConfig = BreakerConfig {
+    failure_threshold: 5,
+    open_secs: 30,
+};
 
 pub struct UsbKey {
     device: Box<dyn Device>,
     key_id: String,
+    fingerprint: Option<DeviceFingerprint>,
+    metrics: Mutex<DeviceMetrics>,
+    write_breaker: CircuitBreaker,
 }
 
 impl UsbKey {
     pub fn new(device: Box<dyn Device>, key_id: String) -> Self {
-        Self { device, key_id }
+        Self {
+            device,
+            fingerprint: None,
+            metrics: Mutex::new(DeviceMetrics::default()),
+            write_breaker: CircuitBreaker::new(
+                format!("USB key {} writes", key_id),
+                DEFAULT_WRITE_BREAKER,
+            ),
+            key_id,
+        }
+    }
+
+    /// Snapshot of the I/O counters and latencies observed on this key.
+    pub fn metrics(&self) -> DeviceMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// Breaker guarding verified writes, so a failing key is not retried on every command.
+    pub fn write_breaker(&self) -> &CircuitBreaker {
+        &self.write_breaker
+    }
+
+    /// Fingerprint computed when the key was initialized.
+    pub fn fingerprint(&self) -> Option<&DeviceFingerprint> {
+        self.fingerprint.as_ref()
     }
 
     pub async fn initialize(&mut self) -> Result<()> {
@@ -23,20 +60,69 @@ impl UsbKey {
         if info.id != self.key_id {
             return Err(anyhow!("Unexpected USB key"));
         }
+        self.fingerprint = Some(DeviceFingerprint::compute(&info));
         Ok(())
     }
 
     pub async fn read_data(&self, size: usize) -> Result<Vec<u8>> {
-        self.device.read(size).await
+        let started = Instant::now();
+        let result = self.device.read(size).await;
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_read(started.elapsed(), result.as_ref().ok().map(Vec::len));
+        result
     }
 
     pub async fn write_data(&self, data: &[u8]) -> Result<()> {
-        self.device.write(data).await
+        let started = Instant::now();
+        let result = self.device.write(data).await;
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_write(started.elapsed(), result.as_ref().ok().map(|_| data.len()));
+        result
     }
 
     pub async fn wait_for_command(&self, timeout: Duration) -> Result<String> {
         self.device.wait_for_command(timeout).await
     }
+
+    pub async fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        let result = self.device.read_file(name).await;
+        let bytes = match &result {
+            Ok(content) => Some(content.as_ref().map_or(0, Vec::len)),
+            Err(_) => None,
+        };
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_read(started.elapsed(), bytes);
+        result
+    }
+
+    pub async fn write_file(&self, name: &str, data: &[u8]) -> Result<()> {
+        let started = Instant::now();
+        let result = self.device.write_file(name, data).await;
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_write(started.elapsed(), result.as_ref().ok().map(|_| data.len()));
+        result
+    }
+
+    /// Writes a file and confirms it by reading it back, see [`Device::write_verified`].
+    pub async fn write_verified(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.write_breaker
+            .call(Device::write_verified(
+                self,
+                name,
+                data,
+                VERIFIED_WRITE_ATTEMPTS,
+            ))
+            .await
+    }
 }
 
 #[async_trait]
@@ -65,6 +151,14 @@ impl Device for UsbKey {
         self.wait_for_command(timeout).await
     }
 
+    async fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        UsbKey::read_file(self, name).await
+    }
+
+    async fn write_file(&self, name: &str, data: &[u8]) -> Result<()> {
+        UsbKey::write_file(self, name, data).await
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
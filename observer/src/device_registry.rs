@@ -0,0 +1,110 @@
+use crate::connector::device_operator::DeviceInfo;
+use crate::connector::fingerprint::DeviceFingerprint;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    #[default]
+    Unknown,
+    Allowed,
+    Blocked,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub fingerprint: DeviceFingerprint,
+    pub name: String,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    pub times_seen: u64,
+    #[serde(default)]
+    pub status: DeviceStatus,
+}
+
+/// Result of recording a device attach.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSighting {
+    pub record: DeviceRecord,
+    /// The device has never been seen on this host before.
+    pub is_new: bool,
+}
+
+/// Every device ever attached to this host, persisted as JSON in the guardian state store.
+pub struct DeviceRegistry {
+    path: PathBuf,
+    devices: Mutex<HashMap<DeviceFingerprint, DeviceRecord>>,
+}
+
+impl DeviceRegistry {
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let devices = match tokio::fs::read(&path).await {
+            Ok(data) => {
+                let records: Vec<DeviceRecord> = serde_json::from_slice(&data)?;
+                records
+                    .into_iter()
+                    .map(|record| (record.fingerprint.clone(), record))
+                    .collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            devices: Mutex::new(devices),
+        })
+    }
+
+    pub async fn observe(&self, info: &DeviceInfo) -> Result<DeviceSighting> {
+        let fingerprint = DeviceFingerprint::compute(info);
+        let now = Local::now();
+        let mut devices = self.devices.lock().await;
+        let is_new = !devices.contains_key(&fingerprint);
+        let record = devices
+            .entry(fingerprint.clone())
+            .or_insert_with(|| DeviceRecord {
+                fingerprint,
+                name: info.name.clone(),
+                first_seen: now,
+                last_seen: now,
+                times_seen: 0,
+                status: DeviceStatus::Unknown,
+            });
+        record.last_seen = now;
+        record.times_seen += 1;
+        let record = record.clone();
+        self.persist(&devices).await?;
+        Ok(DeviceSighting { record, is_new })
+    }
+
+    pub async fn set_status(
+        &self,
+        fingerprint: &DeviceFingerprint,
+        status: DeviceStatus,
+    ) -> Result<bool> {
+        let mut devices = self.devices.lock().await;
+        let Some(record) = devices.get_mut(fingerprint) else {
+            return Ok(false);
+        };
+        record.status = status;
+        self.persist(&devices).await?;
+        Ok(true)
+    }
+
+    pub async fn get(&self, fingerprint: &DeviceFingerprint) -> Option<DeviceRecord> {
+        self.devices.lock().await.get(fingerprint).cloned()
+    }
+
+    async fn persist(&self, devices: &HashMap<DeviceFingerprint, DeviceRecord>) -> Result<()> {
+        let mut records: Vec<_> = devices.values().collect();
+        records.sort_by_key(|record| record.first_seen);
+        tokio::fs::write(&self.path, serde_json::to_vec_pretty(&records)?).await?;
+        Ok(())
+    }
+}
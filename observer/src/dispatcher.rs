@@ -0,0 +1,1379 @@
+use crate::action_lock::ActionLock;
+use crate::approval::{ApprovalOutcome, ApprovalPolicy, ApprovalPrompt};
+use crate::audit::{AuditLog, AuditRecord};
+use crate::batch::{
+    reversal, BatchRejection, Reversal, SignedBatch, BATCH_ROLLBACK_TRIGGER, BATCH_TRIGGER,
+    MAX_BATCH_COMMANDS,
+};
+use crate::command_drop::DropRejection;
+use crate::connector::host_key::{HostSection, KeyRole};
+use crate::connector::usb_key::UsbKey;
+use crate::device_registry::{DeviceRecord, DeviceStatus};
+use crate::edr::{EdrIntegration, EdrOutcome, EDR_SCAN_EVENT};
+use crate::effect::{EffectDelta, EffectMeter};
+use crate::evidence::EvidenceUploader;
+use crate::forensics::{ForensicMode, END_FORENSICS, FORENSICS_TRIGGER};
+use crate::handler::{command_catalog, CommandHandler};
+use crate::hooks::{PostCommandHooks, ScheduledCommand};
+use crate::native::{NativeCommand, NativeCommands};
+use crate::network_env::NetworkEnvironment;
+use crate::outbox::{Outbox, Outgoing};
+use crate::playbook::{
+    run_monitor_action, PlaybookAction, PlaybookConfig, StepCondition, PLAYBOOK_TRIGGER,
+    RUN_PLAYBOOK,
+};
+use crate::policy::PolicyContext;
+use crate::probe::PostureVerifier;
+use crate::protocol::{audit_excerpt, parse_command};
+use crate::result::{CommandResult, ResultCode};
+use crate::session::Posture;
+use crate::user_session::list_user_sessions;
+use chrono::{DateTime, Duration, Local};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// File on the key that requests the emergency posture as soon as the key is inserted.
+pub const PANIC_FILE_NAME: &str = "GUARDIAN_PANIC";
+
+const DIAGNOSE_READ_SIZE: usize = 4096;
+
+pub const DEFAULT_EMERGENCY_POSTURE: &[&str] = &["BLOCK_NETWORK", "LOCK_USB", "LOCK_SCREEN"];
+
+/// Whether guardian acts on commands or only records them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnforcementMode {
+    Enforce,
+    /// Authenticate and audit every command, but execute nothing. Used during rollout to
+    /// validate key provisioning and policy against real operator behavior.
+    Observe,
+    /// Like `Observe`, but forced for a single key by its [`KeyRole::Training`] role.
+    Training,
+}
+
+impl EnforcementMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EnforcementMode::Enforce => "enforce",
+            EnforcementMode::Observe => "observe",
+            EnforcementMode::Training => "training",
+        }
+    }
+
+    /// Whether commands are really run.
+    pub fn executes(self) -> bool {
+        self == EnforcementMode::Enforce
+    }
+}
+
+/// Runs a single authenticated command: permission checks, execution, audit and write-back.
+pub struct CommandDispatcher {
+    command_handler: CommandHandler,
+    host_id: String,
+    mode: EnforcementMode,
+    audit_log: Option<Arc<AuditLog>>,
+    emergency_posture: Vec<String>,
+    posture: Mutex<Posture>,
+    posture_verifier: Option<PostureVerifier>,
+    effect_meter: Option<EffectMeter>,
+    evidence_uploader: Option<EvidenceUploader>,
+    post_command_hooks: PostCommandHooks,
+    scheduled: Mutex<Vec<ScheduledCommand>>,
+    local_approval: Option<(ApprovalPolicy, Arc<dyn ApprovalPrompt>)>,
+    outbox: Option<Arc<Outbox>>,
+    edr: Option<EdrIntegration>,
+    playbooks: Option<PlaybookConfig>,
+    action_lock: Option<ActionLock>,
+    forensics: Option<Arc<ForensicMode>>,
+}
+
+impl CommandDispatcher {
+    pub fn new(command_handler: CommandHandler, host_id: String) -> Self {
+        Self {
+            command_handler,
+            host_id,
+            mode: EnforcementMode::Enforce,
+            audit_log: None,
+            emergency_posture: DEFAULT_EMERGENCY_POSTURE
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+            posture: Mutex::new(Posture::default()),
+            posture_verifier: None,
+            effect_meter: None,
+            evidence_uploader: None,
+            post_command_hooks: PostCommandHooks::default(),
+            scheduled: Mutex::new(Vec::new()),
+            local_approval: None,
+            outbox: None,
+            edr: None,
+            playbooks: None,
+            action_lock: None,
+            forensics: None,
+        }
+    }
+
+    /// Results that cannot be written back to the key are spooled to `outbox` and written
+    /// to the same key when it is next presented, see [`CommandDispatcher::deliver_outbox`].
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Writes results spooled for `usb_key` back to it, oldest first, and retries spooled
+    /// audit records. Call once the key is authenticated. Returns how many results were
+    /// written.
+    pub async fn deliver_outbox(&self, usb_key: &UsbKey) -> usize {
+        let Some(outbox) = &self.outbox else {
+            return 0;
+        };
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = audit_log.redeliver_spooled().await {
+                println!("Failed to write spooled audit records: {}", e);
+            }
+        }
+        let fingerprint = usb_key
+            .fingerprint()
+            .map(|fingerprint| fingerprint.to_string());
+        let delivered = outbox
+            .redeliver(|message| {
+                let fingerprint = &fingerprint;
+                async move {
+                    match message {
+                        Outgoing::Result {
+                            device_fingerprint,
+                            result,
+                        } if device_fingerprint == *fingerprint => {
+                            Some(usb_key.write_data(result.to_json_line().as_bytes()).await)
+                        }
+                        _ => None,
+                    }
+                }
+            })
+            .await;
+        match delivered {
+            Ok(delivered) => delivered,
+            Err(e) => {
+                println!("Failed to update the outbox: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Commands in `policy` only run once a local administrator approves them via `prompt`.
+    /// Without approval they are refused.
+    pub fn with_local_approval(
+        mut self,
+        policy: ApprovalPolicy,
+        prompt: Arc<dyn ApprovalPrompt>,
+    ) -> Self {
+        self.local_approval = Some((policy, prompt));
+        self
+    }
+
+    /// Commands to run (or schedule) after other commands complete.
+    pub fn with_post_command_hooks(mut self, hooks: PostCommandHooks) -> Self {
+        self.post_command_hooks = hooks;
+        self
+    }
+
+    /// Hook commands still waiting for their delay.
+    pub async fn scheduled_commands(&self) -> Vec<ScheduledCommand> {
+        self.scheduled.lock().await.clone()
+    }
+
+    /// Runs the scheduled hook commands due at `now`, returning how many ran.
+    pub async fn run_due_commands(&self, now: DateTime<Local>) -> usize {
+        let due: Vec<_> = {
+            let mut scheduled = self.scheduled.lock().await;
+            let (due, pending) = scheduled.drain(..).partition(|command| command.due <= now);
+            *scheduled = pending;
+            due
+        };
+        for command in &due {
+            self.run_hook_command(command, self.mode).await;
+        }
+        due.len()
+    }
+
+    /// Runs the pending hook commands marked `run_on_shutdown` and drops the rest,
+    /// returning how many ran.
+    pub async fn run_shutdown_commands(&self) -> usize {
+        let pending = std::mem::take(&mut *self.scheduled.lock().await);
+        let mut ran = 0;
+        for command in pending {
+            if command.run_on_shutdown {
+                self.run_hook_command(&command, self.mode).await;
+                ran += 1;
+            } else {
+                println!(
+                    "Dropping {} scheduled for {} on shutdown",
+                    command.command, command.due
+                );
+            }
+        }
+        ran
+    }
+
+    /// Large result payloads are uploaded to the key's evidence area instead of inline.
+    pub fn with_evidence_uploader(mut self, uploader: EvidenceUploader) -> Self {
+        self.evidence_uploader = Some(uploader);
+        self
+    }
+
+    pub fn with_posture_verifier(mut self, verifier: PostureVerifier) -> Self {
+        self.posture_verifier = Some(verifier);
+        self
+    }
+
+    /// Asks the host's EDR/AV agent for scans as part of responses; outcomes are audited
+    /// as `EDR_SCAN` events.
+    pub fn with_edr(mut self, edr: EdrIntegration) -> Self {
+        self.edr = Some(edr);
+        self
+    }
+
+    pub fn edr(&self) -> Option<&EdrIntegration> {
+        self.edr.as_ref()
+    }
+
+    /// Runs the commands the lock covers only while holding it.
+    pub fn with_action_lock(mut self, lock: ActionLock) -> Self {
+        self.action_lock = Some(lock);
+        self
+    }
+
+    /// Adds a command implemented in Rust. Its verb must not be taken yet, by a built-in
+    /// or another native command.
+    pub fn with_native_command(mut self, command: Arc<dyn NativeCommand>) -> anyhow::Result<Self> {
+        self.command_handler = self.command_handler.with_native_command(command)?;
+        Ok(self)
+    }
+
+    pub fn native_commands(&self) -> &NativeCommands {
+        self.command_handler.native_commands()
+    }
+
+    /// Playbooks run by `RUN_PLAYBOOK <name>`.
+    pub fn with_playbooks(mut self, playbooks: PlaybookConfig) -> Self {
+        self.playbooks = Some(playbooks);
+        self
+    }
+
+    pub fn playbooks(&self) -> Option<&PlaybookConfig> {
+        self.playbooks.as_ref()
+    }
+
+    /// Lets forensics keys switch the host into forensic mode, see [`ForensicMode`].
+    pub fn with_forensics(mut self, forensics: Arc<ForensicMode>) -> Self {
+        self.forensics = Some(forensics);
+        self
+    }
+
+    pub fn forensics(&self) -> Option<&Arc<ForensicMode>> {
+        self.forensics.as_ref()
+    }
+
+    /// Enters forensic mode if the key has the [`KeyRole::Forensics`] role, auditing and
+    /// writing back the outcome. Returns `None` for other keys.
+    pub async fn enter_forensics(
+        &self,
+        usb_key: &UsbKey,
+        host_section: Option<&HostSection>,
+    ) -> Option<CommandResult> {
+        if host_section?.role != KeyRole::Forensics {
+            return None;
+        }
+        let fingerprint = usb_key
+            .fingerprint()
+            .map(|fingerprint| fingerprint.to_string());
+        let result = match &self.forensics {
+            None => CommandResult::error(
+                ResultCode::CommandNotPermitted,
+                format!("Forensic mode is not available on host {}", self.host_id),
+            ),
+            Some(_) if !self.mode.executes() => CommandResult::new(
+                ResultCode::Observed,
+                "Observation mode: forensic mode recorded but not entered",
+                json!({ "forensics": false }),
+            ),
+            Some(forensics) => match forensics.activate(fingerprint.as_deref()).await {
+                Ok(entered) => CommandResult::ok(
+                    if entered {
+                        "Forensic mode entered: posture changes are frozen"
+                    } else {
+                        "Host is already in forensic mode"
+                    },
+                    json!({ "forensics": true, "state": forensics.state().await }),
+                ),
+                Err(e) => CommandResult::error(
+                    ResultCode::InternalError,
+                    format!("Failed to enter forensic mode: {}", e),
+                ),
+            },
+        };
+        let record = AuditRecord::new(
+            &self.host_id,
+            "FORENSICS_STARTED",
+            self.mode.as_str(),
+            result.is_success(),
+            &result,
+        )
+        .with_trigger(FORENSICS_TRIGGER)
+        .with_device_fingerprint(usb_key.fingerprint());
+        self.audit(record).await;
+        self.write_back(usb_key, &result).await;
+        Some(result)
+    }
+
+    /// Leaves forensic mode; only a forensics key may.
+    async fn end_forensics(
+        &self,
+        host_section: Option<&HostSection>,
+        mode: EnforcementMode,
+    ) -> (CommandResult, bool) {
+        if host_section.is_none_or(|section| section.role != KeyRole::Forensics) {
+            return (
+                CommandResult::error(
+                    ResultCode::CommandNotPermitted,
+                    "Only a forensics key can end forensic mode",
+                ),
+                false,
+            );
+        }
+        let Some(forensics) = &self.forensics else {
+            return (
+                CommandResult::error(
+                    ResultCode::CommandNotPermitted,
+                    format!("Forensic mode is not available on host {}", self.host_id),
+                ),
+                false,
+            );
+        };
+        if !mode.executes() {
+            return (
+                CommandResult::new(
+                    ResultCode::Observed,
+                    format!(
+                        "Observation mode: {} recorded but not executed",
+                        END_FORENSICS
+                    ),
+                    json!({ "command": END_FORENSICS }),
+                ),
+                false,
+            );
+        }
+        match forensics.deactivate().await {
+            Ok(Some(state)) => (
+                CommandResult::ok(
+                    "Forensic mode ended",
+                    json!({
+                        "started_at": state.started_at,
+                        "started_by": state.device_fingerprint,
+                        "duration_secs": (Local::now() - state.started_at).num_seconds(),
+                    }),
+                ),
+                true,
+            ),
+            Ok(None) => (
+                CommandResult::error(
+                    ResultCode::CommandNotPermitted,
+                    "Host is not in forensic mode",
+                ),
+                false,
+            ),
+            Err(e) => (
+                CommandResult::error(
+                    ResultCode::InternalError,
+                    format!("Failed to end forensic mode: {}", e),
+                ),
+                false,
+            ),
+        }
+    }
+
+    /// Has the EDR/AV agent scan `path` and audits the outcome with `trigger`. Outside
+    /// enforcement mode the scan is only audited. Returns `None` without an agent.
+    pub async fn edr_scan(&self, path: &Path, reason: &str, trigger: &str) -> Option<EdrOutcome> {
+        self.edr_scan_in(path, reason, trigger, self.mode).await
+    }
+
+    async fn edr_scan_in(
+        &self,
+        path: &Path,
+        reason: &str,
+        trigger: &str,
+        mode: EnforcementMode,
+    ) -> Option<EdrOutcome> {
+        let edr = self.edr.as_ref()?;
+        let outcome = if mode.executes() {
+            Some(edr.scan(path, reason).await)
+        } else {
+            None
+        };
+        let message = match &outcome {
+            Some(outcome) => format!(
+                "Scan of {} ({}): {}: {}",
+                path.display(),
+                reason,
+                outcome.verdict,
+                outcome.detail
+            ),
+            None => format!(
+                "Scan of {} ({}) skipped in {} mode",
+                path.display(),
+                reason,
+                mode.as_str()
+            ),
+        };
+        let record = AuditRecord::event(&self.host_id, EDR_SCAN_EVENT, mode.as_str(), message)
+            .with_trigger(trigger);
+        self.audit(record).await;
+        outcome
+    }
+
+    /// Measures the system before and after posture-changing commands and attaches the delta
+    /// to their result as `data.effect`.
+    pub fn with_effect_meter(mut self, meter: EffectMeter) -> Self {
+        self.effect_meter = Some(meter);
+        self
+    }
+
+    /// Posture guardian believes the host is in, based on the commands it has executed.
+    pub async fn posture(&self) -> Posture {
+        self.posture.lock().await.clone()
+    }
+
+    /// Runs the posture probes against the tracked posture and audits any drift.
+    pub async fn verify_posture(&self) -> CommandResult {
+        let Some(verifier) = &self.posture_verifier else {
+            return CommandResult::error(
+                ResultCode::InternalError,
+                "No posture probes are configured",
+            );
+        };
+
+        let expected = self.posture().await;
+        let results = verifier.verify(&expected).await;
+        let drifted: Vec<_> = results.iter().filter(|result| result.drift).collect();
+
+        for result in &drifted {
+            let record = AuditRecord::event(
+                &self.host_id,
+                "POSTURE_DRIFT",
+                self.mode.as_str(),
+                format!(
+                    "Probe {} observed {:?}={:?}, expected {:?}",
+                    result.probe, result.field, result.actual, result.expected
+                ),
+            );
+            self.audit(record).await;
+        }
+
+        let data = json!({ "expected": expected, "probes": results });
+        if drifted.is_empty() {
+            CommandResult::ok("Posture matches the tracked state", data)
+        } else {
+            CommandResult::new(
+                ResultCode::PostureDrift,
+                format!("Posture drift detected by {} probe(s)", drifted.len()),
+                data,
+            )
+        }
+    }
+
+    /// Commands applied, in order, when a key carrying the panic file is inserted.
+    pub fn with_emergency_posture(mut self, commands: Vec<String>) -> Self {
+        self.emergency_posture = commands;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: EnforcementMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_audit_log(mut self, audit_log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn mode(&self) -> EnforcementMode {
+        self.mode
+    }
+
+    /// Mode for commands from a key with `host_section`: training keys never execute.
+    pub fn mode_for(&self, host_section: Option<&HostSection>) -> EnforcementMode {
+        match host_section {
+            Some(section) if section.role == KeyRole::Training => EnforcementMode::Training,
+            _ => self.mode,
+        }
+    }
+
+    pub fn host_id(&self) -> &str {
+        &self.host_id
+    }
+
+    pub async fn dispatch(
+        &self,
+        usb_key: &UsbKey,
+        host_section: Option<&mut HostSection>,
+        command: &str,
+    ) -> CommandResult {
+        self.dispatch_triggered(usb_key, host_section, command, None)
+            .await
+    }
+
+    async fn dispatch_triggered(
+        &self,
+        usb_key: &UsbKey,
+        host_section: Option<&mut HostSection>,
+        command: &str,
+        trigger: Option<&str>,
+    ) -> CommandResult {
+        let mode = self.mode_for(host_section.as_deref());
+        let mut policy_context = None;
+        let mut approved_by = None;
+        let (mut result, executed) = match host_section {
+            _ if command == "LIST_COMMANDS" => (self.list_commands(host_section.as_deref()), true),
+            _ if command == "DIAGNOSE_KEY" => (self.diagnose_key(usb_key).await, true),
+            Some(section) if !section.allows(command) => (
+                CommandResult::error(
+                    ResultCode::CommandNotPermitted,
+                    format!(
+                        "Command {} is not permitted on host {}",
+                        command, self.host_id
+                    ),
+                ),
+                false,
+            ),
+            _ if command == END_FORENSICS => {
+                self.end_forensics(host_section.as_deref(), mode).await
+            }
+            Some(section) => match section.conditions_for(command) {
+                Some(conditions) => {
+                    let context = self.policy_context().await;
+                    let violation = conditions.violation(&context);
+                    policy_context = Some(context);
+                    match violation {
+                        Some(reason) => (
+                            CommandResult::error(
+                                ResultCode::CommandNotPermitted,
+                                format!("Command {} refused: {}", command, reason),
+                            ),
+                            false,
+                        ),
+                        None => {
+                            self.consume_and_execute(section, command, mode, &mut approved_by)
+                                .await
+                        }
+                    }
+                }
+                None => {
+                    self.consume_and_execute(section, command, mode, &mut approved_by)
+                        .await
+                }
+            },
+            None => {
+                self.execute_approved(command, mode, None, &mut approved_by)
+                    .await
+            }
+        };
+
+        self.offload_payload(usb_key, command, &mut result).await;
+
+        let mut record = AuditRecord::new(&self.host_id, command, mode.as_str(), executed, &result)
+            .with_device_fingerprint(usb_key.fingerprint())
+            .with_approved_by(approved_by.as_deref());
+        if let Some(context) = policy_context {
+            record = record.with_policy_context(context);
+        }
+        if let Some(trigger) = trigger {
+            record = record.with_trigger(trigger);
+        }
+        self.audit(record).await;
+        self.write_back(usb_key, &result).await;
+        if executed {
+            self.after_command(command, &result, mode).await;
+        }
+
+        result
+    }
+
+    /// Runs the commands of a validated batch in order, each dispatched as if read from the
+    /// key. Every step is checked against the host section first, so a batch with a step
+    /// the key may not run is refused whole. If a step fails, the posture changes of the
+    /// steps before it are reverted in reverse order; those no command can undo are
+    /// reported as `not_reverted`.
+    pub async fn dispatch_batch(
+        &self,
+        usb_key: &UsbKey,
+        mut host_section: Option<&mut HostSection>,
+        batch: &SignedBatch,
+    ) -> CommandResult {
+        if let Some(rejection) =
+            batch_rejection(host_section.as_deref(), batch, self.native_commands())
+        {
+            return self
+                .reject_batch(usb_key, Some(&batch.batch_id), &rejection)
+                .await;
+        }
+
+        let mode = self.mode_for(host_section.as_deref());
+        let trigger = format!("{}:{}", BATCH_TRIGGER, batch.batch_id);
+        let mut steps = Vec::with_capacity(batch.commands.len());
+        let mut applied = Vec::new();
+        let mut failure = None;
+        for (index, command) in batch.commands.iter().enumerate() {
+            let before = self.posture().await;
+            let result = self
+                .dispatch_triggered(
+                    usb_key,
+                    host_section.as_deref_mut(),
+                    command,
+                    Some(&trigger),
+                )
+                .await;
+            steps.push(json!({
+                "command": command,
+                "code": result.code,
+                "human_message": result.human_message,
+            }));
+            match result.code {
+                ResultCode::Ok => applied.push((command, before)),
+                ResultCode::Observed => {}
+                _ => {
+                    failure = Some((index, result));
+                    break;
+                }
+            }
+        }
+
+        let Some((index, failed)) = failure else {
+            let result = CommandResult::ok(
+                format!(
+                    "Batch {}: {} commands applied",
+                    batch.batch_id,
+                    batch.commands.len()
+                ),
+                json!({ "batch_id": batch.batch_id, "steps": steps }),
+            );
+            self.write_back(usb_key, &result).await;
+            return result;
+        };
+
+        let rollback_trigger = format!("{}:{}", BATCH_ROLLBACK_TRIGGER, batch.batch_id);
+        let mut rolled_back = Vec::new();
+        let mut not_reverted = Vec::new();
+        for (command, before) in applied.into_iter().rev() {
+            let undo = match reversal(command, &before) {
+                Reversal::Nothing => continue,
+                Reversal::Irreversible => {
+                    not_reverted.push(command.clone());
+                    continue;
+                }
+                Reversal::Undo(undo) => undo,
+            };
+            // Undoing a step needs no permission of its own, it restores the posture the
+            // key found.
+            let (result, executed) = self.execute(undo, mode, None).await;
+            let record = AuditRecord::new(&self.host_id, undo, mode.as_str(), executed, &result)
+                .with_trigger(&rollback_trigger)
+                .with_device_fingerprint(usb_key.fingerprint());
+            self.audit(record).await;
+            if result.is_success() {
+                rolled_back.push(command.clone());
+            } else {
+                println!("Failed to revert {}: {}", command, result.human_message);
+                not_reverted.push(command.clone());
+            }
+        }
+
+        let result = CommandResult::new(
+            failed.code,
+            format!(
+                "Batch {} failed at step {} ({}): {}; reverted {}, not reverted {}",
+                batch.batch_id,
+                index + 1,
+                batch.commands[index],
+                failed.human_message,
+                rolled_back.len(),
+                not_reverted.len()
+            ),
+            json!({
+                "batch_id": batch.batch_id,
+                "failed_step": index,
+                "steps": steps,
+                "rolled_back": rolled_back,
+                "not_reverted": not_reverted,
+            }),
+        );
+        self.write_back(usb_key, &result).await;
+        result
+    }
+
+    /// Audits a batch refused before any of its commands ran.
+    pub async fn reject_batch(
+        &self,
+        usb_key: &UsbKey,
+        batch_id: Option<&str>,
+        rejection: &BatchRejection,
+    ) -> CommandResult {
+        let result = CommandResult::new(
+            ResultCode::CommandNotPermitted,
+            format!(
+                "Batch {} refused: {}",
+                batch_id.unwrap_or("(unreadable)"),
+                rejection
+            ),
+            json!({ "batch_id": batch_id, "reason": rejection.reason() }),
+        );
+        let trigger = match batch_id {
+            Some(id) => format!("{}:{}", BATCH_TRIGGER, id),
+            None => BATCH_TRIGGER.to_string(),
+        };
+        let record = AuditRecord::new(&self.host_id, "BATCH", self.mode.as_str(), false, &result)
+            .with_trigger(&trigger)
+            .with_device_fingerprint(usb_key.fingerprint());
+        self.audit(record).await;
+        self.write_back(usb_key, &result).await;
+        result
+    }
+
+    /// Parses a command line read from the key and dispatches it. Lines that fail
+    /// validation are audited, in escaped form, with the reason they were refused.
+    pub async fn dispatch_raw(
+        &self,
+        usb_key: &UsbKey,
+        host_section: Option<&mut HostSection>,
+        raw: &[u8],
+    ) -> CommandResult {
+        let error = match parse_command(raw, self.native_commands()) {
+            Ok(command) => {
+                return self
+                    .dispatch(usb_key, host_section, &command.to_string())
+                    .await
+            }
+            Err(error) => error,
+        };
+        let mode = self.mode_for(host_section.as_deref());
+        let excerpt = audit_excerpt(raw);
+        let result = CommandResult::new(
+            error.result_code(),
+            format!("Command refused: {}", error),
+            json!({ "reason": error.reason() }),
+        );
+        let record = AuditRecord::new(&self.host_id, &excerpt, mode.as_str(), false, &result)
+            .with_device_fingerprint(usb_key.fingerprint());
+        self.audit(record).await;
+        self.write_back(usb_key, &result).await;
+        result
+    }
+
+    /// Runs a command that arrived without a key, e.g. through the command drop directory.
+    /// The caller has already authenticated it; `trigger` records where it came from.
+    pub async fn dispatch_unattended(&self, command: &str, trigger: &str) -> CommandResult {
+        let mut approved_by = None;
+        let (result, executed) = match command {
+            "LIST_COMMANDS" | "DIAGNOSE_KEY" => (
+                CommandResult::error(
+                    ResultCode::CommandNotPermitted,
+                    format!("Command {} needs a USB key", command),
+                ),
+                false,
+            ),
+            _ => {
+                self.execute_approved(command, self.mode, None, &mut approved_by)
+                    .await
+            }
+        };
+        let record = AuditRecord::new(
+            &self.host_id,
+            command,
+            self.mode.as_str(),
+            executed,
+            &result,
+        )
+        .with_trigger(trigger)
+        .with_approved_by(approved_by.as_deref());
+        self.audit(record).await;
+        if executed {
+            self.after_command(command, &result, self.mode).await;
+        }
+        result
+    }
+
+    /// Runs or schedules the hooks of a completed command. In observation mode hooks are
+    /// followed too, so the audit log shows what they would have done. Commands run by
+    /// hooks do not trigger further hooks. Hooks of a training key's command are recorded
+    /// right away, so nothing is left scheduled to run later for real. Commands listed in
+    /// the EDR configuration's `scan_after` are followed by a scan.
+    async fn after_command(&self, command: &str, result: &CommandResult, mode: EnforcementMode) {
+        if !result.is_success() && result.code != ResultCode::Observed {
+            return;
+        }
+        let now = Local::now();
+        for hook in self.post_command_hooks.after(command) {
+            let scheduled = ScheduledCommand::from_hook(hook, now);
+            if hook.delay_secs == 0 || mode == EnforcementMode::Training {
+                self.run_hook_command(&scheduled, mode).await;
+            } else {
+                println!(
+                    "Scheduled {} at {} after {}",
+                    scheduled.command, scheduled.due, command
+                );
+                self.scheduled.lock().await.push(scheduled);
+            }
+        }
+        if let Some(edr) = &self.edr {
+            if edr.scans_after(command) {
+                let reason = format!("after {}", command);
+                let trigger = format!("HOOK:{}", command);
+                self.edr_scan_in(&edr.config().scan_root, &reason, &trigger, mode)
+                    .await;
+            }
+        }
+    }
+
+    /// Hook commands go through local approval like any other, so a hook cannot run a
+    /// command that needs approval without it.
+    async fn run_hook_command(&self, scheduled: &ScheduledCommand, mode: EnforcementMode) {
+        let mut approved_by = None;
+        let (result, executed) = self
+            .execute_approved(&scheduled.command, mode, None, &mut approved_by)
+            .await;
+        let record = AuditRecord::new(
+            &self.host_id,
+            &scheduled.command,
+            mode.as_str(),
+            executed,
+            &result,
+        )
+        .with_trigger(&scheduled.trigger)
+        .with_approved_by(approved_by.as_deref());
+        self.audit(record).await;
+    }
+
+    /// Audits a command that arrived without a key and failed validation.
+    pub async fn reject_unattended(
+        &self,
+        command: &str,
+        trigger: &str,
+        rejection: &DropRejection,
+    ) -> CommandResult {
+        let result = CommandResult::new(
+            ResultCode::CommandNotPermitted,
+            format!("Command {} refused: {}", command, rejection),
+            json!({ "reason": rejection.reason() }),
+        );
+        let record = AuditRecord::new(&self.host_id, command, self.mode.as_str(), false, &result)
+            .with_trigger(trigger);
+        self.audit(record).await;
+        result
+    }
+
+    /// Commands the presented key may run on this host, for key-side tooling to build its UI.
+    fn list_commands(&self, host_section: Option<&HostSection>) -> CommandResult {
+        let commands: Vec<_> = command_catalog(self.native_commands())
+            .into_iter()
+            .filter(|spec| {
+                spec.name == "LIST_COMMANDS"
+                    || host_section.is_none_or(|section| section.allows(&spec.name))
+            })
+            .collect();
+        CommandResult::ok(
+            format!("{} commands available", commands.len()),
+            json!({ "host_id": self.host_id, "commands": commands }),
+        )
+    }
+
+    /// Reports I/O health of the key, including a fresh timed read.
+    async fn diagnose_key(&self, usb_key: &UsbKey) -> CommandResult {
+        let started = std::time::Instant::now();
+        let probe = usb_key.read_data(DIAGNOSE_READ_SIZE).await;
+        let probe_ms = started.elapsed().as_millis() as u64;
+        let metrics = usb_key.metrics();
+
+        let mut data = json!({
+            "fingerprint": usb_key.fingerprint(),
+            "probe_read_ms": probe_ms,
+            "metrics": metrics,
+            "mean_read_ms": metrics.read_latency.mean_ms(),
+            "mean_write_ms": metrics.write_latency.mean_ms(),
+        });
+        if let Err(e) = probe {
+            data["probe_error"] = json!(e.to_string());
+        }
+        CommandResult::ok(
+            format!(
+                "{} reads ({} errors), {} writes ({} errors)",
+                metrics.reads, metrics.read_errors, metrics.writes, metrics.write_errors
+            ),
+            data,
+        )
+    }
+
+    /// Replaces an oversized result payload with a reference to its encrypted upload.
+    async fn offload_payload(&self, usb_key: &UsbKey, command: &str, result: &mut CommandResult) {
+        let Some(uploader) = &self.evidence_uploader else {
+            return;
+        };
+        let payload = match serde_json::to_vec(&result.data) {
+            Ok(payload) if uploader.should_upload(payload.len()) => payload,
+            _ => return,
+        };
+
+        match uploader.upload(usb_key, command, &payload).await {
+            Ok(manifest) => {
+                result.data = json!({ "evidence_upload": manifest });
+            }
+            Err(e) => {
+                println!("Failed to upload evidence to USB key: {}", e);
+                result.data = json!({
+                    "evidence_upload_error": e.to_string(),
+                    "payload_size": payload.len(),
+                });
+            }
+        }
+    }
+
+    async fn policy_context(&self) -> PolicyContext {
+        let user_sessions = list_user_sessions().await.unwrap_or_else(|e| {
+            println!("Failed to list user sessions: {}", e);
+            Vec::new()
+        });
+        PolicyContext {
+            user_sessions,
+            network: NetworkEnvironment::snapshot().await,
+        }
+    }
+
+    /// Asks for local approval if the command needs it. Returns the approving user, or the
+    /// refusal when approval was denied, timed out or could not be requested.
+    async fn local_approval(
+        &self,
+        command: &str,
+        mode: EnforcementMode,
+    ) -> Result<Option<String>, CommandResult> {
+        let Some((policy, prompt)) = &self.local_approval else {
+            return Ok(None);
+        };
+        // Nothing runs in observation or training mode, so there is nothing to approve.
+        if !mode.executes() || !policy.requires_approval(command) {
+            return Ok(None);
+        }
+
+        println!("Command {} requires local administrator approval", command);
+        let outcome = prompt
+            .request(command, policy)
+            .await
+            .unwrap_or_else(|e| ApprovalOutcome::Denied(format!("approval prompt failed: {}", e)));
+        let reason = match outcome {
+            ApprovalOutcome::Approved { user } => return Ok(Some(user)),
+            ApprovalOutcome::Denied(reason) => reason,
+            ApprovalOutcome::TimedOut => format!(
+                "no administrator approved within {} seconds",
+                policy.timeout_secs
+            ),
+        };
+        Err(CommandResult::new(
+            ResultCode::CommandNotPermitted,
+            format!("Command {} refused: {}", command, reason),
+            json!({ "approval": "refused", "reason": reason }),
+        ))
+    }
+
+    /// Asks for local approval if needed, then executes. `section` is the host section of
+    /// the key the command came from, against which playbook steps are checked.
+    async fn execute_approved(
+        &self,
+        command: &str,
+        mode: EnforcementMode,
+        section: Option<&HostSection>,
+        approved_by: &mut Option<String>,
+    ) -> (CommandResult, bool) {
+        match self.local_approval(command, mode).await {
+            Ok(user) => {
+                *approved_by = user;
+                self.execute(command, mode, section).await
+            }
+            Err(result) => (result, false),
+        }
+    }
+
+    async fn consume_and_execute(
+        &self,
+        section: &mut HostSection,
+        command: &str,
+        mode: EnforcementMode,
+        approved_by: &mut Option<String>,
+    ) -> (CommandResult, bool) {
+        // A refused command is not consumed from the key.
+        match self.local_approval(command, mode).await {
+            Ok(user) => *approved_by = user,
+            Err(result) => return (result, false),
+        }
+        // Written back to the key by the caller, which holds the section key.
+        section.record_consumption(&self.host_id, command);
+        self.execute(command, mode, Some(section)).await
+    }
+
+    /// Applies the emergency posture if the key carries the panic file. Runs before
+    /// authentication so a user who panicked on another machine gets the host locked down
+    /// without going through the command handshake, but only for a key whose `device` is
+    /// registered as allowed on this host; any other stick with the file is refused and
+    /// audited. Returns `None` if no panic was requested.
+    pub async fn check_panic(
+        &self,
+        usb_key: &UsbKey,
+        device: Option<&DeviceRecord>,
+    ) -> Option<Vec<CommandResult>> {
+        match usb_key.read_file(PANIC_FILE_NAME).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return None,
+            Err(e) => {
+                println!("Failed to check for panic file: {}", e);
+                return None;
+            }
+        }
+
+        if device.is_none_or(|device| device.status != DeviceStatus::Allowed) {
+            println!("Panic file found on an unregistered USB key. Ignoring it.");
+            let result = CommandResult::new(
+                ResultCode::CommandNotPermitted,
+                "Panic file refused: the key is not registered as allowed on this host".to_string(),
+                json!({ "reason": "UNREGISTERED_DEVICE" }),
+            );
+            let record = AuditRecord::new(
+                &self.host_id,
+                PANIC_FILE_NAME,
+                self.mode.as_str(),
+                false,
+                &result,
+            )
+            .with_trigger(PANIC_FILE_NAME)
+            .with_device_fingerprint(usb_key.fingerprint());
+            self.audit(record).await;
+            return Some(vec![result]);
+        }
+
+        println!("Panic file found on USB key. Applying emergency posture...");
+        let mut results = Vec::with_capacity(self.emergency_posture.len());
+        for command in &self.emergency_posture {
+            let (result, executed) = self.execute(command, self.mode, None).await;
+            let record = AuditRecord::new(
+                &self.host_id,
+                command,
+                self.mode.as_str(),
+                executed,
+                &result,
+            )
+            .with_trigger(PANIC_FILE_NAME)
+            .with_device_fingerprint(usb_key.fingerprint());
+            self.audit(record).await;
+            self.write_back(usb_key, &result).await;
+            results.push(result);
+        }
+        Some(results)
+    }
+
+    async fn write_back(&self, usb_key: &UsbKey, result: &CommandResult) {
+        let Err(e) = usb_key.write_data(result.to_json_line().as_bytes()).await else {
+            return;
+        };
+        let Some(outbox) = &self.outbox else {
+            println!("Failed to write result back to USB key: {}", e);
+            return;
+        };
+        println!("Failed to write result back to USB key, spooling it: {}", e);
+        let message = Outgoing::Result {
+            device_fingerprint: usb_key
+                .fingerprint()
+                .map(|fingerprint| fingerprint.to_string()),
+            result: result.clone(),
+        };
+        if let Err(e) = outbox.push(message).await {
+            println!("Failed to spool result: {}", e);
+        }
+    }
+
+    async fn execute(
+        &self,
+        command: &str,
+        mode: EnforcementMode,
+        section: Option<&HostSection>,
+    ) -> (CommandResult, bool) {
+        if let Some(forensics) = &self.forensics {
+            if !ForensicMode::allows(command, self.native_commands()) && forensics.is_active().await
+            {
+                println!("Forensic mode: {} refused", command);
+                return (
+                    CommandResult::new(
+                        ResultCode::ForensicHold,
+                        format!(
+                            "Command {} refused: host is in forensic mode, only evidence collection runs",
+                            command
+                        ),
+                        json!({ "command": command, "forensics": true }),
+                    ),
+                    false,
+                );
+            }
+        }
+        if command == "VERIFY_POSTURE" {
+            // Read-only, so it runs in observation and training mode too.
+            return (self.verify_posture().await, true);
+        }
+        let native = self.native_commands();
+        if native.is_read_only(command) {
+            // Read-only, so it runs in observation and training mode too.
+            if let Some(result) = native.run(command).await {
+                return (result, true);
+            }
+        }
+        if let Some(name) = command
+            .strip_prefix(RUN_PLAYBOOK)
+            .and_then(|rest| rest.strip_prefix(' '))
+        {
+            // Steps are executed in the same mode, so observation mode audits what they
+            // would have done.
+            return Box::pin(self.run_playbook(name, mode, section)).await;
+        }
+
+        match mode {
+            EnforcementMode::Enforce => {
+                let _lock = match &self.action_lock {
+                    Some(lock) if lock.covers(command) => match lock.acquire(command).await {
+                        Ok(guard) => Some(guard),
+                        Err(busy) => {
+                            println!("Command {} not run: {}", command, busy);
+                            return (busy.to_result(command), false);
+                        }
+                    },
+                    _ => None,
+                };
+                let meter = self
+                    .effect_meter
+                    .as_ref()
+                    .filter(|meter| meter.measures(command));
+                let before = match meter {
+                    Some(meter) => Some(meter.snapshot().await),
+                    None => None,
+                };
+                let mut result = self.command_handler.handle_command(command).await;
+                if result.is_success() {
+                    self.posture.lock().await.apply(command);
+                }
+                if let (Some(meter), Some(before)) = (meter, before) {
+                    let delta = EffectDelta::new(before, meter.snapshot().await);
+                    if let (Some(data), Ok(delta)) =
+                        (result.data.as_object_mut(), serde_json::to_value(delta))
+                    {
+                        data.insert("effect".to_string(), delta);
+                    }
+                }
+                (result, true)
+            }
+            EnforcementMode::Observe => (
+                CommandResult::new(
+                    ResultCode::Observed,
+                    format!("Observation mode: {} recorded but not executed", command),
+                    json!({ "command": command }),
+                ),
+                false,
+            ),
+            EnforcementMode::Training => (
+                CommandResult::new(
+                    ResultCode::Observed,
+                    format!("Training key: {} recorded but not executed", command),
+                    json!({ "command": command, "training": true }),
+                ),
+                false,
+            ),
+        }
+    }
+
+    /// Runs the steps of a playbook in order, each audited with trigger `PLAYBOOK:<name>`.
+    /// A step runs if its `when` matches the outcome of the steps before it and its host
+    /// conditions hold; otherwise it is skipped. Command steps must be allowed by the
+    /// key's `section`, if any, and go through local approval like any other command.
+    /// Delayed steps are scheduled like delayed hooks, so the playbook does not wait for
+    /// them. The result fails with the code of the first failed step.
+    async fn run_playbook(
+        &self,
+        name: &str,
+        mode: EnforcementMode,
+        section: Option<&HostSection>,
+    ) -> (CommandResult, bool) {
+        let Some(playbook) = self.playbooks.as_ref().and_then(|config| config.get(name)) else {
+            return (
+                CommandResult::error(
+                    ResultCode::UnknownCommand,
+                    format!("No playbook named {}", name),
+                ),
+                false,
+            );
+        };
+        let trigger = format!("{}:{}", PLAYBOOK_TRIGGER, name);
+        let mut steps = Vec::with_capacity(playbook.steps.len());
+        let mut failure = None;
+        for (index, step) in playbook.steps.iter().enumerate() {
+            let label = step.label();
+            let mut skipped = match step.when {
+                StepCondition::Success if failure.is_some() => {
+                    Some("an earlier step failed".to_string())
+                }
+                StepCondition::Failure if failure.is_none() => {
+                    Some("no earlier step failed".to_string())
+                }
+                _ => None,
+            };
+            if let (None, Some(conditions)) = (&skipped, &step.conditions) {
+                skipped = conditions.violation(&self.policy_context().await);
+            }
+            if let Some(reason) = skipped {
+                steps.push(json!({
+                    "step": index + 1,
+                    "action": label,
+                    "outcome": "skipped",
+                    "reason": reason,
+                }));
+                continue;
+            }
+
+            let mut approved_by = None;
+            let (result, executed) = match &step.action {
+                PlaybookAction::Command { command }
+                    if section.is_some_and(|section| !section.allows(command)) =>
+                {
+                    (
+                        CommandResult::error(
+                            ResultCode::CommandNotPermitted,
+                            format!(
+                                "Command {} is not permitted on host {}",
+                                command, self.host_id
+                            ),
+                        ),
+                        false,
+                    )
+                }
+                PlaybookAction::Command { command } if step.delay_secs > 0 && mode.executes() => {
+                    // Audited when it runs, like a delayed hook.
+                    let scheduled = ScheduledCommand {
+                        command: command.clone(),
+                        trigger: trigger.clone(),
+                        due: Local::now() + Duration::seconds(step.delay_secs as i64),
+                        run_on_shutdown: false,
+                    };
+                    println!(
+                        "Scheduled {} at {} for playbook {}",
+                        command, scheduled.due, name
+                    );
+                    steps.push(json!({
+                        "step": index + 1,
+                        "action": label,
+                        "outcome": "scheduled",
+                        "due": scheduled.due,
+                    }));
+                    self.scheduled.lock().await.push(scheduled);
+                    continue;
+                }
+                PlaybookAction::Command { command } => {
+                    self.execute_approved(command, mode, section, &mut approved_by)
+                        .await
+                }
+                // Verifying only reads, so it runs in observation and training mode too.
+                action @ PlaybookAction::VerifyBaseline { .. } => {
+                    (run_monitor_action(action).await, true)
+                }
+                action if mode.executes() => (run_monitor_action(action).await, true),
+                _ => (
+                    CommandResult::new(
+                        ResultCode::Observed,
+                        format!("{} recorded but not executed", label),
+                        json!({ "command": label }),
+                    ),
+                    false,
+                ),
+            };
+            let record = AuditRecord::new(&self.host_id, &label, mode.as_str(), executed, &result)
+                .with_trigger(&trigger)
+                .with_approved_by(approved_by.as_deref());
+            self.audit(record).await;
+            if let (true, PlaybookAction::Command { command }) = (executed, &step.action) {
+                self.after_command(command, &result, mode).await;
+            }
+
+            let outcome = match result.code {
+                ResultCode::Ok => "ok",
+                ResultCode::Observed => "observed",
+                _ => "failed",
+            };
+            steps.push(json!({
+                "step": index + 1,
+                "action": label,
+                "outcome": outcome,
+                "code": result.code,
+                "human_message": result.human_message,
+            }));
+            if outcome == "failed" && failure.is_none() {
+                failure = Some((index, label, result));
+            }
+        }
+
+        let data = json!({ "playbook": name, "steps": steps });
+        let result = match failure {
+            None => CommandResult::ok(format!("Playbook {} completed", name), data),
+            Some((index, label, failed)) => CommandResult::new(
+                failed.code,
+                format!(
+                    "Playbook {} failed at step {} ({}): {}",
+                    name,
+                    index + 1,
+                    label,
+                    failed.human_message
+                ),
+                data,
+            ),
+        };
+        (result, true)
+    }
+
+    async fn audit(&self, record: AuditRecord) {
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = audit_log.record(&record).await {
+                println!("Failed to write audit record: {}", e);
+            }
+        }
+    }
+}
+
+/// Why `batch` cannot run as a whole, checked before any of its steps.
+fn batch_rejection(
+    host_section: Option<&HostSection>,
+    batch: &SignedBatch,
+    native: &NativeCommands,
+) -> Option<BatchRejection> {
+    if batch.commands.is_empty() {
+        return Some(BatchRejection::Empty);
+    }
+    if batch.commands.len() > MAX_BATCH_COMMANDS {
+        return Some(BatchRejection::TooLong(batch.commands.len()));
+    }
+    for (index, command) in batch.commands.iter().enumerate() {
+        if let Err(e) = parse_command(command.as_bytes(), native) {
+            return Some(BatchRejection::InvalidStep {
+                index,
+                reason: e.to_string(),
+            });
+        }
+        if host_section.is_some_and(|section| !section.allows(command)) {
+            return Some(BatchRejection::StepNotPermitted {
+                index,
+                command: command.clone(),
+            });
+        }
+    }
+    None
+}
@@ -0,0 +1,306 @@
+use crate::dispatcher::CommandDispatcher;
+use anyhow::{anyhow, Result};
+use file_monitor_core::shell_hook::substitute;
+use file_monitor_core::webhook::post_json;
+use file_monitor_core::{FileEvent, FileMonitor, MonitorEvent, ShutdownToken};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command as AsyncCommand;
+
+/// Audit event recording the outcome of a scan by the EDR/AV agent.
+pub const EDR_SCAN_EVENT: &str = "EDR_SCAN";
+/// Audit trigger of scans requested by the file monitor.
+pub const FILE_MONITOR_TRIGGER: &str = "FILE_MONITOR";
+
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+/// Writes to a file within this window lead to a single scan.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+/// Most output of the agent kept in the audit record.
+const MAX_DETAIL_LENGTH: usize = 512;
+
+/// How guardian reaches the host's EDR/antivirus agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EdrAgent {
+    /// Runs the agent's command-line scanner. `{path}` and `{reason}` in `args` are
+    /// replaced by the path to scan and why. Exit code 0 means clean, one of
+    /// `detected_exit_codes` means something was found; anything else is a failed scan.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_detected_exit_codes")]
+        detected_exit_codes: Vec<i32>,
+    },
+    /// Posts `{"action": "scan", "path", "reason", "host_id"}` to the agent's local API.
+    /// An answer of `{"verdict": "clean" | "detected", "detail"}` is recorded as such;
+    /// any other 2xx answer means the scan was submitted.
+    Api { url: String },
+}
+
+fn default_detected_exit_codes() -> Vec<i32> {
+    vec![1]
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// Which events make guardian ask the EDR/AV agent for a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EdrConfig {
+    pub agent: EdrAgent,
+    /// Commands after which the host is scanned, e.g. `BLOCK_NETWORK`. The scan covers
+    /// `scan_root`.
+    #[serde(default)]
+    pub scan_after: Vec<String>,
+    #[serde(default = "default_scan_root")]
+    pub scan_root: PathBuf,
+    /// Watched with the file monitor; files created or modified under them, and alerts
+    /// raised for them, are scanned.
+    #[serde(default)]
+    pub watch_paths: Vec<PathBuf>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_scan_root() -> PathBuf {
+    PathBuf::from("/")
+}
+
+impl EdrConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdrVerdict {
+    Clean,
+    Detected,
+    /// Handed to the agent, which reports its findings on its own.
+    Submitted,
+    Failed,
+}
+
+impl EdrVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EdrVerdict::Clean => "clean",
+            EdrVerdict::Detected => "detected",
+            EdrVerdict::Submitted => "submitted",
+            EdrVerdict::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for EdrVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EdrOutcome {
+    pub verdict: EdrVerdict,
+    pub detail: String,
+}
+
+impl EdrOutcome {
+    fn failed(error: anyhow::Error) -> Self {
+        Self {
+            verdict: EdrVerdict::Failed,
+            detail: error.to_string(),
+        }
+    }
+}
+
+/// Asks the host's EDR/AV agent to scan paths as part of a response.
+pub struct EdrIntegration {
+    config: EdrConfig,
+    host_id: String,
+}
+
+impl EdrIntegration {
+    pub fn new(config: EdrConfig, host_id: &str) -> Self {
+        Self {
+            config,
+            host_id: host_id.to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &EdrConfig {
+        &self.config
+    }
+
+    /// Whether the host is scanned after `command`.
+    pub fn scans_after(&self, command: &str) -> bool {
+        self.config.scan_after.iter().any(|after| after == command)
+    }
+
+    /// Scans `path`; failures to reach the agent are part of the outcome.
+    pub async fn scan(&self, path: &Path, reason: &str) -> EdrOutcome {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let scanned = match &self.config.agent {
+            EdrAgent::Command {
+                program,
+                args,
+                detected_exit_codes,
+            } => {
+                tokio::time::timeout(
+                    timeout,
+                    scan_with_command(program, args, detected_exit_codes, path, reason),
+                )
+                .await
+            }
+            EdrAgent::Api { url } => {
+                tokio::time::timeout(timeout, self.scan_with_api(url, path, reason)).await
+            }
+        };
+        match scanned {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(e)) => EdrOutcome::failed(e),
+            Err(_) => EdrOutcome::failed(anyhow!(
+                "Scan timed out after {}s",
+                self.config.timeout_secs
+            )),
+        }
+    }
+
+    async fn scan_with_api(&self, url: &str, path: &Path, reason: &str) -> Result<EdrOutcome> {
+        let request = json!({
+            "action": "scan",
+            "path": path,
+            "reason": reason,
+            "host_id": self.host_id,
+        });
+        let response = post_json(url, &serde_json::to_vec(&request)?).await?;
+        #[derive(Deserialize)]
+        struct Answer {
+            verdict: EdrVerdict,
+            #[serde(default)]
+            detail: String,
+        }
+        Ok(match serde_json::from_str::<Answer>(&response) {
+            Ok(answer) => EdrOutcome {
+                verdict: answer.verdict,
+                detail: answer.detail,
+            },
+            Err(_) => EdrOutcome {
+                verdict: EdrVerdict::Submitted,
+                detail: truncate(response.trim()),
+            },
+        })
+    }
+}
+
+/// Watches the configured `watch_paths` with the file monitor and has the EDR/AV agent
+/// scan files as they are created or modified, and files alerts are raised for, until
+/// `shutdown` is cancelled. Does nothing without an agent or paths to watch.
+pub async fn scan_monitored_files(
+    dispatcher: Arc<CommandDispatcher>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let Some(edr) = dispatcher.edr() else {
+        return Ok(());
+    };
+    let Some((first, rest)) = edr.config().watch_paths.split_first() else {
+        return Ok(());
+    };
+    let builder = rest.iter().fold(
+        FileMonitor::builder(first)
+            .debounce(WATCH_DEBOUNCE)
+            .shutdown_token(shutdown.child()),
+        |builder, path| builder.watch(path),
+    );
+    let monitor = Arc::new(builder.build());
+    let mut events = monitor.subscribe();
+    let watcher = Arc::clone(&monitor);
+    tokio::spawn(async move {
+        if let Err(e) = watcher.monitor().await {
+            println!("EDR file watcher stopped: {}", e);
+        }
+    });
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = shutdown.cancelled() => break,
+        };
+        match event {
+            Some(MonitorEvent::File(record)) => {
+                if matches!(
+                    record.event,
+                    FileEvent::Created | FileEvent::Modified | FileEvent::Replaced(_)
+                ) {
+                    let reason = format!("file {}", record.event.kind());
+                    dispatcher
+                        .edr_scan(&record.path, &reason, FILE_MONITOR_TRIGGER)
+                        .await;
+                }
+            }
+            Some(MonitorEvent::Alert {
+                rule,
+                reason,
+                record,
+            }) => {
+                let trigger = format!("{}:{}", FILE_MONITOR_TRIGGER, rule);
+                dispatcher.edr_scan(&record.path, &reason, &trigger).await;
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+async fn scan_with_command(
+    program: &str,
+    args: &[String],
+    detected_exit_codes: &[i32],
+    path: &Path,
+    reason: &str,
+) -> Result<EdrOutcome> {
+    let path = path.to_string_lossy();
+    let output = AsyncCommand::new(program)
+        .args(
+            args.iter()
+                .map(|arg| substitute(arg, &[("{path}", &path), ("{reason}", reason)])),
+        )
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed to run {}: {}", program, e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let detail = truncate(if stdout.trim().is_empty() {
+        stderr.trim()
+    } else {
+        stdout.trim()
+    });
+    let verdict = match output.status.code() {
+        Some(0) => EdrVerdict::Clean,
+        Some(code) if detected_exit_codes.contains(&code) => EdrVerdict::Detected,
+        _ => {
+            return Ok(EdrOutcome {
+                verdict: EdrVerdict::Failed,
+                detail: format!("{} exited with {}: {}", program, output.status, detail),
+            })
+        }
+    };
+    Ok(EdrOutcome { verdict, detail })
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_DETAIL_LENGTH) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,162 @@
+use crate::platform;
+use crate::probe::{default_probes, PostureProbe};
+use crate::user_session::{console_user, list_user_sessions};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tokio::process::Command as AsyncCommand;
+
+/// Commands whose effect on the system is measured.
+pub const POSTURE_CHANGING_COMMANDS: &[&str] = &[
+    "ALLOW_NETWORK",
+    "BLOCK_NETWORK",
+    "LOCK_USB",
+    "UNLOCK_USB",
+    "LOCK_SCREEN",
+];
+
+/// One value observed on the live system, e.g. whether USB storage is disabled.
+#[async_trait]
+pub trait Measurement: Send + Sync {
+    fn name(&self) -> &str;
+    /// Returns `null` if the value cannot be determined on this platform.
+    async fn measure(&self) -> Result<Value>;
+}
+
+/// Measures the state a posture probe observes.
+pub struct ProbeMeasurement(pub Box<dyn PostureProbe>);
+
+#[async_trait]
+impl Measurement for ProbeMeasurement {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    async fn measure(&self) -> Result<Value> {
+        Ok(self.0.observe().await?.map_or(Value::Null, Value::Bool))
+    }
+}
+
+/// Counts the output lines of a command that start with a prefix, e.g. firewall rules.
+pub struct LineCountMeasurement {
+    pub name: String,
+    pub program: String,
+    pub args: Vec<String>,
+    pub line_prefix: String,
+}
+
+#[async_trait]
+impl Measurement for LineCountMeasurement {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn measure(&self) -> Result<Value> {
+        let output = AsyncCommand::new(&self.program)
+            .args(&self.args)
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let count = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.trim_start().starts_with(self.line_prefix.as_str()))
+            .count();
+        Ok(Value::from(count))
+    }
+}
+
+/// Whether the active console session is locked.
+pub struct ScreenLockMeasurement;
+
+#[async_trait]
+impl Measurement for ScreenLockMeasurement {
+    fn name(&self) -> &str {
+        "screen_locked"
+    }
+
+    async fn measure(&self) -> Result<Value> {
+        let sessions = list_user_sessions().await?;
+        Ok(console_user(&sessions).map_or(Value::Null, |session| Value::Bool(session.locked)))
+    }
+}
+
+/// Built-in measurements: the default posture probes, the firewall rule count and the
+/// console screen lock state.
+pub fn default_measurements() -> Vec<Box<dyn Measurement>> {
+    let mut measurements: Vec<Box<dyn Measurement>> = default_probes()
+        .into_iter()
+        .map(|probe| Box::new(ProbeMeasurement(probe)) as Box<dyn Measurement>)
+        .collect();
+    let (command, line_prefix) = platform::current().firewall_rules_command();
+    let firewall_rules = LineCountMeasurement {
+        name: "firewall_rule_count".to_string(),
+        program: command.program,
+        args: command.args,
+        line_prefix: line_prefix.to_string(),
+    };
+    measurements.push(Box::new(firewall_rules));
+    measurements.push(Box::new(ScreenLockMeasurement));
+    measurements
+}
+
+/// Measured values by name. Values that could not be measured are `null`.
+pub type Snapshot = BTreeMap<String, Value>;
+
+/// System state before and after a command, and which measurements it changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectDelta {
+    pub before: Snapshot,
+    pub after: Snapshot,
+    pub changed: Vec<String>,
+}
+
+impl EffectDelta {
+    pub fn new(before: Snapshot, after: Snapshot) -> Self {
+        let changed = after
+            .iter()
+            .filter(|(name, value)| !value.is_null() && before.get(*name) != Some(*value))
+            .map(|(name, _)| name.clone())
+            .collect();
+        Self {
+            before,
+            after,
+            changed,
+        }
+    }
+}
+
+/// Takes snapshots around posture-changing commands, so the audit trail shows whether a
+/// command actually changed the system rather than just that its script exited 0.
+pub struct EffectMeter {
+    measurements: Vec<Box<dyn Measurement>>,
+}
+
+impl EffectMeter {
+    pub fn new(measurements: Vec<Box<dyn Measurement>>) -> Self {
+        Self { measurements }
+    }
+
+    pub fn measures(&self, command: &str) -> bool {
+        POSTURE_CHANGING_COMMANDS.contains(&command)
+    }
+
+    pub async fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for measurement in &self.measurements {
+            let value = measurement.measure().await.unwrap_or_else(|e| {
+                println!("Measurement {} failed: {}", measurement.name(), e);
+                Value::Null
+            });
+            snapshot.insert(measurement.name().to_string(), value);
+        }
+        snapshot
+    }
+}
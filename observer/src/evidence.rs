@@ -0,0 +1,271 @@
+use crate::connector::enrollment::to_hex;
+use crate::connector::usb_key::UsbKey;
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Directory on the key that receives uploaded evidence.
+pub const EVIDENCE_DIR: &str = "EVIDENCE";
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// Result payloads larger than this are uploaded to the key instead of written inline.
+pub const DEFAULT_UPLOAD_THRESHOLD: usize = 64 * 1024;
+const ENCRYPTION_INFO: &[u8] = b"guardian/evidence/encryption";
+const MAC_INFO: &[u8] = b"guardian/evidence/mac";
+
+/// Progress record stored next to the chunks so an interrupted upload can resume.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadManifest {
+    pub upload_id: String,
+    pub label: String,
+    pub total_size: usize,
+    pub chunk_size: usize,
+    pub chunk_count: usize,
+    /// HMAC-SHA256 of the plaintext payload under a key derived from the evidence key, for
+    /// end-to-end verification. Unlike a plain hash, it does not let whoever holds the USB
+    /// key confirm a guessed payload.
+    pub payload_mac: String,
+    pub completed_chunks: Vec<usize>,
+    /// HMAC-SHA256 of the other fields, so the manifest cannot be altered on the key.
+    #[serde(default)]
+    pub mac: String,
+}
+
+impl UploadManifest {
+    pub fn is_complete(&self) -> bool {
+        self.completed_chunks.len() == self.chunk_count
+    }
+}
+
+/// Splits a payload into chunks, encrypts each with ChaCha20-Poly1305 and writes them to
+/// the key, recording progress in an authenticated manifest after every chunk.
+pub struct EvidenceUploader {
+    cipher: ChaCha20Poly1305,
+    mac_key: [u8; 32],
+    chunk_size: usize,
+    threshold: usize,
+}
+
+impl EvidenceUploader {
+    /// Derives separate encryption and MAC keys from the evidence key.
+    pub fn new(key: [u8; 32]) -> Self {
+        let hkdf = Hkdf::<Sha256>::from_prk(&key).expect("32 bytes is a valid HKDF PRK");
+        let mut encryption_key = [0u8; 32];
+        let mut mac_key = [0u8; 32];
+        hkdf.expand(ENCRYPTION_INFO, &mut encryption_key)
+            .and_then(|()| hkdf.expand(MAC_INFO, &mut mac_key))
+            .expect("32 bytes is a valid HKDF output length");
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&encryption_key)),
+            mac_key,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            threshold: DEFAULT_UPLOAD_THRESHOLD,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn should_upload(&self, payload_len: usize) -> bool {
+        payload_len > self.threshold
+    }
+
+    /// Uploads the payload, skipping chunks a previous attempt already wrote. The upload ID
+    /// is a MAC of the payload, so re-running the same upload resumes it.
+    pub async fn upload(
+        &self,
+        usb_key: &UsbKey,
+        label: &str,
+        payload: &[u8],
+    ) -> Result<UploadManifest> {
+        let payload_mac = to_hex(&self.tag(b"payload", payload));
+        let upload_id = to_hex(&self.tag(b"upload-id", payload)[..16]);
+        let chunk_count = payload.len().div_ceil(self.chunk_size).max(1);
+
+        let existing = self
+            .load_manifest(usb_key, &upload_id)
+            .await
+            .unwrap_or_else(|e| {
+                println!("Restarting evidence upload {}: {}", upload_id, e);
+                None
+            });
+        let mut manifest = match existing {
+            Some(existing)
+                if existing.payload_mac == payload_mac
+                    && existing.chunk_size == self.chunk_size =>
+            {
+                existing
+            }
+            _ => UploadManifest {
+                upload_id: upload_id.clone(),
+                label: label.to_string(),
+                total_size: payload.len(),
+                chunk_size: self.chunk_size,
+                chunk_count,
+                payload_mac,
+                completed_chunks: Vec::new(),
+                mac: String::new(),
+            },
+        };
+
+        for index in 0..chunk_count {
+            if manifest.completed_chunks.contains(&index) {
+                continue;
+            }
+            let start = index * self.chunk_size;
+            let end = (start + self.chunk_size).min(payload.len());
+            let sealed = self.seal(&upload_id, index, &payload[start..end])?;
+            usb_key
+                .write_verified(&chunk_file_name(&upload_id, index), &sealed)
+                .await?;
+            manifest.completed_chunks.push(index);
+            manifest.mac = self.manifest_mac(&manifest)?;
+            self.save_manifest(usb_key, &manifest).await?;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Decrypts an uploaded payload. Used by key-side tooling and tests.
+    pub async fn download(&self, usb_key: &UsbKey, upload_id: &str) -> Result<Vec<u8>> {
+        let manifest = self
+            .load_manifest(usb_key, upload_id)
+            .await?
+            .ok_or_else(|| anyhow!("No manifest for upload {}", upload_id))?;
+        if !manifest.is_complete() {
+            return Err(anyhow!("Upload {} is incomplete", upload_id));
+        }
+
+        let mut payload = Vec::with_capacity(manifest.total_size);
+        for index in 0..manifest.chunk_count {
+            let sealed = usb_key
+                .read_file(&chunk_file_name(upload_id, index))
+                .await?
+                .ok_or_else(|| anyhow!("Missing chunk {} of upload {}", index, upload_id))?;
+            payload.extend(self.open(upload_id, index, &sealed)?);
+        }
+
+        if to_hex(&self.tag(b"payload", &payload)) != manifest.payload_mac {
+            return Err(anyhow!("Upload {} failed integrity check", upload_id));
+        }
+        Ok(payload)
+    }
+
+    /// HMAC-SHA256 of `data` under the MAC key, separated by `domain`.
+    fn tag(&self, domain: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.mac_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(domain);
+        mac.update(&[0]);
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    fn manifest_mac(&self, manifest: &UploadManifest) -> Result<String> {
+        let unsigned = UploadManifest {
+            mac: String::new(),
+            ..manifest.clone()
+        };
+        Ok(to_hex(
+            &self.tag(b"manifest", &serde_json::to_vec(&unsigned)?),
+        ))
+    }
+
+    /// Chunks are bound to their upload and position, so they cannot be swapped or
+    /// replayed into another upload.
+    fn associated_data(upload_id: &str, index: usize) -> Vec<u8> {
+        let mut aad = upload_id.as_bytes().to_vec();
+        aad.extend_from_slice(&(index as u64).to_be_bytes());
+        aad
+    }
+
+    fn seal(&self, upload_id: &str, index: usize, chunk: &[u8]) -> Result<Vec<u8>> {
+        // A fresh random nonce per chunk, stored in front of the ciphertext.
+        let mut nonce = [0u8; 12];
+        getrandom::getrandom(&mut nonce).map_err(|e| {
+            anyhow!(
+                "Failed to generate nonce for evidence chunk {}: {}",
+                index,
+                e
+            )
+        })?;
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &Self::associated_data(upload_id, index),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt evidence chunk {}", index))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, upload_id: &str, index: usize, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < 12 {
+            return Err(anyhow!("Evidence chunk {} too short", index));
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &Self::associated_data(upload_id, index),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt evidence chunk {}", index))
+    }
+
+    /// The manifest of `upload_id`, or `None` if there is none. A manifest that does not
+    /// carry a valid MAC for this upload is an error.
+    async fn load_manifest(
+        &self,
+        usb_key: &UsbKey,
+        upload_id: &str,
+    ) -> Result<Option<UploadManifest>> {
+        let Ok(Some(data)) = usb_key.read_file(&manifest_file_name(upload_id)).await else {
+            return Ok(None);
+        };
+        let manifest: UploadManifest = serde_json::from_slice(&data)
+            .map_err(|e| anyhow!("Unreadable manifest for upload {}: {}", upload_id, e))?;
+        if manifest.upload_id != upload_id || self.manifest_mac(&manifest)? != manifest.mac {
+            return Err(anyhow!(
+                "Manifest for upload {} failed authentication",
+                upload_id
+            ));
+        }
+        Ok(Some(manifest))
+    }
+
+    async fn save_manifest(&self, usb_key: &UsbKey, manifest: &UploadManifest) -> Result<()> {
+        usb_key
+            .write_verified(
+                &manifest_file_name(&manifest.upload_id),
+                &serde_json::to_vec_pretty(manifest)?,
+            )
+            .await
+    }
+}
+
+fn manifest_file_name(upload_id: &str) -> String {
+    format!("{}/{}/manifest.json", EVIDENCE_DIR, upload_id)
+}
+
+fn chunk_file_name(upload_id: &str, index: usize) -> String {
+    format!("{}/{}/chunk-{:05}.bin", EVIDENCE_DIR, upload_id, index)
+}
@@ -0,0 +1,227 @@
+use crate::native::NativeCommands;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use file_monitor_core::{FileMonitor, MonitorEvent, ShutdownToken};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Command of a forensics key that returns the host to normal operation.
+pub const END_FORENSICS: &str = "END_FORENSICS";
+/// Audit trigger of the records of forensic mode starting and ending.
+pub const FORENSICS_TRIGGER: &str = "FORENSICS";
+
+/// Commands that only collect evidence, the only ones run in forensic mode.
+pub const EVIDENCE_COMMANDS: &[&str] = &[
+    "COLLECT_EVIDENCE",
+    "CHECK_STATUS",
+    "VERIFY_POSTURE",
+    "LIST_COMMANDS",
+    "DIAGNOSE_KEY",
+    END_FORENSICS,
+];
+
+/// What forensic mode watches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForensicConfig {
+    /// Watched with every file monitor enrichment while forensic mode is on.
+    #[serde(default)]
+    pub watch_paths: Vec<PathBuf>,
+    /// Receives every event recorded by that monitor as a JSON line.
+    #[serde(default = "default_event_log")]
+    pub event_log: PathBuf,
+}
+
+fn default_event_log() -> PathBuf {
+    PathBuf::from("./guardian-forensics-events.jsonl")
+}
+
+impl Default for ForensicConfig {
+    fn default() -> Self {
+        Self {
+            watch_paths: Vec::new(),
+            event_log: default_event_log(),
+        }
+    }
+}
+
+impl ForensicConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// Since when, and by which key, the host is in forensic mode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForensicState {
+    pub started_at: DateTime<Local>,
+    pub device_fingerprint: Option<String>,
+}
+
+/// Preservation mode for investigations, switched on by inserting a key with the
+/// [`crate::connector::KeyRole::Forensics`] role: posture changes are frozen, only
+/// [`EVIDENCE_COMMANDS`] run, and the configured paths are watched at high fidelity.
+/// The state is kept in a file so a restart does not end it; only a forensics key
+/// running [`END_FORENSICS`] does.
+pub struct ForensicMode {
+    config: ForensicConfig,
+    state_path: PathBuf,
+    state: Mutex<Option<ForensicState>>,
+    monitor: Mutex<Option<(Arc<FileMonitor>, JoinHandle<()>)>>,
+    shutdown: ShutdownToken,
+}
+
+impl ForensicMode {
+    /// Loads the state left in `state_path`; call [`ForensicMode::resume`] to restart
+    /// monitoring if the host was in forensic mode.
+    pub fn open<P: AsRef<Path>>(
+        config: ForensicConfig,
+        state_path: P,
+        shutdown: ShutdownToken,
+    ) -> Result<Self> {
+        let state_path = state_path.as_ref().to_path_buf();
+        let state = match std::fs::read(&state_path) {
+            Ok(data) => Some(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            config,
+            state_path,
+            state: Mutex::new(state),
+            monitor: Mutex::new(None),
+            shutdown,
+        })
+    }
+
+    /// Whether `command` runs in forensic mode: evidence commands and read-only
+    /// [`crate::native::NativeCommand`]s.
+    pub fn allows(command: &str, native: &NativeCommands) -> bool {
+        EVIDENCE_COMMANDS.contains(&command) || native.is_read_only(command)
+    }
+
+    pub async fn state(&self) -> Option<ForensicState> {
+        self.state.lock().await.clone()
+    }
+
+    pub async fn is_active(&self) -> bool {
+        self.state.lock().await.is_some()
+    }
+
+    /// Restarts monitoring after guardian restarted in forensic mode.
+    pub async fn resume(&self) {
+        if self.is_active().await {
+            println!("Host is in forensic mode: posture changes are frozen");
+            self.start_monitor().await;
+        }
+    }
+
+    /// Enters forensic mode for the key with `device_fingerprint`. Returns `false` if the
+    /// host already was in it.
+    pub async fn activate(&self, device_fingerprint: Option<&str>) -> Result<bool> {
+        let mut state = self.state.lock().await;
+        if state.is_some() {
+            return Ok(false);
+        }
+        let started = ForensicState {
+            started_at: Local::now(),
+            device_fingerprint: device_fingerprint.map(str::to_string),
+        };
+        tokio::fs::write(&self.state_path, serde_json::to_vec(&started)?).await?;
+        *state = Some(started);
+        drop(state);
+        self.start_monitor().await;
+        Ok(true)
+    }
+
+    /// Leaves forensic mode. Returns the state it was entered with, or `None` if the host
+    /// was not in it.
+    pub async fn deactivate(&self) -> Result<Option<ForensicState>> {
+        let mut state = self.state.lock().await;
+        if state.is_none() {
+            return Ok(None);
+        }
+        if let Err(e) = tokio::fs::remove_file(&self.state_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        let ended = state.take();
+        drop(state);
+        if let Some((monitor, task)) = self.monitor.lock().await.take() {
+            monitor.shutdown().await;
+            let _ = task.await;
+        }
+        Ok(ended)
+    }
+
+    /// The high-fidelity monitor, while forensic mode is on and paths are configured.
+    pub async fn monitor(&self) -> Option<Arc<FileMonitor>> {
+        self.monitor
+            .lock()
+            .await
+            .as_ref()
+            .map(|(monitor, _)| Arc::clone(monitor))
+    }
+
+    async fn start_monitor(&self) {
+        let mut running = self.monitor.lock().await;
+        let Some((first, rest)) = self.config.watch_paths.split_first() else {
+            return;
+        };
+        if running.is_some() {
+            return;
+        }
+        let builder = rest.iter().fold(
+            FileMonitor::builder(first)
+                .shutdown_token(self.shutdown.child())
+                .full_enrichment(),
+            |builder, path| builder.watch(path),
+        );
+        let monitor = Arc::new(builder.build());
+        let mut events = monitor.subscribe();
+        let event_log = self.config.event_log.clone();
+        let watcher = Arc::clone(&monitor);
+        let task = tokio::spawn(async move {
+            let monitoring = watcher.monitor();
+            tokio::pin!(monitoring);
+            loop {
+                tokio::select! {
+                    result = &mut monitoring => {
+                        if let Err(e) = result {
+                            println!("Forensic monitor stopped: {}", e);
+                        }
+                        break;
+                    }
+                    Some(event) = events.recv() => {
+                        if let MonitorEvent::File(record) = event {
+                            if let Err(e) = append_event(&event_log, &record).await {
+                                println!("Failed to record forensic event: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        println!(
+            "Forensic monitoring of {} path(s) started",
+            self.config.watch_paths.len()
+        );
+        *running = Some((monitor, task));
+    }
+}
+
+async fn append_event<T: Serialize>(path: &Path, record: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
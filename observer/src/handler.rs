@@ -1,98 +1,227 @@
-use anyhow::{anyhow, Result};
-use tokio::process::Command as AsyncCommand;
+use crate::native::{NativeCommand, NativeCommands};
+use crate::platform::{self, Platform};
+use crate::result::{CommandResult, ResultCode};
+use crate::user_session::console_user;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Argument accepted by a command, described for key-side tooling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArgumentSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub required: bool,
+    pub description: String,
+}
+
+/// Description of a command verb, returned by LIST_COMMANDS.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandSpec {
+    pub name: String,
+    pub description: String,
+    pub arguments: Vec<ArgumentSpec>,
+    /// Changes host state in a way that disrupts the user.
+    pub destructive: bool,
+    /// Key-side tooling should ask the operator to confirm before sending.
+    pub requires_confirmation: bool,
+}
+
+impl CommandSpec {
+    pub fn new(name: &str, description: &str, destructive: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            arguments: Vec::new(),
+            destructive,
+            requires_confirmation: destructive,
+        }
+    }
+}
+
+/// Every command guardian understands, the built-in ones followed by `native`.
+pub fn command_catalog(native: &NativeCommands) -> Vec<CommandSpec> {
+    let mut catalog = builtin_catalog();
+    catalog.extend(native.specs());
+    catalog
+}
+
+pub(crate) fn builtin_catalog() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec::new("ALLOW_NETWORK", "Restore network connectivity", false),
+        CommandSpec::new("BLOCK_NETWORK", "Block all network traffic", true),
+        CommandSpec::new("LOCK_SCREEN", "Lock the interactive session", true),
+        CommandSpec::new("LOCK_USB", "Disable USB mass storage", true),
+        CommandSpec::new("UNLOCK_USB", "Re-enable USB mass storage", false),
+        CommandSpec::new(
+            "COLLECT_EVIDENCE",
+            "Run live-response triage and upload the results to the key",
+            false,
+        ),
+        CommandSpec::new("CHECK_STATUS", "Report processes and user sessions", false),
+        CommandSpec::new(
+            "VERIFY_POSTURE",
+            "Check that the system matches the tracked posture",
+            false,
+        ),
+        CommandSpec::new("LIST_COMMANDS", "List the commands this key may run", false),
+        CommandSpec::new(
+            "END_FORENSICS",
+            "Leave forensic mode; forensics keys only",
+            false,
+        ),
+        CommandSpec {
+            arguments: vec![ArgumentSpec {
+                name: "playbook".to_string(),
+                kind: "string".to_string(),
+                required: true,
+                description: "Name of a playbook configured on the host".to_string(),
+            }],
+            ..CommandSpec::new("RUN_PLAYBOOK", "Run a configured response playbook", true)
+        },
+    ]
+}
 
 pub struct CommandHandler {
     script_directory: String,
+    platform: Arc<dyn Platform>,
+    native: NativeCommands,
 }
 
 impl CommandHandler {
     pub fn new(script_directory: String) -> Self {
-        Self { script_directory }
+        Self {
+            script_directory,
+            platform: platform::current(),
+            native: NativeCommands::default(),
+        }
+    }
+
+    /// Uses `platform` instead of the one guardian was built for.
+    pub fn with_platform(mut self, platform: Arc<dyn Platform>) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Runs `command` for its verb, which must not be taken yet.
+    pub fn with_native_command(mut self, command: Arc<dyn NativeCommand>) -> anyhow::Result<Self> {
+        self.native.add(command)?;
+        Ok(self)
+    }
+
+    pub fn native_commands(&self) -> &NativeCommands {
+        &self.native
     }
 
-    pub async fn handle_command(&self, command: &str) -> Result<String> {
+    pub async fn handle_command(&self, command: &str) -> CommandResult {
         match command {
             "ALLOW_NETWORK" => self.run_script("AllowNetwork").await,
             "BLOCK_NETWORK" => self.run_script("BlockNetwork").await,
             "LOCK_SCREEN" => self.run_script("LockScreen").await,
             "LOCK_USB" => self.run_script("LockUSB").await,
             "UNLOCK_USB" => self.run_script("UnlockUSB").await,
+            "COLLECT_EVIDENCE" => self.run_script(self.platform.evidence_script()).await,
 
             "CHECK_STATUS" => self.check_status().await,
-            _ => Err(anyhow!("Unknown command: {}", command)),
+            _ => match self.native.run(command).await {
+                Some(result) => result,
+                None => CommandResult::error(
+                    ResultCode::UnknownCommand,
+                    format!("Unknown command: {}", command),
+                ),
+            },
         }
     }
 
-    async fn run_script(&self, script_name: &str) -> Result<String> {
-        let (script_path, shell_command) = if cfg!(target_os = "windows") {
-            (
-                format!("{}\\{}.bat", self.script_directory, script_name),
-                "cmd",
-            )
-        } else {
-            (
-                format!("{}/{}.sh", self.script_directory, script_name),
-                "bash",
-            )
-        };
-
-        let mut command = AsyncCommand::new(shell_command);
+    fn script_path(&self, script_name: &str) -> String {
+        self.platform
+            .script_path(&self.script_directory, script_name)
+    }
 
-        #[cfg(target_os = "windows")]
-        {
-            command.creation_flags(0x08000000);
-            command.arg("/C");
+    async fn run_script(&self, script_name: &str) -> CommandResult {
+        let script_path = self.script_path(script_name);
+        if !self.is_script_exists(script_name) {
+            return CommandResult::new(
+                ResultCode::ScriptNotFound,
+                format!("Script not found: {}", script_name),
+                json!({ "script": script_name, "path": script_path }),
+            );
         }
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            command.arg("-c");
-        }
+        let mut command = self.platform.script_command(&script_path).to_command();
 
-        command.arg(&script_path);
+        let output = match command.output().await {
+            Ok(output) => output,
+            Err(e) => {
+                return CommandResult::error(
+                    ResultCode::InternalError,
+                    format!("Failed to start script {}: {}", script_name, e),
+                )
+            }
+        };
 
-        let output = command.output().await?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let data = json!({
+            "script": script_name,
+            "exit_code": output.status.code(),
+            "stdout": stdout,
+            "stderr": stderr,
+        });
 
         if output.status.success() {
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+            CommandResult::ok(stdout.trim(), data)
         } else {
-            Err(anyhow!(
-                "Script execution failed: {}\nError: {}",
-                script_name,
-                String::from_utf8_lossy(&output.stderr)
-            ))
+            CommandResult::new(
+                ResultCode::ScriptFailed,
+                format!(
+                    "Script execution failed: {}\nError: {}",
+                    script_name,
+                    stderr.trim()
+                ),
+                data,
+            )
         }
     }
 
-    async fn check_status(&self) -> Result<String> {
-        let mut command = if cfg!(target_os = "windows") {
-            AsyncCommand::new("tasklist")
-        } else {
-            AsyncCommand::new("ps")
+    async fn check_status(&self) -> CommandResult {
+        let mut command = self.platform.process_list_command().to_command();
+
+        let output = match command.output().await {
+            Ok(output) => output,
+            Err(e) => {
+                return CommandResult::error(
+                    ResultCode::InternalError,
+                    format!("Failed to run status check: {}", e),
+                )
+            }
         };
 
-        if !cfg!(target_os = "windows") {
-            command.arg("aux");
-        }
-
-        let output = command.output().await?;
-
         if output.status.success() {
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+            let processes = String::from_utf8_lossy(&output.stdout).to_string();
+            let user_sessions = self.platform.list_sessions().await.unwrap_or_default();
+            let console = console_user(&user_sessions).cloned();
+            CommandResult::ok(
+                "Status collected",
+                json!({
+                    "processes": processes,
+                    "user_sessions": user_sessions,
+                    "console_user": console,
+                }),
+            )
         } else {
-            Err(anyhow!(
-                "Status check failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ))
+            CommandResult::error(
+                ResultCode::StatusCheckFailed,
+                format!(
+                    "Status check failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            )
         }
     }
 
     pub fn is_script_exists(&self, script_name: &str) -> bool {
-        let script_path = if cfg!(target_os = "windows") {
-            format!("{}\\{}.bat", self.script_directory, script_name)
-        } else {
-            format!("{}/{}.sh", self.script_directory, script_name)
-        };
-        std::path::Path::new(&script_path).exists()
+        std::path::Path::new(&self.script_path(script_name)).exists()
     }
 }
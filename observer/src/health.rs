@@ -0,0 +1,226 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// What is wrong with the guardian, for orchestration tooling. Ordered by severity: the
+/// guardian reports the most severe condition currently raised.
+///
+/// | State                | Exit code | Meaning                                          |
+/// |----------------------|-----------|--------------------------------------------------|
+/// | `ok`                 | 0         | Running normally, or stopped cleanly             |
+/// | `degraded_no_device` | 69        | The device manager cannot deliver a key          |
+/// | `auth_lockout`       | 77        | Keys keep failing authentication                 |
+/// | `policy_error`       | 78        | A policy or configuration file is invalid        |
+/// | `storage_failure`    | 74        | The audit log, outbox or registry is unwritable  |
+///
+/// Any other fatal error exits with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Ok,
+    DegradedNoDevice,
+    AuthLockout,
+    PolicyError,
+    StorageFailure,
+}
+
+/// Exit code of fatal errors that carry no [`HealthState`].
+pub const EXIT_FAILURE: u8 = 1;
+
+impl HealthState {
+    pub const ALL: [HealthState; 5] = [
+        HealthState::Ok,
+        HealthState::DegradedNoDevice,
+        HealthState::AuthLockout,
+        HealthState::PolicyError,
+        HealthState::StorageFailure,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthState::Ok => "ok",
+            HealthState::DegradedNoDevice => "degraded_no_device",
+            HealthState::AuthLockout => "auth_lockout",
+            HealthState::PolicyError => "policy_error",
+            HealthState::StorageFailure => "storage_failure",
+        }
+    }
+
+    /// Process exit code when the guardian stops in this state, following sysexits.h.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            HealthState::Ok => 0,
+            HealthState::DegradedNoDevice => 69,
+            HealthState::AuthLockout => 77,
+            HealthState::PolicyError => 78,
+            HealthState::StorageFailure => 74,
+        }
+    }
+
+    /// Exit code of a fatal error: that of the state attached to it with
+    /// `.context(state)`, or [`EXIT_FAILURE`].
+    pub fn exit_code_of(error: &anyhow::Error) -> u8 {
+        error
+            .downcast_ref::<HealthState>()
+            .map_or(EXIT_FAILURE, HealthState::exit_code)
+    }
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A raised condition, with what caused it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthCondition {
+    pub state: HealthState,
+    pub detail: String,
+    pub since: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub state: HealthState,
+    pub exit_code: u8,
+    pub conditions: Vec<HealthCondition>,
+}
+
+impl HealthReport {
+    /// The report in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let mut metrics = String::from(
+            "# HELP guardian_health Whether the guardian is in the state.\n\
+             # TYPE guardian_health gauge\n",
+        );
+        for state in HealthState::ALL {
+            metrics.push_str(&format!(
+                "guardian_health{{state=\"{}\"}} {}\n",
+                state,
+                u8::from(state == self.state)
+            ));
+        }
+        metrics.push_str(&format!(
+            "# HELP guardian_health_conditions Conditions currently raised.\n\
+             # TYPE guardian_health_conditions gauge\n\
+             guardian_health_conditions {}\n",
+            self.conditions.len()
+        ));
+        metrics
+    }
+
+    /// One line for the service manager's status field.
+    pub fn status_line(&self) -> String {
+        match self
+            .conditions
+            .iter()
+            .find(|condition| condition.state == self.state)
+        {
+            Some(condition) => format!("{}: {}", self.state, condition.detail),
+            None => self.state.to_string(),
+        }
+    }
+}
+
+/// Conditions currently raised across the guardian's tasks. Clones share the conditions.
+/// Changes of the overall state are passed on to the service manager, see
+/// [`notify_service_status`].
+#[derive(Clone, Default)]
+pub struct Health {
+    conditions: Arc<Mutex<BTreeMap<HealthState, HealthCondition>>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raises `state`, or updates its detail if already raised. Raising `Ok` does nothing.
+    pub fn raise(&self, state: HealthState, detail: impl Into<String>) {
+        if state == HealthState::Ok {
+            return;
+        }
+        let detail = detail.into();
+        self.update(|conditions| {
+            conditions
+                .entry(state)
+                .and_modify(|condition| condition.detail = detail.clone())
+                .or_insert_with(|| HealthCondition {
+                    state,
+                    detail,
+                    since: Local::now(),
+                });
+        });
+    }
+
+    pub fn clear(&self, state: HealthState) {
+        self.update(|conditions| {
+            conditions.remove(&state);
+        });
+    }
+
+    pub fn state(&self) -> HealthState {
+        state_of(&self.conditions.lock().unwrap())
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let conditions = self.conditions.lock().unwrap();
+        let state = state_of(&conditions);
+        HealthReport {
+            state,
+            exit_code: state.exit_code(),
+            conditions: conditions.values().rev().cloned().collect(),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BTreeMap<HealthState, HealthCondition>)) {
+        let mut conditions = self.conditions.lock().unwrap();
+        let before = state_of(&conditions);
+        change(&mut conditions);
+        let after = state_of(&conditions);
+        drop(conditions);
+        if before != after {
+            println!("Health changed from {} to {}", before, after);
+            notify_service_status(&self.report());
+        }
+    }
+}
+
+fn state_of(conditions: &BTreeMap<HealthState, HealthCondition>) -> HealthState {
+    conditions
+        .keys()
+        .next_back()
+        .copied()
+        .unwrap_or(HealthState::Ok)
+}
+
+/// Sets the status shown by `systemctl status` when running under systemd with
+/// `Type=notify`; does nothing otherwise.
+#[cfg(target_os = "linux")]
+pub fn notify_service_status(report: &HealthReport) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let socket_path = socket_path.to_string_lossy().into_owned();
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&socket_path),
+    };
+    let sent = address.and_then(|address| {
+        let message = format!("STATUS={}\n", report.status_line());
+        UnixDatagram::unbound()?.send_to_addr(message.as_bytes(), &address)
+    });
+    if let Err(e) = sent {
+        println!("Failed to notify service manager: {}", e);
+    }
+}
+
+/// Sets the status shown by the service manager; only systemd is supported.
+#[cfg(not(target_os = "linux"))]
+pub fn notify_service_status(_report: &HealthReport) {}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Runs `run` after `after` completes, e.g. collecting evidence after blocking the network
+/// or re-locking USB storage 30 minutes after it was unlocked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostCommandHook {
+    pub after: String,
+    pub run: String,
+    /// Delay before `run` is executed. Zero runs it right after `after`.
+    #[serde(default)]
+    pub delay_secs: u64,
+    /// Runs `run` early if guardian shuts down before the delay passes, so a time-boxed
+    /// posture such as unlocked USB storage is reverted rather than left in place.
+    #[serde(default)]
+    pub run_on_shutdown: bool,
+}
+
+impl PostCommandHook {
+    /// Audit trigger of commands run by this hook.
+    pub fn trigger(&self) -> String {
+        format!("HOOK:{}", self.after)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostCommandHooks {
+    #[serde(default)]
+    pub hooks: Vec<PostCommandHook>,
+}
+
+impl PostCommandHooks {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn after<'a>(&'a self, command: &'a str) -> impl Iterator<Item = &'a PostCommandHook> {
+        self.hooks.iter().filter(move |hook| hook.after == command)
+    }
+}
+
+/// A hook command waiting for its delay to pass.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScheduledCommand {
+    pub command: String,
+    pub trigger: String,
+    pub due: DateTime<Local>,
+    pub run_on_shutdown: bool,
+}
+
+impl ScheduledCommand {
+    pub fn from_hook(hook: &PostCommandHook, now: DateTime<Local>) -> Self {
+        Self {
+            command: hook.run.clone(),
+            trigger: hook.trigger(),
+            due: now + Duration::seconds(hook.delay_secs as i64),
+            run_on_shutdown: hook.run_on_shutdown,
+        }
+    }
+}
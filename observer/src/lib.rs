@@ -1,4 +1,30 @@
+pub mod action_lock;
+pub mod approval;
+pub mod audit;
+pub mod audit_forward;
+pub mod batch;
+pub mod command_drop;
 pub mod connector;
+pub mod device_registry;
+pub mod dispatcher;
+pub mod edr;
+pub mod effect;
+pub mod evidence;
+pub mod forensics;
 pub mod handler;
+pub mod health;
+pub mod hooks;
+pub mod native;
+pub mod network_env;
+pub mod outbox;
+pub mod platform;
+pub mod playbook;
+pub mod policy;
+pub mod probe;
+pub mod protocol;
+pub mod result;
+pub mod selftest;
+pub mod session;
+pub mod user_session;
 
 pub use connector::*;
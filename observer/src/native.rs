@@ -0,0 +1,73 @@
+use crate::handler::{builtin_catalog, CommandSpec};
+use crate::protocol::DISPATCHER_COMMANDS;
+use crate::result::CommandResult;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A command implemented in Rust by a crate embedding the observer library. Once added
+/// to a dispatcher with [`crate::dispatcher::CommandDispatcher::with_native_command`], its
+/// verb is parsed, permitted, approved, locked, audited and written back exactly like the
+/// built-in ones.
+#[async_trait]
+pub trait NativeCommand: Send + Sync {
+    /// The verb, its arguments and policy metadata, listed by LIST_COMMANDS and used to
+    /// validate command lines.
+    fn spec(&self) -> CommandSpec;
+
+    /// Whether the command only reads host state. Read-only commands also run in
+    /// observation and training mode and in forensic mode, like VERIFY_POSTURE.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Runs the command with the arguments validated against [`NativeCommand::spec`].
+    async fn run(&self, arguments: &[String]) -> CommandResult;
+}
+
+/// The native commands of one [`crate::handler::CommandHandler`], added with
+/// [`crate::dispatcher::CommandDispatcher::with_native_command`].
+#[derive(Clone, Default)]
+pub struct NativeCommands {
+    commands: Vec<Arc<dyn NativeCommand>>,
+}
+
+impl NativeCommands {
+    /// Adds `command`; a verb that is already taken, built-in or native, is refused.
+    pub fn add(&mut self, command: Arc<dyn NativeCommand>) -> Result<()> {
+        let verb = command.spec().name;
+        if builtin_catalog().iter().any(|spec| spec.name == verb)
+            || DISPATCHER_COMMANDS.contains(&verb.as_str())
+            || self.find(&verb).is_some()
+        {
+            bail!("Command {} is already defined", verb);
+        }
+        self.commands.push(command);
+        Ok(())
+    }
+
+    /// Specs of the commands, in the order they were added.
+    pub fn specs(&self) -> Vec<CommandSpec> {
+        self.commands.iter().map(|command| command.spec()).collect()
+    }
+
+    /// The command for the verb of `command`, a full command line.
+    pub fn find(&self, command: &str) -> Option<&Arc<dyn NativeCommand>> {
+        let verb = command.split(' ').next().unwrap_or_default();
+        self.commands
+            .iter()
+            .find(|native| native.spec().name == verb)
+    }
+
+    /// Whether `command` is a read-only native command.
+    pub fn is_read_only(&self, command: &str) -> bool {
+        self.find(command).is_some_and(|native| native.read_only())
+    }
+
+    /// Runs `command` if its verb is a native command.
+    pub async fn run(&self, command: &str) -> Option<CommandResult> {
+        let native = self.find(command)?;
+        let arguments: Vec<String> = command.split(' ').skip(1).map(str::to_string).collect();
+        Some(native.run(&arguments).await)
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use tokio::process::Command as AsyncCommand;
+
+/// Snapshot of the network the host is attached to, used by location-bound policies.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkEnvironment {
+    pub ssid: Option<String>,
+    pub gateway_ip: Option<Ipv4Addr>,
+    pub gateway_mac: Option<String>,
+    /// Host IPv4 addresses with their prefix length, e.g. `10.1.2.3/16`.
+    pub addresses: Vec<String>,
+}
+
+impl NetworkEnvironment {
+    /// Whether any host address lies inside `cidr` (e.g. `10.1.0.0/16`).
+    pub fn in_subnet(&self, cidr: &str) -> bool {
+        let Some((network, prefix)) = parse_cidr(cidr) else {
+            return false;
+        };
+        self.addresses
+            .iter()
+            .filter_map(|address| parse_cidr(address))
+            .any(|(address, _)| same_subnet(address, network, prefix))
+    }
+
+    pub async fn snapshot() -> Self {
+        if cfg!(target_os = "windows") {
+            snapshot_windows().await
+        } else {
+            snapshot_linux().await
+        }
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+    let (address, prefix) = cidr.split_once('/').unwrap_or((cidr, "32"));
+    let prefix: u8 = prefix.parse().ok()?;
+    (prefix <= 32).then_some((address.parse().ok()?, prefix))
+}
+
+fn same_subnet(address: Ipv4Addr, network: Ipv4Addr, prefix: u8) -> bool {
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - u32::from(prefix))
+    };
+    u32::from(address) & mask == u32::from(network) & mask
+}
+
+async fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = AsyncCommand::new(program).args(args).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+async fn snapshot_linux() -> NetworkEnvironment {
+    let gateway_ip = run("ip", &["-4", "route", "show", "default"])
+        .await
+        .and_then(|routes| parse_default_route(&routes));
+    let gateway_mac = match gateway_ip {
+        Some(gateway) => run("ip", &["neigh", "show", &gateway.to_string()])
+            .await
+            .and_then(|neighbours| parse_lladdr(&neighbours)),
+        None => None,
+    };
+    let ssid = run("iwgetid", &["-r"])
+        .await
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty());
+    let addresses = run("ip", &["-o", "-4", "addr", "show"])
+        .await
+        .map(|listing| parse_ip_addr(&listing))
+        .unwrap_or_default();
+
+    NetworkEnvironment {
+        ssid,
+        gateway_ip,
+        gateway_mac,
+        addresses,
+    }
+}
+
+async fn snapshot_windows() -> NetworkEnvironment {
+    let ssid = run("netsh", &["wlan", "show", "interfaces"])
+        .await
+        .and_then(|output| {
+            output.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "SSID").then(|| value.trim().to_string())
+            })
+        });
+    let gateway_ip: Option<Ipv4Addr> = run("ipconfig", &[]).await.and_then(|output| {
+        output.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim_start().starts_with("Default Gateway") {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+    });
+    let gateway_mac = match gateway_ip {
+        Some(gateway) => run("arp", &["-a", &gateway.to_string()])
+            .await
+            .and_then(|output| {
+                output.lines().find_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    (fields.next()? == gateway.to_string())
+                        .then(|| fields.next().map(|mac| mac.replace('-', ":")))
+                        .flatten()
+                })
+            }),
+        None => None,
+    };
+
+    NetworkEnvironment {
+        ssid,
+        gateway_ip,
+        gateway_mac,
+        addresses: Vec::new(),
+    }
+}
+
+/// Parses `ip -4 route show default`.
+pub fn parse_default_route(routes: &str) -> Option<Ipv4Addr> {
+    let mut fields = routes.split_whitespace();
+    fields.find(|field| *field == "via")?;
+    fields.next()?.parse().ok()
+}
+
+/// Parses the link-layer address out of `ip neigh show`.
+pub fn parse_lladdr(neighbours: &str) -> Option<String> {
+    let mut fields = neighbours.split_whitespace();
+    fields.find(|field| *field == "lladdr")?;
+    fields.next().map(|mac| mac.to_lowercase())
+}
+
+/// Parses `ip -o -4 addr show` into `address/prefix` entries.
+pub fn parse_ip_addr(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            fields.find(|field| *field == "inet")?;
+            fields.next().map(str::to_string)
+        })
+        .collect()
+}
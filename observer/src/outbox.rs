@@ -0,0 +1,138 @@
+use crate::audit::AuditRecord;
+use crate::result::CommandResult;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const OUTBOX_FILE_NAME: &str = "outbox.jsonl";
+
+/// An outcome that could not be delivered where it belongs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outgoing {
+    /// A result whose write-back to the key failed, e.g. because the key was removed.
+    /// Only written back to the key with the same fingerprint.
+    Result {
+        device_fingerprint: Option<String>,
+        result: CommandResult,
+    },
+    /// A record the audit log could not write, e.g. because the disk was full.
+    Audit { record: Box<AuditRecord> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub queued_at: DateTime<Local>,
+    #[serde(flatten)]
+    pub message: Outgoing,
+}
+
+/// On-disk spool of outcomes waiting for their sink to come back, so that a removed key
+/// or a full audit volume delays them instead of losing them. Entries are kept in order
+/// and survive restarts; each sink takes its own entries via [`Outbox::redeliver`].
+pub struct Outbox {
+    path: PathBuf,
+    entries: Mutex<Vec<OutboxEntry>>,
+}
+
+impl Outbox {
+    pub async fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(OUTBOX_FILE_NAME);
+        let entries = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| Ok(serde_json::from_str(line)?))
+                .collect::<Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Spools `message` until it is redelivered.
+    pub async fn push(&self, message: Outgoing) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        let entry = OutboxEntry {
+            queued_at: Local::now(),
+            message,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        entries.push(entry);
+        Ok(())
+    }
+
+    /// Entries waiting, oldest first.
+    pub async fn pending(&self) -> Vec<OutboxEntry> {
+        self.entries.lock().await.clone()
+    }
+
+    pub async fn has_pending(&self, matches: impl Fn(&Outgoing) -> bool) -> bool {
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .any(|entry| matches(&entry.message))
+    }
+
+    /// Offers the waiting entries, oldest first, to `deliver`, which returns `None` for
+    /// entries that are not for its sink and otherwise whether delivery succeeded.
+    /// Delivered entries are dropped. The first failure stops the attempt, so entries for
+    /// one sink stay in order. Returns how many were delivered.
+    pub async fn redeliver<F, Fut>(&self, mut deliver: F) -> Result<usize>
+    where
+        F: FnMut(Outgoing) -> Fut,
+        Fut: Future<Output = Option<Result<()>>>,
+    {
+        let mut entries = self.entries.lock().await;
+        let mut kept = Vec::with_capacity(entries.len());
+        let mut delivered = 0;
+        let mut failed = false;
+        for entry in entries.drain(..) {
+            if failed {
+                kept.push(entry);
+                continue;
+            }
+            match deliver(entry.message.clone()).await {
+                Some(Ok(())) => delivered += 1,
+                Some(Err(e)) => {
+                    println!("Outbox delivery failed, will retry: {}", e);
+                    failed = true;
+                    kept.push(entry);
+                }
+                None => kept.push(entry),
+            }
+        }
+        *entries = kept;
+        if delivered > 0 {
+            let mut content = String::new();
+            for entry in entries.iter() {
+                content.push_str(&serde_json::to_string(entry)?);
+                content.push('\n');
+            }
+            tokio::fs::write(&self.path, content).await?;
+        }
+        Ok(delivered)
+    }
+}
@@ -0,0 +1,443 @@
+use crate::probe::{PostureField, ProbeConfig, ProbeMatch};
+use crate::user_session::{
+    list_logind_sessions, list_who_sessions, list_wts_sessions, UserSession,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command as AsyncCommand;
+
+/// Firewall anchor holding guardian's rules on macOS.
+const PF_ANCHOR: &str = "com.guardian";
+const USBSTOR_KEY: &str = "HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\USBSTOR";
+/// Name of the firewall rules that block traffic on Windows, as the bundled scripts use.
+const WINDOWS_BLOCK_RULE: &str = "DefenceActiveOn";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkProfile {
+    Open,
+    Blocked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbPolicy {
+    Allowed,
+    Blocked,
+}
+
+/// An external program run for a platform action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Written to the program's standard input.
+    pub stdin: Option<String>,
+}
+
+impl PlatformCommand {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            stdin: None,
+        }
+    }
+
+    pub fn with_stdin(mut self, stdin: &str) -> Self {
+        self.stdin = Some(stdin.to_string());
+        self
+    }
+
+    pub fn to_command(&self) -> AsyncCommand {
+        let mut command = AsyncCommand::new(&self.program);
+        command.args(&self.args);
+        #[cfg(target_os = "windows")]
+        {
+            // CREATE_NO_WINDOW: no console flashes up on the user's desktop.
+            command.creation_flags(0x08000000);
+        }
+        command
+    }
+
+    /// Runs the program and returns its standard output; fails on a non-zero exit.
+    pub async fn run(&self) -> Result<String> {
+        let mut command = self.to_command();
+        let output = match &self.stdin {
+            Some(stdin) => {
+                let mut child = command
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::piped())
+                    .spawn()?;
+                if let Some(mut pipe) = child.stdin.take() {
+                    pipe.write_all(stdin.as_bytes()).await?;
+                }
+                child.wait_with_output().await?
+            }
+            None => command.output().await?,
+        };
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(anyhow!(
+                "{} failed: {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+async fn run_all(commands: Vec<PlatformCommand>) -> Result<()> {
+    for command in commands {
+        command.run().await?;
+    }
+    Ok(())
+}
+
+/// The OS-specific side of guardian: how each response action, status query and posture
+/// probe is carried out. The `*_commands` methods only describe the programs to run, so
+/// every implementation can be checked on any host.
+#[async_trait]
+pub trait Platform: Send + Sync {
+    fn os(&self) -> &'static str;
+    fn lock_screen_commands(&self) -> Result<Vec<PlatformCommand>>;
+    fn network_profile_commands(&self, profile: NetworkProfile) -> Result<Vec<PlatformCommand>>;
+    fn usb_policy_commands(&self, policy: UsbPolicy) -> Result<Vec<PlatformCommand>>;
+    fn shutdown_commands(&self) -> Result<Vec<PlatformCommand>>;
+    /// Probes observing the state the network and USB actions change.
+    fn posture_probes(&self) -> Vec<ProbeConfig>;
+    /// Lists processes, for status reports.
+    fn process_list_command(&self) -> PlatformCommand;
+    /// Prints the firewall rules, and the prefix of lines that are rules.
+    fn firewall_rules_command(&self) -> (PlatformCommand, &'static str);
+    /// Subdirectory of the response directory holding this OS's scripts.
+    fn script_directory_name(&self) -> &'static str;
+    /// File of a bundled response script, by name without extension.
+    fn script_path(&self, script_directory: &str, script_name: &str) -> String;
+    fn script_command(&self, script_path: &str) -> PlatformCommand;
+    /// Name of the bundled live-response triage script.
+    fn evidence_script(&self) -> &'static str;
+    async fn list_sessions(&self) -> Result<Vec<UserSession>>;
+
+    async fn lock_screen(&self) -> Result<()> {
+        run_all(self.lock_screen_commands()?).await
+    }
+
+    async fn set_network_profile(&self, profile: NetworkProfile) -> Result<()> {
+        run_all(self.network_profile_commands(profile)?).await
+    }
+
+    async fn set_usb_policy(&self, policy: UsbPolicy) -> Result<()> {
+        run_all(self.usb_policy_commands(policy)?).await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        run_all(self.shutdown_commands()?).await
+    }
+}
+
+/// The implementation for the OS guardian was built for.
+pub fn current() -> Arc<dyn Platform> {
+    if cfg!(target_os = "windows") {
+        Arc::new(WindowsPlatform)
+    } else if cfg!(target_os = "macos") {
+        Arc::new(MacosPlatform)
+    } else {
+        Arc::new(LinuxPlatform)
+    }
+}
+
+fn unix_script_path(script_directory: &str, script_name: &str) -> String {
+    format!("{}/{}.sh", script_directory, script_name)
+}
+
+fn unix_script_command(script_path: &str) -> PlatformCommand {
+    PlatformCommand::new("bash", &["-c", script_path])
+}
+
+/// systemd-based Linux: logind sessions, iptables and the usb-storage module.
+pub struct LinuxPlatform;
+
+#[async_trait]
+impl Platform for LinuxPlatform {
+    fn os(&self) -> &'static str {
+        "linux"
+    }
+
+    fn lock_screen_commands(&self) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![PlatformCommand::new("loginctl", &["lock-sessions"])])
+    }
+
+    fn network_profile_commands(&self, profile: NetworkProfile) -> Result<Vec<PlatformCommand>> {
+        let policy = match profile {
+            NetworkProfile::Open => "ACCEPT",
+            NetworkProfile::Blocked => "DROP",
+        };
+        Ok(["INPUT", "OUTPUT", "FORWARD"]
+            .iter()
+            .map(|chain| PlatformCommand::new("iptables", &["-P", chain, policy]))
+            .collect())
+    }
+
+    fn usb_policy_commands(&self, policy: UsbPolicy) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![match policy {
+            UsbPolicy::Allowed => PlatformCommand::new("modprobe", &["usb_storage"]),
+            UsbPolicy::Blocked => PlatformCommand::new("modprobe", &["-r", "usb_storage"]),
+        }])
+    }
+
+    fn shutdown_commands(&self) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![PlatformCommand::new("systemctl", &["poweroff"])])
+    }
+
+    fn posture_probes(&self) -> Vec<ProbeConfig> {
+        vec![ProbeConfig {
+            name: "iptables_output_drop".to_string(),
+            field: PostureField::NetworkBlocked,
+            program: "iptables".to_string(),
+            args: vec!["-S".to_string(), "OUTPUT".to_string()],
+            active_when: ProbeMatch::StdoutContains("-P OUTPUT DROP".to_string()),
+        }]
+    }
+
+    fn process_list_command(&self) -> PlatformCommand {
+        PlatformCommand::new("ps", &["aux"])
+    }
+
+    fn firewall_rules_command(&self) -> (PlatformCommand, &'static str) {
+        (PlatformCommand::new("iptables", &["-S"]), "-A ")
+    }
+
+    fn script_directory_name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn script_path(&self, script_directory: &str, script_name: &str) -> String {
+        unix_script_path(script_directory, script_name)
+    }
+
+    fn script_command(&self, script_path: &str) -> PlatformCommand {
+        unix_script_command(script_path)
+    }
+
+    fn evidence_script(&self) -> &'static str {
+        "TriageCollect/nix_Live_Response"
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<UserSession>> {
+        list_logind_sessions().await
+    }
+}
+
+/// Windows: WTS sessions, Windows Firewall rules and the USBSTOR service.
+pub struct WindowsPlatform;
+
+#[async_trait]
+impl Platform for WindowsPlatform {
+    fn os(&self) -> &'static str {
+        "windows"
+    }
+
+    fn lock_screen_commands(&self) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![PlatformCommand::new(
+            "rundll32.exe",
+            &["user32.dll,LockWorkStation"],
+        )])
+    }
+
+    fn network_profile_commands(&self, profile: NetworkProfile) -> Result<Vec<PlatformCommand>> {
+        let name = format!("name={}", WINDOWS_BLOCK_RULE);
+        Ok(match profile {
+            NetworkProfile::Open => vec![PlatformCommand::new(
+                "netsh",
+                &["advfirewall", "firewall", "delete", "rule", &name],
+            )],
+            NetworkProfile::Blocked => ["in", "out"]
+                .iter()
+                .map(|dir| {
+                    PlatformCommand::new(
+                        "netsh",
+                        &[
+                            "advfirewall",
+                            "firewall",
+                            "add",
+                            "rule",
+                            &name,
+                            &format!("dir={}", dir),
+                            "action=block",
+                        ],
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    fn usb_policy_commands(&self, policy: UsbPolicy) -> Result<Vec<PlatformCommand>> {
+        // Start type 3 loads the driver on demand, 4 disables it.
+        let start = match policy {
+            UsbPolicy::Allowed => "3",
+            UsbPolicy::Blocked => "4",
+        };
+        Ok(vec![PlatformCommand::new(
+            "reg",
+            &[
+                "add",
+                USBSTOR_KEY,
+                "/v",
+                "Start",
+                "/t",
+                "REG_DWORD",
+                "/d",
+                start,
+                "/f",
+            ],
+        )])
+    }
+
+    fn shutdown_commands(&self) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![PlatformCommand::new("shutdown", &["/s", "/t", "0"])])
+    }
+
+    fn posture_probes(&self) -> Vec<ProbeConfig> {
+        vec![
+            ProbeConfig {
+                name: "firewall_block_rule".to_string(),
+                field: PostureField::NetworkBlocked,
+                program: "netsh".to_string(),
+                args: [
+                    "advfirewall",
+                    "firewall",
+                    "show",
+                    "rule",
+                    &format!("name={}", WINDOWS_BLOCK_RULE),
+                ]
+                .map(String::from)
+                .to_vec(),
+                active_when: ProbeMatch::ExitSuccess,
+            },
+            ProbeConfig {
+                name: "usbstor_disabled".to_string(),
+                field: PostureField::UsbLocked,
+                program: "reg".to_string(),
+                args: ["query", USBSTOR_KEY, "/v", "Start"]
+                    .map(String::from)
+                    .to_vec(),
+                active_when: ProbeMatch::StdoutContains("0x4".to_string()),
+            },
+        ]
+    }
+
+    fn process_list_command(&self) -> PlatformCommand {
+        PlatformCommand::new("tasklist", &[])
+    }
+
+    fn firewall_rules_command(&self) -> (PlatformCommand, &'static str) {
+        (
+            PlatformCommand::new(
+                "netsh",
+                &["advfirewall", "firewall", "show", "rule", "name=all"],
+            ),
+            "Rule Name:",
+        )
+    }
+
+    fn script_directory_name(&self) -> &'static str {
+        "win"
+    }
+
+    fn script_path(&self, script_directory: &str, script_name: &str) -> String {
+        format!("{}\\{}.bat", script_directory, script_name)
+    }
+
+    fn script_command(&self, script_path: &str) -> PlatformCommand {
+        PlatformCommand::new("cmd", &["/C", script_path])
+    }
+
+    fn evidence_script(&self) -> &'static str {
+        "TriageCollect\\Triage_Windows_Live_Response"
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<UserSession>> {
+        list_wts_sessions().await
+    }
+}
+
+/// macOS: `who` sessions and a pf anchor. USB mass storage cannot be switched off
+/// without an MDM profile, so the USB policy is not supported.
+pub struct MacosPlatform;
+
+#[async_trait]
+impl Platform for MacosPlatform {
+    fn os(&self) -> &'static str {
+        "macos"
+    }
+
+    fn lock_screen_commands(&self) -> Result<Vec<PlatformCommand>> {
+        // Locks when the screen saver requires a password immediately, the default.
+        Ok(vec![PlatformCommand::new("pmset", &["displaysleepnow"])])
+    }
+
+    fn network_profile_commands(&self, profile: NetworkProfile) -> Result<Vec<PlatformCommand>> {
+        Ok(match profile {
+            NetworkProfile::Open => vec![PlatformCommand::new(
+                "pfctl",
+                &["-a", PF_ANCHOR, "-F", "rules"],
+            )],
+            NetworkProfile::Blocked => vec![
+                PlatformCommand::new("pfctl", &["-E"]),
+                PlatformCommand::new("pfctl", &["-a", PF_ANCHOR, "-f", "-"])
+                    .with_stdin("block drop all\n"),
+            ],
+        })
+    }
+
+    fn usb_policy_commands(&self, _policy: UsbPolicy) -> Result<Vec<PlatformCommand>> {
+        Err(anyhow!(
+            "USB mass storage policy is not supported on macOS; use an MDM profile"
+        ))
+    }
+
+    fn shutdown_commands(&self) -> Result<Vec<PlatformCommand>> {
+        Ok(vec![PlatformCommand::new("shutdown", &["-h", "now"])])
+    }
+
+    fn posture_probes(&self) -> Vec<ProbeConfig> {
+        vec![ProbeConfig {
+            name: "pf_block_anchor".to_string(),
+            field: PostureField::NetworkBlocked,
+            program: "pfctl".to_string(),
+            args: ["-a", PF_ANCHOR, "-s", "rules"].map(String::from).to_vec(),
+            active_when: ProbeMatch::StdoutContains("block drop all".to_string()),
+        }]
+    }
+
+    fn process_list_command(&self) -> PlatformCommand {
+        PlatformCommand::new("ps", &["aux"])
+    }
+
+    fn firewall_rules_command(&self) -> (PlatformCommand, &'static str) {
+        (PlatformCommand::new("pfctl", &["-s", "rules"]), "")
+    }
+
+    fn script_directory_name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn script_path(&self, script_directory: &str, script_name: &str) -> String {
+        unix_script_path(script_directory, script_name)
+    }
+
+    fn script_command(&self, script_path: &str) -> PlatformCommand {
+        unix_script_command(script_path)
+    }
+
+    fn evidence_script(&self) -> &'static str {
+        "TriageCollect/nix_Live_Response"
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<UserSession>> {
+        list_who_sessions().await
+    }
+}
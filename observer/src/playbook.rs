@@ -0,0 +1,290 @@
+use crate::dispatcher::CommandDispatcher;
+use crate::native::NativeCommands;
+use crate::policy::CommandConditions;
+use crate::protocol::parse_command;
+use crate::result::{CommandResult, ResultCode};
+use anyhow::{anyhow, Result};
+use file_monitor_core::{
+    Baseline, BaselineRoot, FileMonitor, HashPolicy, MonitorEvent, PathFilter, RateAlertRule,
+    ShutdownToken,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Command that runs a playbook, followed by its name.
+pub const RUN_PLAYBOOK: &str = "RUN_PLAYBOOK";
+/// Audit trigger of playbook steps, followed by `:<playbook>`.
+pub const PLAYBOOK_TRIGGER: &str = "PLAYBOOK";
+/// Audit trigger of playbooks run by a file monitor alert, followed by `:<rule>`.
+pub const ALERT_TRIGGER: &str = "ALERT";
+
+/// What a playbook step does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlaybookAction {
+    /// A guardian command such as `BLOCK_NETWORK`.
+    Command { command: String },
+    /// Records size, modification time and hash of every file under `paths` to `output`.
+    Baseline {
+        paths: Vec<PathBuf>,
+        output: PathBuf,
+    },
+    /// Compares the files with a baseline recorded earlier; any drift fails the step.
+    VerifyBaseline { baseline: PathBuf },
+}
+
+/// When a step runs, given the outcome of the steps before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepCondition {
+    /// While no earlier step has failed.
+    #[default]
+    Success,
+    /// Only once an earlier step has failed, e.g. to collect evidence of what went wrong.
+    Failure,
+    Always,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaybookStep {
+    #[serde(flatten)]
+    pub action: PlaybookAction,
+    #[serde(default)]
+    pub when: StepCondition,
+    /// Host conditions, as in a host section; the step is skipped unless all of them hold.
+    #[serde(default)]
+    pub conditions: Option<CommandConditions>,
+    /// Run the step this many seconds later, scheduled like a delayed hook; the playbook
+    /// goes on without waiting. Only command steps can be delayed.
+    #[serde(default)]
+    pub delay_secs: u64,
+}
+
+impl PlaybookStep {
+    /// How the step is named in the audit log.
+    pub fn label(&self) -> String {
+        match &self.action {
+            PlaybookAction::Command { command } => command.clone(),
+            PlaybookAction::Baseline { output, .. } => format!("BASELINE {}", output.display()),
+            PlaybookAction::VerifyBaseline { baseline } => {
+                format!("VERIFY_BASELINE {}", baseline.display())
+            }
+        }
+    }
+}
+
+/// A named, repeatable incident procedure.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playbook {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub steps: Vec<PlaybookStep>,
+    /// Rate alerts of the playbook configuration that run this playbook when they fire.
+    #[serde(default)]
+    pub on_alert: Vec<String>,
+}
+
+/// Playbooks, plus the paths watched for the alerts that run them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaybookConfig {
+    pub playbooks: Vec<Playbook>,
+    #[serde(default)]
+    pub watch_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub rate_alerts: Vec<RateAlertRule>,
+}
+
+impl PlaybookConfig {
+    /// Loads and validates the playbooks; steps may run the `native` commands.
+    pub fn load<P: AsRef<Path>>(path: P, native: &NativeCommands) -> Result<Self> {
+        let config: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        config.validate(native)?;
+        Ok(config)
+    }
+
+    /// Checks names, that every command step is a valid command other than a playbook,
+    /// and that alerts referred to are configured.
+    pub fn validate(&self, native: &NativeCommands) -> Result<()> {
+        for rule in &self.rate_alerts {
+            rule.validate()?;
+        }
+        let mut names = HashSet::new();
+        for playbook in &self.playbooks {
+            let name = &playbook.name;
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(anyhow!("Invalid playbook name {:?}", name));
+            }
+            if !names.insert(name) {
+                return Err(anyhow!("Playbook {} is defined twice", name));
+            }
+            if playbook.steps.is_empty() {
+                return Err(anyhow!("Playbook {} has no steps", name));
+            }
+            for (index, step) in playbook.steps.iter().enumerate() {
+                let PlaybookAction::Command { command } = &step.action else {
+                    if step.delay_secs > 0 {
+                        return Err(anyhow!(
+                            "Playbook {} step {}: only command steps can be delayed",
+                            name,
+                            index + 1
+                        ));
+                    }
+                    continue;
+                };
+                let parsed = parse_command(command.as_bytes(), native)
+                    .map_err(|e| anyhow!("Playbook {} step {}: {}", name, index + 1, e))?;
+                if parsed.verb == RUN_PLAYBOOK {
+                    return Err(anyhow!(
+                        "Playbook {} step {}: playbooks cannot run playbooks",
+                        name,
+                        index + 1
+                    ));
+                }
+            }
+            for rule in &playbook.on_alert {
+                if !self.rate_alerts.iter().any(|alert| &alert.name == rule) {
+                    return Err(anyhow!("Playbook {} runs on unknown alert {}", name, rule));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Playbook> {
+        self.playbooks.iter().find(|playbook| playbook.name == name)
+    }
+
+    pub fn for_alert<'a>(&'a self, rule: &'a str) -> impl Iterator<Item = &'a Playbook> {
+        self.playbooks
+            .iter()
+            .filter(move |playbook| playbook.on_alert.iter().any(|alert| alert == rule))
+    }
+}
+
+/// Runs a baseline step. Paths are scanned without filters, hashing with the default
+/// policy.
+pub(crate) async fn run_monitor_action(action: &PlaybookAction) -> CommandResult {
+    let outcome = match action {
+        PlaybookAction::Command { command } => {
+            Err(anyhow!("{} is a command, not a monitor action", command))
+        }
+        PlaybookAction::Baseline { paths, output } => record_baseline(paths, output).await,
+        PlaybookAction::VerifyBaseline { baseline } => verify_baseline(baseline).await,
+    };
+    outcome.unwrap_or_else(|e| CommandResult::error(ResultCode::InternalError, e.to_string()))
+}
+
+async fn record_baseline(paths: &[PathBuf], output: &Path) -> Result<CommandResult> {
+    let roots = paths
+        .iter()
+        .map(|path| BaselineRoot {
+            path: path.clone(),
+            max_depth: None,
+            policy: HashPolicy::default(),
+        })
+        .collect();
+    let output = output.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let baseline = Baseline::create(roots, &PathFilter::default())?;
+        baseline.save(&output)?;
+        Ok(CommandResult::ok(
+            format!(
+                "Baseline of {} files saved to {}",
+                baseline.files.len(),
+                output.display()
+            ),
+            json!({ "files": baseline.files.len(), "output": output }),
+        ))
+    })
+    .await?
+}
+
+async fn verify_baseline(path: &Path) -> Result<CommandResult> {
+    let baseline = Baseline::load(path)?;
+    let drift =
+        tokio::task::spawn_blocking(move || baseline.verify(&PathFilter::default())).await??;
+    let drifted: Vec<_> = drift
+        .iter()
+        .map(|(path, change)| json!({ "path": path, "drift": change }))
+        .collect();
+    Ok(if drift.is_empty() {
+        CommandResult::ok(
+            format!("No drift from baseline {}", path.display()),
+            json!({ "drift": drifted }),
+        )
+    } else {
+        CommandResult::new(
+            ResultCode::PostureDrift,
+            format!(
+                "{} files drifted from baseline {}",
+                drift.len(),
+                path.display()
+            ),
+            json!({ "drift": drifted }),
+        )
+    })
+}
+
+/// Watches the configured `watch_paths` with the file monitor and runs the playbooks of
+/// each rate alert that fires, until `shutdown` is cancelled. Does nothing without
+/// playbooks or paths to watch.
+pub async fn run_playbooks_on_alerts(
+    dispatcher: Arc<CommandDispatcher>,
+    shutdown: ShutdownToken,
+) -> Result<()> {
+    let Some(config) = dispatcher.playbooks() else {
+        return Ok(());
+    };
+    let Some((first, rest)) = config.watch_paths.split_first() else {
+        return Ok(());
+    };
+    let builder = rest.iter().fold(
+        FileMonitor::builder(first).shutdown_token(shutdown.child()),
+        |builder, path| builder.watch(path),
+    );
+    let builder = config
+        .rate_alerts
+        .iter()
+        .cloned()
+        .fold(builder, |builder, rule| builder.rate_alert(rule));
+    let monitor = Arc::new(builder.try_build()?);
+    let mut events = monitor.subscribe();
+    let watcher = Arc::clone(&monitor);
+    tokio::spawn(async move {
+        if let Err(e) = watcher.monitor().await {
+            println!("Playbook alert watcher stopped: {}", e);
+        }
+    });
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = shutdown.cancelled() => break,
+        };
+        match event {
+            Some(MonitorEvent::Alert { rule, reason, .. }) => {
+                let trigger = format!("{}:{}", ALERT_TRIGGER, rule);
+                for playbook in config.for_alert(&rule) {
+                    println!(
+                        "Alert {} ({}): running playbook {}",
+                        rule, reason, playbook.name
+                    );
+                    let command = format!("{} {}", RUN_PLAYBOOK, playbook.name);
+                    let result = dispatcher.dispatch_unattended(&command, &trigger).await;
+                    println!("Playbook {}: {}", playbook.name, result.human_message);
+                }
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::network_env::NetworkEnvironment;
+use crate::user_session::{console_user, UserSession};
+use serde::{Deserialize, Serialize};
+
+/// Conditions a host section can attach to a command. Every set condition must hold.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandConditions {
+    /// Only allow the command while this user owns the active console session.
+    #[serde(default)]
+    pub console_user: Option<String>,
+    /// Require the console session to be unlocked (`true`) or locked (`false`).
+    #[serde(default)]
+    pub console_unlocked: Option<bool>,
+    /// Only allow the command while connected to this wireless network.
+    #[serde(default)]
+    pub ssid: Option<String>,
+    /// Only allow the command behind the gateway with this MAC address.
+    #[serde(default)]
+    pub gateway_mac: Option<String>,
+    /// Only allow the command while the host has an address in this IPv4 subnet.
+    #[serde(default)]
+    pub subnet: Option<String>,
+}
+
+/// Host state the conditions are evaluated against.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicyContext {
+    pub user_sessions: Vec<UserSession>,
+    pub network: NetworkEnvironment,
+}
+
+impl CommandConditions {
+    /// Returns the reason the command is refused, or `None` if all conditions hold.
+    pub fn violation(&self, context: &PolicyContext) -> Option<String> {
+        let console = console_user(&context.user_sessions);
+
+        if let Some(required_user) = &self.console_user {
+            match console {
+                Some(session) if &session.user == required_user => {}
+                Some(session) => {
+                    return Some(format!(
+                        "console user is {}, not {}",
+                        session.user, required_user
+                    ))
+                }
+                None => return Some(format!("{} is not logged in at the console", required_user)),
+            }
+        }
+
+        if let Some(unlocked) = self.console_unlocked {
+            match console {
+                Some(session) if session.locked != unlocked => {}
+                Some(_) if unlocked => return Some("console session is locked".to_string()),
+                Some(_) => return Some("console session is unlocked".to_string()),
+                None => return Some("no active console session".to_string()),
+            }
+        }
+
+        if let Some(ssid) = &self.ssid {
+            if context.network.ssid.as_ref() != Some(ssid) {
+                return Some(format!("not connected to network {}", ssid));
+            }
+        }
+
+        if let Some(gateway_mac) = &self.gateway_mac {
+            let matches = context
+                .network
+                .gateway_mac
+                .as_ref()
+                .is_some_and(|mac| mac.eq_ignore_ascii_case(gateway_mac));
+            if !matches {
+                return Some(format!("gateway is not {}", gateway_mac));
+            }
+        }
+
+        if let Some(subnet) = &self.subnet {
+            if !context.network.in_subnet(subnet) {
+                return Some(format!("host is not on subnet {}", subnet));
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,164 @@
+use crate::session::Posture;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::process::Command as AsyncCommand;
+
+/// Posture aspect a probe can observe on the live system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostureField {
+    NetworkBlocked,
+    UsbLocked,
+}
+
+impl PostureField {
+    pub fn expected(self, posture: &Posture) -> Option<bool> {
+        match self {
+            PostureField::NetworkBlocked => posture.network_blocked,
+            PostureField::UsbLocked => posture.usb_locked,
+        }
+    }
+}
+
+/// Checks one aspect of the real system state.
+#[async_trait]
+pub trait PostureProbe: Send + Sync {
+    fn name(&self) -> &str;
+    fn field(&self) -> PostureField;
+    /// Returns the observed state, or `None` if it cannot be determined on this platform.
+    async fn observe(&self) -> Result<Option<bool>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub probe: String,
+    pub field: PostureField,
+    pub expected: Option<bool>,
+    pub actual: Option<bool>,
+    pub drift: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a configured probe decides that the posture aspect is active.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeMatch {
+    ExitSuccess,
+    StdoutContains(String),
+}
+
+/// Probe declared in the probes config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeConfig {
+    pub name: String,
+    pub field: PostureField,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub active_when: ProbeMatch,
+}
+
+/// Runs an external command and interprets its outcome.
+pub struct CommandProbe {
+    config: ProbeConfig,
+}
+
+impl CommandProbe {
+    pub fn new(config: ProbeConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl PostureProbe for CommandProbe {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn field(&self) -> PostureField {
+        self.config.field
+    }
+
+    async fn observe(&self) -> Result<Option<bool>> {
+        let output = AsyncCommand::new(&self.config.program)
+            .args(&self.config.args)
+            .output()
+            .await?;
+        let active = match &self.config.active_when {
+            ProbeMatch::ExitSuccess => output.status.success(),
+            ProbeMatch::StdoutContains(needle) => {
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "{} failed: {}",
+                        self.config.program,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8_lossy(&output.stdout).contains(needle.as_str())
+            }
+        };
+        Ok(Some(active))
+    }
+}
+
+/// Built-in probes matching what the bundled response scripts change.
+pub fn default_probes() -> Vec<Box<dyn PostureProbe>> {
+    crate::platform::current()
+        .posture_probes()
+        .into_iter()
+        .map(|config| Box::new(CommandProbe::new(config)) as Box<dyn PostureProbe>)
+        .collect()
+}
+
+/// Compares tracked posture with what the probes observe.
+pub struct PostureVerifier {
+    probes: Vec<Box<dyn PostureProbe>>,
+}
+
+impl PostureVerifier {
+    pub fn new(probes: Vec<Box<dyn PostureProbe>>) -> Self {
+        Self { probes }
+    }
+
+    /// Built-in probes plus any declared in the JSON config file, if it exists.
+    pub fn load<P: AsRef<Path>>(config_path: P) -> Result<Self> {
+        let mut probes = default_probes();
+        match std::fs::read_to_string(config_path.as_ref()) {
+            Ok(content) => {
+                let configs: Vec<ProbeConfig> = serde_json::from_str(&content)?;
+                probes.extend(
+                    configs
+                        .into_iter()
+                        .map(|config| Box::new(CommandProbe::new(config)) as Box<dyn PostureProbe>),
+                );
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self::new(probes))
+    }
+
+    pub async fn verify(&self, expected: &Posture) -> Vec<ProbeResult> {
+        let mut results = Vec::with_capacity(self.probes.len());
+        for probe in &self.probes {
+            let expected_state = probe.field().expected(expected);
+            let (actual, error) = match probe.observe().await {
+                Ok(actual) => (actual, None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let drift = matches!((expected_state, actual), (Some(e), Some(a)) if e != a);
+            results.push(ProbeResult {
+                probe: probe.name().to_string(),
+                field: probe.field(),
+                expected: expected_state,
+                actual,
+                drift,
+                error,
+            });
+        }
+        results
+    }
+}
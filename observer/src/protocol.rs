@@ -0,0 +1,221 @@
+use crate::handler::{command_catalog, CommandSpec};
+use crate::native::NativeCommands;
+use crate::result::ResultCode;
+use std::fmt;
+
+/// Longest command line accepted, in bytes, including arguments.
+pub const MAX_COMMAND_BYTES: usize = 256;
+pub const MAX_VERB_LEN: usize = 32;
+pub const MAX_ARGUMENT_LEN: usize = 128;
+/// How much of a rejected command is kept, escaped, for the audit log.
+const AUDIT_EXCERPT_BYTES: usize = 64;
+
+/// Commands handled by the dispatcher itself rather than listed in the catalog.
+pub(crate) const DISPATCHER_COMMANDS: &[&str] = &["DIAGNOSE_KEY"];
+
+/// A command line from a key: a verb such as `BLOCK_NETWORK` followed by
+/// space-separated arguments, validated against the verb's [`CommandSpec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedCommand {
+    pub verb: String,
+    pub arguments: Vec<String>,
+}
+
+impl fmt::Display for ParsedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.verb)?;
+        for argument in &self.arguments {
+            write!(f, " {}", argument)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a command line was refused. Offsets are in bytes from the start of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    TooLong { len: usize },
+    InvalidUtf8 { offset: usize },
+    InvalidCharacter { offset: usize, byte: u8 },
+    VerbTooLong,
+    UnknownVerb(String),
+    MissingArgument { verb: String, argument: String },
+    UnexpectedArgument { verb: String, index: usize },
+    InvalidArgument { argument: String, reason: String },
+}
+
+impl ParseError {
+    /// Stable reason code for audit records.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "EMPTY",
+            ParseError::TooLong { .. } => "TOO_LONG",
+            ParseError::InvalidUtf8 { .. } => "INVALID_UTF8",
+            ParseError::InvalidCharacter { .. } => "INVALID_CHARACTER",
+            ParseError::VerbTooLong => "VERB_TOO_LONG",
+            ParseError::UnknownVerb(_) => "UNKNOWN_VERB",
+            ParseError::MissingArgument { .. } => "MISSING_ARGUMENT",
+            ParseError::UnexpectedArgument { .. } => "UNEXPECTED_ARGUMENT",
+            ParseError::InvalidArgument { .. } => "INVALID_ARGUMENT",
+        }
+    }
+
+    pub fn result_code(&self) -> ResultCode {
+        match self {
+            ParseError::UnknownVerb(_) => ResultCode::UnknownCommand,
+            _ => ResultCode::MalformedCommand,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Command is empty"),
+            ParseError::TooLong { len } => write!(
+                f,
+                "Command is {} bytes, at most {} are allowed",
+                len, MAX_COMMAND_BYTES
+            ),
+            ParseError::InvalidUtf8 { offset } => {
+                write!(f, "Command is not valid UTF-8 at byte {}", offset)
+            }
+            ParseError::InvalidCharacter { offset, byte } => write!(
+                f,
+                "Command contains a disallowed character 0x{:02x} at byte {}",
+                byte, offset
+            ),
+            ParseError::VerbTooLong => {
+                write!(f, "Command verb is longer than {} characters", MAX_VERB_LEN)
+            }
+            ParseError::UnknownVerb(verb) => write!(f, "Unknown command: {}", verb),
+            ParseError::MissingArgument { verb, argument } => {
+                write!(f, "Command {} needs argument {}", verb, argument)
+            }
+            ParseError::UnexpectedArgument { verb, index } => {
+                write!(f, "Command {} takes no argument {}", verb, index + 1)
+            }
+            ParseError::InvalidArgument { argument, reason } => {
+                write!(f, "Argument {} is invalid: {}", argument, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a command line as read from removable media. Everything is checked before
+/// the content is interpreted: the length, UTF-8, the character set (upper-case verbs,
+/// printable ASCII arguments without quotes or shell metacharacters), that the verb
+/// exists, built in or in `native`, and that the arguments match its spec. A single
+/// trailing newline is allowed.
+pub fn parse_command(raw: &[u8], native: &NativeCommands) -> Result<ParsedCommand, ParseError> {
+    if raw.len() > MAX_COMMAND_BYTES {
+        return Err(ParseError::TooLong { len: raw.len() });
+    }
+    let raw = raw
+        .strip_suffix(b"\n")
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .unwrap_or(raw);
+    let line = std::str::from_utf8(raw).map_err(|e| ParseError::InvalidUtf8 {
+        offset: e.valid_up_to(),
+    })?;
+    if let Some((offset, byte)) = line
+        .bytes()
+        .enumerate()
+        .find(|(_, byte)| !is_allowed(*byte))
+    {
+        return Err(ParseError::InvalidCharacter { offset, byte });
+    }
+
+    let mut words = line.split(' ');
+    let verb = words.next().unwrap_or_default();
+    if verb.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    if verb.len() > MAX_VERB_LEN {
+        return Err(ParseError::VerbTooLong);
+    }
+    if let Some(offset) = verb
+        .bytes()
+        .position(|byte| !(byte.is_ascii_uppercase() || byte.is_ascii_digit() || byte == b'_'))
+    {
+        return Err(ParseError::InvalidCharacter {
+            offset,
+            byte: verb.as_bytes()[offset],
+        });
+    }
+    let arguments: Vec<String> = words.map(str::to_string).collect();
+    let spec = command_catalog(native)
+        .into_iter()
+        .find(|spec| spec.name == verb)
+        .or_else(|| {
+            DISPATCHER_COMMANDS
+                .contains(&verb)
+                .then(|| CommandSpec::new(verb, "", false))
+        })
+        .ok_or_else(|| ParseError::UnknownVerb(verb.to_string()))?;
+    validate_arguments(&spec, &arguments)?;
+    Ok(ParsedCommand {
+        verb: verb.to_string(),
+        arguments,
+    })
+}
+
+fn validate_arguments(spec: &CommandSpec, arguments: &[String]) -> Result<(), ParseError> {
+    if arguments.len() > spec.arguments.len() {
+        return Err(ParseError::UnexpectedArgument {
+            verb: spec.name.clone(),
+            index: spec.arguments.len(),
+        });
+    }
+    for (index, argument_spec) in spec.arguments.iter().enumerate() {
+        let Some(value) = arguments.get(index) else {
+            if argument_spec.required {
+                return Err(ParseError::MissingArgument {
+                    verb: spec.name.clone(),
+                    argument: argument_spec.name.clone(),
+                });
+            }
+            continue;
+        };
+        let invalid = |reason: &str| ParseError::InvalidArgument {
+            argument: argument_spec.name.clone(),
+            reason: reason.to_string(),
+        };
+        if value.is_empty() {
+            return Err(invalid("empty (doubled space?)"));
+        }
+        if value.len() > MAX_ARGUMENT_LEN {
+            return Err(invalid("too long"));
+        }
+        match argument_spec.kind.as_str() {
+            "integer" if value.parse::<i64>().is_err() => return Err(invalid("not an integer")),
+            "boolean" if value != "true" && value != "false" => {
+                return Err(invalid("not true or false"))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Printable ASCII without quotes, backslashes and shell metacharacters.
+fn is_allowed(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b" _-.:/@=,+".contains(&byte)
+}
+
+/// Escaped, truncated form of a rejected command line, safe to log and audit.
+pub fn audit_excerpt(raw: &[u8]) -> String {
+    let mut excerpt: String = raw
+        .iter()
+        .take(AUDIT_EXCERPT_BYTES)
+        .flat_map(|byte| std::ascii::escape_default(*byte))
+        .map(char::from)
+        .collect();
+    if raw.len() > AUDIT_EXCERPT_BYTES {
+        excerpt.push_str(&format!("... ({} bytes)", raw.len()));
+    }
+    excerpt
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Machine-readable outcome of a command, stable across releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ResultCode {
+    Ok,
+    /// The command was accepted and audited but not executed (observation mode).
+    Observed,
+    UnknownCommand,
+    /// The command line failed validation before it was interpreted.
+    MalformedCommand,
+    CommandNotPermitted,
+    ScriptNotFound,
+    ScriptFailed,
+    StatusCheckFailed,
+    PostureDrift,
+    /// Another agent held the shared action lock, see [`crate::action_lock`].
+    ActionLocked,
+    /// The host is in forensic mode and runs evidence collection only, see
+    /// [`crate::forensics`].
+    ForensicHold,
+    InternalError,
+}
+
+impl ResultCode {
+    pub fn is_success(self) -> bool {
+        self == ResultCode::Ok
+    }
+}
+
+/// Structured command outcome written back to the key and recorded by guardian.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub code: ResultCode,
+    pub human_message: String,
+    #[serde(default)]
+    pub data: Value,
+}
+
+impl CommandResult {
+    pub fn new(code: ResultCode, human_message: impl Into<String>, data: Value) -> Self {
+        Self {
+            code,
+            human_message: human_message.into(),
+            data,
+        }
+    }
+
+    pub fn ok(human_message: impl Into<String>, data: Value) -> Self {
+        Self::new(ResultCode::Ok, human_message, data)
+    }
+
+    pub fn error(code: ResultCode, human_message: impl Into<String>) -> Self {
+        Self::new(code, human_message, Value::Null)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.code.is_success()
+    }
+
+    /// Serializes the result as a single newline-terminated JSON document.
+    pub fn to_json_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_else(|_| {
+            format!(
+                "{{\"code\":\"INTERNAL_ERROR\",\"human_message\":{:?},\"data\":null}}",
+                self.human_message
+            )
+        });
+        line.push('\n');
+        line
+    }
+}
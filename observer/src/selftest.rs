@@ -0,0 +1,231 @@
+use crate::audit::{AuditLog, AuditRecord};
+use crate::connector::device_operator::{Device, DeviceInfo};
+use crate::connector::enrollment::to_hex;
+use crate::connector::usb_key::UsbKey;
+use crate::dispatcher::CommandDispatcher;
+use crate::result::ResultCode;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Content served by the provisioning stub device.
+const PROVISIONING_STUB_DATA: &[u8] = b"guardian self-test provisioning stub";
+
+/// Expected SHA-256 hashes of the response scripts, keyed by path relative to the script
+/// directory, e.g. `{"BlockNetwork.sh": "9f86d0..."}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptHashes(pub BTreeMap<String, String>);
+
+impl ScriptHashes {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SelfTestResult {
+    pub check: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl SelfTestResult {
+    fn from_outcome(check: &str, outcome: Result<String>) -> Self {
+        let (passed, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        Self {
+            check: check.to_string(),
+            passed,
+            detail,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    pub ran_at: DateTime<Local>,
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+}
+
+/// Self-test counters, for exporting as metrics.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SelfTestMetrics {
+    pub runs: u64,
+    pub failed_runs: u64,
+    /// Failures per check since guardian started.
+    pub check_failures: BTreeMap<String, u64>,
+    pub last_report: Option<SelfTestReport>,
+}
+
+/// Periodically exercises guardian's own machinery (script integrity, posture probes, the
+/// device read path and the audit chain), so silent degradation is caught between real
+/// incidents. Failures are audited as `SELF_TEST_FAILED` events, which are forwarded like
+/// any other audit record.
+pub struct SelfTester {
+    dispatcher: Arc<CommandDispatcher>,
+    audit_log: Arc<AuditLog>,
+    script_dir: PathBuf,
+    script_hashes: ScriptHashes,
+    metrics: Mutex<SelfTestMetrics>,
+}
+
+impl SelfTester {
+    pub fn new<P: AsRef<Path>>(
+        dispatcher: Arc<CommandDispatcher>,
+        audit_log: Arc<AuditLog>,
+        script_dir: P,
+        script_hashes: ScriptHashes,
+    ) -> Self {
+        Self {
+            dispatcher,
+            audit_log,
+            script_dir: script_dir.as_ref().to_path_buf(),
+            script_hashes,
+            metrics: Mutex::new(SelfTestMetrics::default()),
+        }
+    }
+
+    pub async fn metrics(&self) -> SelfTestMetrics {
+        self.metrics.lock().await.clone()
+    }
+
+    pub async fn run(&self) -> SelfTestReport {
+        let report = SelfTestReport {
+            ran_at: Local::now(),
+            results: vec![
+                SelfTestResult::from_outcome("script_hashes", self.check_scripts().await),
+                SelfTestResult::from_outcome("posture", self.check_posture().await),
+                SelfTestResult::from_outcome("device_read", check_device_read().await),
+                SelfTestResult::from_outcome("audit_chain", self.check_audit_chain().await),
+            ],
+        };
+
+        for result in report.results.iter().filter(|result| !result.passed) {
+            let record = AuditRecord::event(
+                self.dispatcher.host_id(),
+                "SELF_TEST_FAILED",
+                self.dispatcher.mode().as_str(),
+                format!("Self-test {} failed: {}", result.check, result.detail),
+            );
+            if let Err(e) = self.audit_log.record(&record).await {
+                println!("Failed to write audit record: {}", e);
+            }
+        }
+
+        let mut metrics = self.metrics.lock().await;
+        metrics.runs += 1;
+        if !report.passed() {
+            metrics.failed_runs += 1;
+        }
+        for result in report.results.iter().filter(|result| !result.passed) {
+            *metrics
+                .check_failures
+                .entry(result.check.clone())
+                .or_insert(0) += 1;
+        }
+        metrics.last_report = Some(report.clone());
+        report
+    }
+
+    async fn check_scripts(&self) -> Result<String> {
+        let mut mismatched = Vec::new();
+        for (script, expected) in &self.script_hashes.0 {
+            let actual = match tokio::fs::read(self.script_dir.join(script)).await {
+                Ok(content) => to_hex(&Sha256::digest(content)),
+                Err(e) => {
+                    mismatched.push(format!("{} ({})", script, e));
+                    continue;
+                }
+            };
+            if !actual.eq_ignore_ascii_case(expected) {
+                mismatched.push(script.clone());
+            }
+        }
+        if mismatched.is_empty() {
+            Ok(format!("{} scripts match", self.script_hashes.0.len()))
+        } else {
+            Err(anyhow!("scripts modified: {}", mismatched.join(", ")))
+        }
+    }
+
+    async fn check_posture(&self) -> Result<String> {
+        let result = self.dispatcher.verify_posture().await;
+        match result.code {
+            ResultCode::Ok => Ok(result.human_message),
+            _ => Err(anyhow!(result.human_message)),
+        }
+    }
+
+    async fn check_audit_chain(&self) -> Result<String> {
+        let chained = self.audit_log.verify_chain().await?;
+        Ok(format!("{} chained records intact", chained))
+    }
+}
+
+/// Reads from a stub device through the same `UsbKey` path real keys use.
+async fn check_device_read() -> Result<String> {
+    let usb_key = UsbKey::new(Box::new(ProvisioningStub), "self-test".to_string());
+    let data = usb_key.read_data(PROVISIONING_STUB_DATA.len()).await?;
+    if data != PROVISIONING_STUB_DATA {
+        return Err(anyhow!("stub device returned unexpected data"));
+    }
+    let metrics = usb_key.metrics();
+    Ok(format!(
+        "read {} bytes in {} ms",
+        metrics.bytes_read, metrics.read_latency.total_ms
+    ))
+}
+
+/// In-memory device serving fixed provisioning data.
+struct ProvisioningStub;
+
+#[async_trait]
+impl Device for ProvisioningStub {
+    async fn connect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn read(&self, size: usize) -> Result<Vec<u8>> {
+        Ok(PROVISIONING_STUB_DATA[..size.min(PROVISIONING_STUB_DATA.len())].to_vec())
+    }
+
+    async fn write(&self, _data: &[u8]) -> Result<()> {
+        Err(anyhow!("Provisioning stub is read-only"))
+    }
+
+    async fn get_info(&self) -> Result<DeviceInfo> {
+        Err(anyhow!("Provisioning stub has no device info"))
+    }
+
+    async fn wait_for_command(&self, _timeout: Duration) -> Result<String> {
+        Err(anyhow!("Provisioning stub sends no commands"))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,90 @@
+use crate::connector::usb_key::UsbKey;
+use crate::result::CommandResult;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// File on the key holding the working context of the current session.
+pub const SESSION_FILE_NAME: &str = "GUARDIAN_SESSION.json";
+
+/// Host posture as changed by commands in this session. `None` means untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posture {
+    pub network_blocked: Option<bool>,
+    pub usb_locked: Option<bool>,
+    pub screen_locked: bool,
+}
+
+impl Posture {
+    pub fn apply(&mut self, command: &str) {
+        match command {
+            "ALLOW_NETWORK" => self.network_blocked = Some(false),
+            "BLOCK_NETWORK" => self.network_blocked = Some(true),
+            "LOCK_USB" => self.usb_locked = Some(true),
+            "UNLOCK_USB" => self.usb_locked = Some(false),
+            "LOCK_SCREEN" => self.screen_locked = true,
+            _ => {}
+        }
+    }
+}
+
+/// Session state mirrored to the key after each command, so a key that was briefly unplugged
+/// or moved to another port resumes where it left off instead of starting blind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionContext {
+    pub session_id: String,
+    pub host_id: String,
+    pub posture: Posture,
+    #[serde(default)]
+    pub pending_confirmations: Vec<String>,
+    /// Number of results written back to the key in this session.
+    pub last_result_index: u64,
+    pub last_command: Option<String>,
+    pub updated_at: DateTime<Local>,
+}
+
+impl SessionContext {
+    pub fn new(host_id: &str) -> Self {
+        let now = Local::now();
+        Self {
+            session_id: format!("{}-{}", host_id, now.timestamp_millis()),
+            host_id: host_id.to_string(),
+            posture: Posture::default(),
+            pending_confirmations: Vec::new(),
+            last_result_index: 0,
+            last_command: None,
+            updated_at: now,
+        }
+    }
+
+    /// Loads the context stored on the key if it belongs to this host.
+    pub async fn load(usb_key: &UsbKey, host_id: &str) -> Option<Self> {
+        let data = usb_key.read_file(SESSION_FILE_NAME).await.ok()??;
+        let context: Self = serde_json::from_slice(&data).ok()?;
+        (context.host_id == host_id).then_some(context)
+    }
+
+    /// Resumes the session stored on the key, or starts a new one.
+    pub async fn resume_or_new(usb_key: &UsbKey, host_id: &str) -> (Self, bool) {
+        match Self::load(usb_key, host_id).await {
+            Some(context) => (context, true),
+            None => (Self::new(host_id), false),
+        }
+    }
+
+    pub fn record(&mut self, command: &str, result: &CommandResult) {
+        if result.is_success() {
+            self.posture.apply(command);
+        }
+        self.pending_confirmations
+            .retain(|pending| pending != command);
+        self.last_result_index += 1;
+        self.last_command = Some(command.to_string());
+        self.updated_at = Local::now();
+    }
+
+    pub async fn save(&self, usb_key: &UsbKey) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        usb_key.write_verified(SESSION_FILE_NAME, &data).await
+    }
+}
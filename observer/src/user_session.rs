@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command as AsyncCommand;
+
+/// An interactive OS user session as reported by logind (Linux), WTS (Windows) or
+/// `who` (macOS).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSession {
+    pub id: String,
+    pub user: String,
+    /// Seat (Linux) or session name (Windows). Console sessions are attached to a seat.
+    pub seat: Option<String>,
+    pub active: bool,
+    pub locked: bool,
+}
+
+impl UserSession {
+    pub fn is_console(&self) -> bool {
+        matches!(self.seat.as_deref(), Some(seat) if seat.starts_with("seat") || seat.eq_ignore_ascii_case("console"))
+    }
+}
+
+/// Lock state change of a user session between two polls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionTransition {
+    Locked(UserSession),
+    Unlocked(UserSession),
+}
+
+impl SessionTransition {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            SessionTransition::Locked(_) => "SESSION_LOCKED",
+            SessionTransition::Unlocked(_) => "SESSION_UNLOCKED",
+        }
+    }
+
+    pub fn session(&self) -> &UserSession {
+        match self {
+            SessionTransition::Locked(session) | SessionTransition::Unlocked(session) => session,
+        }
+    }
+}
+
+/// The active session attached to the physical console, if any.
+pub fn console_user(sessions: &[UserSession]) -> Option<&UserSession> {
+    sessions
+        .iter()
+        .find(|session| session.active && session.is_console())
+}
+
+/// Compares two snapshots and reports sessions whose lock state changed.
+pub fn diff_lock_states(
+    previous: &[UserSession],
+    current: &[UserSession],
+) -> Vec<SessionTransition> {
+    current
+        .iter()
+        .filter_map(|session| {
+            let before = previous.iter().find(|p| p.id == session.id)?;
+            match (before.locked, session.locked) {
+                (false, true) => Some(SessionTransition::Locked(session.clone())),
+                (true, false) => Some(SessionTransition::Unlocked(session.clone())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+pub async fn list_user_sessions() -> Result<Vec<UserSession>> {
+    crate::platform::current().list_sessions().await
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = AsyncCommand::new(program).args(args).output().await?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+pub(crate) async fn list_logind_sessions() -> Result<Vec<UserSession>> {
+    let listing = run("loginctl", &["list-sessions", "--no-legend"]).await?;
+    let mut sessions = Vec::new();
+    for id in listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+    {
+        let properties = run(
+            "loginctl",
+            &[
+                "show-session",
+                id,
+                "-p",
+                "Name",
+                "-p",
+                "Seat",
+                "-p",
+                "Active",
+                "-p",
+                "LockedHint",
+            ],
+        )
+        .await?;
+        sessions.push(parse_logind_session(id, &properties));
+    }
+    Ok(sessions)
+}
+
+/// Parses `loginctl show-session` key=value output.
+pub fn parse_logind_session(id: &str, properties: &str) -> UserSession {
+    let mut session = UserSession {
+        id: id.to_string(),
+        user: String::new(),
+        seat: None,
+        active: false,
+        locked: false,
+    };
+    for (key, value) in properties.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "Name" => session.user = value.to_string(),
+            "Seat" if !value.is_empty() => session.seat = Some(value.to_string()),
+            "Active" => session.active = value == "yes",
+            "LockedHint" => session.locked = value == "yes",
+            _ => {}
+        }
+    }
+    session
+}
+
+pub(crate) async fn list_wts_sessions() -> Result<Vec<UserSession>> {
+    let listing = run("query", &["user"]).await?;
+    let console_locked = run("tasklist", &["/FI", "IMAGENAME eq LogonUI.exe"])
+        .await
+        .map(|output| output.contains("LogonUI.exe"))
+        .unwrap_or(false);
+    Ok(parse_query_user(&listing, console_locked))
+}
+
+/// Parses `query user` output. WTS does not report lock state directly; the console is
+/// considered locked while LogonUI is running.
+pub fn parse_query_user(listing: &str, console_locked: bool) -> Vec<UserSession> {
+    listing
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim_start_matches('>').split_whitespace().collect();
+            let state_index = fields
+                .iter()
+                .position(|field| *field == "Active" || *field == "Disc")?;
+            let id = fields.get(state_index.checked_sub(1)?)?.to_string();
+            let seat = (state_index == 3).then(|| fields[1].to_string());
+            let is_console = seat
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case("console"));
+            Some(UserSession {
+                id,
+                user: fields.first()?.to_string(),
+                seat,
+                active: fields[state_index] == "Active",
+                locked: is_console && console_locked,
+            })
+        })
+        .collect()
+}
+
+pub(crate) async fn list_who_sessions() -> Result<Vec<UserSession>> {
+    Ok(parse_who(&run("who", &[]).await?))
+}
+
+/// Parses `who` output. It reports neither activity nor lock state, so every session is
+/// taken as active and unlocked; the `console` line is the console session.
+pub fn parse_who(listing: &str) -> Vec<UserSession> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let user = fields.next()?;
+            let terminal = fields.next()?;
+            Some(UserSession {
+                id: terminal.to_string(),
+                user: user.to_string(),
+                seat: (terminal == "console").then(|| terminal.to_string()),
+                active: true,
+                locked: false,
+            })
+        })
+        .collect()
+}